@@ -0,0 +1,94 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Barometric altitude, for recalibrating recorded elevation from the raw pressure some devices store.
+
+use crate::tcx::{Activity, Trackpoint};
+
+/// Standard atmospheric pressure at sea level, in Pascals.
+pub const STANDARD_SEA_LEVEL_PRESSURE: f64 = 101_325.0;
+
+/// Converts a pressure reading into an altitude using the international barometric formula.
+///
+/// # Parameters
+///
+/// `pressure: f64` -- The measured pressure, in Pascals.
+/// `sea_level_ref: f64` -- The pressure at sea level at the time of the reading, in Pascals.
+///
+/// # Returns
+///
+/// `f64` -- The altitude, in meters.
+///
+/// # Example
+///
+/// ```rust
+/// use crate::tcx::altitude::{pressure_to_altitude, STANDARD_SEA_LEVEL_PRESSURE};
+/// let altitude = pressure_to_altitude(89_874.6, STANDARD_SEA_LEVEL_PRESSURE);
+/// assert!((altitude - 1000.0).abs() < 1.0);
+/// ```
+pub fn pressure_to_altitude(pressure: f64, sea_level_ref: f64) -> f64 {
+    44_330.0 * (1.0 - (pressure / sea_level_ref).powf(1.0 / 5.255))
+}
+
+fn recompute_altitude(point: &mut Trackpoint, sea_level_ref: f64) -> bool {
+    let pressure = point
+        .extensions
+        .as_ref()
+        .and_then(|extensions| extensions.pressure);
+    match pressure {
+        Some(pressure) if pressure > 0.0 => {
+            point.altitude_meters = Some(pressure_to_altitude(pressure, sea_level_ref));
+            true
+        }
+        _ => false,
+    }
+}
+
+impl Activity {
+    /// Recomputes the altitude of every Trackpoint that carries a barometric pressure reading, replacing whatever the
+    /// device recorded. Use this to recalibrate an activity once the actual sea level pressure for the day is known.
+    /// Trackpoints without a pressure reading are left unchanged.
+    ///
+    /// # Parameters
+    ///
+    /// `sea_level_ref: f64` -- The sea level pressure, in Pascals. See `STANDARD_SEA_LEVEL_PRESSURE`.
+    ///
+    /// # Returns
+    ///
+    /// `usize` -- The number of Trackpoints whose altitude was recomputed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+    /// assert_eq!(activity.altitude_from_pressure(101_325.0), 0);
+    /// ```
+    pub fn altitude_from_pressure(&mut self, sea_level_ref: f64) -> usize {
+        let mut recomputed = 0;
+        for point in self.trackpoints_mut() {
+            if recompute_altitude(point, sea_level_ref) {
+                recomputed += 1;
+            }
+        }
+        recomputed
+    }
+}
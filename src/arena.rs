@@ -0,0 +1,93 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Arena-backed Trackpoint storage for bulk ingestion. Trackpoints are streamed out of the document (see
+//! `TrackpointStream`) straight into a `bumpalo::Bump`, so that ingesting many files performs a handful of large
+//! allocations instead of one per Vec growth per lap, and everything can be released at once by resetting the arena.
+
+use bumpalo::collections::Vec as BumpVec;
+use bumpalo::Bump;
+use std::io::Read;
+
+use crate::error::Error;
+use crate::stream::TrackpointStream;
+use crate::tcx::Trackpoint;
+
+/// The Trackpoints of one lap, stored in an arena.
+#[derive(Debug)]
+pub struct ArenaLap<'bump> {
+    /// Index of the activity within the document, counted from zero.
+    pub activity_index: usize,
+
+    /// Index of the lap within its activity, counted from zero.
+    pub lap_index: usize,
+
+    /// The lap's Trackpoints, in document order.
+    pub trackpoints: BumpVec<'bump, Trackpoint>,
+}
+
+/// Reads the Trackpoints of every activity lap in a TCX document into the given arena. Summary data (lap totals,
+/// notes and so on) is not kept; use `read` when it is needed.
+///
+/// # Parameters
+///
+/// `bump: &'bump Bump` -- The arena in which to store the Trackpoints.
+/// `reader: R` -- The source of the TCX data.
+///
+/// # Returns
+///
+/// `Result<BumpVec<'bump, ArenaLap<'bump>>, Error>` -- One entry per lap containing Trackpoints, if OK.
+///
+/// # Example
+///
+/// ```rust
+/// let mut bump = bumpalo::Bump::new();
+/// for filename in ["tests/20210119_run_garmin_fenix6.tcx", "tests/20210323_yoga.tcx"] {
+///     let file = std::fs::File::open(filename).unwrap();
+///     let laps = crate::tcx::arena::read_trackpoints_in(&bump, file).unwrap();
+///     assert!(!laps[0].trackpoints.is_empty());
+///     drop(laps);
+///     bump.reset();
+/// }
+/// ```
+pub fn read_trackpoints_in<R: Read>(
+    bump: &Bump,
+    reader: R,
+) -> Result<BumpVec<'_, ArenaLap<'_>>, Error> {
+    let mut laps = BumpVec::<ArenaLap>::new_in(bump);
+    for item in TrackpointStream::new(reader) {
+        let (activity_index, lap_index, point) = item?;
+        let starts_new_lap = laps
+            .last()
+            .is_none_or(|lap| lap.activity_index != activity_index || lap.lap_index != lap_index);
+        if starts_new_lap {
+            laps.push(ArenaLap {
+                activity_index,
+                lap_index,
+                trackpoints: BumpVec::new_in(bump),
+            });
+        }
+        if let Some(lap) = laps.last_mut() {
+            lap.trackpoints.push(point);
+        }
+    }
+    Ok(laps)
+}
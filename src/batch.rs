@@ -0,0 +1,184 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Bulk conversion of a directory of TCX files.
+
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::csv::CsvOptions;
+use crate::storage::write_with;
+use crate::tcx::read_file;
+
+/// Output formats supported by `convert`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Pretty-printed JSON, as written by `TrainingCenterDatabase::export_json`.
+    Json,
+
+    /// GPX 1.1, as written by `TrainingCenterDatabase::export_gpx`.
+    Gpx,
+
+    /// One row per Trackpoint, as written by `TrainingCenterDatabase::export_csv` with the default options.
+    Csv,
+}
+
+impl Format {
+    /// File extension used for this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Gpx => "gpx",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+/// The outcome of a batch conversion.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct BatchReport {
+    /// The files written, in no particular order.
+    pub converted: Vec<PathBuf>,
+
+    /// The input files that could not be converted, along with the reason.
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Converts every TCX file found (recursively) under `in_dir` to the requested format, writing the results under
+/// `out_dir` with the same relative layout. Files are converted in parallel, one worker per available CPU.
+/// A file that fails to convert does not stop the others; it is listed in the report instead.
+///
+/// # Parameters
+///
+/// `in_dir: &Path` -- Directory to search for `.tcx` files.
+///
+/// `out_dir: &Path` -- Directory to write the converted files to. Created if it does not exist.
+///
+/// `format: Format` -- The output format.
+///
+/// # Returns
+///
+/// `Result<BatchReport, std::io::Error>` -- The per-file report, or an error if the directories cannot be read or created.
+///
+/// # Example
+///
+/// ```rust
+/// use std::path::Path;
+///
+/// let in_dir = Path::new("target/batch_doc_in/2021");
+/// std::fs::create_dir_all(in_dir).unwrap();
+/// for name in ["20210119_run_garmin_fenix6.tcx", "20210308_virtual_ride_with_power.tcx", "20210410_run_unicode_notes.tcx"] {
+///     std::fs::copy(Path::new("tests").join(name), in_dir.join(name)).unwrap();
+/// }
+///
+/// let report = crate::tcx::batch::convert(Path::new("target/batch_doc_in"), Path::new("target/batch_doc"), crate::tcx::batch::Format::Json).unwrap();
+/// assert_eq!(report.converted.len(), 3);
+/// assert!(report.failed.is_empty());
+/// ```
+pub fn convert(
+    in_dir: &Path,
+    out_dir: &Path,
+    format: Format,
+) -> Result<BatchReport, std::io::Error> {
+    let mut inputs = Vec::new();
+    find_tcx_files(in_dir, &mut inputs)?;
+    inputs.sort();
+
+    let report = Mutex::new(BatchReport::default());
+    let next = AtomicUsize::new(0);
+    let workers = std::thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1)
+        .min(inputs.len().max(1));
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let input = match inputs.get(index) {
+                    Some(input) => input,
+                    None => break,
+                };
+                let relative = input.strip_prefix(in_dir).unwrap_or(input);
+                let output = out_dir.join(relative).with_extension(format.extension());
+                let result = convert_file(input, &output, format);
+
+                let mut report = report.lock().unwrap();
+                match result {
+                    Ok(()) => report.converted.push(output),
+                    Err(message) => report.failed.push((input.clone(), message)),
+                }
+            });
+        }
+    });
+
+    Ok(report.into_inner().unwrap())
+}
+
+/// Converts a single file.
+fn convert_file(input: &Path, output: &Path, format: Format) -> Result<(), String> {
+    let input_name = input.to_str().ok_or("file name is not valid UTF-8")?;
+    let output_name = output.to_str().ok_or("file name is not valid UTF-8")?;
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+
+    let database = read_file(input_name).map_err(|e| e.to_string())?;
+    match format {
+        Format::Json => database.export_json(output_name).map_err(|e| e.to_string()),
+        Format::Gpx => database.export_gpx(output_name).map_err(|e| e.to_string()),
+        Format::Csv => write_with(&mut output.to_path_buf(), |mut writer| {
+            database.export_csv(&mut writer, &CsvOptions::default())
+        })
+        .map_err(|e| e.to_string()),
+    }
+}
+
+/// Recursively collects the paths of files with a `.tcx` extension (in any case). Symbolic links are followed, but
+/// each directory is only searched once, so a link back up the tree doesn't loop forever.
+pub(crate) fn find_tcx_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), std::io::Error> {
+    collect_tcx_files(dir, files, &mut HashSet::new())
+}
+
+fn collect_tcx_files(
+    dir: &Path,
+    files: &mut Vec<PathBuf>,
+    visited: &mut HashSet<PathBuf>,
+) -> Result<(), std::io::Error> {
+    if !visited.insert(dir.canonicalize()?) {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_tcx_files(&path, files, visited)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("tcx"))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
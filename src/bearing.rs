@@ -0,0 +1,71 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Bearing: the direction of travel along the track, in degrees clockwise from true north.
+
+use chrono::{DateTime, Utc};
+
+use crate::geo::bearings;
+use crate::tcx::{Activity, Trackpoint};
+
+impl Activity {
+    /// Computes the heading at each Trackpoint from the positions, smoothed with a centered moving average.
+    /// Bearings are averaged as unit vectors, so headings either side of north average to north rather than south.
+    ///
+    /// # Parameters
+    ///
+    /// `window: usize` -- Number of bearings averaged at each Trackpoint. `1` returns the raw bearings.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<(DateTime<Utc>, f64)>` -- The time and heading, from 0 up to 360 degrees, of every Trackpoint that has
+    /// a position, once the track has started moving.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let series = tcx.activities.unwrap().activities[0].bearing_series(5);
+    /// assert!(series.iter().all(|(_, bearing)| (0.0..360.0).contains(bearing)));
+    /// ```
+    pub fn bearing_series(&self, window: usize) -> Vec<(DateTime<Utc>, f64)> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        let raw: Vec<(DateTime<Utc>, f64)> = points
+            .iter()
+            .zip(bearings(&points))
+            .filter_map(|(point, bearing)| bearing.map(|bearing| (point.time, bearing)))
+            .collect();
+        let half = window.max(1) / 2;
+        (0..raw.len())
+            .map(|i| {
+                let span = &raw[i.saturating_sub(half)..(i + half + 1).min(raw.len())];
+                let (sin, cos) = span.iter().fold((0.0, 0.0), |(sin, cos), (_, bearing)| {
+                    let radians = bearing.to_radians();
+                    (sin + radians.sin(), cos + radians.cos())
+                });
+                (
+                    raw[i].0,
+                    f64::atan2(sin, cos).to_degrees().rem_euclid(360.0),
+                )
+            })
+            .collect()
+    }
+}
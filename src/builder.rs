@@ -0,0 +1,368 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Fluent builders for creating TCX documents from your own data. Required values are taken by the constructors
+//! (`TrackpointBuilder::new` needs a time, `ActivityBuilder::new` a sport) or checked by `ActivityBuilder::build`,
+//! and everything else the schema requires is filled in with sensible defaults so the result can be written out
+//! directly with `TrainingCenterDatabase::export_tcx`.
+
+use chrono::{DateTime, Utc};
+use std::fmt;
+
+use crate::sport::Sport;
+use crate::tcx::{
+    Activities, Activity, ActivityLap, Extensions, HeartRate, Intensity, Position, Track,
+    Trackpoint, TrainingCenterDatabase, TriggerMethod,
+};
+use crate::validate::Violation;
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Errors
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Reasons why an activity could not be built.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    /// The sport is not one of `Running`, `Biking` or `Other`.
+    InvalidSport(String),
+
+    /// No `Id` was given and there are no Trackpoints to derive one from.
+    MissingId,
+
+    /// The activity has no laps. The schema requires at least one.
+    MissingLaps,
+
+    /// A value breaks a restriction of the schema, such as a cadence above 254. See `Activity::validate`.
+    Invalid(Violation),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::InvalidSport(sport) => write!(f, "invalid sport '{}'", sport),
+            BuildError::MissingId => write!(
+                f,
+                "the activity has no Id and no Trackpoints to derive one from"
+            ),
+            BuildError::MissingLaps => write!(f, "the activity has no laps"),
+            BuildError::Invalid(violation) => write!(f, "{}", violation),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Builders
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Builds a `Trackpoint`. The time is required and so is taken by `new`.
+#[derive(Debug, Clone)]
+pub struct TrackpointBuilder {
+    point: Trackpoint,
+}
+
+impl TrackpointBuilder {
+    /// Starts a Trackpoint recorded at `time`.
+    pub fn new(time: DateTime<Utc>) -> Self {
+        TrackpointBuilder {
+            point: Trackpoint {
+                time,
+                position: None,
+                altitude_meters: None,
+                distance_meters: None,
+                heart_rate: None,
+                cadence: None,
+                extensions: None,
+            },
+        }
+    }
+
+    /// Sets the GPS position, in degrees.
+    pub fn position(mut self, latitude: f64, longitude: f64) -> Self {
+        self.point.position = Some(Position {
+            latitude,
+            longitude,
+        });
+        self
+    }
+
+    /// Sets the altitude, in meters.
+    pub fn altitude(mut self, meters: f64) -> Self {
+        self.point.altitude_meters = Some(meters);
+        self
+    }
+
+    /// Sets the cumulative distance, in meters.
+    pub fn distance(mut self, meters: f64) -> Self {
+        self.point.distance_meters = Some(meters);
+        self
+    }
+
+    /// Sets the heart rate, in beats per minute.
+    pub fn heart_rate(mut self, bpm: f64) -> Self {
+        self.point.heart_rate = Some(HeartRate { value: bpm });
+        self
+    }
+
+    /// Sets the cadence, in revolutions (or strides) per minute.
+    pub fn cadence(mut self, rpm: u8) -> Self {
+        self.point.cadence = Some(rpm);
+        self
+    }
+
+    /// Sets the speed, in meters per second. Stored in the TPX extension.
+    pub fn speed(mut self, meters_per_second: f64) -> Self {
+        self.tpx().speed = Some(meters_per_second);
+        self
+    }
+
+    /// Sets the running cadence, in strides per minute. Stored in the TPX extension.
+    pub fn run_cadence(mut self, spm: u8) -> Self {
+        self.tpx().run_cadence = Some(spm);
+        self
+    }
+
+    /// Sets the power, in Watts. Stored in the TPX extension.
+    pub fn watts(mut self, watts: u16) -> Self {
+        self.tpx().watts = Some(watts);
+        self
+    }
+
+    /// Returns the Trackpoint.
+    pub fn build(self) -> Trackpoint {
+        self.point
+    }
+
+    fn tpx(&mut self) -> &mut crate::tcx::Ns3Tpx {
+        self.point
+            .extensions
+            .get_or_insert_with(Extensions::default)
+            .tpx
+            .get_or_insert_with(Default::default)
+    }
+}
+
+/// Builds an `ActivityLap`. Nothing is required: the start time, duration and distance are derived from the
+/// Trackpoints when not set, and the intensity and trigger method default to `Active` and `Manual`.
+#[derive(Debug, Clone, Default)]
+pub struct LapBuilder {
+    lap: ActivityLap,
+    trackpoints: Vec<Trackpoint>,
+}
+
+impl LapBuilder {
+    /// Starts an empty lap.
+    pub fn new() -> Self {
+        LapBuilder::default()
+    }
+
+    /// Sets the time the lap started.
+    pub fn start_time(mut self, time: DateTime<Utc>) -> Self {
+        self.lap.start_time = Some(time);
+        self
+    }
+
+    /// Sets the duration of the lap, in seconds.
+    pub fn total_time_seconds(mut self, seconds: f64) -> Self {
+        self.lap.total_time_seconds = seconds;
+        self
+    }
+
+    /// Sets the distance covered during the lap, in meters.
+    pub fn distance_meters(mut self, meters: f64) -> Self {
+        self.lap.distance_meters = meters;
+        self
+    }
+
+    /// Sets the maximum speed, in meters per second.
+    pub fn maximum_speed(mut self, meters_per_second: f64) -> Self {
+        self.lap.maximum_speed = Some(meters_per_second);
+        self
+    }
+
+    /// Sets the calories burned.
+    pub fn calories(mut self, calories: u16) -> Self {
+        self.lap.calories = calories;
+        self
+    }
+
+    /// Sets the average and maximum heart rates, in beats per minute.
+    pub fn heart_rate(mut self, average: f64, maximum: f64) -> Self {
+        self.lap.average_heart_rate = Some(average);
+        self.lap.maximum_heart_rate = Some(maximum);
+        self
+    }
+
+    /// Sets the intensity.
+    pub fn intensity(mut self, intensity: Intensity) -> Self {
+        self.lap.intensity = Some(intensity);
+        self
+    }
+
+    /// Sets the average cadence.
+    pub fn cadence(mut self, rpm: u8) -> Self {
+        self.lap.cadence = Some(rpm);
+        self
+    }
+
+    /// Sets what ended the lap.
+    pub fn trigger_method(mut self, trigger_method: TriggerMethod) -> Self {
+        self.lap.trigger_method = Some(trigger_method);
+        self
+    }
+
+    /// Sets the lap notes.
+    pub fn notes(mut self, notes: &str) -> Self {
+        self.lap.notes = Some(notes.to_string());
+        self
+    }
+
+    /// Appends a Trackpoint.
+    pub fn trackpoint(mut self, point: Trackpoint) -> Self {
+        self.trackpoints.push(point);
+        self
+    }
+
+    /// Appends several Trackpoints.
+    pub fn trackpoints<I: IntoIterator<Item = Trackpoint>>(mut self, points: I) -> Self {
+        self.trackpoints.extend(points);
+        self
+    }
+
+    /// Returns the lap, with its Trackpoints in a single Track.
+    pub fn build(self) -> ActivityLap {
+        let mut lap = self.lap;
+        if lap.start_time.is_none() {
+            lap.start_time = self.trackpoints.first().map(|point| point.time);
+        }
+        if !self.trackpoints.is_empty() {
+            lap.tracks.push(Track {
+                trackpoints: self.trackpoints,
+            });
+        }
+        lap
+    }
+}
+
+/// Builds an `Activity`. The sport is required and so is taken by `new`; `build` checks the rest.
+#[derive(Debug, Clone)]
+pub struct ActivityBuilder {
+    activity: Activity,
+}
+
+impl ActivityBuilder {
+    /// Starts an activity of the given sport: `Running`, `Biking` or `Other`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+    ///
+    /// let start = chrono::Utc::now();
+    /// let tcx = ActivityBuilder::new("Running")
+    ///     .lap(
+    ///         LapBuilder::new()
+    ///             .trackpoint(TrackpointBuilder::new(start).distance(0.0).heart_rate(120.0).build())
+    ///             .trackpoint(
+    ///                 TrackpointBuilder::new(start + chrono::Duration::seconds(60))
+    ///                     .distance(200.0)
+    ///                     .heart_rate(140.0)
+    ///                     .build(),
+    ///             )
+    ///             .build(),
+    ///     )
+    ///     .build_database()
+    ///     .unwrap();
+    /// let lap = &tcx.activities.unwrap().activities[0].laps[0];
+    /// assert_eq!(lap.total_time_seconds, 60.0);
+    /// assert_eq!(lap.distance_meters, 200.0);
+    /// ```
+    pub fn new(sport: &str) -> Self {
+        ActivityBuilder {
+            activity: Activity {
+                sport: sport.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Sets the `Id`, which is conventionally the start time. Defaults to the time of the first Trackpoint.
+    pub fn id(mut self, id: &str) -> Self {
+        self.activity.id = id.to_string();
+        self
+    }
+
+    /// Sets the activity notes.
+    pub fn notes(mut self, notes: &str) -> Self {
+        self.activity.notes = Some(notes.to_string());
+        self
+    }
+
+    /// Appends a lap. See `LapBuilder`.
+    pub fn lap(mut self, lap: ActivityLap) -> Self {
+        self.activity.laps.push(lap);
+        self
+    }
+
+    /// Checks the activity, including against the schema's restrictions (see `Activity::validate`), and fills in the
+    /// remaining schema-required values (see `Activity::fill_required_defaults`).
+    ///
+    /// # Returns
+    ///
+    /// `Result<Activity, BuildError>` -- The activity if OK, the reason it is incomplete if not.
+    pub fn build(self) -> Result<Activity, BuildError> {
+        let mut activity = self.activity;
+        if !Sport::ALL
+            .iter()
+            .any(|sport| sport.name() == activity.sport)
+        {
+            return Err(BuildError::InvalidSport(activity.sport));
+        }
+        if activity.laps.is_empty() {
+            return Err(BuildError::MissingLaps);
+        }
+        activity.fill_required_defaults();
+        if activity.id.trim().is_empty() {
+            return Err(BuildError::MissingId);
+        }
+        if let Some(violation) = activity.validate(None).into_iter().next() {
+            return Err(BuildError::Invalid(violation));
+        }
+        Ok(activity)
+    }
+
+    /// Builds the activity (see `build`) and wraps it in a `TrainingCenterDatabase`.
+    ///
+    /// # Returns
+    ///
+    /// `Result<TrainingCenterDatabase, BuildError>` -- The document if OK, the reason it is incomplete if not.
+    pub fn build_database(self) -> Result<TrainingCenterDatabase, BuildError> {
+        let activity = self.build()?;
+        Ok(TrainingCenterDatabase {
+            activities: Some(Activities {
+                activities: vec![activity],
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,168 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Cadence analysis: time-weighted cadence distribution and detection of high-cadence drills.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::seconds_between;
+use crate::tcx::{Activity, Trackpoint};
+
+/// Shortest high-cadence segment, in seconds, that is considered a deliberate drill rather than noise.
+const MIN_DRILL_SECONDS: f64 = 10.0;
+
+/// One bucket of a cadence histogram.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct CadenceBin {
+    /// Lowest cadence (inclusive) counted in this bucket.
+    pub low: u16,
+
+    /// Highest cadence (exclusive) counted in this bucket.
+    pub high: u16,
+
+    /// Time spent in this bucket, in seconds.
+    pub seconds: f64,
+}
+
+/// A sustained segment where cadence stayed at or above a threshold.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CadenceDrill {
+    /// Time of the first Trackpoint in the segment.
+    pub start: DateTime<Utc>,
+
+    /// Time of the last Trackpoint in the segment.
+    pub end: DateTime<Utc>,
+
+    /// Duration of the segment in seconds.
+    pub duration_seconds: f64,
+
+    /// Average cadence over the segment.
+    pub average_cadence: f64,
+
+    /// Maximum cadence over the segment.
+    pub maximum_cadence: u8,
+}
+
+impl Activity {
+    /// Computes a time-weighted histogram of the cadence recorded in the Trackpoints.
+    ///
+    /// # Parameters
+    ///
+    /// `bin_width: u8` -- Width of each bucket in Revolutions (or Steps) per Minute.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<CadenceBin>` -- Buckets from zero up to the highest cadence recorded. Empty if there is no cadence data.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let histogram = tcx.activities.unwrap().activities[0].cadence_histogram(10);
+    /// assert_eq!(histogram[0].low, 0);
+    /// ```
+    pub fn cadence_histogram(&self, bin_width: u8) -> Vec<CadenceBin> {
+        let bin_width = u16::from(bin_width.max(1));
+        let mut bins: Vec<CadenceBin> = Vec::new();
+
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        for pair in points.windows(2) {
+            let elapsed = seconds_between(pair[0], pair[1]);
+            if elapsed <= 0.0 {
+                continue;
+            }
+            if let Some(cadence) = pair[1].cadence {
+                let index = (u16::from(cadence) / bin_width) as usize;
+                while bins.len() <= index {
+                    let low = bins.len() as u16 * bin_width;
+                    bins.push(CadenceBin {
+                        low,
+                        high: low + bin_width,
+                        seconds: 0.0,
+                    });
+                }
+                bins[index].seconds += elapsed;
+            }
+        }
+        bins
+    }
+
+    /// Finds deliberate high-cadence segments, such as spin-up drills, where the cadence stays at or above
+    /// the threshold for at least ten seconds.
+    ///
+    /// # Parameters
+    ///
+    /// `threshold: u8` -- Minimum cadence in Revolutions (or Steps) per Minute for a Trackpoint to be part of a drill.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<CadenceDrill>` -- The drills, in the order they were performed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let drills = tcx.activities.unwrap().activities[0].detect_cadence_drills(95);
+    /// assert_eq!(drills.len(), 11);
+    /// assert!(drills.iter().all(|drill| drill.duration_seconds >= 10.0 && drill.average_cadence >= 95.0));
+    /// ```
+    pub fn detect_cadence_drills(&self, threshold: u8) -> Vec<CadenceDrill> {
+        let mut drills = Vec::new();
+        let mut segment: Vec<&Trackpoint> = Vec::new();
+
+        for point in self.trackpoints() {
+            match point.cadence {
+                Some(cadence) if cadence >= threshold => segment.push(point),
+                _ => {
+                    if let Some(drill) = make_drill(&segment) {
+                        drills.push(drill);
+                    }
+                    segment.clear();
+                }
+            }
+        }
+        if let Some(drill) = make_drill(&segment) {
+            drills.push(drill);
+        }
+        drills
+    }
+}
+
+/// Summarizes a segment of high-cadence Trackpoints, discarding it if it is too short to be deliberate.
+fn make_drill(segment: &[&Trackpoint]) -> Option<CadenceDrill> {
+    let first = segment.first()?;
+    let last = segment.last()?;
+    let duration_seconds = seconds_between(first, last);
+    if duration_seconds < MIN_DRILL_SECONDS {
+        return None;
+    }
+
+    let cadences: Vec<u8> = segment.iter().filter_map(|point| point.cadence).collect();
+    let total: f64 = cadences.iter().map(|cadence| f64::from(*cadence)).sum();
+    Some(CadenceDrill {
+        start: first.time,
+        end: last.time,
+        duration_seconds,
+        average_cadence: total / cadences.len() as f64,
+        maximum_cadence: cadences.iter().copied().max().unwrap_or(0),
+    })
+}
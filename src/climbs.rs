@@ -0,0 +1,240 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Climb detection and categorization, using the scoring used by popular cycling sites: the climb's length in
+//! meters multiplied by its average grade in percent.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::smoothed_altitudes;
+use crate::tcx::{Activity, Course, CoursePoint, CoursePointType, Position, Trackpoint};
+
+/// How far, in meters, the road may drop below the highest point reached before the climb is considered over.
+const DESCENT_TOLERANCE_METERS: f64 = 10.0;
+
+/// Minimum elevation gain, in meters, for a rise to count as a climb.
+const MIN_CLIMB_GAIN_METERS: f64 = 15.0;
+
+/// Minimum average grade, in percent, for a rise to count as a climb.
+const MIN_CLIMB_GRADE_PERCENT: f64 = 3.0;
+
+/// Minimum scores (length in meters times average grade in percent) for each category, hardest first.
+const CATEGORY_SCORES: [(f64, CoursePointType); 5] = [
+    (80000.0, CoursePointType::HorsCategory),
+    (64000.0, CoursePointType::FirstCategory),
+    (32000.0, CoursePointType::SecondCategory),
+    (16000.0, CoursePointType::ThirdCategory),
+    (8000.0, CoursePointType::FourthCategory),
+];
+
+/// A climb found along a track.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Climb {
+    /// Time of the Trackpoint at the bottom of the climb.
+    pub start_time: DateTime<Utc>,
+
+    /// Position at the bottom of the climb, if known.
+    pub start_position: Option<Position>,
+
+    /// Position at the top of the climb, if known.
+    pub top_position: Option<Position>,
+
+    /// Distance along the track, in meters, at the bottom of the climb.
+    pub start_distance_meters: f64,
+
+    /// Length of the climb in meters.
+    pub length_meters: f64,
+
+    /// Elevation gained from the bottom to the top of the climb, in meters.
+    pub elevation_gain_meters: f64,
+
+    /// Average grade of the climb in percent.
+    pub average_grade_percent: f64,
+
+    /// The climb's category (`FourthCategory` through `HorsCategory`), or `None` if it is too small to be categorized.
+    pub category: Option<CoursePointType>,
+}
+
+impl Climb {
+    /// Categorization score: length in meters times average grade in percent.
+    pub fn score(&self) -> f64 {
+        self.length_meters * self.average_grade_percent
+    }
+}
+
+/// Finds the climbs along a sequence of Trackpoints. Trackpoints without both altitude and distance are ignored.
+///
+/// # Parameters
+///
+/// `points: &[&Trackpoint]` -- The Trackpoints, in order.
+///
+/// # Returns
+///
+/// `Vec<Climb>` -- The climbs, in the order they occur.
+pub fn detect_climbs(points: &[&Trackpoint]) -> Vec<Climb> {
+    let points: Vec<&Trackpoint> = points
+        .iter()
+        .copied()
+        .filter(|point| point.altitude_meters.is_some() && point.distance_meters.is_some())
+        .collect();
+    let altitudes = smoothed_altitudes(&points);
+
+    let mut climbs = Vec::new();
+    let mut bottom = 0;
+    let mut top = 0;
+    for i in 1..points.len() {
+        if altitudes[i] > altitudes[top] {
+            top = i;
+        }
+        if altitudes[i] < altitudes[bottom]
+            || altitudes[top] - altitudes[i] > DESCENT_TOLERANCE_METERS
+        {
+            if let Some(climb) = make_climb(&points, &altitudes, bottom, top) {
+                climbs.push(climb);
+            }
+            bottom = i;
+            top = i;
+        }
+    }
+    if let Some(climb) = make_climb(&points, &altitudes, bottom, top) {
+        climbs.push(climb);
+    }
+    climbs
+}
+
+impl Activity {
+    /// Finds the climbs in the activity. See `detect_climbs`.
+    pub fn climbs(&self) -> Vec<Climb> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        detect_climbs(&points)
+    }
+}
+
+impl Course {
+    /// Finds the climbs along the course's tracks. See `detect_climbs`.
+    pub fn climbs(&self) -> Vec<Climb> {
+        let points: Vec<&Trackpoint> = self
+            .tracks
+            .iter()
+            .flatten()
+            .flat_map(|track| track.trackpoints.iter())
+            .collect();
+        detect_climbs(&points)
+    }
+
+    /// Runs climb detection on the course and inserts a `FourthCategory` through `HorsCategory` course point at the
+    /// start of every categorized climb, matching how devices display categorized climbs. Each point is placed at
+    /// the bottom of its climb, or at the top if the bottom has no position; climbs with neither are left out, as
+    /// course points need a position. Category points already on the course are replaced, so calling this again
+    /// does not duplicate them. Course points are kept in time order.
+    ///
+    /// # Parameters
+    ///
+    /// None. `&mut self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// `usize` -- The number of course points inserted.
+    pub fn insert_category_points(&mut self) -> usize {
+        let climbs = self.climbs();
+        self.course_points
+            .retain(|point| !point.point_type.is_some_and(is_category));
+        let mut inserted = 0;
+        for climb in climbs {
+            let category = match climb.category {
+                Some(category) => category,
+                None => continue,
+            };
+            let position = match climb.start_position.or(climb.top_position) {
+                Some(position) => position,
+                None => continue,
+            };
+            let name = match category {
+                CoursePointType::HorsCategory => "HC climb",
+                CoursePointType::FirstCategory => "Cat 1",
+                CoursePointType::SecondCategory => "Cat 2",
+                CoursePointType::ThirdCategory => "Cat 3",
+                _ => "Cat 4",
+            };
+            self.course_points.push(CoursePoint {
+                name: Some(name.to_string()),
+                time: climb.start_time,
+                position: Some(position),
+                altitude_meters: None,
+                point_type: Some(category),
+                notes: Some(format!(
+                    "{:.1} km at {:.1}%",
+                    climb.length_meters / 1000.0,
+                    climb.average_grade_percent
+                )),
+                extensions: None,
+            });
+            inserted += 1;
+        }
+        self.course_points.sort_by_key(|point| point.time);
+        inserted
+    }
+}
+
+/// Whether the course point type is one of the climb categories.
+fn is_category(point_type: CoursePointType) -> bool {
+    CATEGORY_SCORES
+        .iter()
+        .any(|(_, category)| *category == point_type)
+}
+
+/// Describes the rise from `bottom` to `top`, if it is big enough to be a climb.
+fn make_climb(
+    points: &[&Trackpoint],
+    altitudes: &[f64],
+    bottom: usize,
+    top: usize,
+) -> Option<Climb> {
+    if top <= bottom {
+        return None;
+    }
+    let start_distance = points[bottom].distance_meters?;
+    let length = points[top].distance_meters? - start_distance;
+    let gain = altitudes[top] - altitudes[bottom];
+    if length <= 0.0 || gain < MIN_CLIMB_GAIN_METERS {
+        return None;
+    }
+    let grade = gain / length * 100.0;
+    if grade < MIN_CLIMB_GRADE_PERCENT {
+        return None;
+    }
+
+    let score = length * grade;
+    Some(Climb {
+        start_time: points[bottom].time,
+        start_position: points[bottom].position.clone(),
+        top_position: points[top].position.clone(),
+        start_distance_meters: start_distance,
+        length_meters: length,
+        elevation_gain_meters: gain,
+        average_grade_percent: grade,
+        category: CATEGORY_SCORES
+            .iter()
+            .find(|(minimum, _)| score >= *minimum)
+            .map(|(_, category)| *category),
+    })
+}
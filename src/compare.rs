@@ -0,0 +1,295 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Side-by-side comparison of two activities for coaching review: the same route ridden or run twice, or the same
+//! workout repeated. Whole-activity metrics are compared, and the activities are split (by distance for a route,
+//! by lap for a workout) so that the reader sees where time was gained or lost. The report serializes with serde,
+//! such as to JSON, and writes itself as Markdown.
+
+use serde_derive::{Deserialize, Serialize};
+use std::io::Write;
+
+use crate::metrics::{MetricId, MetricsCache};
+use crate::stats::Split;
+use crate::tcx::Activity;
+use crate::writer::format_number;
+
+/// How the two activities correspond, which decides how they are split.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum ComparisonBasis {
+    /// The same route: the activities are split at every `CompareOptions::split_meters`. This is the default.
+    #[default]
+    SameRoute,
+    /// The same workout: the activities are split by lap, so that each interval is compared with its repeat.
+    SameWorkout,
+}
+
+/// Controls `compare_activities`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CompareOptions {
+    /// How the activities correspond. Defaults to `ComparisonBasis::SameRoute`.
+    pub basis: ComparisonBasis,
+
+    /// The length of each split of a route, in meters. Defaults to 1000.
+    pub split_meters: f64,
+
+    /// The metrics compared. Defaults to `MetricId::ALL`.
+    pub metrics: Vec<MetricId>,
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        CompareOptions {
+            basis: ComparisonBasis::SameRoute,
+            split_meters: 1000.0,
+            metrics: MetricId::ALL.to_vec(),
+        }
+    }
+}
+
+/// One metric of both activities.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MetricDelta {
+    pub metric: MetricId,
+
+    /// The first activity's value, if it has data for the metric.
+    pub first: Option<f64>,
+
+    /// The second activity's value, if it has data for the metric.
+    pub second: Option<f64>,
+
+    /// The second value less the first, if both have one.
+    pub difference: Option<f64>,
+}
+
+/// One split of both activities.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SplitDelta {
+    /// The split's number, from 1.
+    pub number: usize,
+
+    /// The first activity's split, if it reached it.
+    pub first: Option<Split>,
+
+    /// The second activity's split, if it reached it.
+    pub second: Option<Split>,
+
+    /// The second activity's time over the split less the first's, in seconds. Negative where the second was
+    /// faster.
+    pub difference_seconds: Option<f64>,
+
+    /// The running total of `difference_seconds` up to and including this split: how far ahead (negative) or
+    /// behind the second activity is.
+    pub cumulative_difference_seconds: Option<f64>,
+}
+
+/// The result of `compare_activities`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ActivityComparison {
+    /// The first activity's `Id`.
+    pub first_id: String,
+
+    /// The second activity's `Id`.
+    pub second_id: String,
+
+    /// How the activities were split.
+    pub basis: ComparisonBasis,
+
+    /// The whole-activity metrics, in the order of `CompareOptions::metrics`.
+    pub metrics: Vec<MetricDelta>,
+
+    /// The splits, as many as the longer activity has.
+    pub splits: Vec<SplitDelta>,
+}
+
+/// Compares two activities side by side. Differences are the second activity less the first, so the first is
+/// usually the reference, such as last month's effort or the planned workout.
+///
+/// # Parameters
+///
+/// `first: &Activity` -- The reference activity.
+///
+/// `second: &Activity` -- The activity compared with it.
+///
+/// `options: &CompareOptions` -- How to split the activities and which metrics to compare.
+///
+/// # Returns
+///
+/// `ActivityComparison` -- The report.
+///
+/// # Example
+///
+/// ```rust
+/// use crate::tcx::compare::{compare_activities, CompareOptions};
+/// use crate::tcx::metrics::MetricId;
+///
+/// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+/// let run = &tcx.activities.unwrap().activities[0];
+/// let comparison = compare_activities(run, run, &CompareOptions::default());
+/// assert_eq!(comparison.metrics[0].metric, MetricId::DistanceMeters);
+/// assert_eq!(comparison.metrics[0].difference, Some(0.0));
+/// assert!(comparison.splits.iter().all(|split| split.difference_seconds == Some(0.0)));
+/// ```
+pub fn compare_activities(
+    first: &Activity,
+    second: &Activity,
+    options: &CompareOptions,
+) -> ActivityComparison {
+    let (first_cache, second_cache) = (MetricsCache::new(first), MetricsCache::new(second));
+    let metrics = options
+        .metrics
+        .iter()
+        .map(|metric| {
+            let (a, b) = (
+                metric.evaluate(&first_cache),
+                metric.evaluate(&second_cache),
+            );
+            MetricDelta {
+                metric: *metric,
+                first: a,
+                second: b,
+                difference: a.zip(b).map(|(a, b)| b - a),
+            }
+        })
+        .collect();
+
+    let split = |activity: &Activity| match options.basis {
+        ComparisonBasis::SameRoute => activity.distance_splits(options.split_meters),
+        ComparisonBasis::SameWorkout => activity.lap_splits(),
+    };
+    let (first_splits, second_splits) = (split(first), split(second));
+    let mut cumulative = Some(0.0);
+    let splits = (0..first_splits.len().max(second_splits.len()))
+        .map(|i| {
+            let (a, b) = (first_splits.get(i), second_splits.get(i));
+            let difference = a.zip(b).map(|(a, b)| b.elapsed_seconds - a.elapsed_seconds);
+            cumulative = cumulative
+                .zip(difference)
+                .map(|(total, difference)| total + difference);
+            SplitDelta {
+                number: i + 1,
+                first: a.cloned(),
+                second: b.cloned(),
+                difference_seconds: difference,
+                cumulative_difference_seconds: cumulative,
+            }
+        })
+        .collect();
+
+    ActivityComparison {
+        first_id: first.id.clone(),
+        second_id: second.id.clone(),
+        basis: options.basis,
+        metrics,
+        splits,
+    }
+}
+
+impl ActivityComparison {
+    /// Writes the report as Markdown: a table of the metrics and a table of the split times, with differences
+    /// signed so that negative means the second activity was lower (or faster). Missing values are shown as `-`.
+    ///
+    /// # Parameters
+    ///
+    /// `writer: &mut W` -- Where to write the Markdown.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), std::io::Error>` -- Nothing if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::compare::{compare_activities, CompareOptions};
+    ///
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let run = &tcx.activities.unwrap().activities[0];
+    /// let mut markdown = Vec::new();
+    /// compare_activities(run, run, &CompareOptions::default()).to_markdown(&mut markdown).unwrap();
+    /// let markdown = String::from_utf8(markdown).unwrap();
+    /// assert!(markdown.starts_with("# Activity comparison"));
+    /// assert!(markdown.contains("| distance_meters |"));
+    /// ```
+    pub fn to_markdown<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let cell = |value: Option<f64>| {
+            value.map_or("-".to_string(), |value| format_number(value, Some(1)))
+        };
+        let signed = |value: Option<f64>| match value {
+            Some(value) if value > 0.0 => format!("+{}", format_number(value, Some(1))),
+            _ => cell(value),
+        };
+
+        writeln!(writer, "# Activity comparison")?;
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "| Metric | {} | {} | Difference |",
+            markdown_escape(&self.first_id),
+            markdown_escape(&self.second_id)
+        )?;
+        writeln!(writer, "| --- | ---: | ---: | ---: |")?;
+        for delta in &self.metrics {
+            writeln!(
+                writer,
+                "| {} | {} | {} | {} |",
+                delta.metric.name(),
+                cell(delta.first),
+                cell(delta.second),
+                signed(delta.difference)
+            )?;
+        }
+
+        writeln!(writer)?;
+        match self.basis {
+            ComparisonBasis::SameRoute => writeln!(writer, "## Splits by distance")?,
+            ComparisonBasis::SameWorkout => writeln!(writer, "## Splits by lap")?,
+        }
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "| Split | Distance (m) | First (s) | Second (s) | Difference (s) | Cumulative (s) |"
+        )?;
+        writeln!(writer, "| ---: | ---: | ---: | ---: | ---: | ---: |")?;
+        for split in &self.splits {
+            let distance = split
+                .first
+                .as_ref()
+                .or(split.second.as_ref())
+                .map(|split| split.distance_meters);
+            writeln!(
+                writer,
+                "| {} | {} | {} | {} | {} | {} |",
+                split.number,
+                cell(distance),
+                cell(split.first.as_ref().map(|split| split.elapsed_seconds)),
+                cell(split.second.as_ref().map(|split| split.elapsed_seconds)),
+                signed(split.difference_seconds),
+                signed(split.cumulative_difference_seconds)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Escapes the characters that would break a Markdown table cell.
+fn markdown_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('|', "\\|")
+}
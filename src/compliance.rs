@@ -0,0 +1,357 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Workout compliance: how closely a recorded activity followed a planned TCX workout, step by step.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::{seconds_between, speeds};
+use crate::tcx::{
+    Activity, HeartRateValue, StepDuration, StepTarget, Trackpoint, Workout, WorkoutStep,
+};
+
+/// Schema type of heart rates given in beats per minute. Heart rates given as a percentage of maximum cannot be
+/// checked without knowing the athlete's maximum.
+const BEATS_PER_MINUTE_TYPE: &str = "HeartRateInBeatsPerMinute_t";
+
+/// How closely one planned step was followed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StepCompliance {
+    /// The step's `StepId`.
+    pub step_id: u8,
+
+    /// The step's name, if it has one.
+    pub name: Option<String>,
+
+    /// When the step started in the recording, or `None` if the recording ended before reaching it.
+    pub start_time: Option<DateTime<Utc>>,
+
+    /// How long the step lasted in the recording, in seconds.
+    pub actual_seconds: f64,
+
+    /// How far was covered during the step in the recording, in meters.
+    pub actual_meters: f64,
+
+    /// From 0 to 1, how closely the step's length matched the plan: for time and distance steps, one minus the
+    /// relative error; for heart rate steps, 1 if the heart rate was reached and 0 if not. `None` for steps that
+    /// end on calories or at the athlete's request.
+    pub duration_score: Option<f64>,
+
+    /// From 0 to 1, the fraction of the step's samples that were within the target. `None` for steps without a
+    /// target, or whose target is a predefined zone or a percentage of maximum heart rate.
+    pub target_score: Option<f64>,
+}
+
+/// How closely an activity followed a workout.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ComplianceReport {
+    /// One entry per planned step, with repeats expanded, in order.
+    pub steps: Vec<StepCompliance>,
+
+    /// The average of every duration and target score, or `None` if nothing could be scored.
+    pub overall_score: Option<f64>,
+}
+
+/// A recorded Trackpoint, with the index of the lap it belongs to and its speed.
+struct Sample<'a> {
+    point: &'a Trackpoint,
+    lap: usize,
+    speed: Option<f64>,
+}
+
+impl Activity {
+    /// Aligns the recording with the steps of a workout and scores each step. When the activity has one lap per
+    /// step, as devices record when following a workout, laps are matched to steps. Otherwise each step is carved
+    /// out of the recording in turn by its duration: a time, a distance, a heart rate to reach, or (for steps that
+    /// end on calories or at the athlete's request) the end of the current lap.
+    ///
+    /// # Parameters
+    ///
+    /// `workout: &Workout` -- The planned workout.
+    ///
+    /// # Returns
+    ///
+    /// `ComplianceReport` -- The per-step scores.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let plan = crate::tcx::read_file("tests/20210602_workout_intervals.tcx").unwrap();
+    /// let workout = &plan.workouts.unwrap().workouts[0];
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let report = tcx.activities.unwrap().activities[0].compliance_report(workout);
+    /// assert_eq!(report.steps.len(), 14);
+    /// ```
+    pub fn compliance_report(&self, workout: &Workout) -> ComplianceReport {
+        let mut planned = Vec::new();
+        flatten(&workout.steps, &mut planned);
+
+        let mut samples = Vec::new();
+        for (lap_index, lap) in self.laps.iter().enumerate() {
+            let points: Vec<&Trackpoint> = lap.trackpoints().collect();
+            for (point, speed) in points.iter().zip(speeds(&points)) {
+                samples.push(Sample {
+                    point,
+                    lap: lap_index,
+                    speed,
+                });
+            }
+        }
+
+        let mut steps = Vec::with_capacity(planned.len());
+        let mut start = 0;
+        for (index, step) in planned.iter().enumerate() {
+            let end = if self.laps.len() == planned.len() {
+                samples[start..]
+                    .iter()
+                    .position(|sample| sample.lap > index)
+                    .map_or(samples.len(), |offset| start + offset)
+            } else {
+                step_end(&samples, start, step.duration.as_ref())
+            };
+            let next = samples.get(end).map(|sample| sample.point);
+            steps.push(score_step(step, &samples[start..end], next));
+            start = end;
+        }
+
+        let scores: Vec<f64> = steps
+            .iter()
+            .flat_map(|step| [step.duration_score, step.target_score])
+            .flatten()
+            .collect();
+        let overall_score = if scores.is_empty() {
+            None
+        } else {
+            Some(scores.iter().sum::<f64>() / scores.len() as f64)
+        };
+        ComplianceReport {
+            steps,
+            overall_score,
+        }
+    }
+}
+
+/// Expands repeat blocks into the single steps they run, in order.
+fn flatten<'a>(steps: &'a [WorkoutStep], out: &mut Vec<&'a WorkoutStep>) {
+    for step in steps {
+        if step.is_repeat() {
+            for _ in 0..step.repetitions.unwrap_or(1) {
+                flatten(&step.children, out);
+            }
+        } else {
+            out.push(step);
+        }
+    }
+}
+
+/// Index of the sample that ends a step beginning at `start`, which is also the first sample of the next step.
+fn step_end(samples: &[Sample], start: usize, duration: Option<&StepDuration>) -> usize {
+    let first = match samples.get(start) {
+        Some(first) => first,
+        None => return start,
+    };
+    for (index, sample) in samples.iter().enumerate().skip(start + 1) {
+        match completes(duration, first.point, sample.point) {
+            Some(true) => return index,
+            Some(false) => {}
+            None if sample.lap != first.lap => return index,
+            None => {}
+        }
+    }
+    samples.len()
+}
+
+/// Whether a step that began at `first` is complete at `point`, or `None` if its duration cannot be measured from
+/// the recording, in which case the step runs to the end of the lap.
+fn completes(
+    duration: Option<&StepDuration>,
+    first: &Trackpoint,
+    point: &Trackpoint,
+) -> Option<bool> {
+    let duration = duration?;
+    match duration.duration_type.as_deref() {
+        Some("Time_t") => duration
+            .seconds
+            .map(|seconds| seconds_between(first, point) >= seconds as f64),
+        Some("Distance_t") => match (
+            duration.meters,
+            first.distance_meters,
+            point.distance_meters,
+        ) {
+            (Some(meters), Some(from), Some(to)) => Some(to - from >= meters as f64),
+            _ => None,
+        },
+        Some("HeartRateAbove_t") => {
+            let limit = bpm(duration.heart_rate.as_ref())?;
+            Some(
+                point
+                    .heart_rate
+                    .as_ref()
+                    .is_some_and(|hr| hr.value >= limit),
+            )
+        }
+        Some("HeartRateBelow_t") => {
+            let limit = bpm(duration.heart_rate.as_ref())?;
+            Some(
+                point
+                    .heart_rate
+                    .as_ref()
+                    .is_some_and(|hr| hr.value <= limit),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Scores a step from its samples. The step is measured up to `next`, the first sample of the following step,
+/// when there is one.
+fn score_step(step: &WorkoutStep, samples: &[Sample], next: Option<&Trackpoint>) -> StepCompliance {
+    let mut compliance = StepCompliance {
+        step_id: step.step_id,
+        name: step.name.clone(),
+        start_time: None,
+        actual_seconds: 0.0,
+        actual_meters: 0.0,
+        duration_score: None,
+        target_score: None,
+    };
+    let first = match samples.first() {
+        Some(first) => first.point,
+        None => {
+            // Never reached: a measurable duration scores zero.
+            if step.duration.as_ref().is_some_and(measurable) {
+                compliance.duration_score = Some(0.0);
+            }
+            return compliance;
+        }
+    };
+    let end = next.unwrap_or_else(|| samples.last().unwrap().point);
+    compliance.start_time = Some(first.time);
+    compliance.actual_seconds = seconds_between(first, end);
+    if let (Some(from), Some(to)) = (first.distance_meters, end.distance_meters) {
+        compliance.actual_meters = to - from;
+    }
+    compliance.duration_score = step
+        .duration
+        .as_ref()
+        .and_then(|duration| duration_score(duration, first, end));
+    compliance.target_score = step
+        .target
+        .as_ref()
+        .and_then(|target| target_score(target, samples));
+    compliance
+}
+
+fn duration_score(duration: &StepDuration, first: &Trackpoint, end: &Trackpoint) -> Option<f64> {
+    let relative = |actual: f64, planned: f64| {
+        if planned > 0.0 {
+            (1.0 - (actual - planned).abs() / planned).max(0.0)
+        } else {
+            1.0
+        }
+    };
+    match duration.duration_type.as_deref() {
+        Some("Time_t") => duration
+            .seconds
+            .map(|planned| relative(seconds_between(first, end), planned as f64)),
+        Some("Distance_t") => match (duration.meters, first.distance_meters, end.distance_meters) {
+            (Some(planned), Some(from), Some(to)) => Some(relative(to - from, planned as f64)),
+            _ => None,
+        },
+        Some("HeartRateAbove_t" | "HeartRateBelow_t") => {
+            completes(Some(duration), first, end).map(|done| if done { 1.0 } else { 0.0 })
+        }
+        _ => None,
+    }
+}
+
+/// Whether a step's duration is a time, distance or heart rate that the recording can be checked against.
+fn measurable(duration: &StepDuration) -> bool {
+    match duration.duration_type.as_deref() {
+        Some("Time_t") => duration.seconds.is_some(),
+        Some("Distance_t") => duration.meters.is_some(),
+        Some("HeartRateAbove_t" | "HeartRateBelow_t") => {
+            bpm(duration.heart_rate.as_ref()).is_some()
+        }
+        _ => false,
+    }
+}
+
+fn target_score(target: &StepTarget, samples: &[Sample]) -> Option<f64> {
+    match target.target_type.as_deref() {
+        Some("Speed_t") => {
+            let zone = target.speed_zone.as_ref()?;
+            let (low, high) = (
+                zone.low_in_meters_per_second?,
+                zone.high_in_meters_per_second?,
+            );
+            fraction_within(samples.iter().map(|sample| sample.speed), low, high)
+        }
+        Some("HeartRate_t") => {
+            let zone = target.heart_rate_zone.as_ref()?;
+            let (low, high) = (bpm(zone.low.as_ref())?, bpm(zone.high.as_ref())?);
+            let heart_rates = samples
+                .iter()
+                .map(|sample| sample.point.heart_rate.as_ref().map(|hr| hr.value));
+            fraction_within(heart_rates, low, high)
+        }
+        Some("Cadence_t") => {
+            let cadences = samples.iter().map(|sample| {
+                sample
+                    .point
+                    .cadence
+                    .or_else(|| {
+                        sample
+                            .point
+                            .extensions
+                            .as_ref()
+                            .and_then(|ext| ext.tpx.as_ref())
+                            .and_then(|tpx| tpx.run_cadence)
+                    })
+                    .map(f64::from)
+            });
+            fraction_within(cadences, target.low?, target.high?)
+        }
+        _ => None,
+    }
+}
+
+/// Fraction of the values present that lie within `low..=high`.
+fn fraction_within<I: Iterator<Item = Option<f64>>>(values: I, low: f64, high: f64) -> Option<f64> {
+    let (within, total) = values.flatten().fold((0, 0), |(within, total), value| {
+        (within + (value >= low && value <= high) as usize, total + 1)
+    });
+    if total > 0 {
+        Some(within as f64 / total as f64)
+    } else {
+        None
+    }
+}
+
+/// A workout heart rate in beats per minute, if it is given that way.
+fn bpm(value: Option<&HeartRateValue>) -> Option<f64> {
+    let value = value?;
+    match value.value_type.as_deref() {
+        Some(BEATS_PER_MINUTE_TYPE) | None => Some(value.value as f64),
+        Some(_) => None,
+    }
+}
@@ -0,0 +1,210 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Checking the crate against a directory of TCX files, such as a personal archive, before relying on it. Each file
+//! is read both strictly and leniently (see `crate::lenient`), and written back out to find the elements the crate
+//! does not model, which would be lost by a round trip.
+
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::batch::find_tcx_files;
+use crate::storage::read_all;
+use crate::tcx::{read_with_options, ReadOptions, TrainingCenterDatabase, WriteOptions};
+
+/// How a file fared.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The file reads strictly.
+    Strict,
+    /// The file only reads leniently, after repairs.
+    Lenient,
+    /// The file cannot be read either way.
+    Failed,
+}
+
+/// The result of checking one file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileReport {
+    /// The file.
+    pub path: PathBuf,
+
+    /// How the file fared.
+    pub outcome: Outcome,
+
+    /// Why the strict read failed, if it did.
+    pub strict_error: Option<String>,
+
+    /// Why the lenient read failed, if it did.
+    pub lenient_error: Option<String>,
+
+    /// The repairs the lenient read made. See `crate::lenient::Warning`.
+    pub repairs: Vec<String>,
+
+    /// Elements of the file, by local name, that are missing when the parsed document is written back out, with
+    /// how many of each were lost. Empty if the file could not be read.
+    pub unknown_elements: BTreeMap<String, usize>,
+}
+
+/// The result of checking a directory.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct CorpusReport {
+    /// One report per file, sorted by path.
+    pub files: Vec<FileReport>,
+
+    /// The unknown elements of all files together, by local name.
+    pub unknown_elements: BTreeMap<String, usize>,
+}
+
+impl CorpusReport {
+    /// Counts the files with the given outcome.
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.files
+            .iter()
+            .filter(|file| file.outcome == outcome)
+            .count()
+    }
+}
+
+/// Checks every TCX file found (recursively) under `dir`. A file that cannot be read does not stop the others.
+///
+/// # Parameters
+///
+/// `dir: &Path` -- Directory to search for `.tcx` files.
+///
+/// # Returns
+///
+/// `Result<CorpusReport, std::io::Error>` -- The per-file report, or an error if the directory cannot be read.
+///
+/// # Example
+///
+/// ```rust
+/// use crate::tcx::corpus::{check, Outcome};
+///
+/// let report = check(std::path::Path::new("tests")).unwrap();
+/// assert_eq!(report.count(Outcome::Failed), 0);
+/// ```
+pub fn check(dir: &Path) -> Result<CorpusReport, std::io::Error> {
+    let mut paths = Vec::new();
+    find_tcx_files(dir, &mut paths)?;
+    paths.sort();
+
+    let mut report = CorpusReport::default();
+    for path in paths {
+        let file = check_file(path);
+        for (name, count) in &file.unknown_elements {
+            *report.unknown_elements.entry(name.clone()).or_insert(0) += count;
+        }
+        report.files.push(file);
+    }
+    Ok(report)
+}
+
+/// Checks a single file.
+fn check_file(path: PathBuf) -> FileReport {
+    let mut report = FileReport {
+        path,
+        outcome: Outcome::Failed,
+        strict_error: None,
+        lenient_error: None,
+        repairs: Vec::new(),
+        unknown_elements: BTreeMap::new(),
+    };
+    let bytes = match read_all(&mut report.path.clone()) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            report.strict_error = Some(err.to_string());
+            report.lenient_error = Some(err.to_string());
+            return report;
+        }
+    };
+
+    let strict = match read_with_options(
+        &mut BufReader::new(bytes.as_slice()),
+        &ReadOptions::default(),
+    ) {
+        Ok(database) => Some(database),
+        Err(err) => {
+            report.strict_error = Some(err.to_string());
+            None
+        }
+    };
+    let lenient = match crate::lenient::deserialize(&bytes, crate::tcx::TrackpointPolicy::All) {
+        Ok((database, warnings)) => {
+            report.repairs = warnings.iter().map(|warning| warning.to_string()).collect();
+            Some(database)
+        }
+        Err(err) => {
+            report.lenient_error = Some(err.to_string());
+            None
+        }
+    };
+    report.outcome = match (&strict, &lenient) {
+        (Some(_), _) => Outcome::Strict,
+        (None, Some(_)) => Outcome::Lenient,
+        (None, None) => Outcome::Failed,
+    };
+    if let Some(database) = strict.or(lenient) {
+        report.unknown_elements = unknown_elements(&bytes, &database);
+    }
+    report
+}
+
+/// Counts the elements of the original that are missing from the database written back out.
+fn unknown_elements(original: &[u8], database: &TrainingCenterDatabase) -> BTreeMap<String, usize> {
+    let options = WriteOptions {
+        validate: false,
+        ..Default::default()
+    };
+    let mut written = Vec::new();
+    if database
+        .to_writer_with_options(&mut written, &options)
+        .is_err()
+    {
+        return BTreeMap::new();
+    }
+    let mut unknown = count_elements(original);
+    for (name, count) in count_elements(&written) {
+        if let Some(original_count) = unknown.get_mut(&name) {
+            *original_count = original_count.saturating_sub(count);
+        }
+    }
+    unknown.retain(|_, count| *count > 0);
+    unknown
+}
+
+/// Counts the elements of a document by local name, up to the first XML error.
+fn count_elements(bytes: &[u8]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for event in EventReader::new(bytes) {
+        match event {
+            Ok(XmlEvent::StartElement { name, .. }) => {
+                *counts.entry(name.local_name).or_insert(0) += 1;
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    counts
+}
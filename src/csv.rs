@@ -0,0 +1,496 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! CSV exports for pasting into spreadsheets or loading into data frames: summaries with one row per lap or per
+//! activity, and a table with one row per Trackpoint, whose positions can be reprojected for GIS tools with the
+//! `proj` feature.
+
+use serde_derive::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::Write;
+
+#[cfg(feature = "proj")]
+use crate::projection::{CoordinateSystem, Projector};
+use crate::storage::TcxSink;
+use crate::tcx::{Activity, ActivityLap, Trackpoint, TrainingCenterDatabase};
+use crate::writer::format_time;
+
+/// Stands in for `projection::Projector` without the `proj` feature, when positions are never reprojected.
+#[cfg(not(feature = "proj"))]
+enum Projector {}
+
+#[cfg(not(feature = "proj"))]
+impl Projector {
+    fn project(&self, _position: &crate::tcx::Position) -> Option<(f64, f64)> {
+        match *self {}
+    }
+}
+
+/// Column headings of the lap summary.
+const LAP_HEADER: &str = "activity_id,lap,start_time,duration_seconds,distance_meters,average_heart_rate,max_heart_rate,average_power,ascent_meters,average_temperature";
+
+/// Column headings of the activity summary.
+const ACTIVITY_HEADER: &str = "activity_id,sport,start_time,laps,duration_seconds,moving_seconds,distance_meters,average_heart_rate,max_heart_rate,average_power,normalized_power,ascent_meters,average_temperature";
+
+/// A column of the per-Trackpoint CSV export.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum CsvColumn {
+    /// The time the Trackpoint was recorded, as RFC 3339. Heading `timestamp`.
+    Timestamp,
+    /// Degrees latitude. Heading `latitude`.
+    Latitude,
+    /// Degrees longitude. Heading `longitude`.
+    Longitude,
+    /// Altitude in meters. Heading `altitude_meters`.
+    Altitude,
+    /// Cumulative distance in meters. Heading `distance_meters`.
+    Distance,
+    /// Heart rate in beats per minute. Heading `heart_rate`.
+    HeartRate,
+    /// Cadence, from the Trackpoint or else the TPX `RunCadence`. Heading `cadence`.
+    Cadence,
+    /// Power in watts. Heading `watts`.
+    Watts,
+    /// Speed in meters per second, from the TPX extension. Heading `speed`.
+    Speed,
+    /// Air temperature in degrees Celsius, from the extension. Heading `temperature_celsius`.
+    Temperature,
+}
+
+impl CsvColumn {
+    /// Every column, in the default order.
+    pub const ALL: [CsvColumn; 10] = [
+        CsvColumn::Timestamp,
+        CsvColumn::Latitude,
+        CsvColumn::Longitude,
+        CsvColumn::Altitude,
+        CsvColumn::Distance,
+        CsvColumn::HeartRate,
+        CsvColumn::Cadence,
+        CsvColumn::Watts,
+        CsvColumn::Speed,
+        CsvColumn::Temperature,
+    ];
+
+    /// The column heading.
+    pub fn heading(&self) -> &'static str {
+        match self {
+            CsvColumn::Timestamp => "timestamp",
+            CsvColumn::Latitude => "latitude",
+            CsvColumn::Longitude => "longitude",
+            CsvColumn::Altitude => "altitude_meters",
+            CsvColumn::Distance => "distance_meters",
+            CsvColumn::HeartRate => "heart_rate",
+            CsvColumn::Cadence => "cadence",
+            CsvColumn::Watts => "watts",
+            CsvColumn::Speed => "speed",
+            CsvColumn::Temperature => "temperature_celsius",
+        }
+    }
+}
+
+/// Options controlling the per-Trackpoint CSV export.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CsvOptions {
+    /// The columns to write, in order, after the `activity_id` and `lap` columns. Defaults to `CsvColumn::ALL`.
+    pub columns: Vec<CsvColumn>,
+
+    /// Whether to follow each lap's Trackpoints with a summary row for the lap. When set, a `row` column is added
+    /// that reads `trackpoint` or `lap`. Defaults to false.
+    pub lap_summaries: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            columns: CsvColumn::ALL.to_vec(),
+            lap_summaries: false,
+        }
+    }
+}
+
+impl TrainingCenterDatabase {
+    /// Writes one CSV row per Trackpoint of every activity, with the columns chosen in the options, optionally
+    /// followed by a summary row for each lap. Lap numbers start at 1. Values that were not recorded are left empty.
+    ///
+    /// # Parameters
+    ///
+    /// `writer: &mut W` -- Where to write the CSV.
+    ///
+    /// `options: &CsvOptions` -- Which columns to write, and whether to add lap summary rows.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), std::io::Error>` -- Nothing if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let options = crate::tcx::csv::CsvOptions {
+    ///     columns: vec![crate::tcx::csv::CsvColumn::Timestamp, crate::tcx::csv::CsvColumn::Watts],
+    ///     lap_summaries: false,
+    /// };
+    /// let mut csv = Vec::new();
+    /// tcx.export_csv(&mut csv, &options).unwrap();
+    /// let csv = String::from_utf8(csv).unwrap();
+    /// assert_eq!(csv.lines().next().unwrap(), "activity_id,lap,timestamp,watts");
+    /// ```
+    pub fn export_csv<W: Write>(
+        &self,
+        writer: &mut W,
+        options: &CsvOptions,
+    ) -> std::io::Result<()> {
+        self.write_csv(writer, options, None)
+    }
+
+    /// Writes the per-Trackpoint CSV of `export_csv` with the positions reprojected into another coordinate
+    /// reference system. The `Latitude` and `Longitude` columns hold the northing and easting, headed `y` and `x`.
+    /// Requires the `proj` feature.
+    ///
+    /// # Parameters
+    ///
+    /// `writer: &mut W` -- Where to write the CSV.
+    ///
+    /// `options: &CsvOptions` -- Which columns to write, and whether to add lap summary rows.
+    ///
+    /// `system: &CoordinateSystem` -- The coordinate reference system of the positions.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if the system cannot be used or writing fails.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::csv::{CsvColumn, CsvOptions};
+    /// use crate::tcx::projection::CoordinateSystem;
+    ///
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let options = CsvOptions { columns: vec![CsvColumn::Longitude, CsvColumn::Latitude], lap_summaries: false };
+    /// let mut csv = Vec::new();
+    /// tcx.export_projected_csv(&mut csv, &options, &CoordinateSystem::Epsg(32617)).unwrap();
+    /// let csv = String::from_utf8(csv).unwrap();
+    /// assert_eq!(csv.lines().next().unwrap(), "activity_id,lap,x,y");
+    /// let easting: f64 = csv.lines().nth(1).unwrap().split(',').nth(2).unwrap().parse().unwrap();
+    /// assert!(easting > 500000.0 && easting < 600000.0);
+    /// ```
+    #[cfg(feature = "proj")]
+    pub fn export_projected_csv<W: Write>(
+        &self,
+        writer: &mut W,
+        options: &CsvOptions,
+        system: &CoordinateSystem,
+    ) -> Result<(), Box<dyn Error>> {
+        let projector = Projector::new(system)?;
+        self.write_csv(writer, options, Some(&projector))?;
+        Ok(())
+    }
+
+    fn write_csv<W: Write>(
+        &self,
+        writer: &mut W,
+        options: &CsvOptions,
+        projector: Option<&Projector>,
+    ) -> std::io::Result<()> {
+        let mut header = vec!["activity_id", "lap"];
+        if options.lap_summaries {
+            header.push("row");
+        }
+        header.extend(
+            options
+                .columns
+                .iter()
+                .map(|column| match (column, projector) {
+                    (CsvColumn::Latitude, Some(_)) => "y",
+                    (CsvColumn::Longitude, Some(_)) => "x",
+                    _ => column.heading(),
+                }),
+        );
+        writeln!(writer, "{}", header.join(","))?;
+
+        for activity in self.refreshed().all_activities() {
+            let id = quote(&activity.id);
+            for (i, lap) in activity.laps.iter().enumerate() {
+                let prefix = if options.lap_summaries {
+                    format!("{},{},trackpoint", id, i + 1)
+                } else {
+                    format!("{},{}", id, i + 1)
+                };
+                for point in lap.trackpoints() {
+                    let fields: Vec<String> = options
+                        .columns
+                        .iter()
+                        .map(|column| point_field(point, *column, projector))
+                        .collect();
+                    writeln!(writer, "{},{}", prefix, fields.join(","))?;
+                }
+                if options.lap_summaries {
+                    let fields: Vec<String> = options
+                        .columns
+                        .iter()
+                        .map(|column| lap_field(lap, *column))
+                        .collect();
+                    writeln!(writer, "{},{},lap,{}", id, i + 1, fields.join(","))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes one CSV row per lap of every activity: start, duration, distance, average and maximum heart rate,
+    /// average power, ascent and average temperature. Lap numbers start at 1. Values that were not recorded are left empty.
+    ///
+    /// # Parameters
+    ///
+    /// `writer: &mut W` -- Where to write the CSV.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), std::io::Error>` -- Nothing if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let mut csv = Vec::new();
+    /// tcx.to_lap_csv(&mut csv).unwrap();
+    /// assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 2);
+    /// ```
+    pub fn to_lap_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "{}", LAP_HEADER)?;
+        for activity in self.refreshed().all_activities() {
+            for (i, lap) in activity.laps.iter().enumerate() {
+                let summary = lap.summary();
+                writeln!(
+                    writer,
+                    "{},{},{},{:.1},{:.1},{},{},{},{:.1},{}",
+                    quote(&activity.id),
+                    i + 1,
+                    lap_start(lap).unwrap_or_default(),
+                    lap.total_time_seconds,
+                    lap.distance_meters,
+                    rounded(lap.average_heart_rate.or(summary.average_heart_rate)),
+                    rounded(lap.maximum_heart_rate.or(summary.max_heart_rate)),
+                    rounded(summary.average_power.or_else(|| lap_avg_watts(lap))),
+                    summary.elevation_gain_meters,
+                    tenths(summary.average_temperature),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the lap summary to a CSV file. See `to_lap_csv`.
+    ///
+    /// # Parameters
+    ///
+    /// `filename: &str` -- The name of the CSV file to be produced.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn export_lap_csv(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        self.write_lap_csv_to(&mut std::path::Path::new(filename))
+    }
+
+    /// Writes the lap summary to any sink. See `to_lap_csv`.
+    ///
+    /// # Parameters
+    ///
+    /// `sink: &mut S` -- Where to write, such as a `PathBuf`, a `Vec<u8>` or a `Stream`.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn write_lap_csv_to<S: TcxSink + ?Sized>(
+        &self,
+        sink: &mut S,
+    ) -> Result<(), Box<dyn Error>> {
+        crate::storage::write_with(sink, |mut writer| Ok(self.to_lap_csv(&mut writer)?))
+    }
+
+    /// Writes one CSV row per activity, with totals and averages over all of its laps. See `Activity::summary`.
+    ///
+    /// # Parameters
+    ///
+    /// `writer: &mut W` -- Where to write the CSV.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), std::io::Error>` -- Nothing if OK, Error if not.
+    pub fn to_activity_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "{}", ACTIVITY_HEADER)?;
+        for activity in self.refreshed().all_activities() {
+            let summary = activity.summary();
+            writeln!(
+                writer,
+                "{},{},{},{},{:.1},{:.1},{:.1},{},{},{},{},{:.1},{}",
+                quote(&activity.id),
+                quote(&activity.sport),
+                activity.start_time().map(format_time).unwrap_or_default(),
+                activity.laps.len(),
+                activity
+                    .laps
+                    .iter()
+                    .map(|lap| lap.total_time_seconds)
+                    .sum::<f64>(),
+                summary.moving_seconds,
+                activity
+                    .laps
+                    .iter()
+                    .map(|lap| lap.distance_meters)
+                    .sum::<f64>(),
+                rounded(summary.average_heart_rate),
+                rounded(summary.max_heart_rate),
+                rounded(summary.average_power),
+                rounded(summary.normalized_power),
+                summary.elevation_gain_meters,
+                tenths(summary.average_temperature),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the activity summary to a CSV file. See `to_activity_csv`.
+    ///
+    /// # Parameters
+    ///
+    /// `filename: &str` -- The name of the CSV file to be produced.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn export_activity_csv(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        self.write_activity_csv_to(&mut std::path::Path::new(filename))
+    }
+
+    /// Writes the activity summary to any sink. See `to_activity_csv`.
+    ///
+    /// # Parameters
+    ///
+    /// `sink: &mut S` -- Where to write, such as a `PathBuf`, a `Vec<u8>` or a `Stream`.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn write_activity_csv_to<S: TcxSink + ?Sized>(
+        &self,
+        sink: &mut S,
+    ) -> Result<(), Box<dyn Error>> {
+        crate::storage::write_with(sink, |mut writer| Ok(self.to_activity_csv(&mut writer)?))
+    }
+
+    fn all_activities(&self) -> impl Iterator<Item = &Activity> {
+        self.activities
+            .iter()
+            .flat_map(|activities| activities.activities.iter())
+    }
+}
+
+/// Formats one column of a Trackpoint row, with the position reprojected if there is a projector.
+fn point_field(point: &Trackpoint, column: CsvColumn, projector: Option<&Projector>) -> String {
+    let tpx = point.extensions.as_ref().and_then(|ext| ext.tpx.as_ref());
+    let projected = || {
+        let position = point.position.as_ref()?;
+        match projector {
+            Some(projector) => projector.project(position),
+            None => Some((position.longitude, position.latitude)),
+        }
+    };
+    let value = match column {
+        CsvColumn::Timestamp => return format_time(point.time),
+        CsvColumn::Latitude => projected().map(|(_, y)| y),
+        CsvColumn::Longitude => projected().map(|(x, _)| x),
+        CsvColumn::Altitude => point.altitude_meters,
+        CsvColumn::Distance => point.distance_meters,
+        CsvColumn::HeartRate => point.heart_rate.as_ref().map(|hr| hr.value),
+        CsvColumn::Cadence => point
+            .cadence
+            .or_else(|| tpx.and_then(|tpx| tpx.run_cadence))
+            .map(f64::from),
+        CsvColumn::Watts => tpx.and_then(|tpx| tpx.watts).map(f64::from),
+        CsvColumn::Speed => tpx.and_then(|tpx| tpx.speed),
+        CsvColumn::Temperature => point.extensions.as_ref().and_then(|ext| ext.temperature),
+    };
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// Formats one column of a lap summary row: the lap's start, distance, averages and average speed. Position and
+/// altitude are left empty.
+fn lap_field(lap: &ActivityLap, column: CsvColumn) -> String {
+    match column {
+        CsvColumn::Timestamp => lap_start(lap).unwrap_or_default(),
+        CsvColumn::Latitude | CsvColumn::Longitude | CsvColumn::Altitude => String::new(),
+        CsvColumn::Distance => format!("{:.1}", lap.distance_meters),
+        CsvColumn::HeartRate => rounded(
+            lap.average_heart_rate
+                .or_else(|| lap.summary().average_heart_rate),
+        ),
+        CsvColumn::Cadence => rounded(
+            lap.cadence
+                .map(f64::from)
+                .or_else(|| lap.summary().average_cadence),
+        ),
+        CsvColumn::Watts => rounded(lap.summary().average_power.or_else(|| lap_avg_watts(lap))),
+        CsvColumn::Speed if lap.total_time_seconds > 0.0 => {
+            format!("{:.3}", lap.distance_meters / lap.total_time_seconds)
+        }
+        CsvColumn::Speed => String::new(),
+        CsvColumn::Temperature => tenths(lap.summary().average_temperature),
+    }
+}
+
+fn lap_start(lap: &ActivityLap) -> Option<String> {
+    lap.start_time
+        .or_else(|| lap.trackpoints().next().map(|point| point.time))
+        .map(format_time)
+}
+
+/// Average power from the lap's LX extension, for laps without power in their Trackpoints.
+fn lap_avg_watts(lap: &ActivityLap) -> Option<f64> {
+    lap.extensions
+        .as_ref()
+        .and_then(|ext| ext.lx.as_ref())
+        .and_then(|lx| lx.avg_watts)
+        .map(f64::from)
+}
+
+fn rounded(value: Option<f64>) -> String {
+    value
+        .map(|value| format!("{:.0}", value))
+        .unwrap_or_default()
+}
+
+fn tenths(value: Option<f64>) -> String {
+    value
+        .map(|value| format!("{:.1}", value))
+        .unwrap_or_default()
+}
+
+/// Quotes a field if it contains a comma, quote or line break.
+pub(crate) fn quote(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,387 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Machine-learning datasets: activities cut into fixed-length windows of resampled, normalized samples, written
+//! as JSON lines or as one set of stacked arrays in the manner of a NumPy `.npz` archive.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::io::Write;
+
+use crate::sampling::Channel;
+use crate::series::ActivitySeries;
+use crate::tcx::Activity;
+
+/// How the samples are scaled before they are written.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Normalization {
+    /// Values are written as recorded.
+    None,
+
+    /// Each column is shifted by its mean and divided by its standard deviation.
+    #[default]
+    ZScore,
+
+    /// Each column is scaled to lie between zero and one.
+    MinMax,
+}
+
+/// How the windows are laid out in the output.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DatasetFormat {
+    /// One JSON object per line and window, holding its samples as rows of column values.
+    #[default]
+    JsonLines,
+
+    /// One JSON object holding named arrays, as a NumPy `.npz` archive would: the samples of every window as one
+    /// flat array in `[window, sample, column]` order with its `shape`, and the activity, sport and start time of
+    /// each window.
+    Stacked,
+}
+
+/// What goes into a dataset and how it is cut, scaled and written.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DatasetSchema {
+    /// The channels to write, in column order. `Channel::Position` adds two columns, latitude and longitude.
+    /// Defaults to heart rate, cadence, speed, power and altitude.
+    pub channels: Vec<Channel>,
+
+    /// Seconds between samples after resampling. Defaults to 1.
+    pub interval_seconds: f64,
+
+    /// Number of samples in each window. Defaults to 60.
+    pub window_samples: usize,
+
+    /// Number of samples from the start of one window to the start of the next. Equal to `window_samples` for
+    /// windows that do not overlap, which is the default.
+    pub stride_samples: usize,
+
+    /// The longest gap in the recording, in seconds, to interpolate across when resampling. Defaults to 10.
+    pub max_gap_seconds: f64,
+
+    /// The largest share of a window's values, from 0 to 1, that may be missing before the window is left out.
+    /// Missing values in the windows that are kept are written as zero, after normalization. Defaults to 0.
+    pub max_missing: f64,
+
+    /// How the values are scaled. Defaults to `Normalization::ZScore`.
+    pub normalization: Normalization,
+
+    /// How the windows are written. Defaults to `DatasetFormat::JsonLines`.
+    pub format: DatasetFormat,
+}
+
+impl Default for DatasetSchema {
+    fn default() -> Self {
+        DatasetSchema {
+            channels: vec![
+                Channel::HeartRate,
+                Channel::Cadence,
+                Channel::Speed,
+                Channel::Power,
+                Channel::Altitude,
+            ],
+            interval_seconds: 1.0,
+            window_samples: 60,
+            stride_samples: 60,
+            max_gap_seconds: 10.0,
+            max_missing: 0.0,
+            normalization: Normalization::default(),
+            format: DatasetFormat::default(),
+        }
+    }
+}
+
+/// The statistics of one column over every window written, as used for its normalization.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ColumnStats {
+    /// Name of the column.
+    pub name: String,
+
+    /// Mean of the recorded values.
+    pub mean: f64,
+
+    /// Population standard deviation of the recorded values.
+    pub std_dev: f64,
+
+    /// Smallest recorded value.
+    pub min: f64,
+
+    /// Largest recorded value.
+    pub max: f64,
+}
+
+/// A description of a written dataset, to keep alongside it so that predictions can be scaled back.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DatasetSummary {
+    /// Number of windows written.
+    pub windows: usize,
+
+    /// Number of windows left out because too many of their values were missing.
+    pub skipped_windows: usize,
+
+    /// How the values were scaled.
+    pub normalization: Normalization,
+
+    /// The columns, in the order they were written.
+    pub columns: Vec<ColumnStats>,
+}
+
+/// One window in the JSON lines output.
+#[derive(Serialize)]
+struct WindowRecord<'a> {
+    activity_id: &'a str,
+    sport: &'a str,
+    start_time: DateTime<Utc>,
+    values: Vec<Vec<f64>>,
+}
+
+/// Every window in the stacked output.
+#[derive(Serialize)]
+struct StackedRecord<'a> {
+    columns: Vec<&'a str>,
+    shape: [usize; 3],
+    data: Vec<f64>,
+    activity_ids: Vec<&'a str>,
+    sports: Vec<&'a str>,
+    start_times: Vec<DateTime<Utc>>,
+}
+
+/// A window cut from one activity's resampled columns.
+struct Window {
+    activity: usize,
+    start_time: DateTime<Utc>,
+    values: Vec<Vec<Option<f64>>>,
+}
+
+/// Names of the columns a channel adds.
+fn column_names(channel: Channel) -> &'static [&'static str] {
+    match channel {
+        Channel::Position => &["latitude", "longitude"],
+        Channel::Altitude => &["altitude"],
+        Channel::Distance => &["distance"],
+        Channel::HeartRate => &["heart_rate"],
+        Channel::Cadence => &["cadence"],
+        Channel::Speed => &["speed"],
+        Channel::Power => &["power"],
+    }
+}
+
+/// Values of the columns a channel adds.
+fn column_values(series: &ActivitySeries, channel: Channel) -> Vec<&[Option<f64>]> {
+    match channel {
+        Channel::Position => vec![&series.latitude, &series.longitude],
+        Channel::Altitude => vec![&series.altitude],
+        Channel::Distance => vec![&series.distance],
+        Channel::HeartRate => vec![&series.heart_rate],
+        Channel::Cadence => vec![&series.cadence],
+        Channel::Speed => vec![&series.speed],
+        Channel::Power => vec![&series.power],
+    }
+}
+
+/// Computes the statistics of one column over the windows.
+fn column_stats(name: &str, windows: &[Window], column: usize) -> ColumnStats {
+    let values: Vec<f64> = windows
+        .iter()
+        .flat_map(|window| window.values.iter().filter_map(|row| row[column]))
+        .collect();
+    if values.is_empty() {
+        return ColumnStats {
+            name: name.to_string(),
+            mean: 0.0,
+            std_dev: 0.0,
+            min: 0.0,
+            max: 0.0,
+        };
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / values.len() as f64;
+    ColumnStats {
+        name: name.to_string(),
+        mean,
+        std_dev: variance.sqrt(),
+        min: values.iter().cloned().fold(f64::INFINITY, f64::min),
+        max: values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+    }
+}
+
+/// Scales a value as the normalization asks. Columns that do not vary become zero.
+fn normalize(value: Option<f64>, stats: &ColumnStats, normalization: Normalization) -> f64 {
+    let value = match value {
+        Some(value) => value,
+        None => return 0.0,
+    };
+    match normalization {
+        Normalization::None => value,
+        Normalization::ZScore if stats.std_dev > 0.0 => (value - stats.mean) / stats.std_dev,
+        Normalization::MinMax if stats.max > stats.min => {
+            (value - stats.min) / (stats.max - stats.min)
+        }
+        _ => 0.0,
+    }
+}
+
+/// Turns activities into a machine-learning dataset in one call: each activity is resampled to a fixed interval,
+/// cut into fixed-length windows, and the windows of every activity are normalized together and written out.
+/// Windows missing too many values (such as where a sensor dropped out) are left out.
+///
+/// # Parameters
+///
+/// `activities: &[Activity]` -- The activities, for example every activity read from an archive of TCX files.
+///
+/// `schema: &DatasetSchema` -- The channels, window size, normalization and output format.
+///
+/// `writer: &mut W` -- Where to write the dataset.
+///
+/// # Returns
+///
+/// `Result<DatasetSummary, std::io::Error>` -- The number of windows and the statistics used to normalize each
+/// column if OK, Error if not.
+///
+/// # Example
+///
+/// ```rust
+/// use crate::tcx::dataset::{export_ml_dataset, DatasetSchema};
+/// use crate::tcx::sampling::Channel;
+///
+/// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+/// let schema = DatasetSchema {
+///     channels: vec![Channel::HeartRate, Channel::Power],
+///     ..Default::default()
+/// };
+/// let mut output = Vec::new();
+/// let summary = export_ml_dataset(&tcx.activities.unwrap().activities, &schema, &mut output).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap().lines().count(), summary.windows);
+/// assert_eq!(summary.columns[1].name, "power");
+/// ```
+pub fn export_ml_dataset<W: Write>(
+    activities: &[Activity],
+    schema: &DatasetSchema,
+    writer: &mut W,
+) -> std::io::Result<DatasetSummary> {
+    let names: Vec<&str> = schema
+        .channels
+        .iter()
+        .flat_map(|channel| column_names(*channel).iter().cloned())
+        .collect();
+    let window_samples = schema.window_samples.max(1);
+    let stride_samples = schema.stride_samples.max(1);
+
+    let mut windows = Vec::new();
+    let mut skipped_windows = 0;
+    for (index, activity) in activities.iter().enumerate() {
+        let series = activity
+            .series()
+            .resample(schema.interval_seconds, schema.max_gap_seconds);
+        let columns: Vec<&[Option<f64>]> = schema
+            .channels
+            .iter()
+            .flat_map(|channel| column_values(&series, *channel))
+            .collect();
+        let mut start = 0;
+        while start + window_samples <= series.len() {
+            let values: Vec<Vec<Option<f64>>> = (start..start + window_samples)
+                .map(|sample| columns.iter().map(|column| column[sample]).collect())
+                .collect();
+            let missing = values
+                .iter()
+                .flatten()
+                .filter(|value| value.is_none())
+                .count();
+            if missing as f64 > schema.max_missing * (window_samples * names.len()) as f64 {
+                skipped_windows += 1;
+            } else {
+                windows.push(Window {
+                    activity: index,
+                    start_time: series.time[start],
+                    values,
+                });
+            }
+            start += stride_samples;
+        }
+    }
+
+    let stats: Vec<ColumnStats> = names
+        .iter()
+        .enumerate()
+        .map(|(column, name)| column_stats(name, &windows, column))
+        .collect();
+    let scaled = |window: &Window| -> Vec<Vec<f64>> {
+        window
+            .values
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .zip(&stats)
+                    .map(|(value, stats)| normalize(*value, stats, schema.normalization))
+                    .collect()
+            })
+            .collect()
+    };
+
+    match schema.format {
+        DatasetFormat::JsonLines => {
+            for window in &windows {
+                let activity = &activities[window.activity];
+                let record = WindowRecord {
+                    activity_id: &activity.id,
+                    sport: &activity.sport,
+                    start_time: window.start_time,
+                    values: scaled(window),
+                };
+                serde_json::to_writer(&mut *writer, &record)?;
+                writeln!(writer)?;
+            }
+        }
+        DatasetFormat::Stacked => {
+            let record = StackedRecord {
+                columns: names.clone(),
+                shape: [windows.len(), window_samples, names.len()],
+                data: windows
+                    .iter()
+                    .flat_map(|window| scaled(window).concat())
+                    .collect(),
+                activity_ids: windows
+                    .iter()
+                    .map(|window| activities[window.activity].id.as_str())
+                    .collect(),
+                sports: windows
+                    .iter()
+                    .map(|window| activities[window.activity].sport.as_str())
+                    .collect(),
+                start_times: windows.iter().map(|window| window.start_time).collect(),
+            };
+            serde_json::to_writer(&mut *writer, &record)?;
+            writeln!(writer)?;
+        }
+    }
+
+    Ok(DatasetSummary {
+        windows: windows.len(),
+        skipped_windows,
+        normalization: schema.normalization,
+        columns: stats,
+    })
+}
@@ -0,0 +1,143 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Daylight: how much of an activity took place between sunrise and sunset, using the NOAA solar position
+//! equations.
+
+use chrono::{DateTime, Timelike, Utc};
+
+use crate::geo::seconds_between;
+use crate::tcx::{Activity, Position};
+
+/// Solar elevation, in degrees, at which the sun's upper edge is on the horizon, allowing for atmospheric
+/// refraction. This is the elevation used for published sunrise and sunset times.
+pub const SUNRISE_ELEVATION_DEGREES: f64 = -0.833;
+
+/// Computes the elevation of the sun above the horizon, following NOAA's solar calculator. Refraction is not
+/// included; compare against `SUNRISE_ELEVATION_DEGREES` to decide whether it is daytime.
+///
+/// # Parameters
+///
+/// `time: DateTime<Utc>` -- The time of the observation.
+///
+/// `position: &Position` -- Where the observation is made.
+///
+/// # Returns
+///
+/// `f64` -- The geometric elevation of the center of the sun, in degrees. Negative below the horizon.
+///
+/// # Example
+///
+/// ```rust
+/// let noon = chrono::DateTime::parse_from_rfc3339("2021-06-21T12:00:00Z").unwrap().with_timezone(&chrono::Utc);
+/// let greenwich = crate::tcx::Position { latitude: 51.48, longitude: 0.0 };
+/// let elevation = crate::tcx::daylight::solar_elevation(noon, &greenwich);
+/// assert!((elevation - 61.95).abs() < 0.2);
+/// ```
+pub fn solar_elevation(time: DateTime<Utc>, position: &Position) -> f64 {
+    let julian_day = time.timestamp() as f64 / 86400.0 + 2440587.5;
+    let century = (julian_day - 2451545.0) / 36525.0;
+
+    let mean_longitude =
+        (280.46646 + century * (36000.76983 + century * 0.0003032)).rem_euclid(360.0);
+    let mean_anomaly = 357.52911 + century * (35999.05029 - 0.0001537 * century);
+    let eccentricity = 0.016708634 - century * (0.000042037 + 0.0000001267 * century);
+    let anomaly = mean_anomaly.to_radians();
+    let equation_of_center = anomaly.sin() * (1.914602 - century * (0.004817 + 0.000014 * century))
+        + (2.0 * anomaly).sin() * (0.019993 - 0.000101 * century)
+        + (3.0 * anomaly).sin() * 0.000289;
+    let omega = (125.04 - 1934.136 * century).to_radians();
+    let apparent_longitude =
+        (mean_longitude + equation_of_center - 0.00569 - 0.00478 * omega.sin()).to_radians();
+    let mean_obliquity = 23.0
+        + (26.0 + (21.448 - century * (46.815 + century * (0.00059 - century * 0.001813))) / 60.0)
+            / 60.0;
+    let obliquity = (mean_obliquity + 0.00256 * omega.cos()).to_radians();
+    let declination = (obliquity.sin() * apparent_longitude.sin()).asin();
+
+    let y = (obliquity / 2.0).tan().powi(2);
+    let longitude = mean_longitude.to_radians();
+    let equation_of_time_minutes = 4.0
+        * (y * (2.0 * longitude).sin() - 2.0 * eccentricity * anomaly.sin()
+            + 4.0 * eccentricity * y * anomaly.sin() * (2.0 * longitude).cos()
+            - 0.5 * y * y * (4.0 * longitude).sin()
+            - 1.25 * eccentricity * eccentricity * (2.0 * anomaly).sin())
+        .to_degrees();
+
+    let minutes = time.num_seconds_from_midnight() as f64 / 60.0;
+    let true_solar_time =
+        (minutes + equation_of_time_minutes + 4.0 * position.longitude).rem_euclid(1440.0);
+    let hour_angle = (true_solar_time / 4.0 - 180.0).to_radians();
+
+    let latitude = position.latitude.to_radians();
+    let cos_zenith =
+        latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees()
+}
+
+impl Activity {
+    /// Computes the fraction of the activity's elapsed time that took place in daylight, between sunrise and
+    /// sunset. Each interval between consecutive Trackpoints counts as daylight if the sun was up at its start,
+    /// at the most recently recorded position.
+    ///
+    /// # Parameters
+    ///
+    /// None. `&self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// `Option<f64>` -- A value from 0 (entirely after sunset or before sunrise) to 1 (entirely in daylight), or
+    /// `None` if the activity has no positions or no duration.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// // An evening run in Florida in January, after sunset.
+    /// assert_eq!(activity.daylight_fraction(), Some(0.0));
+    /// ```
+    pub fn daylight_fraction(&self) -> Option<f64> {
+        let mut position: Option<&Position> = None;
+        let mut daylight_seconds = 0.0;
+        let mut total_seconds = 0.0;
+        let mut points = self.trackpoints().peekable();
+        while let Some(point) = points.next() {
+            if point.position.is_some() {
+                position = point.position.as_ref();
+            }
+            let (next, position) = match (points.peek(), position) {
+                (Some(next), Some(position)) => (next, position),
+                _ => continue,
+            };
+            let seconds = seconds_between(point, next).max(0.0);
+            total_seconds += seconds;
+            if solar_elevation(point.time, position) > SUNRISE_ELEVATION_DEGREES {
+                daylight_seconds += seconds;
+            }
+        }
+        if total_seconds > 0.0 {
+            Some(daylight_seconds / total_seconds)
+        } else {
+            None
+        }
+    }
+}
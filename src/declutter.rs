@@ -0,0 +1,120 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Thinning out a course's course points, since devices struggle with courses carrying hundreds of cues and a
+//! rider cannot act on cues a few meters apart anyway.
+
+use crate::geo::haversine_distance;
+use crate::tcx::{Course, CoursePoint, CoursePointType};
+
+/// How much a course point matters when two are too close together: safety first, then turns, then supplies, then
+/// terrain, then the rest.
+fn priority(point: &CoursePoint) -> u8 {
+    match point.point_type {
+        Some(CoursePointType::Danger) | Some(CoursePointType::FirstAid) => 5,
+        Some(CoursePointType::Left) | Some(CoursePointType::Right) => 4,
+        Some(CoursePointType::Water) | Some(CoursePointType::Food) => 3,
+        Some(CoursePointType::Summit)
+        | Some(CoursePointType::Valley)
+        | Some(CoursePointType::FourthCategory)
+        | Some(CoursePointType::ThirdCategory)
+        | Some(CoursePointType::SecondCategory)
+        | Some(CoursePointType::FirstCategory)
+        | Some(CoursePointType::HorsCategory)
+        | Some(CoursePointType::Sprint) => 2,
+        Some(CoursePointType::Straight) => 1,
+        Some(CoursePointType::Generic) | None => 0,
+    }
+}
+
+/// Folds a dropped course point into the one kept in its place, which takes the name and notes it lacks.
+fn merge(kept: &mut CoursePoint, dropped: CoursePoint) {
+    if kept.name.is_none() {
+        kept.name = dropped.name;
+    }
+    if kept.notes.is_none() {
+        kept.notes = dropped.notes;
+    }
+}
+
+impl Course {
+    /// Thins out course points closer together than `min_spacing_meters`. Going through the points in order, a point
+    /// too close to the last one kept is merged into it, or replaces it if its type matters more (`Danger` and
+    /// `FirstAid`, then `Left` and `Right`, then `Water` and `Food`, then terrain and sprints, then `Straight`,
+    /// then the rest); on a tie the earlier point is kept. The kept point takes the name and notes it lacks from the
+    /// point merged into it. Points without a position are always kept.
+    ///
+    /// # Parameters
+    ///
+    /// `min_spacing_meters: f64` -- The closest two course points may be, in meters.
+    ///
+    /// # Returns
+    ///
+    /// `usize` -- The number of course points removed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210601_course_lake_loop.tcx").unwrap();
+    /// let course = &mut tcx.courses.as_mut().unwrap().courses[0];
+    /// let before = course.course_points.len();
+    /// assert_eq!(course.thin_points(1000.0), before - 1);
+    /// assert_eq!(course.course_points[0].point_type, Some(crate::tcx::CoursePointType::Water));
+    /// ```
+    pub fn thin_points(&mut self, min_spacing_meters: f64) -> usize {
+        let before = self.course_points.len();
+        let mut kept: Vec<CoursePoint> = Vec::with_capacity(before);
+        for point in std::mem::take(&mut self.course_points) {
+            let positioned = point.position.is_some();
+            kept.push(point);
+            if !positioned {
+                continue;
+            }
+
+            // Settle the new point against the last positioned point kept before it; if the new point wins, it may
+            // in turn be too close to the one before that.
+            let mut index = kept.len() - 1;
+            while let Some(previous) = kept[..index]
+                .iter()
+                .rposition(|kept| kept.position.is_some())
+            {
+                let spacing = haversine_distance(
+                    kept[previous].position.as_ref().unwrap(),
+                    kept[index].position.as_ref().unwrap(),
+                );
+                if spacing >= min_spacing_meters {
+                    break;
+                }
+                if priority(&kept[index]) > priority(&kept[previous]) {
+                    let dropped = kept.remove(previous);
+                    index -= 1;
+                    merge(&mut kept[index], dropped);
+                } else {
+                    let dropped = kept.remove(index);
+                    merge(&mut kept[previous], dropped);
+                    break;
+                }
+            }
+        }
+        self.course_points = kept;
+        before - self.course_points.len()
+    }
+}
@@ -0,0 +1,199 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Checking a course's turn directions against its track, to catch courses that were reversed or mis-edited before
+//! a device announces "turn left" at a right turn.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::{haversine_distance, initial_bearing};
+use crate::tcx::{Course, CoursePointType, Position};
+
+/// How far, in meters, before and after a course point the track is followed to measure the turn there.
+const TURN_LOOK_METERS: f64 = 25.0;
+
+/// The smallest change of heading, in degrees, that counts as a turn.
+const MIN_TURN_DEGREES: f64 = 30.0;
+
+/// How far, in meters, a course point may be from the track before it is considered off it.
+const OFF_TRACK_METERS: f64 = 50.0;
+
+/// What is wrong with a course point's direction.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectionProblem {
+    /// The track turns the other way.
+    OppositeTurn,
+    /// The point says to turn, but the track carries on more or less straight.
+    NoTurn,
+    /// The point says to go straight, but the track turns.
+    UnexpectedTurn,
+    /// The point is too far from the track to check.
+    OffTrack,
+}
+
+/// A `Left`, `Right` or `Straight` course point that does not match the track.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DirectionMismatch {
+    /// Index of the point in `Course::course_points`.
+    pub course_point: usize,
+
+    /// The point's name, if any.
+    pub name: Option<String>,
+
+    /// The direction the point gives.
+    pub point_type: CoursePointType,
+
+    /// The turn the track makes at the point, in degrees: positive to the right, negative to the left. `None` if
+    /// the point is off the track.
+    pub turn_degrees: Option<f64>,
+
+    /// Distance from the point to the nearest position on the track, in meters.
+    pub distance_from_track_meters: f64,
+
+    /// What is wrong.
+    pub problem: DirectionProblem,
+}
+
+impl Course {
+    /// Checks every `Left`, `Right` and `Straight` course point against the track: the heading is measured over
+    /// the 25 meters before and after the nearest Trackpoint, and the change tells which way the track turns there.
+    /// Where the track passes a point more than once, as on an out-and-back, the pass closest in time is used.
+    ///
+    /// # Parameters
+    ///
+    /// None. `&self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<DirectionMismatch>` -- The points that do not match, in course point order. Empty if the course has no
+    /// track.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210601_course_lake_loop.tcx").unwrap();
+    /// let course = &tcx.courses.unwrap().courses[0];
+    /// assert!(course.validate_direction().iter().all(|mismatch| mismatch.course_point < course.course_points.len()));
+    /// ```
+    pub fn validate_direction(&self) -> Vec<DirectionMismatch> {
+        // The track's positions, with their times and the distance along the track to each.
+        let mut track: Vec<(DateTime<Utc>, &Position, f64)> = Vec::new();
+        let mut along = 0.0;
+        for point in self
+            .tracks
+            .iter()
+            .flatten()
+            .flat_map(|track| track.trackpoints.iter())
+        {
+            if let Some(position) = &point.position {
+                if let Some((_, previous, previous_along)) = track.last() {
+                    along = previous_along + haversine_distance(previous, position);
+                }
+                track.push((point.time, position, along));
+            }
+        }
+        if track.len() < 2 {
+            return Vec::new();
+        }
+
+        let mut mismatches = Vec::new();
+        for (index, course_point) in self.course_points.iter().enumerate() {
+            let point_type = match course_point.point_type {
+                Some(
+                    point_type @ (CoursePointType::Left
+                    | CoursePointType::Right
+                    | CoursePointType::Straight),
+                ) => point_type,
+                _ => continue,
+            };
+            let position = match &course_point.position {
+                Some(position) => position,
+                None => continue,
+            };
+
+            // The nearest pass, or of the passes near enough, the one closest in time.
+            let distances: Vec<f64> = track
+                .iter()
+                .map(|(_, track_position, _)| haversine_distance(position, track_position))
+                .collect();
+            let nearest = (0..track.len())
+                .min_by(|&a, &b| distances[a].total_cmp(&distances[b]))
+                .unwrap();
+            let nearest = (0..track.len())
+                .filter(|&i| distances[i] <= OFF_TRACK_METERS)
+                .min_by_key(|&i| (track[i].0 - course_point.time).num_milliseconds().abs())
+                .unwrap_or(nearest);
+
+            let mut mismatch = DirectionMismatch {
+                course_point: index,
+                name: course_point.name.clone(),
+                point_type,
+                turn_degrees: None,
+                distance_from_track_meters: distances[nearest],
+                problem: DirectionProblem::OffTrack,
+            };
+            if distances[nearest] > OFF_TRACK_METERS {
+                mismatches.push(mismatch);
+                continue;
+            }
+
+            let at = track[nearest].2;
+            let before = track[..=nearest]
+                .iter()
+                .rev()
+                .find(|(_, _, distance)| at - distance >= TURN_LOOK_METERS)
+                .unwrap_or(&track[0]);
+            let after = track[nearest..]
+                .iter()
+                .find(|(_, _, distance)| distance - at >= TURN_LOOK_METERS)
+                .unwrap_or(&track[track.len() - 1]);
+            let turn = match turn_degrees(before.1, track[nearest].1, after.1) {
+                Some(turn) => turn,
+                None => continue,
+            };
+            mismatch.turn_degrees = Some(turn);
+            mismatch.problem = match point_type {
+                CoursePointType::Straight if turn.abs() >= MIN_TURN_DEGREES => {
+                    DirectionProblem::UnexpectedTurn
+                }
+                CoursePointType::Left | CoursePointType::Right if turn.abs() < MIN_TURN_DEGREES => {
+                    DirectionProblem::NoTurn
+                }
+                CoursePointType::Left if turn > 0.0 => DirectionProblem::OppositeTurn,
+                CoursePointType::Right if turn < 0.0 => DirectionProblem::OppositeTurn,
+                _ => continue,
+            };
+            mismatches.push(mismatch);
+        }
+        mismatches
+    }
+}
+
+/// The change of heading, in degrees from -180 to 180 with right turns positive, from travelling `before` to `at`
+/// to travelling `at` to `after`. `None` at either end of the track, where there is no heading on one side.
+fn turn_degrees(before: &Position, at: &Position, after: &Position) -> Option<f64> {
+    if before == at || at == after {
+        return None;
+    }
+    let change = initial_bearing(at, after) - initial_bearing(before, at);
+    Some((change + 180.0).rem_euclid(360.0) - 180.0)
+}
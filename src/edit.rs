@@ -0,0 +1,672 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Editing recordings: cropping, splitting and merging activities. Lap totals (`TotalTimeSeconds`,
+//! `DistanceMeters` and `Calories`) are scaled to match the Trackpoints that remain after each edit. Individual
+//! Trackpoints can be edited too, in which case the laps affected are marked with `summary_dirty` and their totals
+//! recomputed from the Trackpoints when next needed.
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::geo::seconds_between;
+use crate::tcx::{
+    Activity, ActivityLap, Extensions, HeartRate, Track, Trackpoint, TrainingCenterDatabase,
+};
+
+/// How far apart, in seconds, Trackpoints from two devices may be and still be treated as the same moment.
+const MERGE_TOLERANCE_SECONDS: i64 = 2;
+
+/// The span of a lap's Trackpoints, taken before an edit so the lap's totals can be scaled afterwards.
+#[derive(Clone)]
+struct LapExtent {
+    first_time: DateTime<Utc>,
+    seconds: f64,
+    distance: Option<f64>,
+}
+
+impl Activity {
+    /// Removes every Trackpoint outside the given time range, such as the minutes spent waiting for a start or
+    /// walking home after forgetting to stop the recording. Laps left without Trackpoints are removed, and the
+    /// totals of shortened laps are scaled down. Distances are rebased to start from zero, and an `Id` holding the
+    /// old start time is updated to the new one.
+    ///
+    /// # Parameters
+    ///
+    /// `start: DateTime<Utc>` -- The earliest time to keep.
+    ///
+    /// `end: DateTime<Utc>` -- The latest time to keep.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+    /// let start = activity.start_time().unwrap();
+    /// activity.crop(start + chrono::Duration::minutes(2), start + chrono::Duration::minutes(12));
+    /// assert!((activity.laps[0].total_time_seconds - 600.0).abs() < 5.0);
+    /// ```
+    pub fn crop(&mut self, start: DateTime<Utc>, end: DateTime<Utc>) {
+        let old_start = self.start_time();
+        for lap in self.laps.iter_mut() {
+            let before = extent(lap);
+            for track in lap.tracks.iter_mut() {
+                track
+                    .trackpoints
+                    .retain(|point| point.time >= start && point.time <= end);
+            }
+            lap.tracks.retain(|track| !track.trackpoints.is_empty());
+            rescale(lap, before);
+        }
+        self.laps.retain(|lap| match lap.trackpoints().next() {
+            Some(_) => true,
+            None => lap
+                .start_time
+                .is_some_and(|time| time >= start && time <= end),
+        });
+        self.rebase_distances();
+        self.refresh_id(old_start);
+    }
+
+    /// Splits the activity in two at the given time, such as when the recording ran on from one workout into the
+    /// next. Trackpoints before `time` stay in this activity and the rest move to the returned one. A lap that
+    /// spans the split is divided, with its totals shared between the halves.
+    ///
+    /// # Parameters
+    ///
+    /// `time: DateTime<Utc>` -- The first time that belongs to the second activity.
+    ///
+    /// # Returns
+    ///
+    /// `Option<Activity>` -- The second activity, or `None` (leaving this one unchanged) if there are no
+    /// Trackpoints on one side of `time`.
+    pub fn split_at(&mut self, time: DateTime<Utc>) -> Option<Activity> {
+        let has_before = self.trackpoints().any(|point| point.time < time);
+        let has_after = self.trackpoints().any(|point| point.time >= time);
+        if !has_before || !has_after {
+            return None;
+        }
+
+        let mut first_laps = Vec::new();
+        let mut second_laps = Vec::new();
+        for mut lap in self.laps.drain(..) {
+            let before = extent(&lap);
+            let tracks = std::mem::take(&mut lap.tracks);
+            let mut later = lap.clone();
+            for track in tracks {
+                let (early, late): (Vec<Trackpoint>, Vec<Trackpoint>) = track
+                    .trackpoints
+                    .into_iter()
+                    .partition(|point| point.time < time);
+                if !early.is_empty() {
+                    lap.tracks.push(Track { trackpoints: early });
+                }
+                if !late.is_empty() {
+                    later.tracks.push(Track { trackpoints: late });
+                }
+            }
+
+            match (lap.tracks.is_empty(), later.tracks.is_empty()) {
+                (false, false) => {
+                    rescale(&mut later, before.clone());
+                    rescale(&mut lap, before);
+                    first_laps.push(lap);
+                    second_laps.push(later);
+                }
+                (false, true) => first_laps.push(lap),
+                (true, false) => second_laps.push(later),
+                (true, true) => {
+                    if lap.start_time.is_some_and(|start| start >= time) {
+                        second_laps.push(lap);
+                    } else {
+                        first_laps.push(lap);
+                    }
+                }
+            }
+        }
+        self.laps = first_laps;
+
+        let mut second = Activity {
+            sport: self.sport.clone(),
+            laps: second_laps,
+            creator: self.creator.clone(),
+            ..Default::default()
+        };
+        second.rebase_distances();
+        if let Some(start) = second.start_time() {
+            second.id = start.to_rfc3339_opts(SecondsFormat::Millis, true);
+        }
+        Some(second)
+    }
+
+    /// Splits the activity into consecutive parts of at most `max_trackpoints` Trackpoints each, such as for upload
+    /// services that reject large files. Parts end on lap boundaries where possible; a lap with more Trackpoints
+    /// than the limit is itself divided, as by `split_at`. The first part keeps this activity's `Id` and notes.
+    ///
+    /// # Parameters
+    ///
+    /// `max_trackpoints: usize` -- The most Trackpoints in any part. Treated as 1 if zero.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<Activity>` -- The parts, in time order. Just a copy of this activity if it is within the limit.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// let parts = activity.split_by_trackpoints(500);
+    /// assert_eq!(parts.len(), 3);
+    /// assert!(parts.iter().all(|part| part.trackpoints().count() <= 500));
+    /// ```
+    pub fn split_by_trackpoints(&self, max_trackpoints: usize) -> Vec<Activity> {
+        let max_trackpoints = max_trackpoints.max(1);
+        let mut parts = Vec::new();
+        let mut rest = self.clone();
+        while rest.trackpoints().count() > max_trackpoints {
+            let time = match split_time(&rest, max_trackpoints) {
+                Some(time) => time,
+                None => break,
+            };
+            match rest.split_at(time) {
+                Some(second) => parts.push(std::mem::replace(&mut rest, second)),
+                None => break,
+            }
+        }
+        parts.push(rest);
+        parts
+    }
+
+    /// Merges in a recording of the same session made by another device, for example heart rate from a watch
+    /// and power from a bike computer. This activity's Trackpoints are kept, and any values they are missing are
+    /// filled from the other recording's Trackpoint nearest in time, if it is within a couple of seconds. Lap
+    /// distances and calories that this recording lacked are then filled in.
+    ///
+    /// # Parameters
+    ///
+    /// `other: &Activity` -- The other device's recording.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn merge(&mut self, other: &Activity) {
+        let mut others: Vec<&Trackpoint> = other.trackpoints().collect();
+        others.sort_by_key(|point| point.time);
+        let had_distances = self
+            .trackpoints()
+            .any(|point| point.distance_meters.is_some());
+        let tolerance = merge_tolerance();
+
+        for point in self.trackpoints_mut() {
+            if let Some(source) = nearest(&others, point.time, tolerance) {
+                fill_missing(point, source, !had_distances);
+            }
+        }
+        if !had_distances {
+            self.rebase_distances();
+        }
+
+        let other_seconds: f64 = other.laps.iter().map(|lap| lap.total_time_seconds).sum();
+        let other_calories: f64 = other.laps.iter().map(|lap| lap.calories as f64).sum();
+        for lap in self.laps.iter_mut() {
+            if lap.calories == 0 && other_seconds > 0.0 {
+                lap.calories =
+                    (other_calories * lap.total_time_seconds / other_seconds).round() as u16;
+            }
+            if lap.distance_meters == 0.0 {
+                let mut points = lap.trackpoints().filter_map(|point| point.distance_meters);
+                if let Some(first) = points.next() {
+                    lap.distance_meters = points.last().unwrap_or(first) - first;
+                }
+            }
+        }
+    }
+
+    /// Shifts the Trackpoint distances so that the first one is zero.
+    fn rebase_distances(&mut self) {
+        let offset = match self.trackpoints().find_map(|point| point.distance_meters) {
+            Some(offset) if offset != 0.0 => offset,
+            _ => return,
+        };
+        for point in self.trackpoints_mut() {
+            if let Some(distance) = point.distance_meters.as_mut() {
+                *distance -= offset;
+            }
+        }
+    }
+
+    /// Updates an `Id` that held the activity's old start time to hold the new one.
+    fn refresh_id(&mut self, old_start: Option<DateTime<Utc>>) {
+        let id_time = DateTime::parse_from_rfc3339(self.id.trim())
+            .ok()
+            .map(|time| time.with_timezone(&Utc));
+        if id_time.is_some() && id_time == old_start {
+            if let Some(start) = self.start_time() {
+                self.id = start.to_rfc3339_opts(SecondsFormat::Millis, true);
+            }
+        }
+    }
+}
+
+impl TrainingCenterDatabase {
+    /// Merges another file into this one. Activities that overlap in time with one of this file's activities are
+    /// merged into it (see `Activity::merge`); the rest are added. Activities are kept sorted by start time.
+    ///
+    /// # Parameters
+    ///
+    /// `other: TrainingCenterDatabase` -- The file to merge in.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn merge(&mut self, other: TrainingCenterDatabase) {
+        let others = match other.activities {
+            Some(activities) => activities.activities,
+            None => return,
+        };
+        let activities = self.activities.get_or_insert_with(Default::default);
+        for other in others {
+            let overlapping = activities
+                .activities
+                .iter_mut()
+                .find(|activity| overlaps(activity, &other));
+            match overlapping {
+                Some(activity) => activity.merge(&other),
+                None => activities.activities.push(other),
+            }
+        }
+        activities
+            .activities
+            .sort_by_key(|activity| activity.start_time());
+    }
+}
+
+impl TrainingCenterDatabase {
+    /// The database with the totals of every edited lap recomputed (see `ActivityLap::refresh_summary`), so that
+    /// exporters never write stale totals. Borrows the database unchanged when no lap has been edited.
+    pub(crate) fn refreshed(&self) -> Cow<'_, TrainingCenterDatabase> {
+        let mut laps = self.activities.iter().flat_map(|activities| {
+            let sessions = activities.multi_sport_sessions.iter();
+            activities
+                .activities
+                .iter()
+                .chain(sessions.clone().flat_map(|session| session.activities()))
+                .flat_map(|activity| activity.laps.iter())
+                .chain(sessions.flat_map(|session| session.transitions()))
+        });
+        if !laps.any(|lap| lap.summary_dirty) {
+            return Cow::Borrowed(self);
+        }
+
+        let mut database = self.clone();
+        if let Some(activities) = database.activities.as_mut() {
+            for activity in activities.activities.iter_mut() {
+                activity.refresh_summaries();
+            }
+            for session in activities.multi_sport_sessions.iter_mut() {
+                session.first_sport.activity.refresh_summaries();
+                for next in session.next_sports.iter_mut() {
+                    next.activity.refresh_summaries();
+                    if let Some(transition) = next.transition.as_mut() {
+                        transition.refresh_summary();
+                    }
+                }
+            }
+        }
+        Cow::Owned(database)
+    }
+}
+
+impl ActivityLap {
+    /// Recomputes the lap's totals from its Trackpoints if they have been edited (see `summary_dirty`): the start
+    /// time, `TotalTimeSeconds` and `DistanceMeters` from the first and last Trackpoints, the maximum speed, the
+    /// heart rate and cadence summaries, and `Calories` in proportion to the new duration.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn refresh_summary(&mut self) {
+        if !self.summary_dirty {
+            return;
+        }
+        let summary = self.summary();
+        if self.total_time_seconds > 0.0 {
+            let ratio = summary.elapsed_seconds / self.total_time_seconds;
+            self.calories = (self.calories as f64 * ratio).round() as u16;
+        }
+        let first_time = self.trackpoints().next().map(|first| first.time);
+        if first_time.is_some() {
+            self.start_time = first_time;
+        }
+        self.total_time_seconds = summary.elapsed_seconds;
+        self.distance_meters = summary.distance_meters;
+        self.maximum_speed = summary.max_speed;
+        self.average_heart_rate = summary.average_heart_rate;
+        self.maximum_heart_rate = summary.max_heart_rate;
+        if self.cadence.is_some() {
+            self.cadence = summary.average_cadence.map(|cadence| cadence.round() as u8);
+        }
+        self.summary_dirty = false;
+    }
+}
+
+impl Activity {
+    /// Sets, or clears, the heart rate of one Trackpoint and marks its lap's totals as needing to be recomputed.
+    ///
+    /// # Parameters
+    ///
+    /// `index: usize` -- The position of the Trackpoint within the activity, counting across laps and tracks.
+    ///
+    /// `bpm: Option<f64>` -- The new heart rate, in beats per minute.
+    ///
+    /// # Returns
+    ///
+    /// `bool` -- True if there is a Trackpoint at `index`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+    /// assert!(activity.set_trackpoint_hr(10, Some(230.0)));
+    /// assert!(activity.laps[0].summary_dirty);
+    /// activity.refresh_summaries();
+    /// assert_eq!(activity.laps[0].maximum_heart_rate, Some(230.0));
+    /// ```
+    pub fn set_trackpoint_hr(&mut self, index: usize, bpm: Option<f64>) -> bool {
+        let (lap, track, position) = match self.locate(index) {
+            Some(location) => location,
+            None => return false,
+        };
+        let lap = &mut self.laps[lap];
+        lap.tracks[track].trackpoints[position].heart_rate = bpm.map(|value| HeartRate { value });
+        lap.summary_dirty = true;
+        true
+    }
+
+    /// Inserts a Trackpoint in time order, into the last lap starting at or before it (or the first lap, if it is
+    /// earlier than all of them), and marks that lap's totals as needing to be recomputed. An activity without laps
+    /// is given one.
+    ///
+    /// # Parameters
+    ///
+    /// `point: Trackpoint` -- The Trackpoint to insert.
+    ///
+    /// # Returns
+    ///
+    /// `usize` -- The position of the new Trackpoint within the activity, counting across laps and tracks.
+    pub fn insert_trackpoint(&mut self, point: Trackpoint) -> usize {
+        if self.laps.is_empty() {
+            self.laps.push(ActivityLap {
+                start_time: Some(point.time),
+                ..Default::default()
+            });
+        }
+        let starts = |lap: &ActivityLap| {
+            lap.trackpoints()
+                .next()
+                .map(|first| first.time)
+                .or(lap.start_time)
+        };
+        let lap_index = self
+            .laps
+            .iter()
+            .rposition(|lap| starts(lap).is_some_and(|start| start <= point.time))
+            .unwrap_or(0);
+        let before: usize = self.laps[..lap_index]
+            .iter()
+            .map(|lap| lap.trackpoints().count())
+            .sum();
+
+        let lap = &mut self.laps[lap_index];
+        lap.summary_dirty = true;
+        if lap.tracks.is_empty() {
+            lap.tracks.push(Track::default());
+        }
+        let track_index = lap
+            .tracks
+            .iter()
+            .rposition(|track| {
+                track
+                    .trackpoints
+                    .first()
+                    .is_some_and(|first| first.time <= point.time)
+            })
+            .unwrap_or(0);
+        let earlier: usize = lap.tracks[..track_index]
+            .iter()
+            .map(|track| track.trackpoints.len())
+            .sum();
+        let trackpoints = &mut lap.tracks[track_index].trackpoints;
+        let position = trackpoints.partition_point(|existing| existing.time <= point.time);
+        trackpoints.insert(position, point);
+        before + earlier + position
+    }
+
+    /// Removes a range of Trackpoints and marks the laps they were in as needing their totals recomputed. Tracks
+    /// left empty are removed; laps are kept.
+    ///
+    /// # Parameters
+    ///
+    /// `range: Range<usize>` -- The positions of the Trackpoints within the activity, counting across laps and
+    /// tracks.
+    ///
+    /// # Returns
+    ///
+    /// `usize` -- The number of Trackpoints removed.
+    pub fn delete_range(&mut self, range: Range<usize>) -> usize {
+        let mut index = 0;
+        let mut removed = 0;
+        for lap in self.laps.iter_mut() {
+            for track in lap.tracks.iter_mut() {
+                let before = track.trackpoints.len();
+                track.trackpoints.retain(|_| {
+                    let keep = !range.contains(&index);
+                    index += 1;
+                    keep
+                });
+                if track.trackpoints.len() < before {
+                    removed += before - track.trackpoints.len();
+                    lap.summary_dirty = true;
+                }
+            }
+            lap.tracks.retain(|track| !track.trackpoints.is_empty());
+        }
+        removed
+    }
+
+    /// Recomputes the totals of every lap whose Trackpoints have been edited. See `ActivityLap::refresh_summary`.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn refresh_summaries(&mut self) {
+        for lap in self.laps.iter_mut() {
+            lap.refresh_summary();
+        }
+    }
+
+    /// Finds the lap, track and position within the track of the Trackpoint at `index`.
+    fn locate(&self, index: usize) -> Option<(usize, usize, usize)> {
+        let mut remaining = index;
+        for (i, lap) in self.laps.iter().enumerate() {
+            for (j, track) in lap.tracks.iter().enumerate() {
+                if remaining < track.trackpoints.len() {
+                    return Some((i, j, remaining));
+                }
+                remaining -= track.trackpoints.len();
+            }
+        }
+        None
+    }
+}
+
+/// Where to split an activity so that the first part has no more than `max_trackpoints` Trackpoints: at the start
+/// of the last lap that fits, or else part way through the first lap.
+fn split_time(activity: &Activity, max_trackpoints: usize) -> Option<DateTime<Utc>> {
+    let mut count = 0;
+    for lap in activity
+        .laps
+        .iter()
+        .skip_while(|lap| lap.trackpoints().next().is_none())
+    {
+        let lap_count = lap.trackpoints().count();
+        if count + lap_count > max_trackpoints {
+            if count > 0 {
+                return lap.trackpoints().next().map(|point| point.time);
+            }
+            break;
+        }
+        count += lap_count;
+    }
+    activity
+        .trackpoints()
+        .nth(max_trackpoints)
+        .map(|point| point.time)
+}
+
+/// How far apart Trackpoints from two devices may be and still be treated as the same moment.
+pub(crate) fn merge_tolerance() -> Duration {
+    Duration::seconds(MERGE_TOLERANCE_SECONDS)
+}
+
+/// Finds the Trackpoint nearest in time to `time`, if it is within `tolerance`. `points` must be sorted by time.
+pub(crate) fn nearest<'a>(
+    points: &[&'a Trackpoint],
+    time: DateTime<Utc>,
+    tolerance: Duration,
+) -> Option<&'a Trackpoint> {
+    let index = points.partition_point(|candidate| candidate.time < time);
+    [index.checked_sub(1), Some(index)]
+        .iter()
+        .flatten()
+        .filter_map(|i| points.get(*i).copied())
+        .min_by_key(|candidate| (candidate.time - time).abs())
+        .filter(|candidate| (candidate.time - time).abs() <= tolerance)
+}
+
+fn extent(lap: &ActivityLap) -> Option<LapExtent> {
+    let mut points = lap.trackpoints();
+    let first = points.next()?;
+    let last = points.last().unwrap_or(first);
+    Some(LapExtent {
+        first_time: first.time,
+        seconds: seconds_between(first, last),
+        distance: match (first.distance_meters, last.distance_meters) {
+            (Some(start), Some(end)) => Some(end - start),
+            _ => None,
+        },
+    })
+}
+
+/// Scales the lap's totals by how much of its span, in time and distance, is left after an edit.
+fn rescale(lap: &mut ActivityLap, before: Option<LapExtent>) {
+    let (before, after) = match (before, extent(lap)) {
+        (Some(before), Some(after)) => (before, after),
+        _ => return,
+    };
+    let time_ratio = if before.seconds > 0.0 {
+        after.seconds / before.seconds
+    } else {
+        1.0
+    };
+    let distance_ratio = match (before.distance, after.distance) {
+        (Some(before), Some(after)) if before > 0.0 => after / before,
+        _ => time_ratio,
+    };
+    lap.total_time_seconds *= time_ratio;
+    lap.distance_meters *= distance_ratio;
+    lap.calories = (lap.calories as f64 * time_ratio).round() as u16;
+    if after.first_time != before.first_time {
+        lap.start_time = Some(after.first_time);
+    }
+}
+
+fn overlaps(a: &Activity, b: &Activity) -> bool {
+    match (time_range(a), time_range(b)) {
+        (Some((a_start, a_end)), Some((b_start, b_end))) => a_start <= b_end && b_start <= a_end,
+        _ => false,
+    }
+}
+
+fn time_range(activity: &Activity) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut points = activity.trackpoints();
+    let first = points.next()?;
+    let last = points.last().unwrap_or(first);
+    Some((first.time, last.time))
+}
+
+/// Copies the values `point` is missing from `source`. Distances are only copied when asked, since distances from
+/// different devices do not line up.
+fn fill_missing(point: &mut Trackpoint, source: &Trackpoint, distance: bool) {
+    if point.position.is_none() {
+        point.position = source.position.clone();
+    }
+    if point.altitude_meters.is_none() {
+        point.altitude_meters = source.altitude_meters;
+    }
+    if distance && point.distance_meters.is_none() {
+        point.distance_meters = source.distance_meters;
+    }
+    let copy_heart_rate = point.heart_rate.is_none() && source.heart_rate.is_some();
+    if copy_heart_rate {
+        point.heart_rate = source.heart_rate.clone();
+    }
+    if point.cadence.is_none() {
+        point.cadence = source.cadence;
+    }
+    if let Some(source) = &source.extensions {
+        let extensions = point.extensions.get_or_insert_with(Extensions::default);
+        if extensions.pressure.is_none() {
+            extensions.pressure = source.pressure;
+        }
+        if extensions.temperature.is_none() {
+            extensions.temperature = source.temperature;
+        }
+        if extensions.hdop.is_none() {
+            extensions.hdop = source.hdop;
+        }
+        if extensions.rr_intervals.is_empty() {
+            extensions.rr_intervals = source.rr_intervals.clone();
+        }
+        if copy_heart_rate {
+            extensions.heart_rate_source = source.heart_rate_source;
+        }
+        if let Some(source) = &source.tpx {
+            let tpx = extensions.tpx.get_or_insert_with(Default::default);
+            if tpx.speed.is_none() {
+                tpx.speed = source.speed;
+            }
+            if tpx.run_cadence.is_none() {
+                tpx.run_cadence = source.run_cadence;
+            }
+            if tpx.watts.is_none() {
+                tpx.watts = source.watts;
+            }
+        }
+    }
+}
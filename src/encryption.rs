@@ -0,0 +1,220 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Encrypted JSON exports, for archives synced through storage that should not be able to read them. Requires the
+//! `encryption` feature.
+//!
+//! An export is the bytes `TCXJ`, a format version byte (1), a random 12 byte nonce and then the JSON sealed with
+//! ChaCha20-Poly1305 under a 32 byte key, which also authenticates the header. Keys are the caller's to generate
+//! and keep, such as with `generate_key`.
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+
+use crate::storage::{TcxSink, TcxSource};
+use crate::tcx::{JsonOptions, TrainingCenterDatabase};
+
+/// The bytes every encrypted export starts with: a marker and the format version.
+const HEADER: &[u8] = b"TCXJ\x01";
+
+/// The length of the nonce that follows the header.
+const NONCE_LENGTH: usize = 12;
+
+/// Why an encrypted export could not be read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecryptionError {
+    /// The data does not start with the header of a supported export.
+    NotEncrypted,
+    /// The key is wrong or the data was changed after it was written.
+    Rejected,
+}
+
+impl fmt::Display for DecryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecryptionError::NotEncrypted => write!(f, "Not an encrypted TCX JSON export"),
+            DecryptionError::Rejected => {
+                write!(f, "Wrong key, or the export has been tampered with")
+            }
+        }
+    }
+}
+
+impl Error for DecryptionError {}
+
+/// Generates a random key from the operating system's random number generator.
+pub fn generate_key() -> [u8; 32] {
+    ChaCha20Poly1305::generate_key(&mut OsRng).into()
+}
+
+impl TrainingCenterDatabase {
+    /// Serializes the database as JSON (see `to_json_writer`) and encrypts it. See the module description for the
+    /// format.
+    ///
+    /// # Parameters
+    ///
+    /// `key: &[u8; 32]` -- The encryption key.
+    ///
+    /// `options: &JsonOptions` -- Controls how values such as timestamps and numbers are written.
+    ///
+    /// # Returns
+    ///
+    /// `Result<Vec<u8>, Box<dyn Error>>` -- The encrypted export if OK, Error if not.
+    pub fn to_json_encrypted(
+        &self,
+        key: &[u8; 32],
+        options: &JsonOptions,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut json = Vec::new();
+        self.to_json_writer(&mut json, options)?;
+
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = ChaCha20Poly1305::new(Key::from_slice(key)).encrypt(
+            &nonce,
+            Payload {
+                msg: &json,
+                aad: HEADER,
+            },
+        )?;
+        let mut export = Vec::with_capacity(HEADER.len() + NONCE_LENGTH + sealed.len());
+        export.extend_from_slice(HEADER);
+        export.extend_from_slice(&nonce);
+        export.extend_from_slice(&sealed);
+        Ok(export)
+    }
+
+    /// Exports the parsed contents as encrypted JSON. See `to_json_encrypted`.
+    ///
+    /// # Parameters
+    ///
+    /// `filename: &str` -- The name of the file to be produced.
+    ///
+    /// `key: &[u8; 32]` -- The encryption key.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::encryption::generate_key;
+    ///
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let key = generate_key();
+    /// let file = std::env::temp_dir().join("tcx_encrypted_export.json.enc");
+    /// tcx.export_json_encrypted(file.to_str().unwrap(), &key).unwrap();
+    /// let again = crate::tcx::TrainingCenterDatabase::read_json_encrypted(&mut file.as_path(), &key).unwrap();
+    /// assert_eq!(again.activities.unwrap().activities.len(), 1);
+    /// ```
+    pub fn export_json_encrypted(
+        &self,
+        filename: &str,
+        key: &[u8; 32],
+    ) -> Result<(), Box<dyn Error>> {
+        self.write_json_encrypted_to(
+            &mut std::path::Path::new(filename),
+            key,
+            &JsonOptions::default(),
+        )
+    }
+
+    /// Writes the database as encrypted JSON to any sink. See `to_json_encrypted`.
+    ///
+    /// # Parameters
+    ///
+    /// `sink: &mut S` -- Where to write.
+    ///
+    /// `key: &[u8; 32]` -- The encryption key.
+    ///
+    /// `options: &JsonOptions` -- Controls how values such as timestamps and numbers are written.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn write_json_encrypted_to<S: TcxSink + ?Sized>(
+        &self,
+        sink: &mut S,
+        key: &[u8; 32],
+        options: &JsonOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let export = self.to_json_encrypted(key, options)?;
+        let mut writer = sink.create()?;
+        writer.write_all(&export)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Decrypts and parses an export written with the default `JsonOptions`.
+    ///
+    /// # Parameters
+    ///
+    /// `export: &[u8]` -- The encrypted export.
+    ///
+    /// `key: &[u8; 32]` -- The key it was encrypted with.
+    ///
+    /// # Returns
+    ///
+    /// `Result<TrainingCenterDatabase, Box<dyn Error>>` -- The database if OK, a `DecryptionError` or JSON error if
+    /// not.
+    pub fn from_json_encrypted(
+        export: &[u8],
+        key: &[u8; 32],
+    ) -> Result<TrainingCenterDatabase, Box<dyn Error>> {
+        if export.len() < HEADER.len() + NONCE_LENGTH || !export.starts_with(HEADER) {
+            return Err(Box::new(DecryptionError::NotEncrypted));
+        }
+        let (nonce, sealed) = export[HEADER.len()..].split_at(NONCE_LENGTH);
+        let json = ChaCha20Poly1305::new(Key::from_slice(key))
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: HEADER,
+                },
+            )
+            .map_err(|_| DecryptionError::Rejected)?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    /// Reads an encrypted export from any source. See `from_json_encrypted`.
+    ///
+    /// # Parameters
+    ///
+    /// `source: &mut S` -- Where to read from, such as a `PathBuf` or a byte slice.
+    ///
+    /// `key: &[u8; 32]` -- The key it was encrypted with.
+    ///
+    /// # Returns
+    ///
+    /// `Result<TrainingCenterDatabase, Box<dyn Error>>` -- The database if OK, Error if not.
+    pub fn read_json_encrypted<S: TcxSource + ?Sized>(
+        source: &mut S,
+        key: &[u8; 32],
+    ) -> Result<TrainingCenterDatabase, Box<dyn Error>> {
+        let mut export = Vec::new();
+        source.open()?.read_to_end(&mut export)?;
+        TrainingCenterDatabase::from_json_encrypted(&export, key)
+    }
+}
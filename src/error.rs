@@ -0,0 +1,248 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! The error type returned when reading TCX data.
+
+use std::fmt;
+use xml::common::Position;
+use xml::reader::{EventReader, XmlEvent};
+
+/// Parent elements that must contain a given child element (or attribute), keyed by the child's name. Parents are
+/// matched against the end of the element path, so `Activity/Lap` does not match a course's `Lap`.
+const REQUIRED_ELEMENTS: &[(&str, &[&str])] = &[
+    ("Activity", &["FirstSport", "NextSport"]),
+    ("Build", &["Author"]),
+    ("Calories", &["Activity/Lap", "Transition"]),
+    ("DistanceMeters", &["Lap", "Transition"]),
+    ("FirstSport", &["MultiSportSession"]),
+    (
+        "Id",
+        &[
+            "Activity",
+            "MultiSportSession",
+            "ActivityRef",
+            "MultiSportActivityRef",
+            "CourseNameRef",
+            "WorkoutNameRef",
+        ],
+    ),
+    (
+        "LatitudeDegrees",
+        &["Position", "BeginPosition", "EndPosition"],
+    ),
+    (
+        "LongitudeDegrees",
+        &["Position", "BeginPosition", "EndPosition"],
+    ),
+    ("Name", &["Workout", "Activity/Creator", "Author"]),
+    ("Sport", &["Activity", "Workout"]),
+    ("StartDay", &["Week"]),
+    ("StepId", &["Step", "Child"]),
+    ("Time", &["Trackpoint", "CoursePoint"]),
+    ("TotalTimeSeconds", &["Lap", "Transition"]),
+    (
+        "Value",
+        &[
+            "HeartRateBpm",
+            "AverageHeartRateBpm",
+            "MaximumHeartRateBpm",
+            "AverageHeartRate",
+            "MaximumHeartRate",
+            "HeartRate",
+            "Low",
+            "High",
+        ],
+    ),
+    ("Version", &["Build"]),
+    ("VersionMajor", &["Version"]),
+    ("VersionMinor", &["Version"]),
+];
+
+/// An error encountered while reading TCX data.
+#[derive(Debug)]
+pub enum Error {
+    /// The data could not be read.
+    Io(std::io::Error),
+
+    /// The data is not well-formed XML. Line and column are 1-based.
+    Xml {
+        message: String,
+        line: u64,
+        column: u64,
+    },
+
+    /// A required element or attribute is absent, named by its path, such as `Lap/TotalTimeSeconds`.
+    MissingElement(String),
+
+    /// An element or attribute holds a value that cannot be interpreted, such as a malformed number or date.
+    InvalidValue(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Xml {
+                message,
+                line,
+                column,
+            } => write!(
+                f,
+                "XML syntax error at line {}, column {}: {}",
+                line, column, message
+            ),
+            Error::MissingElement(path) => write!(f, "Missing required element {}", path),
+            Error::InvalidValue(message) => write!(f, "Invalid value: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl Error {
+    /// Translates an error from the XML deserializer. `document` is the XML that was being read, and is used to
+    /// work out which element is missing a required child.
+    pub(crate) fn from_deserializer(err: serde_xml_rs::Error, document: &[u8]) -> Self {
+        match err {
+            serde_xml_rs::Error::Io { source } => Error::Io(source),
+            serde_xml_rs::Error::Syntax { source } => {
+                let position = source.position();
+                Error::Xml {
+                    message: source.msg().to_string(),
+                    line: position.row + 1,
+                    column: position.column + 1,
+                }
+            }
+            serde_xml_rs::Error::Custom { field } => match missing_field(&field) {
+                Some(child) => Error::MissingElement(missing_element_path(document, child)),
+                None => Error::InvalidValue(field),
+            },
+            other => Error::InvalidValue(other.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "quick_xml")]
+impl Error {
+    /// Translates an error from the quick-xml deserializer. `document` is the XML that was being read, and is used
+    /// to locate syntax errors and to work out which element is missing a required child.
+    pub(crate) fn from_quick_xml(err: quick_xml::DeError, document: &[u8]) -> Self {
+        match err {
+            quick_xml::DeError::InvalidXml(quick_xml::Error::Io(source)) => {
+                Error::Io(std::io::Error::new(source.kind(), source.to_string()))
+            }
+            quick_xml::DeError::InvalidXml(source) => syntax_error(document, source.to_string()),
+            quick_xml::DeError::Custom(message) => match missing_field(&message) {
+                Some(child) => Error::MissingElement(missing_element_path(document, child)),
+                None => Error::InvalidValue(message),
+            },
+            other => Error::InvalidValue(other.to_string()),
+        }
+    }
+}
+
+/// Locates the first syntax error in the document, for parsers that do not report positions. Uses `message` if the
+/// document turns out to be well-formed.
+#[cfg(feature = "quick_xml")]
+fn syntax_error(document: &[u8], message: String) -> Error {
+    for event in EventReader::new(document) {
+        if let Err(err) = event {
+            let position = err.position();
+            return Error::Xml {
+                message: err.msg().to_string(),
+                line: position.row + 1,
+                column: position.column + 1,
+            };
+        }
+    }
+    Error::InvalidValue(message)
+}
+
+/// Extracts the field name from serde's "missing field `X`" message.
+fn missing_field(message: &str) -> Option<&str> {
+    message
+        .strip_prefix("missing field `")
+        .and_then(|rest| rest.strip_suffix('`'))
+}
+
+/// Finds the first element in the document that should contain `child` but does not, and returns its path as
+/// `Parent/child`. Falls back to just `child` if no such element is found.
+fn missing_element_path(document: &[u8], child: &str) -> String {
+    let parents = REQUIRED_ELEMENTS
+        .iter()
+        .find(|(name, _)| *name == child)
+        .map(|(_, parents)| *parents)
+        .unwrap_or(&[]);
+
+    // Each open element, and whether it is a parent that has been seen to contain the child.
+    let mut path: Vec<(String, Option<bool>)> = Vec::new();
+    for event in EventReader::new(document) {
+        match event {
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) => {
+                let name = name.local_name;
+                if let Some((_, Some(found))) = path.last_mut() {
+                    *found |= name == child;
+                }
+                path.push((name, None));
+                let is_parent = parents.iter().any(|parent| ends_with_path(&path, parent));
+                if is_parent {
+                    let has_attribute = attributes
+                        .iter()
+                        .any(|attribute| attribute.name.local_name == child);
+                    path.last_mut().unwrap().1 = Some(has_attribute);
+                }
+            }
+            Ok(XmlEvent::EndElement { .. }) => {
+                if let Some((parent, Some(false))) = path.pop() {
+                    return format!("{}/{}", parent, child);
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    child.to_string()
+}
+
+/// Whether the open elements end with the `/`-separated element names in `suffix`.
+fn ends_with_path(path: &[(String, Option<bool>)], suffix: &str) -> bool {
+    let names: Vec<&str> = suffix.split('/').collect();
+    names.len() <= path.len()
+        && path[path.len() - names.len()..]
+            .iter()
+            .zip(names)
+            .all(|((element, _), name)| element == name)
+}
@@ -0,0 +1,263 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Export to the FITLOG format of the SportTracks desktop training log (`FitnessWorkbook`, version 3), for moving
+//! long histories between training logs. Each activity carries its totals, laps and a single track; Trackpoint
+//! times in the track are seconds from its start. Activity `Id`s are GUIDs derived from the activity's
+//! fingerprint (see `Activity::fingerprint`), so exporting the same activity twice does not create a duplicate on
+//! import.
+
+use std::error::Error;
+use std::io::Write;
+
+use crate::storage::TcxSink;
+use crate::tcx::{Activity, ActivityLap, Intensity, TrainingCenterDatabase};
+use crate::writer::{format_number, format_time, XmlWriter};
+
+/// Namespace of FITLOG documents.
+const FITLOG_NAMESPACE: &str = "http://www.zonefivesoftware.com/xmlschemas/FitnessLogbook/v3";
+
+/// Namespace of XML Schema instance attributes.
+const XSI_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+impl TrainingCenterDatabase {
+    /// Writes the activities, including the legs of multisport sessions, as a SportTracks FITLOG document. Courses,
+    /// workouts and extensions other than power are dropped.
+    ///
+    /// # Parameters
+    ///
+    /// `writer: &mut W` -- Where to write the FITLOG.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), std::io::Error>` -- Nothing if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let mut fitlog = Vec::new();
+    /// tcx.to_fitlog(&mut fitlog).unwrap();
+    /// let fitlog = String::from_utf8(fitlog).unwrap();
+    /// assert!(fitlog.contains("<Activity StartTime=\"2021-01-19T23:30:50.000Z\""));
+    /// ```
+    pub fn to_fitlog<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        let mut xml = XmlWriter::new(writer);
+        xml.open(
+            "FitnessWorkbook",
+            &[("xmlns", FITLOG_NAMESPACE), ("xmlns:xsi", XSI_NAMESPACE)],
+        )?;
+        xml.open("AthleteLog", &[])?;
+        for activities in self.refreshed().activities.iter() {
+            let legs = activities
+                .multi_sport_sessions
+                .iter()
+                .flat_map(|session| session.activities());
+            for activity in activities.activities.iter().chain(legs) {
+                write_activity(&mut xml, activity)?;
+            }
+        }
+        xml.close("AthleteLog")?;
+        xml.close("FitnessWorkbook")
+    }
+
+    /// Writes the activities to a FITLOG file. See `to_fitlog`.
+    ///
+    /// # Parameters
+    ///
+    /// `filename: &str` -- The name of the FITLOG file to be produced.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn export_fitlog(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        self.write_fitlog_to(&mut std::path::Path::new(filename))
+    }
+
+    /// Writes the activities as FITLOG to any sink. See `to_fitlog`.
+    ///
+    /// # Parameters
+    ///
+    /// `sink: &mut S` -- Where to write, such as a `PathBuf`, a `Vec<u8>` or a `Stream`.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn write_fitlog_to<S: TcxSink + ?Sized>(&self, sink: &mut S) -> Result<(), Box<dyn Error>> {
+        crate::storage::write_with(sink, |mut writer| Ok(self.to_fitlog(&mut writer)?))
+    }
+}
+
+fn write_activity<W: Write>(xml: &mut XmlWriter<W>, activity: &Activity) -> std::io::Result<()> {
+    let start = match activity.start_time() {
+        Some(start) => start,
+        None => return Ok(()),
+    };
+    let start_time = format_time(start);
+    let id = guid(&activity.fingerprint());
+    xml.open("Activity", &[("StartTime", &start_time), ("Id", &id)])?;
+
+    if let Some(title) = activity.meta().and_then(|meta| meta.title) {
+        xml.text("Name", title)?;
+    }
+    xml.optional(
+        "Notes",
+        activity.user_notes().filter(|notes| !notes.is_empty()),
+    )?;
+    xml.empty("Category", &[("Name", category(&activity.sport))])?;
+
+    let summary = activity.summary();
+    let laps = &activity.laps;
+    let seconds: f64 = laps.iter().map(|lap| lap.total_time_seconds).sum();
+    let meters: f64 = laps.iter().map(|lap| lap.distance_meters).sum();
+    let calories: u32 = laps.iter().map(|lap| u32::from(lap.calories)).sum();
+    xml.empty("Duration", &[("TotalSeconds", &number(seconds))])?;
+    xml.empty("Distance", &[("TotalMeters", &number(meters))])?;
+    xml.empty("Calories", &[("TotalCal", &calories.to_string())])?;
+    xml.empty(
+        "Elevation",
+        &[
+            ("AscendMeters", &number(summary.elevation_gain_meters)),
+            ("DescendMeters", &number(summary.elevation_loss_meters)),
+        ],
+    )?;
+    if let (Some(average), Some(maximum)) = (summary.average_heart_rate, summary.max_heart_rate) {
+        xml.empty(
+            "HeartRate",
+            &[
+                ("AverageBPM", &number(average.round())),
+                ("MaximumBPM", &number(maximum.round())),
+            ],
+        )?;
+    }
+
+    if !laps.is_empty() {
+        xml.open("Laps", &[])?;
+        for lap in laps {
+            write_lap(xml, lap)?;
+        }
+        xml.close("Laps")?;
+    }
+
+    let mut points = activity.trackpoints().peekable();
+    if let Some(first) = points.peek() {
+        let track_start = first.time;
+        xml.open("Track", &[("StartTime", &format_time(track_start))])?;
+        for point in points {
+            let seconds = (point.time - track_start).num_milliseconds() as f64 / 1000.0;
+            let mut attributes = vec![("tm", number(seconds))];
+            if let Some(position) = &point.position {
+                attributes.push(("lat", position.latitude.to_string()));
+                attributes.push(("lon", position.longitude.to_string()));
+            }
+            if let Some(altitude) = point.altitude_meters {
+                attributes.push(("ele", number(altitude)));
+            }
+            if let Some(distance) = point.distance_meters {
+                attributes.push(("dist", number(distance)));
+            }
+            if let Some(heart_rate) = &point.heart_rate {
+                attributes.push(("hr", number(heart_rate.value.round())));
+            }
+            if let Some(cadence) = point.cadence {
+                attributes.push(("cadence", cadence.to_string()));
+            }
+            let watts = point
+                .extensions
+                .as_ref()
+                .and_then(|extensions| extensions.tpx.as_ref())
+                .and_then(|tpx| tpx.watts);
+            if let Some(watts) = watts {
+                attributes.push(("power", watts.to_string()));
+            }
+            let attributes: Vec<(&str, &str)> = attributes
+                .iter()
+                .map(|(name, value)| (*name, value.as_str()))
+                .collect();
+            xml.empty("pt", &attributes)?;
+        }
+        xml.close("Track")?;
+    }
+
+    xml.close("Activity")
+}
+
+fn write_lap<W: Write>(xml: &mut XmlWriter<W>, lap: &ActivityLap) -> std::io::Result<()> {
+    let start = match lap
+        .start_time
+        .or_else(|| lap.trackpoints().next().map(|p| p.time))
+    {
+        Some(start) => start,
+        None => return Ok(()),
+    };
+    let mut attributes = vec![
+        ("StartTime", format_time(start)),
+        ("DurationSeconds", number(lap.total_time_seconds)),
+    ];
+    if matches!(lap.intensity, Some(Intensity::Resting)) {
+        attributes.push(("Rest", "true".to_string()));
+    }
+    let attributes: Vec<(&str, &str)> = attributes
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect();
+    xml.open("Lap", &attributes)?;
+    xml.empty("Calories", &[("TotalCal", &lap.calories.to_string())])?;
+    xml.empty("Distance", &[("TotalMeters", &number(lap.distance_meters))])?;
+    if let (Some(average), Some(maximum)) = (lap.average_heart_rate, lap.maximum_heart_rate) {
+        xml.empty(
+            "HeartRate",
+            &[
+                ("AverageBPM", &number(average.round())),
+                ("MaximumBPM", &number(maximum.round())),
+            ],
+        )?;
+    }
+    xml.close("Lap")
+}
+
+/// The SportTracks category for a TCX sport.
+fn category(sport: &str) -> &str {
+    match sport {
+        "Running" => "Running",
+        "Biking" => "Cycling",
+        _ => "Other",
+    }
+}
+
+/// Formats a number for an attribute, in plain decimal notation rounded to millimeters and milliseconds.
+fn number(value: f64) -> String {
+    format_number(value, Some(3))
+}
+
+/// Formats the first 32 hexadecimal digits of a fingerprint as a GUID.
+fn guid(fingerprint: &str) -> String {
+    let hex = &fingerprint[..32];
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
@@ -0,0 +1,84 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Equipment tracking, based on the equipment recorded in each activity's metadata (see `Activity::set_meta`).
+
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::tcx::Activity;
+
+/// Accumulated use of a single piece of equipment.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct GearTotals {
+    /// Total distance covered, in meters.
+    pub distance_meters: f64,
+
+    /// Total time, in seconds.
+    pub total_time_seconds: f64,
+
+    /// Number of activities the equipment was used for.
+    pub activities: usize,
+}
+
+/// Adds up the distance and time of every activity for each piece of equipment it was tagged with.
+///
+/// # Parameters
+///
+/// `activities: I` -- The activities to scan, for example `&database.activities.unwrap().activities`.
+///
+/// # Returns
+///
+/// `BTreeMap<String, GearTotals>` -- Totals keyed by equipment name.
+///
+/// # Example
+///
+/// ```rust
+/// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+/// let activities = &mut tcx.activities.as_mut().unwrap().activities;
+/// activities[0].set_meta(&crate::tcx::meta::ActivityMeta {
+///     equipment: vec![String::from("Road shoes")],
+///     ..Default::default()
+/// });
+/// let totals = crate::tcx::gear::totals(activities.iter());
+/// assert_eq!(totals["Road shoes"].activities, 1);
+/// ```
+pub fn totals<'a, I>(activities: I) -> BTreeMap<String, GearTotals>
+where
+    I: IntoIterator<Item = &'a Activity>,
+{
+    let mut result: BTreeMap<String, GearTotals> = BTreeMap::new();
+    for activity in activities {
+        let meta = match activity.meta() {
+            Some(meta) => meta,
+            None => continue,
+        };
+        let distance: f64 = activity.laps.iter().map(|lap| lap.distance_meters).sum();
+        let time: f64 = activity.laps.iter().map(|lap| lap.total_time_seconds).sum();
+        for equipment in meta.equipment {
+            let entry = result.entry(equipment).or_default();
+            entry.distance_meters += distance;
+            entry.total_time_seconds += time;
+            entry.activities += 1;
+        }
+    }
+    result
+}
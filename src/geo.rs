@@ -0,0 +1,525 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Geometric helpers: distances recomputed from positions, speed and grade at each Trackpoint, bounding boxes,
+//! markers at every kilometer or mile and Google encoded polylines for drawing activities on a map. Also home to
+//! the lower-level helpers shared by the analysis modules.
+
+use chrono::{DateTime, Duration, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::stats::step_distance;
+use crate::tcx::{Activity, Position, Trackpoint};
+
+/// Mean radius of the Earth, in meters.
+pub(crate) const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+
+/// Minimum horizontal distance, in meters, over which a grade is measured. Shorter spans amplify altitude noise.
+const GRADE_WINDOW_METERS: f64 = 10.0;
+
+/// Speed, in meters per second, below which the athlete is considered stopped.
+pub(crate) const MOVING_SPEED_THRESHOLD: f64 = 0.5;
+
+/// Minimum distance, in meters, between two positions before a bearing is taken from them. Closer positions are
+/// dominated by GPS noise.
+const MIN_BEARING_DISTANCE_METERS: f64 = 2.0;
+
+/// Number of Trackpoints averaged when smoothing altitude.
+const ALTITUDE_SMOOTHING_POINTS: usize = 5;
+
+/// Decimal places kept by the standard Google polyline encoding.
+const POLYLINE_PRECISION: u32 = 5;
+
+/// The smallest latitude and longitude range that contains every position of an activity. A box that crosses the
+/// antimeridian has its western edge east of its eastern one (`min_longitude > max_longitude`), as in GeoJSON.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct BoundingBox {
+    /// Southern edge, in degrees latitude.
+    pub min_latitude: f64,
+
+    /// Western edge, in degrees longitude.
+    pub min_longitude: f64,
+
+    /// Northern edge, in degrees latitude.
+    pub max_latitude: f64,
+
+    /// Eastern edge, in degrees longitude.
+    pub max_longitude: f64,
+}
+
+impl BoundingBox {
+    /// Whether the position lies within the box, edges included.
+    pub fn contains(&self, position: &Position) -> bool {
+        let longitude = position.longitude;
+        let within_longitude = if self.min_longitude <= self.max_longitude {
+            (self.min_longitude..=self.max_longitude).contains(&longitude)
+        } else {
+            longitude >= self.min_longitude || longitude <= self.max_longitude
+        };
+        (self.min_latitude..=self.max_latitude).contains(&position.latitude) && within_longitude
+    }
+}
+
+impl Activity {
+    /// Recomputes the `DistanceMeters` of every Trackpoint as the haversine distance along the positions, starting
+    /// from zero, for devices that do not record distance or record it badly. Trackpoints without a position are
+    /// given the distance reached so far. Lap `DistanceMeters` totals are updated to match. Nothing changes if no
+    /// Trackpoint has a position.
+    ///
+    /// # Parameters
+    ///
+    /// None. `&mut self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// `f64` -- The total distance in meters.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+    /// let total = activity.recompute_distances();
+    /// assert!((total - activity.laps[0].distance_meters).abs() < 1e-6);
+    /// ```
+    pub fn recompute_distances(&mut self) -> f64 {
+        if !self.trackpoints().any(|point| point.position.is_some()) {
+            return 0.0;
+        }
+        let mut total = 0.0;
+        let mut previous: Option<Position> = None;
+        for lap in self.laps.iter_mut() {
+            let lap_start = total;
+            for point in lap.trackpoints_mut() {
+                if let Some(position) = &point.position {
+                    if let Some(previous) = &previous {
+                        total += haversine_distance(previous, position);
+                    }
+                    previous = Some(position.clone());
+                }
+                point.distance_meters = Some(total);
+            }
+            lap.distance_meters = total - lap_start;
+        }
+        total
+    }
+
+    /// Computes the speed at each Trackpoint, from the TPX speed extension when present, otherwise from the change
+    /// in distance since the previous Trackpoint.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<(DateTime<Utc>, f64)>` -- The time and speed, in meters per second, of every Trackpoint with a speed.
+    pub fn speed_series(&self) -> Vec<(DateTime<Utc>, f64)> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        points
+            .iter()
+            .zip(speeds(&points))
+            .filter_map(|(point, speed)| speed.map(|speed| (point.time, speed)))
+            .collect()
+    }
+
+    /// Computes the grade at each Trackpoint, measured over at least the last ten meters to keep altitude noise in
+    /// check. Needs both altitude and distance.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<(DateTime<Utc>, f64)>` -- The time and grade (rise over run, so 0.05 is 5%) of every Trackpoint with a
+    /// grade.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let grades = tcx.activities.unwrap().activities[0].grade_series();
+    /// assert!(grades.iter().all(|(_, grade)| grade.abs() < 0.5));
+    /// ```
+    pub fn grade_series(&self) -> Vec<(DateTime<Utc>, f64)> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        points
+            .iter()
+            .zip(grades(&points))
+            .filter_map(|(point, grade)| grade.map(|grade| (point.time, grade)))
+            .collect()
+    }
+
+    /// Finds the smallest box containing every position in the activity. The longitude range is the one that leaves
+    /// out the widest gap between positions, so a track crossing the antimeridian gets a narrow box that wraps
+    /// around it rather than one spanning the rest of the globe.
+    ///
+    /// # Returns
+    ///
+    /// `Option<BoundingBox>` -- The box, or `None` if no Trackpoint has a position.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::Position;
+    ///
+    /// // Move the run to either side of the antimeridian.
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+    /// for (i, point) in activity.trackpoints_mut().enumerate() {
+    ///     if let Some(position) = point.position.as_mut() {
+    ///         position.longitude = if i % 2 == 0 { 179.9 } else { -179.8 };
+    ///     }
+    /// }
+    /// let bounds = activity.bounding_box().unwrap();
+    /// assert_eq!((bounds.min_longitude, bounds.max_longitude), (179.9, -179.8));
+    /// let latitude = bounds.min_latitude;
+    /// assert!(bounds.contains(&Position { latitude, longitude: 180.0 }));
+    /// assert!(!bounds.contains(&Position { latitude, longitude: 0.0 }));
+    /// ```
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        let positions: Vec<&Position> = self
+            .trackpoints()
+            .filter_map(|point| point.position.as_ref())
+            .collect();
+        let first = positions.first()?;
+        let mut longitudes: Vec<f64> = positions
+            .iter()
+            .map(|position| position.longitude)
+            .collect();
+        longitudes.sort_by(f64::total_cmp);
+
+        // The gap from the easternmost position round to the westernmost, and then each gap between neighbors. The
+        // box spans everything but the widest.
+        let (mut min_longitude, mut max_longitude) =
+            (longitudes[0], longitudes[longitudes.len() - 1]);
+        let mut widest = longitudes[0] + 360.0 - max_longitude;
+        for pair in longitudes.windows(2) {
+            if pair[1] - pair[0] > widest {
+                widest = pair[1] - pair[0];
+                min_longitude = pair[1];
+                max_longitude = pair[0];
+            }
+        }
+
+        let start = BoundingBox {
+            min_latitude: first.latitude,
+            min_longitude,
+            max_latitude: first.latitude,
+            max_longitude,
+        };
+        Some(
+            positions
+                .iter()
+                .fold(start, |bounds, position| BoundingBox {
+                    min_latitude: bounds.min_latitude.min(position.latitude),
+                    max_latitude: bounds.max_latitude.max(position.latitude),
+                    ..bounds
+                }),
+        )
+    }
+
+    /// Encodes the track as a Google encoded polyline, as used by the Google Maps, Mapbox and Strava APIs.
+    ///
+    /// # Returns
+    ///
+    /// `String` -- The polyline, at five decimal places. Empty if no Trackpoint has a position.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let polyline = tcx.activities.unwrap().activities[0].encoded_polyline();
+    /// assert!(!polyline.is_empty());
+    /// ```
+    pub fn encoded_polyline(&self) -> String {
+        let positions: Vec<&Position> = self
+            .trackpoints()
+            .filter_map(|point| point.position.as_ref())
+            .collect();
+        encode_polyline(&positions, POLYLINE_PRECISION)
+    }
+}
+
+/// A point at a round distance along an activity, such as each kilometer or mile, for labelling a map or marking
+/// where a split ends.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DistanceMarker {
+    /// The distance from the start, in meters: a multiple of the interval.
+    pub distance_meters: f64,
+
+    /// Where the distance was reached, interpolated between the Trackpoints either side.
+    pub position: Position,
+
+    /// When the distance was reached, interpolated likewise.
+    pub time: DateTime<Utc>,
+}
+
+impl Activity {
+    /// Finds the position at every multiple of a distance along the activity, such as each kilometer or each mile
+    /// (1609.344 meters), interpolating between the Trackpoints either side. Distance is measured as
+    /// `distance_splits` measures it, from the recorded distances when present and otherwise from the positions,
+    /// so the n-th marker falls where the n-th split ends.
+    ///
+    /// # Parameters
+    ///
+    /// `interval: f64` -- The distance between markers, in meters.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<DistanceMarker>` -- The markers, in order, excluding the start. Empty if `interval` is not positive.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// let markers = activity.generate_distance_markers(1000.0);
+    /// assert_eq!(markers.len(), activity.distance_splits(1000.0).len() - 1);
+    /// assert_eq!(markers[0].distance_meters, 1000.0);
+    /// ```
+    pub fn generate_distance_markers(&self, interval: f64) -> Vec<DistanceMarker> {
+        let mut markers = Vec::new();
+        if interval <= 0.0 {
+            return markers;
+        }
+        let points: Vec<&Trackpoint> = self
+            .trackpoints()
+            .filter(|point| point.position.is_some())
+            .collect();
+        let mut total = 0.0;
+        for pair in points.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            let step = step_distance(from, to);
+            let next = total + step;
+            let mut marker = interval * ((total / interval).floor() + 1.0);
+            while marker <= next && step > 0.0 {
+                let fraction = (marker - total) / step;
+                let (start, end) = (
+                    from.position.as_ref().unwrap(),
+                    to.position.as_ref().unwrap(),
+                );
+                let offset = seconds_between(from, to) * fraction;
+                markers.push(DistanceMarker {
+                    distance_meters: marker,
+                    position: Position {
+                        latitude: start.latitude + (end.latitude - start.latitude) * fraction,
+                        longitude: start.longitude + (end.longitude - start.longitude) * fraction,
+                    },
+                    time: from.time + Duration::milliseconds((offset * 1000.0).round() as i64),
+                });
+                marker += interval;
+            }
+            total = next;
+        }
+        markers
+    }
+}
+
+/// Encodes positions using Google's encoded polyline algorithm.
+///
+/// # Parameters
+///
+/// `positions: &[&Position]` -- The positions, in order.
+///
+/// `precision: u32` -- Decimal places to keep: 5 for the standard encoding, 6 for the variant used by OSRM and
+/// Valhalla.
+///
+/// # Returns
+///
+/// `String` -- The polyline.
+///
+/// # Example
+///
+/// ```rust
+/// use crate::tcx::Position;
+/// let points = [(38.5, -120.2), (40.7, -120.95), (43.252, -126.453)]
+///     .iter()
+///     .map(|(latitude, longitude)| Position { latitude: *latitude, longitude: *longitude })
+///     .collect::<Vec<_>>();
+/// let positions: Vec<&Position> = points.iter().collect();
+/// assert_eq!(crate::tcx::geo::encode_polyline(&positions, 5), "_p~iF~ps|U_ulLnnqC_mqNvxq`@");
+/// ```
+pub fn encode_polyline(positions: &[&Position], precision: u32) -> String {
+    let factor = 10f64.powi(precision as i32);
+    let mut encoded = String::new();
+    let (mut previous_latitude, mut previous_longitude) = (0i64, 0i64);
+    for position in positions {
+        let latitude = (position.latitude * factor).round() as i64;
+        let longitude = (position.longitude * factor).round() as i64;
+        encode_polyline_value(latitude - previous_latitude, &mut encoded);
+        encode_polyline_value(longitude - previous_longitude, &mut encoded);
+        previous_latitude = latitude;
+        previous_longitude = longitude;
+    }
+    encoded
+}
+
+/// Appends one signed delta to a polyline, five bits per character.
+fn encode_polyline_value(delta: i64, encoded: &mut String) {
+    let mut value = if delta < 0 { !(delta << 1) } else { delta << 1 } as u64;
+    while value >= 0x20 {
+        encoded.push(char::from((0x20 | (value & 0x1f)) as u8 + 63));
+        value >>= 5;
+    }
+    encoded.push(char::from(value as u8 + 63));
+}
+
+/// Great-circle distance in meters between two positions, using the haversine formula.
+pub(crate) fn haversine_distance(from: &Position, to: &Position) -> f64 {
+    let lat1 = from.latitude.to_radians();
+    let lat2 = to.latitude.to_radians();
+    let dlat = lat2 - lat1;
+    let dlon = (to.longitude - from.longitude).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_METERS * a.sqrt().asin()
+}
+
+/// Initial great-circle bearing, in degrees clockwise from true north (0 to 360), for travel from one position to
+/// another.
+pub(crate) fn initial_bearing(from: &Position, to: &Position) -> f64 {
+    let lat1 = from.latitude.to_radians();
+    let lat2 = to.latitude.to_radians();
+    let dlon = (to.longitude - from.longitude).to_radians();
+    let y = dlon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+/// Direction of travel at each Trackpoint, in degrees clockwise from true north, taken from the last position at
+/// least a couple of meters behind it. `None` for Trackpoints without a position and until the track has moved.
+pub(crate) fn bearings(points: &[&Trackpoint]) -> Vec<Option<f64>> {
+    let mut result = Vec::with_capacity(points.len());
+    let mut anchor: Option<&Position> = None;
+    let mut current: Option<f64> = None;
+    for point in points {
+        let position = match &point.position {
+            Some(position) => position,
+            None => {
+                result.push(None);
+                continue;
+            }
+        };
+        match anchor {
+            Some(previous)
+                if haversine_distance(previous, position) >= MIN_BEARING_DISTANCE_METERS =>
+            {
+                current = Some(initial_bearing(previous, position));
+                anchor = Some(position);
+            }
+            Some(_) => {}
+            None => anchor = Some(position),
+        }
+        result.push(current);
+    }
+    result
+}
+
+/// Seconds elapsed between two Trackpoints.
+pub(crate) fn seconds_between(from: &Trackpoint, to: &Trackpoint) -> f64 {
+    (to.time - from.time).num_milliseconds() as f64 / 1000.0
+}
+
+/// Speed at each Trackpoint. Uses the TPX speed extension when present, otherwise derives it from the
+/// change in distance since the previous Trackpoint.
+pub(crate) fn speeds(points: &[&Trackpoint]) -> Vec<Option<f64>> {
+    let mut result = Vec::with_capacity(points.len());
+    for (i, point) in points.iter().enumerate() {
+        let tpx_speed = point
+            .extensions
+            .as_ref()
+            .and_then(|ext| ext.tpx.as_ref())
+            .and_then(|tpx| tpx.speed);
+        if tpx_speed.is_some() {
+            result.push(tpx_speed);
+            continue;
+        }
+
+        let derived = if i > 0 {
+            let prev = points[i - 1];
+            let elapsed = seconds_between(prev, point);
+            match (prev.distance_meters, point.distance_meters) {
+                (Some(d1), Some(d2)) if elapsed > 0.0 => Some((d2 - d1) / elapsed),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        result.push(derived);
+    }
+    result
+}
+
+/// Whether the athlete is pedalling (or striding) at a Trackpoint, judged by cadence and power: `Some(true)` if
+/// either is positive, `Some(false)` if those present are all zero, and `None` if there is neither.
+pub(crate) fn pedalling(point: &Trackpoint) -> Option<bool> {
+    let tpx = point.extensions.as_ref().and_then(|ext| ext.tpx.as_ref());
+    let cadence = point
+        .cadence
+        .or_else(|| tpx.and_then(|tpx| tpx.run_cadence));
+    let watts = tpx.and_then(|tpx| tpx.watts);
+    match (cadence, watts) {
+        (None, None) => None,
+        (cadence, watts) => Some(cadence.unwrap_or(0) > 0 || watts.unwrap_or(0) > 0),
+    }
+}
+
+/// Grade (rise over run, so 0.05 is 5%) at each Trackpoint, measured back to the most recent Trackpoint that is at
+/// least ten meters behind it. Requires both altitude and distance.
+pub(crate) fn grades(points: &[&Trackpoint]) -> Vec<Option<f64>> {
+    let mut result = Vec::with_capacity(points.len());
+    let mut start = 0;
+    for (i, point) in points.iter().enumerate() {
+        let (distance, altitude) = match (point.distance_meters, point.altitude_meters) {
+            (Some(distance), Some(altitude)) => (distance, altitude),
+            _ => {
+                result.push(None);
+                continue;
+            }
+        };
+
+        // Slide the start of the window forward while it stays far enough behind.
+        while start + 1 < i {
+            match points[start + 1].distance_meters {
+                Some(next) if distance - next >= GRADE_WINDOW_METERS => start += 1,
+                _ => break,
+            }
+        }
+
+        let grade = match (points[start].distance_meters, points[start].altitude_meters) {
+            (Some(d0), Some(a0)) if distance - d0 >= GRADE_WINDOW_METERS => {
+                Some((altitude - a0) / (distance - d0))
+            }
+            _ => None,
+        };
+        result.push(grade);
+    }
+    result
+}
+
+/// Moving average of the altitude, to keep GPS and barometer noise from splitting or inventing climbs and from
+/// inflating elevation gain. Trackpoints without an altitude count as zero, so callers should filter them out first.
+pub(crate) fn smoothed_altitudes(points: &[&Trackpoint]) -> Vec<f64> {
+    let raw: Vec<f64> = points
+        .iter()
+        .map(|point| point.altitude_meters.unwrap_or(0.0))
+        .collect();
+    let half = ALTITUDE_SMOOTHING_POINTS / 2;
+    (0..raw.len())
+        .map(|i| {
+            let window = &raw[i.saturating_sub(half)..(i + half + 1).min(raw.len())];
+            window.iter().sum::<f64>() / window.len() as f64
+        })
+        .collect()
+}
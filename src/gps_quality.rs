@@ -0,0 +1,322 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Estimating how far each GPS position can be trusted, from whether the movement it implies is physically
+//! plausible (speed and acceleration), whether it agrees with the speed the device reported, and the receiver's
+//! own dilution of precision where a file carries it. The per-point confidence drives the outlier filter and the
+//! position smoothing below, so that a single bad fix neither survives nor drags its neighbors along.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::{haversine_distance, seconds_between};
+use crate::sport::Sport;
+use crate::tcx::{Activity, Position, Trackpoint};
+
+/// How far, in meters per second, the implied speed may differ from the reported speed before they disagree, at
+/// low speeds. At higher speeds the tolerance is half the reported speed.
+const SPEED_MISMATCH_METERS_PER_SECOND: f64 = 3.0;
+
+/// What made a position less trustworthy.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GpsFlag {
+    /// Reaching the position, and leaving it again, both need an implausible speed: a spike.
+    Spike,
+    /// Reaching or leaving the position needs an implausible speed, as next to a spike or after a jump.
+    ImpliedSpeed,
+    /// The speed into and out of the position differ by an implausible acceleration.
+    Acceleration,
+    /// The movement disagrees with the speed the device reported (TPX `Speed`).
+    SpeedMismatch,
+    /// The receiver reported a high horizontal dilution of precision.
+    Hdop,
+}
+
+/// Controls `Activity::estimate_gps_quality`.
+#[derive(Debug, Clone)]
+pub struct GpsQualityOptions {
+    /// The fastest plausible speed, in meters per second. Defaults to `None`, for a limit by sport: 12 for
+    /// running, 30 for biking and 60 otherwise.
+    pub max_speed: Option<f64>,
+
+    /// The largest plausible change of speed, in meters per second per second. Defaults to 6.
+    pub max_acceleration: f64,
+
+    /// The horizontal dilution of precision above which a fix is doubted. Defaults to 5.
+    pub max_hdop: f64,
+}
+
+impl Default for GpsQualityOptions {
+    fn default() -> Self {
+        GpsQualityOptions {
+            max_speed: None,
+            max_acceleration: 6.0,
+            max_hdop: 5.0,
+        }
+    }
+}
+
+/// The plausibility of one Trackpoint's position.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PointQuality {
+    pub time: DateTime<Utc>,
+
+    /// How far the position can be trusted, from 0 (not at all) to 1, or `None` if the Trackpoint has no position.
+    pub confidence: Option<f64>,
+
+    /// The speed needed to reach the position from the previous one, in meters per second, if there is one.
+    pub implied_speed: Option<f64>,
+
+    /// What lowered the confidence, if anything.
+    pub flags: Vec<GpsFlag>,
+}
+
+impl Activity {
+    /// Scores the plausibility of every Trackpoint's position. The confidence starts at 1 and is lowered by each
+    /// problem found: to a tenth for a spike, 0.7 next to one, a half for an implausible acceleration or a
+    /// disagreement with the reported speed, and in proportion to how far the HDOP exceeds `max_hdop`.
+    ///
+    /// # Parameters
+    ///
+    /// `options: &GpsQualityOptions` -- The plausibility limits.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<PointQuality>` -- One per Trackpoint, in order.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// let quality = activity.estimate_gps_quality(&Default::default());
+    /// assert_eq!(quality.len(), activity.trackpoints().count());
+    /// let trusted = quality.iter().filter(|point| point.confidence == Some(1.0)).count();
+    /// assert!(trusted as f64 > 0.9 * quality.len() as f64);
+    /// ```
+    pub fn estimate_gps_quality(&self, options: &GpsQualityOptions) -> Vec<PointQuality> {
+        let max_speed = options
+            .max_speed
+            .unwrap_or(match Sport::from_name(&self.sport) {
+                Sport::Running => 12.0,
+                Sport::Biking => 30.0,
+                Sport::Other => 60.0,
+            });
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        // Devices often repeat the last fix until a new one arrives, so only the points where the position changes
+        // are judged, and the repeats share their judgment.
+        let mut positioned: Vec<usize> = Vec::new();
+        let mut repeats: Vec<(usize, usize)> = Vec::new();
+        for (i, point) in points.iter().enumerate() {
+            if let Some(position) = &point.position {
+                match positioned.last() {
+                    Some(&last) if points[last].position.as_ref() == Some(position) => {
+                        repeats.push((i, last))
+                    }
+                    _ => positioned.push(i),
+                }
+            }
+        }
+
+        // The speed from each fix to the next, or `None` where no time passed.
+        let legs: Vec<Option<f64>> = positioned
+            .windows(2)
+            .map(|pair| {
+                let (from, to) = (points[pair[0]], points[pair[1]]);
+                let seconds = seconds_between(from, to);
+                let meters = haversine_distance(
+                    from.position.as_ref().unwrap(),
+                    to.position.as_ref().unwrap(),
+                );
+                (seconds > 0.0).then(|| meters / seconds)
+            })
+            .collect();
+
+        let mut quality: Vec<PointQuality> = points
+            .iter()
+            .map(|point| PointQuality {
+                time: point.time,
+                confidence: None,
+                implied_speed: None,
+                flags: Vec::new(),
+            })
+            .collect();
+        for (k, &i) in positioned.iter().enumerate() {
+            let speed_in = if k > 0 { legs[k - 1] } else { None };
+            let speed_out = legs.get(k).copied().flatten();
+            let mut confidence = 1.0;
+            let mut flags = Vec::new();
+
+            let too_fast = |speed: Option<f64>| speed.is_some_and(|speed| speed > max_speed);
+            match (too_fast(speed_in), too_fast(speed_out)) {
+                (true, true) => {
+                    flags.push(GpsFlag::Spike);
+                    confidence *= 0.1;
+                }
+                (true, false) | (false, true) => {
+                    flags.push(GpsFlag::ImpliedSpeed);
+                    confidence *= 0.7;
+                }
+                (false, false) => {}
+            }
+
+            // A jump already explains a change of speed, so acceleration is only judged between plausible speeds.
+            if let (true, Some(speed_in), Some(speed_out)) = (flags.is_empty(), speed_in, speed_out)
+            {
+                let seconds =
+                    seconds_between(points[positioned[k - 1]], points[positioned[k + 1]]) / 2.0;
+                if seconds > 0.0
+                    && (speed_out - speed_in).abs() / seconds > options.max_acceleration
+                {
+                    flags.push(GpsFlag::Acceleration);
+                    confidence *= 0.5;
+                }
+            }
+
+            let extensions = points[i].extensions.as_ref();
+            let reported = extensions
+                .and_then(|ext| ext.tpx.as_ref())
+                .and_then(|tpx| tpx.speed);
+            if let Some(reported) = reported {
+                let tolerance = SPEED_MISMATCH_METERS_PER_SECOND.max(reported / 2.0);
+                let disagrees = |speed: Option<f64>| {
+                    speed.is_none_or(|speed| (speed - reported).abs() > tolerance)
+                };
+                if (speed_in.is_some() || speed_out.is_some())
+                    && disagrees(speed_in)
+                    && disagrees(speed_out)
+                {
+                    flags.push(GpsFlag::SpeedMismatch);
+                    confidence *= 0.5;
+                }
+            }
+
+            if let Some(hdop) = extensions.and_then(|ext| ext.hdop) {
+                if hdop > options.max_hdop {
+                    flags.push(GpsFlag::Hdop);
+                    confidence *= options.max_hdop / hdop;
+                }
+            }
+
+            quality[i].confidence = Some(confidence);
+            quality[i].implied_speed = speed_in;
+            quality[i].flags = flags;
+        }
+        for (i, fix) in repeats {
+            quality[i].confidence = quality[fix].confidence;
+            quality[i].implied_speed = Some(0.0);
+            quality[i].flags = quality[fix].flags.clone();
+        }
+        quality
+    }
+
+    /// Removes the positions whose confidence (see `estimate_gps_quality`) is below `min_confidence`. The rest of
+    /// each Trackpoint, such as its time and heart rate, is kept.
+    ///
+    /// # Parameters
+    ///
+    /// `options: &GpsQualityOptions` -- The plausibility limits.
+    ///
+    /// `min_confidence: f64` -- The lowest confidence kept, such as 0.5.
+    ///
+    /// # Returns
+    ///
+    /// `usize` -- The number of positions removed.
+    pub fn remove_gps_outliers(
+        &mut self,
+        options: &GpsQualityOptions,
+        min_confidence: f64,
+    ) -> usize {
+        let quality = self.estimate_gps_quality(options);
+        let mut removed = 0;
+        for (point, quality) in self.trackpoints_mut().zip(quality) {
+            if quality
+                .confidence
+                .is_some_and(|confidence| confidence < min_confidence)
+            {
+                point.position = None;
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Smooths the positions with a centered moving average weighted by confidence (see `estimate_gps_quality`), so
+    /// that doubtful fixes are pulled towards their trusted neighbors rather than the other way around.
+    ///
+    /// # Parameters
+    ///
+    /// `options: &GpsQualityOptions` -- The plausibility limits.
+    ///
+    /// `window: usize` -- The number of positions averaged. `1` leaves the positions unchanged.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+    /// let count = activity.trackpoints().filter(|point| point.position.is_some()).count();
+    /// activity.smooth_positions(&Default::default(), 5);
+    /// assert_eq!(activity.trackpoints().filter(|point| point.position.is_some()).count(), count);
+    /// ```
+    pub fn smooth_positions(&mut self, options: &GpsQualityOptions, window: usize) {
+        let quality = self.estimate_gps_quality(options);
+        let known: Vec<(usize, f64, f64, f64)> = self
+            .trackpoints()
+            .zip(&quality)
+            .enumerate()
+            .filter_map(|(i, (point, quality))| {
+                let position = point.position.as_ref()?;
+                let weight = quality.confidence?.max(1e-3);
+                Some((i, position.latitude, position.longitude, weight))
+            })
+            .collect();
+        let half = window.max(1) / 2;
+        let mut smoothed = vec![None; quality.len()];
+        for k in 0..known.len() {
+            let span = &known[k.saturating_sub(half)..(k + half + 1).min(known.len())];
+            let total: f64 = span.iter().map(|(_, _, _, weight)| weight).sum();
+            let latitude = span
+                .iter()
+                .map(|(_, lat, _, weight)| lat * weight)
+                .sum::<f64>()
+                / total;
+            let longitude = span
+                .iter()
+                .map(|(_, _, lon, weight)| lon * weight)
+                .sum::<f64>()
+                / total;
+            smoothed[known[k].0] = Some(Position {
+                latitude,
+                longitude,
+            });
+        }
+        for (point, position) in self.trackpoints_mut().zip(smoothed) {
+            if position.is_some() {
+                point.position = position;
+            }
+        }
+    }
+}
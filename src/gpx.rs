@@ -0,0 +1,377 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Conversion to and from GPX 1.1. Heart rate, cadence and temperature travel in the Garmin TrackPointExtension,
+//! which is what Strava, Garmin Connect and most other tools read and write. Each activity becomes a GPX track and
+//! each lap a track segment; activity titles set with `set_meta` become track names. Each point's `hdop` is kept
+//! in `Extensions::hdop`.
+
+use chrono::{DateTime, Utc};
+use serde_derive::Deserialize;
+use std::error::Error;
+use std::io::{BufReader, Read, Write};
+
+use crate::geo::haversine_distance;
+use crate::meta::ActivityMeta;
+use crate::storage::{TcxSink, TcxSource};
+use crate::tcx::{
+    Activities, Activity, ActivityLap, Extensions, HeartRate, Position, Track, Trackpoint,
+    TrainingCenterDatabase,
+};
+use crate::writer::{format_time, XmlWriter};
+
+/// Namespace of the GPX 1.1 schema.
+const GPX_NAMESPACE: &str = "http://www.topografix.com/GPX/1/1";
+
+/// Namespace of the Garmin TrackPointExtension.
+const TRACKPOINT_EXTENSION_NAMESPACE: &str =
+    "http://www.garmin.com/xmlschemas/TrackPointExtension/v1";
+
+/// Locations of the GPX and TrackPointExtension schemas.
+const GPX_SCHEMA_LOCATION: &str = "http://www.topografix.com/GPX/1/1 http://www.topografix.com/GPX/1/1/gpx.xsd http://www.garmin.com/xmlschemas/TrackPointExtension/v1 http://www.garmin.com/xmlschemas/TrackPointExtensionv1.xsd";
+
+/// Namespace of XML Schema instance attributes.
+const XSI_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// GPX input structs
+
+#[derive(Deserialize, Debug, Default)]
+struct Gpx {
+    #[serde(rename = "trk", default)]
+    tracks: Vec<GpxTrack>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct GpxTrack {
+    name: Option<String>,
+    desc: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    #[serde(rename = "trkseg", default)]
+    segments: Vec<GpxSegment>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct GpxSegment {
+    #[serde(rename = "trkpt", default)]
+    points: Vec<GpxPoint>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct GpxPoint {
+    lat: f64,
+    lon: f64,
+    ele: Option<f64>,
+    time: Option<DateTime<Utc>>,
+    hdop: Option<f64>,
+    extensions: Option<GpxExtensions>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct GpxExtensions {
+    #[serde(rename = "TrackPointExtension")]
+    trackpoint_extension: Option<GpxTrackPointExtension>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct GpxTrackPointExtension {
+    atemp: Option<f64>,
+    hr: Option<f64>,
+    cad: Option<u8>,
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Functions
+
+impl TrainingCenterDatabase {
+    /// Writes the activities as a GPX 1.1 document. Trackpoints without a position are left out, since GPX requires
+    /// one; lap summaries, power and other data GPX cannot represent are dropped.
+    ///
+    /// # Parameters
+    ///
+    /// `writer: &mut W` -- Where to write the GPX.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), std::io::Error>` -- Nothing if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let mut gpx = Vec::new();
+    /// tcx.to_gpx(&mut gpx).unwrap();
+    /// let converted = crate::tcx::TrainingCenterDatabase::from_gpx(&mut std::io::BufReader::new(gpx.as_slice())).unwrap();
+    /// assert_eq!(converted.activities.unwrap().activities[0].sport, "Running");
+    /// ```
+    pub fn to_gpx<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        let mut xml = XmlWriter::new(writer);
+        xml.open(
+            "gpx",
+            &[
+                ("version", "1.1"),
+                ("creator", concat!("rust_tcx ", env!("CARGO_PKG_VERSION"))),
+                ("xsi:schemaLocation", GPX_SCHEMA_LOCATION),
+                ("xmlns", GPX_NAMESPACE),
+                ("xmlns:gpxtpx", TRACKPOINT_EXTENSION_NAMESPACE),
+                ("xmlns:xsi", XSI_NAMESPACE),
+            ],
+        )?;
+
+        let activities = self
+            .activities
+            .iter()
+            .flat_map(|activities| activities.activities.iter());
+        if let Some(start) = activities
+            .clone()
+            .filter_map(|activity| activity.start_time())
+            .min()
+        {
+            xml.open("metadata", &[])?;
+            xml.text("time", format_time(start))?;
+            xml.close("metadata")?;
+        }
+        for activity in activities {
+            write_track(&mut xml, activity)?;
+        }
+
+        xml.close("gpx")
+    }
+
+    /// Writes the activities to a GPX file. See `to_gpx`.
+    ///
+    /// # Parameters
+    ///
+    /// `filename: &str` -- The name of the GPX file to be produced.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn export_gpx(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        self.write_gpx_to(&mut std::path::Path::new(filename))
+    }
+
+    /// Writes the activities as GPX to any sink. See `to_gpx`.
+    ///
+    /// # Parameters
+    ///
+    /// `sink: &mut S` -- Where to write, such as a `PathBuf`, a `Vec<u8>` or a `Stream`.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn write_gpx_to<S: TcxSink + ?Sized>(&self, sink: &mut S) -> Result<(), Box<dyn Error>> {
+        crate::storage::write_with(sink, |mut writer| Ok(self.to_gpx(&mut writer)?))
+    }
+
+    /// Builds activities from the tracks of a GPX document: one activity per track and one lap per track segment.
+    /// The sport is taken from the track type (`running`, `cycling` and so on), the track name becomes the activity
+    /// title (see `meta`) and the description its notes. GPX has no distances, so they are computed from the
+    /// positions. Points without a time are skipped, since TCX requires one.
+    ///
+    /// # Parameters
+    ///
+    /// `reader: &mut BufReader<R>` -- A buffer of a GPX file previously opened.
+    ///
+    /// # Returns
+    ///
+    /// `Result<TrainingCenterDatabase, crate::error::Error>`
+    pub fn from_gpx<R: Read>(reader: &mut BufReader<R>) -> Result<Self, crate::error::Error> {
+        // The document is buffered so that, on failure, it can be searched for the element at fault.
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let gpx: Gpx = serde_xml_rs::from_reader(bytes.as_slice())
+            .map_err(|err| crate::error::Error::from_deserializer(err, &bytes))?;
+        let activities = gpx.tracks.into_iter().map(activity_from_track).collect();
+        Ok(TrainingCenterDatabase {
+            activities: Some(Activities {
+                activities,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    /// Reads a GPX file. See `from_gpx`.
+    ///
+    /// # Parameters
+    ///
+    /// `filename: &str` -- The name of the GPX file to be read.
+    ///
+    /// # Returns
+    ///
+    /// `Result<TrainingCenterDatabase, crate::error::Error>`
+    pub fn from_gpx_file(filename: &str) -> Result<Self, crate::error::Error> {
+        let mut path = std::path::Path::new(filename);
+        let mut reader = BufReader::new(path.open()?);
+        Self::from_gpx(&mut reader)
+    }
+}
+
+fn write_track<W: Write>(xml: &mut XmlWriter<W>, activity: &Activity) -> std::io::Result<()> {
+    xml.open("trk", &[])?;
+    let title = activity.meta().and_then(|meta| meta.title);
+    xml.text("name", title.as_deref().unwrap_or(&activity.id))?;
+    xml.optional(
+        "desc",
+        activity.user_notes().filter(|notes| !notes.is_empty()),
+    )?;
+    xml.text("type", gpx_type(&activity.sport))?;
+    for lap in &activity.laps {
+        let mut points = lap
+            .trackpoints()
+            .filter(|point| point.position.is_some())
+            .peekable();
+        if points.peek().is_none() {
+            continue;
+        }
+        xml.open("trkseg", &[])?;
+        for point in points {
+            write_point(xml, point)?;
+        }
+        xml.close("trkseg")?;
+    }
+    xml.close("trk")
+}
+
+fn write_point<W: Write>(xml: &mut XmlWriter<W>, point: &Trackpoint) -> std::io::Result<()> {
+    let position = match &point.position {
+        Some(position) => position,
+        None => return Ok(()),
+    };
+    let latitude = position.latitude.to_string();
+    let longitude = position.longitude.to_string();
+    xml.open("trkpt", &[("lat", &latitude), ("lon", &longitude)])?;
+    xml.optional("ele", point.altitude_meters)?;
+    xml.text("time", format_time(point.time))?;
+    xml.optional(
+        "hdop",
+        point
+            .extensions
+            .as_ref()
+            .and_then(|extensions| extensions.hdop),
+    )?;
+
+    let temperature = point
+        .extensions
+        .as_ref()
+        .and_then(|extensions| extensions.temperature);
+    let heart_rate = point.heart_rate.as_ref().map(|hr| hr.value.round() as u8);
+    if temperature.is_some() || heart_rate.is_some() || point.cadence.is_some() {
+        xml.open("extensions", &[])?;
+        xml.open("gpxtpx:TrackPointExtension", &[])?;
+        xml.optional("gpxtpx:atemp", temperature)?;
+        xml.optional("gpxtpx:hr", heart_rate)?;
+        xml.optional("gpxtpx:cad", point.cadence)?;
+        xml.close("gpxtpx:TrackPointExtension")?;
+        xml.close("extensions")?;
+    }
+    xml.close("trkpt")
+}
+
+/// The GPX track type for a TCX sport, using the names Strava and Garmin Connect export.
+fn gpx_type(sport: &str) -> &str {
+    match sport {
+        "Running" => "running",
+        "Biking" => "cycling",
+        _ => "other",
+    }
+}
+
+/// The TCX sport for a GPX track type, which is free text in GPX.
+fn tcx_sport(kind: Option<&str>) -> &'static str {
+    let kind = kind.unwrap_or_default().to_ascii_lowercase();
+    if kind.contains("run") {
+        "Running"
+    } else if kind.contains("cycl") || kind.contains("bik") || kind.contains("ride") {
+        "Biking"
+    } else {
+        "Other"
+    }
+}
+
+fn activity_from_track(track: GpxTrack) -> Activity {
+    let mut activity = Activity {
+        sport: tcx_sport(track.kind.as_deref()).to_string(),
+        notes: track.desc.filter(|desc| !desc.trim().is_empty()),
+        ..Default::default()
+    };
+
+    let mut distance = 0.0;
+    let mut previous: Option<Position> = None;
+    for segment in track.segments {
+        let mut trackpoints = Vec::with_capacity(segment.points.len());
+        for point in segment.points {
+            let time = match point.time {
+                Some(time) => time,
+                None => continue,
+            };
+            let position = Position {
+                latitude: point.lat,
+                longitude: point.lon,
+            };
+            if let Some(previous) = &previous {
+                distance += haversine_distance(previous, &position);
+            }
+            let hdop = point.hdop;
+            let extension = point
+                .extensions
+                .and_then(|extensions| extensions.trackpoint_extension);
+            let (temperature, heart_rate, cadence) = match extension {
+                Some(extension) => (extension.atemp, extension.hr, extension.cad),
+                None => (None, None, None),
+            };
+            trackpoints.push(Trackpoint {
+                time,
+                position: Some(position.clone()),
+                altitude_meters: point.ele,
+                distance_meters: Some(distance),
+                heart_rate: heart_rate.map(|value| HeartRate { value }),
+                cadence,
+                extensions: (temperature.is_some() || hdop.is_some()).then(|| Extensions {
+                    temperature,
+                    hdop,
+                    ..Default::default()
+                }),
+            });
+            previous = Some(position);
+        }
+        if trackpoints.is_empty() {
+            continue;
+        }
+        activity.laps.push(ActivityLap {
+            start_time: Some(trackpoints[0].time),
+            tracks: vec![Track { trackpoints }],
+            ..Default::default()
+        });
+    }
+
+    if let Some(name) = track.name.filter(|name| !name.trim().is_empty()) {
+        activity.set_meta(&ActivityMeta {
+            title: Some(name),
+            ..Default::default()
+        });
+    }
+    activity.fill_required_defaults();
+    activity
+}
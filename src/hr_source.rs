@@ -0,0 +1,124 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tagging heart rates with the kind of sensor that measured them, and reconciling two recordings of the same
+//! session so that chest strap readings win over optical ones.
+
+use crate::edit::{merge_tolerance, nearest};
+use crate::tcx::{Activity, Extensions, HeartRateSource, Trackpoint};
+
+impl Activity {
+    /// Records which kind of sensor measured the heart rates of this activity, on every Trackpoint that has one.
+    ///
+    /// # Parameters
+    ///
+    /// `source: HeartRateSource` -- The kind of sensor.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+    /// activity.tag_heart_rate_source(crate::tcx::HeartRateSource::Optical);
+    /// assert_eq!(activity.heart_rate_source(), Some(crate::tcx::HeartRateSource::Optical));
+    /// ```
+    pub fn tag_heart_rate_source(&mut self, source: HeartRateSource) {
+        for point in self.trackpoints_mut() {
+            if point.heart_rate.is_some() {
+                point
+                    .extensions
+                    .get_or_insert_with(Extensions::default)
+                    .heart_rate_source = Some(source);
+            }
+        }
+    }
+
+    /// Returns the kind of sensor that measured the heart rates, if every tagged Trackpoint agrees.
+    ///
+    /// # Returns
+    ///
+    /// `Option<HeartRateSource>` -- The source, or `None` if no heart rate is tagged or the tags are mixed.
+    pub fn heart_rate_source(&self) -> Option<HeartRateSource> {
+        let mut sources = self.trackpoints().filter_map(heart_rate_source);
+        let first = sources.next()?;
+        if sources.all(|source| source == first) {
+            Some(first)
+        } else {
+            None
+        }
+    }
+
+    /// Reconciles this activity's heart rate with another recording of the same session, such as an optical
+    /// watch and a chest strap paired to a bike computer. Where both have a reading within a couple of seconds,
+    /// the chest strap's is kept; where only the other recording has one, it is copied in. Untagged readings count
+    /// as neither, so they are replaced by a chest strap but not by an optical sensor.
+    ///
+    /// # Parameters
+    ///
+    /// `other: &Activity` -- The other recording, with its heart rate source tagged.
+    ///
+    /// # Returns
+    ///
+    /// `usize` -- The number of Trackpoints whose heart rate changed.
+    pub fn reconcile_heart_rate(&mut self, other: &Activity) -> usize {
+        let mut others: Vec<&Trackpoint> = other
+            .trackpoints()
+            .filter(|point| point.heart_rate.is_some())
+            .collect();
+        others.sort_by_key(|point| point.time);
+        let tolerance = merge_tolerance();
+
+        let mut changed = 0;
+        for point in self.trackpoints_mut() {
+            let source = match nearest(&others, point.time, tolerance) {
+                Some(source) => source,
+                None => continue,
+            };
+            let replace = match point.heart_rate {
+                None => true,
+                Some(_) => {
+                    heart_rate_source(source) == Some(HeartRateSource::ChestStrap)
+                        && heart_rate_source(point) != Some(HeartRateSource::ChestStrap)
+                }
+            };
+            if replace {
+                point.heart_rate = source.heart_rate.clone();
+                point
+                    .extensions
+                    .get_or_insert_with(Extensions::default)
+                    .heart_rate_source = heart_rate_source(source);
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+fn heart_rate_source(point: &Trackpoint) -> Option<HeartRateSource> {
+    point
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.heart_rate_source)
+}
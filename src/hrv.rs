@@ -0,0 +1,156 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Heart rate variability from the R-R intervals some devices record alongside heart rate, for recovery analysis.
+
+use crate::tcx::Activity;
+
+/// Shortest R-R interval, in milliseconds, that is taken to be a real beat (a heart rate of 200 BPM).
+const MIN_RR_MILLISECONDS: f64 = 300.0;
+
+/// Longest R-R interval, in milliseconds, that is taken to be a real beat (a heart rate of 30 BPM).
+const MAX_RR_MILLISECONDS: f64 = 2000.0;
+
+/// Largest change from the previous beat, as a fraction of it, before an interval is treated as an artifact or an
+/// ectopic beat.
+const MAX_RR_CHANGE: f64 = 0.2;
+
+/// Computes the root mean square of successive differences (RMSSD) of a series of R-R intervals.
+///
+/// # Parameters
+///
+/// `intervals: &[f64]` -- The R-R intervals, in milliseconds.
+///
+/// # Returns
+///
+/// `Option<f64>` -- The RMSSD in milliseconds, or None if there are fewer than two intervals.
+///
+/// # Example
+///
+/// ```rust
+/// let rmssd = crate::tcx::hrv::rmssd(&[800.0, 810.0, 790.0]).unwrap();
+/// assert!((rmssd - 250.0_f64.sqrt()).abs() < 1e-9);
+/// ```
+pub fn rmssd(intervals: &[f64]) -> Option<f64> {
+    if intervals.len() < 2 {
+        return None;
+    }
+    let sum: f64 = intervals
+        .windows(2)
+        .map(|pair| (pair[1] - pair[0]).powi(2))
+        .sum();
+    Some((sum / (intervals.len() - 1) as f64).sqrt())
+}
+
+/// Computes the standard deviation of a series of normal-to-normal R-R intervals (SDNN).
+///
+/// # Parameters
+///
+/// `intervals: &[f64]` -- The R-R intervals, in milliseconds.
+///
+/// # Returns
+///
+/// `Option<f64>` -- The sample standard deviation in milliseconds, or None if there are fewer than two intervals.
+///
+/// # Example
+///
+/// ```rust
+/// let sdnn = crate::tcx::hrv::sdnn(&[800.0, 810.0, 790.0]).unwrap();
+/// assert!((sdnn - 10.0).abs() < 1e-9);
+/// ```
+pub fn sdnn(intervals: &[f64]) -> Option<f64> {
+    if intervals.len() < 2 {
+        return None;
+    }
+    let mean = intervals.iter().sum::<f64>() / intervals.len() as f64;
+    let sum: f64 = intervals.iter().map(|value| (value - mean).powi(2)).sum();
+    Some((sum / (intervals.len() - 1) as f64).sqrt())
+}
+
+impl Activity {
+    /// Collects the R-R intervals recorded in the Trackpoint extensions into one series, dropping intervals
+    /// outside 300-2000 milliseconds and those that differ from the previous kept beat by more than a fifth, which
+    /// are artifacts or ectopic beats rather than normal-to-normal intervals.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<f64>` -- The R-R intervals in milliseconds, in the order they were recorded. Empty if the device
+    /// recorded none.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// assert!(tcx.activities.unwrap().activities[0].rr_intervals().is_empty());
+    /// ```
+    pub fn rr_intervals(&self) -> Vec<f64> {
+        let mut intervals: Vec<f64> = Vec::new();
+        let recorded = self
+            .trackpoints()
+            .filter_map(|point| point.extensions.as_ref())
+            .flat_map(|extensions| extensions.rr_intervals.iter());
+        for interval in recorded {
+            let interval = f64::from(*interval);
+            if !(MIN_RR_MILLISECONDS..=MAX_RR_MILLISECONDS).contains(&interval) {
+                continue;
+            }
+            if let Some(previous) = intervals.last() {
+                if (interval - previous).abs() > previous * MAX_RR_CHANGE {
+                    continue;
+                }
+            }
+            intervals.push(interval);
+        }
+        intervals
+    }
+
+    /// Computes the RMSSD of the activity's R-R intervals, as collected by `rr_intervals`. See `hrv::rmssd`.
+    ///
+    /// # Returns
+    ///
+    /// `Option<f64>` -- The RMSSD in milliseconds, or None if fewer than two usable intervals were recorded.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// assert_eq!(tcx.activities.unwrap().activities[0].rmssd(), None);
+    /// ```
+    pub fn rmssd(&self) -> Option<f64> {
+        rmssd(&self.rr_intervals())
+    }
+
+    /// Computes the SDNN of the activity's R-R intervals, as collected by `rr_intervals`. See `hrv::sdnn`.
+    ///
+    /// # Returns
+    ///
+    /// `Option<f64>` -- The SDNN in milliseconds, or None if fewer than two usable intervals were recorded.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// assert_eq!(tcx.activities.unwrap().activities[0].sdnn(), None);
+    /// ```
+    pub fn sdnn(&self) -> Option<f64> {
+        sdnn(&self.rr_intervals())
+    }
+}
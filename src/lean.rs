@@ -0,0 +1,286 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! A compact, read-only summary of an activity, small enough to cache thousands of them in memory for list views.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::io::Read;
+
+use crate::error::Error;
+use crate::geo::EARTH_RADIUS_METERS;
+use crate::stream::TrackpointStream;
+use crate::tcx::{Activity, Position, Trackpoint};
+
+/// Default tolerance, in meters, used when simplifying the route.
+const DEFAULT_TOLERANCE_METERS: f64 = 10.0;
+
+/// Summary numbers and a simplified route for an activity.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct LeanActivity {
+    /// The activity's identifier (usually its start time).
+    pub id: String,
+
+    /// The sport.
+    pub sport: String,
+
+    /// Time of the first Trackpoint.
+    pub start_time: Option<DateTime<Utc>>,
+
+    /// Sum of the laps' `TotalTimeSeconds`.
+    pub total_time_seconds: f64,
+
+    /// Sum of the laps' `DistanceMeters`.
+    pub distance_meters: f64,
+
+    /// Sum of the laps' `Calories`.
+    pub calories: u32,
+
+    /// Average heart rate over all Trackpoints, in Beats per Minute.
+    pub average_heart_rate: Option<f32>,
+
+    /// Maximum heart rate over all Trackpoints, in Beats per Minute.
+    pub maximum_heart_rate: Option<f32>,
+
+    /// Number of Trackpoints in the original activity.
+    pub num_trackpoints: usize,
+
+    /// Simplified route as (latitude, longitude) pairs, in degrees.
+    pub polyline: Vec<(f32, f32)>,
+}
+
+impl LeanActivity {
+    /// Summarizes an activity, simplifying its route so that no point strays more than `tolerance_meters` from the original.
+    ///
+    /// # Parameters
+    ///
+    /// `activity: &Activity` -- The activity to summarize.
+    ///
+    /// `tolerance_meters: f64` -- Maximum allowed deviation of the simplified route, in meters.
+    ///
+    /// # Returns
+    ///
+    /// `LeanActivity`
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let lean = crate::tcx::lean::LeanActivity::new(&tcx.activities.unwrap().activities[0], 5.0);
+    /// assert!(lean.polyline.len() < lean.num_trackpoints / 4);
+    /// ```
+    pub fn new(activity: &Activity, tolerance_meters: f64) -> Self {
+        let mut totals = PointTotals::default();
+        let mut positions: Vec<&Position> = Vec::new();
+        for point in activity.trackpoints() {
+            totals.add(point);
+            if let Some(position) = &point.position {
+                positions.push(position);
+            }
+        }
+
+        LeanActivity {
+            id: activity.id.clone(),
+            sport: activity.sport.clone(),
+            start_time: activity.start_time(),
+            total_time_seconds: activity.laps.iter().map(|lap| lap.total_time_seconds).sum(),
+            distance_meters: activity.laps.iter().map(|lap| lap.distance_meters).sum(),
+            calories: activity
+                .laps
+                .iter()
+                .map(|lap| u32::from(lap.calories))
+                .sum(),
+            average_heart_rate: totals.average_heart_rate(),
+            maximum_heart_rate: totals.maximum_heart_rate(),
+            num_trackpoints: totals.num_trackpoints,
+            polyline: polyline(&positions, tolerance_meters),
+        }
+    }
+
+    /// Summarizes every activity in a TCX document without building the document tree: Trackpoints are streamed
+    /// (see `TrackpointStream`) and only the route positions are kept until each summary is complete. Gives the
+    /// same result as `new` on each activity of the parsed document.
+    ///
+    /// # Parameters
+    ///
+    /// `reader: R` -- The source of the TCX data.
+    ///
+    /// `tolerance_meters: f64` -- Maximum allowed deviation of the simplified routes, in meters.
+    ///
+    /// # Returns
+    ///
+    /// `Result<Vec<LeanActivity>, Error>` -- One summary per activity, including each leg of a multisport session,
+    /// in document order, if OK.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let file = std::fs::File::open("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let summaries = crate::tcx::lean::LeanActivity::from_reader(file, 5.0).unwrap();
+    /// assert_eq!(summaries.len(), 1);
+    /// assert!(summaries[0].polyline.len() < summaries[0].num_trackpoints / 4);
+    /// ```
+    pub fn from_reader<R: Read>(reader: R, tolerance_meters: f64) -> Result<Vec<Self>, Error> {
+        let mut stream = TrackpointStream::new(reader);
+        let mut activities: Vec<(PointTotals, Option<DateTime<Utc>>, Vec<Position>)> = Vec::new();
+        for item in stream.by_ref() {
+            let (activity_index, _, point) = item?;
+            if activities.len() <= activity_index {
+                activities.resize_with(activity_index + 1, Default::default);
+            }
+            let (totals, start_time, positions) = &mut activities[activity_index];
+            totals.add(&point);
+            start_time.get_or_insert(point.time);
+            if let Some(position) = point.position {
+                positions.push(position);
+            }
+        }
+
+        let summaries = stream.summaries();
+        activities.resize_with(summaries.len().max(activities.len()), Default::default);
+        Ok(activities
+            .into_iter()
+            .enumerate()
+            .map(|(index, (totals, start_time, positions))| {
+                let summary = summaries.get(index).cloned().unwrap_or_default();
+                let positions: Vec<&Position> = positions.iter().collect();
+                LeanActivity {
+                    start_time: start_time.or_else(|| {
+                        DateTime::parse_from_rfc3339(&summary.id)
+                            .ok()
+                            .map(|time| time.with_timezone(&Utc))
+                    }),
+                    id: summary.id,
+                    sport: summary.sport,
+                    total_time_seconds: summary.total_time_seconds,
+                    distance_meters: summary.distance_meters,
+                    calories: summary.calories,
+                    average_heart_rate: totals.average_heart_rate(),
+                    maximum_heart_rate: totals.maximum_heart_rate(),
+                    num_trackpoints: totals.num_trackpoints,
+                    polyline: polyline(&positions, tolerance_meters),
+                }
+            })
+            .collect())
+    }
+}
+
+/// Running heart rate and Trackpoint counts for an activity.
+#[derive(Default)]
+struct PointTotals {
+    hr_total: f64,
+    hr_count: usize,
+    hr_max: Option<f64>,
+    num_trackpoints: usize,
+}
+
+impl PointTotals {
+    fn add(&mut self, point: &Trackpoint) {
+        self.num_trackpoints += 1;
+        if let Some(hr) = &point.heart_rate {
+            self.hr_total += hr.value;
+            self.hr_count += 1;
+            self.hr_max = Some(self.hr_max.map_or(hr.value, |max| max.max(hr.value)));
+        }
+    }
+
+    fn average_heart_rate(&self) -> Option<f32> {
+        if self.hr_count > 0 {
+            Some((self.hr_total / self.hr_count as f64) as f32)
+        } else {
+            None
+        }
+    }
+
+    fn maximum_heart_rate(&self) -> Option<f32> {
+        self.hr_max.map(|max| max as f32)
+    }
+}
+
+/// Simplifies the route and converts it to (latitude, longitude) pairs.
+fn polyline(positions: &[&Position], tolerance_meters: f64) -> Vec<(f32, f32)> {
+    simplify(positions, tolerance_meters)
+        .iter()
+        .map(|position| (position.latitude as f32, position.longitude as f32))
+        .collect()
+}
+
+impl From<&Activity> for LeanActivity {
+    /// Summarizes an activity using a ten meter route tolerance.
+    fn from(activity: &Activity) -> Self {
+        LeanActivity::new(activity, DEFAULT_TOLERANCE_METERS)
+    }
+}
+
+/// Ramer-Douglas-Peucker simplification of a route.
+fn simplify<'a>(positions: &[&'a Position], tolerance_meters: f64) -> Vec<&'a Position> {
+    if positions.len() < 3 {
+        return positions.to_vec();
+    }
+
+    let mut keep = vec![false; positions.len()];
+    keep[0] = true;
+    keep[positions.len() - 1] = true;
+    let mut stack = vec![(0, positions.len() - 1)];
+    while let Some((first, last)) = stack.pop() {
+        let mut farthest = first;
+        let mut farthest_distance = 0.0;
+        for i in (first + 1)..last {
+            let distance = distance_to_segment(positions[i], positions[first], positions[last]);
+            if distance > farthest_distance {
+                farthest = i;
+                farthest_distance = distance;
+            }
+        }
+        if farthest_distance > tolerance_meters {
+            keep[farthest] = true;
+            stack.push((first, farthest));
+            stack.push((farthest, last));
+        }
+    }
+
+    positions
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(position, _)| *position)
+        .collect()
+}
+
+/// Distance in meters from `point` to the segment from `start` to `end`, using a local flat-earth projection.
+fn distance_to_segment(point: &Position, start: &Position, end: &Position) -> f64 {
+    let scale_x = start.latitude.to_radians().cos() * EARTH_RADIUS_METERS;
+    let project = |position: &Position| {
+        (
+            (position.longitude - start.longitude).to_radians() * scale_x,
+            (position.latitude - start.latitude).to_radians() * EARTH_RADIUS_METERS,
+        )
+    };
+    let (px, py) = project(point);
+    let (ex, ey) = project(end);
+    let length_squared = ex * ex + ey * ey;
+    let t = if length_squared > 0.0 {
+        ((px * ex + py * ey) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    ((px - t * ex).powi(2) + (py - t * ey).powi(2)).sqrt()
+}
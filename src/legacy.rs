@@ -0,0 +1,394 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Importers for the JSON data exports of platforms that have shut down, so that old history can be consolidated
+//! as TCX: Endomondo workout files (`Workouts/*.json` in its archive) and Runkeeper fitness activities (as returned
+//! by its Health Graph API). Each file becomes one activity with one lap. The platform and its own name for the
+//! sport are kept as tags in the activity's metadata (see `meta`), since TCX only knows running and biking.
+
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::meta::ActivityMeta;
+use crate::tcx::{
+    Activities, Activity, ActivityLap, Extensions, HeartRate, Ns3Tpx, Position, Track, Trackpoint,
+    TrainingCenterDatabase,
+};
+
+/// Why a legacy export could not be imported.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportError {
+    /// The document is not shaped like the expected export.
+    UnexpectedFormat(String),
+
+    /// A time could not be read.
+    InvalidTime(String),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::UnexpectedFormat(what) => write!(f, "Unexpected format: {}", what),
+            ImportError::InvalidTime(time) => write!(f, "Invalid time '{}'", time),
+        }
+    }
+}
+
+impl Error for ImportError {}
+
+impl TrainingCenterDatabase {
+    /// Imports an Endomondo workout file. Endomondo writes each workout as an array of single-field objects, with
+    /// the samples under `points`; distances are in kilometers and speeds in kilometers per hour.
+    ///
+    /// # Parameters
+    ///
+    /// `json: &[u8]` -- The contents of the workout file.
+    ///
+    /// # Returns
+    ///
+    /// `Result<TrainingCenterDatabase, Box<dyn Error>>` -- A database with the one activity if OK, a JSON error or
+    /// `ImportError` if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let json = br#"[
+    ///     {"sport": "CYCLING_SPORT"},
+    ///     {"start_time": "2019-06-09 15:11:29.0"},
+    ///     {"duration_s": 10.0},
+    ///     {"distance_km": 0.08},
+    ///     {"points": [
+    ///         [{"location": [[{"latitude": 52.1}, {"longitude": 21.0}]]}, {"timestamp": "Sun Jun 09 15:11:29 UTC 2019"}],
+    ///         [{"location": [[{"latitude": 52.1007}, {"longitude": 21.0}]]}, {"timestamp": "Sun Jun 09 15:11:39 UTC 2019"}]
+    ///     ]}
+    /// ]"#;
+    /// let tcx = crate::tcx::TrainingCenterDatabase::from_endomondo_json(json).unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// assert_eq!(activity.sport, "Biking");
+    /// assert_eq!(activity.laps[0].distance_meters, 80.0);
+    /// ```
+    pub fn from_endomondo_json(json: &[u8]) -> Result<TrainingCenterDatabase, Box<dyn Error>> {
+        let workout = fields(&serde_json::from_slice(json)?);
+        let sport = text(&workout, "sport").unwrap_or_default();
+        let mut trackpoints = Vec::new();
+        for point in workout
+            .get("points")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let point = fields(point);
+            let time = match text(&point, "timestamp") {
+                Some(time) => parse_endomondo_time(&time)?,
+                None => continue,
+            };
+            let location = point.get("location").map(fields).unwrap_or_default();
+            trackpoints.push(trackpoint(
+                time,
+                number(&location, "latitude").zip(number(&location, "longitude")),
+                number(&point, "altitude"),
+                number(&point, "distance_km").map(|km| km * 1000.0),
+                number(&point, "heart_rate_bpm"),
+                number(&point, "cadence_rpm"),
+                number(&point, "speed_kmh").map(|kmh| kmh / 3.6),
+            ));
+        }
+
+        let start_time = match text(&workout, "start_time") {
+            Some(time) => Some(parse_endomondo_time(&time)?),
+            None => trackpoints.first().map(|point| point.time),
+        };
+        let start_time = start_time
+            .ok_or_else(|| ImportError::UnexpectedFormat(String::from("no start time")))?;
+        let lap = ActivityLap {
+            start_time: Some(start_time),
+            total_time_seconds: number(&workout, "duration_s").unwrap_or_default(),
+            distance_meters: number(&workout, "distance_km").unwrap_or_default() * 1000.0,
+            calories: number(&workout, "calories_kcal").map_or(0, |kcal| kcal.round() as u16),
+            tracks: tracks(vec![trackpoints]),
+            ..Default::default()
+        };
+        Ok(database(activity(
+            "endomondo",
+            &sport,
+            start_time,
+            lap,
+            text(&workout, "message"),
+        )))
+    }
+
+    /// Imports a Runkeeper fitness activity. The local `start_time` is moved to UTC with `utc_offset`, in hours, and
+    /// the `path`, `heart_rate` and `distance` samples, each timed in seconds from the start, are merged into
+    /// Trackpoints. Each pause in the path starts a new Track.
+    ///
+    /// # Parameters
+    ///
+    /// `json: &[u8]` -- The fitness activity.
+    ///
+    /// # Returns
+    ///
+    /// `Result<TrainingCenterDatabase, Box<dyn Error>>` -- A database with the one activity if OK, a JSON error or
+    /// `ImportError` if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let json = br#"{
+    ///     "type": "Running",
+    ///     "start_time": "Tue, 1 Mar 2011 07:00:00",
+    ///     "utc_offset": -8,
+    ///     "duration": 60,
+    ///     "total_distance": 200,
+    ///     "path": [
+    ///         {"timestamp": 0, "latitude": 47.6, "longitude": -122.3, "altitude": 20, "type": "start"},
+    ///         {"timestamp": 60, "latitude": 47.6018, "longitude": -122.3, "altitude": 21, "type": "end"}
+    ///     ],
+    ///     "heart_rate": [{"timestamp": 0, "heart_rate": 110}, {"timestamp": 60, "heart_rate": 150}]
+    /// }"#;
+    /// let tcx = crate::tcx::TrainingCenterDatabase::from_runkeeper_json(json).unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// assert_eq!(activity.id, "2011-03-01T15:00:00.000Z");
+    /// assert_eq!(activity.trackpoints().count(), 2);
+    /// ```
+    pub fn from_runkeeper_json(json: &[u8]) -> Result<TrainingCenterDatabase, Box<dyn Error>> {
+        let value: Value = serde_json::from_slice(json)?;
+        let fitness = value
+            .as_object()
+            .ok_or_else(|| ImportError::UnexpectedFormat(String::from("not an object")))?;
+        let start = text(fitness, "start_time")
+            .ok_or_else(|| ImportError::UnexpectedFormat(String::from("no start_time")))?;
+        let local = NaiveDateTime::parse_from_str(&start, "%a, %d %b %Y %H:%M:%S")
+            .map_err(|_| ImportError::InvalidTime(start.clone()))?;
+        let offset = number(fitness, "utc_offset").unwrap_or_default();
+        let start_time = offset_time(
+            Utc.from_utc_datetime(&local),
+            -(offset * 3_600_000.0).round() as i64,
+        )?;
+
+        // Merge the sample streams by time, with the path deciding where Tracks break.
+        #[derive(Default)]
+        struct Sample {
+            position: Option<(f64, f64)>,
+            altitude: Option<f64>,
+            distance: Option<f64>,
+            heart_rate: Option<f64>,
+            resume: bool,
+        }
+        let mut samples: BTreeMap<i64, Sample> = BTreeMap::new();
+        let stream = |name: &str| {
+            fitness
+                .get(name)
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_object)
+                .filter_map(|sample| {
+                    number(sample, "timestamp")
+                        .map(|seconds| ((seconds * 1000.0).round() as i64, sample))
+                })
+        };
+        let mut paused = false;
+        for (millis, point) in stream("path") {
+            let sample = samples.entry(millis).or_default();
+            sample.position = number(point, "latitude").zip(number(point, "longitude"));
+            sample.altitude = number(point, "altitude");
+            match text(point, "type").as_deref() {
+                Some("pause") => paused = true,
+                Some("resume") if paused => {
+                    sample.resume = true;
+                    paused = false;
+                }
+                _ => {}
+            }
+        }
+        for (millis, point) in stream("heart_rate") {
+            samples.entry(millis).or_default().heart_rate = number(point, "heart_rate");
+        }
+        for (millis, point) in stream("distance") {
+            samples.entry(millis).or_default().distance = number(point, "distance");
+        }
+
+        let mut segments = vec![Vec::new()];
+        for (millis, sample) in samples {
+            if sample.resume {
+                segments.push(Vec::new());
+            }
+            segments.last_mut().unwrap().push(trackpoint(
+                offset_time(start_time, millis)?,
+                sample.position,
+                sample.altitude,
+                sample.distance,
+                sample.heart_rate,
+                None,
+                None,
+            ));
+        }
+        let lap = ActivityLap {
+            start_time: Some(start_time),
+            total_time_seconds: number(fitness, "duration").unwrap_or_default(),
+            distance_meters: number(fitness, "total_distance").unwrap_or_default(),
+            calories: number(fitness, "total_calories").map_or(0, |kcal| kcal.round() as u16),
+            tracks: tracks(segments),
+            ..Default::default()
+        };
+        let sport = text(fitness, "type").unwrap_or_default();
+        Ok(database(activity(
+            "runkeeper",
+            &sport,
+            start_time,
+            lap,
+            text(fitness, "notes"),
+        )))
+    }
+}
+
+/// Collects the fields of an object, or of an array of (arrays of) single-field objects as Endomondo writes them.
+fn fields(value: &Value) -> Map<String, Value> {
+    match value {
+        Value::Object(object) => object.clone(),
+        Value::Array(items) => {
+            let mut merged = Map::new();
+            for item in items {
+                merged.extend(fields(item));
+            }
+            merged
+        }
+        _ => Map::new(),
+    }
+}
+
+fn text(object: &Map<String, Value>, key: &str) -> Option<String> {
+    object.get(key).and_then(Value::as_str).map(String::from)
+}
+
+fn number(object: &Map<String, Value>, key: &str) -> Option<f64> {
+    object.get(key).and_then(Value::as_f64)
+}
+
+/// The time `millis` milliseconds after `time`, or `ImportError::InvalidTime` if that is out of range.
+fn offset_time(time: DateTime<Utc>, millis: i64) -> Result<DateTime<Utc>, ImportError> {
+    Duration::try_milliseconds(millis)
+        .and_then(|offset| time.checked_add_signed(offset))
+        .ok_or_else(|| ImportError::InvalidTime(format!("{} {:+} ms", time.to_rfc3339(), millis)))
+}
+
+/// Reads an Endomondo time: `2019-06-09 15:11:29.0` in workout summaries, `Sun Jun 09 15:11:29 UTC 2019` in
+/// samples. Both are in UTC.
+fn parse_endomondo_time(time: &str) -> Result<DateTime<Utc>, ImportError> {
+    ["%Y-%m-%d %H:%M:%S%.f", "%a %b %d %H:%M:%S UTC %Y"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(time.trim(), format).ok())
+        .map(|time| Utc.from_utc_datetime(&time))
+        .or_else(|| {
+            DateTime::parse_from_rfc3339(time.trim())
+                .ok()
+                .map(|time| time.with_timezone(&Utc))
+        })
+        .ok_or_else(|| ImportError::InvalidTime(time.to_string()))
+}
+
+fn trackpoint(
+    time: DateTime<Utc>,
+    position: Option<(f64, f64)>,
+    altitude: Option<f64>,
+    distance: Option<f64>,
+    heart_rate: Option<f64>,
+    cadence: Option<f64>,
+    speed: Option<f64>,
+) -> Trackpoint {
+    Trackpoint {
+        time,
+        position: position.map(|(latitude, longitude)| Position {
+            latitude,
+            longitude,
+        }),
+        altitude_meters: altitude,
+        distance_meters: distance,
+        heart_rate: heart_rate.map(|value| HeartRate { value }),
+        cadence: cadence.map(|cadence| cadence.round().clamp(0.0, 254.0) as u8),
+        extensions: speed.map(|speed| Extensions {
+            tpx: Some(Ns3Tpx {
+                speed: Some(speed),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+    }
+}
+
+fn tracks(segments: Vec<Vec<Trackpoint>>) -> Vec<Track> {
+    segments
+        .into_iter()
+        .filter(|trackpoints| !trackpoints.is_empty())
+        .map(|trackpoints| Track { trackpoints })
+        .collect()
+}
+
+/// The TCX sport for a platform's sport name, such as `CYCLING_SPORT` or `Mountain Biking`.
+fn tcx_sport(sport: &str) -> &'static str {
+    let sport = sport.to_ascii_lowercase();
+    if sport.contains("run") {
+        "Running"
+    } else if sport.contains("cycl") || sport.contains("bik") {
+        "Biking"
+    } else {
+        "Other"
+    }
+}
+
+fn activity(
+    platform: &str,
+    sport: &str,
+    start_time: DateTime<Utc>,
+    lap: ActivityLap,
+    notes: Option<String>,
+) -> Activity {
+    let mut activity = Activity {
+        sport: tcx_sport(sport).to_string(),
+        id: crate::writer::format_time(start_time),
+        laps: vec![lap],
+        notes: notes.filter(|notes| !notes.trim().is_empty()),
+        ..Default::default()
+    };
+    let mut tags = vec![platform.to_string()];
+    if !sport.trim().is_empty() {
+        tags.push(sport.trim().to_string());
+    }
+    activity.set_meta(&ActivityMeta {
+        tags,
+        ..Default::default()
+    });
+    activity.fill_required_defaults();
+    activity
+}
+
+fn database(activity: Activity) -> TrainingCenterDatabase {
+    TrainingCenterDatabase {
+        activities: Some(Activities {
+            activities: vec![activity],
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,522 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Lenient reading of malformed TCX files, such as exports from apps that leave out `Calories`, write empty
+//! `<Position/>` elements, record impossible cadences or omit the time zone from timestamps. The document is
+//! repaired before it is deserialized: missing required lap totals are set to zero, timestamps without a time zone
+//! are taken to be UTC, values that cannot be read are dropped, and Trackpoints without a usable time are skipped.
+//! Each repair is reported as a `Warning`. Only the `Activities` part of the file is repaired.
+
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufReader, Read};
+use xml::common::Position;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::error::Error;
+use crate::storage::TcxSource;
+use crate::tcx::{TrackpointPolicy, TrainingCenterDatabase};
+use crate::writer::escape;
+
+/// Lap totals that the schema requires, which are set to zero when missing.
+const REQUIRED_LAP_TOTALS: &[&str] = &["TotalTimeSeconds", "DistanceMeters", "Calories"];
+
+/// Elements holding real numbers.
+const REAL_ELEMENTS: &[&str] = &[
+    "TotalTimeSeconds",
+    "DistanceMeters",
+    "MaximumSpeed",
+    "AverageHeartRate",
+    "MaximumHeartRate",
+    "AltitudeMeters",
+    "LatitudeDegrees",
+    "LongitudeDegrees",
+    "Value",
+    "Speed",
+    "AvgSpeed",
+    "Pressure",
+    "Temperature",
+];
+
+/// Elements holding whole numbers, with the largest value each can hold.
+const INTEGER_ELEMENTS: &[(&str, u32)] = &[
+    ("Cadence", u8::MAX as u32),
+    ("RunCadence", u8::MAX as u32),
+    ("MaxBikeCadence", u8::MAX as u32),
+    ("AvgRunCadence", u8::MAX as u32),
+    ("MaxRunCadence", u8::MAX as u32),
+    ("Calories", u16::MAX as u32),
+    ("Watts", u16::MAX as u32),
+    ("Steps", u16::MAX as u32),
+    ("AvgWatts", u16::MAX as u32),
+    ("MaxWatts", u16::MAX as u32),
+];
+
+/// Elements holding one of a fixed set of names.
+const NAMED_ELEMENTS: &[(&str, &[&str])] = &[
+    ("Intensity", &["Active", "Resting"]),
+    (
+        "TriggerMethod",
+        &["Manual", "Distance", "Location", "Time", "HeartRate"],
+    ),
+];
+
+/// Something that was wrong with the file and how it was recovered from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// The 1-based line of the element concerned.
+    pub line: u64,
+
+    /// The path of the element concerned, starting below `Activities`, such as `Activity/Lap/Calories`.
+    pub path: String,
+
+    /// What was wrong, and what was done about it.
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.path, self.message)
+    }
+}
+
+/// An element of the document being repaired.
+struct Element {
+    /// The name as written, with any prefix.
+    name: String,
+    /// The name without its prefix.
+    local: String,
+    /// Attributes as (name as written, local name, value).
+    attributes: Vec<(String, String, String)>,
+    /// Namespace declarations made on this element, as (attribute name, namespace).
+    declarations: Vec<(String, String)>,
+    children: Vec<Node>,
+    line: u64,
+}
+
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    /// The element's text content, trimmed.
+    fn text(&self) -> String {
+        let mut text = String::new();
+        for child in &self.children {
+            if let Node::Text(part) = child {
+                text.push_str(part);
+            }
+        }
+        text.trim().to_string()
+    }
+
+    fn set_text(&mut self, text: String) {
+        self.children = vec![Node::Text(text)];
+    }
+
+    fn has_child(&self, local: &str) -> bool {
+        self.children
+            .iter()
+            .any(|child| matches!(child, Node::Element(element) if element.local == local))
+    }
+}
+
+/// Reads TCX data from a buffer previously defined, repairing what it can rather than failing.
+///
+/// # Parameters
+///
+/// `reader: &mut BufReader<R>` -- A buffer of a file previously opened.
+///
+/// # Returns
+///
+/// `Result<(TrainingCenterDatabase, Vec<Warning>), crate::tcx::Error>` -- The data and a description of each
+/// repair made. An Error if the file is not well-formed XML, or is beyond repair.
+///
+/// # Example
+///
+/// ```rust
+/// let xml = r#"<TrainingCenterDatabase><Activities><Activity Sport="Running"><Id>1</Id>
+///   <Lap StartTime="2021-01-19T23:30:50"><TotalTimeSeconds>1</TotalTimeSeconds><DistanceMeters>3</DistanceMeters>
+///   <Track><Trackpoint><Time>2021-01-19T23:30:50</Time><Position/><Cadence>300</Cadence></Trackpoint></Track>
+///   </Lap></Activity></Activities></TrainingCenterDatabase>"#;
+/// let mut reader = std::io::BufReader::new(xml.as_bytes());
+/// let (tcx, warnings) = crate::tcx::lenient::read_lossy(&mut reader).unwrap();
+/// assert_eq!(tcx.activities.unwrap().activities[0].laps[0].tracks[0].trackpoints.len(), 1);
+/// assert_eq!(warnings.len(), 5);
+/// ```
+pub fn read_lossy<R: Read>(
+    reader: &mut BufReader<R>,
+) -> Result<(TrainingCenterDatabase, Vec<Warning>), Error> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    deserialize(&bytes, TrackpointPolicy::All)
+}
+
+/// Reads TCX data from the filename specified, repairing what it can rather than failing. See `read_lossy`.
+///
+/// # Parameters
+///
+/// `filename: &str` -- The name of the file to be read.
+///
+/// # Returns
+///
+/// `Result<(TrainingCenterDatabase, Vec<Warning>), crate::tcx::Error>`
+pub fn read_file_lossy(filename: &str) -> Result<(TrainingCenterDatabase, Vec<Warning>), Error> {
+    let mut path = std::path::Path::new(filename);
+    let file = path.open()?;
+    let mut reader = BufReader::new(file);
+    read_lossy(&mut reader)
+}
+
+/// Repairs the document and deserializes the result.
+pub(crate) fn deserialize(
+    bytes: &[u8],
+    policy: TrackpointPolicy,
+) -> Result<(TrainingCenterDatabase, Vec<Warning>), Error> {
+    let (repaired, warnings) = repair(bytes, policy)?;
+    let tcx = serde_xml_rs::from_reader(repaired.as_slice())
+        .map_err(|err| Error::from_deserializer(err, &repaired))?;
+    Ok((tcx, warnings))
+}
+
+/// Repairs the document, returning the repaired XML and the repairs made. The Trackpoints the policy skips are
+/// removed first, so they are neither repaired nor counted when the policy picks the rest.
+fn repair(bytes: &[u8], policy: TrackpointPolicy) -> Result<(Vec<u8>, Vec<Warning>), Error> {
+    let mut root = parse(bytes)?;
+    skip_trackpoints(&mut root, policy);
+    let mut warnings = Vec::new();
+    repair_element(&mut root, &mut Vec::new(), &mut warnings);
+
+    let mut repaired = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    write_element(&root, &mut repaired);
+    Ok((repaired.into_bytes(), warnings))
+}
+
+/// Reads the document into a tree of elements.
+fn parse(bytes: &[u8]) -> Result<Element, Error> {
+    let mut reader = EventReader::new(bytes);
+    let mut open: Vec<(Element, BTreeMap<String, String>)> = Vec::new();
+    loop {
+        let event = reader.next();
+        let line = reader.position().row + 1;
+        match event {
+            Ok(XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            }) => {
+                let inherited = open.last().map(|(_, namespace)| namespace);
+                let declarations = namespace
+                    .0
+                    .iter()
+                    .filter(|(prefix, _)| prefix.as_str() != "xml" && prefix.as_str() != "xmlns")
+                    .filter(
+                        |(prefix, uri)| match inherited.and_then(|map| map.get(*prefix)) {
+                            Some(parent_uri) => parent_uri != *uri,
+                            None => !(prefix.is_empty() && uri.is_empty()),
+                        },
+                    )
+                    .map(|(prefix, uri)| {
+                        let attribute = if prefix.is_empty() {
+                            "xmlns".to_string()
+                        } else {
+                            format!("xmlns:{}", prefix)
+                        };
+                        (attribute, uri.clone())
+                    })
+                    .collect();
+                let element = Element {
+                    name: qualified(&name.prefix, &name.local_name),
+                    local: name.local_name,
+                    attributes: attributes
+                        .into_iter()
+                        .map(|attribute| {
+                            (
+                                qualified(&attribute.name.prefix, &attribute.name.local_name),
+                                attribute.name.local_name,
+                                attribute.value,
+                            )
+                        })
+                        .collect(),
+                    declarations,
+                    children: Vec::new(),
+                    line,
+                };
+                open.push((element, namespace.0));
+            }
+            Ok(XmlEvent::EndElement { .. }) => {
+                let (element, _) = open.pop().expect("unbalanced element");
+                match open.last_mut() {
+                    Some((parent, _)) => parent.children.push(Node::Element(element)),
+                    None => return Ok(element),
+                }
+            }
+            Ok(XmlEvent::Characters(text)) | Ok(XmlEvent::CData(text)) => {
+                if let Some((parent, _)) = open.last_mut() {
+                    parent.children.push(Node::Text(text));
+                }
+            }
+            Ok(XmlEvent::EndDocument) => {
+                return Err(Error::InvalidValue("the document is empty".to_string()))
+            }
+            Ok(_) => {}
+            Err(err) => {
+                let position = err.position();
+                return Err(Error::Xml {
+                    message: err.msg().to_string(),
+                    line: position.row + 1,
+                    column: position.column + 1,
+                });
+            }
+        }
+    }
+}
+
+fn qualified(prefix: &Option<String>, local: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}:{}", prefix, local),
+        None => local.to_string(),
+    }
+}
+
+/// Repairs an element and its children. Returns false if the element should be removed.
+fn repair_element(
+    element: &mut Element,
+    path: &mut Vec<String>,
+    warnings: &mut Vec<Warning>,
+) -> bool {
+    path.push(element.local.clone());
+    let inside = path.iter().any(|name| name == "Activities") && element.local != "Activities";
+
+    if inside {
+        let (line, location) = (element.line, location(path));
+        let mut warn = |message: String| {
+            warnings.push(Warning {
+                line,
+                path: location.clone(),
+                message,
+            });
+        };
+        let mut attributes = std::mem::take(&mut element.attributes);
+        attributes.retain_mut(|(_, local, value)| {
+            if local != "StartTime" {
+                return true;
+            }
+            match repair_time(value) {
+                Some(time) => {
+                    if time != *value {
+                        warn(format!("StartTime {} has no time zone; assumed UTC", value));
+                        *value = time;
+                    }
+                    true
+                }
+                None => {
+                    warn(format!("StartTime {} is not a valid time; removed", value));
+                    false
+                }
+            }
+        });
+        element.attributes = attributes;
+    }
+
+    let mut children = std::mem::take(&mut element.children);
+    children.retain_mut(|child| match child {
+        Node::Element(child) => repair_element(child, path, warnings),
+        Node::Text(_) => true,
+    });
+    element.children = children;
+
+    let keep = !inside || repair_content(element, path, warnings);
+    path.pop();
+    keep
+}
+
+/// Removes the Trackpoints the policy skips from every track within the element.
+fn skip_trackpoints(element: &mut Element, policy: TrackpointPolicy) {
+    let mut index = 0;
+    let is_track = element.local == "Track";
+    element.children.retain_mut(|child| match child {
+        Node::Element(child) if is_track && child.local == "Trackpoint" => {
+            index += 1;
+            policy.keeps(index - 1)
+        }
+        Node::Element(child) => {
+            skip_trackpoints(child, policy);
+            true
+        }
+        Node::Text(_) => true,
+    });
+}
+
+/// Applies the repairs for the element's own content, once its children have been repaired. Returns false if the
+/// element should be removed.
+fn repair_content(element: &mut Element, path: &[String], warnings: &mut Vec<Warning>) -> bool {
+    let (line, location) = (element.line, location(path));
+    let mut warn = |message: String| {
+        warnings.push(Warning {
+            line,
+            path: location.clone(),
+            message,
+        });
+    };
+    let local = element.local.clone();
+    let local = local.as_str();
+    let parent = path.len().checked_sub(2).map(|i| path[i].as_str());
+
+    match local {
+        "Trackpoint" if !element.has_child("Time") => {
+            warn("Trackpoint has no valid Time; skipped".to_string());
+            false
+        }
+        "Position"
+            if !element.has_child("LatitudeDegrees") || !element.has_child("LongitudeDegrees") =>
+        {
+            warn("Position is incomplete; removed".to_string());
+            false
+        }
+        "HeartRateBpm" | "AverageHeartRateBpm" | "MaximumHeartRateBpm"
+            if !element.has_child("Value") =>
+        {
+            warn(format!("{} has no valid Value; removed", local));
+            false
+        }
+        "Lap" | "Transition" => {
+            for total in REQUIRED_LAP_TOTALS {
+                if !element.has_child(total) {
+                    warn(format!("{} is missing; using 0", total));
+                    element.children.insert(
+                        0,
+                        Node::Element(Element {
+                            name: qualified_like(&element.name, total),
+                            local: total.to_string(),
+                            attributes: Vec::new(),
+                            declarations: Vec::new(),
+                            children: vec![Node::Text("0".to_string())],
+                            line: element.line,
+                        }),
+                    );
+                }
+            }
+            true
+        }
+        "Time" => repair_leaf(element, &mut warn, repair_time),
+        "Id" if parent == Some("MultiSportSession") => repair_leaf(element, &mut warn, repair_time),
+        _ if REAL_ELEMENTS.contains(&local) => repair_leaf(element, &mut warn, |text| {
+            text.parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .map(|_| text.to_string())
+        }),
+        _ => {
+            if let Some((_, max)) = INTEGER_ELEMENTS.iter().find(|(name, _)| *name == local) {
+                let max = *max as f64;
+                return repair_leaf(element, &mut warn, |text| {
+                    text.parse::<f64>()
+                        .ok()
+                        .filter(|value| *value >= 0.0 && *value <= max)
+                        .map(|value| value.round().to_string())
+                });
+            }
+            if let Some((_, names)) = NAMED_ELEMENTS.iter().find(|(name, _)| *name == local) {
+                return repair_leaf(element, &mut warn, |text| {
+                    names.contains(&text).then(|| text.to_string())
+                });
+            }
+            true
+        }
+    }
+}
+
+/// The path of the element, starting below `Activities`.
+fn location(path: &[String]) -> String {
+    let start = path
+        .iter()
+        .position(|name| name == "Activities")
+        .map_or(0, |i| i + 1);
+    path[start..].join("/")
+}
+
+/// Checks an element's text with `fix`, which returns the text to use or `None` if it cannot be read. Replaces the
+/// text if it was fixed, and returns false if it cannot be read.
+fn repair_leaf<F, P>(element: &mut Element, warn: &mut F, fix: P) -> bool
+where
+    F: FnMut(String),
+    P: Fn(&str) -> Option<String>,
+{
+    let text = element.text();
+    match fix(&text) {
+        Some(fixed) if fixed == text => true,
+        Some(fixed) => {
+            warn(format!("{} {} was read as {}", element.local, text, fixed));
+            element.set_text(fixed);
+            true
+        }
+        None => {
+            warn(format!(
+                "{} {:?} cannot be read; removed",
+                element.local, text
+            ));
+            false
+        }
+    }
+}
+
+/// Returns the timestamp as it should be written: unchanged if it is valid, in UTC if it has no time zone, or
+/// `None` if it cannot be read.
+fn repair_time(text: &str) -> Option<String> {
+    let text = text.trim();
+    if text.parse::<DateTime<Utc>>().is_ok() {
+        return Some(text.to_string());
+    }
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .map(|time| time.and_utc().to_rfc3339_opts(SecondsFormat::Millis, true))
+}
+
+/// Gives a new element the same prefix as `sibling_name`.
+fn qualified_like(sibling_name: &str, local: &str) -> String {
+    match sibling_name.split_once(':') {
+        Some((prefix, _)) => format!("{}:{}", prefix, local),
+        None => local.to_string(),
+    }
+}
+
+fn write_element(element: &Element, out: &mut String) {
+    out.push('<');
+    out.push_str(&element.name);
+    for (name, value) in &element.declarations {
+        out.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+    }
+    for (name, _, value) in &element.attributes {
+        out.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+    }
+    out.push('>');
+    for child in &element.children {
+        match child {
+            Node::Element(child) => write_element(child, out),
+            Node::Text(text) => out.push_str(&escape(text)),
+        }
+    }
+    out.push_str(&format!("</{}>", element.name));
+}
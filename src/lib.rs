@@ -24,8 +24,83 @@
 //! information from runs, bike rides, etc.
 //! This crate builds on Rust's serde deserialization framework.
 
+pub use crate::error::Error;
+pub use crate::stream::{StreamPosition, TrackpointStream};
 pub use crate::tcx::*;
+pub mod altitude;
+#[cfg(feature = "arena")]
+pub mod arena;
+pub mod batch;
+pub mod bearing;
+pub mod builder;
+pub mod cadence;
+pub mod climbs;
+pub mod compare;
+pub mod compliance;
+pub mod corpus;
+pub mod csv;
+pub mod dataset;
+pub mod daylight;
+pub mod declutter;
+pub mod directions;
+pub mod edit;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub mod error;
+pub mod fitlog;
+pub mod gear;
+pub mod geo;
+pub mod gps_quality;
+pub mod gpx;
+pub mod hr_source;
+pub mod hrv;
+pub mod lean;
+pub mod legacy;
+pub mod lenient;
+pub mod manifest;
+#[cfg(feature = "map_match")]
+pub mod map_match;
+pub mod meta;
+pub mod metrics;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+pub mod multisport;
+pub mod normalize;
+#[cfg(feature = "object_store")]
+pub mod object;
+pub mod patch;
+pub mod power;
+pub mod predict;
+pub mod privacy;
+#[cfg(feature = "proj")]
+pub mod projection;
+#[cfg(feature = "proto")]
+pub mod proto;
+pub mod quality;
+#[cfg(feature = "quick_xml")]
+pub mod quick;
+pub mod repair;
+pub mod sampling;
+pub mod series;
+pub mod shared;
+pub mod sport;
+pub mod stats;
+pub mod stops;
+pub mod storage;
+pub mod stream;
+pub mod swim;
+pub mod synthetic;
 pub mod tcx;
+pub mod thresholds;
+pub mod topology;
+pub mod training_log;
+pub mod validate;
+#[cfg(feature = "watch")]
+pub mod watch;
+pub mod wind;
+pub mod workout;
+pub mod writer;
+pub mod zones;
 
 #[cfg(test)]
 mod tests {
@@ -62,7 +137,7 @@ mod tests {
     #[test]
     fn file2_ride_with_power() {
         let result = crate::tcx::TrainingCenterDatabase::from_file(
-            &mut "tests/20210308_virtual_ride_with_power.tcx",
+            "tests/20210308_virtual_ride_with_power.tcx",
         )
         .unwrap();
         let activities = result.activities.unwrap();
@@ -98,8 +173,7 @@ mod tests {
 
     #[test]
     fn file3_yoga() {
-        let result =
-            crate::tcx::read_file("tests/20210323_yoga.tcx").unwrap();
+        let result = crate::tcx::read_file("tests/20210323_yoga.tcx").unwrap();
         let activities = result.activities.unwrap();
 
         // Correct number of activities?
@@ -110,4 +184,6066 @@ mod tests {
         assert_eq!(activity.laps.len(), 1);
         let _lap = &activity.laps[0];
     }
+
+    #[test]
+    fn pace_zones_run() {
+        let result = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let activities = result.activities.unwrap();
+        let activity = &activities.activities[0];
+
+        // Five zones, covering (almost) the whole run.
+        let zones = crate::zones::PaceZones::from_threshold_pace(270.0);
+        let distribution = activity.pace_zone_distribution(&zones);
+        assert_eq!(distribution.len(), 5);
+        let total: f64 = distribution.iter().sum();
+        assert!(total > 1200.0 && total <= 1232.0);
+
+        // The per-lap distribution matches for a single lap activity.
+        assert_eq!(
+            activity.laps[0].pace_zone_distribution(&zones),
+            distribution
+        );
+    }
+
+    #[test]
+    fn hr_and_power_zones() {
+        let result = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let activities = result.activities.unwrap();
+        let activity = &activities.activities[0];
+
+        // Heart rate and power zones each cover the ride, and match the single lap.
+        let hr_zones = crate::zones::HeartRateZones::from_lthr(170.0);
+        let hr = activity.hr_zone_distribution(&hr_zones);
+        assert_eq!(hr.len(), 5);
+        assert_eq!(activity.laps[0].hr_zone_distribution(&hr_zones), hr);
+        let power_zones = crate::zones::PowerZones::from_ftp(250.0);
+        let power = activity.power_zone_distribution(&power_zones);
+        assert_eq!(power.len(), 7);
+        assert!(power.iter().sum::<f64>() > 1400.0);
+        assert_eq!(
+            activity.laps[0].power_zone_distribution(&power_zones),
+            power
+        );
+
+        // A harder effort relative to a lower FTP.
+        let intensity = activity.intensity_factor(250.0).unwrap();
+        assert!(activity.intensity_factor(200.0).unwrap() > intensity);
+        let tss = activity.training_stress_score(250.0).unwrap();
+        let hours = activity.summary().elapsed_seconds / 3600.0;
+        assert!((tss - hours * intensity * intensity * 100.0).abs() < 1e-9);
+
+        // Women's weighting gives a higher TRIMP at moderate intensity.
+        let male = activity
+            .trimp(50.0, 190.0, crate::zones::Sex::Male)
+            .unwrap();
+        let female = activity
+            .trimp(50.0, 190.0, crate::zones::Sex::Female)
+            .unwrap();
+        assert!(male > 0.0 && female > male);
+        assert!(activity
+            .trimp(190.0, 190.0, crate::zones::Sex::Male)
+            .is_none());
+
+        // Heart rate reserve runs from 0% at rest to 100% at the maximum, and there is none when the two meet.
+        let reserve = activity.hr_reserve_series(50.0, 190.0).unwrap();
+        assert_eq!(reserve.len(), activity.hr_percent_series(190.0).len());
+        assert!(reserve.iter().all(|(_, percent)| percent.is_finite()));
+        assert!(activity.hr_reserve_series(190.0, 190.0).is_none());
+        assert!(activity.hr_reserve_series(200.0, 190.0).is_none());
+    }
+
+    #[test]
+    fn cadence_ride() {
+        let result = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let activities = result.activities.unwrap();
+        let activity = &activities.activities[0];
+
+        // The histogram accounts for the whole ride.
+        let histogram = activity.cadence_histogram(10);
+        let total: f64 = histogram.iter().map(|bin| bin.seconds).sum();
+        assert!(total > 1400.0);
+        assert_eq!(histogram[1].low, 10);
+        assert_eq!(histogram[1].high, 20);
+
+        // The ride has eleven stretches of ten seconds or more at 95 rpm or above, the first from the 239th
+        // Trackpoint to the 259th. Every drill respects the threshold and the minimum duration.
+        let drills = activity.detect_cadence_drills(95);
+        assert_eq!(drills.len(), 11);
+        let points: Vec<_> = activity.trackpoints().collect();
+        assert_eq!(drills[0].start, points[238].time);
+        assert_eq!(drills[0].end, points[258].time);
+        assert_eq!(drills[0].duration_seconds, 20.0);
+        for drill in &drills {
+            assert!(drill.duration_seconds >= 10.0);
+            assert!(drill.average_cadence >= 95.0);
+            assert!((95..=108).contains(&drill.maximum_cadence));
+        }
+        assert!(drills.windows(2).all(|pair| pair[0].end < pair[1].start));
+
+        // The ride peaks at 108 rpm, but never holds 100 for ten seconds.
+        assert!(activity.detect_cadence_drills(100).is_empty());
+
+        // Nobody pedals this fast.
+        assert!(activity.detect_cadence_drills(250).is_empty());
+    }
+
+    #[test]
+    fn running_power_estimate() {
+        let mut result = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let activity = &mut result.activities.as_mut().unwrap().activities[0];
+        let profile = crate::power::AthleteProfile::default();
+        activity.estimate_running_power(&profile);
+
+        // Around 4 m/s a 70 kg runner produces a few hundred Watts.
+        let watts: Vec<u16> = activity
+            .trackpoints()
+            .filter_map(|point| point.extensions.as_ref()?.tpx.as_ref()?.watts)
+            .collect();
+        assert_eq!(watts.len(), 1232);
+        let average = watts.iter().map(|w| f64::from(*w)).sum::<f64>() / watts.len() as f64;
+        assert!(average > 150.0 && average < 400.0);
+    }
+
+    #[test]
+    fn normalize_run() {
+        let result = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let original = result.activities.unwrap().activities[0].clone();
+
+        // Scramble a copy of the activity.
+        let mut scrambled = original.clone();
+        scrambled.sport = String::from("running");
+        scrambled.id = String::new();
+        scrambled.notes = Some(String::from("  Easy run \n"));
+        scrambled.laps[0].tracks[0].trackpoints.reverse();
+        scrambled.laps[0].notes = Some(String::from("   "));
+
+        // An earlier activity should be sorted before it.
+        let mut earlier = original.clone();
+        for point in earlier.laps[0].tracks[0].trackpoints.iter_mut() {
+            point.time -= chrono::Duration::days(1);
+        }
+
+        let mut db = crate::tcx::TrainingCenterDatabase {
+            activities: Some(crate::tcx::Activities {
+                activities: vec![scrambled, earlier],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        db.normalize();
+
+        let activities = db.activities.unwrap().activities;
+        assert!(activities[0].start_time() < activities[1].start_time());
+        let normalized = &activities[1];
+        assert_eq!(normalized.sport, "Running");
+        assert_eq!(normalized.id, "2021-01-19T23:30:50.000Z");
+        assert_eq!(normalized.notes.as_deref(), Some("Easy run"));
+        assert!(normalized.laps[0].notes.is_none());
+        let first = &normalized.laps[0].tracks[0].trackpoints[0];
+        assert_eq!(first.time, original.laps[0].tracks[0].trackpoints[0].time);
+    }
+
+    #[test]
+    fn fill_required_defaults_built_activity() {
+        let result = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let mut activity = result.activities.unwrap().activities[0].clone();
+
+        // Strip everything a hand-built activity would be missing.
+        activity.sport = String::new();
+        activity.id = String::new();
+        let lap = &mut activity.laps[0];
+        lap.intensity = None;
+        lap.trigger_method = None;
+        lap.total_time_seconds = 0.0;
+        lap.distance_meters = 0.0;
+
+        activity.fill_required_defaults();
+        assert_eq!(activity.sport, "Other");
+        assert_eq!(activity.id, "2021-03-08T21:32:21.000Z");
+        let lap = &activity.laps[0];
+        assert!(matches!(lap.intensity, Some(crate::tcx::Intensity::Active)));
+        assert!(matches!(
+            lap.trigger_method,
+            Some(crate::tcx::TriggerMethod::Manual)
+        ));
+        assert!(lap.total_time_seconds > 1400.0);
+        assert!((lap.distance_meters - 16179.21).abs() < 0.1);
+    }
+
+    #[test]
+    fn align_laps_off_by_one() {
+        let result = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let mut activity = result.activities.unwrap().activities[0].clone();
+        assert_eq!(activity.laps[0].start_time, activity.start_time());
+
+        // Split the lap in two, then give the first point of the second lap to the first lap.
+        let mut second = activity.laps[0].clone();
+        let points = activity.laps[0].tracks[0].trackpoints.split_off(600);
+        second.start_time = Some(points[0].time);
+        second.total_time_seconds = 600.0;
+        second.tracks[0].trackpoints = points;
+        activity.laps[0].total_time_seconds = 5000.0;
+        let stolen = second.tracks[0].trackpoints.remove(0);
+        activity.laps[0].tracks[0].trackpoints.push(stolen.clone());
+        activity.laps.push(second);
+
+        let report = activity.align_laps_to_trackpoints();
+        assert_eq!(report.moved_trackpoints, 1);
+        assert_eq!(report.inconsistent_laps, vec![0]);
+        assert_eq!(activity.laps[0].trackpoints().count(), 600);
+        assert_eq!(activity.laps[1].tracks[0].trackpoints[0].time, stolen.time);
+    }
+
+    #[test]
+    fn activity_meta_round_trip() {
+        let mut activity = crate::tcx::Activity {
+            notes: Some(String::from("Windy along the river.")),
+            ..Default::default()
+        };
+        assert!(activity.meta().is_none());
+
+        let meta = crate::meta::ActivityMeta {
+            rpe: Some(8),
+            title: Some(String::from("Tempo")),
+            equipment: vec![String::from("Road shoes")],
+            tags: vec![String::from("workout"), String::from("river")],
+        };
+        activity.set_meta(&meta);
+        activity.set_meta(&meta);
+
+        // Survives a trip through JSON, and the user's notes are untouched.
+        let json = serde_json::to_string(&activity).unwrap();
+        let activity: crate::tcx::Activity = serde_json::from_str(&json).unwrap();
+        assert_eq!(activity.meta(), Some(meta));
+        assert_eq!(
+            activity.user_notes().as_deref(),
+            Some("Windy along the river.")
+        );
+        assert_eq!(activity.notes.as_ref().unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn gear_totals() {
+        let run = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let ride = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let mut activities = run.activities.unwrap().activities;
+        activities.extend(ride.activities.unwrap().activities);
+        activities.push(activities[0].clone());
+
+        let shoes = crate::meta::ActivityMeta {
+            equipment: vec![String::from("Shoes")],
+            ..Default::default()
+        };
+        let bike = crate::meta::ActivityMeta {
+            equipment: vec![String::from("Bike"), String::from("HR strap")],
+            ..Default::default()
+        };
+        activities[0].set_meta(&shoes);
+        activities[1].set_meta(&bike);
+        activities[2].set_meta(&shoes);
+
+        let totals = crate::gear::totals(&activities);
+        assert_eq!(totals.len(), 3);
+        assert_eq!(totals["Shoes"].activities, 2);
+        assert!((totals["Shoes"].distance_meters - 2.0 * 5124.84).abs() < 0.01);
+        assert_eq!(totals["Bike"], totals["HR strap"]);
+        assert_eq!(totals["Bike"].total_time_seconds, 1434.0);
+    }
+
+    #[test]
+    fn structured_workout_erg() {
+        use crate::workout::{StructuredStep, StructuredWorkout};
+
+        let workout = StructuredWorkout {
+            name: String::from("Over-unders"),
+            sport: String::from("Biking"),
+            description: Some(String::from("2x(3x(2' @ 95%, 1' @ 105%))")),
+            steps: vec![
+                StructuredStep::ramp(300.0, 50.0, 70.0),
+                StructuredStep::Repeat {
+                    count: 2,
+                    steps: vec![StructuredStep::Repeat {
+                        count: 3,
+                        steps: vec![
+                            StructuredStep::steady(120.0, 95.0),
+                            StructuredStep::steady(60.0, 105.0),
+                        ],
+                    }],
+                },
+            ],
+        };
+        assert_eq!(workout.duration_seconds(), 300.0 + 6.0 * 180.0);
+
+        let erg = workout.to_erg(200);
+        let data: Vec<&str> = erg
+            .lines()
+            .skip_while(|line| *line != "[COURSE DATA]")
+            .skip(1)
+            .take_while(|line| *line != "[END COURSE DATA]")
+            .collect();
+        assert_eq!(data.len(), 2 * 13);
+        assert_eq!(data[0], "0.00\t100");
+        assert_eq!(data[1], "5.00\t140");
+        assert_eq!(data[2], "5.00\t190");
+        assert_eq!(data[25], "23.00\t210");
+        assert!(erg.contains("FTP = 200"));
+    }
+
+    #[test]
+    fn power_profile_workout() {
+        let result = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let activity = &result.activities.unwrap().activities[0];
+        let workout = crate::workout::StructuredWorkout::from_activity_power(activity, 200, 60.0);
+
+        // 1434 seconds of riding in one minute steps, averaging around the lap's 210 Watts.
+        assert_eq!(workout.steps.len(), 24);
+        let erg = workout.to_erg(200);
+        let watts: Vec<f64> = erg
+            .lines()
+            .filter_map(|line| line.split('\t').nth(1))
+            .map(|value| value.parse().unwrap())
+            .collect();
+        let average = watts.iter().sum::<f64>() / watts.len() as f64;
+        assert!(average > 180.0 && average < 230.0);
+    }
+
+    #[test]
+    fn structured_workout_tcx_conversion() {
+        use crate::workout::{StructuredStep, StructuredWorkout};
+
+        let tcx = crate::tcx::read_file("tests/20210602_workout_intervals.tcx").unwrap();
+        let workout = StructuredWorkout::from(&tcx.workouts.as_ref().unwrap().workouts[0]);
+        assert_eq!(workout.name, "6x800m");
+        assert_eq!(workout.sport, "Running");
+        // Only the timed warm up has a fixed length; the distance and heart rate repeats and the open-ended cool
+        // down are left out.
+        assert_eq!(workout.steps.len(), 1);
+        assert_eq!(workout.duration_seconds(), 600.0);
+
+        let workout = StructuredWorkout {
+            name: String::from("Threshold intervals"),
+            sport: String::from("Running"),
+            description: Some(String::from("4x8' @ 100%")),
+            steps: vec![
+                StructuredStep::ramp(600.0, 40.0, 75.0),
+                StructuredStep::Repeat {
+                    count: 4,
+                    steps: vec![
+                        StructuredStep::steady(480.0, 100.0),
+                        StructuredStep::steady(120.0, 45.0),
+                    ],
+                },
+            ],
+        };
+        let tcx_workout = workout.to_tcx_workout().unwrap();
+        assert_eq!(tcx_workout.name, "Threshold inter");
+        assert_eq!(tcx_workout.sport, "Running");
+        assert_eq!(tcx_workout.notes, workout.description);
+        let repeat = &tcx_workout.steps[1];
+        let ids = [
+            tcx_workout.steps[0].step_id,
+            repeat.step_id,
+            repeat.children[0].step_id,
+            repeat.children[1].step_id,
+        ];
+        assert_eq!(ids, [1, 2, 3, 4]);
+        assert!(matches!(
+            tcx_workout.steps[1].children[1].intensity,
+            Some(crate::tcx::Intensity::Resting)
+        ));
+
+        let round_trip = StructuredWorkout::from(&tcx_workout);
+        assert_eq!(round_trip.duration_seconds(), workout.duration_seconds());
+        assert_eq!(
+            round_trip.steps[1],
+            StructuredStep::Repeat {
+                count: 4,
+                steps: vec![
+                    StructuredStep::steady(480.0, 75.0),
+                    StructuredStep::steady(120.0, 50.0),
+                ],
+            }
+        );
+
+        // Step IDs run out at 20, counting the repeat itself.
+        let too_long = StructuredWorkout {
+            steps: vec![StructuredStep::Repeat {
+                count: 2,
+                steps: vec![StructuredStep::steady(60.0, 100.0); 20],
+            }],
+            ..workout.clone()
+        };
+        assert!(matches!(
+            too_long.to_tcx_workout(),
+            Err(crate::Error::InvalidValue(_))
+        ));
+        let longest = StructuredWorkout {
+            steps: vec![StructuredStep::Repeat {
+                count: 2,
+                steps: vec![StructuredStep::steady(60.0, 100.0); 19],
+            }],
+            ..workout
+        };
+        assert_eq!(
+            longest.to_tcx_workout().unwrap().steps[0].children[18].step_id,
+            20
+        );
+    }
+
+    #[test]
+    fn power_profile_workout_rejects_bad_parameters() {
+        let result = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let activity = &result.activities.unwrap().activities[0];
+        let workout = crate::workout::StructuredWorkout::from_activity_power(activity, 0, 60.0);
+        assert!(workout.steps.is_empty());
+        let workout = crate::workout::StructuredWorkout::from_activity_power(activity, 200, 0.0);
+        assert!(workout.steps.is_empty());
+        let workout = crate::workout::StructuredWorkout::from_activity_power(activity, 200, -30.0);
+        assert!(workout.steps.is_empty());
+    }
+
+    #[test]
+    fn course_category_points() {
+        // Flat, a 2 km climb at 6%, a descent, then a 5 km climb at 8%.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-06-01T08:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let profile = |distance: f64| -> f64 {
+            match distance {
+                d if d < 1000.0 => 100.0,
+                d if d < 3000.0 => 100.0 + (d - 1000.0) * 0.06,
+                d if d < 5000.0 => 220.0 - (d - 3000.0) * 0.05,
+                d if d < 10000.0 => 120.0 + (d - 5000.0) * 0.08,
+                _ => 520.0,
+            }
+        };
+        let trackpoints = (0..=1100)
+            .map(|i| crate::tcx::Trackpoint {
+                time: start + chrono::Duration::seconds(i),
+                // The first climb starts where the positions are missing.
+                position: (i > 150).then_some(crate::tcx::Position {
+                    latitude: 45.0 + i as f64 * 1e-4,
+                    longitude: 7.0,
+                }),
+                altitude_meters: Some(profile(i as f64 * 10.0)),
+                distance_meters: Some(i as f64 * 10.0),
+                heart_rate: None,
+                cadence: None,
+                extensions: None,
+            })
+            .collect();
+        let mut course = crate::tcx::Course {
+            tracks: Some(vec![crate::tcx::Track { trackpoints }]),
+            ..Default::default()
+        };
+
+        let climbs = course.climbs();
+        assert_eq!(climbs.len(), 2);
+        assert!((climbs[0].elevation_gain_meters - 120.0).abs() < 2.0);
+        assert!((climbs[1].average_grade_percent - 8.0).abs() < 0.2);
+
+        let water = crate::tcx::CoursePoint {
+            name: Some(String::from("Water")),
+            time: start,
+            position: Some(crate::tcx::Position {
+                latitude: 45.0,
+                longitude: 7.0,
+            }),
+            altitude_meters: None,
+            point_type: Some(crate::tcx::CoursePointType::Water),
+            notes: None,
+            extensions: None,
+        };
+        course.course_points.push(water);
+        assert_eq!(course.insert_category_points(), 2);
+        // Running it again replaces the climb points rather than adding more.
+        assert_eq!(course.insert_category_points(), 2);
+        let types: Vec<_> = course
+            .course_points
+            .iter()
+            .map(|point| point.point_type)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                Some(crate::tcx::CoursePointType::Water),
+                Some(crate::tcx::CoursePointType::FourthCategory),
+                Some(crate::tcx::CoursePointType::SecondCategory)
+            ]
+        );
+        // The first climb's bottom has no position, so its point takes the top's.
+        assert_eq!(
+            course.course_points[1].position,
+            climbs[0].top_position.clone()
+        );
+        assert_eq!(
+            course.course_points[2].position,
+            climbs[1].start_position.clone()
+        );
+        assert!(climbs[0].start_position.is_none());
+    }
+
+    #[test]
+    fn json_patch_round_trip() {
+        let old = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let mut new = old.clone();
+        {
+            let activity = &mut new.activities.as_mut().unwrap().activities[0];
+            activity.notes = Some(String::from("Zwift / Watopia"));
+            activity.laps[0].calories = 312;
+            activity.laps[0].tracks[0].trackpoints.truncate(1000);
+            activity.laps.push(activity.laps[0].clone());
+        }
+
+        let patch = crate::patch::diff_json(&old, &new).unwrap();
+        assert!(patch.len() < 500);
+        let patched = crate::patch::apply_json_patch(&old, &patch).unwrap();
+        assert_eq!(
+            serde_json::to_value(&patched).unwrap(),
+            serde_json::to_value(&new).unwrap()
+        );
+
+        // No changes, no patch.
+        assert!(crate::patch::diff_json(&new, &new).unwrap().is_empty());
+
+        // Malformed paths and replacing missing members are errors, not panics.
+        use crate::patch::PatchOperation;
+        for path in ["é", "Activities", "/Activities/Activity/0/NoSuchField"] {
+            let patch = vec![PatchOperation::Replace {
+                path: path.to_string(),
+                value: serde_json::json!(1),
+            }];
+            assert!(crate::patch::apply_json_patch(&old, &patch).is_err());
+        }
+        let patch = vec![PatchOperation::Remove {
+            path: "é".to_string(),
+        }];
+        assert!(crate::patch::apply_json_patch(&old, &patch).is_err());
+
+        // Inserting a trackpoint is one addition, not a replacement of every later trackpoint.
+        let mut inserted = old.clone();
+        {
+            let trackpoints = &mut inserted.activities.as_mut().unwrap().activities[0].laps[0]
+                .tracks[0]
+                .trackpoints;
+            let mut trackpoint = trackpoints[100].clone();
+            trackpoint.time += chrono::Duration::milliseconds(500);
+            trackpoints.insert(101, trackpoint);
+        }
+        let patch = crate::patch::diff_json(&old, &inserted).unwrap();
+        assert_eq!(patch.len(), 1);
+        match &patch[0] {
+            PatchOperation::Add { path, .. } => {
+                assert_eq!(path, "/Activities/Activity/0/Lap/0/Track/0/Trackpoint/101")
+            }
+            operation => panic!("unexpected {:?}", operation),
+        }
+        let patched = crate::patch::apply_json_patch(&old, &patch).unwrap();
+        assert_eq!(
+            serde_json::to_value(&patched).unwrap(),
+            serde_json::to_value(&inserted).unwrap()
+        );
+
+        // Removing one is one removal. Arrays without times, such as tracks, are aligned on their common items.
+        let patch = crate::patch::diff_json(&inserted, &old).unwrap();
+        assert_eq!(patch.len(), 1);
+        assert!(
+            matches!(&patch[0], PatchOperation::Remove { path } if path.ends_with("/Trackpoint/101"))
+        );
+        let mut split = old.clone();
+        {
+            let lap = &mut split.activities.as_mut().unwrap().activities[0].laps[0];
+            let mut track = lap.tracks[0].clone();
+            track.trackpoints.truncate(3);
+            lap.tracks.insert(0, track);
+        }
+        let patch = crate::patch::diff_json(&old, &split).unwrap();
+        assert_eq!(patch.len(), 1);
+        assert!(
+            matches!(&patch[0], PatchOperation::Add { path, .. } if path.ends_with("/Track/0"))
+        );
+        let patched = crate::patch::apply_json_patch(&old, &patch).unwrap();
+        assert_eq!(
+            serde_json::to_value(&patched).unwrap(),
+            serde_json::to_value(&split).unwrap()
+        );
+    }
+
+    #[test]
+    fn lean_activity_summary() {
+        let result = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let activity = &result.activities.unwrap().activities[0];
+        let lean = crate::lean::LeanActivity::from(activity);
+
+        assert_eq!(lean.num_trackpoints, 1434);
+        assert_eq!(lean.total_time_seconds, 1434.0);
+        assert_eq!(lean.maximum_heart_rate, Some(181.0));
+        assert!(lean.polyline.len() > 2 && lean.polyline.len() < 400);
+
+        // The ends of the route are always kept.
+        let first = activity
+            .trackpoints()
+            .next()
+            .unwrap()
+            .position
+            .clone()
+            .unwrap();
+        assert_eq!(
+            lean.polyline[0],
+            (first.latitude as f32, first.longitude as f32)
+        );
+    }
+
+    #[test]
+    fn lean_activity_from_reader() {
+        for filename in &[
+            "tests/20210119_run_garmin_fenix6.tcx",
+            "tests/20210308_virtual_ride_with_power.tcx",
+            "tests/20210323_yoga.tcx",
+            "tests/20210415_pool_swim_rest_laps.tcx",
+        ] {
+            let tcx = crate::tcx::read_file(filename).unwrap();
+            let expected: Vec<_> = tcx
+                .activities
+                .as_ref()
+                .unwrap()
+                .activities
+                .iter()
+                .map(|activity| crate::lean::LeanActivity::new(activity, 5.0))
+                .collect();
+            let file = std::fs::File::open(filename).unwrap();
+            let streamed = crate::lean::LeanActivity::from_reader(file, 5.0).unwrap();
+            assert_eq!(streamed, expected);
+        }
+
+        // The legs of multisport sessions are summarized too, in document order.
+        let tcx = crate::tcx::read_file("tests/20210704_triathlon_multisport.tcx").unwrap();
+        let activities = tcx.activities.as_ref().unwrap();
+        let expected: Vec<_> = activities
+            .activities
+            .iter()
+            .chain(activities.multi_sport_sessions.iter().flat_map(|session| {
+                std::iter::once(&session.first_sport.activity)
+                    .chain(session.next_sports.iter().map(|next| &next.activity))
+            }))
+            .map(|activity| crate::lean::LeanActivity::new(activity, 5.0))
+            .collect();
+        let file = std::fs::File::open("tests/20210704_triathlon_multisport.tcx").unwrap();
+        let streamed = crate::lean::LeanActivity::from_reader(file, 5.0).unwrap();
+        assert_eq!(streamed, expected);
+
+        let truncated =
+            b"<TrainingCenterDatabase><Activities><Activity><Lap><Track><Trackpoint><Time>";
+        assert!(crate::lean::LeanActivity::from_reader(&truncated[..], 5.0).is_err());
+    }
+
+    #[test]
+    fn batch_convert_json_and_csv() {
+        let in_dir = std::path::Path::new("target/batch_test_in");
+        let out_dir = std::path::Path::new("target/batch_test_out");
+        let _ = std::fs::remove_dir_all(in_dir);
+        let _ = std::fs::remove_dir_all(out_dir);
+        std::fs::create_dir_all(in_dir.join("2021")).unwrap();
+        std::fs::copy("tests/20210323_yoga.tcx", in_dir.join("2021/yoga.TCX")).unwrap();
+        std::fs::copy(
+            "tests/20210308_virtual_ride_with_power.tcx",
+            in_dir.join("ride.tcx"),
+        )
+        .unwrap();
+        std::fs::write(in_dir.join("broken.tcx"), "<TrainingCenterDatabase>").unwrap();
+        std::fs::write(in_dir.join("notes.txt"), "not a TCX file").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("..", in_dir.join("2021/up")).unwrap();
+
+        let report = crate::batch::convert(in_dir, out_dir, crate::batch::Format::Json).unwrap();
+        assert_eq!(report.converted.len(), 2);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, in_dir.join("broken.tcx"));
+        assert!(out_dir.join("2021/yoga.json").exists());
+        assert!(out_dir.join("ride.json").exists());
+
+        let report = crate::batch::convert(in_dir, out_dir, crate::batch::Format::Csv).unwrap();
+        assert_eq!(report.converted.len(), 2);
+        assert_eq!(report.failed.len(), 1);
+        let csv = std::fs::read_to_string(out_dir.join("ride.csv")).unwrap();
+        let mut expected = Vec::new();
+        crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx")
+            .unwrap()
+            .export_csv(&mut expected, &crate::csv::CsvOptions::default())
+            .unwrap();
+        assert_eq!(csv.as_bytes(), expected.as_slice());
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn watch_dir_delivers_new_files() {
+        let dir = std::path::Path::new("target/watch_test");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let _watcher = crate::watch::watch_dir(dir, move |path, database| {
+            sender.send((path.to_path_buf(), database)).unwrap();
+        })
+        .unwrap();
+        std::fs::copy("tests/20210323_yoga.tcx", dir.join("yoga.tcx")).unwrap();
+
+        let (path, database) = receiver
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .unwrap();
+        assert!(path.ends_with("yoga.tcx"));
+        assert_eq!(database.activities.unwrap().activities.len(), 1);
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn watch_dir_does_not_retry_malformed_files() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let dir = std::path::Path::new("target/watch_malformed_test");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+
+        let reads = Arc::new(AtomicUsize::new(0));
+        let counter = reads.clone();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let _watcher = crate::watch::watch_dir_with(
+            dir,
+            move |name| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                crate::tcx::read_file(name)
+            },
+            move |path, _| {
+                sender.send(path.to_path_buf()).unwrap();
+            },
+        )
+        .unwrap();
+        let contents = std::fs::read("tests/20210323_yoga.tcx").unwrap();
+        std::fs::write(dir.join("truncated.tcx"), &contents[..contents.len() / 2]).unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        assert!(receiver.try_recv().is_err());
+        let after_first_write = reads.load(Ordering::SeqCst);
+        assert!((1..=3).contains(&after_first_write));
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        assert_eq!(reads.load(Ordering::SeqCst), after_first_write);
+
+        // Completing the file changes it, so it is read again and delivered.
+        std::fs::write(dir.join("truncated.tcx"), &contents).unwrap();
+        let path = receiver
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .unwrap();
+        assert!(path.ends_with("truncated.tcx"));
+    }
+
+    #[cfg(feature = "object_store")]
+    #[test]
+    fn read_from_object_store() {
+        use object_store::ObjectStore;
+
+        let store = object_store::memory::InMemory::new();
+        let path = object_store::path::Path::from("athlete/20210323_yoga.tcx");
+        let contents = std::fs::read("tests/20210323_yoga.tcx").unwrap();
+        futures::executor::block_on(async {
+            store.put(&path, contents.into()).await.unwrap();
+            let result = crate::object::read_object(&store, &path).await.unwrap();
+            assert_eq!(result.activities.unwrap().activities.len(), 1);
+
+            let missing = object_store::path::Path::from("athlete/missing.tcx");
+            assert!(crate::object::read_object(&store, &missing).await.is_err());
+        });
+    }
+
+    #[test]
+    fn json_epoch_timestamps() {
+        let result = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let options = crate::tcx::JsonOptions {
+            timestamps: crate::tcx::TimestampFormat::EpochMillis,
+            ..Default::default()
+        };
+        let value = result.to_json_value(&options).unwrap();
+        let lap = &value["Activities"]["Activity"][0]["Lap"][0];
+        assert_eq!(lap["StartTime"], 1611099050000i64);
+        assert_eq!(lap["Track"][0]["Trackpoint"][1]["Time"], 1611099051000i64);
+
+        let options = crate::tcx::JsonOptions {
+            timestamps: crate::tcx::TimestampFormat::EpochSeconds,
+            ..Default::default()
+        };
+        let value = result.to_json_value(&options).unwrap();
+        let lap = &value["Activities"]["Activity"][0]["Lap"][0];
+        assert_eq!(lap["StartTime"], 1611099050i64);
+
+        // The default keeps RFC 3339 strings.
+        let value = result.to_json_value(&Default::default()).unwrap();
+        let lap = &value["Activities"]["Activity"][0]["Lap"][0];
+        assert!(lap["StartTime"].is_string());
+
+        // Ids that are times are converted too, and the written JSON keeps the schema's field order.
+        let triathlon = crate::tcx::read_file("tests/20210704_triathlon_multisport.tcx").unwrap();
+        let options = crate::tcx::JsonOptions {
+            timestamps: crate::tcx::TimestampFormat::EpochSeconds,
+            ..Default::default()
+        };
+        let mut json = Vec::new();
+        triathlon.to_json_writer(&mut json, &options).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let activities = &value["Activities"];
+        let session = &activities["MultiSportSession"][0];
+        assert_eq!(session["Id"], 1625382000);
+        assert_eq!(session["FirstSport"]["Activity"]["Id"], 1625382000);
+
+        let mut json = Vec::new();
+        result.to_json_writer(&mut json, &options).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        let position = |key: &str| json.find(&format!("\"{}\"", key)).unwrap();
+        assert!(position("Sport") < position("Id"));
+        assert!(position("Id") < position("Lap"));
+        assert!(position("TotalTimeSeconds") < position("DistanceMeters"));
+        assert!(position("Time") < position("AltitudeMeters"));
+    }
+
+    #[test]
+    fn quality_issue_codes() {
+        use crate::quality::{IssueCode, Severity};
+
+        let mut result =
+            crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        assert!(result.check_quality().is_empty());
+
+        {
+            let activity = &mut result.activities.as_mut().unwrap().activities[0];
+            let points = &mut activity.laps[0].tracks[0].trackpoints;
+            points.swap(10, 11);
+            points[100].altitude_meters = None;
+            points[200].time = points[199].time;
+            activity.laps.push(crate::tcx::ActivityLap::default());
+        }
+
+        let issues = result.check_quality();
+        let codes: Vec<&str> = issues.iter().map(|issue| issue.code.code()).collect();
+        assert_eq!(codes, vec!["E001", "E002", "W103", "W101", "W105"]);
+        assert_eq!(issues[0].trackpoint, Some(11));
+        assert_eq!(issues[0].severity(), Severity::Error);
+        assert_eq!(issues[4].lap, Some(1));
+        assert_eq!(
+            IssueCode::MissingAltitude.to_string(),
+            "W101 MissingAltitude"
+        );
+    }
+
+    #[test]
+    fn hr_power_gap_filling() {
+        // Two minute intervals alternating between 150 and 300 Watts, with heart rate following power with a lag.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-06-01T08:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let mut smoothed = 150.0;
+        let trackpoints = (0..1200)
+            .map(|i| {
+                let watts = if (i / 120) % 2 == 0 { 150 } else { 300 };
+                smoothed += (1.0 - (-1.0f64 / 30.0).exp()) * (f64::from(watts) - smoothed);
+                crate::tcx::Trackpoint {
+                    time: start + chrono::Duration::seconds(i),
+                    position: None,
+                    altitude_meters: None,
+                    distance_meters: None,
+                    heart_rate: Some(crate::tcx::HeartRate {
+                        value: (80.0 + 0.3 * smoothed).round(),
+                    }),
+                    cadence: None,
+                    extensions: Some(crate::tcx::Extensions {
+                        tpx: Some(crate::tcx::Ns3Tpx {
+                            watts: Some(watts),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                }
+            })
+            .collect();
+        let mut original = crate::tcx::Activity::default();
+        original.laps.push(crate::tcx::ActivityLap {
+            tracks: vec![crate::tcx::Track { trackpoints }],
+            ..Default::default()
+        });
+        let profile = crate::power::AthleteProfile::default();
+
+        // Drop the heart rate strap for five minutes.
+        let mut activity = original.clone();
+        for point in activity.laps[0].tracks[0].trackpoints[600..900].iter_mut() {
+            point.heart_rate = None;
+        }
+        assert_eq!(activity.estimate_hr_from_power(&profile), Some(300));
+        for (estimated, actual) in activity.trackpoints().zip(original.trackpoints()) {
+            let estimated = estimated.heart_rate.as_ref().unwrap().value;
+            let actual = actual.heart_rate.as_ref().unwrap().value;
+            assert!((estimated - actual).abs() <= 1.0);
+        }
+
+        // Now drop the power meter instead; the average power over the gap should be recovered.
+        let mut activity = original.clone();
+        for point in activity.laps[0].tracks[0].trackpoints[600..900].iter_mut() {
+            point.extensions = None;
+        }
+        assert_eq!(activity.estimate_power_from_hr(&profile), Some(300));
+        let watts = |activity: &crate::tcx::Activity| -> f64 {
+            activity.laps[0].tracks[0].trackpoints[600..900]
+                .iter()
+                .map(|point| {
+                    f64::from(
+                        point
+                            .extensions
+                            .as_ref()
+                            .unwrap()
+                            .tpx
+                            .as_ref()
+                            .unwrap()
+                            .watts
+                            .unwrap(),
+                    )
+                })
+                .sum::<f64>()
+                / 300.0
+        };
+        assert!((watts(&activity) - watts(&original)).abs() < 15.0);
+
+        // Too little data to fit a model.
+        let mut empty = crate::tcx::Activity::default();
+        assert_eq!(empty.estimate_hr_from_power(&profile), None);
+    }
+
+    #[test]
+    fn tcx_writer_round_trip() {
+        for filename in &[
+            "tests/20210119_run_garmin_fenix6.tcx",
+            "tests/20210308_virtual_ride_with_power.tcx",
+            "tests/20210323_yoga.tcx",
+        ] {
+            let mut original = crate::tcx::read_file(filename).unwrap();
+            original.activities.as_mut().unwrap().activities[0].notes =
+                Some(String::from("Intervals <4x4'> & \"cool\" down"));
+
+            let mut xml = Vec::new();
+            original.to_writer(&mut xml).unwrap();
+            let text = String::from_utf8(xml.clone()).unwrap();
+            assert!(text
+                .contains("xmlns:ns3=\"http://www.garmin.com/xmlschemas/ActivityExtension/v2\""));
+            assert!(text.contains("xsi:schemaLocation="));
+
+            let reparsed = crate::tcx::read(&mut std::io::BufReader::new(xml.as_slice())).unwrap();
+            assert_eq!(
+                serde_json::to_value(&reparsed).unwrap(),
+                serde_json::to_value(&original).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn altitude_from_pressure() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Activities>
+    <Activity Sport="Biking">
+      <Id>2021-05-01T10:00:00.000Z</Id>
+      <Lap StartTime="2021-05-01T10:00:00.000Z">
+        <TotalTimeSeconds>2</TotalTimeSeconds>
+        <DistanceMeters>10</DistanceMeters>
+        <Calories>1</Calories>
+        <Intensity>Active</Intensity>
+        <TriggerMethod>Manual</TriggerMethod>
+        <Track>
+          <Trackpoint>
+            <Time>2021-05-01T10:00:00.000Z</Time>
+            <AltitudeMeters>50.0</AltitudeMeters>
+            <Extensions><Pressure>101325</Pressure></Extensions>
+          </Trackpoint>
+          <Trackpoint>
+            <Time>2021-05-01T10:00:01.000Z</Time>
+            <AltitudeMeters>1050.0</AltitudeMeters>
+            <Extensions><Pressure>89874.6</Pressure></Extensions>
+          </Trackpoint>
+          <Trackpoint>
+            <Time>2021-05-01T10:00:02.000Z</Time>
+            <AltitudeMeters>7.0</AltitudeMeters>
+          </Trackpoint>
+        </Track>
+      </Lap>
+    </Activity>
+  </Activities>
+</TrainingCenterDatabase>"#;
+        let mut tcx = crate::tcx::read(&mut std::io::BufReader::new(xml.as_bytes())).unwrap();
+        let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+        assert_eq!(
+            activity.altitude_from_pressure(crate::altitude::STANDARD_SEA_LEVEL_PRESSURE),
+            2
+        );
+
+        let altitudes: Vec<f64> = activity
+            .trackpoints()
+            .map(|point| point.altitude_meters.unwrap())
+            .collect();
+        assert!(altitudes[0].abs() < 0.01);
+        assert!((altitudes[1] - 1000.0).abs() < 1.0);
+        assert_eq!(altitudes[2], 7.0);
+
+        // A higher reference pressure means the same readings were taken higher up.
+        activity.altitude_from_pressure(102_000.0);
+        assert!(
+            activity
+                .trackpoints()
+                .next()
+                .unwrap()
+                .altitude_meters
+                .unwrap()
+                > 50.0
+        );
+    }
+
+    #[test]
+    fn file4_unicode_notes() {
+        let mut tcx = crate::tcx::read_file("tests/20210410_run_unicode_notes.tcx").unwrap();
+        {
+            let activity = &tcx.activities.as_ref().unwrap().activities[0];
+            assert_eq!(
+                activity.notes.as_deref(),
+                Some("Morning run with Zoe\u{301} “the hare” — felt great 🏃 & legs <3 it’s done")
+            );
+            assert_eq!(
+                activity.laps[0].notes.as_deref(),
+                Some("Tempo <5:00/km> & “smart” quotes über 日本")
+            );
+        }
+
+        tcx.normalize();
+        let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+        assert_eq!(
+            activity.notes.as_deref(),
+            Some("Morning run with Zoé “the hare” — felt great 🏃 & legs <3 it’s done")
+        );
+        activity.laps[0].notes = Some(String::from("Line one\r\nLine two\u{7}"));
+
+        let mut xml = Vec::new();
+        tcx.to_writer(&mut xml).unwrap();
+        let reparsed = crate::tcx::read(&mut std::io::BufReader::new(xml.as_slice())).unwrap();
+        let original = &tcx.activities.as_ref().unwrap().activities[0];
+        let activity = &reparsed.activities.as_ref().unwrap().activities[0];
+        assert_eq!(activity.notes, original.notes);
+        assert_eq!(
+            activity.laps[0].notes.as_deref(),
+            Some("Line one\r\nLine two")
+        );
+
+        // Names are normalized too, and folder references follow the names they refer to.
+        let decomposed = "Lac Le\u{301}man ";
+        let mut tcx = crate::tcx::read_file("tests/20210601_course_lake_loop.tcx").unwrap();
+        {
+            let course = &mut tcx.courses.as_mut().unwrap().courses[0];
+            course.name = decomposed.to_string();
+            course.course_points[0].name = Some(decomposed.to_string());
+            let folder = tcx
+                .folders
+                .as_mut()
+                .unwrap()
+                .courses
+                .as_mut()
+                .unwrap()
+                .folder
+                .as_mut()
+                .unwrap();
+            folder.course_name_refs[0].id = decomposed.to_string();
+        }
+        tcx.author = Some(crate::tcx::Application {
+            name: decomposed.to_string(),
+            ..Default::default()
+        });
+        tcx.normalize();
+        let course = &tcx.courses.as_ref().unwrap().courses[0];
+        assert_eq!(course.name, "Lac Léman");
+        assert_eq!(course.course_points[0].name.as_deref(), Some("Lac Léman"));
+        assert_eq!(tcx.author.unwrap().name, "Lac Léman");
+        let folder = tcx.folders.unwrap().courses.unwrap().folder.unwrap();
+        assert_eq!(folder.course_name_refs[0].id, "Lac Léman");
+
+        let mut tcx = crate::tcx::read_file("tests/20210602_workout_intervals.tcx").unwrap();
+        {
+            let workout = &mut tcx.workouts.as_mut().unwrap().workouts[0];
+            workout.name = decomposed.to_string();
+            workout.steps[1].children[0].name = Some(decomposed.to_string());
+        }
+        let mut triathlon =
+            crate::tcx::read_file("tests/20210704_triathlon_multisport.tcx").unwrap();
+        {
+            let session = &mut triathlon.activities.as_mut().unwrap().multi_sport_sessions[0];
+            session.first_sport.activity.notes = Some(decomposed.to_string());
+        }
+        tcx.normalize();
+        triathlon.normalize();
+        let workout = &tcx.workouts.as_ref().unwrap().workouts[0];
+        assert_eq!(workout.name, "Lac Léman");
+        assert_eq!(
+            workout.steps[1].children[0].name.as_deref(),
+            Some("Lac Léman")
+        );
+        let session = &triathlon.activities.as_ref().unwrap().multi_sport_sessions[0];
+        assert_eq!(
+            session.first_sport.activity.notes.as_deref(),
+            Some("Lac Léman")
+        );
+    }
+
+    #[test]
+    fn trackpoint_stream_matches_full_parse() {
+        for filename in &[
+            "tests/20210119_run_garmin_fenix6.tcx",
+            "tests/20210308_virtual_ride_with_power.tcx",
+            "tests/20210323_yoga.tcx",
+        ] {
+            let tcx = crate::tcx::read_file(filename).unwrap();
+            let mut expected = Vec::new();
+            for (activity_index, activity) in tcx
+                .activities
+                .as_ref()
+                .unwrap()
+                .activities
+                .iter()
+                .enumerate()
+            {
+                for (lap_index, lap) in activity.laps.iter().enumerate() {
+                    for point in lap.trackpoints() {
+                        expected.push((
+                            activity_index,
+                            lap_index,
+                            serde_json::to_value(point).unwrap(),
+                        ));
+                    }
+                }
+            }
+
+            let file = std::fs::File::open(filename).unwrap();
+            let streamed: Vec<_> = crate::TrackpointStream::new(file)
+                .map(|item| {
+                    let (activity_index, lap_index, point) = item.unwrap();
+                    (
+                        activity_index,
+                        lap_index,
+                        serde_json::to_value(&point).unwrap(),
+                    )
+                })
+                .collect();
+            assert_eq!(streamed, expected);
+        }
+
+        let truncated =
+            b"<TrainingCenterDatabase><Activities><Activity><Lap><Track><Trackpoint><Time>";
+        let results: Vec<_> = crate::TrackpointStream::new(&truncated[..]).collect();
+        assert_eq!(results.len(), 1);
+        assert!(matches!(results[0], Err(crate::Error::Xml { line: 1, .. })));
+
+        let timeless = b"<TrainingCenterDatabase><Activities><Activity><Lap><Track>\
+            <Trackpoint><HeartRateBpm><Value>120</Value></HeartRateBpm></Trackpoint>\
+            </Track></Lap></Activity></Activities></TrainingCenterDatabase>";
+        let results: Vec<_> = crate::TrackpointStream::new(&timeless[..]).collect();
+        match &results[0] {
+            Err(crate::Error::MissingElement(path)) => assert_eq!(path, "Trackpoint/Time"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn trackpoint_stream_resume() {
+        let filename = "tests/20210415_pool_swim_rest_laps.tcx";
+        let times = |stream: &mut dyn Iterator<
+            Item = Result<(usize, usize, crate::Trackpoint), crate::Error>,
+        >| {
+            stream
+                .map(|item| {
+                    let (activity_index, lap_index, point) = item.unwrap();
+                    (activity_index, lap_index, point.time)
+                })
+                .collect::<Vec<_>>()
+        };
+        let all = times(&mut crate::TrackpointStream::new(
+            std::fs::File::open(filename).unwrap(),
+        ));
+        assert!(all.iter().any(|(_, lap_index, _)| *lap_index > 1));
+
+        for split in 0..=all.len() {
+            let mut stream = crate::TrackpointStream::new(std::fs::File::open(filename).unwrap());
+            let head = times(&mut stream.by_ref().take(split));
+            let checkpoint = serde_json::to_string(&stream.position()).unwrap();
+            let position: crate::StreamPosition = serde_json::from_str(&checkpoint).unwrap();
+            if let Some((activity_index, lap_index, _)) = all.get(split) {
+                assert_eq!(position.activity_index, *activity_index);
+                assert!(position.lap_index <= *lap_index);
+            }
+
+            let mut resumed =
+                crate::TrackpointStream::resume(std::fs::File::open(filename).unwrap(), position);
+            assert_eq!(resumed.position(), position);
+            let tail = times(&mut resumed);
+            assert_eq!([head, tail].concat(), all);
+        }
+
+        // A position past the end of a lap carries on with the next lap.
+        let position = crate::StreamPosition {
+            activity_index: 0,
+            lap_index: 0,
+            point_index: usize::MAX,
+        };
+        let resumed = times(&mut crate::TrackpointStream::resume(
+            std::fs::File::open(filename).unwrap(),
+            position,
+        ));
+        let first_lap = all
+            .iter()
+            .filter(|(_, lap_index, _)| *lap_index == 0)
+            .count();
+        assert_eq!(resumed, all[first_lap..]);
+    }
+
+    #[cfg(feature = "arena")]
+    #[test]
+    fn arena_trackpoints() {
+        let bump = bumpalo::Bump::new();
+        let file = std::fs::File::open("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let laps = crate::arena::read_trackpoints_in(&bump, file).unwrap();
+        let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let activity = &tcx.activities.as_ref().unwrap().activities[0];
+
+        assert_eq!(laps.len(), activity.laps.len());
+        assert_eq!(laps[0].trackpoints.len(), 1434);
+        assert_eq!(
+            laps[0].trackpoints[1433].distance_meters,
+            activity.laps[0].tracks[0].trackpoints[1433].distance_meters
+        );
+        assert!(bump.allocated_bytes() >= 1434 * std::mem::size_of::<crate::tcx::Trackpoint>());
+    }
+
+    #[test]
+    fn lap_and_trackpoint_extensions() {
+        let run = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let lap = &run.activities.as_ref().unwrap().activities[0].laps[0];
+        let lx = lap.extensions.as_ref().unwrap().lx.as_ref().unwrap();
+        assert_eq!(lx.avg_run_cadence, Some(91));
+        assert_eq!(lx.max_run_cadence, Some(99));
+        assert!(lap.trackpoints().any(|point| point
+            .extensions
+            .as_ref()
+            .unwrap()
+            .tpx
+            .as_ref()
+            .unwrap()
+            .run_cadence
+            .unwrap_or(0)
+            > 0));
+
+        let ride = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let lap = &ride.activities.as_ref().unwrap().activities[0].laps[0];
+        let lx = lap.extensions.as_ref().unwrap().lx.as_ref().unwrap();
+        assert_eq!(lx.max_bike_cadence, Some(108));
+        assert_eq!(lx.avg_watts, Some(210));
+        assert_eq!(lx.max_watts, Some(462));
+        assert!(lx.avg_run_cadence.is_none());
+    }
+
+    #[test]
+    fn builder_round_trip() {
+        use crate::builder::{ActivityBuilder, BuildError, LapBuilder, TrackpointBuilder};
+
+        let start = chrono::DateTime::parse_from_rfc3339("2021-06-01T06:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let points = (0..120).map(|i| {
+            TrackpointBuilder::new(start + chrono::Duration::seconds(i))
+                .position(47.0 + i as f64 * 0.0001, 8.0)
+                .distance(i as f64 * 8.0)
+                .heart_rate(130.0)
+                .cadence(85)
+                .watts(200)
+                .build()
+        });
+        let tcx = ActivityBuilder::new("Biking")
+            .notes("Built by hand")
+            .lap(LapBuilder::new().calories(40).trackpoints(points).build())
+            .build_database()
+            .unwrap();
+
+        let activity = &tcx.activities.as_ref().unwrap().activities[0];
+        assert_eq!(activity.id, "2021-06-01T06:00:00.000Z");
+        assert_eq!(activity.laps[0].total_time_seconds, 119.0);
+        assert_eq!(activity.laps[0].distance_meters, 952.0);
+
+        let mut xml = Vec::new();
+        tcx.to_writer(&mut xml).unwrap();
+        let reparsed = crate::tcx::read(&mut std::io::BufReader::new(xml.as_slice())).unwrap();
+        assert_eq!(
+            serde_json::to_value(&reparsed).unwrap(),
+            serde_json::to_value(&tcx).unwrap()
+        );
+
+        assert_eq!(
+            ActivityBuilder::new("Swimming")
+                .lap(LapBuilder::new().build())
+                .build()
+                .unwrap_err(),
+            BuildError::InvalidSport(String::from("Swimming"))
+        );
+        assert_eq!(
+            ActivityBuilder::new("Running").build().unwrap_err(),
+            BuildError::MissingLaps
+        );
+        assert_eq!(
+            ActivityBuilder::new("Running")
+                .lap(LapBuilder::new().build())
+                .build()
+                .unwrap_err(),
+            BuildError::MissingId
+        );
+    }
+
+    #[test]
+    fn retain_original_xml() {
+        let filename = "tests/20210119_run_garmin_fenix6.tcx";
+        let bytes = std::fs::read(filename).unwrap();
+
+        let options = crate::tcx::ReadOptions {
+            original: crate::tcx::OriginalRetention::Hash,
+            ..Default::default()
+        };
+        let tcx = crate::tcx::read_file_with_options(filename, &options).unwrap();
+        let original = tcx.original.as_ref().unwrap();
+        assert!(original.bytes.is_none());
+        assert!(original.matches(&bytes));
+        assert!(!original.matches(b"<TrainingCenterDatabase/>"));
+        assert!(!tcx.write_original(&mut Vec::new()).unwrap());
+
+        let options = crate::tcx::ReadOptions {
+            original: crate::tcx::OriginalRetention::Bytes,
+            ..Default::default()
+        };
+        let mut kept = crate::tcx::read_file_with_options(filename, &options).unwrap();
+        assert_eq!(kept.original.as_ref().unwrap().sha256, original.sha256);
+        kept.normalize();
+        let mut copy = Vec::new();
+        assert!(kept.write_original(&mut copy).unwrap());
+        assert_eq!(copy, bytes);
+
+        assert!(crate::tcx::read_file(filename).unwrap().original.is_none());
+    }
+
+    #[cfg(feature = "map_match")]
+    #[test]
+    fn map_match_surfaces() {
+        use crate::map_match::{MapMatch, MatchOptions, RoadNetwork, WayCandidate};
+
+        /// Two east-west ways about 33 meters apart: paved to the south, gravel to the north.
+        struct ParallelWays;
+
+        impl RoadNetwork for ParallelWays {
+            fn nearby_ways(
+                &self,
+                position: &crate::tcx::Position,
+                _radius_meters: f64,
+            ) -> Vec<WayCandidate> {
+                [(1, 47.0, "asphalt"), (2, 47.0003, "gravel")]
+                    .iter()
+                    .map(|(way_id, latitude, surface)| WayCandidate {
+                        way_id: *way_id,
+                        surface: Some(surface.to_string()),
+                        snapped: crate::tcx::Position {
+                            latitude: *latitude,
+                            longitude: position.longitude,
+                        },
+                        distance_meters: (position.latitude - latitude).abs() * 111_195.0,
+                    })
+                    .collect()
+            }
+        }
+
+        // Ride east along the paved way, wobble towards the middle, then move onto the gravel way.
+        let start = chrono::Utc::now();
+        let latitudes = [
+            47.0, 47.00002, 47.00014, 47.00016, 47.0, 47.0, 47.0, 47.0, 47.00029, 47.0003,
+        ];
+        let points = latitudes.iter().enumerate().map(|(i, latitude)| {
+            crate::builder::TrackpointBuilder::new(start + chrono::Duration::seconds(i as i64))
+                .position(*latitude, 8.0 + i as f64 * 0.0001)
+                .build()
+        });
+        let activity = crate::builder::ActivityBuilder::new("Biking")
+            .lap(
+                crate::builder::LapBuilder::new()
+                    .trackpoints(points)
+                    .build(),
+            )
+            .build()
+            .unwrap();
+
+        let matched = activity.map_match(&ParallelWays, &MatchOptions::default());
+        let ways: Vec<u64> = matched.iter().map(|point| point.way_id).collect();
+        // The wobble at index 3 is slightly closer to the gravel way but stays on the paved one.
+        assert_eq!(ways, vec![1, 1, 1, 1, 1, 1, 1, 1, 2, 2]);
+
+        let breakdown = crate::map_match::surface_breakdown(&matched);
+        assert!(breakdown["asphalt"] > breakdown["gravel"]);
+        assert!(!breakdown.contains_key(crate::map_match::UNKNOWN_SURFACE));
+    }
+
+    #[test]
+    fn gpx_import_export() {
+        let gpx =
+            crate::tcx::TrainingCenterDatabase::from_gpx_file("tests/20210501_ride_strava.gpx")
+                .unwrap();
+        let activity = &gpx.activities.as_ref().unwrap().activities[0];
+        assert_eq!(activity.sport, "Biking");
+        assert_eq!(activity.id, "2021-05-01T08:00:00.000Z");
+        assert_eq!(
+            activity.meta().unwrap().title.as_deref(),
+            Some("Lunch Ride")
+        );
+        assert_eq!(activity.laps.len(), 2);
+        // The point without a time is skipped.
+        assert_eq!(activity.laps[0].trackpoints().count(), 3);
+        assert_eq!(activity.laps[0].total_time_seconds, 6.0);
+        assert!(activity.laps[0].distance_meters > 40.0 && activity.laps[0].distance_meters < 60.0);
+        let first = activity.trackpoints().next().unwrap();
+        assert_eq!(first.heart_rate.as_ref().unwrap().value, 101.0);
+        assert_eq!(first.extensions.as_ref().unwrap().temperature, Some(18.0));
+
+        let mut xml = Vec::new();
+        gpx.to_gpx(&mut xml).unwrap();
+        let text = String::from_utf8(xml.clone()).unwrap();
+        assert!(text.contains("<name>Lunch Ride</name>"));
+        assert!(text.contains("<gpxtpx:atemp>19</gpxtpx:atemp>"));
+        let again = crate::tcx::TrainingCenterDatabase::from_gpx(&mut std::io::BufReader::new(
+            xml.as_slice(),
+        ))
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&again).unwrap(),
+            serde_json::to_value(&gpx).unwrap()
+        );
+
+        let run = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let mut xml = Vec::new();
+        run.to_gpx(&mut xml).unwrap();
+        let converted = crate::tcx::TrainingCenterDatabase::from_gpx(&mut std::io::BufReader::new(
+            xml.as_slice(),
+        ))
+        .unwrap();
+        let original = &run.activities.as_ref().unwrap().activities[0];
+        let converted = &converted.activities.as_ref().unwrap().activities[0];
+        assert_eq!(
+            converted.trackpoints().count(),
+            original
+                .trackpoints()
+                .filter(|point| point.position.is_some())
+                .count()
+        );
+        assert_eq!(converted.start_time(), original.start_time());
+
+        let broken = b"<gpx><trk><trkseg><trkpt lat=\"1\" lon=\"2\"></trkseg></trk></gpx>";
+        let result =
+            crate::tcx::TrainingCenterDatabase::from_gpx(&mut std::io::BufReader::new(&broken[..]));
+        assert!(matches!(result, Err(crate::Error::Xml { line: 1, .. })));
+        assert!(matches!(
+            crate::tcx::TrainingCenterDatabase::from_gpx_file("tests/missing.gpx"),
+            Err(crate::Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn file5_course() {
+        let tcx = crate::tcx::read_file("tests/20210601_course_lake_loop.tcx").unwrap();
+        let course = &tcx.courses.as_ref().unwrap().courses[0];
+        assert_eq!(course.name, "Lake Loop");
+        assert_eq!(course.laps.len(), 2);
+        assert_eq!(course.laps[0].end_altitude_meters, Some(548.0));
+        assert_eq!(course.tracks.as_ref().unwrap()[0].trackpoints.len(), 3);
+        assert_eq!(course.course_points.len(), 2);
+        assert_eq!(course.course_points[0].name.as_deref(), Some("Fountain"));
+        assert_eq!(
+            course.course_points[1].point_type,
+            Some(crate::tcx::CoursePointType::FourthCategory)
+        );
+        let folder = tcx
+            .folders
+            .as_ref()
+            .unwrap()
+            .courses
+            .as_ref()
+            .unwrap()
+            .folder
+            .as_ref()
+            .unwrap();
+        assert_eq!(folder.name, "Summer");
+        assert_eq!(folder.course_name_refs[0].id, "Lake Loop");
+
+        let mut xml = Vec::new();
+        tcx.to_writer(&mut xml).unwrap();
+        let reparsed = crate::tcx::read(&mut std::io::BufReader::new(xml.as_slice())).unwrap();
+        assert_eq!(
+            serde_json::to_value(&reparsed).unwrap(),
+            serde_json::to_value(&tcx).unwrap()
+        );
+    }
+
+    #[test]
+    fn file6_workout() {
+        let tcx = crate::tcx::read_file("tests/20210602_workout_intervals.tcx").unwrap();
+        let workout = &tcx.workouts.as_ref().unwrap().workouts[0];
+        assert_eq!(workout.sport, "Running");
+        assert_eq!(workout.name, "6x800m");
+        assert_eq!(workout.steps.len(), 3);
+        assert_eq!(workout.scheduled_on.len(), 2);
+
+        let warm_up = &workout.steps[0];
+        assert!(!warm_up.is_repeat());
+        assert_eq!(warm_up.duration.as_ref().unwrap().seconds, Some(600));
+        let zone = warm_up
+            .target
+            .as_ref()
+            .unwrap()
+            .heart_rate_zone
+            .as_ref()
+            .unwrap();
+        assert_eq!(zone.number, Some(2));
+
+        let repeat = &workout.steps[1];
+        assert!(repeat.is_repeat());
+        assert_eq!(repeat.repetitions, Some(6));
+        assert_eq!(repeat.children.len(), 2);
+        assert_eq!(
+            repeat.children[0].duration.as_ref().unwrap().meters,
+            Some(800)
+        );
+        let speed_zone = repeat.children[0]
+            .target
+            .as_ref()
+            .unwrap()
+            .speed_zone
+            .as_ref()
+            .unwrap();
+        assert_eq!(speed_zone.high_in_meters_per_second, Some(4.7));
+        let recovery = repeat.children[1].duration.as_ref().unwrap();
+        assert_eq!(recovery.heart_rate.as_ref().unwrap().value, 130);
+
+        let cool_down = workout.steps[2].target.as_ref().unwrap();
+        assert_eq!((cool_down.low, cool_down.high), (Some(80.0), Some(90.0)));
+
+        let folders = tcx.folders.as_ref().unwrap().workouts.as_ref().unwrap();
+        assert_eq!(
+            folders.running.as_ref().unwrap().workout_name_refs[0].id,
+            "6x800m"
+        );
+
+        let mut xml = Vec::new();
+        tcx.to_writer(&mut xml).unwrap();
+        let reparsed = crate::tcx::read(&mut std::io::BufReader::new(xml.as_slice())).unwrap();
+        assert_eq!(
+            serde_json::to_value(&reparsed).unwrap(),
+            serde_json::to_value(&tcx).unwrap()
+        );
+    }
+
+    #[test]
+    fn daylight_fraction() {
+        let tcx = crate::tcx::read_file("tests/20210410_run_unicode_notes.tcx").unwrap();
+        let activity = &tcx.activities.as_ref().unwrap().activities[0];
+        assert_eq!(activity.daylight_fraction(), Some(1.0));
+
+        // A run in Paris starting about twenty minutes before sunset (around 17:38 UTC on 2021-03-01), sampled every minute.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-03-01T17:19:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let points = (0..=40).map(|minute| {
+            crate::builder::TrackpointBuilder::new(start + chrono::Duration::minutes(minute))
+                .position(48.8566, 2.3522)
+                .build()
+        });
+        let activity = crate::builder::ActivityBuilder::new("Running")
+            .lap(
+                crate::builder::LapBuilder::new()
+                    .trackpoints(points)
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let fraction = activity.daylight_fraction().unwrap();
+        assert!((fraction - 0.5).abs() < 0.15, "{}", fraction);
+
+        let tcx = crate::tcx::read_file("tests/20210323_yoga.tcx").unwrap();
+        assert_eq!(
+            tcx.activities.as_ref().unwrap().activities[0].daylight_fraction(),
+            None
+        );
+    }
+
+    #[test]
+    fn file7_multisport() {
+        let tcx = crate::tcx::read_file("tests/20210704_triathlon_multisport.tcx").unwrap();
+        let activities = tcx.activities.as_ref().unwrap();
+        assert!(activities.activities.is_empty());
+        let session = &activities.multi_sport_sessions[0];
+        assert_eq!(session.id.to_rfc3339(), "2021-07-04T07:00:00+00:00");
+        let sports: Vec<&str> = session
+            .activities()
+            .map(|activity| activity.sport.as_str())
+            .collect();
+        assert_eq!(sports, vec!["Other", "Biking", "Running"]);
+        let transitions: Vec<f64> = session
+            .transitions()
+            .map(|lap| lap.total_time_seconds)
+            .collect();
+        assert_eq!(transitions, vec![45.0, 30.0]);
+        assert_eq!(session.next_sports[1].activity.trackpoints().count(), 2);
+        assert_eq!(
+            session.notes.as_deref(),
+            Some("Sprint triathlon (shortened)")
+        );
+
+        let mut xml = Vec::new();
+        tcx.to_writer(&mut xml).unwrap();
+        let reparsed = crate::tcx::read(&mut std::io::BufReader::new(xml.as_slice())).unwrap();
+        assert_eq!(
+            serde_json::to_value(&reparsed).unwrap(),
+            serde_json::to_value(&tcx).unwrap()
+        );
+    }
+
+    #[test]
+    fn wind_adjusted_speeds() {
+        use crate::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+        use crate::wind::Wind;
+
+        // Ten minutes north at about 5.6 m/s, then ten minutes back south.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-06-01T06:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let points = (0..1200).map(|i| {
+            let leg = if i < 600 { i } else { 1200 - i };
+            TrackpointBuilder::new(start + chrono::Duration::seconds(i))
+                .position(47.0 + leg as f64 * 0.00005, 8.0)
+                .distance(i as f64 * 5.56)
+                .build()
+        });
+        let activity = ActivityBuilder::new("Biking")
+            .lap(LapBuilder::new().trackpoints(points).build())
+            .build()
+            .unwrap();
+
+        let north_wind = Wind {
+            speed_meters_per_second: 4.0,
+            from_degrees: 0.0,
+        };
+        let adjusted = activity.wind_adjusted_speeds(&north_wind);
+        assert_eq!(adjusted.len(), 1199);
+        let outbound = &adjusted[100];
+        assert!(outbound.bearing_degrees < 1.0);
+        assert!((outbound.headwind_meters_per_second - 4.0).abs() < 0.01);
+        assert!(outbound.crosswind_meters_per_second.abs() < 0.01);
+        assert!((outbound.air_speed - 9.56).abs() < 0.01);
+        let inbound = &adjusted[900];
+        assert!((inbound.bearing_degrees - 180.0).abs() < 1.0);
+        assert!((inbound.headwind_meters_per_second + 4.0).abs() < 0.01);
+        assert!((inbound.air_speed - 1.56).abs() < 0.01);
+
+        let west_wind = Wind {
+            speed_meters_per_second: 4.0,
+            from_degrees: 270.0,
+        };
+        let outbound = &activity.wind_adjusted_speeds(&west_wind)[100];
+        assert!(outbound.headwind_meters_per_second.abs() < 0.01);
+        assert!((outbound.crosswind_meters_per_second + 4.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn summary_statistics() {
+        let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let activity = &tcx.activities.as_ref().unwrap().activities[0];
+        let summary = activity.summary();
+        assert!((summary.distance_meters - activity.laps[0].distance_meters).abs() < 0.01);
+        assert_eq!(summary.elapsed_seconds, 1231.0);
+        assert!(summary.moving_seconds < summary.elapsed_seconds);
+        assert!(summary.elevation_gain_meters > 0.0 && summary.elevation_loss_meters > 0.0);
+        assert_eq!(summary.max_heart_rate, Some(189.0));
+        assert_eq!(summary.average_power, None);
+        // The device's own lap extension reports an average of 91 and a maximum of 99 steps per minute.
+        assert_eq!(summary.average_cadence.unwrap().round(), 91.0);
+        assert_eq!(summary.max_cadence, Some(99));
+        assert_eq!(tcx.summary(), summary);
+
+        let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let summary = tcx.summary();
+        assert_eq!(summary.average_power.unwrap().round(), 210.0);
+        assert_eq!(summary.max_power, Some(462));
+        assert_eq!(summary.max_cadence, Some(108));
+        assert!(summary.normalized_power.unwrap() > summary.average_power.unwrap());
+        assert!(summary.average_speed.unwrap() <= summary.max_speed.unwrap());
+    }
+
+    #[test]
+    fn bearing_series() {
+        use crate::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+
+        // North-east along a zig-zag, then due south.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-06-01T06:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let points = (0..200).map(|i| {
+            let (latitude, longitude) = if i < 100 {
+                (47.0 + i as f64 * 0.0001, 8.0 + (i % 2) as f64 * 0.0002)
+            } else {
+                (47.0099 - (i - 99) as f64 * 0.0001, 8.0)
+            };
+            TrackpointBuilder::new(start + chrono::Duration::seconds(i))
+                .position(latitude, longitude)
+                .build()
+        });
+        let activity = ActivityBuilder::new("Biking")
+            .lap(LapBuilder::new().trackpoints(points).build())
+            .build()
+            .unwrap();
+
+        let raw = activity.bearing_series(1);
+        assert_eq!(raw.len(), 199);
+        assert!(raw[10].1 < 90.0 && raw[11].1 > 270.0);
+        let smoothed = activity.bearing_series(6);
+        assert_eq!(smoothed.len(), 199);
+        assert!(smoothed[10].1 < 15.0 || smoothed[10].1 > 345.0);
+        assert!((smoothed[150].1 - 180.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn read_errors() {
+        let read = |xml: &str| crate::tcx::read(&mut std::io::BufReader::new(xml.as_bytes()));
+        let activity = |lap: &str| {
+            format!(
+                "<TrainingCenterDatabase><Activities><Activity Sport=\"Running\">\
+                 <Id>2021-01-19T22:03:16.000Z</Id>{}</Activity></Activities></TrainingCenterDatabase>",
+                lap
+            )
+        };
+
+        match crate::tcx::read_file("tests/does_not_exist.tcx") {
+            Err(crate::Error::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+            other => panic!("expected an I/O error, got {:?}", other),
+        }
+
+        match read("<TrainingCenterDatabase>\n  <Activities>\n</TrainingCenterDatabase>") {
+            Err(crate::Error::Xml { line, .. }) => assert_eq!(line, 3),
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+
+        let err = read(&activity(
+            "<Lap StartTime=\"2021-01-19T22:03:16.000Z\"><DistanceMeters>10</DistanceMeters>\
+             <Calories>1</Calories></Lap>",
+        ))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Missing required element Lap/TotalTimeSeconds"
+        );
+
+        let err = read(&activity(
+            "<Lap StartTime=\"2021-01-19T22:03:16.000Z\"><TotalTimeSeconds>ten</TotalTimeSeconds>\
+             <DistanceMeters>10</DistanceMeters><Calories>1</Calories></Lap>",
+        ))
+        .unwrap_err();
+        assert!(matches!(err, crate::Error::InvalidValue(_)));
+    }
+
+    #[test]
+    fn significant_stops() {
+        use crate::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+
+        // Riding north at 5 m/s, with a two minute stop at a light, a 20 second pause, and a 10 minute coffee stop
+        // where the device auto-paused and recorded nothing.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-06-01T06:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let segments = [
+            (0, 300, 5.0),
+            (0, 120, 0.0),
+            (0, 300, 5.0),
+            (0, 20, 0.0),
+            (0, 300, 5.0),
+            (600, 300, 5.0),
+        ];
+        let mut points = Vec::new();
+        let mut time = start;
+        let mut meters = 0.0;
+        for (gap, seconds, speed) in segments {
+            time += chrono::Duration::seconds(gap);
+            for i in 0..seconds {
+                time += chrono::Duration::seconds(1);
+                meters += speed;
+                let drift = if speed == 0.0 {
+                    (i % 3) as f64 * 0.00002
+                } else {
+                    0.0
+                };
+                points.push(
+                    TrackpointBuilder::new(time)
+                        .position(47.0 + meters / 111_195.0 + drift, 8.0)
+                        .distance(meters)
+                        .build(),
+                );
+            }
+        }
+        let activity = ActivityBuilder::new("Biking")
+            .lap(LapBuilder::new().trackpoints(points).build())
+            .build()
+            .unwrap();
+
+        let stops = activity.significant_stops(chrono::Duration::minutes(1));
+        assert_eq!(stops.len(), 2);
+        assert_eq!(stops[0].start_time, start + chrono::Duration::seconds(300));
+        assert!((stops[0].duration_seconds - 120.0).abs() <= 2.0);
+        assert!((stops[0].position.latitude - (47.0 + 1500.0 / 111_195.0)).abs() < 0.0001);
+        assert!((stops[1].duration_seconds - 601.0).abs() <= 2.0);
+        assert_eq!(
+            activity
+                .significant_stops(chrono::Duration::seconds(15))
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn creator_and_author() {
+        let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let creator = tcx.activities.as_ref().unwrap().activities[0]
+            .creator
+            .as_ref()
+            .unwrap();
+        assert_eq!(creator.name, "fenix 6 Sapphire");
+        assert_eq!(creator.unit_id, Some(3317383806));
+        assert_eq!(creator.product_id, Some(3290));
+        let version = creator.version.as_ref().unwrap();
+        assert_eq!((version.version_major, version.version_minor), (13, 10));
+
+        let author = tcx.author.as_ref().unwrap();
+        assert_eq!(author.name, "Connect Api");
+        assert_eq!(author.build.version.build_major, Some(0));
+        assert_eq!(author.lang_id.as_deref(), Some("en"));
+        assert_eq!(author.part_number.as_deref(), Some("006-D2449-00"));
+
+        let mut xml = Vec::new();
+        tcx.to_writer(&mut xml).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        assert!(xml.contains("<Creator xsi:type=\"Device_t\">"));
+        assert!(xml.contains("<Author xsi:type=\"Application_t\">"));
+        let reparsed = crate::tcx::read(&mut std::io::BufReader::new(xml.as_bytes())).unwrap();
+        assert_eq!(reparsed.author.unwrap().name, "Connect Api");
+
+        let tcx = crate::tcx::read_file("tests/20210323_yoga.tcx").unwrap();
+        assert!(tcx.author.is_some());
+    }
+
+    #[test]
+    fn lap_and_activity_csv() {
+        let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let mut csv = Vec::new();
+        tcx.to_lap_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0][0], "activity_id");
+        assert_eq!(rows[0].len(), rows[1].len());
+        let lap = &tcx.activities.as_ref().unwrap().activities[0].laps[0];
+        assert_eq!(rows[1][1], "1");
+        assert_eq!(rows[1][3], format!("{:.1}", lap.total_time_seconds));
+        assert_eq!(rows[1][7], "210");
+
+        let mut csv = Vec::new();
+        tcx.to_activity_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].len(), rows[1].len());
+        assert_eq!(rows[1][1], "Biking");
+        assert_eq!(rows[1][3], "1");
+        assert!(rows[1][10].parse::<f64>().unwrap() > 210.0);
+    }
+
+    #[test]
+    fn trackpoint_csv() {
+        let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let lap = &tcx.activities.as_ref().unwrap().activities[0].laps[0];
+        let count = lap.trackpoints().count();
+
+        let mut csv = Vec::new();
+        tcx.export_csv(&mut csv, &crate::csv::CsvOptions::default())
+            .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), count + 1);
+        assert_eq!(rows[0].len(), 12);
+        assert_eq!(rows[0][2], "timestamp");
+        assert!(rows.iter().all(|row| row.len() == 12));
+        assert!(rows[1..].iter().any(|row| !row[9].is_empty()));
+
+        let options = crate::csv::CsvOptions {
+            columns: vec![
+                crate::csv::CsvColumn::Distance,
+                crate::csv::CsvColumn::Speed,
+            ],
+            lap_summaries: true,
+        };
+        let mut csv = Vec::new();
+        tcx.export_csv(&mut csv, &options).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(
+            rows[0],
+            vec!["activity_id", "lap", "row", "distance_meters", "speed"]
+        );
+        assert_eq!(rows.len(), count + 2);
+        assert_eq!(rows[1][2], "trackpoint");
+        let summary = rows.last().unwrap();
+        assert_eq!(summary[2], "lap");
+        assert_eq!(summary[3], format!("{:.1}", lap.distance_meters));
+    }
+
+    #[test]
+    fn temperature_series_csv_and_summary() {
+        let mut tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+        let count = activity.trackpoints().count();
+        for (i, point) in activity.trackpoints_mut().enumerate() {
+            let temperature = if i == 0 { 4.0 } else { 12.0 + (i % 2) as f64 };
+            point
+                .extensions
+                .get_or_insert_with(crate::tcx::Extensions::default)
+                .temperature = Some(temperature);
+        }
+        let activity = &tcx.activities.as_ref().unwrap().activities[0];
+
+        let series = activity.series();
+        assert_eq!(series.temperature.len(), count);
+        assert_eq!(series.temperature[0], Some(4.0));
+        assert_eq!(series.temperature[1], Some(13.0));
+        let resampled = series.resample(1.0, 10.0);
+        assert_eq!(resampled.temperature[0], Some(4.0));
+
+        let summary = activity.summary();
+        assert_eq!(summary.min_temperature, Some(4.0));
+        assert_eq!(summary.max_temperature, Some(13.0));
+        let average = summary.average_temperature.unwrap();
+        assert!(average > 12.0 && average < 13.0);
+        assert_eq!(tcx.summary().min_temperature, Some(4.0));
+        assert_eq!(tcx.summary().max_temperature, Some(13.0));
+        assert_eq!(
+            crate::tcx::TrainingCenterDatabase::default()
+                .summary()
+                .average_temperature,
+            None
+        );
+
+        let options = crate::csv::CsvOptions {
+            columns: vec![crate::csv::CsvColumn::Temperature],
+            lap_summaries: true,
+        };
+        let mut csv = Vec::new();
+        tcx.export_csv(&mut csv, &options).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows[0][3], "temperature_celsius");
+        assert_eq!(rows[1][3], "4");
+        assert_eq!(rows[2][3], "13");
+        assert_eq!(rows.last().unwrap()[3], format!("{:.1}", average));
+
+        let mut csv = Vec::new();
+        tcx.to_activity_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows[0].last(), Some(&"average_temperature"));
+        assert_eq!(rows[1].last(), Some(&format!("{:.1}", average).as_str()));
+    }
+
+    #[test]
+    fn chunked_tcx() {
+        let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+        let total = activity.trackpoints().count();
+        let start = activity.start_time().unwrap();
+        let second = activity
+            .split_at(start + chrono::Duration::minutes(5))
+            .unwrap();
+        let first_count = activity.trackpoints().count();
+        activity.laps.extend(second.laps);
+        assert_eq!(activity.laps.len(), 2);
+
+        // Whole laps are kept together when they fit.
+        let parts = tcx.split_by_trackpoints(total - 1);
+        assert_eq!(parts.len(), 2);
+        let counts: Vec<usize> = parts
+            .iter()
+            .map(|part| {
+                part.activities.as_ref().unwrap().activities[0]
+                    .trackpoints()
+                    .count()
+            })
+            .collect();
+        assert_eq!(counts, vec![first_count, total - first_count]);
+
+        let parts = tcx.split_by_trackpoints(100);
+        let counts: Vec<usize> = parts
+            .iter()
+            .flat_map(|part| part.activities.as_ref().unwrap().activities.iter())
+            .map(|activity| activity.trackpoints().count())
+            .collect();
+        assert!(counts.iter().all(|count| *count <= 100));
+        assert_eq!(counts.iter().sum::<usize>(), total);
+
+        let files = tcx.export_tcx_chunked("target/chunked.tcx", 500).unwrap();
+        assert_eq!(files.len(), 3);
+        for file in files {
+            let part = crate::tcx::read_file(&file).unwrap();
+            assert!(part.activities.unwrap().activities[0].trackpoints().count() <= 500);
+        }
+        let files = tcx.export_tcx_chunked("target/chunked.tcx", total).unwrap();
+        assert_eq!(files, vec!["target/chunked.tcx"]);
+    }
+
+    #[test]
+    fn write_options() {
+        let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let options = crate::tcx::WriteOptions {
+            extension_prefix: "x".to_string(),
+            root_attributes: vec![
+                crate::tcx::RootAttribute::TrainingCenterDatabase,
+                crate::tcx::RootAttribute::ActivityExtension,
+            ],
+            ..Default::default()
+        };
+        let mut xml = Vec::new();
+        tcx.to_writer_with_options(&mut xml, &options).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        let root = xml.lines().nth(1).unwrap();
+        let positions: Vec<usize> = ["xmlns=", "xmlns:x=", "xsi:schemaLocation=", "xmlns:xsi="]
+            .iter()
+            .map(|attribute| root.find(attribute).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(xml.contains("<x:TPX>"));
+        assert!(!xml.contains("ns3:"));
+
+        let reparsed = crate::tcx::read(&mut std::io::BufReader::new(xml.as_bytes())).unwrap();
+        let point = reparsed.activities.unwrap().activities[0].laps[0]
+            .trackpoints()
+            .find(|point| point.extensions.is_some())
+            .cloned()
+            .unwrap();
+        assert!(point.extensions.unwrap().tpx.unwrap().watts.is_some());
+
+        let mut xml = Vec::new();
+        tcx.to_writer(&mut xml).unwrap();
+        assert!(String::from_utf8(xml).unwrap().contains("xmlns:ns3="));
+    }
+
+    #[test]
+    fn heart_rate_sources() {
+        let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let mut watch = tcx.activities.unwrap().activities.remove(0);
+        let mut strap = watch.clone();
+        assert_eq!(watch.heart_rate_source(), None);
+        watch.tag_heart_rate_source(crate::tcx::HeartRateSource::Optical);
+        strap.tag_heart_rate_source(crate::tcx::HeartRateSource::ChestStrap);
+        for point in strap.trackpoints_mut() {
+            point.heart_rate.as_mut().unwrap().value += 5.0;
+        }
+
+        // An optical recording does not displace the strap.
+        let mut merged = strap.clone();
+        assert_eq!(merged.reconcile_heart_rate(&watch), 0);
+
+        // The strap replaces every optical reading.
+        let count = watch.trackpoints().count();
+        let before: Vec<f64> = watch
+            .trackpoints()
+            .map(|point| point.heart_rate.as_ref().unwrap().value)
+            .collect();
+        assert_eq!(watch.reconcile_heart_rate(&strap), count);
+        assert_eq!(
+            watch.heart_rate_source(),
+            Some(crate::tcx::HeartRateSource::ChestStrap)
+        );
+        let after = watch
+            .trackpoints()
+            .map(|point| point.heart_rate.as_ref().unwrap().value);
+        assert!(before
+            .iter()
+            .zip(after)
+            .all(|(before, after)| after == before + 5.0));
+
+        // Merging copies the source along with a missing heart rate.
+        let mut bare = strap.clone();
+        for point in bare.trackpoints_mut() {
+            point.heart_rate = None;
+            point.extensions = None;
+        }
+        bare.merge(&strap);
+        assert_eq!(
+            bare.heart_rate_source(),
+            Some(crate::tcx::HeartRateSource::ChestStrap)
+        );
+        // The source is written as an extension element and read back.
+        let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        tcx.activities.as_mut().unwrap().activities[0]
+            .tag_heart_rate_source(crate::tcx::HeartRateSource::Optical);
+        let mut xml = Vec::new();
+        tcx.to_writer(&mut xml).unwrap();
+        let text = String::from_utf8(xml.clone()).unwrap();
+        assert!(text.contains("<ns3:HeartRateSource>Optical</ns3:HeartRateSource>"));
+        let read = crate::tcx::read(&mut std::io::BufReader::new(xml.as_slice())).unwrap();
+        assert_eq!(
+            read.activities.unwrap().activities[0].heart_rate_source(),
+            Some(crate::tcx::HeartRateSource::Optical)
+        );
+        #[cfg(feature = "quick_xml")]
+        {
+            let read = crate::quick::read(&mut std::io::BufReader::new(xml.as_slice())).unwrap();
+            let activity = &read.activities.unwrap().activities[0];
+            assert_eq!(
+                activity.heart_rate_source(),
+                Some(crate::tcx::HeartRateSource::Optical)
+            );
+            assert!(activity.trackpoints().all(|point| point
+                .extensions
+                .as_ref()
+                .unwrap()
+                .unknown
+                .is_empty()));
+        }
+    }
+
+    #[test]
+    fn lenient_reading() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2" xmlns:ns3="http://www.garmin.com/xmlschemas/ActivityExtension/v2">
+  <Activities>
+    <Activity Sport="Biking">
+      <Id>2021-06-01T06:00:00Z</Id>
+      <Lap StartTime="2021-06-01T06:00:00">
+        <TotalTimeSeconds>2</TotalTimeSeconds>
+        <DistanceMeters>12.5</DistanceMeters>
+        <Intensity>active</Intensity>
+        <Track>
+          <Trackpoint>
+            <Time>2021-06-01T06:00:00</Time>
+            <Position/>
+            <HeartRateBpm><Value>120</Value></HeartRateBpm>
+            <Cadence>300</Cadence>
+          </Trackpoint>
+          <Trackpoint>
+            <Time>yesterday</Time>
+          </Trackpoint>
+          <Trackpoint>
+            <Time>2021-06-01T06:00:02Z</Time>
+            <Cadence>90.0</Cadence>
+            <Extensions><ns3:TPX><ns3:Watts>250</ns3:Watts></ns3:TPX></Extensions>
+          </Trackpoint>
+        </Track>
+      </Lap>
+    </Activity>
+  </Activities>
+</TrainingCenterDatabase>"#;
+        assert!(crate::tcx::read(&mut std::io::BufReader::new(xml.as_bytes())).is_err());
+
+        let mut reader = std::io::BufReader::new(xml.as_bytes());
+        let (tcx, warnings) = crate::lenient::read_lossy(&mut reader).unwrap();
+        let lap = &tcx.activities.as_ref().unwrap().activities[0].laps[0];
+        assert_eq!(lap.calories, 0);
+        assert!(lap.intensity.is_none());
+        assert_eq!(
+            lap.start_time.unwrap().to_rfc3339(),
+            "2021-06-01T06:00:00+00:00"
+        );
+        let points = &lap.tracks[0].trackpoints;
+        assert_eq!(points.len(), 2);
+        assert!(points[0].position.is_none());
+        assert!(points[0].cadence.is_none());
+        assert_eq!(points[0].heart_rate.as_ref().unwrap().value, 120.0);
+        assert_eq!(points[1].cadence, Some(90));
+        let tpx = points[1].extensions.as_ref().unwrap().tpx.as_ref().unwrap();
+        assert_eq!(tpx.watts, Some(250));
+
+        let messages: Vec<String> = warnings.iter().map(|warning| warning.to_string()).collect();
+        assert_eq!(warnings.len(), 9, "{:#?}", messages);
+        assert!(warnings
+            .iter()
+            .any(|warning| warning.path == "Activity/Lap" && warning.message.contains("Calories")));
+        assert!(warnings.iter().any(|warning| warning.path
+            == "Activity/Lap/Track/Trackpoint/Cadence"
+            && warning.line == 15));
+
+        // The same repairs through ReadOptions, and well-formed files are left alone.
+        let options = crate::tcx::ReadOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let mut reader = std::io::BufReader::new(xml.as_bytes());
+        let tcx = crate::tcx::read_with_options(&mut reader, &options).unwrap();
+        assert_eq!(
+            tcx.activities.unwrap().activities[0].laps[0].tracks[0]
+                .trackpoints
+                .len(),
+            2
+        );
+        let (tcx, warnings) =
+            crate::lenient::read_file_lossy("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        assert!(warnings.is_empty());
+        let expected = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        assert_eq!(
+            serde_json::to_string(&tcx).unwrap(),
+            serde_json::to_string(&expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn set_sport() {
+        let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let ride = tcx.activities.unwrap().activities.remove(0);
+        let cadences: Vec<Option<u8>> = ride.trackpoints().map(|point| point.cadence).collect();
+        let summary = ride.summary();
+
+        // Cycling cadence moves to RunCadence, with the lap summaries following.
+        let mut run = ride.clone();
+        run.set_sport("RUNNING");
+        assert_eq!(run.sport, "Running");
+        let run_cadences: Vec<Option<u8>> = run
+            .trackpoints()
+            .map(|point| {
+                assert!(point.cadence.is_none());
+                point
+                    .extensions
+                    .as_ref()
+                    .and_then(|ext| ext.tpx.as_ref())
+                    .and_then(|tpx| tpx.run_cadence)
+            })
+            .collect();
+        assert_eq!(run_cadences, cadences);
+        let lap = &run.laps[0];
+        assert!(lap.cadence.is_none());
+        let lx = lap.extensions.as_ref().unwrap().lx.as_ref().unwrap();
+        assert_eq!(lx.max_run_cadence, summary.max_cadence);
+        assert!(lx.max_bike_cadence.is_none());
+        assert_eq!(run.summary(), summary);
+
+        // And back again.
+        let mut back = run.clone();
+        back.set_sport("Biking");
+        let back_cadences: Vec<Option<u8>> =
+            back.trackpoints().map(|point| point.cadence).collect();
+        assert_eq!(back_cadences, cadences);
+        let lap = &back.laps[0];
+        assert_eq!(
+            lap.cadence,
+            summary.average_cadence.map(|cadence| cadence.round() as u8)
+        );
+        let lx = lap.extensions.as_ref().unwrap().lx.as_ref().unwrap();
+        assert!(lx.avg_run_cadence.is_none());
+        assert_eq!(lx.max_bike_cadence, summary.max_cadence);
+
+        // Other leaves the cadence alone.
+        let mut other = ride.clone();
+        other.set_sport("other");
+        assert_eq!(other.sport, "Other");
+        let other_cadences: Vec<Option<u8>> =
+            other.trackpoints().map(|point| point.cadence).collect();
+        assert_eq!(other_cadences, cadences);
+    }
+
+    #[test]
+    fn geo_helpers() {
+        let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let recorded = tcx.activities.unwrap().activities.remove(0);
+
+        // Distances from positions come close to the device's own.
+        let mut activity = recorded.clone();
+        for point in activity.trackpoints_mut() {
+            point.distance_meters = None;
+        }
+        let total = activity.recompute_distances();
+        let device = recorded.laps[0].distance_meters;
+        assert!((total - device).abs() / device < 0.05);
+        let distances: Vec<f64> = activity
+            .trackpoints()
+            .map(|point| point.distance_meters.unwrap())
+            .collect();
+        assert_eq!(distances[0], 0.0);
+        assert!(distances.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert_eq!(activity.laps[0].distance_meters, total);
+
+        // Speed and grade come with every Trackpoint after the first.
+        assert_eq!(
+            recorded.speed_series().len(),
+            recorded.trackpoints().count()
+        );
+        assert!(!activity.grade_series().is_empty());
+
+        let bounds = recorded.bounding_box().unwrap();
+        assert!(bounds.min_latitude < bounds.max_latitude);
+        assert!(bounds.min_longitude < bounds.max_longitude);
+        assert!(recorded
+            .trackpoints()
+            .filter_map(|point| point.position.as_ref())
+            .all(|position| bounds.contains(position)));
+        assert!(crate::tcx::Activity::default().bounding_box().is_none());
+
+        // The polyline decodes back to the track, to within the encoding's precision.
+        let polyline = recorded.encoded_polyline();
+        let mut decoded = Vec::new();
+        let (mut value, mut shift, mut total) = (0i64, 0, [0i64; 2]);
+        for byte in polyline.bytes() {
+            let chunk = (byte - 63) as i64;
+            value |= (chunk & 0x1f) << shift;
+            shift += 5;
+            if chunk < 0x20 {
+                let delta = if value & 1 == 1 {
+                    !(value >> 1)
+                } else {
+                    value >> 1
+                };
+                total[decoded.len() % 2] += delta;
+                decoded.push(total[decoded.len() % 2] as f64 / 1e5);
+                value = 0;
+                shift = 0;
+            }
+        }
+        let positions: Vec<f64> = recorded
+            .trackpoints()
+            .filter_map(|point| point.position.as_ref())
+            .flat_map(|position| [position.latitude, position.longitude])
+            .collect();
+        assert_eq!(decoded.len(), positions.len());
+        assert!(decoded
+            .iter()
+            .zip(positions)
+            .all(|(decoded, original)| (decoded - original).abs() < 1e-5));
+    }
+
+    #[test]
+    fn anonymize() {
+        use crate::privacy::{AnonymizeOptions, PrivacyZone, ZoneHandling};
+
+        let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let vendor_xml = String::from("<Vendor><Pulse>142</Pulse></Vendor>");
+        for point in tcx.activities.as_mut().unwrap().activities[0].trackpoints_mut() {
+            let extensions = point.extensions.get_or_insert_with(Default::default);
+            extensions.rr_intervals = vec![800, 810];
+            extensions.unknown = vec![vendor_xml.clone()];
+        }
+        for lap in tcx.activities.as_mut().unwrap().activities[0]
+            .laps
+            .iter_mut()
+        {
+            lap.extensions.get_or_insert_with(Default::default).unknown = vec![vendor_xml.clone()];
+        }
+        let original = &tcx.activities.as_ref().unwrap().activities[0];
+        let start = original
+            .trackpoints()
+            .find_map(|point| point.position.clone())
+            .unwrap();
+        let zone = PrivacyZone {
+            center: start,
+            radius_meters: 300.0,
+        };
+        let hidden = original
+            .trackpoints()
+            .filter(|point| point.position.as_ref().is_some_and(|p| zone.contains(p)))
+            .count();
+        assert!(hidden > 0);
+
+        // Stripping removes the points near home, along with the serial number.
+        let options = AnonymizeOptions {
+            zones: vec![zone.clone()],
+            remove_heart_rate: true,
+            ..Default::default()
+        };
+        let shared = tcx.anonymize(&options);
+        assert!(shared.original.is_none());
+        let activity = &shared.activities.as_ref().unwrap().activities[0];
+        assert_eq!(
+            activity.trackpoints().count(),
+            original.trackpoints().count() - hidden
+        );
+        assert!(activity
+            .creator
+            .as_ref()
+            .is_none_or(|creator| creator.unit_id.is_none()));
+        assert!(activity
+            .trackpoints()
+            .all(|point| point.heart_rate.is_none()
+                && point.extensions.as_ref().unwrap().rr_intervals.is_empty()));
+        assert!(activity
+            .laps
+            .iter()
+            .all(|lap| lap.average_heart_rate.is_none() && lap.notes.is_none()));
+
+        // Hiding positions keeps every point, but none inside the zone has a position.
+        let options = AnonymizeOptions {
+            zones: vec![zone.clone()],
+            zone_handling: ZoneHandling::HidePosition,
+            ..Default::default()
+        };
+        let shared = tcx.anonymize(&options);
+        let activity = &shared.activities.as_ref().unwrap().activities[0];
+        assert_eq!(
+            activity.trackpoints().count(),
+            original.trackpoints().count()
+        );
+        assert_eq!(
+            activity
+                .trackpoints()
+                .filter(|point| point.position.is_none())
+                .count(),
+            original
+                .trackpoints()
+                .filter(|point| point.position.is_none())
+                .count()
+                + hidden
+        );
+        assert!(activity.trackpoints().all(|point| point
+            .extensions
+            .as_ref()
+            .unwrap()
+            .rr_intervals
+            == vec![800, 810]));
+
+        // Vendor extensions kept as raw XML may hold anything, so they always go.
+        assert!(activity.trackpoints().all(|point| point
+            .extensions
+            .as_ref()
+            .unwrap()
+            .unknown
+            .is_empty()));
+        assert!(activity.laps.iter().all(|lap| lap
+            .extensions
+            .as_ref()
+            .unwrap()
+            .unknown
+            .is_empty()));
+        assert!(!serde_json::to_string(&shared).unwrap().contains("Vendor"));
+
+        // Power can be dropped too.
+        let ride = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let options = AnonymizeOptions {
+            remove_power: true,
+            ..Default::default()
+        };
+        let shared = ride.anonymize(&options);
+        assert!(shared.activities.unwrap().activities[0]
+            .trackpoints()
+            .all(|point| point
+                .extensions
+                .as_ref()
+                .and_then(|ext| ext.tpx.as_ref())
+                .is_none_or(|tpx| tpx.watts.is_none())));
+
+        // Notes go by default.
+        let notes = crate::tcx::read_file("tests/20210410_run_unicode_notes.tcx").unwrap();
+        let shared = notes.anonymize(&AnonymizeOptions::default());
+        let activity = &shared.activities.unwrap().activities[0];
+        assert!(activity.notes.is_none());
+        assert!(activity.laps.iter().all(|lap| lap.notes.is_none()));
+    }
+
+    #[test]
+    fn raw_extensions_in_json() {
+        let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let raw = r#"<v:Core xmlns:v="urn:vendor"><v:Temp>37.5</v:Temp></v:Core>"#.to_string();
+        let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+        let point = activity.laps[0].trackpoints_mut().next().unwrap();
+        point
+            .extensions
+            .get_or_insert_with(Default::default)
+            .unknown
+            .push(raw.clone());
+
+        let json = tcx
+            .to_json_value(&crate::tcx::JsonOptions::default())
+            .unwrap();
+        let laps = &json["Activities"]["Activity"][0]["Lap"];
+        assert_eq!(
+            laps[0]["Track"][0]["Trackpoint"][0]["Extensions"]["extensions_raw"],
+            serde_json::json!([raw])
+        );
+        // Nothing is added where there is nothing to pass through.
+        assert!(laps[0]["Track"][0]["Trackpoint"][1]["Extensions"]
+            .get("extensions_raw")
+            .is_none());
+
+        // And it comes back when the JSON is read.
+        let reread: crate::tcx::TrainingCenterDatabase = serde_json::from_value(json).unwrap();
+        let point = reread.activities.unwrap().activities[0].laps[0]
+            .trackpoints()
+            .next()
+            .unwrap()
+            .clone();
+        assert_eq!(point.extensions.unwrap().unknown, vec![raw]);
+    }
+
+    #[test]
+    fn plain_numbers() {
+        let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let lap = &mut tcx.activities.as_mut().unwrap().activities[0].laps[0];
+        lap.distance_meters = 1.2e17;
+        let point = lap.trackpoints_mut().nth(1).unwrap();
+        point.distance_meters = Some(0.000000123456);
+        point.altitude_meters = Some(-0.0001);
+
+        // XML never uses scientific notation, and rounds when asked.
+        let mut xml = Vec::new();
+        tcx.to_writer(&mut xml).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        assert!(xml.contains("<DistanceMeters>120000000000000000</DistanceMeters>"));
+        assert!(xml.contains("<DistanceMeters>0.000000123456</DistanceMeters>"));
+        let options = crate::tcx::WriteOptions {
+            decimal_places: Some(2),
+            ..Default::default()
+        };
+        let mut xml = Vec::new();
+        tcx.to_writer_with_options(&mut xml, &options).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        assert!(xml.contains("<DistanceMeters>0</DistanceMeters>"));
+        assert!(xml.contains("<AltitudeMeters>0</AltitudeMeters>"));
+        assert!(xml.contains("<LatitudeDegrees>28.08</LatitudeDegrees>"));
+        let reparsed = crate::tcx::read(&mut std::io::BufReader::new(xml.as_bytes())).unwrap();
+        let position = reparsed.activities.unwrap().activities[0]
+            .trackpoints()
+            .find_map(|point| point.position.clone())
+            .unwrap();
+        assert_eq!(
+            position.latitude,
+            (position.latitude * 100.0).round() / 100.0
+        );
+
+        // Neither does JSON, which still reads back.
+        let mut json = Vec::new();
+        tcx.to_json_writer(&mut json, &Default::default()).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains("\"DistanceMeters\": 120000000000000000.0"));
+        assert!(json.contains("\"DistanceMeters\": 0.000000123456"));
+        assert!(!json.contains("e-") && !json.contains("e17"));
+        let reread: crate::tcx::TrainingCenterDatabase = serde_json::from_str(&json).unwrap();
+        let lap = &reread.activities.unwrap().activities[0].laps[0];
+        assert_eq!(lap.distance_meters, 1.2e17);
+        assert_eq!(
+            lap.trackpoints().nth(1).unwrap().distance_meters,
+            Some(0.000000123456)
+        );
+
+        let options = crate::tcx::JsonOptions {
+            decimal_places: Some(3),
+            ..Default::default()
+        };
+        let mut json = Vec::new();
+        tcx.to_json_writer(&mut json, &options).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains("\"AltitudeMeters\": 0,"));
+        assert_eq!(crate::writer::format_number(2.5, Some(3)), "2.5");
+        assert_eq!(crate::writer::format_number(1.23456, Some(3)), "1.235");
+    }
+
+    #[test]
+    fn unwritable_values() {
+        let original = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let write = |tcx: &crate::tcx::TrainingCenterDatabase| {
+            let options = crate::tcx::WriteOptions {
+                validate: false,
+                ..Default::default()
+            };
+            tcx.to_writer_with_options(&mut Vec::new(), &options)
+                .unwrap_err()
+                .to_string()
+        };
+
+        // NaN and infinity have no XML Schema spelling for these elements.
+        let mut tcx = original.clone();
+        let lap = &mut tcx.activities.as_mut().unwrap().activities[0].laps[0];
+        lap.total_time_seconds = f64::NAN;
+        assert_eq!(
+            write(&tcx),
+            "TotalTimeSeconds is NaN, which cannot be written"
+        );
+        let mut tcx = original.clone();
+        let lap = &mut tcx.activities.as_mut().unwrap().activities[0].laps[0];
+        lap.trackpoints_mut().nth(1).unwrap().altitude_meters = Some(f64::INFINITY);
+        assert_eq!(
+            write(&tcx),
+            "AltitudeMeters is inf, which cannot be written"
+        );
+        let mut tcx = original.clone();
+        let lap = &mut tcx.activities.as_mut().unwrap().activities[0].laps[0];
+        lap.trackpoints_mut()
+            .nth(1)
+            .unwrap()
+            .position
+            .as_mut()
+            .unwrap()
+            .latitude = f64::NAN;
+        assert_eq!(
+            write(&tcx),
+            "LatitudeDegrees is NaN, which cannot be written"
+        );
+        let mut tcx = original.clone();
+        let lap = &mut tcx.activities.as_mut().unwrap().activities[0].laps[0];
+        lap.average_heart_rate = Some(f64::NEG_INFINITY);
+        assert_eq!(
+            write(&tcx),
+            "AverageHeartRateBpm is -inf, which cannot be written"
+        );
+
+        // A lap needs a start time, either its own or its first trackpoint's.
+        let mut tcx = original.clone();
+        let lap = &mut tcx.activities.as_mut().unwrap().activities[0].laps[0];
+        lap.start_time = None;
+        lap.tracks.clear();
+        assert_eq!(
+            write(&tcx),
+            "Lap has no StartTime and no trackpoints to take one from"
+        );
+    }
+
+    #[test]
+    fn write_options_per_field_precision() {
+        use crate::tcx::WriteOptions;
+
+        let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let write = |tcx: &crate::tcx::TrainingCenterDatabase, options: &WriteOptions| {
+            let mut xml = Vec::new();
+            tcx.to_writer_with_options(&mut xml, options).unwrap();
+            String::from_utf8(xml).unwrap()
+        };
+
+        // Each kind of value follows its own setting, and the rest follow decimal_places.
+        let options = WriteOptions {
+            decimal_places: Some(4),
+            coordinate_decimal_places: Some(6),
+            altitude_decimal_places: Some(1),
+            distance_decimal_places: Some(0),
+            ..Default::default()
+        };
+        let xml = write(&tcx, &options);
+        assert!(xml.contains("<LatitudeDegrees>-10.383889</LatitudeDegrees>"));
+        assert!(xml.contains("<AltitudeMeters>119.4</AltitudeMeters>"));
+        assert!(xml.contains("<DistanceMeters>11</DistanceMeters>"));
+        assert!(xml.contains("<DistanceMeters>16190</DistanceMeters>"));
+        assert!(xml.contains("<ns3:Speed>"));
+        assert!(xml
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("<ns3:Speed>"))
+            .map(|speed| speed.split('<').next().unwrap())
+            .all(|speed| speed
+                .split('.')
+                .nth(1)
+                .is_none_or(|decimals| decimals.len() <= 4)));
+
+        // Unset, they follow decimal_places, and the same options always give the same bytes.
+        let options = WriteOptions {
+            decimal_places: Some(2),
+            ..Default::default()
+        };
+        let xml = write(&tcx, &options);
+        assert!(xml.contains("<LatitudeDegrees>-10.38</LatitudeDegrees>"));
+        assert!(xml.contains("<DistanceMeters>10.62</DistanceMeters>"));
+        assert_eq!(xml, write(&tcx, &options));
+
+        // Optional elements with nothing in them are written unless turned off.
+        let mut tcx = tcx;
+        let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+        activity.notes = Some(String::new());
+        activity.laps[0].extensions = Some(Default::default());
+        activity.laps[0].tracks[0].trackpoints[0]
+            .extensions
+            .as_mut()
+            .unwrap()
+            .tpx = Some(Default::default());
+        let xml = write(&tcx, &WriteOptions::default());
+        assert!(xml.contains("<Notes></Notes>"));
+        assert_eq!(xml.matches("<ns3:TPX>").count(), 1434);
+        assert!(xml.contains("<Extensions>\n        </Extensions>"));
+        let options = WriteOptions {
+            emit_empty_elements: false,
+            ..Default::default()
+        };
+        let compact = write(&tcx, &options);
+        assert!(!compact.contains("<Notes>"));
+        assert_eq!(compact.matches("<ns3:TPX>").count(), 1433);
+        assert!(!compact.contains("<Extensions>\n        </Extensions>"));
+        assert!(compact.len() < xml.len());
+        let reparsed = crate::tcx::read(&mut std::io::BufReader::new(compact.as_bytes())).unwrap();
+        assert_eq!(
+            reparsed.activities.unwrap().activities[0]
+                .trackpoints()
+                .count(),
+            1434
+        );
+    }
+
+    #[test]
+    fn shared_activities() {
+        let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let shared = tcx.into_shared_activities().remove(0);
+        let count = shared.trackpoints().count();
+
+        // Many threads can read the same activity without copying it.
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let activity = shared.clone();
+                std::thread::spawn(move || activity.trackpoints().count())
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), count);
+        }
+
+        // Reading through an editor copies nothing, and committing it gives back the same activity.
+        let editor = shared.edit();
+        assert_eq!(editor.laps.len(), shared.laps.len());
+        assert!(!editor.is_modified());
+        assert!(editor.commit().ptr_eq(&shared));
+
+        // Changes stay private to the editor until committed, and never reach the original.
+        let mut editor = shared.edit();
+        editor.laps.truncate(1);
+        editor.laps[0].tracks.clear();
+        assert!(editor.is_modified());
+        assert_eq!(shared.trackpoints().count(), count);
+        let edited = editor.commit();
+        assert!(!edited.ptr_eq(&shared));
+        assert_eq!(edited.trackpoints().count(), 0);
+        assert_eq!(shared.trackpoints().count(), count);
+
+        // The activity can be taken back out.
+        let activity = edited.into_activity();
+        assert_eq!(activity.laps.len(), 1);
+    }
+
+    #[test]
+    fn metrics_cache() {
+        let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let activity = tcx.activities.unwrap().activities.remove(0);
+        let mut metrics = crate::metrics::MetricsCache::new(activity.clone());
+
+        // The cached metrics match those computed directly, and are computed only once.
+        assert_eq!(metrics.speed_series(), activity.speed_series().as_slice());
+        assert_eq!(metrics.grade_series(), activity.grade_series().as_slice());
+        assert_eq!(
+            metrics.normalized_power(),
+            activity.summary().normalized_power
+        );
+        assert_eq!(metrics.summary(), &activity.summary());
+        assert!(std::ptr::eq(
+            metrics.speed_series().as_ptr(),
+            metrics.speed_series().as_ptr()
+        ));
+        assert!(!metrics.rolling_power().is_empty());
+
+        // Changing the activity invalidates them.
+        let point = metrics.activity_mut().laps[0]
+            .trackpoints_mut()
+            .last()
+            .unwrap();
+        point
+            .extensions
+            .as_mut()
+            .unwrap()
+            .tpx
+            .as_mut()
+            .unwrap()
+            .watts = Some(2000);
+        assert!(metrics.normalized_power().unwrap() > activity.summary().normalized_power.unwrap());
+        assert_eq!(
+            metrics.normalized_power(),
+            metrics.activity().summary().normalized_power
+        );
+
+        metrics.activity_mut().laps.clear();
+        assert!(metrics.speed_series().is_empty());
+        assert!(metrics.rolling_power().is_empty());
+        assert!(metrics.normalized_power().is_none());
+        assert!(metrics.into_activity().laps.is_empty());
+    }
+
+    #[test]
+    fn hill_repeat_splits() {
+        // Three repeats of 500 m up at 10% and 500 m back down, one lap each way.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-06-01T08:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let laps = (0..6)
+            .map(|lap| {
+                let trackpoints = (0..50)
+                    .map(|i| {
+                        let step = lap * 50 + i + 1;
+                        let into_repeat = (step % 100) as f64 * 10.0;
+                        let altitude = if into_repeat <= 500.0 {
+                            into_repeat * 0.1
+                        } else {
+                            (1000.0 - into_repeat) * 0.1
+                        };
+                        crate::tcx::Trackpoint {
+                            time: start + chrono::Duration::seconds(step as i64 * 3),
+                            altitude_meters: Some(altitude),
+                            distance_meters: Some(step as f64 * 10.0),
+                            position: None,
+                            heart_rate: None,
+                            cadence: None,
+                            extensions: None,
+                        }
+                    })
+                    .collect();
+                crate::tcx::ActivityLap {
+                    tracks: vec![crate::tcx::Track { trackpoints }],
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let activity = crate::tcx::Activity {
+            laps,
+            ..Default::default()
+        };
+        let summary = activity.summary();
+
+        let splits = activity.lap_splits();
+        assert_eq!(splits.len(), 6);
+        for (i, split) in splits.iter().enumerate() {
+            let (climbed, descended) = if i % 2 == 0 {
+                (split.elevation_gain_meters, split.elevation_loss_meters)
+            } else {
+                (split.elevation_loss_meters, split.elevation_gain_meters)
+            };
+            assert!(climbed > 40.0 && descended < 5.0);
+        }
+        assert_eq!(splits[1].start_time, start + chrono::Duration::seconds(150));
+        let total = |value: fn(&crate::stats::Split) -> f64| splits.iter().map(value).sum::<f64>();
+        assert!(
+            (total(|split| split.elevation_gain_meters) - summary.elevation_gain_meters).abs()
+                < 1e-9
+        );
+        assert!(
+            (total(|split| split.elevation_loss_meters) - summary.elevation_loss_meters).abs()
+                < 1e-9
+        );
+        assert!((total(|split| split.distance_meters) - summary.distance_meters).abs() < 1e-9);
+        assert!((total(|split| split.elapsed_seconds) - summary.elapsed_seconds).abs() < 1e-9);
+
+        // By distance, each kilometer is one repeat, so climbs as much as it descends.
+        let splits = activity.distance_splits(1000.0);
+        assert_eq!(splits.len(), 3);
+        assert!(splits
+            .iter()
+            .all(|split| split.elevation_gain_meters > 40.0 && split.elevation_loss_meters > 40.0));
+        assert!(activity.distance_splits(0.0).is_empty());
+    }
+
+    #[test]
+    fn course_directions() {
+        use crate::directions::DirectionProblem;
+        use crate::tcx::CoursePointType;
+
+        // North for 500 m, then a right turn to the east for 500 m.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-06-01T08:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let step = 0.00009;
+        let position = |i: i64| {
+            let north = i.min(50) as f64;
+            let east = (i - 50).max(0) as f64;
+            crate::tcx::Position {
+                latitude: 40.0 + north * step,
+                longitude: -105.0 + east * step / 40f64.to_radians().cos(),
+            }
+        };
+        let trackpoints = (0..=100)
+            .map(|i| crate::tcx::Trackpoint {
+                time: start + chrono::Duration::seconds(i * 3),
+                position: Some(position(i)),
+                altitude_meters: None,
+                distance_meters: None,
+                heart_rate: None,
+                cadence: None,
+                extensions: None,
+            })
+            .collect();
+        let course_point = |i: i64, point_type| crate::tcx::CoursePoint {
+            name: None,
+            time: start + chrono::Duration::seconds(i * 3),
+            position: Some(position(i)),
+            altitude_meters: None,
+            point_type: Some(point_type),
+            notes: None,
+            extensions: None,
+        };
+        let mut far = course_point(80, CoursePointType::Left);
+        far.position.as_mut().unwrap().latitude += 0.01;
+        let mut course = crate::tcx::Course {
+            tracks: Some(vec![crate::tcx::Track { trackpoints }]),
+            course_points: vec![
+                course_point(50, CoursePointType::Right),
+                course_point(50, CoursePointType::Left),
+                course_point(50, CoursePointType::Straight),
+                course_point(20, CoursePointType::Right),
+                course_point(20, CoursePointType::Straight),
+                course_point(30, CoursePointType::Summit),
+                far,
+            ],
+            ..Default::default()
+        };
+
+        let mismatches = course.validate_direction();
+        let problems: Vec<(usize, DirectionProblem)> = mismatches
+            .iter()
+            .map(|mismatch| (mismatch.course_point, mismatch.problem))
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                (1, DirectionProblem::OppositeTurn),
+                (2, DirectionProblem::UnexpectedTurn),
+                (3, DirectionProblem::NoTurn),
+                (6, DirectionProblem::OffTrack),
+            ]
+        );
+        assert!((mismatches[0].turn_degrees.unwrap() - 90.0).abs() < 5.0);
+        assert!(mismatches[3].turn_degrees.is_none());
+        assert!(mismatches[3].distance_from_track_meters > 1000.0);
+
+        // Reversing the track, but not the course points, turns the right turn into a left one.
+        let track = &mut course.tracks.as_mut().unwrap()[0];
+        let times: Vec<_> = track.trackpoints.iter().map(|point| point.time).collect();
+        track.trackpoints.reverse();
+        for (point, time) in track.trackpoints.iter_mut().zip(times) {
+            point.time = time;
+        }
+        course.course_points.truncate(1);
+        let mismatches = course.validate_direction();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].problem, DirectionProblem::OppositeTurn);
+        assert!(mismatches[0].turn_degrees.unwrap() < -85.0);
+    }
+
+    #[test]
+    fn storage_sources_and_sinks() {
+        use crate::storage::{read_from, Stream, TcxSink, TcxSource};
+
+        // An embedder's own storage: a map of keys to documents, as a wasm host or mobile app might supply.
+        struct Blob<'a> {
+            store: &'a mut std::collections::HashMap<String, Vec<u8>>,
+            key: String,
+        }
+        impl TcxSource for Blob<'_> {
+            fn open(&mut self) -> std::io::Result<Box<dyn std::io::Read + '_>> {
+                match self.store.get(&self.key) {
+                    Some(bytes) => Ok(Box::new(bytes.as_slice())),
+                    None => Err(std::io::ErrorKind::NotFound.into()),
+                }
+            }
+        }
+        impl TcxSink for Blob<'_> {
+            fn create(&mut self) -> std::io::Result<Box<dyn std::io::Write + '_>> {
+                let bytes = self.store.entry(self.key.clone()).or_default();
+                bytes.clear();
+                Ok(Box::new(bytes))
+            }
+        }
+
+        let tcx = read_from(
+            &mut std::path::PathBuf::from("tests/20210308_virtual_ride_with_power.tcx"),
+            &Default::default(),
+        )
+        .unwrap();
+        let mut store = std::collections::HashMap::new();
+        let mut blob = Blob {
+            store: &mut store,
+            key: "ride".to_string(),
+        };
+        tcx.write_to(&mut blob, &Default::default()).unwrap();
+        let reread = read_from(&mut blob, &Default::default()).unwrap();
+        assert_eq!(
+            reread.activities.unwrap().activities[0].summary(),
+            tcx.activities.as_ref().unwrap().activities[0].summary()
+        );
+        assert!(store["ride"].starts_with(b"<?xml"));
+        let mut missing = Blob {
+            store: &mut store,
+            key: "missing".to_string(),
+        };
+        assert!(read_from(&mut missing, &Default::default()).is_err());
+
+        // Writing to a vector replaces what was there; a stream carries on.
+        let mut bytes = b"stale".to_vec();
+        tcx.write_to(&mut bytes, &Default::default()).unwrap();
+        assert!(bytes.starts_with(b"<?xml"));
+        let mut stream = Stream(b"prefix\n".to_vec());
+        tcx.write_json_to(&mut stream, &Default::default()).unwrap();
+        assert!(stream.0.starts_with(b"prefix\n{"));
+        let mut stream = Stream(std::io::Cursor::new(bytes.clone()));
+        let from_stream = read_from(&mut stream, &Default::default()).unwrap();
+        assert_eq!(
+            from_stream.activities.unwrap().activities[0]
+                .trackpoints()
+                .count(),
+            1434
+        );
+        assert_eq!(
+            read_from(&mut bytes.as_slice(), &Default::default())
+                .unwrap()
+                .activities
+                .unwrap()
+                .activities
+                .len(),
+            1
+        );
+
+        // The other formats go to the same sinks.
+        let mut gpx = Vec::new();
+        tcx.to_gpx(&mut gpx).unwrap();
+        let mut blob = Blob {
+            store: &mut store,
+            key: "ride.gpx".to_string(),
+        };
+        tcx.write_gpx_to(&mut blob).unwrap();
+        assert_eq!(store["ride.gpx"], gpx);
+        let mut csv = b"stale".to_vec();
+        tcx.write_activity_csv_to(&mut csv).unwrap();
+        assert!(csv.starts_with(b"activity_id,"));
+        let workout = crate::tcx::read_file("tests/20210602_workout_intervals.tcx").unwrap();
+        let workout =
+            crate::workout::StructuredWorkout::from(&workout.workouts.unwrap().workouts[0]);
+        let mut erg = Vec::new();
+        workout.write_erg_to(&mut erg, 250).unwrap();
+        assert_eq!(String::from_utf8(erg).unwrap(), workout.to_erg(250));
+    }
+
+    #[test]
+    fn reduced_position_precision() {
+        let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let count = tcx.activities.as_ref().unwrap().activities[0]
+            .trackpoints()
+            .filter(|point| point.position.is_some())
+            .count();
+        tcx.reduce_position_precision(4);
+        let activity = &tcx.activities.as_ref().unwrap().activities[0];
+        let positions: Vec<_> = activity
+            .trackpoints()
+            .filter_map(|point| point.position.as_ref())
+            .collect();
+        assert_eq!(positions.len(), count);
+        assert_eq!(positions[0].latitude, 28.0784);
+        assert_eq!(positions[0].longitude, -80.604);
+        assert!(positions.iter().all(|position| {
+            let scaled = position.longitude * 1e4;
+            (scaled - scaled.round()).abs() < 1e-6
+        }));
+
+        let mut tcx = crate::tcx::read_file("tests/20210601_course_lake_loop.tcx").unwrap();
+        tcx.reduce_position_precision(2);
+        let course = &tcx.courses.as_ref().unwrap().courses[0];
+        let positions = course
+            .tracks
+            .iter()
+            .flatten()
+            .flat_map(|track| track.trackpoints.iter())
+            .filter_map(|point| point.position.as_ref())
+            .chain(
+                course
+                    .course_points
+                    .iter()
+                    .filter_map(|point| point.position.as_ref()),
+            )
+            .chain(
+                course
+                    .laps
+                    .iter()
+                    .filter_map(|lap| lap.begin_position.as_ref()),
+            );
+        for position in positions {
+            assert_eq!(
+                position.latitude,
+                (position.latitude * 100.0).trunc() / 100.0
+            );
+            assert_eq!(
+                position.longitude,
+                (position.longitude * 100.0).trunc() / 100.0
+            );
+        }
+
+        let mut tcx = crate::tcx::read_file("tests/20210704_triathlon_multisport.tcx").unwrap();
+        tcx.reduce_position_precision(1);
+        let session = &tcx.activities.as_ref().unwrap().multi_sport_sessions[0];
+        let mut checked = 0;
+        for position in session
+            .activities()
+            .flat_map(|leg| leg.trackpoints())
+            .chain(session.transitions().flat_map(|lap| lap.trackpoints()))
+            .filter_map(|point| point.position.as_ref())
+        {
+            assert_eq!(position.latitude, (position.latitude * 10.0).trunc() / 10.0);
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn bulk_metrics() {
+        use crate::metrics::{compute_metrics_bulk, MetricId};
+
+        let activities: Vec<crate::tcx::Activity> = [
+            "tests/20210119_run_garmin_fenix6.tcx",
+            "tests/20210308_virtual_ride_with_power.tcx",
+            "tests/20210323_yoga.tcx",
+        ]
+        .iter()
+        .flat_map(|file| {
+            crate::tcx::read_file(file)
+                .unwrap()
+                .activities
+                .unwrap()
+                .activities
+        })
+        .collect();
+        let table = compute_metrics_bulk(&activities, &MetricId::ALL);
+        assert_eq!(table.rows.len(), activities.len());
+        for (i, activity) in activities.iter().enumerate() {
+            let summary = activity.summary();
+            assert_eq!(table.rows[i].activity_id, activity.id);
+            assert_eq!(
+                table.get(i, MetricId::DistanceMeters),
+                Some(summary.distance_meters)
+            );
+            assert_eq!(
+                table.get(i, MetricId::NormalizedPower),
+                summary.normalized_power
+            );
+            assert_eq!(table.get(i, MetricId::MaxHeartRate), summary.max_heart_rate);
+            assert_eq!(
+                table.get(i, MetricId::Trackpoints),
+                Some(activity.trackpoints().count() as f64)
+            );
+        }
+
+        // Only the metrics asked for, in the order asked for.
+        let table =
+            compute_metrics_bulk(&activities, &[MetricId::MaxPower, MetricId::ElapsedSeconds]);
+        assert_eq!(table.get(1, MetricId::MaxPower), Some(462.0));
+        assert!(table.get(1, MetricId::DistanceMeters).is_none());
+        let mut csv = Vec::new();
+        table.to_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "activity_id,max_power,elapsed_seconds");
+        assert_eq!(lines.len(), activities.len() + 1);
+        assert!(lines[1].starts_with(&format!("{},,", activities[0].id)));
+    }
+
+    #[test]
+    fn trackpoint_policy() {
+        use crate::tcx::{ReadOptions, TrackpointPolicy};
+
+        let read = |file: &str, policy| {
+            let options = ReadOptions {
+                trackpoint_policy: policy,
+                original: crate::tcx::OriginalRetention::Bytes,
+                ..Default::default()
+            };
+            crate::tcx::read_file_with_options(file, &options).unwrap()
+        };
+        let file = "tests/20210308_virtual_ride_with_power.tcx";
+        let all = read(file, TrackpointPolicy::All);
+        let counts: Vec<usize> = all.activities.as_ref().unwrap().activities[0]
+            .laps
+            .iter()
+            .flat_map(|lap| lap.tracks.iter().map(|track| track.trackpoints.len()))
+            .collect();
+
+        // Lap summaries survive without any Trackpoints, and the original is untouched.
+        let summaries = read(file, TrackpointPolicy::None);
+        let activity = &summaries.activities.as_ref().unwrap().activities[0];
+        assert_eq!(activity.trackpoints().count(), 0);
+        let original = &all.activities.as_ref().unwrap().activities[0];
+        assert_eq!(activity.laps.len(), original.laps.len());
+        assert_eq!(
+            activity.laps[0].total_time_seconds,
+            original.laps[0].total_time_seconds
+        );
+        assert_eq!(summaries.original, all.original);
+
+        // Every tenth Trackpoint of each track, starting with the first.
+        let sampled = read(file, TrackpointPolicy::EveryNth(10));
+        let sampled = &sampled.activities.as_ref().unwrap().activities[0];
+        let expected: usize = counts.iter().map(|count| count.div_ceil(10)).sum();
+        assert_eq!(sampled.trackpoints().count(), expected);
+        let originals: Vec<_> = original.trackpoints().map(|point| point.time).collect();
+        let kept: Vec<_> = sampled.trackpoints().map(|point| point.time).collect();
+        assert_eq!(kept[0], originals[0]);
+        assert_eq!(kept[1], originals[10]);
+        let everything = read(file, TrackpointPolicy::EveryNth(1));
+        assert_eq!(
+            everything.activities.unwrap().activities[0]
+                .trackpoints()
+                .count(),
+            originals.len()
+        );
+
+        // Courses, self-closing Trackpoints and lenient reading.
+        let course = read(
+            "tests/20210601_course_lake_loop.tcx",
+            TrackpointPolicy::None,
+        );
+        assert!(course.courses.unwrap().courses[0]
+            .tracks
+            .iter()
+            .flatten()
+            .all(|track| track.trackpoints.is_empty()));
+        let xml = r#"<TrainingCenterDatabase><Activities><Activity Sport="Running"><Id>2021-06-01T06:00:00Z</Id>
+            <Lap StartTime="2021-06-01T06:00:00Z"><TotalTimeSeconds>2</TotalTimeSeconds><DistanceMeters>5</DistanceMeters>
+            <Calories>0</Calories><Track><Trackpoint/><Trackpoint><Time>2021-06-01T06:00:01Z</Time></Trackpoint>
+            <Trackpoint><Time>2021-06-01T06:00:02Z</Time></Trackpoint></Track></Lap></Activity></Activities>
+            </TrainingCenterDatabase>"#;
+        let options = ReadOptions {
+            trackpoint_policy: TrackpointPolicy::EveryNth(2),
+            lenient: true,
+            ..Default::default()
+        };
+        let tcx =
+            crate::tcx::read_with_options(&mut std::io::BufReader::new(xml.as_bytes()), &options)
+                .unwrap();
+        let points: Vec<_> = tcx.activities.unwrap().activities[0]
+            .trackpoints()
+            .map(|point| point.time.timestamp() % 60)
+            .collect();
+        assert_eq!(points, vec![2]);
+
+        // Prefixed Trackpoints are filtered like any other, and comments and CDATA that look like Trackpoints are
+        // left alone.
+        let xml = r#"<tcx:TrainingCenterDatabase xmlns:tcx="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+            <tcx:Activities><tcx:Activity Sport="Running"><tcx:Id>2021-06-01T06:00:00Z</tcx:Id>
+            <tcx:Lap StartTime="2021-06-01T06:00:00Z"><tcx:TotalTimeSeconds>3</tcx:TotalTimeSeconds>
+            <tcx:DistanceMeters>5</tcx:DistanceMeters><tcx:Calories>0</tcx:Calories><tcx:Track>
+            <!-- <Trackpoint><Time>2021-06-01T06:00:09Z</Time></Trackpoint> -->
+            <tcx:Trackpoint><tcx:Time>2021-06-01T06:00:01Z</tcx:Time></tcx:Trackpoint>
+            <tcx:Trackpoint><tcx:Time>2021-06-01T06:00:02Z</tcx:Time></tcx:Trackpoint>
+            <tcx:Trackpoint><tcx:Time>2021-06-01T06:00:03Z</tcx:Time></tcx:Trackpoint>
+            </tcx:Track></tcx:Lap><tcx:Notes><![CDATA[</Trackpoint><Trackpoint>]]></tcx:Notes>
+            </tcx:Activity></tcx:Activities></tcx:TrainingCenterDatabase>"#;
+        type Reader =
+            fn(&[u8], &ReadOptions) -> Result<crate::TrainingCenterDatabase, crate::Error>;
+        let readers: Vec<Reader> = vec![
+            |xml, options| {
+                crate::tcx::read_with_options(&mut std::io::BufReader::new(xml), options)
+            },
+            |xml, options| {
+                let options = ReadOptions {
+                    lenient: true,
+                    ..*options
+                };
+                crate::tcx::read_with_options(&mut std::io::BufReader::new(xml), &options)
+            },
+            #[cfg(feature = "quick_xml")]
+            |xml, options| {
+                crate::quick::read_with_options(&mut std::io::BufReader::new(xml), options)
+            },
+        ];
+        for read in &readers {
+            for (policy, expected) in [
+                (TrackpointPolicy::All, vec![1, 2, 3]),
+                (TrackpointPolicy::EveryNth(2), vec![1, 3]),
+                (TrackpointPolicy::None, vec![]),
+            ] {
+                let options = ReadOptions {
+                    trackpoint_policy: policy,
+                    ..Default::default()
+                };
+                let tcx = read(xml.as_bytes(), &options).unwrap();
+                let activity = &tcx.activities.unwrap().activities[0];
+                let points: Vec<_> = activity
+                    .trackpoints()
+                    .map(|point| point.time.timestamp() % 60)
+                    .collect();
+                assert_eq!(points, expected);
+                assert_eq!(activity.notes.as_deref(), Some("</Trackpoint><Trackpoint>"));
+            }
+        }
+
+        // Errors are located in the document as given, not with the skipped Trackpoints taken out.
+        let broken = xml.replace("</tcx:Notes>", "</tcx:Note>");
+        let line = broken
+            .lines()
+            .position(|line| line.contains("</tcx:Note>"))
+            .unwrap() as u64
+            + 1;
+        let options = ReadOptions {
+            trackpoint_policy: TrackpointPolicy::None,
+            ..Default::default()
+        };
+        match crate::tcx::read_with_options(
+            &mut std::io::BufReader::new(broken.as_bytes()),
+            &options,
+        ) {
+            Err(crate::Error::Xml { line: at, .. }) => assert_eq!(at, line),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        #[cfg(feature = "quick_xml")]
+        match crate::quick::read_with_options(
+            &mut std::io::BufReader::new(broken.as_bytes()),
+            &options,
+        ) {
+            Err(crate::Error::Xml { line: at, .. }) => assert_eq!(at, line),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn schema_validation_on_write() {
+        use crate::validate::{Problem, Violation};
+
+        // Every sample file is valid as it is.
+        for file in [
+            "tests/20210119_run_garmin_fenix6.tcx",
+            "tests/20210308_virtual_ride_with_power.tcx",
+            "tests/20210601_course_lake_loop.tcx",
+            "tests/20210602_workout_intervals.tcx",
+            "tests/20210704_triathlon_multisport.tcx",
+        ] {
+            let tcx = crate::tcx::read_file(file).unwrap();
+            assert_eq!(tcx.validate(None), vec![], "{}", file);
+        }
+
+        let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+        activity.laps[0].tracks[0].trackpoints[3].cadence = Some(255);
+        activity.notes = Some("A long and rambling account of the run".to_string());
+        let violations = tcx.validate(Some(20));
+        assert_eq!(
+            violations,
+            vec![
+                Violation {
+                    path: "Activities/Activity[0]/Lap[0]/Track[0]/Trackpoint[3]/Cadence"
+                        .to_string(),
+                    problem: Problem::OutOfRange {
+                        value: 255.0,
+                        min: 0.0,
+                        max: 254.0
+                    },
+                },
+                Violation {
+                    path: "Activities/Activity[0]/Notes".to_string(),
+                    problem: Problem::TooLong {
+                        length: 38,
+                        max: 20
+                    },
+                },
+            ]
+        );
+
+        // Writing fails with the first violation, and writes nothing.
+        let mut xml = Vec::new();
+        let err = tcx.to_writer(&mut xml).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("Trackpoint[3]/Cadence is 255"));
+        assert!(err.get_ref().unwrap().downcast_ref::<Violation>().is_some());
+        assert!(xml.is_empty());
+        let options = crate::tcx::WriteOptions {
+            validate: false,
+            ..Default::default()
+        };
+        tcx.to_writer_with_options(&mut xml, &options).unwrap();
+        assert!(!xml.is_empty());
+
+        // Course and course point names.
+        let mut tcx = crate::tcx::read_file("tests/20210601_course_lake_loop.tcx").unwrap();
+        let course = &mut tcx.courses.as_mut().unwrap().courses[0];
+        course.name = String::new();
+        course.course_points[0].name = Some("Turn left at the lake".to_string());
+        let paths: Vec<String> = tcx
+            .validate(None)
+            .into_iter()
+            .map(|violation| violation.path)
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "Courses/Course[0]/Name",
+                "Courses/Course[0]/CoursePoint[0]/Name"
+            ]
+        );
+
+        // Course laps' positions and course points without one.
+        let mut tcx = crate::tcx::read_file("tests/20210601_course_lake_loop.tcx").unwrap();
+        let course = &mut tcx.courses.as_mut().unwrap().courses[0];
+        course.laps[0].begin_position = Some(crate::tcx::Position {
+            latitude: 0.0,
+            longitude: 200.0,
+        });
+        course.course_points[1].position = None;
+        let violations = tcx.validate(None);
+        let paths: Vec<&str> = violations
+            .iter()
+            .map(|violation| violation.path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "Courses/Course[0]/Lap[0]/BeginPosition/LongitudeDegrees",
+                "Courses/Course[0]/CoursePoint[1]/Position"
+            ]
+        );
+        assert_eq!(violations[1].problem, Problem::Missing);
+        assert_eq!(
+            violations[1].to_string(),
+            "Courses/Course[0]/CoursePoint[1]/Position is missing"
+        );
+
+        // Building checks the activity too.
+        let start = chrono::Utc::now();
+        let result = crate::builder::ActivityBuilder::new("Running")
+            .lap(
+                crate::builder::LapBuilder::new()
+                    .trackpoint(
+                        crate::builder::TrackpointBuilder::new(start)
+                            .position(91.0, 0.0)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        match result {
+            Err(crate::builder::BuildError::Invalid(violation)) => {
+                assert_eq!(
+                    violation.path,
+                    "Lap[0]/Track[0]/Trackpoint[0]/Position/LatitudeDegrees"
+                )
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn course_names() {
+        use crate::validate::{Problem, Violation};
+
+        let mut tcx = crate::tcx::read_file("tests/20210601_course_lake_loop.tcx").unwrap();
+        let courses = tcx.courses.as_mut().unwrap();
+        assert_eq!(courses.names().collect::<Vec<_>>(), vec!["Lake Loop"]);
+        assert!(courses.get(" Lake Loop ").is_some());
+
+        let mut hills = crate::tcx::Course::new("Hill Repeats on Main St");
+        assert_eq!(hills.name, "Hill Repeats on");
+        assert!(!hills.set_name("Hill Repeats"));
+        hills.notes = Some("Five times up".to_string());
+        assert!(courses.insert(hills.clone()).is_none());
+        assert_eq!(
+            courses.names().collect::<Vec<_>>(),
+            vec!["Lake Loop", "Hill Repeats"]
+        );
+
+        // Inserting under an existing name replaces that course.
+        hills.notes = Some("Eight times up".to_string());
+        let replaced = courses.insert(hills).unwrap();
+        assert_eq!(replaced.notes.as_deref(), Some("Five times up"));
+        assert_eq!(courses.courses.len(), 2);
+        courses.get_mut("Hill Repeats").unwrap().notes = None;
+        assert_eq!(courses.courses[1].notes, None);
+        assert!(tcx.validate(None).is_empty());
+
+        // Duplicates pushed directly are reported.
+        let courses = tcx.courses.as_mut().unwrap();
+        courses.courses.push(crate::tcx::Course::new("Lake Loop"));
+        assert_eq!(
+            tcx.validate(None),
+            vec![Violation {
+                path: "Courses/Course[2]/Name".to_string(),
+                problem: Problem::Duplicate {
+                    name: "Lake Loop".to_string()
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn activities_by_sport() {
+        use crate::sport::Sport;
+
+        let tcx = crate::tcx::read_file("tests/20210704_triathlon_multisport.tcx").unwrap();
+        let mut activities = tcx.activities.unwrap();
+        assert_eq!(
+            activities.sports_present(),
+            vec![Sport::Running, Sport::Biking, Sport::Other]
+        );
+
+        let run = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let mut run = run.activities.unwrap().activities.remove(0);
+        run.sport = "running".to_string();
+        activities.activities.push(run);
+        let runs: Vec<&crate::tcx::Activity> = activities.by_sport(Sport::Running).collect();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].sport, "running");
+        assert_eq!(runs[1].sport, "Running");
+
+        activities.multi_sport_sessions.clear();
+        assert_eq!(activities.sports_present(), vec![Sport::Running]);
+        assert_eq!(activities.by_sport(Sport::Biking).count(), 0);
+        assert_eq!(Sport::Biking.to_string(), "Biking");
+    }
+
+    #[test]
+    fn training_log_rollup() {
+        use crate::training_log::{rollup, Period};
+
+        let run = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let ride = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let run = run.activities.unwrap().activities.remove(0);
+        let ride = ride.activities.unwrap().activities.remove(0);
+        let mut second_run = run.clone();
+        let days = chrono::Duration::days(3);
+        for point in second_run.trackpoints_mut() {
+            point.time += days;
+        }
+        let activities = vec![
+            ride.clone(),
+            run.clone(),
+            second_run,
+            crate::tcx::Activity::default(),
+        ];
+
+        // Tuesday the 19th and Friday the 22nd fall in the week of Monday the 18th; the ride is seven weeks later.
+        let weeks = rollup(&activities, Period::Week, None);
+        assert_eq!(weeks.len(), 8);
+        assert_eq!(weeks[0].period_start.to_string(), "2021-01-18");
+        assert_eq!(weeks[0].sessions, 2);
+        assert!((weeks[0].distance_meters - 2.0 * run.summary().distance_meters).abs() < 1e-6);
+        assert_eq!(weeks[0].training_stress_score, None);
+        assert!(weeks[1..7]
+            .iter()
+            .all(|week| week.sessions == 0 && week.distance_meters == 0.0));
+        assert_eq!(weeks[7].period_start.to_string(), "2021-03-08");
+        assert_eq!(weeks[7].elapsed_seconds, ride.summary().elapsed_seconds);
+
+        let months = rollup(&activities, Period::Month, Some(250.0));
+        let starts: Vec<String> = months
+            .iter()
+            .map(|month| month.period_start.to_string())
+            .collect();
+        assert_eq!(starts, vec!["2021-01-01", "2021-02-01", "2021-03-01"]);
+        assert_eq!(months[0].training_stress_score, None);
+        assert_eq!(
+            months[2].training_stress_score,
+            ride.training_stress_score(250.0)
+        );
+        let json = serde_json::to_string(&months[2]).unwrap();
+        assert!(json.contains("\"period_start\":\"2021-03-01\""));
+
+        assert!(rollup(&[], Period::Week, None).is_empty());
+    }
+
+    #[test]
+    fn season_thresholds() {
+        use crate::thresholds::{season_thresholds, ThresholdOptions};
+
+        let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let ride = tcx.activities.unwrap().activities.remove(0);
+        let shifted = |days: i64, scale: f64| {
+            let mut activity = ride.clone();
+            activity.id = format!("+{}", days);
+            for point in activity.trackpoints_mut() {
+                point.time += chrono::Duration::days(days);
+                let tpx = point.extensions.as_mut().unwrap().tpx.as_mut().unwrap();
+                tpx.watts = tpx.watts.map(|watts| (watts as f64 * scale).round() as u16);
+            }
+            activity
+        };
+        let ftp = ride.estimated_ftp().unwrap();
+        assert!(ftp > 150.0 && ftp < 400.0);
+        assert!(ride.estimated_lthr().unwrap() > 100.0);
+
+        // An easy ride, a harder one, then, after the window has passed, a slightly easier one.
+        let activities = vec![
+            shifted(7, 1.1),
+            ride.clone(),
+            shifted(100, 1.08),
+            shifted(3, 0.8),
+        ];
+        let season = season_thresholds(&activities, &ThresholdOptions::default());
+        let ids: Vec<&str> = season
+            .iter()
+            .map(|estimate| estimate.activity_id.as_str())
+            .collect();
+        assert_eq!(ids, vec![ride.id.as_str(), "+3", "+7", "+100"]);
+        assert_eq!(season[0].ftp, Some(ftp));
+        assert_eq!(season[1].ftp, Some(ftp));
+        assert!(!season[1].ftp_changed);
+        assert!(season[2].ftp.unwrap() > ftp * 1.09);
+        assert!(season[2].ftp_changed);
+        assert!(season[3].ftp.unwrap() < season[2].ftp.unwrap());
+        assert!(!season[3].ftp_changed);
+        assert!(season.iter().all(|estimate| !estimate.lthr_changed));
+
+        // A run without power still tracks LTHR.
+        let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let run = &tcx.activities.unwrap().activities[0];
+        assert_eq!(run.estimated_ftp(), None);
+        assert!(run.estimated_lthr().is_some());
+        let season = season_thresholds(std::slice::from_ref(run), &ThresholdOptions::default());
+        assert_eq!(season[0].ftp, None);
+        assert_eq!(season[0].lthr, run.estimated_lthr());
+    }
+
+    #[test]
+    fn power_baseline_drift() {
+        use crate::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+
+        // A minute of pedalling on the flat, half a minute coasting down a 5% grade, then pedalling again.
+        let ride = |coasting_watts: u16| {
+            let start = chrono::Utc::now();
+            let mut lap = LapBuilder::new();
+            let mut altitude = 100.0;
+            for i in 0..120 {
+                let coasting = (60..90).contains(&i);
+                if coasting {
+                    altitude -= 0.5;
+                }
+                let (cadence, watts) = match i {
+                    60 => (0, 180),
+                    _ if coasting => (0, coasting_watts),
+                    _ => (90, 200),
+                };
+                lap = lap.trackpoint(
+                    TrackpointBuilder::new(start + chrono::Duration::seconds(i))
+                        .distance(i as f64 * 10.0)
+                        .altitude(altitude)
+                        .cadence(cadence)
+                        .watts(watts)
+                        .build(),
+                );
+            }
+            ActivityBuilder::new("Biking")
+                .lap(lap.build())
+                .build()
+                .unwrap()
+        };
+
+        let drift = ride(12).detect_power_baseline_drift(5.0).unwrap();
+        assert_eq!(drift.segments.len(), 1);
+        assert_eq!(drift.average_watts, 12.0);
+        assert_eq!(drift.segments[0].average_watts, 12.0);
+        assert!(drift.coasting_seconds >= 20.0 && drift.coasting_seconds < 30.0);
+        assert!(drift.drifted);
+
+        let drift = ride(0).detect_power_baseline_drift(5.0).unwrap();
+        assert_eq!(drift.average_watts, 0.0);
+        assert!(!drift.drifted);
+
+        // No descents, so nothing to judge by.
+        let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        assert_eq!(
+            tcx.activities.unwrap().activities[0].detect_power_baseline_drift(5.0),
+            None
+        );
+    }
+
+    #[test]
+    fn pauses_from_cadence() {
+        use crate::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+
+        // A trainer ride without speed or distance: pedalling, a half minute stop during which the cadence sensor
+        // drops out twice, more pedalling, then two seconds of freewheeling at the end.
+        let start = chrono::Utc::now();
+        let mut lap = LapBuilder::new();
+        for i in 0..122 {
+            let point = TrackpointBuilder::new(start + chrono::Duration::seconds(i));
+            let point = match i {
+                70 | 71 => point,
+                60..=89 | 120.. => point.cadence(0),
+                _ => point.cadence(90),
+            };
+            lap = lap.trackpoint(point.build());
+        }
+        let activity = ActivityBuilder::new("Biking")
+            .lap(lap.build())
+            .build()
+            .unwrap();
+
+        let pauses = activity.reconstruct_pauses_from_cadence(chrono::Duration::seconds(10));
+        assert_eq!(pauses.len(), 1);
+        assert_eq!(pauses[0].start_time, start + chrono::Duration::seconds(59));
+        assert_eq!(pauses[0].end_time, start + chrono::Duration::seconds(89));
+        assert_eq!(pauses[0].duration_seconds, 30.0);
+        let pauses = activity.reconstruct_pauses_from_cadence(chrono::Duration::seconds(1));
+        assert_eq!(pauses.len(), 2);
+        assert_eq!(pauses[1].duration_seconds, 2.0);
+
+        let summary = activity.summary();
+        assert_eq!(summary.elapsed_seconds, 121.0);
+        assert_eq!(summary.moving_seconds, 89.0);
+        assert_eq!(summary.average_speed, None);
+    }
+
+    #[test]
+    fn sampling_report() {
+        use crate::sampling::Channel;
+
+        let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let mut activity = tcx.activities.unwrap().activities.remove(0);
+        let report = activity.sampling_report();
+        assert_eq!(report.trackpoints, 1232);
+        assert_eq!(report.median_interval_seconds, Some(1.0));
+        assert!(!report.smart_recording);
+        assert!(!report.needs_resampling);
+        let heart_rate = report
+            .channels
+            .iter()
+            .find(|sampling| sampling.channel == Channel::HeartRate)
+            .unwrap();
+        assert_eq!(heart_rate.samples, 1232);
+        assert!(heart_rate.dropouts.is_empty());
+        assert!(report
+            .channels
+            .iter()
+            .all(|sampling| sampling.channel != Channel::Power));
+
+        // The heart rate strap drops out for ten seconds.
+        let times: Vec<chrono::DateTime<chrono::Utc>> =
+            activity.trackpoints().map(|point| point.time).collect();
+        for point in activity.trackpoints_mut().skip(101).take(9) {
+            point.heart_rate = None;
+        }
+        let report = activity.sampling_report();
+        assert!(report.needs_resampling);
+        let heart_rate = report
+            .channels
+            .iter()
+            .find(|sampling| sampling.channel == Channel::HeartRate)
+            .unwrap();
+        assert_eq!(heart_rate.max_interval_seconds, Some(10.0));
+        assert_eq!(heart_rate.dropouts.len(), 1);
+        assert_eq!(heart_rate.dropouts[0].start_time, times[100]);
+        assert_eq!(heart_rate.dropouts[0].end_time, times[110]);
+
+        // Smart recording keeps only every third Trackpoint or so.
+        for lap in activity.laps.iter_mut() {
+            for track in lap.tracks.iter_mut() {
+                let mut i = 0;
+                track.trackpoints.retain(|_| {
+                    i += 1;
+                    i % 3 == 1
+                });
+            }
+        }
+        let report = activity.sampling_report();
+        assert!(report.smart_recording);
+        assert!(report.needs_resampling);
+        assert!(report.median_interval_seconds.unwrap() >= 3.0);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_json_export() {
+        use crate::encryption::{generate_key, DecryptionError};
+        use crate::tcx::{JsonOptions, TrainingCenterDatabase};
+
+        let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let key = generate_key();
+        let export = tcx
+            .to_json_encrypted(&key, &JsonOptions::default())
+            .unwrap();
+        assert!(export.starts_with(b"TCXJ\x01"));
+        assert!(!String::from_utf8_lossy(&export).contains("Running"));
+
+        // The same document encrypts differently each time.
+        assert_ne!(
+            export,
+            tcx.to_json_encrypted(&key, &JsonOptions::default())
+                .unwrap()
+        );
+
+        let again = TrainingCenterDatabase::from_json_encrypted(&export, &key).unwrap();
+        let activity = &again.activities.unwrap().activities[0];
+        let original = &tcx.activities.as_ref().unwrap().activities[0];
+        assert_eq!(activity.id, original.id);
+        assert_eq!(
+            activity.trackpoints().count(),
+            original.trackpoints().count()
+        );
+
+        let mut sink = Vec::new();
+        tcx.write_json_encrypted_to(&mut sink, &key, &JsonOptions::default())
+            .unwrap();
+        assert!(TrainingCenterDatabase::read_json_encrypted(&mut sink.as_slice(), &key).is_ok());
+
+        let rejected = |export: &[u8], key: &[u8; 32]| {
+            let err = TrainingCenterDatabase::from_json_encrypted(export, key).unwrap_err();
+            err.downcast_ref::<DecryptionError>().cloned()
+        };
+        assert_eq!(
+            rejected(&export, &generate_key()),
+            Some(DecryptionError::Rejected)
+        );
+        let mut tampered = export.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(rejected(&tampered, &key), Some(DecryptionError::Rejected));
+        assert_eq!(rejected(b"{}", &key), Some(DecryptionError::NotEncrypted));
+    }
+
+    #[test]
+    fn archive_manifest() {
+        use crate::manifest::Manifest;
+
+        let dir = std::env::temp_dir().join(format!("tcx_archive_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // Two copies of the same run end up in separate files with the same fingerprint.
+        let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let activities = tcx.activities.as_mut().unwrap();
+        activities.activities.push(activities.activities[0].clone());
+        let triathlon = crate::tcx::read_file("tests/20210704_triathlon_multisport.tcx").unwrap();
+        activities.multi_sport_sessions = triathlon.activities.unwrap().multi_sport_sessions;
+        tcx.courses = crate::tcx::read_file("tests/20210601_course_lake_loop.tcx")
+            .unwrap()
+            .courses;
+
+        let manifest = tcx.export_with_manifest(&dir).unwrap();
+        let names: Vec<&str> = manifest
+            .entries
+            .iter()
+            .map(|entry| entry.file_name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "20210119T233050Z_running.tcx",
+                "20210119T233050Z_running-2.tcx",
+                "20210704T070000Z_multisport.tcx",
+                "courses.tcx",
+            ]
+        );
+        assert_eq!(
+            manifest.entries[0].fingerprint,
+            manifest.entries[1].fingerprint
+        );
+        assert_eq!(manifest.entries[3].fingerprint, None);
+        assert_eq!(Manifest::read(&dir).unwrap(), manifest);
+        assert!(manifest.verify(&dir).is_empty());
+
+        // Each file reads back with the same fingerprint.
+        let run = crate::tcx::read_file(dir.join(names[0]).to_str().unwrap()).unwrap();
+        assert_eq!(
+            Some(run.activities.unwrap().activities[0].fingerprint()),
+            manifest.entries[0].fingerprint
+        );
+
+        // Damage and loss are both reported.
+        std::fs::write(dir.join(names[1]), b"<TrainingCenterDatabase/>").unwrap();
+        std::fs::remove_file(dir.join("courses.tcx")).unwrap();
+        assert_eq!(manifest.verify(&dir), vec![names[1], "courses.tcx"]);
+
+        // Only the multisport session is new compared with an archive of the run.
+        let mut synced = manifest.clone();
+        synced
+            .entries
+            .retain(|entry| entry.file_name.contains("running"));
+        let new: Vec<&str> = manifest
+            .new_since(&synced)
+            .iter()
+            .map(|entry| entry.file_name.as_str())
+            .collect();
+        assert_eq!(new, vec!["20210704T070000Z_multisport.tcx", "courses.tcx"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corpus_check() {
+        use crate::corpus::{check, Outcome};
+
+        let dir = std::env::temp_dir().join(format!("tcx_corpus_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("old")).unwrap();
+        std::fs::copy(
+            "tests/20210601_course_lake_loop.tcx",
+            dir.join("course.tcx"),
+        )
+        .unwrap();
+        std::fs::copy(
+            "tests/20210119_run_garmin_fenix6.tcx",
+            dir.join("old").join("run.TCX"),
+        )
+        .unwrap();
+        // Missing Calories and a time without a time zone only read leniently.
+        std::fs::write(
+            dir.join("app_export.tcx"),
+            r#"<TrainingCenterDatabase><Activities><Activity Sport="Running"><Id>1</Id>
+            <Lap StartTime="2021-01-19T23:30:50"><TotalTimeSeconds>1</TotalTimeSeconds>
+            <DistanceMeters>3</DistanceMeters><Track><Trackpoint><Time>2021-01-19T23:30:50</Time>
+            <Vendor>x</Vendor></Trackpoint></Track></Lap></Activity></Activities></TrainingCenterDatabase>"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("truncated.tcx"),
+            "<TrainingCenterDatabase><Activities>",
+        )
+        .unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a TCX file").unwrap();
+
+        let report = check(&dir).unwrap();
+        let names: Vec<String> = report
+            .files
+            .iter()
+            .map(|file| file.path.strip_prefix(&dir).unwrap().display().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "app_export.tcx",
+                "course.tcx",
+                "old/run.TCX",
+                "truncated.tcx"
+            ]
+        );
+        let outcomes: Vec<Outcome> = report.files.iter().map(|file| file.outcome).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Lenient,
+                Outcome::Strict,
+                Outcome::Strict,
+                Outcome::Failed
+            ]
+        );
+        assert_eq!(report.count(Outcome::Strict), 2);
+
+        let app_export = &report.files[0];
+        assert!(app_export.strict_error.is_some());
+        assert_eq!(app_export.lenient_error, None);
+        assert!(!app_export.repairs.is_empty());
+        assert_eq!(app_export.unknown_elements.get("Vendor"), Some(&1));
+
+        assert!(report.files[1].unknown_elements.is_empty());
+        // Activity lap heart rate summaries are read from `AverageHeartRateBpm` and `MaximumHeartRateBpm`.
+        assert_eq!(
+            report.files[2].unknown_elements.get("AverageHeartRateBpm"),
+            None
+        );
+        assert!(report.files[3].lenient_error.is_some());
+        assert!(report.files[3].unknown_elements.is_empty());
+
+        assert_eq!(report.unknown_elements.get("Vendor"), Some(&1));
+        assert_eq!(report.unknown_elements.get("MaximumHeartRateBpm"), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn trackpoint_edits_refresh_lap_totals() {
+        let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let mut activity = tcx.activities.unwrap().activities.remove(0);
+        let calories = activity.laps[0].calories;
+        assert!(!activity.laps[0].summary_dirty);
+        assert!(!activity.set_trackpoint_hr(5000, Some(100.0)));
+
+        // Editing a heart rate only marks the lap; the totals are recomputed on request or when written.
+        assert!(activity.set_trackpoint_hr(0, Some(250.0)));
+        assert!(activity.laps[0].summary_dirty);
+        let mut xml = Vec::new();
+        crate::tcx::TrainingCenterDatabase {
+            activities: Some(crate::tcx::Activities {
+                activities: vec![activity.clone()],
+                multi_sport_sessions: Vec::new(),
+            }),
+            ..Default::default()
+        }
+        .to_writer(&mut xml)
+        .unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        let maximum = &xml[xml.find("<MaximumHeartRateBpm>").unwrap()..];
+        let maximum = &maximum[..maximum.find("</MaximumHeartRateBpm>").unwrap()];
+        assert!(maximum.contains("<Value>250</Value>"));
+        assert!(activity.laps[0].summary_dirty);
+        activity.refresh_summaries();
+        assert!(!activity.laps[0].summary_dirty);
+        assert_eq!(activity.laps[0].maximum_heart_rate, Some(250.0));
+
+        // Dropping the first half shortens the lap and scales its calories.
+        let count = activity.trackpoints().count();
+        let elapsed = activity.laps[0].total_time_seconds;
+        assert_eq!(activity.delete_range(0..count / 2), count / 2);
+        assert_eq!(activity.trackpoints().count(), count - count / 2);
+        activity.refresh_summaries();
+        let lap = &activity.laps[0];
+        let first = activity.trackpoints().next().unwrap();
+        assert_eq!(lap.start_time, Some(first.time));
+        assert!(lap.total_time_seconds < elapsed * 0.6);
+        assert!(lap.calories < calories);
+        assert_ne!(lap.maximum_heart_rate, Some(250.0));
+        assert_eq!(lap.distance_meters, activity.summary().distance_meters);
+
+        // Inserting goes in time order, and an empty activity gets a lap.
+        let mut point = first.clone();
+        point.time -= chrono::Duration::seconds(30);
+        assert_eq!(activity.insert_trackpoint(point.clone()), 0);
+        let mut later = activity.trackpoints().nth(10).unwrap().clone();
+        later.time += chrono::Duration::milliseconds(500);
+        assert_eq!(activity.insert_trackpoint(later), 11);
+        activity.refresh_summaries();
+        assert_eq!(activity.laps[0].start_time, Some(point.time));
+
+        let mut empty = crate::tcx::Activity::default();
+        assert_eq!(empty.insert_trackpoint(point), 0);
+        assert_eq!(empty.laps.len(), 1);
+        assert!(empty.laps[0].summary_dirty);
+        assert_eq!(empty.delete_range(0..10), 1);
+        assert!(empty.laps[0].tracks.is_empty());
+    }
+
+    #[cfg(feature = "proj")]
+    #[test]
+    fn projected_csv() {
+        use crate::csv::{CsvColumn, CsvOptions};
+        use crate::projection::{CoordinateSystem, ProjectionError, Projector};
+        use crate::tcx::Position;
+
+        let london = Position {
+            latitude: 51.5,
+            longitude: -0.1,
+        };
+        let (x, y) = Projector::new(&CoordinateSystem::WebMercator)
+            .unwrap()
+            .project(&london)
+            .unwrap();
+        assert!((x + 11131.95).abs() < 0.01);
+        assert!((y - 6710219.08).abs() < 0.01);
+        let (x, y) = Projector::new(&CoordinateSystem::Epsg(27700))
+            .unwrap()
+            .project(&london)
+            .unwrap();
+        assert!((x - 531_980.0).abs() < 10.0 && (y - 179_607.0).abs() < 10.0);
+        let (x, y) = Projector::new(&CoordinateSystem::Epsg(4326))
+            .unwrap()
+            .project(&london)
+            .unwrap();
+        assert!((x + 0.1).abs() < 1e-9 && (y - 51.5).abs() < 1e-9);
+        let utm = CoordinateSystem::Proj("+proj=utm +zone=30 +datum=WGS84 +units=m".to_string());
+        let (x, _) = Projector::new(&utm).unwrap().project(&london).unwrap();
+        assert!((x - 701_278.0).abs() < 10.0);
+        assert_eq!(
+            Projector::new(&CoordinateSystem::Epsg(2000)).err(),
+            Some(ProjectionError::UnknownEpsg(2000))
+        );
+        assert!(matches!(
+            Projector::new(&CoordinateSystem::Proj("+proj=nonsense".to_string())),
+            Err(ProjectionError::InvalidDefinition(_))
+        ));
+
+        let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let options = CsvOptions {
+            columns: vec![
+                CsvColumn::Timestamp,
+                CsvColumn::Latitude,
+                CsvColumn::Longitude,
+            ],
+            lap_summaries: true,
+        };
+        let mut plain = Vec::new();
+        tcx.export_csv(&mut plain, &options).unwrap();
+        let mut projected = Vec::new();
+        tcx.export_projected_csv(&mut projected, &options, &CoordinateSystem::Wgs84)
+            .unwrap();
+        let (plain, projected) = (
+            String::from_utf8(plain).unwrap(),
+            String::from_utf8(projected).unwrap(),
+        );
+        assert_eq!(
+            projected.lines().next().unwrap(),
+            "activity_id,lap,row,timestamp,y,x"
+        );
+        assert_eq!(plain.lines().count(), projected.lines().count());
+        for (a, b) in plain.lines().zip(projected.lines()).skip(1) {
+            let a: Vec<&str> = a.split(',').collect();
+            let b: Vec<&str> = b.split(',').collect();
+            assert_eq!(a[..4], b[..4]);
+            for i in 4..6 {
+                match (a[i].parse::<f64>(), b[i].parse::<f64>()) {
+                    (Ok(a), Ok(b)) => assert!((a - b).abs() < 1e-9),
+                    _ => assert_eq!(a[i], b[i]),
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "proto")]
+    #[test]
+    fn protobuf_round_trip() {
+        use crate::proto::{ConversionError, Database, Trackpoint};
+        use crate::tcx::TrainingCenterDatabase;
+
+        let run = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let message = run.to_proto();
+        let activity = &message.activities[0];
+        assert!(activity.laps[0].tracks[0]
+            .trackpoints
+            .iter()
+            .any(|point| point.watts.is_some()));
+        assert!(activity.laps[0].lx.as_ref().unwrap().avg_watts.is_some());
+        let again = TrainingCenterDatabase::from_proto_bytes(&run.to_proto_bytes()).unwrap();
+        assert_eq!(again.to_proto(), message);
+        let lap = &again.activities.as_ref().unwrap().activities[0].laps[0];
+        assert_eq!(
+            lap.extensions
+                .as_ref()
+                .unwrap()
+                .lx
+                .as_ref()
+                .unwrap()
+                .avg_watts,
+            run.activities.as_ref().unwrap().activities[0].laps[0]
+                .extensions
+                .as_ref()
+                .unwrap()
+                .lx
+                .as_ref()
+                .unwrap()
+                .avg_watts
+        );
+        assert_eq!(
+            again.activities.as_ref().unwrap().activities[0]
+                .summary()
+                .distance_meters,
+            run.activities.as_ref().unwrap().activities[0]
+                .summary()
+                .distance_meters
+        );
+
+        let fenix = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let message = fenix.to_proto();
+        let version = message.activities[0]
+            .creator
+            .as_ref()
+            .unwrap()
+            .version
+            .as_ref();
+        assert!(version.unwrap().version_major > 0);
+        let again = TrainingCenterDatabase::from_proto(message.clone()).unwrap();
+        assert_eq!(again.to_proto(), message);
+
+        let course = crate::tcx::read_file("tests/20210601_course_lake_loop.tcx").unwrap();
+        let message = course.to_proto();
+        assert!(!message.courses[0].course_points.is_empty());
+        assert!(message.courses[0]
+            .course_points
+            .iter()
+            .all(|point| point.point_type != 0));
+        let again = TrainingCenterDatabase::from_proto(message.clone()).unwrap();
+        assert_eq!(again.to_proto(), message);
+        assert_eq!(
+            again.courses.as_ref().unwrap().courses[0].name,
+            course.courses.as_ref().unwrap().courses[0].name
+        );
+
+        // Multisport sessions, with their transitions, and workouts are carried too.
+        let triathlon = crate::tcx::read_file("tests/20210704_triathlon_multisport.tcx").unwrap();
+        let message = triathlon.to_proto();
+        assert_eq!(message.multi_sport_sessions[0].legs.len(), 3);
+        assert!(message.multi_sport_sessions[0].legs[1].transition.is_some());
+        let again = TrainingCenterDatabase::from_proto_bytes(&triathlon.to_proto_bytes()).unwrap();
+        assert_eq!(again.to_proto(), message);
+        let session = &again.activities.as_ref().unwrap().multi_sport_sessions[0];
+        assert_eq!(
+            session.id,
+            triathlon.activities.as_ref().unwrap().multi_sport_sessions[0].id
+        );
+        assert_eq!(session.transitions().count(), 2);
+
+        let workouts = crate::tcx::read_file("tests/20210602_workout_intervals.tcx").unwrap();
+        let message = workouts.to_proto();
+        assert!(!message.workouts[0].steps.is_empty());
+        let again = TrainingCenterDatabase::from_proto(message.clone()).unwrap();
+        assert_eq!(again.to_proto(), message);
+        let workout = &again.workouts.as_ref().unwrap().workouts[0];
+        let original = &workouts.workouts.as_ref().unwrap().workouts[0];
+        assert_eq!(
+            serde_json::to_value(&workout.steps).unwrap(),
+            serde_json::to_value(&original.steps).unwrap()
+        );
+        assert_eq!(workout.scheduled_on, original.scheduled_on);
+
+        let mut bad = message;
+        bad.workouts[0].scheduled_on = vec!["June".to_string()];
+        assert_eq!(
+            TrainingCenterDatabase::from_proto(bad).unwrap_err(),
+            ConversionError::InvalidDate("June".to_string())
+        );
+        let mut bad = Database::default();
+        bad.multi_sport_sessions.push(Default::default());
+        assert_eq!(
+            TrainingCenterDatabase::from_proto(bad).unwrap_err(),
+            ConversionError::MissingActivity
+        );
+
+        let mut bad = Database::default();
+        bad.activities.push(crate::proto::Activity {
+            laps: vec![crate::proto::Lap {
+                tracks: vec![crate::proto::Track {
+                    trackpoints: vec![Trackpoint {
+                        time_ms: i64::MAX,
+                        ..Default::default()
+                    }],
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        assert_eq!(
+            TrainingCenterDatabase::from_proto(bad).unwrap_err(),
+            ConversionError::InvalidTime(i64::MAX)
+        );
+        assert!(TrainingCenterDatabase::from_proto_bytes(&[0xff, 0xff]).is_err());
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack_cache() {
+        use crate::tcx::TrainingCenterDatabase;
+
+        for file in &[
+            "tests/20210119_run_garmin_fenix6.tcx",
+            "tests/20210601_course_lake_loop.tcx",
+            "tests/20210602_workout_intervals.tcx",
+            "tests/20210704_triathlon_multisport.tcx",
+        ] {
+            let tcx = crate::tcx::read_file(file).unwrap();
+            let bytes = tcx.to_msgpack().unwrap();
+            let cached = TrainingCenterDatabase::from_msgpack(&bytes).unwrap();
+            let xml = |db: &TrainingCenterDatabase| {
+                let mut xml = Vec::new();
+                db.to_writer(&mut xml).unwrap();
+                xml
+            };
+            assert_eq!(xml(&cached), xml(&tcx), "{}", file);
+
+            let mut sink = Vec::new();
+            cached.write_msgpack_to(&mut sink).unwrap();
+            assert_eq!(sink, bytes);
+            let again = TrainingCenterDatabase::read_msgpack(&mut &sink[..]).unwrap();
+            assert_eq!(xml(&again), xml(&tcx));
+        }
+        assert!(TrainingCenterDatabase::from_msgpack(b"not msgpack").is_err());
+    }
+
+    #[test]
+    fn synthetic_routes() {
+        use crate::sport::Sport;
+        use crate::synthetic::{activity, route, RouteOptions, RouteShape};
+
+        let options = RouteOptions {
+            seed: 42,
+            ..Default::default()
+        };
+        let points = route(&options).unwrap();
+        assert_eq!(points.len(), 3600);
+        let again = route(&options).unwrap();
+        assert!(points
+            .iter()
+            .zip(again.iter())
+            .all(|(a, b)| a.position == b.position && a.altitude_meters == b.altitude_meters));
+        let other = route(&RouteOptions {
+            seed: 43,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_ne!(other[100].position, points[100].position);
+
+        // A loop ends where it started, at the same altitude.
+        let first = points[0].position.as_ref().unwrap();
+        let last = points[3599].position.as_ref().unwrap();
+        assert!(crate::geo::haversine_distance(first, last) < 1.0);
+        assert!((points[3599].altitude_meters.unwrap() - 1600.0).abs() < 1e-6);
+        let open = route(&RouteOptions {
+            shape: RouteShape::PointToPoint,
+            ..options.clone()
+        })
+        .unwrap();
+        let open_last = open[3599].position.as_ref().unwrap();
+        assert!(crate::geo::haversine_distance(first, open_last) > 100.0);
+
+        // It turns and climbs rather than running straight and flat.
+        let refs: Vec<&crate::tcx::Trackpoint> = points.iter().collect();
+        let bearings: Vec<f64> = crate::geo::bearings(&refs).into_iter().flatten().collect();
+        let turns = bearings
+            .windows(2)
+            .filter(|pair| {
+                let change = (pair[1] - pair[0]).rem_euclid(360.0);
+                change.min(360.0 - change) > 30.0
+            })
+            .count();
+        assert!(turns >= 5, "{} turns", turns);
+        let altitudes: Vec<f64> = points
+            .iter()
+            .filter_map(|point| point.altitude_meters)
+            .collect();
+        let highest = altitudes.iter().cloned().fold(f64::MIN, f64::max);
+        let lowest = altitudes.iter().cloned().fold(f64::MAX, f64::min);
+        assert!(highest - lowest > 20.0);
+
+        let run = activity(
+            Sport::Running,
+            &RouteOptions {
+                speed_meters_per_second: 3.0,
+                ..options
+            },
+        )
+        .unwrap();
+        assert!(run.validate(None).is_empty());
+        assert!(run.trackpoints().all(|point| point.cadence.is_none()
+            && point
+                .extensions
+                .as_ref()
+                .unwrap()
+                .tpx
+                .as_ref()
+                .unwrap()
+                .run_cadence
+                .is_some()));
+        assert!(run.laps[0].average_heart_rate.is_some());
+
+        // Options that cannot make a valid activity are errors rather than panics.
+        let invalid = |options: RouteOptions| match activity(Sport::Biking, &options) {
+            Err(crate::Error::InvalidValue(_)) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        };
+        invalid(RouteOptions {
+            trackpoints: 0,
+            ..Default::default()
+        });
+        invalid(RouteOptions {
+            start_latitude: 89.99,
+            ..Default::default()
+        });
+        invalid(RouteOptions {
+            start_latitude: f64::NAN,
+            ..Default::default()
+        });
+        invalid(RouteOptions {
+            start_longitude: 181.0,
+            ..Default::default()
+        });
+        assert!(route(&RouteOptions {
+            trackpoints: 0,
+            ..Default::default()
+        })
+        .unwrap()
+        .is_empty());
+
+        // Routes near the antimeridian wrap rather than leaving the valid longitudes.
+        let ride = activity(
+            Sport::Biking,
+            &RouteOptions {
+                start_longitude: 179.99,
+                shape: RouteShape::PointToPoint,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(ride.validate(None).is_empty());
+        assert!(ride
+            .trackpoints()
+            .any(|point| point.position.as_ref().unwrap().longitude < 0.0));
+    }
+
+    #[test]
+    fn power_smoothing_variants() {
+        use crate::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+        use crate::metrics::{compute_metrics_bulk, MetricId, MetricsCache, PowerSmoothing};
+
+        // Steady power reads the same however it is smoothed.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-03-08T12:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let ride = |watts: &dyn Fn(i64) -> u16| {
+            ActivityBuilder::new("Biking")
+                .lap(
+                    LapBuilder::new()
+                        .trackpoints((0..600).map(|i| {
+                            TrackpointBuilder::new(start + chrono::Duration::seconds(i))
+                                .watts(watts(i))
+                                .build()
+                        }))
+                        .build(),
+                )
+                .build()
+                .unwrap()
+        };
+        let steady = ride(&|_| 200);
+        for smoothing in &[
+            PowerSmoothing::Rolling30,
+            PowerSmoothing::XPower,
+            PowerSmoothing::Exponential(10.0),
+        ] {
+            let np = steady.normalized_power_with(*smoothing).unwrap();
+            assert!((np - 200.0).abs() < 1e-9, "{:?}", smoothing);
+        }
+
+        // Surges raise every variant above the average, and a shorter time constant follows them more closely.
+        let surges = ride(&|i| if (i / 30) % 2 == 0 { 100 } else { 300 });
+        let rolling = surges
+            .normalized_power_with(PowerSmoothing::Rolling30)
+            .unwrap();
+        let xpower = surges
+            .normalized_power_with(PowerSmoothing::XPower)
+            .unwrap();
+        let quick = surges
+            .normalized_power_with(PowerSmoothing::Exponential(5.0))
+            .unwrap();
+        assert!(rolling > 200.0 && xpower > 200.0);
+        assert!(quick > xpower);
+        assert_eq!(
+            surges.normalized_power_with(PowerSmoothing::Exponential(25.0)),
+            Some(xpower)
+        );
+        assert_eq!(
+            surges.normalized_power_with(PowerSmoothing::Rolling30),
+            surges.summary().normalized_power
+        );
+
+        // The cache smooths as chosen, and invalidates the smoothed power with the rest.
+        let mut cache = MetricsCache::with_smoothing(surges.clone(), PowerSmoothing::XPower);
+        assert_eq!(cache.normalized_power(), Some(xpower));
+        assert!(cache.smoothed_power().len() < cache.activity().trackpoints().count());
+        cache.activity_mut().laps[0].tracks[0]
+            .trackpoints
+            .truncate(10);
+        assert!(cache.smoothed_power().is_empty());
+        assert_eq!(cache.normalized_power(), None);
+
+        let table = compute_metrics_bulk(
+            &[steady, surges],
+            &[MetricId::NormalizedPower, MetricId::XPower],
+        );
+        assert_eq!(table.get(1, MetricId::XPower), Some(xpower));
+        assert_eq!(table.get(1, MetricId::NormalizedPower), Some(rolling));
+    }
+
+    #[test]
+    fn normalized_graded_pace() {
+        use crate::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+
+        // Ten minutes at 3 m/s, on the flat and up a 10% grade.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-01-19T07:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let run = |grade: f64| {
+            ActivityBuilder::new("Running")
+                .lap(
+                    LapBuilder::new()
+                        .trackpoints((0..600).map(|i| {
+                            TrackpointBuilder::new(start + chrono::Duration::seconds(i))
+                                .distance(3.0 * i as f64)
+                                .altitude(100.0 + grade * 3.0 * i as f64)
+                                .build()
+                        }))
+                        .build(),
+                )
+                .build()
+                .unwrap()
+        };
+        let flat = run(0.0);
+        let ngp = flat.normalized_graded_pace().unwrap();
+        assert!((ngp - 1000.0 / 3.0).abs() < 1e-6);
+
+        // Climbing at the same speed is the effort of running faster on the flat.
+        let hill = run(0.1);
+        let uphill = hill.normalized_graded_pace().unwrap();
+        assert!(uphill < ngp * 0.75);
+        let adjusted = hill.grade_adjusted_speed_series();
+        assert_eq!(adjusted.len(), 599);
+        assert_eq!(adjusted[0].1, 3.0);
+        assert!(adjusted.last().unwrap().1 > 4.0);
+
+        // Without speeds there is nothing to normalize.
+        assert!(crate::tcx::Activity::default()
+            .normalized_graded_pace()
+            .is_none());
+    }
+
+    #[test]
+    fn swim_lengths() {
+        use crate::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+        use crate::swim::{LengthSource, StrokeType, SwimOptions};
+
+        // Three 20 second lengths, a 30 second rest at the wall, then two more lengths. The speed dips at each turn.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-07-01T07:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let points = (0..130).map(|i| {
+            let point = TrackpointBuilder::new(start + chrono::Duration::seconds(i));
+            let resting = (60..90).contains(&i);
+            match (resting, (i - if i >= 90 { 90 } else { 0 }) % 20) {
+                (true, _) => point.speed(0.0),
+                (false, 0) => point.speed(0.5).cadence(30),
+                (false, _) => point.speed(1.4).cadence(30),
+            }
+            .build()
+        });
+        let mut swim = ActivityBuilder::new("Other")
+            .lap(
+                LapBuilder::new()
+                    .distance_meters(125.0)
+                    .trackpoints(points)
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        swim.laps[0]
+            .extensions
+            .get_or_insert_with(Default::default)
+            .unknown
+            .push(String::from(
+                "<ns5:SwimStroke>Breaststroke</ns5:SwimStroke>",
+            ));
+
+        let report = swim.swim_lengths(&SwimOptions::default());
+        assert_eq!(report.lengths.len(), 5);
+        assert_eq!(report.rests.len(), 1);
+        assert_eq!(
+            report.rests[0].start_time,
+            start + chrono::Duration::seconds(60)
+        );
+        assert_eq!(report.rests[0].duration_seconds, 29.0);
+        assert_eq!(report.distance_meters, 125.0);
+        let first = &report.lengths[0];
+        assert_eq!(first.source, LengthSource::Speed);
+        assert_eq!(first.stroke_type, StrokeType::Breaststroke);
+        assert_eq!(first.duration_seconds, 20.0);
+        assert_eq!(first.pace_seconds_per_100m, 80.0);
+        assert_eq!(first.strokes, Some(10));
+        assert_eq!(
+            report.lengths[3].start_time,
+            start + chrono::Duration::seconds(90)
+        );
+
+        // In a 50 meter pool the laps are still not lengths, so the speed is used.
+        let long_pool = SwimOptions {
+            pool_length_meters: 50.0,
+            ..Default::default()
+        };
+        assert_eq!(swim.swim_lengths(&long_pool).distance_meters, 250.0);
+
+        assert_eq!(StrokeType::from_name(" FLY"), StrokeType::Butterfly);
+        assert_eq!(StrokeType::from_name("sidestroke"), StrokeType::Unknown);
+        assert!(
+            crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx")
+                .unwrap()
+                .activities
+                .unwrap()
+                .activities[0]
+                .swim_lengths(&SwimOptions::default())
+                .lengths
+                .iter()
+                .all(|length| length.source == LengthSource::Speed)
+        );
+    }
+
+    #[test]
+    fn multisport_legs() {
+        use crate::sport::Sport;
+
+        let tcx = crate::tcx::read_file("tests/20210704_triathlon_multisport.tcx").unwrap();
+        // The database summary covers the legs, but not the transitions between them.
+        let summary = tcx.summary();
+        assert_eq!(summary.elapsed_seconds, 60.0 + 60.0 + 60.0);
+        assert!(summary.distance_meters > 0.0);
+        let mut session = tcx.activities.unwrap().multi_sport_sessions.remove(0);
+        let legs = session.leg_summaries();
+        assert_eq!(legs.len(), 5);
+        assert_eq!(legs[0].sport, Some(Sport::Other));
+        assert_eq!(legs[1].sport, None);
+        assert_eq!(legs[1].duration_seconds, 45.0);
+        assert_eq!(legs[2].sport, Some(Sport::Biking));
+        assert_eq!(
+            legs[2].start_time,
+            session.next_sports[0].activity.laps[0].start_time
+        );
+        assert_eq!(legs[4].summary, session.next_sports[1].activity.summary());
+        let total: f64 = legs.iter().map(|leg| leg.duration_seconds).sum();
+        assert_eq!(total, 60.0 + 45.0 + 60.0 + 30.0 + 60.0);
+
+        // Without a recorded transition, it is timed from the gap between the legs.
+        session.next_sports[0].transition = None;
+        let times = session.transition_times();
+        assert!(!times[0].recorded && times[1].recorded);
+        assert_eq!(times[0].label, "T1");
+        assert_eq!(times[0].duration_seconds, 45.0);
+        assert_eq!(
+            times[0].start_time.unwrap().to_rfc3339(),
+            "2021-07-04T07:01:00+00:00"
+        );
+        let legs = session.leg_summaries();
+        assert_eq!(legs[1].label, "T1");
+        assert_eq!(legs[1].duration_seconds, 45.0);
+    }
+
+    #[test]
+    fn thin_course_points() {
+        use crate::tcx::{Course, CoursePoint, CoursePointType, Position};
+
+        let time = chrono::DateTime::parse_from_rfc3339("2021-06-01T06:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let point = |meters: Option<f64>, point_type, name: Option<&str>, notes: Option<&str>| {
+            CoursePoint {
+                name: name.map(String::from),
+                time,
+                position: meters.map(|meters| Position {
+                    latitude: 46.95 + meters / 111_195.0,
+                    longitude: 7.45,
+                }),
+                point_type: Some(point_type),
+                notes: notes.map(String::from),
+                altitude_meters: None,
+                extensions: None,
+            }
+        };
+        let mut course = Course::new("Cues");
+        course.course_points = vec![
+            point(Some(0.0), CoursePointType::Generic, Some("Start"), None),
+            point(Some(10.0), CoursePointType::Generic, Some("Cue"), None),
+            point(Some(20.0), CoursePointType::Danger, None, None),
+            point(Some(200.0), CoursePointType::Left, Some("Left"), None),
+            point(
+                Some(205.0),
+                CoursePointType::Straight,
+                None,
+                Some("Keep on the path"),
+            ),
+            point(None, CoursePointType::Generic, Some("Info"), None),
+            point(Some(400.0), CoursePointType::Generic, Some("End"), None),
+        ];
+
+        assert_eq!(course.thin_points(50.0), 3);
+        let kept: Vec<(Option<CoursePointType>, Option<&str>, Option<&str>)> = course
+            .course_points
+            .iter()
+            .map(|point| {
+                (
+                    point.point_type,
+                    point.name.as_deref(),
+                    point.notes.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            kept,
+            vec![
+                (Some(CoursePointType::Danger), Some("Start"), None),
+                (
+                    Some(CoursePointType::Left),
+                    Some("Left"),
+                    Some("Keep on the path")
+                ),
+                (Some(CoursePointType::Generic), Some("Info"), None),
+                (Some(CoursePointType::Generic), Some("End"), None),
+            ]
+        );
+
+        // Already far enough apart.
+        assert_eq!(course.thin_points(50.0), 0);
+        assert_eq!(course.thin_points(0.0), 0);
+    }
+
+    #[test]
+    fn fitlog_export() {
+        use xml::reader::{EventReader, XmlEvent};
+
+        let elements = |tcx: &crate::tcx::TrainingCenterDatabase| {
+            let mut fitlog = Vec::new();
+            tcx.to_fitlog(&mut fitlog).unwrap();
+            let mut elements: Vec<(String, Vec<(String, String)>)> = Vec::new();
+            for event in EventReader::new(fitlog.as_slice()) {
+                if let XmlEvent::StartElement {
+                    name, attributes, ..
+                } = event.unwrap()
+                {
+                    let attributes = attributes
+                        .into_iter()
+                        .map(|attribute| (attribute.name.local_name, attribute.value))
+                        .collect();
+                    elements.push((name.local_name, attributes));
+                }
+            }
+            elements
+        };
+        let attribute = |attributes: &[(String, String)], name: &str| {
+            attributes
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let ride = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let parsed = elements(&ride);
+        assert_eq!(parsed[0].0, "FitnessWorkbook");
+        let points: Vec<&Vec<(String, String)>> = parsed
+            .iter()
+            .filter(|(name, _)| name == "pt")
+            .map(|(_, attributes)| attributes)
+            .collect();
+        let activity = &ride.activities.as_ref().unwrap().activities[0];
+        assert_eq!(points.len(), activity.trackpoints().count());
+        assert!(points
+            .iter()
+            .any(|point| attribute(point, "power").is_some()));
+        assert_eq!(attribute(points[0], "tm").as_deref(), Some("0"));
+        let category = parsed.iter().find(|(name, _)| name == "Category").unwrap();
+        assert_eq!(attribute(&category.1, "Name").as_deref(), Some("Cycling"));
+
+        // Every leg of a multisport session is an activity, with a stable Id of its own.
+        let triathlon = crate::tcx::read_file("tests/20210704_triathlon_multisport.tcx").unwrap();
+        let ids: Vec<String> = elements(&triathlon)
+            .iter()
+            .filter(|(name, _)| name == "Activity")
+            .filter_map(|(_, attributes)| attribute(attributes, "Id"))
+            .collect();
+        assert_eq!(ids.len(), 3);
+        assert!(ids[0] != ids[1] && ids[1] != ids[2]);
+        assert_eq!(ids[0].len(), 36);
+        let again: Vec<String> = elements(&triathlon)
+            .iter()
+            .filter(|(name, _)| name == "Activity")
+            .filter_map(|(_, attributes)| attribute(attributes, "Id"))
+            .collect();
+        assert_eq!(again, ids);
+    }
+
+    #[test]
+    fn legacy_json_imports() {
+        use crate::legacy::ImportError;
+        use crate::tcx::TrainingCenterDatabase;
+
+        let json = std::fs::read("tests/20190609_ride_endomondo.json").unwrap();
+        let tcx = TrainingCenterDatabase::from_endomondo_json(&json).unwrap();
+        let ride = &tcx.activities.as_ref().unwrap().activities[0];
+        assert_eq!(ride.sport, "Biking");
+        assert_eq!(ride.id, "2019-06-09T15:11:29.000Z");
+        assert_eq!(ride.user_notes().as_deref(), Some("Trail still muddy"));
+        assert_eq!(
+            ride.meta().unwrap().tags,
+            vec!["endomondo", "MOUNTAIN_BIKING"]
+        );
+        let lap = &ride.laps[0];
+        assert_eq!(lap.total_time_seconds, 60.0);
+        assert_eq!(lap.distance_meters, 360.0);
+        assert_eq!(lap.calories, 12);
+        let points: Vec<&crate::tcx::Trackpoint> = ride.trackpoints().collect();
+        assert_eq!(points.len(), 4);
+        assert_eq!(points[1].distance_meters, Some(110.0));
+        assert_eq!(points[1].cadence, Some(84));
+        assert_eq!(points[3].cadence, None);
+        let speed = points[1]
+            .extensions
+            .as_ref()
+            .unwrap()
+            .tpx
+            .as_ref()
+            .unwrap()
+            .speed;
+        assert!((speed.unwrap() - 6.0).abs() < 1e-9);
+        assert!(ride.validate(None).is_empty());
+
+        let json = std::fs::read("tests/20110301_run_runkeeper.json").unwrap();
+        let tcx = TrainingCenterDatabase::from_runkeeper_json(&json).unwrap();
+        let run = &tcx.activities.as_ref().unwrap().activities[0];
+        assert_eq!(run.sport, "Running");
+        assert_eq!(run.id, "2011-03-01T15:00:00.000Z");
+        assert_eq!(run.meta().unwrap().tags, vec!["runkeeper", "Running"]);
+        assert_eq!(run.laps[0].calories, 31);
+        // The pause splits the path into two Tracks; the heart rate sample at 15 seconds has a Trackpoint of its own.
+        let counts: Vec<usize> = run.laps[0]
+            .tracks
+            .iter()
+            .map(|track| track.trackpoints.len())
+            .collect();
+        assert_eq!(counts, vec![4, 3]);
+        let points: Vec<&crate::tcx::Trackpoint> = run.trackpoints().collect();
+        assert!(points[1].position.is_none());
+        assert_eq!(points[1].heart_rate.as_ref().unwrap().value, 128.0);
+        assert_eq!(points[2].distance_meters, Some(100.0));
+        assert_eq!(points[6].heart_rate.as_ref().unwrap().value, 152.0);
+
+        let error =
+            TrainingCenterDatabase::from_endomondo_json(br#"[{"start_time": "yesterday"}]"#)
+                .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ImportError>(),
+            Some(&ImportError::InvalidTime(String::from("yesterday")))
+        );
+        assert!(TrainingCenterDatabase::from_runkeeper_json(b"[]").is_err());
+
+        // Offsets and sample times beyond chrono's range are errors rather than panics.
+        for json in [
+            r#"{"start_time": "Tue, 1 Mar 2011 07:00:00", "utc_offset": 1e12}"#,
+            r#"{"start_time": "Tue, 1 Mar 2011 07:00:00", "path": [{"timestamp": 1e13, "latitude": 47.6, "longitude": -122.3}]}"#,
+            r#"{"start_time": "Tue, 1 Mar 2011 07:00:00", "heart_rate": [{"timestamp": -1e13, "heart_rate": 110}]}"#,
+        ] {
+            let error = TrainingCenterDatabase::from_runkeeper_json(json.as_bytes()).unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<ImportError>(),
+                Some(ImportError::InvalidTime(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn gps_quality_flags() {
+        use crate::gps_quality::{GpsFlag, GpsQualityOptions};
+        use crate::sport::Sport;
+        use crate::synthetic::{activity, RouteOptions};
+        use crate::tcx::Extensions;
+
+        let options = RouteOptions {
+            seed: 7,
+            trackpoints: 600,
+            ..Default::default()
+        };
+        let mut ride = activity(Sport::Biking, &options).unwrap();
+        let quality = ride.estimate_gps_quality(&GpsQualityOptions::default());
+        assert_eq!(quality.len(), 600);
+        let trusted = quality
+            .iter()
+            .filter(|point| point.confidence == Some(1.0))
+            .count();
+        assert!(trusted > 590);
+
+        let original = ride
+            .trackpoints()
+            .nth(300)
+            .unwrap()
+            .position
+            .clone()
+            .unwrap();
+        {
+            let mut points = ride.trackpoints_mut();
+            let spike = points.nth(300).unwrap();
+            spike.position.as_mut().unwrap().latitude += 0.01;
+            let doubtful = points.nth(99).unwrap();
+            doubtful
+                .extensions
+                .get_or_insert_with(Extensions::default)
+                .hdop = Some(10.0);
+        }
+        let quality = ride.estimate_gps_quality(&GpsQualityOptions::default());
+        assert!(quality[300].flags.contains(&GpsFlag::Spike));
+        assert!(quality[300].confidence.unwrap() <= 0.1);
+        assert_eq!(quality[299].flags, vec![GpsFlag::ImpliedSpeed]);
+        assert_eq!(quality[301].flags, vec![GpsFlag::ImpliedSpeed]);
+        assert_eq!(quality[400].flags, vec![GpsFlag::Hdop]);
+        assert_eq!(quality[400].confidence, Some(0.5));
+
+        let mut smoothed = ride.clone();
+        smoothed.smooth_positions(&GpsQualityOptions::default(), 5);
+        let pulled = smoothed
+            .trackpoints()
+            .nth(300)
+            .unwrap()
+            .position
+            .clone()
+            .unwrap();
+        assert!((pulled.latitude - original.latitude).abs() < 0.002);
+
+        assert_eq!(
+            ride.remove_gps_outliers(&GpsQualityOptions::default(), 0.5),
+            1
+        );
+        assert!(ride.trackpoints().nth(300).unwrap().position.is_none());
+        assert!(ride.trackpoints().nth(400).unwrap().position.is_some());
+    }
+
+    #[test]
+    fn remove_points_in_polygon() {
+        use crate::sport::Sport;
+        use crate::synthetic::{activity, RouteOptions};
+        use crate::tcx::Position;
+
+        let options = RouteOptions {
+            seed: 11,
+            trackpoints: 600,
+            ..Default::default()
+        };
+        let mut ride = activity(Sport::Biking, &options).unwrap();
+        let middle = ride
+            .trackpoints()
+            .nth(300)
+            .unwrap()
+            .position
+            .clone()
+            .unwrap();
+        let corner = |lat: f64, lon: f64| Position {
+            latitude: middle.latitude + lat,
+            longitude: middle.longitude + lon,
+        };
+        let polygon = vec![
+            corner(-0.0005, -0.0005),
+            corner(-0.0005, 0.0005),
+            corner(0.0, 0.0008),
+            corner(0.0005, 0.0005),
+            corner(0.0005, -0.0005),
+        ];
+        assert_eq!(ride.remove_points_in_polygon(&polygon[..2]), 0);
+
+        let count = ride.trackpoints().count();
+        let removed = ride.remove_points_in_polygon(&polygon);
+        assert!(removed > 0 && removed < count);
+        assert_eq!(ride.trackpoints().count(), count - removed);
+        assert!(ride
+            .trackpoints()
+            .all(
+                |point| (point.position.as_ref().unwrap().latitude - middle.latitude).abs()
+                    > 0.0005
+                    || (point.position.as_ref().unwrap().longitude - middle.longitude).abs()
+                        > 0.0005
+            ));
+        let lap = ride
+            .laps
+            .iter()
+            .find(|lap| lap.tracks.len() > 1)
+            .expect("the excised section splits its track");
+        assert!(!lap.summary_dirty);
+        assert_eq!(ride.remove_points_in_polygon(&polygon), 0);
+    }
+
+    #[test]
+    fn distance_markers() {
+        use crate::sport::Sport;
+        use crate::synthetic::{activity, RouteOptions};
+
+        let options = RouteOptions {
+            seed: 3,
+            trackpoints: 1200,
+            ..Default::default()
+        };
+        let ride = activity(Sport::Biking, &options).unwrap();
+        assert!(ride.generate_distance_markers(0.0).is_empty());
+        let total: f64 = ride
+            .distance_splits(1000.0)
+            .iter()
+            .map(|split| split.distance_meters)
+            .sum();
+        let markers = ride.generate_distance_markers(1000.0);
+        assert_eq!(markers.len(), (total / 1000.0) as usize);
+        for (i, marker) in markers.iter().enumerate() {
+            assert_eq!(marker.distance_meters, 1000.0 * (i + 1) as f64);
+        }
+        assert!(markers.windows(2).all(|pair| pair[0].time < pair[1].time));
+
+        let miles = ride.generate_distance_markers(1609.344);
+        assert_eq!(miles.len(), (total / 1609.344) as usize);
+        let after = ride
+            .trackpoints()
+            .find(|point| point.time >= miles[0].time)
+            .unwrap();
+        let before = ride
+            .trackpoints()
+            .filter(|point| point.time <= miles[0].time)
+            .last()
+            .unwrap();
+        let between = |a: f64, b: f64, x: f64| a.min(b) <= x && x <= a.max(b);
+        let (from, to) = (
+            before.position.as_ref().unwrap(),
+            after.position.as_ref().unwrap(),
+        );
+        assert!(between(
+            from.latitude,
+            to.latitude,
+            miles[0].position.latitude
+        ));
+        assert!(between(
+            from.longitude,
+            to.longitude,
+            miles[0].position.longitude
+        ));
+    }
+
+    #[test]
+    fn route_topologies() {
+        use crate::sport::Sport;
+        use crate::synthetic::{activity, RouteOptions, RouteShape};
+        use crate::tcx::Track;
+        use crate::topology::RouteTopology;
+
+        let options = RouteOptions {
+            seed: 5,
+            trackpoints: 1800,
+            ..Default::default()
+        };
+        let mut ride = activity(Sport::Biking, &options).unwrap();
+        assert_eq!(ride.route_topology(), Some(RouteTopology::Loop));
+
+        let options = RouteOptions {
+            shape: RouteShape::PointToPoint,
+            ..options
+        };
+        let mut ride_out = activity(Sport::Biking, &options).unwrap();
+        assert_eq!(ride_out.route_topology(), Some(RouteTopology::PointToPoint));
+
+        let end = ride_out.trackpoints().last().unwrap().time;
+        let mut back: Vec<_> = ride_out.trackpoints().cloned().collect();
+        back.reverse();
+        for (i, point) in back.iter_mut().enumerate() {
+            point.time = end + chrono::Duration::seconds(i as i64 + 1);
+        }
+        ride_out.laps[0].tracks.push(Track { trackpoints: back });
+        assert_eq!(ride_out.route_topology(), Some(RouteTopology::OutAndBack));
+
+        for point in ride.trackpoints_mut() {
+            point.position = None;
+        }
+        assert_eq!(ride.route_topology(), None);
+    }
+
+    #[test]
+    fn fastest_time_window() {
+        let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let activity = &tcx.activities.unwrap().activities[0];
+        assert!(activity.fastest_window_by_time(0.0).is_none());
+        assert!(activity.fastest_window_by_time(1e6).is_none());
+
+        let window = activity.fastest_window_by_time(300.0).unwrap();
+        assert_eq!(window.seconds, 300.0);
+        let longer = activity.fastest_window_by_time(600.0).unwrap();
+        assert!(longer.distance_meters > window.distance_meters);
+        assert!(longer.distance_meters / 600.0 <= window.distance_meters / 300.0 + 1e-9);
+
+        // Every other window of the same length covers no more distance.
+        let points: Vec<_> = activity
+            .trackpoints()
+            .filter(|point| point.distance_meters.is_some())
+            .collect();
+        for (i, start) in points.iter().enumerate().step_by(25) {
+            if let Some(end) = points[i..]
+                .iter()
+                .find(|end| (end.time - start.time).num_seconds() == 300)
+            {
+                let covered = end.distance_meters.unwrap() - start.distance_meters.unwrap();
+                assert!(covered <= window.distance_meters + 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn redact_time_window() {
+        use crate::sampling::Channel;
+
+        let mut tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+        for point in activity.trackpoints_mut() {
+            point.extensions.as_mut().unwrap().rr_intervals = vec![800, 810];
+        }
+        let original = activity.clone();
+        let peak = activity
+            .trackpoints()
+            .max_by_key(|point| {
+                point
+                    .extensions
+                    .as_ref()
+                    .unwrap()
+                    .tpx
+                    .as_ref()
+                    .unwrap()
+                    .watts
+            })
+            .unwrap()
+            .time;
+        let (start, end) = (
+            peak - chrono::Duration::seconds(30),
+            peak + chrono::Duration::seconds(30),
+        );
+        let inside = |time: chrono::DateTime<chrono::Utc>| time >= start && time <= end;
+
+        let redacted =
+            activity.redact_time_window(start, end, &[Channel::Power, Channel::HeartRate]);
+        assert_eq!(
+            redacted,
+            original
+                .trackpoints()
+                .filter(|point| inside(point.time))
+                .count()
+        );
+        for (point, before) in activity.trackpoints().zip(original.trackpoints()) {
+            let watts = point
+                .extensions
+                .as_ref()
+                .unwrap()
+                .tpx
+                .as_ref()
+                .unwrap()
+                .watts;
+            let rr_intervals = &point.extensions.as_ref().unwrap().rr_intervals;
+            if inside(point.time) {
+                assert!(watts.is_none() && point.heart_rate.is_none());
+                assert!(rr_intervals.is_empty());
+            } else {
+                assert_eq!(rr_intervals, &vec![800, 810]);
+                assert_eq!(
+                    watts,
+                    before
+                        .extensions
+                        .as_ref()
+                        .unwrap()
+                        .tpx
+                        .as_ref()
+                        .unwrap()
+                        .watts
+                );
+            }
+            assert_eq!(point.cadence, before.cadence);
+            assert_eq!(point.position, before.position);
+        }
+        let max_power = activity.summary().max_power.unwrap();
+        assert!(max_power < original.summary().max_power.unwrap());
+        let lap = &activity.laps[0];
+        if let Some(lx) = lap.extensions.as_ref().and_then(|ext| ext.lx.as_ref()) {
+            assert_eq!(lx.max_watts, Some(max_power));
+        }
+        assert_eq!(lap.total_time_seconds, original.laps[0].total_time_seconds);
+        assert_eq!(lap.calories, original.laps[0].calories);
+        assert_eq!(activity.redact_time_window(end, start, &Channel::ALL), 0);
+    }
+
+    #[test]
+    fn mixed_extension_prefixes() {
+        use crate::tcx::{Activity, ReadOptions};
+
+        let filename = "tests/20210308_ride_mixed_extension_prefixes.tcx";
+        let expected = vec![
+            (Some(9.9), Some(216)),
+            (Some(10.0), Some(220)),
+            (Some(10.1), Some(230)),
+            (Some(10.2), Some(240)),
+        ];
+        let check = |activity: &Activity| {
+            let values: Vec<_> = activity
+                .trackpoints()
+                .map(|point| {
+                    let tpx = point.extensions.as_ref().unwrap().tpx.as_ref().unwrap();
+                    (tpx.speed, tpx.watts)
+                })
+                .collect();
+            assert_eq!(values, expected);
+            let lx = activity.laps[0]
+                .extensions
+                .as_ref()
+                .unwrap()
+                .lx
+                .as_ref()
+                .unwrap();
+            assert_eq!((lx.avg_speed, lx.avg_watts), (Some(10.0), Some(226)));
+        };
+
+        let tcx = crate::tcx::read_file(filename).unwrap();
+        check(&tcx.activities.unwrap().activities[0]);
+        let options = ReadOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let tcx = crate::tcx::read_file_with_options(filename, &options).unwrap();
+        check(&tcx.activities.unwrap().activities[0]);
+        #[cfg(feature = "quick_xml")]
+        check(
+            &crate::quick::read_file(filename)
+                .unwrap()
+                .activities
+                .unwrap()
+                .activities[0],
+        );
+
+        let file = std::fs::File::open(filename).unwrap();
+        let streamed: Vec<_> = crate::TrackpointStream::new(file)
+            .map(|item| {
+                let tpx = item.unwrap().2.extensions.unwrap().tpx.unwrap();
+                (tpx.speed, tpx.watts)
+            })
+            .collect();
+        assert_eq!(streamed, expected);
+
+        // LX and TPX repeated and interleaved within one element, under different prefixes, with markup-like text
+        // in a comment and CDATA.
+        let interleaved = br#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2" xmlns:a="http://www.garmin.com/xmlschemas/ActivityExtension/v2">
+  <Activities>
+    <Activity Sport="Biking">
+      <Id>2021-03-08T21:32:20.000Z</Id>
+      <Lap StartTime="2021-03-08T21:32:20.000Z">
+        <TotalTimeSeconds>1.0</TotalTimeSeconds>
+        <DistanceMeters>10.0</DistanceMeters>
+        <Calories>0</Calories>
+        <Intensity>Active</Intensity>
+        <TriggerMethod>Manual</TriggerMethod>
+        <Track>
+          <Trackpoint>
+            <Time>2021-03-08T21:32:21.000Z</Time>
+            <tcx:Extensions xmlns:tcx="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+              <b:TPX xmlns:b="http://www.garmin.com/xmlschemas/ActivityExtension/v2"><b:Watts>250</b:Watts></b:TPX>
+              <!-- <a:TPX><a:Speed>1.0</a:Speed></a:TPX> -->
+              <a:TPX><a:Speed>9.5</a:Speed><a:Watts>1</a:Watts></a:TPX>
+            </tcx:Extensions>
+          </Trackpoint>
+        </Track>
+        <Notes><![CDATA[<Extensions><a:LX></Extensions>]]></Notes>
+        <Extensions>
+          <a:LX><a:AvgSpeed>9.5</a:AvgSpeed></a:LX>
+          <a:TPX><a:Speed>9.0</a:Speed></a:TPX>
+          <c:LX xmlns:c="http://www.garmin.com/xmlschemas/ActivityExtension/v2"><c:AvgWatts>250</c:AvgWatts></c:LX>
+          <c:TPX xmlns:c="http://www.garmin.com/xmlschemas/ActivityExtension/v2"><c:Watts>240</c:Watts></c:TPX>
+        </Extensions>
+      </Lap>
+    </Activity>
+  </Activities>
+</TrainingCenterDatabase>"#;
+        let check = |activity: &Activity| {
+            let tpx = activity.trackpoints().next().unwrap().extensions.as_ref();
+            let tpx = tpx.unwrap().tpx.as_ref().unwrap();
+            assert_eq!((tpx.speed, tpx.watts), (Some(9.5), Some(250)));
+            let lap = &activity.laps[0];
+            assert_eq!(
+                lap.notes.as_deref(),
+                Some("<Extensions><a:LX></Extensions>")
+            );
+            let extensions = lap.extensions.as_ref().unwrap();
+            let lx = extensions.lx.as_ref().unwrap();
+            assert_eq!((lx.avg_speed, lx.avg_watts), (Some(9.5), Some(250)));
+            let tpx = extensions.tpx.as_ref().unwrap();
+            assert_eq!((tpx.speed, tpx.watts), (Some(9.0), Some(240)));
+        };
+        let read = |options: &ReadOptions| {
+            crate::tcx::read_with_options(&mut std::io::BufReader::new(&interleaved[..]), options)
+                .unwrap()
+        };
+        check(&read(&ReadOptions::default()).activities.unwrap().activities[0]);
+        let lenient = ReadOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        check(&read(&lenient).activities.unwrap().activities[0]);
+        #[cfg(feature = "quick_xml")]
+        check(
+            &crate::quick::read(&mut std::io::BufReader::new(&interleaved[..]))
+                .unwrap()
+                .activities
+                .unwrap()
+                .activities[0],
+        );
+        let streamed: Vec<_> = crate::TrackpointStream::new(&interleaved[..])
+            .map(|item| {
+                let tpx = item.unwrap().2.extensions.unwrap().tpx.unwrap();
+                (tpx.speed, tpx.watts)
+            })
+            .collect();
+        assert_eq!(streamed, vec![(Some(9.5), Some(250))]);
+    }
+
+    #[test]
+    fn course_lap_from_track() {
+        use crate::tcx::{CourseLap, Position, Track, Trackpoint};
+
+        let start = chrono::DateTime::parse_from_rfc3339("2021-05-01T08:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let point = |seconds: i64, latitude: Option<f64>, altitude: Option<f64>| Trackpoint {
+            time: start + chrono::Duration::seconds(seconds),
+            position: latitude.map(|latitude| Position {
+                latitude,
+                longitude: 7.0,
+            }),
+            altitude_meters: altitude,
+            distance_meters: None,
+            heart_rate: None,
+            cadence: None,
+            extensions: None,
+        };
+        let track = Track {
+            trackpoints: vec![
+                point(0, None, None),
+                point(10, Some(45.0), Some(300.0)),
+                point(110, Some(45.005), Some(320.0)),
+                point(200, Some(45.01), None),
+            ],
+        };
+
+        let mut lap = CourseLap::default();
+        lap.recalc_from_track(&Track::default());
+        assert_eq!(lap.total_time_seconds, 0.0);
+        assert!(lap.extensions.is_none());
+
+        lap.recalc_from_track(&track);
+        assert_eq!(lap.total_time_seconds, 200.0);
+        assert!((lap.distance_meters - 1111.95).abs() < 0.1);
+        assert_eq!(lap.begin_position, track.trackpoints[1].position);
+        assert_eq!(lap.end_position, track.trackpoints[3].position);
+        assert_eq!(lap.begin_altitude_meters, Some(300.0));
+        assert_eq!(lap.end_altitude_meters, Some(320.0));
+        let speed = lap
+            .extensions
+            .as_ref()
+            .unwrap()
+            .lx
+            .as_ref()
+            .unwrap()
+            .avg_speed;
+        assert!((speed.unwrap() - lap.distance_meters / 200.0).abs() < 1e-9);
+
+        // Recorded distances take precedence over the positions.
+        let mut recorded = track.clone();
+        for (i, point) in recorded.trackpoints.iter_mut().enumerate() {
+            point.distance_meters = Some(500.0 * i as f64);
+        }
+        lap.recalc_from_track(&recorded);
+        assert_eq!(lap.distance_meters, 1500.0);
+    }
+
+    #[test]
+    fn lap_heart_rate_names() {
+        use crate::tcx::{ActivityLap, CourseLap, ReadOptions};
+
+        let filename = "tests/20210119_run_garmin_fenix6.tcx";
+        let tcx = crate::tcx::read_file(filename).unwrap();
+        let lap = &tcx.activities.as_ref().unwrap().activities[0].laps[0];
+        assert_eq!(lap.average_heart_rate, Some(157.0));
+        assert_eq!(lap.maximum_heart_rate, Some(189.0));
+        let options = ReadOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let lenient = crate::tcx::read_file_with_options(filename, &options).unwrap();
+        assert_eq!(
+            lenient.activities.unwrap().activities[0].laps[0].average_heart_rate,
+            Some(157.0)
+        );
+        #[cfg(feature = "quick_xml")]
+        assert_eq!(
+            crate::quick::read_file(filename)
+                .unwrap()
+                .activities
+                .unwrap()
+                .activities[0]
+                .laps[0]
+                .maximum_heart_rate,
+            Some(189.0)
+        );
+
+        // JSON uses the schema's names, and still reads the names earlier versions wrote.
+        let json = serde_json::to_string(lap).unwrap();
+        assert!(json.contains(r#""AverageHeartRateBpm":157.0"#));
+        assert!(json.contains(r#""MaximumHeartRateBpm":189.0"#));
+        let old = json
+            .replace("AverageHeartRateBpm", "AverageHeartRate")
+            .replace("MaximumHeartRateBpm", "MaximumHeartRate");
+        let again: ActivityLap = serde_json::from_str(&old).unwrap();
+        assert_eq!(again.average_heart_rate, Some(157.0));
+        assert_eq!(again.maximum_heart_rate, Some(189.0));
+
+        let course_lap = CourseLap {
+            maximum_heart_rate: Some(175.0),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&course_lap).unwrap();
+        assert!(json.contains(r#""MaximumHeartRateBpm":175.0"#));
+        let old = json.replace("MaximumHeartRateBpm", "MaximumHeartRate");
+        let again: CourseLap = serde_json::from_str(&old).unwrap();
+        assert_eq!(again.maximum_heart_rate, Some(175.0));
+        assert_eq!(again.average_heart_rate, None);
+    }
+
+    #[test]
+    fn activity_comparison() {
+        use crate::compare::{
+            compare_activities, ActivityComparison, CompareOptions, ComparisonBasis,
+        };
+        use crate::metrics::MetricId;
+        use crate::sport::Sport;
+        use crate::synthetic::{activity, RouteOptions};
+
+        let options = RouteOptions {
+            seed: 9,
+            trackpoints: 1200,
+            ..Default::default()
+        };
+        let first = activity(Sport::Biking, &options).unwrap();
+        // The same ride, ten percent slower.
+        let mut second = first.clone();
+        second.id = "slower".to_string();
+        let start = first.trackpoints().next().unwrap().time;
+        for point in second.trackpoints_mut() {
+            let offset = (point.time - start).num_milliseconds() as f64 * 1.1;
+            point.time = start + chrono::Duration::milliseconds(offset as i64);
+        }
+
+        let comparison = compare_activities(&first, &second, &CompareOptions::default());
+        let elapsed = comparison
+            .metrics
+            .iter()
+            .find(|delta| delta.metric == MetricId::ElapsedSeconds)
+            .unwrap();
+        assert!((elapsed.difference.unwrap() - 0.1 * elapsed.first.unwrap()).abs() < 1.0);
+        let distance = &comparison.metrics[0];
+        assert!(distance.difference.unwrap().abs() < 1e-6);
+        assert_eq!(comparison.splits.len(), first.distance_splits(1000.0).len());
+        assert!(comparison
+            .splits
+            .windows(2)
+            .all(|pair| pair[1].cumulative_difference_seconds
+                > pair[0].cumulative_difference_seconds));
+        let last = comparison.splits.last().unwrap();
+        assert!(
+            (last.cumulative_difference_seconds.unwrap() - elapsed.difference.unwrap()).abs() < 1.0
+        );
+
+        let json = serde_json::to_string(&comparison).unwrap();
+        let again: ActivityComparison = serde_json::from_str(&json).unwrap();
+        assert_eq!(again.second_id, "slower");
+        assert_eq!(again.metrics.len(), comparison.metrics.len());
+        assert_eq!(again.splits.len(), comparison.splits.len());
+
+        let mut markdown = Vec::new();
+        comparison.to_markdown(&mut markdown).unwrap();
+        let markdown = String::from_utf8(markdown).unwrap();
+        assert!(markdown.contains("| Metric | 2021-01-01T08:00:00.000Z | slower | Difference |"));
+        assert!(markdown.contains("## Splits by distance"));
+        assert_eq!(
+            markdown
+                .lines()
+                .filter(|line| line.starts_with('|'))
+                .count(),
+            2 + MetricId::ALL.len() + 2 + comparison.splits.len()
+        );
+
+        // A shorter second activity leaves its missing splits empty.
+        let mut lapped = first.clone();
+        lapped.laps = first
+            .split_by_trackpoints(300)
+            .into_iter()
+            .flat_map(|part| part.laps)
+            .collect();
+        let lapped = &lapped;
+        let mut shorter = lapped.clone();
+        shorter.laps.truncate(2);
+        let options = CompareOptions {
+            basis: ComparisonBasis::SameWorkout,
+            metrics: vec![MetricId::MaxPower],
+            ..Default::default()
+        };
+        let comparison = compare_activities(lapped, &shorter, &options);
+        assert_eq!(comparison.metrics[0].difference, None);
+        assert_eq!(comparison.splits.len(), lapped.lap_splits().len());
+        assert_eq!(comparison.splits[1].difference_seconds, Some(0.0));
+        assert!(comparison.splits[2].second.is_none());
+        assert_eq!(comparison.splits[2].cumulative_difference_seconds, None);
+    }
+
+    #[test]
+    fn heart_rate_variability() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Activities>
+    <Activity Sport="Running">
+      <Id>2021-05-01T10:00:00.000Z</Id>
+      <Lap StartTime="2021-05-01T10:00:00.000Z">
+        <TotalTimeSeconds>2</TotalTimeSeconds>
+        <DistanceMeters>10</DistanceMeters>
+        <Calories>1</Calories>
+        <Intensity>Active</Intensity>
+        <TriggerMethod>Manual</TriggerMethod>
+        <Track>
+          <Trackpoint>
+            <Time>2021-05-01T10:00:00.000Z</Time>
+            <HeartRateBpm><Value>75</Value></HeartRateBpm>
+            <Extensions><RRIntervals>800 810</RRIntervals></Extensions>
+          </Trackpoint>
+          <Trackpoint>
+            <Time>2021-05-01T10:00:02.000Z</Time>
+            <HeartRateBpm><Value>75</Value></HeartRateBpm>
+            <Extensions><RRIntervals>0.790,1.2,0.8</RRIntervals></Extensions>
+          </Trackpoint>
+          <Trackpoint>
+            <Time>2021-05-01T10:00:03.000Z</Time>
+            <HeartRateBpm><Value>75</Value></HeartRateBpm>
+          </Trackpoint>
+        </Track>
+      </Lap>
+    </Activity>
+  </Activities>
+</TrainingCenterDatabase>"#;
+        let tcx = crate::tcx::read(&mut std::io::BufReader::new(xml.as_bytes())).unwrap();
+        let activity = &tcx.activities.as_ref().unwrap().activities[0];
+        let recorded: Vec<u16> = activity
+            .trackpoints()
+            .filter_map(|point| point.extensions.as_ref())
+            .flat_map(|extensions| extensions.rr_intervals.clone())
+            .collect();
+        assert_eq!(recorded, vec![800, 810, 790, 1200, 800]);
+
+        // The 1200 ms beat is an artifact and is left out.
+        assert_eq!(activity.rr_intervals(), vec![800.0, 810.0, 790.0, 800.0]);
+        let rmssd = activity.rmssd().unwrap();
+        assert!((rmssd - (600.0_f64 / 3.0).sqrt()).abs() < 1e-9);
+        let sdnn = activity.sdnn().unwrap();
+        assert!((sdnn - (200.0_f64 / 3.0).sqrt()).abs() < 1e-9);
+        assert_eq!(crate::hrv::rmssd(&[800.0]), None);
+
+        // JSON holds the intervals as a list, and leaves them out when there are none.
+        let json = serde_json::to_string(activity).unwrap();
+        assert!(json.contains(r#""RRIntervals":[800,810]"#));
+        assert_eq!(json.matches("RRIntervals").count(), 2);
+        let again: crate::tcx::Activity = serde_json::from_str(&json).unwrap();
+        assert_eq!(again.rr_intervals(), activity.rr_intervals());
+
+        #[cfg(feature = "quick_xml")]
+        {
+            let tcx = crate::quick::read(&mut std::io::BufReader::new(xml.as_bytes())).unwrap();
+            let activity = &tcx.activities.unwrap().activities[0];
+            assert_eq!(activity.rr_intervals(), vec![800.0, 810.0, 790.0, 800.0]);
+        }
+    }
+
+    #[test]
+    fn ml_dataset_export() {
+        use crate::dataset::{export_ml_dataset, DatasetFormat, DatasetSchema, Normalization};
+        use crate::sampling::Channel;
+        use crate::sport::Sport;
+        use crate::synthetic::{activity, RouteOptions};
+
+        let options = RouteOptions {
+            seed: 4,
+            trackpoints: 600,
+            ..Default::default()
+        };
+        let ride = activity(Sport::Biking, &options).unwrap();
+        let mut run = activity(Sport::Running, &options).unwrap();
+        run.id = "run".to_string();
+        // The heart rate strap drops out for the second half of the run.
+        for point in run.trackpoints_mut().skip(300) {
+            point.heart_rate = None;
+        }
+        let activities = vec![ride, run];
+        let schema = DatasetSchema {
+            channels: vec![Channel::HeartRate, Channel::Cadence, Channel::Position],
+            window_samples: 30,
+            stride_samples: 30,
+            ..Default::default()
+        };
+
+        let mut output = Vec::new();
+        let summary = export_ml_dataset(&activities, &schema, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let names: Vec<&str> = summary.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["heart_rate", "cadence", "latitude", "longitude"]);
+        assert_eq!(summary.windows + summary.skipped_windows, 40);
+        assert!(summary.skipped_windows >= 9 && summary.skipped_windows <= 11);
+
+        let mut sums = vec![0.0; 4];
+        for line in output.lines() {
+            let record: serde_json::Value = serde_json::from_str(line).unwrap();
+            let values = record["values"].as_array().unwrap();
+            assert_eq!(values.len(), 30);
+            for row in values {
+                let row = row.as_array().unwrap();
+                assert_eq!(row.len(), 4);
+                for (sum, value) in sums.iter_mut().zip(row) {
+                    *sum += value.as_f64().unwrap();
+                }
+            }
+        }
+        assert_eq!(output.lines().count(), summary.windows);
+        assert!(output
+            .lines()
+            .last()
+            .unwrap()
+            .contains(r#""activity_id":"run""#));
+        // Z-scores average to zero over the dataset.
+        for sum in sums {
+            assert!((sum / (summary.windows * 30) as f64).abs() < 1e-9);
+        }
+
+        // Overlapping, min-max scaled windows as stacked arrays.
+        let schema = DatasetSchema {
+            stride_samples: 15,
+            max_missing: 1.0,
+            normalization: Normalization::MinMax,
+            format: DatasetFormat::Stacked,
+            ..schema
+        };
+        let mut output = Vec::new();
+        let summary = export_ml_dataset(&activities, &schema, &mut output).unwrap();
+        assert_eq!(summary.windows, 78);
+        assert_eq!(summary.skipped_windows, 0);
+        let stacked: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(stacked["shape"], serde_json::json!([78, 30, 4]));
+        let data = stacked["data"].as_array().unwrap();
+        assert_eq!(data.len(), 78 * 30 * 4);
+        assert!(data
+            .iter()
+            .all(|value| (0.0..=1.0).contains(&value.as_f64().unwrap())));
+        assert_eq!(stacked["sports"][77], "Running");
+    }
+
+    #[test]
+    fn empty_laps() {
+        let mut tcx = crate::tcx::read_file("tests/20210415_pool_swim_rest_laps.tcx").unwrap();
+        tcx.calc_heartrates();
+        let activity = &tcx.activities.as_ref().unwrap().activities[0];
+        let empty: Vec<bool> = activity.laps.iter().map(|lap| lap.is_empty()).collect();
+        assert_eq!(empty, vec![false, true, false, true, false]);
+
+        // Rest laps keep what the device recorded; the others are recomputed from their samples.
+        assert_eq!(activity.laps[1].average_heart_rate, Some(118.0));
+        assert_eq!(activity.laps[3].maximum_heart_rate, Some(126.0));
+        assert_eq!(activity.laps[0].average_heart_rate, Some(133.5));
+        for lap in &activity.laps {
+            let summary = lap.summary();
+            assert!(summary.elapsed_seconds.is_finite() && summary.distance_meters.is_finite());
+            assert_eq!(summary.average_speed.is_some(), !lap.is_empty());
+        }
+        let summary = activity.summary();
+        assert_eq!(summary.distance_meters, 150.0);
+        assert_eq!(summary.elapsed_seconds, 185.0);
+        assert_eq!(activity.lap_splits().len(), 3);
+        assert!(activity
+            .distance_splits(50.0)
+            .iter()
+            .all(|split| split.elapsed_seconds.is_finite()));
+
+        let mut csv = Vec::new();
+        tcx.to_lap_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 6);
+        assert!(!csv.contains("NaN"));
+
+        let mut xml = Vec::new();
+        tcx.to_writer(&mut xml).unwrap();
+        let again = crate::tcx::read(&mut std::io::BufReader::new(xml.as_slice())).unwrap();
+        let laps = &again.activities.unwrap().activities[0].laps;
+        assert_eq!(laps.len(), 5);
+        assert_eq!(laps[1].total_time_seconds, 30.0);
+        assert!(laps[3].is_empty());
+
+        // Samples without heart rate do not drag the average down to zero.
+        let mut lap = activity.laps[0].clone();
+        lap.average_heart_rate = None;
+        lap.maximum_heart_rate = None;
+        for point in lap.trackpoints_mut() {
+            point.heart_rate = None;
+        }
+        lap.calc_heartrates();
+        assert_eq!(lap.average_heart_rate, None);
+        for point in lap.trackpoints_mut().take(2) {
+            point.heart_rate = Some(crate::tcx::HeartRate { value: 100.0 });
+        }
+        lap.calc_heartrates();
+        assert_eq!(lap.average_heart_rate, Some(100.0));
+
+        // Emptying a lap by editing leaves zero totals rather than NaN.
+        let mut activity = activity.clone();
+        assert_eq!(activity.delete_range(0..10), 10);
+        activity.refresh_summaries();
+        assert!(activity.laps[0].is_empty());
+        assert_eq!(activity.laps[0].total_time_seconds, 0.0);
+        assert_eq!(activity.laps[0].calories, 0);
+        assert_eq!(activity.laps[0].average_heart_rate, None);
+
+        // A document holding no activities has nothing to calculate.
+        let mut courses = crate::tcx::read_file("tests/20210601_course_lake_loop.tcx").unwrap();
+        courses.calc_heartrates();
+    }
+
+    #[test]
+    fn exports_refresh_edited_laps() {
+        let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        {
+            let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+            assert!(activity.set_trackpoint_hr(10, Some(250.0)));
+            assert_eq!(activity.delete_range(0..600), 600);
+        }
+        let mut fresh = tcx.clone();
+        fresh.activities.as_mut().unwrap().activities[0].refresh_summaries();
+        let fresh_lap = &fresh.activities.as_ref().unwrap().activities[0].laps[0];
+        let stale_lap = &tcx.activities.as_ref().unwrap().activities[0].laps[0];
+        assert!(fresh_lap.total_time_seconds < stale_lap.total_time_seconds);
+
+        let json = tcx.to_json_value(&Default::default()).unwrap();
+        assert_eq!(
+            json["Activities"]["Activity"][0]["Lap"][0]["TotalTimeSeconds"],
+            serde_json::json!(fresh_lap.total_time_seconds)
+        );
+
+        let mut csv = Vec::new();
+        tcx.to_lap_csv(&mut csv).unwrap();
+        let mut expected = Vec::new();
+        fresh.to_lap_csv(&mut expected).unwrap();
+        assert_eq!(csv, expected);
+        let mut csv = Vec::new();
+        tcx.to_activity_csv(&mut csv).unwrap();
+        let mut expected = Vec::new();
+        fresh.to_activity_csv(&mut expected).unwrap();
+        assert_eq!(csv, expected);
+
+        let mut fitlog = Vec::new();
+        tcx.to_fitlog(&mut fitlog).unwrap();
+        let mut expected = Vec::new();
+        fresh.to_fitlog(&mut expected).unwrap();
+        assert_eq!(fitlog, expected);
+
+        #[cfg(feature = "proto")]
+        assert_eq!(tcx.to_proto(), fresh.to_proto());
+        #[cfg(feature = "msgpack")]
+        assert_eq!(tcx.to_msgpack().unwrap(), fresh.to_msgpack().unwrap());
+
+        // Exporting leaves the database itself as it was.
+        assert!(tcx.activities.unwrap().activities[0].laps[0].summary_dirty);
+    }
+
+    #[test]
+    fn crop_split_and_merge() {
+        let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+        let original = tcx.activities.as_ref().unwrap().activities[0].clone();
+        let start = original.start_time().unwrap();
+        let lap = &original.laps[0];
+
+        let mut cropped = original.clone();
+        cropped.crop(
+            start + chrono::Duration::minutes(5),
+            start + chrono::Duration::hours(1),
+        );
+        assert_eq!(
+            cropped.start_time(),
+            Some(start + chrono::Duration::minutes(5))
+        );
+        assert_eq!(cropped.laps[0].start_time, cropped.start_time());
+        assert_eq!(cropped.id, "2021-01-19T23:35:50.000Z");
+        assert_eq!(
+            cropped.trackpoints().next().unwrap().distance_meters,
+            Some(0.0)
+        );
+        let last = cropped
+            .trackpoints()
+            .last()
+            .unwrap()
+            .distance_meters
+            .unwrap();
+        assert!((cropped.laps[0].distance_meters - last).abs() < 1.0);
+        assert!(cropped.laps[0].calories < lap.calories);
+
+        let mut first = original.clone();
+        let second = first
+            .split_at(start + chrono::Duration::minutes(10))
+            .unwrap();
+        assert_eq!(second.id, "2021-01-19T23:40:50.000Z");
+        assert_eq!(
+            first.trackpoints().count() + second.trackpoints().count(),
+            original.trackpoints().count()
+        );
+        let total_time = first.laps[0].total_time_seconds + second.laps[0].total_time_seconds;
+        assert!((total_time - lap.total_time_seconds).abs() < 2.0);
+        let total_distance = first.laps[0].distance_meters + second.laps[0].distance_meters;
+        assert!((total_distance - lap.distance_meters).abs() < 10.0);
+        assert!(first
+            .split_at(start - chrono::Duration::minutes(1))
+            .is_none());
+
+        // One device recorded only heart rate, the other everything else.
+        let mut watch = original.clone();
+        let mut phone = original.clone();
+        for point in watch.trackpoints_mut() {
+            point.position = None;
+            point.distance_meters = None;
+        }
+        watch.laps[0].distance_meters = 0.0;
+        for point in phone.trackpoints_mut() {
+            point.heart_rate = None;
+        }
+        phone.laps[0].calories = 0;
+        let database_of = |activity| crate::tcx::TrainingCenterDatabase {
+            activities: Some(crate::tcx::Activities {
+                activities: vec![activity],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut merged = database_of(watch);
+        merged.merge(database_of(phone));
+        let activities = &merged.activities.as_ref().unwrap().activities;
+        assert_eq!(activities.len(), 1);
+        let merged = &activities[0];
+        assert!(merged.trackpoints().all(|point| point.position.is_some()));
+        assert!(merged.trackpoints().any(|point| point.heart_rate.is_some()));
+        assert!((merged.laps[0].distance_meters - lap.distance_meters).abs() < 1.0);
+        assert_eq!(merged.laps[0].calories, lap.calories);
+    }
+
+    #[test]
+    fn workout_compliance() {
+        use crate::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+
+        let plan = crate::tcx::read_file("tests/20210602_workout_intervals.tcx").unwrap();
+        let workout = &plan.workouts.as_ref().unwrap().workouts[0];
+
+        // Ten minutes of warm up, six 800 m repeats at 4.5 m/s with a jog until the heart rate drops below 130,
+        // then five minutes of cool down. The last repeat is run too slowly.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-06-02T18:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let mut segments = vec![(600, 3.0, 120.0, 0.0, 80)];
+        for repeat in 0..6 {
+            let speed = if repeat == 5 { 4.0 } else { 4.5 };
+            segments.push(((800.0 / speed) as i64, speed, 160.0, 0.0, 90));
+            segments.push((90, 2.0, 160.0, -0.5, 75));
+        }
+        segments.push((300, 2.5, 120.0, 0.0, 85));
+        let mut points = Vec::new();
+        let mut seconds = 0;
+        let mut meters = 0.0;
+        for (duration, speed, heart_rate, drift, cadence) in segments {
+            for i in 0..duration {
+                points.push(
+                    TrackpointBuilder::new(start + chrono::Duration::seconds(seconds))
+                        .distance(meters)
+                        .heart_rate(heart_rate + drift * i as f64)
+                        .cadence(cadence)
+                        .build(),
+                );
+                seconds += 1;
+                meters += speed;
+            }
+        }
+        let activity = ActivityBuilder::new("Running")
+            .lap(LapBuilder::new().trackpoints(points).build())
+            .build()
+            .unwrap();
+
+        let report = activity.compliance_report(workout);
+        assert_eq!(report.steps.len(), 14);
+        let warm_up = &report.steps[0];
+        assert_eq!(warm_up.name.as_deref(), Some("Warm up"));
+        assert_eq!(warm_up.actual_seconds, 600.0);
+        assert_eq!(warm_up.duration_score, Some(1.0));
+        assert_eq!(warm_up.target_score, None);
+        let fast = &report.steps[1];
+        assert_eq!(fast.step_id, 2);
+        assert!((fast.actual_meters - 800.0).abs() < 5.0);
+        assert!(fast.duration_score.unwrap() > 0.99);
+        assert!(fast.target_score.unwrap() > 0.95);
+        let jog = &report.steps[2];
+        assert_eq!(jog.duration_score, Some(1.0));
+        assert!((jog.actual_seconds - 60.0).abs() <= 3.0);
+        assert!(report.steps[11].target_score.unwrap() < 0.05);
+        let cool_down = &report.steps[13];
+        assert_eq!(cool_down.duration_score, None);
+        assert!(cool_down.target_score.unwrap() > 0.9);
+        assert!(report.overall_score.unwrap() > 0.8 && report.overall_score.unwrap() < 1.0);
+
+        let empty = ActivityBuilder::new("Running")
+            .id("2021-06-02T18:00:00.000Z")
+            .lap(LapBuilder::new().build())
+            .build()
+            .unwrap();
+        let report = empty.compliance_report(workout);
+        assert!(report.steps.iter().all(|step| step.start_time.is_none()));
+        assert_eq!(report.overall_score, Some(0.0));
+    }
+
+    #[test]
+    fn race_predictions() {
+        use crate::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+        use crate::predict::RaceModel;
+
+        // A steady 3 m/s run with a 5K at 19:57 (VDOT 50) in the middle.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-06-01T06:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let fast = 5000.0 / 1197.0;
+        let mut meters = 0.0;
+        let points = (0..2400).map(|i| {
+            let point = TrackpointBuilder::new(start + chrono::Duration::seconds(i))
+                .distance(meters)
+                .build();
+            meters += if (600..1797).contains(&i) { fast } else { 3.0 };
+            point
+        });
+        let activity = ActivityBuilder::new("Running")
+            .lap(LapBuilder::new().trackpoints(points).build())
+            .build()
+            .unwrap();
+
+        let effort = activity.best_effort(5000.0).unwrap();
+        assert!((effort.seconds - 1197.0).abs() < 1.0);
+        assert_eq!(effort.start_time, start + chrono::Duration::seconds(600));
+        assert!(activity.best_effort(10000.0).is_none());
+
+        // Daniels' tables for VDOT 50: 10K in 41:21, marathon in 3:10:49.
+        let vdot = activity.predict_race_times(RaceModel::Vdot);
+        assert_eq!(vdot[1].race, "10K");
+        assert!((vdot[0].seconds - 1197.0).abs() < 2.0);
+        assert!((vdot[1].seconds - 2481.0).abs() < 15.0);
+        assert!((vdot[3].seconds - 11449.0).abs() < 60.0);
+
+        let riegel = activity.predict_race_times(RaceModel::Riegel);
+        assert!((riegel[0].seconds - 1197.0).abs() < 2.0);
+        assert!((riegel[1].seconds - 1197.0 * 2f64.powf(1.06)).abs() < 3.0);
+    }
+
+    #[test]
+    fn series_resampling() {
+        use crate::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+
+        // Samples at 0, 2 and 4 s with a heart rate gap, then a 60 s pause.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-06-01T06:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let at = |seconds| start + chrono::Duration::seconds(seconds);
+        let points = vec![
+            TrackpointBuilder::new(at(0))
+                .position(45.0, 7.0)
+                .distance(0.0)
+                .heart_rate(100.0)
+                .build(),
+            TrackpointBuilder::new(at(2)).distance(10.0).build(),
+            TrackpointBuilder::new(at(4))
+                .position(45.0002, 7.0)
+                .distance(20.0)
+                .heart_rate(110.0)
+                .build(),
+            TrackpointBuilder::new(at(64))
+                .position(45.0004, 7.0)
+                .distance(30.0)
+                .heart_rate(130.0)
+                .build(),
+        ];
+        let activity = ActivityBuilder::new("Running")
+            .lap(LapBuilder::new().trackpoints(points).build())
+            .build()
+            .unwrap();
+
+        let mut series = activity.series();
+        assert_eq!(series.len(), 4);
+        assert_eq!(series.latitude[1], None);
+        series.interpolate(10.0);
+        assert!((series.latitude[1].unwrap() - 45.0001).abs() < 1e-9);
+        assert_eq!(series.heart_rate[1], Some(105.0));
+
+        let resampled = activity.series().resample(1.0, 10.0);
+        assert_eq!(resampled.len(), 65);
+        assert_eq!(resampled.time[3], at(3));
+        assert_eq!(resampled.distance[3], Some(15.0));
+        assert_eq!(resampled.heart_rate[3], Some(107.5));
+        assert_eq!(resampled.heart_rate[30], None);
+        assert_eq!(resampled.heart_rate[64], Some(130.0));
+
+        let mut bumpy = crate::series::ActivitySeries {
+            altitude: vec![Some(100.0), Some(106.0), Some(100.0), Some(106.0)],
+            ..Default::default()
+        };
+        bumpy.smooth_altitude(3);
+        assert_eq!(
+            bumpy.altitude,
+            vec![Some(103.0), Some(102.0), Some(104.0), Some(103.0)]
+        );
+    }
+
+    #[test]
+    fn distance_monotonicity() {
+        use crate::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+
+        // A glitch at 2 s and a sensor reset at 4 s.
+        let start = chrono::DateTime::parse_from_rfc3339("2021-06-01T06:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let raw = [0.0, 10.0, 8.0, 30.0, 5.0, 15.0];
+        let points = raw.iter().enumerate().map(|(i, meters)| {
+            TrackpointBuilder::new(start + chrono::Duration::seconds(i as i64))
+                .distance(*meters)
+                .build()
+        });
+        let mut activity = ActivityBuilder::new("Running")
+            .lap(LapBuilder::new().trackpoints(points).build())
+            .build()
+            .unwrap();
+
+        assert_eq!(activity.fix_distance_monotonicity(), 3);
+        let distances: Vec<f64> = activity
+            .trackpoints()
+            .map(|point| point.distance_meters.unwrap())
+            .collect();
+        assert_eq!(distances, vec![0.0, 10.0, 10.0, 30.0, 35.0, 45.0]);
+        assert_eq!(activity.fix_distance_monotonicity(), 0);
+    }
+
+    #[cfg(feature = "quick_xml")]
+    #[test]
+    fn quick_xml_reader() {
+        // Matches the default reader on a real file.
+        let expected = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        let actual = crate::quick::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+        assert_eq!(
+            serde_json::to_string(&actual).unwrap(),
+            serde_json::to_string(&expected).unwrap()
+        );
+
+        // TPX under an unusual prefix, alongside HDOP and a vendor extension. Only the vendor one is kept as raw XML.
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2" xmlns:ax="http://www.garmin.com/xmlschemas/ActivityExtension/v2" xmlns:v="urn:vendor">
+  <Activities>
+    <Activity Sport="Biking">
+      <Id>2021-06-01T06:00:00Z</Id>
+      <Lap StartTime="2021-06-01T06:00:00Z">
+        <TotalTimeSeconds>1</TotalTimeSeconds>
+        <DistanceMeters>5</DistanceMeters>
+        <Calories>0</Calories>
+        <Track>
+          <Trackpoint>
+            <Time>2021-06-01T06:00:00Z</Time>
+            <Extensions>
+              <ax:TPX><ax:Watts>250</ax:Watts></ax:TPX>
+              <HDOP>1.5</HDOP>
+              <v:Core><v:Temp>37.5</v:Temp></v:Core>
+            </Extensions>
+          </Trackpoint>
+        </Track>
+      </Lap>
+    </Activity>
+  </Activities>
+</TrainingCenterDatabase>"#;
+        let mut reader = std::io::BufReader::new(xml.as_bytes());
+        let tcx = crate::quick::read(&mut reader).unwrap();
+        let activity = &tcx.activities.as_ref().unwrap().activities[0];
+        let extensions = activity.laps[0]
+            .trackpoints()
+            .next()
+            .unwrap()
+            .extensions
+            .as_ref()
+            .unwrap();
+        assert_eq!(extensions.tpx.as_ref().unwrap().watts, Some(250));
+        assert_eq!(extensions.hdop, Some(1.5));
+        assert_eq!(
+            extensions.unknown,
+            vec![r#"<v:Core xmlns:v="urn:vendor"><v:Temp>37.5</v:Temp></v:Core>"#]
+        );
+
+        let mut written = Vec::new();
+        tcx.to_writer(&mut written).unwrap();
+        let mut reader = std::io::BufReader::new(written.as_slice());
+        let reread = crate::quick::read(&mut reader).unwrap();
+        let activity = &reread.activities.unwrap().activities[0];
+        let extensions = activity.laps[0]
+            .trackpoints()
+            .next()
+            .unwrap()
+            .extensions
+            .clone()
+            .unwrap();
+        assert_eq!(extensions.unknown.len(), 1);
+
+        let mut reader = std::io::BufReader::new(&b"<TrainingCenterDatabase><Activities>"[..]);
+        assert!(crate::quick::read(&mut reader).is_err());
+    }
 }
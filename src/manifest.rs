@@ -0,0 +1,283 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Exporting a document as an archive: one TCX file per activity, plus a manifest listing each file's SHA-256 hash
+//! and a fingerprint of its content. The hashes let the archive be checked for corruption, and the fingerprints,
+//! which do not change when the same activity is re-exported with different formatting, let a sync tool tell which
+//! activities are new.
+
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::storage::{read_all, write_with};
+use crate::tcx::{
+    Activities, Activity, CourseList, MultiSportSession, TrainingCenterDatabase, WorkoutList,
+    WriteOptions,
+};
+
+/// The name of the manifest file within an archive.
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// One file in an archive.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    /// The file name, relative to the archive directory.
+    pub file_name: String,
+
+    /// SHA-256 hash of the file, in lowercase hexadecimal.
+    pub sha256: String,
+
+    /// The fingerprint of the activity or multisport session in the file (see `Activity::fingerprint`), or `None`
+    /// for the files holding courses and workouts.
+    pub fingerprint: Option<String>,
+}
+
+/// The list of files in an archive, written to `manifest.json`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Manifest {
+    /// The files, in the order they were written.
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Reads the manifest of an archive.
+    ///
+    /// # Parameters
+    ///
+    /// `dir: &Path` -- The archive directory.
+    ///
+    /// # Returns
+    ///
+    /// `std::io::Result<Manifest>` -- The manifest if OK, Error if it is missing or cannot be parsed.
+    pub fn read(dir: &Path) -> std::io::Result<Manifest> {
+        let bytes = read_all(&mut dir.join(MANIFEST_FILE_NAME))?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Checks the files of an archive against the manifest.
+    ///
+    /// # Parameters
+    ///
+    /// `dir: &Path` -- The archive directory.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<String>` -- The names of the files that are missing or whose hash no longer matches. Empty if the
+    /// archive is intact.
+    pub fn verify(&self, dir: &Path) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|entry| match read_all(&mut dir.join(&entry.file_name)) {
+                Ok(bytes) => sha256_hex(&bytes) != entry.sha256,
+                Err(_) => true,
+            })
+            .map(|entry| entry.file_name.clone())
+            .collect()
+    }
+
+    /// Finds the entries whose content is not in another manifest, as a sync tool needs to decide what to upload.
+    ///
+    /// # Parameters
+    ///
+    /// `other: &Manifest` -- The manifest to compare against, such as the one already synced.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<&ManifestEntry>` -- Entries whose fingerprint (or, for files without one, hash) `other` does not have.
+    pub fn new_since<'a>(&'a self, other: &Manifest) -> Vec<&'a ManifestEntry> {
+        let known: HashSet<&str> = other.entries.iter().map(content_key).collect();
+        self.entries
+            .iter()
+            .filter(|entry| !known.contains(content_key(entry)))
+            .collect()
+    }
+}
+
+impl Activity {
+    /// Computes a fingerprint of the activity's content: a SHA-256 hash, in lowercase hexadecimal, of the sport, the
+    /// `Id` and every Trackpoint's time, position, altitude, distance and heart rate. It does not depend on how the
+    /// file was formatted, nor on lap summaries, notes or extensions.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let mut activity = tcx.activities.unwrap().activities.remove(0);
+    /// let fingerprint = activity.fingerprint();
+    /// activity.notes = Some("Felt good".to_string());
+    /// assert_eq!(activity.fingerprint(), fingerprint);
+    /// activity.laps[0].tracks[0].trackpoints.pop();
+    /// assert_ne!(activity.fingerprint(), fingerprint);
+    /// ```
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{}\n{}\n", self.sport.trim(), self.id.trim()));
+        let text = |value: Option<f64>, places: usize| {
+            value
+                .map(|value| format!("{:.*}", places, value))
+                .unwrap_or_default()
+        };
+        for point in self.trackpoints() {
+            let position = point.position.as_ref();
+            hasher.update(format!(
+                "{}|{},{}|{}|{}|{}\n",
+                point.time.timestamp_millis(),
+                text(position.map(|position| position.latitude), 7),
+                text(position.map(|position| position.longitude), 7),
+                text(point.altitude_meters, 3),
+                text(point.distance_meters, 3),
+                text(point.heart_rate.as_ref().map(|hr| hr.value), 0),
+            ));
+        }
+        hex(&hasher.finalize())
+    }
+}
+
+impl MultiSportSession {
+    /// Computes a fingerprint of the session from the fingerprints of its legs. See `Activity::fingerprint`.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{}\n", self.id.timestamp_millis()));
+        for activity in self.activities() {
+            hasher.update(format!("{}\n", activity.fingerprint()));
+        }
+        hex(&hasher.finalize())
+    }
+}
+
+impl TrainingCenterDatabase {
+    /// Exports the document as an archive: each activity and multisport session in its own TCX file, named after
+    /// its start time and sport, any courses in `courses.tcx` and workouts in `workouts.tcx`, and a `manifest.json`
+    /// listing them all. See `Manifest`.
+    ///
+    /// # Parameters
+    ///
+    /// `dir: &Path` -- The directory to write to. Created if it does not exist; files already there with the same
+    /// names are replaced.
+    ///
+    /// # Returns
+    ///
+    /// `std::io::Result<Manifest>` -- The manifest written if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let dir = std::env::temp_dir().join("tcx_manifest_example");
+    /// let manifest = tcx.export_with_manifest(&dir).unwrap();
+    /// assert_eq!(manifest.entries[0].file_name, "20210119T233050Z_running.tcx");
+    /// assert!(manifest.verify(&dir).is_empty());
+    /// ```
+    pub fn export_with_manifest(&self, dir: &Path) -> std::io::Result<Manifest> {
+        std::fs::create_dir_all(dir)?;
+        let mut manifest = Manifest::default();
+        let mut names = HashSet::new();
+        let mut write =
+            |base: String, fingerprint: Option<String>, part: TrainingCenterDatabase| {
+                let mut file_name = format!("{}.tcx", base);
+                let mut copy = 1;
+                while !names.insert(file_name.clone()) {
+                    copy += 1;
+                    file_name = format!("{}-{}.tcx", base, copy);
+                }
+                let mut bytes = Vec::new();
+                part.to_writer_with_options(&mut bytes, &WriteOptions::default())?;
+                write_with(&mut dir.join(&file_name), |writer| writer.write_all(&bytes))?;
+                manifest.entries.push(ManifestEntry {
+                    file_name,
+                    sha256: sha256_hex(&bytes),
+                    fingerprint,
+                });
+                std::io::Result::Ok(())
+            };
+
+        if let Some(activities) = &self.activities {
+            for (i, activity) in activities.activities.iter().enumerate() {
+                let base = match activity.start_time() {
+                    Some(start) => format!(
+                        "{}_{}",
+                        start.format("%Y%m%dT%H%M%SZ"),
+                        activity.sport.trim().to_lowercase()
+                    ),
+                    None => format!("activity_{}", i + 1),
+                };
+                let part = self.part(Activities {
+                    activities: vec![activity.clone()],
+                    multi_sport_sessions: Vec::new(),
+                });
+                write(base, Some(activity.fingerprint()), part)?;
+            }
+            for session in &activities.multi_sport_sessions {
+                let base = format!("{}_multisport", session.id.format("%Y%m%dT%H%M%SZ"));
+                let part = self.part(Activities {
+                    activities: Vec::new(),
+                    multi_sport_sessions: vec![session.clone()],
+                });
+                write(base, Some(session.fingerprint()), part)?;
+            }
+        }
+        if let Some(courses) = &self.courses {
+            let part = TrainingCenterDatabase {
+                courses: Some(CourseList::clone(courses)),
+                author: self.author.clone(),
+                ..Default::default()
+            };
+            write("courses".to_string(), None, part)?;
+        }
+        if let Some(workouts) = &self.workouts {
+            let part = TrainingCenterDatabase {
+                workouts: Some(WorkoutList::clone(workouts)),
+                author: self.author.clone(),
+                ..Default::default()
+            };
+            write("workouts".to_string(), None, part)?;
+        }
+
+        let json = serde_json::to_vec_pretty(&manifest)?;
+        write_with(&mut dir.join(MANIFEST_FILE_NAME), |writer| {
+            writer.write_all(&json)
+        })?;
+        Ok(manifest)
+    }
+
+    /// A document holding only the given activities, with this document's author.
+    fn part(&self, activities: Activities) -> TrainingCenterDatabase {
+        TrainingCenterDatabase {
+            activities: Some(activities),
+            author: self.author.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+fn content_key(entry: &ManifestEntry) -> &str {
+    entry.fingerprint.as_deref().unwrap_or(&entry.sha256)
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,203 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Map matching: snapping a GPS track onto a road network, so that each Trackpoint can be attributed to a way (such
+//! as an OpenStreetMap way) and its surface. The road network itself is supplied by the caller through the
+//! `RoadNetwork` trait, so any data source (a local OSM extract, a routing service, a test fixture) can be used.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::geo::haversine_distance;
+use crate::tcx::{Activity, Course, Position, Trackpoint};
+
+/// Surface reported for ways whose provider did not supply one.
+pub const UNKNOWN_SURFACE: &str = "unknown";
+
+/// A way near a GPS position, as reported by a `RoadNetwork`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WayCandidate {
+    /// The way's identifier, such as an OSM way ID.
+    pub way_id: u64,
+
+    /// The way's surface, such as `asphalt` or `gravel` (OSM `surface=*` values), if known.
+    pub surface: Option<String>,
+
+    /// The point on the way closest to the GPS position.
+    pub snapped: Position,
+
+    /// Distance, in meters, from the GPS position to `snapped`.
+    pub distance_meters: f64,
+}
+
+/// Supplies the ways near a position. Implement this over whatever road data is available.
+pub trait RoadNetwork {
+    /// Returns the ways within `radius_meters` of `position`, in any order.
+    fn nearby_ways(&self, position: &Position, radius_meters: f64) -> Vec<WayCandidate>;
+}
+
+/// Options controlling map matching.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatchOptions {
+    /// How far, in meters, from a GPS position to search for ways. Points with no way this close are left unmatched.
+    pub search_radius_meters: f64,
+
+    /// How much closer, in meters, another way has to be before the match switches away from the current way.
+    /// This keeps GPS noise from bouncing the match between parallel or crossing ways.
+    pub switch_penalty_meters: f64,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        MatchOptions {
+            search_radius_meters: 25.0,
+            switch_penalty_meters: 5.0,
+        }
+    }
+}
+
+/// A Trackpoint snapped onto the road network.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MatchedPoint {
+    /// Time of the Trackpoint.
+    pub time: DateTime<Utc>,
+
+    /// The way the Trackpoint was matched to.
+    pub way_id: u64,
+
+    /// The surface of that way, if known.
+    pub surface: Option<String>,
+
+    /// The Trackpoint's position snapped onto the way.
+    pub snapped: Position,
+
+    /// Distance, in meters, between the recorded and the snapped position.
+    pub offset_meters: f64,
+}
+
+/// Map matching for anything with a GPS track.
+pub trait MapMatch {
+    /// Snaps each positioned Trackpoint onto the road network. Trackpoints without a position, or with no way within
+    /// the search radius, are omitted from the result.
+    ///
+    /// # Parameters
+    ///
+    /// `network: &N` -- The road network provider.
+    ///
+    /// `options: &MatchOptions` -- Matching options.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<MatchedPoint>` -- The matched points, in time order.
+    fn map_match<N: RoadNetwork + ?Sized>(
+        &self,
+        network: &N,
+        options: &MatchOptions,
+    ) -> Vec<MatchedPoint>;
+}
+
+impl MapMatch for Activity {
+    fn map_match<N: RoadNetwork + ?Sized>(
+        &self,
+        network: &N,
+        options: &MatchOptions,
+    ) -> Vec<MatchedPoint> {
+        match_points(self.trackpoints(), network, options)
+    }
+}
+
+impl MapMatch for Course {
+    fn map_match<N: RoadNetwork + ?Sized>(
+        &self,
+        network: &N,
+        options: &MatchOptions,
+    ) -> Vec<MatchedPoint> {
+        let points = self
+            .tracks
+            .iter()
+            .flatten()
+            .flat_map(|track| track.trackpoints.iter());
+        match_points(points, network, options)
+    }
+}
+
+/// Greedy matching with hysteresis: each point takes its nearest way, unless the way matched to the previous point
+/// is still nearby and not much farther away.
+fn match_points<'a, I, N>(points: I, network: &N, options: &MatchOptions) -> Vec<MatchedPoint>
+where
+    I: Iterator<Item = &'a Trackpoint>,
+    N: RoadNetwork + ?Sized,
+{
+    let mut matched: Vec<MatchedPoint> = Vec::new();
+    for point in points {
+        let position = match &point.position {
+            Some(position) => position,
+            None => continue,
+        };
+        let candidates = network.nearby_ways(position, options.search_radius_meters);
+        let nearest = candidates
+            .iter()
+            .filter(|candidate| candidate.distance_meters <= options.search_radius_meters)
+            .min_by(|a, b| a.distance_meters.total_cmp(&b.distance_meters));
+        let nearest = match nearest {
+            Some(nearest) => nearest,
+            None => continue,
+        };
+        let current = matched.last().and_then(|previous| {
+            candidates.iter().find(|candidate| {
+                candidate.way_id == previous.way_id
+                    && candidate.distance_meters <= options.search_radius_meters
+                    && candidate.distance_meters - nearest.distance_meters
+                        <= options.switch_penalty_meters
+            })
+        });
+        let chosen = current.unwrap_or(nearest);
+        matched.push(MatchedPoint {
+            time: point.time,
+            way_id: chosen.way_id,
+            surface: chosen.surface.clone(),
+            snapped: chosen.snapped.clone(),
+            offset_meters: chosen.distance_meters,
+        });
+    }
+    matched
+}
+
+/// Distance covered on each surface, measured along the snapped positions. A segment between two consecutive
+/// matched points is attributed to the surface of the point that ends it.
+///
+/// # Parameters
+///
+/// `matched: &[MatchedPoint]` -- Output of `MapMatch::map_match`.
+///
+/// # Returns
+///
+/// `BTreeMap<String, f64>` -- Meters per surface, with ways of unknown surface under `UNKNOWN_SURFACE`.
+pub fn surface_breakdown(matched: &[MatchedPoint]) -> BTreeMap<String, f64> {
+    let mut breakdown = BTreeMap::new();
+    for pair in matched.windows(2) {
+        let surface = pair[1].surface.as_deref().unwrap_or(UNKNOWN_SURFACE);
+        *breakdown.entry(surface.to_string()).or_insert(0.0) +=
+            haversine_distance(&pair[0].snapped, &pair[1].snapped);
+    }
+    breakdown
+}
@@ -0,0 +1,120 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! User-entered activity metadata (perceived exertion, title, equipment and tags) stored inside standard TCX.
+//! The metadata is written as a single tagged JSON line at the end of the activity's `Notes`, which any TCX consumer
+//! preserves, and can be recovered from files written by this crate.
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::tcx::Activity;
+
+/// Prefix of the line in `Notes` that holds the metadata.
+const META_MARKER: &str = "[tcx-meta]";
+
+/// Metadata entered by the athlete rather than recorded by the device.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ActivityMeta {
+    /// Session rating of perceived exertion, on the 1-10 scale.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpe: Option<u8>,
+
+    /// A title for the activity, such as "Tuesday track session".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    /// The equipment (shoes, bikes, etc.) used during the activity.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub equipment: Vec<String>,
+
+    /// Free-form tags.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl Activity {
+    /// Recovers the metadata previously attached with `set_meta`.
+    ///
+    /// # Parameters
+    ///
+    /// None. `&self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// `Option<ActivityMeta>` -- The metadata, or `None` if the activity has none (or it cannot be read).
+    pub fn meta(&self) -> Option<ActivityMeta> {
+        let notes = self.notes.as_ref()?;
+        let line = notes
+            .lines()
+            .rev()
+            .find(|line| line.trim_start().starts_with(META_MARKER))?;
+        serde_json::from_str(&line.trim_start()[META_MARKER.len()..]).ok()
+    }
+
+    /// Attaches metadata to the activity, replacing any metadata already present. The user's own notes are kept.
+    ///
+    /// # Parameters
+    ///
+    /// `meta: &ActivityMeta` -- The metadata to attach.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+    /// let meta = crate::tcx::meta::ActivityMeta {
+    ///     rpe: Some(6),
+    ///     equipment: vec![String::from("Trail shoes")],
+    ///     ..Default::default()
+    /// };
+    /// activity.set_meta(&meta);
+    /// assert_eq!(activity.meta(), Some(meta));
+    /// ```
+    pub fn set_meta(&mut self, meta: &ActivityMeta) {
+        let mut notes = self.user_notes().unwrap_or_default();
+        if !notes.is_empty() {
+            notes.push('\n');
+        }
+        notes.push_str(META_MARKER);
+        notes.push_str(&serde_json::to_string(meta).unwrap_or_default());
+        self.notes = Some(notes);
+    }
+
+    /// Removes any metadata attached with `set_meta`, leaving the user's own notes.
+    pub fn clear_meta(&mut self) {
+        let notes = self.user_notes();
+        self.notes = notes.filter(|notes| !notes.is_empty());
+    }
+
+    /// Returns the activity's notes without the metadata line.
+    pub fn user_notes(&self) -> Option<String> {
+        let notes = self.notes.as_ref()?;
+        let kept: Vec<&str> = notes
+            .lines()
+            .filter(|line| !line.trim_start().starts_with(META_MARKER))
+            .collect();
+        Some(kept.join("\n").trim_end().to_string())
+    }
+}
@@ -0,0 +1,546 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Memoizing the derived series that interactive tools ask for again and again, so that shared intermediates such
+//! as the rolling power behind normalized power are computed once per version of the activity, and computing a set
+//! of metrics over many activities at once (in parallel with the `rayon` feature). Also the choice of how power is
+//! smoothed behind normalized power and TSS, since platforms differ and their numbers only match when smoothed alike,
+//! and its running counterpart, normalized graded pace.
+
+use chrono::{DateTime, Utc};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::borrow::{Borrow, BorrowMut};
+use std::io::Write;
+use std::sync::OnceLock;
+
+use crate::geo::{grades, speeds};
+use crate::power::cost_of_running;
+use crate::stats::{exponential_power, normalized_power, rolling_average, rolling_power, Summary};
+use crate::tcx::{Activity, Trackpoint};
+
+/// The speed of each Trackpoint with one, scaled by the energy cost of running at its grade relative to the flat.
+fn grade_adjusted_speeds<'a>(points: &[&'a Trackpoint]) -> Vec<(&'a Trackpoint, f64)> {
+    let flat = cost_of_running(0.0);
+    points
+        .iter()
+        .zip(speeds(points).into_iter().zip(grades(points)))
+        .filter_map(|(point, (speed, grade))| {
+            speed.map(|speed| (*point, speed * cost_of_running(grade.unwrap_or(0.0)) / flat))
+        })
+        .collect()
+}
+
+/// The time constant of Skiba's xPower, in seconds.
+const XPOWER_TIME_CONSTANT_SECONDS: f64 = 25.0;
+
+/// How power is smoothed before the fourth-power mean behind normalized power. Pick the one used by the platform
+/// whose numbers are to be matched.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum PowerSmoothing {
+    /// A 30 second rolling average, as in Coggan's normalized power. The default.
+    #[default]
+    Rolling30,
+
+    /// A 25 second exponentially weighted average, as in Skiba's xPower.
+    XPower,
+
+    /// An exponentially weighted average with the given time constant, in seconds.
+    Exponential(f64),
+}
+
+impl PowerSmoothing {
+    /// Smooths the power of the given Trackpoints.
+    fn smooth(self, points: &[&Trackpoint]) -> Vec<(DateTime<Utc>, f64)> {
+        match self {
+            PowerSmoothing::Rolling30 => rolling_power(points),
+            PowerSmoothing::XPower => exponential_power(points, XPOWER_TIME_CONSTANT_SECONDS),
+            PowerSmoothing::Exponential(seconds) => exponential_power(points, seconds.max(1.0)),
+        }
+    }
+}
+
+/// An activity along with its derived metrics, each computed the first time it is asked for and then kept until the
+/// activity is changed through `activity_mut`. The activity is usually owned, but can be borrowed (`&Activity`) when
+/// it only needs reading.
+///
+/// # Example
+///
+/// ```rust
+/// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+/// let mut metrics = crate::tcx::metrics::MetricsCache::new(tcx.activities.unwrap().activities.remove(0));
+/// let np = metrics.normalized_power().unwrap();
+/// assert!(np >= metrics.summary().average_power.unwrap());
+///
+/// // Changing the activity throws the cached metrics away.
+/// metrics.activity_mut().laps.clear();
+/// assert!(metrics.normalized_power().is_none());
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct MetricsCache<A = Activity> {
+    activity: A,
+    speeds: OnceLock<Vec<(DateTime<Utc>, f64)>>,
+    grades: OnceLock<Vec<(DateTime<Utc>, f64)>>,
+    rolling_power: OnceLock<Vec<(DateTime<Utc>, f64)>>,
+    smoothed_power: OnceLock<Vec<(DateTime<Utc>, f64)>>,
+    normalized_power: OnceLock<Option<f64>>,
+    summary: OnceLock<Summary>,
+    smoothing: PowerSmoothing,
+}
+
+impl<A: Borrow<Activity>> MetricsCache<A> {
+    /// Takes an activity whose metrics are to be cached.
+    pub fn new(activity: A) -> Self {
+        MetricsCache::with_smoothing(activity, PowerSmoothing::default())
+    }
+
+    /// Takes an activity whose metrics are to be cached, with normalized power computed from power smoothed the
+    /// given way.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::metrics::{MetricsCache, PowerSmoothing};
+    ///
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let activity = &tcx.activities.as_ref().unwrap().activities[0];
+    /// let xpower = MetricsCache::with_smoothing(activity, PowerSmoothing::XPower).normalized_power().unwrap();
+    /// let np = MetricsCache::new(activity).normalized_power().unwrap();
+    /// assert!((xpower - np).abs() < 0.05 * np);
+    /// ```
+    pub fn with_smoothing(activity: A, smoothing: PowerSmoothing) -> Self {
+        MetricsCache {
+            activity,
+            speeds: OnceLock::new(),
+            grades: OnceLock::new(),
+            rolling_power: OnceLock::new(),
+            smoothed_power: OnceLock::new(),
+            normalized_power: OnceLock::new(),
+            summary: OnceLock::new(),
+            smoothing,
+        }
+    }
+
+    /// How power is smoothed for normalized power.
+    pub fn smoothing(&self) -> PowerSmoothing {
+        self.smoothing
+    }
+
+    /// The activity.
+    pub fn activity(&self) -> &Activity {
+        self.activity.borrow()
+    }
+
+    /// Throws away every cached metric.
+    pub fn invalidate(&mut self) {
+        self.speeds = OnceLock::new();
+        self.grades = OnceLock::new();
+        self.rolling_power = OnceLock::new();
+        self.smoothed_power = OnceLock::new();
+        self.normalized_power = OnceLock::new();
+        self.summary = OnceLock::new();
+    }
+
+    /// Returns the activity, dropping the cache.
+    pub fn into_activity(self) -> A {
+        self.activity
+    }
+
+    /// The speed at each Trackpoint. See `Activity::speed_series`.
+    pub fn speed_series(&self) -> &[(DateTime<Utc>, f64)] {
+        self.speeds.get_or_init(|| {
+            let points = self.points();
+            points
+                .iter()
+                .zip(speeds(&points))
+                .filter_map(|(point, speed)| speed.map(|speed| (point.time, speed)))
+                .collect()
+        })
+    }
+
+    /// The grade at each Trackpoint. See `Activity::grade_series`.
+    pub fn grade_series(&self) -> &[(DateTime<Utc>, f64)] {
+        self.grades.get_or_init(|| {
+            let points = self.points();
+            points
+                .iter()
+                .zip(grades(&points))
+                .filter_map(|(point, grade)| grade.map(|grade| (point.time, grade)))
+                .collect()
+        })
+    }
+
+    /// The 30 second rolling average power, in watts, at each Trackpoint with power from 30 seconds after the first
+    /// one on. Normalized power is computed from these unless another smoothing was chosen.
+    pub fn rolling_power(&self) -> &[(DateTime<Utc>, f64)] {
+        self.rolling_power
+            .get_or_init(|| rolling_power(&self.points()))
+    }
+
+    /// The power smoothed as chosen (see `PowerSmoothing`), in watts, at each Trackpoint with power from one window
+    /// or time constant after the first one on.
+    pub fn smoothed_power(&self) -> &[(DateTime<Utc>, f64)] {
+        match self.smoothing {
+            PowerSmoothing::Rolling30 => self.rolling_power(),
+            smoothing => self
+                .smoothed_power
+                .get_or_init(|| smoothing.smooth(&self.points())),
+        }
+    }
+
+    /// Normalized power, in watts, from the cached smoothed power.
+    pub fn normalized_power(&self) -> Option<f64> {
+        *self
+            .normalized_power
+            .get_or_init(|| normalized_power(self.smoothed_power()))
+    }
+
+    /// The summary statistics of the whole activity. See `Activity::summary`.
+    pub fn summary(&self) -> &Summary {
+        self.summary.get_or_init(|| self.activity().summary())
+    }
+
+    fn points(&self) -> Vec<&Trackpoint> {
+        self.activity().trackpoints().collect()
+    }
+}
+
+impl<A: BorrowMut<Activity>> MetricsCache<A> {
+    /// The activity, for changing it. Every cached metric is thrown away, since any of them may no longer hold.
+    pub fn activity_mut(&mut self) -> &mut Activity {
+        self.invalidate();
+        self.activity.borrow_mut()
+    }
+}
+
+impl Activity {
+    /// Computes normalized power from power smoothed the given way. With `PowerSmoothing::Rolling30` this is the
+    /// summary's normalized power; with `PowerSmoothing::XPower` it is Skiba's xPower.
+    ///
+    /// # Parameters
+    ///
+    /// `smoothing: PowerSmoothing` -- How power is smoothed before the fourth-power mean.
+    ///
+    /// # Returns
+    ///
+    /// `Option<f64>` -- The normalized power in Watts, or `None` without enough power data to smooth.
+    pub fn normalized_power_with(&self, smoothing: PowerSmoothing) -> Option<f64> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        normalized_power(&smoothing.smooth(&points))
+    }
+
+    /// Computes the grade-adjusted speed at each Trackpoint: the speed on the flat that would take as much effort,
+    /// from the energy cost of running at the Trackpoint's grade (Minetti et al., 2002). Trackpoints without a
+    /// grade, such as those in the first few meters or without altitude, count as flat.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<(DateTime<Utc>, f64)>` -- The time and grade-adjusted speed, in meters per second, of each Trackpoint
+    /// with a speed.
+    pub fn grade_adjusted_speed_series(&self) -> Vec<(DateTime<Utc>, f64)> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        grade_adjusted_speeds(&points)
+            .into_iter()
+            .map(|(point, speed)| (point.time, speed))
+            .collect()
+    }
+
+    /// Computes normalized graded pace (NGP), the running counterpart of normalized power: the grade-adjusted speed
+    /// (see `grade_adjusted_speed_series`) is smoothed and averaged as power is for normalized power, so that hills
+    /// and surges both count for more than the average pace suggests.
+    ///
+    /// # Returns
+    ///
+    /// `Option<f64>` -- The pace in seconds per kilometer, or `None` without at least 30 seconds of speed data.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let run = &tcx.activities.unwrap().activities[0];
+    /// let ngp = run.normalized_graded_pace().unwrap();
+    /// let average_pace = 1000.0 / run.summary().average_speed.unwrap();
+    /// assert!(ngp > 0.0 && ngp <= average_pace);
+    /// ```
+    pub fn normalized_graded_pace(&self) -> Option<f64> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        let speed = normalized_power(&rolling_average(&grade_adjusted_speeds(&points)))?;
+        (speed > 0.0).then(|| 1000.0 / speed)
+    }
+
+    /// Computes the Training Stress Score (see `training_stress_score`) from normalized power smoothed the given way,
+    /// to match the numbers of a platform that smooths differently.
+    ///
+    /// # Parameters
+    ///
+    /// `ftp: f64` -- The athlete's Functional Threshold Power in Watts.
+    ///
+    /// `smoothing: PowerSmoothing` -- How power is smoothed before the fourth-power mean.
+    ///
+    /// # Returns
+    ///
+    /// `Option<f64>` -- The Training Stress Score, or `None` without enough power data to smooth.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::metrics::PowerSmoothing;
+    ///
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let ride = &tcx.activities.unwrap().activities[0];
+    /// let tss = ride.training_stress_score_with(250.0, PowerSmoothing::Rolling30).unwrap();
+    /// assert!((tss - ride.training_stress_score(250.0).unwrap()).abs() < 1e-9);
+    /// assert!(ride.training_stress_score_with(250.0, PowerSmoothing::XPower).is_some());
+    /// ```
+    pub fn training_stress_score_with(&self, ftp: f64, smoothing: PowerSmoothing) -> Option<f64> {
+        if ftp <= 0.0 {
+            return None;
+        }
+        let intensity = self.normalized_power_with(smoothing)? / ftp;
+        let seconds = self.summary().elapsed_seconds;
+        Some(seconds * intensity * intensity / 3600.0 * 100.0)
+    }
+}
+
+impl From<Activity> for MetricsCache {
+    fn from(activity: Activity) -> Self {
+        MetricsCache::new(activity)
+    }
+}
+
+/// The metrics `compute_metrics_bulk` can compute. All are over the whole activity.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetricId {
+    /// Distance covered, in meters.
+    DistanceMeters,
+    /// Time from the first to the last Trackpoint, in seconds.
+    ElapsedSeconds,
+    /// Time spent moving, in seconds.
+    MovingSeconds,
+    /// Elevation gained, in meters.
+    ElevationGainMeters,
+    /// Elevation lost, in meters.
+    ElevationLossMeters,
+    /// Average moving speed, in meters per second.
+    AverageSpeed,
+    /// Maximum speed, in meters per second.
+    MaxSpeed,
+    /// Average heart rate, in beats per minute.
+    AverageHeartRate,
+    /// Maximum heart rate, in beats per minute.
+    MaxHeartRate,
+    /// Average power, in watts.
+    AveragePower,
+    /// Normalized power, in watts.
+    NormalizedPower,
+    /// Skiba's xPower, in watts.
+    XPower,
+    /// Maximum power, in watts.
+    MaxPower,
+    /// Average cadence, in revolutions or steps per minute.
+    AverageCadence,
+    /// Maximum cadence, in revolutions or steps per minute.
+    MaxCadence,
+    /// The number of Trackpoints.
+    Trackpoints,
+}
+
+impl MetricId {
+    /// Every metric, in column order.
+    pub const ALL: [MetricId; 16] = [
+        MetricId::DistanceMeters,
+        MetricId::ElapsedSeconds,
+        MetricId::MovingSeconds,
+        MetricId::ElevationGainMeters,
+        MetricId::ElevationLossMeters,
+        MetricId::AverageSpeed,
+        MetricId::MaxSpeed,
+        MetricId::AverageHeartRate,
+        MetricId::MaxHeartRate,
+        MetricId::AveragePower,
+        MetricId::NormalizedPower,
+        MetricId::XPower,
+        MetricId::MaxPower,
+        MetricId::AverageCadence,
+        MetricId::MaxCadence,
+        MetricId::Trackpoints,
+    ];
+
+    /// The column name used for the metric, such as `distance_meters`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MetricId::DistanceMeters => "distance_meters",
+            MetricId::ElapsedSeconds => "elapsed_seconds",
+            MetricId::MovingSeconds => "moving_seconds",
+            MetricId::ElevationGainMeters => "elevation_gain_meters",
+            MetricId::ElevationLossMeters => "elevation_loss_meters",
+            MetricId::AverageSpeed => "average_speed",
+            MetricId::MaxSpeed => "max_speed",
+            MetricId::AverageHeartRate => "average_heart_rate",
+            MetricId::MaxHeartRate => "max_heart_rate",
+            MetricId::AveragePower => "average_power",
+            MetricId::NormalizedPower => "normalized_power",
+            MetricId::XPower => "xpower",
+            MetricId::MaxPower => "max_power",
+            MetricId::AverageCadence => "average_cadence",
+            MetricId::MaxCadence => "max_cadence",
+            MetricId::Trackpoints => "trackpoints",
+        }
+    }
+
+    /// Computes the metric from an activity's cached metrics.
+    pub(crate) fn evaluate(&self, metrics: &MetricsCache<&Activity>) -> Option<f64> {
+        let summary = || metrics.summary();
+        match self {
+            MetricId::DistanceMeters => Some(summary().distance_meters),
+            MetricId::ElapsedSeconds => Some(summary().elapsed_seconds),
+            MetricId::MovingSeconds => Some(summary().moving_seconds),
+            MetricId::ElevationGainMeters => Some(summary().elevation_gain_meters),
+            MetricId::ElevationLossMeters => Some(summary().elevation_loss_meters),
+            MetricId::AverageSpeed => summary().average_speed,
+            MetricId::MaxSpeed => summary().max_speed,
+            MetricId::AverageHeartRate => summary().average_heart_rate,
+            MetricId::MaxHeartRate => summary().max_heart_rate,
+            MetricId::AveragePower => summary().average_power,
+            MetricId::NormalizedPower => metrics.normalized_power(),
+            MetricId::XPower => match metrics.smoothing() {
+                PowerSmoothing::XPower => metrics.normalized_power(),
+                _ => normalized_power(&PowerSmoothing::XPower.smooth(&metrics.points())),
+            },
+            MetricId::MaxPower => summary().max_power.map(f64::from),
+            MetricId::AverageCadence => summary().average_cadence,
+            MetricId::MaxCadence => summary().max_cadence.map(f64::from),
+            MetricId::Trackpoints => Some(metrics.activity().trackpoints().count() as f64),
+        }
+    }
+}
+
+/// The results of `compute_metrics_bulk`: one row per activity and one column per metric.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct MetricsTable {
+    /// The metrics, in column order.
+    pub metrics: Vec<MetricId>,
+
+    /// The rows, in the order of the activities.
+    pub rows: Vec<MetricsRow>,
+}
+
+/// One activity's results in a `MetricsTable`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct MetricsRow {
+    /// The activity's `Id`.
+    pub activity_id: String,
+
+    /// The value of each metric, in column order, or `None` where the activity has no data for it.
+    pub values: Vec<Option<f64>>,
+}
+
+impl MetricsTable {
+    /// Looks up one activity's value of a metric.
+    ///
+    /// # Parameters
+    ///
+    /// `row: usize` -- The index of the activity.
+    ///
+    /// `metric: MetricId` -- The metric.
+    ///
+    /// # Returns
+    ///
+    /// `Option<f64>` -- The value, or `None` if there is no such row or metric, or no data.
+    pub fn get(&self, row: usize, metric: MetricId) -> Option<f64> {
+        let column = self.metrics.iter().position(|m| *m == metric)?;
+        self.rows.get(row)?.values[column]
+    }
+
+    /// Writes the table as CSV, with an `activity_id` column followed by one column per metric named as by
+    /// `MetricId::name`. Missing values are left empty.
+    ///
+    /// # Parameters
+    ///
+    /// `writer: &mut W` -- Where to write the CSV.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), std::io::Error>` -- Nothing if OK, Error if not.
+    pub fn to_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut header = vec!["activity_id"];
+        header.extend(self.metrics.iter().map(MetricId::name));
+        writeln!(writer, "{}", header.join(","))?;
+        for row in &self.rows {
+            let mut fields = vec![crate::csv::quote(&row.activity_id)];
+            fields.extend(
+                row.values
+                    .iter()
+                    .map(|value| value.map(|value| value.to_string()).unwrap_or_default()),
+            );
+            writeln!(writer, "{}", fields.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes a set of metrics over many activities in one go. Each activity's shared intermediates, such as its
+/// summary and rolling power, are computed once however many metrics need them. With the `rayon` feature the
+/// activities are evaluated in parallel.
+///
+/// # Parameters
+///
+/// `activities: &[Activity]` -- The activities.
+///
+/// `metrics: &[MetricId]` -- The metrics to compute, in the column order wanted.
+///
+/// # Returns
+///
+/// `MetricsTable` -- One row per activity, in order.
+///
+/// # Example
+///
+/// ```rust
+/// use crate::tcx::metrics::{compute_metrics_bulk, MetricId};
+///
+/// let run = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+/// let ride = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+/// let activities: Vec<_> = vec![run, ride].into_iter().flat_map(|tcx| tcx.activities.unwrap().activities).collect();
+/// let table = compute_metrics_bulk(&activities, &[MetricId::DistanceMeters, MetricId::NormalizedPower]);
+/// assert!(table.get(0, MetricId::NormalizedPower).is_none());
+/// assert!(table.get(1, MetricId::NormalizedPower).unwrap() > 200.0);
+/// ```
+pub fn compute_metrics_bulk(activities: &[Activity], metrics: &[MetricId]) -> MetricsTable {
+    let row = |activity: &Activity| {
+        let cache = MetricsCache::new(activity);
+        MetricsRow {
+            activity_id: activity.id.clone(),
+            values: metrics
+                .iter()
+                .map(|metric| metric.evaluate(&cache))
+                .collect(),
+        }
+    };
+    #[cfg(feature = "rayon")]
+    let rows = activities.par_iter().map(row).collect();
+    #[cfg(not(feature = "rayon"))]
+    let rows = activities.iter().map(row).collect();
+    MetricsTable {
+        metrics: metrics.to_vec(),
+        rows,
+    }
+}
@@ -0,0 +1,99 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Compact MessagePack serialization, for caching parsed databases in Redis or on disk where reading them back is
+//! far faster than parsing the XML again. Requires the `msgpack` feature. Fields are written by name, so a cache
+//! written by one version of this crate can be read by another that adds fields.
+
+use std::error::Error;
+use std::io::Read;
+
+use crate::storage::{TcxSink, TcxSource};
+use crate::tcx::TrainingCenterDatabase;
+
+impl TrainingCenterDatabase {
+    /// Serializes the database as MessagePack.
+    ///
+    /// # Returns
+    ///
+    /// `Result<Vec<u8>, rmp_serde::encode::Error>` -- The bytes if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let bytes = tcx.to_msgpack().unwrap();
+    /// let cached = crate::tcx::TrainingCenterDatabase::from_msgpack(&bytes).unwrap();
+    /// assert_eq!(cached.to_msgpack().unwrap(), bytes);
+    /// ```
+    pub fn to_msgpack(&self) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+        rmp_serde::to_vec_named(&*self.refreshed())
+    }
+
+    /// Reads a database serialized with `to_msgpack`.
+    ///
+    /// # Parameters
+    ///
+    /// `bytes: &[u8]` -- The MessagePack bytes.
+    ///
+    /// # Returns
+    ///
+    /// `Result<TrainingCenterDatabase, rmp_serde::decode::Error>` -- The database if OK, Error if not.
+    pub fn from_msgpack(bytes: &[u8]) -> Result<TrainingCenterDatabase, rmp_serde::decode::Error> {
+        rmp_serde::from_slice(bytes)
+    }
+
+    /// Writes the database as MessagePack to any sink. See `to_msgpack`.
+    ///
+    /// # Parameters
+    ///
+    /// `sink: &mut S` -- Where to write, such as a `PathBuf` or a `Vec<u8>`.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn write_msgpack_to<S: TcxSink + ?Sized>(
+        &self,
+        sink: &mut S,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut writer = sink.create()?;
+        rmp_serde::encode::write_named(&mut writer, &*self.refreshed())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads a database serialized with `to_msgpack` from any source.
+    ///
+    /// # Parameters
+    ///
+    /// `source: &mut S` -- Where to read from, such as a `PathBuf` or a byte slice.
+    ///
+    /// # Returns
+    ///
+    /// `Result<TrainingCenterDatabase, Box<dyn Error>>` -- The database if OK, Error if not.
+    pub fn read_msgpack<S: TcxSource + ?Sized>(
+        source: &mut S,
+    ) -> Result<TrainingCenterDatabase, Box<dyn Error>> {
+        let mut bytes = Vec::new();
+        source.open()?.read_to_end(&mut bytes)?;
+        Ok(TrainingCenterDatabase::from_msgpack(&bytes)?)
+    }
+}
@@ -0,0 +1,199 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Leg-by-leg analysis of multisport sessions, such as triathlon race files: how long each transition took, and a
+//! summary of every leg and transition in order (swim, T1, bike, T2, run).
+
+use chrono::{DateTime, Duration, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::sport::Sport;
+use crate::stats::Summary;
+use crate::tcx::{Activity, ActivityLap, MultiSportSession};
+
+/// The time spent in one transition between legs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TransitionTime {
+    /// `T1`, `T2` and so on.
+    pub label: String,
+
+    /// When the transition started, if known.
+    pub start_time: Option<DateTime<Utc>>,
+
+    /// How long the transition took, in seconds.
+    pub duration_seconds: f64,
+
+    /// Whether the device recorded the transition. If not, its time is the gap between the legs either side.
+    pub recorded: bool,
+}
+
+/// A leg or transition of a multisport session, with its totals.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LegSummary {
+    /// `Swim`, `Bike` or `Run` for legs (`Other` for legs other than a leading swim), `T1`, `T2` and so on for
+    /// transitions.
+    pub label: String,
+
+    /// The sport of a leg, or `None` for a transition.
+    pub sport: Option<Sport>,
+
+    /// When the leg started, if known.
+    pub start_time: Option<DateTime<Utc>>,
+
+    /// The time recorded in the laps, in seconds.
+    pub duration_seconds: f64,
+
+    /// The distance recorded in the laps, in meters.
+    pub distance_meters: f64,
+
+    /// Statistics computed from the Trackpoints. Empty for transitions without any.
+    pub summary: Summary,
+}
+
+impl MultiSportSession {
+    /// Lists the time spent in each transition. A transition the device did not record is timed as the gap between
+    /// the end of one leg and the start of the next.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<TransitionTime>` -- One per pair of consecutive legs, in order.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210704_triathlon_multisport.tcx").unwrap();
+    /// let session = &tcx.activities.unwrap().multi_sport_sessions[0];
+    /// let times: Vec<f64> = session.transition_times().iter().map(|t| t.duration_seconds).collect();
+    /// assert_eq!(times, vec![45.0, 30.0]);
+    /// ```
+    pub fn transition_times(&self) -> Vec<TransitionTime> {
+        let mut previous = &self.first_sport.activity;
+        let mut times = Vec::with_capacity(self.next_sports.len());
+        for (i, next) in self.next_sports.iter().enumerate() {
+            let label = format!("T{}", i + 1);
+            times.push(match &next.transition {
+                Some(lap) => TransitionTime {
+                    label,
+                    start_time: lap.start_time,
+                    duration_seconds: lap.total_time_seconds,
+                    recorded: true,
+                },
+                None => {
+                    let end = end_time(previous);
+                    let gap = end
+                        .zip(start_time(&next.activity))
+                        .map(|(end, start)| (start - end).num_milliseconds() as f64 / 1000.0);
+                    TransitionTime {
+                        label,
+                        start_time: end,
+                        duration_seconds: gap.unwrap_or(0.0).max(0.0),
+                        recorded: false,
+                    }
+                }
+            });
+            previous = &next.activity;
+        }
+        times
+    }
+
+    /// Summarizes every leg and transition in order. Transitions the device did not record are included with the
+    /// time between the legs either side (see `transition_times`).
+    ///
+    /// # Returns
+    ///
+    /// `Vec<LegSummary>` -- The legs and transitions, in order.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210704_triathlon_multisport.tcx").unwrap();
+    /// let session = &tcx.activities.unwrap().multi_sport_sessions[0];
+    /// let labels: Vec<String> = session.leg_summaries().into_iter().map(|leg| leg.label).collect();
+    /// assert_eq!(labels, vec!["Swim", "T1", "Bike", "T2", "Run"]);
+    /// ```
+    pub fn leg_summaries(&self) -> Vec<LegSummary> {
+        let transitions = self.transition_times();
+        let mut legs = vec![leg_summary(&self.first_sport.activity, true)];
+        for (next, time) in self.next_sports.iter().zip(transitions) {
+            legs.push(match &next.transition {
+                Some(lap) => transition_summary(lap, time),
+                None => LegSummary {
+                    label: time.label,
+                    sport: None,
+                    start_time: time.start_time,
+                    duration_seconds: time.duration_seconds,
+                    distance_meters: 0.0,
+                    summary: Summary::default(),
+                },
+            });
+            legs.push(leg_summary(&next.activity, false));
+        }
+        legs
+    }
+}
+
+fn leg_summary(activity: &Activity, first: bool) -> LegSummary {
+    let sport = Sport::from_name(&activity.sport);
+    let label = match sport {
+        Sport::Running => "Run",
+        Sport::Biking => "Bike",
+        Sport::Other if first => "Swim",
+        Sport::Other => "Other",
+    };
+    LegSummary {
+        label: label.to_string(),
+        sport: Some(sport),
+        start_time: start_time(activity),
+        duration_seconds: activity.laps.iter().map(|lap| lap.total_time_seconds).sum(),
+        distance_meters: activity.laps.iter().map(|lap| lap.distance_meters).sum(),
+        summary: activity.summary(),
+    }
+}
+
+fn transition_summary(lap: &ActivityLap, time: TransitionTime) -> LegSummary {
+    LegSummary {
+        label: time.label,
+        sport: None,
+        start_time: time.start_time,
+        duration_seconds: lap.total_time_seconds,
+        distance_meters: lap.distance_meters,
+        summary: lap.summary(),
+    }
+}
+
+/// When a leg started: its first lap, or failing that its first Trackpoint.
+fn start_time(activity: &Activity) -> Option<DateTime<Utc>> {
+    activity
+        .laps
+        .iter()
+        .find_map(|lap| lap.start_time)
+        .or_else(|| activity.trackpoints().next().map(|point| point.time))
+}
+
+/// When a leg ended: the end of its last timed lap, or its last Trackpoint if that is later.
+fn end_time(activity: &Activity) -> Option<DateTime<Utc>> {
+    let lap_end = activity.laps.iter().rev().find_map(|lap| {
+        lap.start_time
+            .map(|start| start + Duration::milliseconds((lap.total_time_seconds * 1000.0) as i64))
+    });
+    let last_point = activity.trackpoints().last().map(|point| point.time);
+    lap_end.max(last_point)
+}
@@ -0,0 +1,335 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Canonical formatting of a parsed document, so that equivalent files produce identical output.
+
+use chrono::SecondsFormat;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::geo::seconds_between;
+use crate::sport::Sport;
+use crate::tcx::{
+    Activity, CourseFolder, HistoryFolder, Intensity, TrainingCenterDatabase, TriggerMethod,
+    WorkoutFolder, WorkoutStep,
+};
+
+impl Activity {
+    /// Puts the activity into canonical form: Trackpoints are sorted by time, the sport uses the schema's casing,
+    /// a missing `Id` is filled from the first Trackpoint and notes and the creator's name are cleaned up (see
+    /// `normalize_text`).
+    ///
+    /// # Parameters
+    ///
+    /// None. `&mut self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn normalize(&mut self) {
+        for lap in self.laps.iter_mut() {
+            for track in lap.tracks.iter_mut() {
+                track.trackpoints.sort_by_key(|point| point.time);
+            }
+            normalize_notes(&mut lap.notes);
+        }
+        normalize_notes(&mut self.notes);
+        if let Some(creator) = self.creator.as_mut() {
+            normalize_name(&mut creator.name);
+        }
+
+        if let Some(sport) = Sport::known(&self.sport) {
+            self.sport = sport.name().to_string();
+        }
+
+        self.fill_missing_id();
+    }
+
+    /// Fills in values for the schema-required fields that a programmatically built activity may have left at their
+    /// Rust defaults, so that the activity can be written out as valid TCX. The sport defaults to `Other`, the `Id`
+    /// to the time of the first Trackpoint, lap intensity to `Active` and the lap trigger method to `Manual`.
+    /// Zero lap durations and distances are computed from the lap's Trackpoints when possible.
+    ///
+    /// # Parameters
+    ///
+    /// None. `&mut self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn fill_required_defaults(&mut self) {
+        if self.sport.trim().is_empty() {
+            self.sport = String::from("Other");
+        }
+        self.fill_missing_id();
+
+        for lap in self.laps.iter_mut() {
+            if lap.intensity.is_none() {
+                lap.intensity = Some(Intensity::Active);
+            }
+            if lap.trigger_method.is_none() {
+                lap.trigger_method = Some(TriggerMethod::Manual);
+            }
+
+            let (elapsed, distance) = {
+                let mut points = lap.trackpoints();
+                match points.next() {
+                    Some(first) => {
+                        let last = points.last().unwrap_or(first);
+                        let distance = match (first.distance_meters, last.distance_meters) {
+                            (Some(start), Some(end)) => Some(end - start),
+                            _ => None,
+                        };
+                        (Some(seconds_between(first, last)), distance)
+                    }
+                    None => (None, None),
+                }
+            };
+            if lap.total_time_seconds == 0.0 {
+                if let Some(elapsed) = elapsed {
+                    lap.total_time_seconds = elapsed;
+                }
+            }
+            if lap.distance_meters == 0.0 {
+                if let Some(distance) = distance {
+                    lap.distance_meters = distance;
+                }
+            }
+        }
+    }
+
+    /// Sets the `Id` to the time of the first Trackpoint if it is empty.
+    fn fill_missing_id(&mut self) {
+        if self.id.trim().is_empty() {
+            let first_time = self.trackpoints().next().map(|point| point.time);
+            if let Some(time) = first_time {
+                self.id = time.to_rfc3339_opts(SecondsFormat::Millis, true);
+            }
+        }
+    }
+}
+
+impl TrainingCenterDatabase {
+    /// Puts the whole document into canonical form so that archives can be diffed deterministically.
+    /// Every activity, including the legs of multisport sessions, is normalized (see `Activity::normalize`) and
+    /// activities are sorted by start time. The notes and names of courses, course points, workouts, workout steps,
+    /// folders and the author are cleaned up as well (see `normalize_text`), along with the folders' references to
+    /// courses and workouts by name, so that they still match.
+    ///
+    /// # Parameters
+    ///
+    /// None. `&mut self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// tcx.normalize();
+    /// ```
+    pub fn normalize(&mut self) {
+        if let Some(activities) = self.activities.as_mut() {
+            for activity in activities.activities.iter_mut() {
+                activity.normalize();
+            }
+            activities
+                .activities
+                .sort_by_key(|activity| activity.start_time());
+            for session in activities.multi_sport_sessions.iter_mut() {
+                session.first_sport.activity.normalize();
+                for next in session.next_sports.iter_mut() {
+                    next.activity.normalize();
+                }
+                normalize_notes(&mut session.notes);
+            }
+        }
+
+        if let Some(courses) = self.courses.as_mut() {
+            for course in courses.courses.iter_mut() {
+                normalize_name(&mut course.name);
+                normalize_notes(&mut course.notes);
+                for point in course.course_points.iter_mut() {
+                    normalize_notes(&mut point.name);
+                    normalize_notes(&mut point.notes);
+                }
+            }
+        }
+        if let Some(workouts) = self.workouts.as_mut() {
+            for workout in workouts.workouts.iter_mut() {
+                normalize_name(&mut workout.name);
+                normalize_notes(&mut workout.notes);
+                normalize_steps(&mut workout.steps);
+            }
+        }
+        if let Some(author) = self.author.as_mut() {
+            normalize_name(&mut author.name);
+        }
+
+        if let Some(folders) = self.folders.as_mut() {
+            if let Some(history) = folders.history.as_mut() {
+                let sports = vec![
+                    history.running.as_mut(),
+                    history.biking.as_mut(),
+                    history.other.as_mut(),
+                    history.multi_sport.as_mut(),
+                ];
+                for folder in sports.into_iter().flatten() {
+                    normalize_history_folder(folder);
+                }
+            }
+            if let Some(folder) = folders
+                .courses
+                .as_mut()
+                .and_then(|courses| courses.folder.as_mut())
+            {
+                normalize_course_folder(folder);
+            }
+            if let Some(workouts) = folders.workouts.as_mut() {
+                let sports = vec![
+                    workouts.running.as_mut(),
+                    workouts.biking.as_mut(),
+                    workouts.other.as_mut(),
+                ];
+                for folder in sports.into_iter().flatten() {
+                    normalize_workout_folder(folder);
+                }
+            }
+        }
+    }
+
+    /// Fills in the schema-required fields of every activity that were left at their Rust defaults
+    /// (see `Activity::fill_required_defaults`). Call this before writing a programmatically built document.
+    ///
+    /// # Parameters
+    ///
+    /// None. `&mut self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::TrainingCenterDatabase::default();
+    /// tcx.activities = Some(crate::tcx::Activities {
+    ///     activities: vec![crate::tcx::Activity::default()],
+    ///     ..Default::default()
+    /// });
+    /// tcx.fill_required_defaults();
+    /// assert_eq!(tcx.activities.unwrap().activities[0].sport, "Other");
+    /// ```
+    pub fn fill_required_defaults(&mut self) {
+        if let Some(activities) = self.activities.as_mut() {
+            for activity in activities.activities.iter_mut() {
+                activity.fill_required_defaults();
+            }
+        }
+    }
+}
+
+/// Cleans up free text such as notes and names: the text is converted to Unicode normalization form C (so that,
+/// for example, an `e` followed by a combining accent becomes a single `é`), Windows line endings become `\n`,
+/// control characters that XML cannot represent are dropped and surrounding whitespace is trimmed.
+///
+/// # Parameters
+///
+/// `text: &str` -- The text to clean up.
+///
+/// # Returns
+///
+/// `String` -- The normalized text.
+///
+/// # Example
+///
+/// ```rust
+/// assert_eq!(crate::tcx::normalize::normalize_text(" Zoe\u{301}\r\nrun\u{1} "), "Zoé\nrun");
+/// ```
+pub fn normalize_text(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .nfc()
+        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Normalizes a required name, which is kept even if nothing is left.
+fn normalize_name(name: &mut String) {
+    *name = normalize_text(name);
+}
+
+/// Normalizes the names of workout steps, including those within repeat blocks.
+fn normalize_steps(steps: &mut [WorkoutStep]) {
+    for step in steps.iter_mut() {
+        normalize_notes(&mut step.name);
+        normalize_steps(&mut step.children);
+    }
+}
+
+/// Normalizes the name and notes of a history folder and its sub-folders.
+fn normalize_history_folder(folder: &mut HistoryFolder) {
+    normalize_name(&mut folder.name);
+    normalize_notes(&mut folder.notes);
+    for week in folder.weeks.iter_mut() {
+        normalize_notes(&mut week.notes);
+    }
+    for child in folder.folders.iter_mut() {
+        normalize_history_folder(child);
+    }
+}
+
+/// Normalizes the name, notes and course references of a course folder and its sub-folders.
+fn normalize_course_folder(folder: &mut CourseFolder) {
+    normalize_name(&mut folder.name);
+    normalize_notes(&mut folder.notes);
+    for reference in folder.course_name_refs.iter_mut() {
+        normalize_name(&mut reference.id);
+    }
+    for child in folder.folders.iter_mut() {
+        normalize_course_folder(child);
+    }
+}
+
+/// Normalizes the name and workout references of a workout folder and its sub-folders.
+fn normalize_workout_folder(folder: &mut WorkoutFolder) {
+    normalize_name(&mut folder.name);
+    for reference in folder.workout_name_refs.iter_mut() {
+        normalize_name(&mut reference.id);
+    }
+    for child in folder.folders.iter_mut() {
+        normalize_workout_folder(child);
+    }
+}
+
+/// Normalizes notes, dropping them entirely if nothing is left.
+fn normalize_notes(notes: &mut Option<String>) {
+    if let Some(text) = notes.as_ref() {
+        let normalized = normalize_text(text);
+        *notes = if normalized.is_empty() {
+            None
+        } else {
+            Some(normalized)
+        };
+    }
+}
@@ -0,0 +1,56 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Reading TCX files directly from an object store (S3, GCS, Azure, local or in-memory), so that cloud ingestion
+//! services do not need to stage files on disk. Requires the `object_store` feature.
+
+use object_store::path::Path;
+use object_store::ObjectStore;
+use std::error::Error;
+
+use crate::tcx::TrainingCenterDatabase;
+
+/// Fetches an object from the store and parses it as TCX.
+///
+/// # Parameters
+///
+/// `store: &dyn ObjectStore` -- The store to read from.
+///
+/// `path: &Path` -- The location of the TCX file within the store.
+///
+/// # Returns
+///
+/// `Result<TrainingCenterDatabase, Box<dyn Error + Send + Sync>>` -- The parsed file, or the storage or parse error.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let store = object_store::aws::AmazonS3Builder::from_env().with_bucket_name("activities").build()?;
+/// let tcx = crate::tcx::object::read_object(&store, &"2021/run.tcx".into()).await?;
+/// ```
+pub async fn read_object(
+    store: &dyn ObjectStore,
+    path: &Path,
+) -> Result<TrainingCenterDatabase, Box<dyn Error + Send + Sync>> {
+    let bytes = store.get(path).await?.bytes().await?;
+    let database = crate::storage::read_from(&mut bytes.as_ref(), &Default::default())?;
+    Ok(database)
+}
@@ -0,0 +1,372 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! JSON Patch ([RFC 6902](https://datatracker.ietf.org/doc/html/rfc6902)) support, so that edits to a database can be
+//! transmitted without sending the whole document.
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+
+use crate::tcx::TrainingCenterDatabase;
+
+/// A single JSON Patch operation. Serializes to the RFC 6902 representation, e.g. `{"op":"replace","path":"/a","value":1}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    /// Adds a value to an object or inserts it into an array.
+    Add { path: String, value: Value },
+
+    /// Removes the value at the path.
+    Remove { path: String },
+
+    /// Replaces the value at the path, which must already exist.
+    Replace { path: String, value: Value },
+}
+
+/// Produces the JSON Patch that turns the JSON export of `old` into the JSON export of `new`.
+///
+/// # Parameters
+///
+/// `old: &TrainingCenterDatabase` -- The previous version of the database.
+///
+/// `new: &TrainingCenterDatabase` -- The current version of the database.
+///
+/// # Returns
+///
+/// `Result<Vec<PatchOperation>, serde_json::Error>` -- The operations, which serialize to an RFC 6902 document.
+///
+/// # Example
+///
+/// ```rust
+/// let old = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+/// let mut new = old.clone();
+/// new.activities.as_mut().unwrap().activities[0].notes = Some(String::from("Felt good"));
+/// let patch = crate::tcx::patch::diff_json(&old, &new).unwrap();
+/// assert_eq!(
+///     serde_json::to_string(&patch).unwrap(),
+///     r#"[{"op":"replace","path":"/Activities/Activity/0/Notes","value":"Felt good"}]"#
+/// );
+/// ```
+pub fn diff_json(
+    old: &TrainingCenterDatabase,
+    new: &TrainingCenterDatabase,
+) -> Result<Vec<PatchOperation>, serde_json::Error> {
+    let mut operations = Vec::new();
+    diff_values(
+        &serde_json::to_value(old)?,
+        &serde_json::to_value(new)?,
+        "",
+        &mut operations,
+    );
+    Ok(operations)
+}
+
+/// Applies a JSON Patch, as produced by `diff_json`, to a database.
+///
+/// # Parameters
+///
+/// `database: &TrainingCenterDatabase` -- The database to patch.
+///
+/// `operations: &[PatchOperation]` -- The operations to apply, in order.
+///
+/// # Returns
+///
+/// `Result<TrainingCenterDatabase, Box<dyn Error>>` -- The patched database, or an error if a path is malformed or
+/// does not exist.
+pub fn apply_json_patch(
+    database: &TrainingCenterDatabase,
+    operations: &[PatchOperation],
+) -> Result<TrainingCenterDatabase, Box<dyn Error>> {
+    let mut document = serde_json::to_value(database)?;
+    for operation in operations {
+        apply_operation(&mut document, operation)?;
+    }
+    Ok(serde_json::from_value(document)?)
+}
+
+/// Appends the operations that turn `old` into `new` (both found at `path`) to `operations`.
+fn diff_values(old: &Value, new: &Value, path: &str, operations: &mut Vec<PatchOperation>) {
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            for (key, old_value) in old_map {
+                let child = format!("{}/{}", path, escape(key));
+                match new_map.get(key) {
+                    Some(new_value) => diff_values(old_value, new_value, &child, operations),
+                    None => operations.push(PatchOperation::Remove { path: child }),
+                }
+            }
+            for (key, new_value) in new_map {
+                if !old_map.contains_key(key) {
+                    operations.push(PatchOperation::Add {
+                        path: format!("{}/{}", path, escape(key)),
+                        value: new_value.clone(),
+                    });
+                }
+            }
+        }
+        (Value::Array(old_items), Value::Array(new_items)) => {
+            // Walk the alignment, tracking each item's index in the array as patched so far.
+            let mut index = 0;
+            for step in align(old_items, new_items) {
+                let child = format!("{}/{}", path, index);
+                match step {
+                    Step::Keep(i, j) => {
+                        diff_values(&old_items[i], &new_items[j], &child, operations);
+                        index += 1;
+                    }
+                    Step::Remove => operations.push(PatchOperation::Remove { path: child }),
+                    Step::Insert(j) => {
+                        operations.push(PatchOperation::Add {
+                            path: child,
+                            value: new_items[j].clone(),
+                        });
+                        index += 1;
+                    }
+                }
+            }
+        }
+        _ => {
+            if old != new {
+                operations.push(PatchOperation::Replace {
+                    path: path.to_string(),
+                    value: new.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// The largest number of item pairs compared when aligning arrays without keys. Beyond this, the items between the
+/// common start and end are paired by position.
+const MAX_ALIGNMENT_CELLS: usize = 1_000_000;
+
+/// One step in turning an old array into a new one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    /// The old item at the first index becomes the new item at the second, with any differences patched within it.
+    Keep(usize, usize),
+    /// The next old item is removed.
+    Remove,
+    /// The new item at the index is inserted.
+    Insert(usize),
+}
+
+/// The key that identifies an array item across versions: the `Time` of a Trackpoint or course point, the `StartTime`
+/// of a lap or the `Id` of an activity.
+fn key(item: &Value) -> Option<&str> {
+    ["Time", "StartTime", "Id"]
+        .iter()
+        .find_map(|name| item.get(name))
+        .and_then(Value::as_str)
+}
+
+/// Aligns two arrays, so that a single insertion or removal becomes a single operation rather than a change to every
+/// later item. Arrays whose items all have keys (see `key`) are aligned by key, in order. Others are aligned on their
+/// longest common subsequence of equal items, with any runs of removed and inserted items paired up, so that an
+/// item edited in place is patched within rather than replaced.
+fn align(old: &[Value], new: &[Value]) -> Vec<Step> {
+    let old_keys: Option<Vec<&str>> = old.iter().map(key).collect();
+    let new_keys: Option<Vec<&str>> = new.iter().map(key).collect();
+    if let (Some(old_keys), Some(new_keys)) = (old_keys, new_keys) {
+        return merge_by_key(&old_keys, &new_keys);
+    }
+
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let (old_middle, new_middle) = (old.len() - suffix, new.len() - suffix);
+
+    let mut steps: Vec<Step> = (0..prefix).map(|i| Step::Keep(i, i)).collect();
+    let middle = if (old_middle - prefix) * (new_middle - prefix) <= MAX_ALIGNMENT_CELLS {
+        longest_common_subsequence(&old[prefix..old_middle], &new[prefix..new_middle], prefix)
+    } else {
+        Vec::new()
+    };
+    steps.extend(pair_changes(middle, prefix..old_middle, prefix..new_middle));
+    steps.extend((0..suffix).map(|k| Step::Keep(old_middle + k, new_middle + k)));
+    steps
+}
+
+/// Aligns items by key, assuming both arrays are in key order, as times are.
+fn merge_by_key(old: &[&str], new: &[&str]) -> Vec<Step> {
+    let (mut i, mut j) = (0, 0);
+    let mut steps = Vec::with_capacity(old.len().max(new.len()));
+    while i < old.len() || j < new.len() {
+        match (old.get(i), new.get(j)) {
+            (Some(old_key), Some(new_key)) if old_key == new_key => {
+                steps.push(Step::Keep(i, j));
+                i += 1;
+                j += 1;
+            }
+            (Some(old_key), Some(new_key)) if old_key > new_key => {
+                steps.push(Step::Insert(j));
+                j += 1;
+            }
+            (Some(_), _) => {
+                steps.push(Step::Remove);
+                i += 1;
+            }
+            (None, _) => {
+                steps.push(Step::Insert(j));
+                j += 1;
+            }
+        }
+    }
+    steps
+}
+
+/// The pairs of indices, offset by `offset`, of the longest common subsequence of equal items.
+fn longest_common_subsequence(old: &[Value], new: &[Value], offset: usize) -> Vec<(usize, usize)> {
+    // lengths[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let width = new.len() + 1;
+    let mut lengths = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i * width + j] = if old[i] == new[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut pairs = Vec::new();
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            pairs.push((offset + i, offset + j));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// Turns the matched pairs of the old and new ranges into steps, pairing the unmatched items between each match by
+/// position and removing or inserting the rest.
+fn pair_changes(
+    matches: Vec<(usize, usize)>,
+    old: std::ops::Range<usize>,
+    new: std::ops::Range<usize>,
+) -> Vec<Step> {
+    let mut steps = Vec::new();
+    let (mut i, mut j) = (old.start, new.start);
+    for (next_i, next_j) in matches
+        .into_iter()
+        .chain(std::iter::once((old.end, new.end)))
+    {
+        let paired = (next_i - i).min(next_j - j);
+        steps.extend((0..paired).map(|k| Step::Keep(i + k, j + k)));
+        steps.extend((i + paired..next_i).map(|_| Step::Remove));
+        steps.extend((j + paired..next_j).map(Step::Insert));
+        if next_i < old.end {
+            steps.push(Step::Keep(next_i, next_j));
+        }
+        i = next_i + 1;
+        j = next_j + 1;
+    }
+    steps
+}
+
+/// Applies a single operation to the document.
+fn apply_operation(document: &mut Value, operation: &PatchOperation) -> Result<(), Box<dyn Error>> {
+    let path = match operation {
+        PatchOperation::Add { path, .. }
+        | PatchOperation::Remove { path }
+        | PatchOperation::Replace { path, .. } => path,
+    };
+    if path.is_empty() {
+        if let PatchOperation::Add { value, .. } | PatchOperation::Replace { value, .. } = operation
+        {
+            *document = value.clone();
+            return Ok(());
+        }
+        return Err("cannot remove the whole document".into());
+    }
+    if !path.starts_with('/') {
+        return Err(format!("invalid path: {}", path).into());
+    }
+
+    let (parent_path, key) = path.split_at(path.rfind('/').unwrap_or(0));
+    let key = unescape(&key[1..]);
+    let parent = document
+        .pointer_mut(parent_path)
+        .ok_or_else(|| format!("path not found: {}", parent_path))?;
+
+    match (parent, operation) {
+        (Value::Object(map), PatchOperation::Remove { .. }) => {
+            map.remove(&key)
+                .ok_or_else(|| format!("path not found: {}", path))?;
+        }
+        (Value::Object(map), PatchOperation::Add { value, .. }) => {
+            map.insert(key, value.clone());
+        }
+        (Value::Object(map), PatchOperation::Replace { value, .. }) => {
+            let target = map
+                .get_mut(&key)
+                .ok_or_else(|| format!("path not found: {}", path))?;
+            *target = value.clone();
+        }
+        (Value::Array(items), operation) => {
+            let index = if key == "-" {
+                items.len()
+            } else {
+                key.parse::<usize>()?
+            };
+            match operation {
+                PatchOperation::Add { value, .. } if index <= items.len() => {
+                    items.insert(index, value.clone())
+                }
+                PatchOperation::Remove { .. } if index < items.len() => {
+                    items.remove(index);
+                }
+                PatchOperation::Replace { value, .. } if index < items.len() => {
+                    items[index] = value.clone()
+                }
+                _ => return Err(format!("index out of range: {}", path).into()),
+            }
+        }
+        _ => return Err(format!("path not found: {}", path).into()),
+    }
+    Ok(())
+}
+
+/// Escapes a key for use in a JSON Pointer.
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Reverses `escape`.
+fn unescape(key: &str) -> String {
+    key.replace("~1", "/").replace("~0", "~")
+}
@@ -0,0 +1,451 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Power estimation for athletes without a power meter, and checking the zero offset of those with one.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::{grades, seconds_between, speeds};
+use crate::tcx::{Activity, Extensions, HeartRate, Ns3Tpx, Trackpoint};
+
+/// Density of air at sea level, in kg/m^3.
+const AIR_DENSITY: f64 = 1.225;
+
+/// Drag coefficient of an upright runner.
+const RUNNER_DRAG_COEFFICIENT: f64 = 0.9;
+
+/// Steepest grade (either direction) over which Minetti's cost-of-running polynomial was measured.
+const MAX_GRADE: f64 = 0.45;
+
+/// Minimum number of Trackpoints with both heart rate and power needed to fit the heart rate model.
+const MIN_FIT_POINTS: usize = 60;
+
+/// Grade at or below which a rider who is not pedalling is taken to be coasting downhill.
+const COASTING_GRADE: f64 = -0.02;
+
+/// Shortest coasting stretch, in seconds, worth comparing against zero.
+const MIN_COASTING_SECONDS: f64 = 3.0;
+
+/// Fitted steady-state relationship between smoothed power and heart rate: `HR = intercept + slope * P_smoothed`.
+struct HrModel {
+    intercept: f64,
+    slope: f64,
+}
+
+/// Physical characteristics of the athlete, used by the estimation models.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AthleteProfile {
+    /// Body weight in kilograms.
+    pub weight_kg: f64,
+
+    /// Height in meters. Used to estimate the frontal area for air resistance.
+    pub height_m: f64,
+
+    /// Fraction of metabolic energy turned into mechanical work. Typically around 0.25.
+    pub efficiency: f64,
+
+    /// How quickly heart rate responds to a change in effort: the time, in seconds, to cover about two thirds of
+    /// the change. Typically 20-60 seconds.
+    pub hr_time_constant_seconds: f64,
+}
+
+impl Default for AthleteProfile {
+    fn default() -> Self {
+        AthleteProfile {
+            weight_kg: 70.0,
+            height_m: 1.75,
+            efficiency: 0.25,
+            hr_time_constant_seconds: 30.0,
+        }
+    }
+}
+
+impl AthleteProfile {
+    /// Frontal area in square meters, from the Du Bois body surface area scaled as in Skiba's GOVSS model.
+    fn frontal_area(&self) -> f64 {
+        0.266 * 0.2025 * self.height_m.powf(0.725) * self.weight_kg.powf(0.425)
+    }
+}
+
+impl Activity {
+    /// Estimates running power for each Trackpoint from speed, grade and the athlete's weight using a GOVSS-style model:
+    /// Minetti's grade-dependent energy cost of running plus air resistance, scaled by the athlete's efficiency.
+    /// The result is stored in the TPX `Watts` field of any Trackpoint that does not already have a power reading.
+    ///
+    /// # Parameters
+    ///
+    /// `profile: &AthleteProfile` -- The athlete's weight, height and efficiency.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let profile = crate::tcx::power::AthleteProfile::default();
+    /// tcx.activities.as_mut().unwrap().activities[0].estimate_running_power(&profile);
+    /// ```
+    pub fn estimate_running_power(&mut self, profile: &AthleteProfile) {
+        let estimates: Vec<Option<f64>> = {
+            let points: Vec<&Trackpoint> = self.trackpoints().collect();
+            speeds(&points)
+                .iter()
+                .zip(grades(&points))
+                .map(|(speed, grade)| {
+                    speed.map(|speed| running_power(profile, speed, grade.unwrap_or(0.0)))
+                })
+                .collect()
+        };
+
+        for (point, estimate) in self.trackpoints_mut().zip(estimates) {
+            let watts = match estimate {
+                Some(watts) => watts.round() as u16,
+                None => continue,
+            };
+            let tpx = point
+                .extensions
+                .get_or_insert_with(Extensions::default)
+                .tpx
+                .get_or_insert_with(Ns3Tpx::default);
+            if tpx.watts.is_none() {
+                tpx.watts = Some(watts);
+            }
+        }
+    }
+}
+
+impl Activity {
+    /// Fills gaps in the heart rate channel (for example, after a strap dropped out mid-ride) from the power channel.
+    /// Heart rate is modelled as following power through a first-order lag, `HR = a + b * P_smoothed`, where power is
+    /// exponentially smoothed with the profile's heart rate time constant and `a` and `b` are fitted to the parts of
+    /// this activity where both sensors recorded data.
+    ///
+    /// # Parameters
+    ///
+    /// `profile: &AthleteProfile` -- Supplies the heart rate time constant.
+    ///
+    /// # Returns
+    ///
+    /// `Option<usize>` -- The number of Trackpoints given an estimated heart rate, or `None` if there was not enough
+    /// overlapping data to fit the model.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let profile = crate::tcx::power::AthleteProfile::default();
+    /// if let Some(filled) = tcx.activities.as_mut().unwrap().activities[0].estimate_hr_from_power(&profile) {
+    ///     println!("Estimated heart rate for {} trackpoints", filled);
+    /// }
+    /// ```
+    pub fn estimate_hr_from_power(&mut self, profile: &AthleteProfile) -> Option<usize> {
+        let (smoothed, model) = self.fit_hr_model(profile)?;
+
+        let mut filled = 0;
+        for (point, power) in self.trackpoints_mut().zip(smoothed) {
+            if let (None, Some(power)) = (&point.heart_rate, power) {
+                point.heart_rate = Some(HeartRate {
+                    value: (model.intercept + model.slope * power).round(),
+                });
+                filled += 1;
+            }
+        }
+        Some(filled)
+    }
+
+    /// Fills gaps in the power channel from the heart rate channel, using the same fitted first-order model as
+    /// `estimate_hr_from_power` run in reverse. Because heart rate lags effort, the estimate is only meaningful when
+    /// averaged over a minute or more.
+    ///
+    /// # Parameters
+    ///
+    /// `profile: &AthleteProfile` -- Supplies the heart rate time constant.
+    ///
+    /// # Returns
+    ///
+    /// `Option<usize>` -- The number of Trackpoints given an estimated power, or `None` if there was not enough
+    /// overlapping data to fit the model.
+    pub fn estimate_power_from_hr(&mut self, profile: &AthleteProfile) -> Option<usize> {
+        let (_, model) = self.fit_hr_model(profile)?;
+
+        // Invert HR = a + b * P_smoothed, then undo the smoothing: P = P_smoothed + tau * dP_smoothed/dt.
+        let estimates: Vec<Option<f64>> = {
+            let points: Vec<&Trackpoint> = self.trackpoints().collect();
+            let smoothed: Vec<Option<f64>> = points
+                .iter()
+                .map(|point| {
+                    point
+                        .heart_rate
+                        .as_ref()
+                        .map(|hr| (hr.value - model.intercept) / model.slope)
+                })
+                .collect();
+            (0..points.len())
+                .map(|i| {
+                    let current = smoothed[i]?;
+                    let next = match smoothed.get(i + 1) {
+                        Some(Some(next)) => *next,
+                        _ => return Some(current),
+                    };
+                    let elapsed = seconds_between(points[i], points[i + 1]);
+                    if elapsed <= 0.0 {
+                        return Some(current);
+                    }
+                    Some(current + profile.hr_time_constant_seconds * (next - current) / elapsed)
+                })
+                .collect()
+        };
+
+        let mut filled = 0;
+        for (point, estimate) in self.trackpoints_mut().zip(estimates) {
+            let estimate = match estimate {
+                Some(estimate) => estimate.max(0.0).round() as u16,
+                None => continue,
+            };
+            let tpx = point
+                .extensions
+                .get_or_insert_with(Extensions::default)
+                .tpx
+                .get_or_insert_with(Ns3Tpx::default);
+            if tpx.watts.is_none() {
+                tpx.watts = Some(estimate);
+                filled += 1;
+            }
+        }
+        Some(filled)
+    }
+
+    /// Smooths the power channel and fits `HR = a + b * P_smoothed` by least squares. Returns the smoothed power for
+    /// every Trackpoint along with the fitted model.
+    fn fit_hr_model(&self, profile: &AthleteProfile) -> Option<(Vec<Option<f64>>, HrModel)> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        let smoothed = smoothed_power(&points, profile.hr_time_constant_seconds);
+
+        let pairs: Vec<(f64, f64)> = points
+            .iter()
+            .zip(&smoothed)
+            .filter_map(|(point, power)| Some(((*power)?, point.heart_rate.as_ref()?.value)))
+            .collect();
+        if pairs.len() < MIN_FIT_POINTS {
+            return None;
+        }
+
+        let n = pairs.len() as f64;
+        let mean_p = pairs.iter().map(|pair| pair.0).sum::<f64>() / n;
+        let mean_hr = pairs.iter().map(|pair| pair.1).sum::<f64>() / n;
+        let covariance: f64 = pairs
+            .iter()
+            .map(|(p, hr)| (p - mean_p) * (hr - mean_hr))
+            .sum();
+        let variance: f64 = pairs.iter().map(|(p, _)| (p - mean_p).powi(2)).sum();
+        if variance <= 0.0 || covariance <= 0.0 {
+            return None;
+        }
+        let slope = covariance / variance;
+        let model = HrModel {
+            intercept: mean_hr - slope * mean_p,
+            slope,
+        };
+        Some((smoothed, model))
+    }
+}
+
+/// A stretch of coasting downhill (zero cadence, negative grade) along with the power recorded during it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CoastingSegment {
+    /// Time of the first Trackpoint counted.
+    pub start_time: DateTime<Utc>,
+
+    /// Time of the last Trackpoint counted.
+    pub end_time: DateTime<Utc>,
+
+    /// Average recorded power, in Watts, which should be zero.
+    pub average_watts: f64,
+}
+
+/// The result of comparing power recorded while coasting against zero.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PowerBaselineDrift {
+    /// The coasting stretches found, in order.
+    pub segments: Vec<CoastingSegment>,
+
+    /// Total time spent coasting, in seconds.
+    pub coasting_seconds: f64,
+
+    /// Average power over all of the coasting, in Watts.
+    pub average_watts: f64,
+
+    /// True if the average is above the tolerance, suggesting the power meter needs a zero offset calibration.
+    pub drifted: bool,
+}
+
+impl Activity {
+    /// Checks a power meter's zero offset by looking at the power it recorded while the rider was coasting downhill:
+    /// Trackpoints with a `Cadence` of zero on a grade of -2% or steeper. A correctly calibrated meter reads zero
+    /// there. The first Trackpoint of each stretch is skipped, since power is often recorded a second late, and
+    /// stretches shorter than three seconds are ignored.
+    ///
+    /// # Parameters
+    ///
+    /// `tolerance_watts: f64` -- The average coasting power above which the meter is flagged, to allow for noise.
+    ///
+    /// # Returns
+    ///
+    /// `Option<PowerBaselineDrift>` -- The coasting stretches and the verdict, or `None` if there was no coasting
+    /// with power, altitude and distance to judge by.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+    ///
+    /// // Twenty seconds freewheeling down a 5% grade with the meter reading 12 W.
+    /// let start = chrono::Utc::now();
+    /// let lap = (0..20).fold(LapBuilder::new(), |lap, i| {
+    ///     let point = TrackpointBuilder::new(start + chrono::Duration::seconds(i))
+    ///         .distance(i as f64 * 10.0)
+    ///         .altitude(100.0 - i as f64 * 0.5)
+    ///         .cadence(0)
+    ///         .watts(12);
+    ///     lap.trackpoint(point.build())
+    /// });
+    /// let descent = ActivityBuilder::new("Biking").lap(lap.build()).build().unwrap();
+    /// let drift = descent.detect_power_baseline_drift(5.0).unwrap();
+    /// assert_eq!(drift.average_watts, 12.0);
+    /// assert!(drift.drifted);
+    /// assert!(!descent.detect_power_baseline_drift(15.0).unwrap().drifted);
+    ///
+    /// // A run has no coasting to judge by.
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// assert!(tcx.activities.unwrap().activities[0].detect_power_baseline_drift(5.0).is_none());
+    /// ```
+    pub fn detect_power_baseline_drift(&self, tolerance_watts: f64) -> Option<PowerBaselineDrift> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        let grades = grades(&points);
+        let coasting: Vec<Option<f64>> = points
+            .iter()
+            .zip(&grades)
+            .map(|(point, grade)| match (point.cadence, grade) {
+                (Some(0), Some(grade)) if *grade <= COASTING_GRADE => watts(point),
+                _ => None,
+            })
+            .collect();
+
+        let mut segments = Vec::new();
+        let mut coasting_seconds = 0.0;
+        let mut total_watts = 0.0;
+        let mut total_samples = 0;
+        let mut i = 0;
+        while i < points.len() {
+            if coasting[i].is_none() {
+                i += 1;
+                continue;
+            }
+            let mut end = i;
+            while end + 1 < points.len() && coasting[end + 1].is_some() {
+                end += 1;
+            }
+
+            // Skip the first Trackpoint, whose power may still be from the last pedal stroke.
+            let start = i + 1;
+            if start <= end {
+                let seconds = seconds_between(points[start], points[end]);
+                if seconds >= MIN_COASTING_SECONDS {
+                    let samples: Vec<f64> =
+                        coasting[start..=end].iter().flatten().copied().collect();
+                    let sum: f64 = samples.iter().sum();
+                    segments.push(CoastingSegment {
+                        start_time: points[start].time,
+                        end_time: points[end].time,
+                        average_watts: sum / samples.len() as f64,
+                    });
+                    coasting_seconds += seconds;
+                    total_watts += sum;
+                    total_samples += samples.len();
+                }
+            }
+            i = end + 1;
+        }
+
+        if total_samples == 0 {
+            return None;
+        }
+        let average_watts = total_watts / total_samples as f64;
+        Some(PowerBaselineDrift {
+            segments,
+            coasting_seconds,
+            average_watts,
+            drifted: average_watts > tolerance_watts,
+        })
+    }
+}
+
+/// The power of a Trackpoint, from the TPX extension.
+fn watts(point: &Trackpoint) -> Option<f64> {
+    point
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.tpx.as_ref())
+        .and_then(|tpx| tpx.watts)
+        .map(f64::from)
+}
+
+/// Exponentially smoothed power at each Trackpoint. Gaps in the power channel hold the last smoothed value.
+fn smoothed_power(points: &[&Trackpoint], time_constant_seconds: f64) -> Vec<Option<f64>> {
+    let mut result = Vec::with_capacity(points.len());
+    let mut state: Option<f64> = None;
+    for (i, point) in points.iter().enumerate() {
+        state = match (state, watts(point)) {
+            (None, watts) => watts,
+            (Some(previous), Some(watts)) => {
+                let elapsed = seconds_between(points[i - 1], point).max(0.0);
+                let alpha = 1.0 - (-elapsed / time_constant_seconds).exp();
+                Some(previous + alpha * (watts - previous))
+            }
+            (Some(previous), None) => Some(previous),
+        };
+        result.push(state);
+    }
+    result
+}
+
+/// Minetti et al. (2002) energy cost of running at the given grade, in J/kg/m. Grades beyond those measured are
+/// clamped.
+pub(crate) fn cost_of_running(grade: f64) -> f64 {
+    let i = grade.clamp(-MAX_GRADE, MAX_GRADE);
+    155.4 * i.powi(5) - 30.4 * i.powi(4) - 43.3 * i.powi(3) + 46.3 * i.powi(2) + 19.5 * i + 3.6
+}
+
+/// Mechanical running power, in Watts, at the given speed (meters per second) and grade.
+fn running_power(profile: &AthleteProfile, speed: f64, grade: f64) -> f64 {
+    let cost_of_running = cost_of_running(grade);
+
+    // Air resistance, in J/kg/m.
+    let cost_of_air =
+        0.5 * AIR_DENSITY * profile.frontal_area() * RUNNER_DRAG_COEFFICIENT * speed * speed
+            / profile.weight_kg;
+
+    let watts = (cost_of_running + cost_of_air) * speed * profile.weight_kg * profile.efficiency;
+    watts.max(0.0)
+}
@@ -0,0 +1,290 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Race time prediction from an activity's best efforts, using Riegel's endurance formula or Jack Daniels' VDOT
+//! tables (as fitted by Daniels and Gilbert). Also the farthest distance covered in a given time, for time-based
+//! challenges such as the Cooper test.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::seconds_between;
+use crate::tcx::{Activity, Trackpoint};
+
+/// Distances, in meters, searched for best efforts. An effort shorter than a mile predicts long races poorly.
+const EFFORT_DISTANCES: [f64; 6] = [1609.344, 3000.0, 5000.0, 10000.0, 21097.5, 42195.0];
+
+/// The races predicted, with their distances in meters.
+const RACES: [(&str, f64); 4] = [
+    ("5K", 5000.0),
+    ("10K", 10000.0),
+    ("Half Marathon", 21097.5),
+    ("Marathon", 42195.0),
+];
+
+/// Riegel's fatigue exponent.
+const RIEGEL_EXPONENT: f64 = 1.06;
+
+/// The fastest time over a distance within an activity.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BestEffort {
+    /// The distance of the effort, in meters.
+    pub distance_meters: f64,
+
+    /// The time taken, in seconds.
+    pub seconds: f64,
+
+    /// When the effort started.
+    pub start_time: DateTime<Utc>,
+}
+
+/// The farthest distance covered in a given time within an activity.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TimeWindow {
+    /// The length of the window, in seconds.
+    pub seconds: f64,
+
+    /// The distance covered, in meters.
+    pub distance_meters: f64,
+
+    /// When the window started.
+    pub start_time: DateTime<Utc>,
+}
+
+/// How to extrapolate from a best effort to other distances.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum RaceModel {
+    /// Riegel's formula, `T2 = T1 * (D2 / D1) ^ 1.06`.
+    #[default]
+    Riegel,
+
+    /// Daniels' VDOT: the effort is converted to a VDOT score, and each race time is the one with the same score.
+    Vdot,
+}
+
+/// A predicted race time.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RacePrediction {
+    /// The race, such as `5K` or `Marathon`.
+    pub race: String,
+
+    /// The race distance, in meters.
+    pub distance_meters: f64,
+
+    /// The predicted finishing time, in seconds.
+    pub seconds: f64,
+}
+
+impl Activity {
+    /// Finds the fastest time over the given distance, based on the Trackpoint distances. The time over the
+    /// shortest span of Trackpoints covering the distance is scaled down to the exact distance.
+    ///
+    /// # Parameters
+    ///
+    /// `distance_meters: f64` -- The distance of the effort.
+    ///
+    /// # Returns
+    ///
+    /// `Option<BestEffort>` -- The best effort, or `None` if the activity is shorter than the distance.
+    pub fn best_effort(&self, distance_meters: f64) -> Option<BestEffort> {
+        let points: Vec<&Trackpoint> = self
+            .trackpoints()
+            .filter(|point| point.distance_meters.is_some())
+            .collect();
+        let distance = |i: usize| points[i].distance_meters.unwrap_or(0.0);
+
+        let mut best: Option<BestEffort> = None;
+        let mut start = 0;
+        for end in 1..points.len() {
+            while start + 1 < end && distance(end) - distance(start + 1) >= distance_meters {
+                start += 1;
+            }
+            let covered = distance(end) - distance(start);
+            if covered < distance_meters || covered <= 0.0 {
+                continue;
+            }
+            let seconds = seconds_between(points[start], points[end]) * distance_meters / covered;
+            if best.as_ref().is_none_or(|best| seconds < best.seconds) {
+                best = Some(BestEffort {
+                    distance_meters,
+                    seconds,
+                    start_time: points[start].time,
+                });
+            }
+        }
+        best
+    }
+
+    /// Finds the fastest stretch of the given duration: the one covering the most distance, based on the Trackpoint
+    /// distances. The distance over the shortest span of Trackpoints lasting the duration is scaled down to the
+    /// exact duration. The time-based counterpart of `best_effort`.
+    ///
+    /// # Parameters
+    ///
+    /// `duration_seconds: f64` -- The length of the window.
+    ///
+    /// # Returns
+    ///
+    /// `Option<TimeWindow>` -- The fastest window, or `None` if the activity is shorter than the duration or the
+    /// duration is not positive.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// let cooper = activity.fastest_window_by_time(720.0).unwrap();
+    /// let mile = activity.best_effort(1609.344).unwrap();
+    /// assert!(cooper.distance_meters > 1609.344 * 720.0 / mile.seconds * 0.9);
+    /// ```
+    pub fn fastest_window_by_time(&self, duration_seconds: f64) -> Option<TimeWindow> {
+        if duration_seconds <= 0.0 {
+            return None;
+        }
+        let points: Vec<&Trackpoint> = self
+            .trackpoints()
+            .filter(|point| point.distance_meters.is_some())
+            .collect();
+        let distance = |i: usize| points[i].distance_meters.unwrap_or(0.0);
+
+        let mut best: Option<TimeWindow> = None;
+        let mut start = 0;
+        for end in 1..points.len() {
+            while start + 1 < end
+                && seconds_between(points[start + 1], points[end]) >= duration_seconds
+            {
+                start += 1;
+            }
+            let elapsed = seconds_between(points[start], points[end]);
+            if elapsed < duration_seconds {
+                continue;
+            }
+            let distance_meters =
+                (distance(end) - distance(start)).max(0.0) * duration_seconds / elapsed;
+            if best
+                .as_ref()
+                .is_none_or(|best| distance_meters > best.distance_meters)
+            {
+                best = Some(TimeWindow {
+                    seconds: duration_seconds,
+                    distance_meters,
+                    start_time: points[start].time,
+                });
+            }
+        }
+        best
+    }
+
+    /// Predicts 5K, 10K, half marathon and marathon times from the activity's best effort. Best efforts are found
+    /// for the standard distances from a mile to the marathon that the activity covers, and the one with the
+    /// highest VDOT score (the best performance) is used. This only makes sense for running activities.
+    ///
+    /// # Parameters
+    ///
+    /// `model: RaceModel` -- How to extrapolate from the best effort.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<RacePrediction>` -- One prediction per race, or nothing if the activity is shorter than a mile.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// let predictions = activity.predict_race_times(crate::tcx::predict::RaceModel::Riegel);
+    /// assert_eq!(predictions.len(), 4);
+    /// assert!(predictions[0].seconds < predictions[3].seconds);
+    /// ```
+    pub fn predict_race_times(&self, model: RaceModel) -> Vec<RacePrediction> {
+        let effort = EFFORT_DISTANCES
+            .iter()
+            .filter_map(|distance| self.best_effort(*distance))
+            .max_by(|a, b| {
+                vdot(a.distance_meters, a.seconds).total_cmp(&vdot(b.distance_meters, b.seconds))
+            });
+        let effort = match effort {
+            Some(effort) => effort,
+            None => return Vec::new(),
+        };
+
+        RACES
+            .iter()
+            .map(|(race, distance)| RacePrediction {
+                race: race.to_string(),
+                distance_meters: *distance,
+                seconds: match model {
+                    RaceModel::Riegel => {
+                        effort.seconds * (distance / effort.distance_meters).powf(RIEGEL_EXPONENT)
+                    }
+                    RaceModel::Vdot => {
+                        vdot_race_time(vdot(effort.distance_meters, effort.seconds), *distance)
+                    }
+                },
+            })
+            .collect()
+    }
+}
+
+/// Computes Daniels and Gilbert's VDOT: the oxygen cost of a pace divided by the fraction of VO2max that can be
+/// sustained for the time taken.
+///
+/// # Parameters
+///
+/// `meters: f64` -- The distance run.
+///
+/// `seconds: f64` -- The time taken.
+///
+/// # Returns
+///
+/// `f64` -- The VDOT score, in milliliters of oxygen per kilogram per minute.
+///
+/// # Example
+///
+/// ```rust
+/// // Daniels' tables list a 19:57 5K for a VDOT of 50.
+/// assert!((crate::tcx::predict::vdot(5000.0, 1197.0) - 50.0).abs() < 0.1);
+/// ```
+pub fn vdot(meters: f64, seconds: f64) -> f64 {
+    let minutes = seconds / 60.0;
+    let velocity = meters / minutes;
+    let oxygen_cost = -4.60 + 0.182258 * velocity + 0.000104 * velocity * velocity;
+    let fraction_of_max =
+        0.8 + 0.1894393 * (-0.012778 * minutes).exp() + 0.2989558 * (-0.1932605 * minutes).exp();
+    oxygen_cost / fraction_of_max
+}
+
+/// The time, in seconds, to run `meters` at the given VDOT. VDOT falls as time rises, so the time is found by
+/// bisection.
+fn vdot_race_time(score: f64, meters: f64) -> f64 {
+    // Bracket the answer between 10 m/s and 0.5 m/s.
+    let mut fast = meters / 10.0;
+    let mut slow = meters / 0.5;
+    for _ in 0..100 {
+        let middle = (fast + slow) / 2.0;
+        if vdot(meters, middle) > score {
+            fast = middle;
+        } else {
+            slow = middle;
+        }
+    }
+    (fast + slow) / 2.0
+}
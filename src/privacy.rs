@@ -0,0 +1,575 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Scrubbing files before they are shared publicly: hiding the track near places such as home, removing notes,
+//! device serial numbers and, optionally, heart rate and power, cutting out sections recorded within an area
+//! such as private property or an indoor venue, and blanking channels for a stretch of time, such as heart rate
+//! during a medical event.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::haversine_distance;
+use crate::sampling::Channel;
+use crate::tcx::{
+    Activity, ActivityLap, Extensions, Position, Track, Trackpoint, TrainingCenterDatabase,
+};
+
+/// A circle around a private place, such as home or work, within which the track is hidden.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct PrivacyZone {
+    /// The place to hide.
+    pub center: Position,
+
+    /// The radius of the circle, in meters.
+    pub radius_meters: f64,
+}
+
+impl PrivacyZone {
+    /// Whether the position lies within the zone.
+    pub fn contains(&self, position: &Position) -> bool {
+        haversine_distance(&self.center, position) <= self.radius_meters
+    }
+}
+
+/// What to do with Trackpoints inside a privacy zone.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum ZoneHandling {
+    /// Remove the Trackpoints altogether. This is the default.
+    #[default]
+    Strip,
+    /// Keep the Trackpoints, so that time, heart rate and the like still add up, but remove their positions and
+    /// altitudes.
+    HidePosition,
+}
+
+/// Options controlling `TrainingCenterDatabase::anonymize`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AnonymizeOptions {
+    /// Places to hide. Defaults to none.
+    pub zones: Vec<PrivacyZone>,
+
+    /// What to do with Trackpoints inside a zone. Defaults to `ZoneHandling::Strip`.
+    pub zone_handling: ZoneHandling,
+
+    /// Whether to remove the `Notes` of activities, laps, multisport sessions and courses. Defaults to true.
+    pub remove_notes: bool,
+
+    /// Whether to remove the serial number (`UnitId`) of the recording devices. Defaults to true.
+    pub remove_device_serial: bool,
+
+    /// Whether to remove heart rate, from the Trackpoints and the lap summaries. Defaults to false.
+    pub remove_heart_rate: bool,
+
+    /// Whether to remove power, from the Trackpoints and the lap summaries. Defaults to false.
+    pub remove_power: bool,
+}
+
+impl Default for AnonymizeOptions {
+    fn default() -> Self {
+        AnonymizeOptions {
+            zones: Vec::new(),
+            zone_handling: ZoneHandling::Strip,
+            remove_notes: true,
+            remove_device_serial: true,
+            remove_heart_rate: false,
+            remove_power: false,
+        }
+    }
+}
+
+impl TrainingCenterDatabase {
+    /// Makes a copy of the database that is safe to share: the track is hidden within the privacy zones, and notes,
+    /// device serial numbers, heart rate and power are removed as the options say. Laps left without Trackpoints
+    /// are removed, along with any original XML retained when reading and any vendor extensions that were kept as
+    /// raw XML, since they may hold the same data.
+    ///
+    /// # Parameters
+    ///
+    /// `options: &AnonymizeOptions` -- What to hide or remove.
+    ///
+    /// # Returns
+    ///
+    /// `TrainingCenterDatabase` -- The cleaned copy.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let start = tcx.activities.as_ref().unwrap().activities[0]
+    ///     .trackpoints()
+    ///     .find_map(|point| point.position.clone())
+    ///     .unwrap();
+    /// let options = crate::tcx::privacy::AnonymizeOptions {
+    ///     zones: vec![crate::tcx::privacy::PrivacyZone { center: start, radius_meters: 200.0 }],
+    ///     ..Default::default()
+    /// };
+    /// let shared = tcx.anonymize(&options);
+    /// let activity = &shared.activities.unwrap().activities[0];
+    /// assert!(activity.trackpoints().all(|point| !options.zones[0].contains(point.position.as_ref().unwrap())));
+    /// ```
+    pub fn anonymize(&self, options: &AnonymizeOptions) -> TrainingCenterDatabase {
+        let mut copy = self.clone();
+        copy.original = None;
+        drop_unknown_extensions(&mut copy.extensions);
+
+        if let Some(activities) = copy.activities.as_mut() {
+            for activity in activities.activities.iter_mut() {
+                scrub_activity(activity, options);
+            }
+            for session in activities.multi_sport_sessions.iter_mut() {
+                if options.remove_notes {
+                    session.notes = None;
+                }
+                scrub_activity(&mut session.first_sport.activity, options);
+                for next in session.next_sports.iter_mut() {
+                    scrub_activity(&mut next.activity, options);
+                    if next
+                        .transition
+                        .as_mut()
+                        .is_some_and(|lap| scrub_lap(lap, options))
+                    {
+                        next.transition = None;
+                    }
+                }
+            }
+        }
+
+        if let Some(courses) = copy.courses.as_mut() {
+            for course in courses.courses.iter_mut() {
+                if options.remove_notes {
+                    course.notes = None;
+                }
+                drop_unknown_extensions(&mut course.extensions);
+                for track in course.tracks.iter_mut().flatten() {
+                    scrub_track(track, options);
+                }
+                if let Some(tracks) = course.tracks.as_mut() {
+                    tracks.retain(|track| !track.trackpoints.is_empty());
+                }
+                for lap in course.laps.iter_mut() {
+                    drop_unknown_extensions(&mut lap.extensions);
+                    if lap
+                        .begin_position
+                        .as_ref()
+                        .is_some_and(|p| in_zone(p, options))
+                    {
+                        lap.begin_position = None;
+                        lap.begin_altitude_meters = None;
+                    }
+                    if lap
+                        .end_position
+                        .as_ref()
+                        .is_some_and(|p| in_zone(p, options))
+                    {
+                        lap.end_position = None;
+                        lap.end_altitude_meters = None;
+                    }
+                    if options.remove_heart_rate {
+                        lap.average_heart_rate = None;
+                        lap.maximum_heart_rate = None;
+                    }
+                }
+                for point in course.course_points.iter_mut() {
+                    drop_unknown_extensions(&mut point.extensions);
+                }
+                course.course_points.retain(|point| {
+                    !point
+                        .position
+                        .as_ref()
+                        .is_some_and(|position| in_zone(position, options))
+                });
+            }
+        }
+        copy
+    }
+}
+
+impl TrainingCenterDatabase {
+    /// Truncates every coordinate in the document (Trackpoints of activities, multisport sessions and courses, course
+    /// points and course lap begin and end positions) to the given number of decimal places. A lighter-weight option
+    /// than privacy zones: three places leave a position accurate to about a hundred meters, four to about ten.
+    ///
+    /// # Parameters
+    ///
+    /// `decimals: u32` -- The number of decimal places of latitude and longitude to keep.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// tcx.reduce_position_precision(3);
+    /// let position = tcx.activities.unwrap().activities[0].trackpoints().find_map(|p| p.position.clone()).unwrap();
+    /// assert_eq!(position.latitude, 28.078);
+    /// ```
+    pub fn reduce_position_precision(&mut self, decimals: u32) {
+        let scale = 10f64.powi(decimals as i32);
+        let reduce = |position: &mut Position| {
+            position.latitude = (position.latitude * scale).trunc() / scale;
+            position.longitude = (position.longitude * scale).trunc() / scale;
+        };
+
+        let mut reduce_lap = |lap: &mut ActivityLap| {
+            lap.trackpoints_mut()
+                .filter_map(|point| point.position.as_mut())
+                .for_each(reduce);
+        };
+        if let Some(activities) = self.activities.as_mut() {
+            for activity in activities.activities.iter_mut() {
+                activity.laps.iter_mut().for_each(&mut reduce_lap);
+            }
+            for session in activities.multi_sport_sessions.iter_mut() {
+                session
+                    .first_sport
+                    .activity
+                    .laps
+                    .iter_mut()
+                    .for_each(&mut reduce_lap);
+                for next in session.next_sports.iter_mut() {
+                    next.activity.laps.iter_mut().for_each(&mut reduce_lap);
+                    next.transition.iter_mut().for_each(&mut reduce_lap);
+                }
+            }
+        }
+
+        if let Some(courses) = self.courses.as_mut() {
+            for course in courses.courses.iter_mut() {
+                course
+                    .tracks
+                    .iter_mut()
+                    .flatten()
+                    .flat_map(|track| track.trackpoints.iter_mut())
+                    .filter_map(|point| point.position.as_mut())
+                    .for_each(reduce);
+                course
+                    .course_points
+                    .iter_mut()
+                    .filter_map(|point| point.position.as_mut())
+                    .for_each(reduce);
+                for lap in course.laps.iter_mut() {
+                    lap.begin_position.iter_mut().for_each(reduce);
+                    lap.end_position.iter_mut().for_each(reduce);
+                }
+            }
+        }
+    }
+}
+
+impl Activity {
+    /// Removes the Trackpoints recorded inside a polygon, such as the outline of private property or of an indoor
+    /// venue whose GPS track is meaningless. Where Trackpoints are removed from the middle of a track, the track is
+    /// split in two so the section is not drawn as a straight line. Empty tracks are removed; laps are kept, and the
+    /// totals of the laps affected are recomputed (see `ActivityLap::refresh_summary`).
+    ///
+    /// # Parameters
+    ///
+    /// `polygon: &[Position]` -- The corners of the area, in order. The last corner joins the first. Polygons with
+    /// fewer than three corners contain nothing.
+    ///
+    /// # Returns
+    ///
+    /// `usize` -- The number of Trackpoints removed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::Position;
+    ///
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+    /// let start = activity.trackpoints().find_map(|point| point.position.clone()).unwrap();
+    /// let corner = |lat: f64, lon: f64| Position { latitude: start.latitude + lat, longitude: start.longitude + lon };
+    /// let polygon = vec![corner(-0.001, -0.001), corner(-0.001, 0.001), corner(0.001, 0.001), corner(0.001, -0.001)];
+    /// let count = activity.trackpoints().count();
+    /// let removed = activity.remove_points_in_polygon(&polygon);
+    /// assert!(removed > 0);
+    /// assert_eq!(activity.trackpoints().count(), count - removed);
+    /// ```
+    pub fn remove_points_in_polygon(&mut self, polygon: &[Position]) -> usize {
+        let inside = |point: &Trackpoint| {
+            point
+                .position
+                .as_ref()
+                .is_some_and(|position| polygon_contains(polygon, position))
+        };
+        let mut removed = 0;
+        for lap in self.laps.iter_mut() {
+            let before = lap.trackpoints().count();
+            let mut tracks = Vec::new();
+            for track in std::mem::take(&mut lap.tracks) {
+                let mut current = Vec::new();
+                for point in track.trackpoints {
+                    if !inside(&point) {
+                        current.push(point);
+                    } else if !current.is_empty() {
+                        tracks.push(Track {
+                            trackpoints: std::mem::take(&mut current),
+                        });
+                    }
+                }
+                if !current.is_empty() {
+                    tracks.push(Track {
+                        trackpoints: current,
+                    });
+                }
+            }
+            lap.tracks = tracks;
+            let after = lap.trackpoints().count();
+            if after < before {
+                removed += before - after;
+                lap.summary_dirty = true;
+                lap.refresh_summary();
+            }
+        }
+        removed
+    }
+}
+
+impl Activity {
+    /// Blanks the chosen channels of the Trackpoints recorded within a time range, such as the heart rate during a
+    /// medical event, leaving the rest of the activity as it was. The lap summaries of the channels blanked (heart
+    /// rate, cadence, power and maximum speed) are recomputed from what remains, so they do not give the values
+    /// away; other lap totals are kept.
+    ///
+    /// # Parameters
+    ///
+    /// `start: DateTime<Utc>` -- The start of the range, inclusive.
+    ///
+    /// `end: DateTime<Utc>` -- The end of the range, inclusive.
+    ///
+    /// `channels: &[Channel]` -- The channels to blank. `Channel::Cadence` includes the TPX `RunCadence` and
+    /// `Channel::HeartRate` the heart rate source.
+    ///
+    /// # Returns
+    ///
+    /// `usize` -- The number of Trackpoints within the range.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::sampling::Channel;
+    ///
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+    /// let start = activity.trackpoints().nth(100).unwrap().time;
+    /// let end = start + chrono::Duration::seconds(60);
+    /// let count = activity.trackpoints().count();
+    /// assert!(activity.redact_time_window(start, end, &[Channel::HeartRate]) > 0);
+    /// assert!(activity.trackpoints().filter(|p| p.time >= start && p.time <= end).all(|p| p.heart_rate.is_none()));
+    /// assert!(activity.trackpoints().filter(|p| p.time >= start && p.time <= end).all(|p| p.position.is_some()));
+    /// assert_eq!(activity.trackpoints().count(), count);
+    /// ```
+    pub fn redact_time_window(
+        &mut self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        channels: &[Channel],
+    ) -> usize {
+        let mut redacted = 0;
+        for lap in self.laps.iter_mut() {
+            let before = redacted;
+            for point in lap.trackpoints_mut() {
+                if point.time >= start && point.time <= end {
+                    for channel in channels {
+                        blank_channel(point, *channel);
+                    }
+                    redacted += 1;
+                }
+            }
+            if redacted > before {
+                refresh_channel_summaries(lap, channels);
+            }
+        }
+        redacted
+    }
+}
+
+fn blank_channel(point: &mut Trackpoint, channel: Channel) {
+    let extensions = point.extensions.as_mut();
+    match channel {
+        Channel::Position => point.position = None,
+        Channel::Altitude => point.altitude_meters = None,
+        Channel::Distance => point.distance_meters = None,
+        Channel::HeartRate => {
+            point.heart_rate = None;
+            if let Some(extensions) = extensions {
+                extensions.heart_rate_source = None;
+                extensions.rr_intervals.clear();
+            }
+        }
+        Channel::Cadence => {
+            point.cadence = None;
+            if let Some(tpx) = extensions.and_then(|ext| ext.tpx.as_mut()) {
+                tpx.run_cadence = None;
+            }
+        }
+        Channel::Speed => {
+            if let Some(tpx) = extensions.and_then(|ext| ext.tpx.as_mut()) {
+                tpx.speed = None;
+            }
+        }
+        Channel::Power => {
+            if let Some(tpx) = extensions.and_then(|ext| ext.tpx.as_mut()) {
+                tpx.watts = None;
+            }
+        }
+    }
+}
+
+/// Recomputes the lap summaries of the blanked channels from the Trackpoints that still have them.
+fn refresh_channel_summaries(lap: &mut ActivityLap, channels: &[Channel]) {
+    let summary = lap.summary();
+    let lx = lap.extensions.as_mut().and_then(|ext| ext.lx.as_mut());
+    if channels.contains(&Channel::HeartRate) {
+        lap.average_heart_rate = summary.average_heart_rate;
+        lap.maximum_heart_rate = summary.max_heart_rate;
+    }
+    if channels.contains(&Channel::Speed) {
+        lap.maximum_speed = summary.max_speed;
+    }
+    let rounded = |value: Option<f64>| value.map(|value| value.round() as u8);
+    if channels.contains(&Channel::Cadence) && lap.cadence.is_some() {
+        lap.cadence = rounded(summary.average_cadence);
+    }
+    if let Some(lx) = lx {
+        if channels.contains(&Channel::Cadence) {
+            if lx.avg_run_cadence.is_some() {
+                lx.avg_run_cadence = rounded(summary.average_cadence);
+                lx.max_run_cadence = summary.max_cadence;
+            }
+            if lx.max_bike_cadence.is_some() {
+                lx.max_bike_cadence = summary.max_cadence;
+            }
+        }
+        if channels.contains(&Channel::Power) {
+            lx.avg_watts = summary.average_power.map(|watts| watts.round() as u16);
+            lx.max_watts = summary.max_power;
+        }
+    }
+}
+
+/// Whether a position lies within a polygon, by counting how many of its edges a line running east from the
+/// position crosses. Degrees are treated as planar, which is accurate enough for areas the size of a venue.
+fn polygon_contains(polygon: &[Position], position: &Position) -> bool {
+    if polygon.len() < 3 {
+        return false;
+    }
+    let mut inside = false;
+    let mut previous = &polygon[polygon.len() - 1];
+    for corner in polygon {
+        if (corner.latitude > position.latitude) != (previous.latitude > position.latitude) {
+            let crossing = corner.longitude
+                + (position.latitude - corner.latitude) * (previous.longitude - corner.longitude)
+                    / (previous.latitude - corner.latitude);
+            if position.longitude < crossing {
+                inside = !inside;
+            }
+        }
+        previous = corner;
+    }
+    inside
+}
+
+fn scrub_activity(activity: &mut Activity, options: &AnonymizeOptions) {
+    if options.remove_notes {
+        activity.notes = None;
+    }
+    if options.remove_device_serial {
+        if let Some(creator) = activity.creator.as_mut() {
+            creator.unit_id = None;
+        }
+    }
+    drop_unknown_extensions(&mut activity.extensions);
+    activity.laps.retain_mut(|lap| !scrub_lap(lap, options));
+}
+
+/// Returns true if the lap had Trackpoints and they were all removed.
+fn scrub_lap(lap: &mut ActivityLap, options: &AnonymizeOptions) -> bool {
+    if options.remove_notes {
+        lap.notes = None;
+    }
+    drop_unknown_extensions(&mut lap.extensions);
+    if options.remove_heart_rate {
+        lap.average_heart_rate = None;
+        lap.maximum_heart_rate = None;
+    }
+    if options.remove_power {
+        if let Some(lx) = lap.extensions.as_mut().and_then(|ext| ext.lx.as_mut()) {
+            lx.avg_watts = None;
+            lx.max_watts = None;
+        }
+    }
+    let had_trackpoints = lap.trackpoints().next().is_some();
+    for track in lap.tracks.iter_mut() {
+        scrub_track(track, options);
+    }
+    lap.tracks.retain(|track| !track.trackpoints.is_empty());
+    had_trackpoints && lap.tracks.is_empty()
+}
+
+fn scrub_track(track: &mut Track, options: &AnonymizeOptions) {
+    if options.zone_handling == ZoneHandling::Strip {
+        track
+            .trackpoints
+            .retain(|point| !point_in_zone(point, options));
+    }
+    for point in track.trackpoints.iter_mut() {
+        drop_unknown_extensions(&mut point.extensions);
+        if point_in_zone(point, options) {
+            point.position = None;
+            point.altitude_meters = None;
+        }
+        if options.remove_heart_rate {
+            point.heart_rate = None;
+            if let Some(extensions) = point.extensions.as_mut() {
+                extensions.heart_rate_source = None;
+                extensions.rr_intervals.clear();
+            }
+        }
+        if options.remove_power {
+            if let Some(tpx) = point.extensions.as_mut().and_then(|ext| ext.tpx.as_mut()) {
+                tpx.watts = None;
+            }
+        }
+    }
+}
+
+/// Removes vendor extensions that were kept as raw XML; nothing is known about what they hold.
+fn drop_unknown_extensions(extensions: &mut Option<Extensions>) {
+    if let Some(extensions) = extensions.as_mut() {
+        extensions.unknown.clear();
+    }
+}
+
+fn point_in_zone(point: &Trackpoint, options: &AnonymizeOptions) -> bool {
+    point
+        .position
+        .as_ref()
+        .is_some_and(|position| in_zone(position, options))
+}
+
+fn in_zone(position: &Position, options: &AnonymizeOptions) -> bool {
+    options.zones.iter().any(|zone| zone.contains(position))
+}
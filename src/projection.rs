@@ -0,0 +1,163 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Reprojecting positions from WGS 84 latitude and longitude into other coordinate reference systems, such as Web
+//! Mercator or a national grid, for GIS tools that expect projected coordinates. Systems are named by EPSG code
+//! from a small built-in table (WGS 84, ETRS89, Web Mercator, World Mercator, the British National Grid and every
+//! WGS 84 UTM zone) or given as a PROJ string. Requires the `proj` feature.
+
+use proj4rs::transform::transform;
+use proj4rs::Proj;
+use std::error::Error;
+use std::fmt;
+
+use crate::tcx::Position;
+
+/// The PROJ definition of WGS 84 latitude and longitude, in which positions are recorded.
+const WGS84: &str = "+proj=longlat +datum=WGS84 +no_defs";
+
+/// The PROJ definition of Web Mercator (EPSG:3857), as used by web maps.
+const WEB_MERCATOR: &str = "+proj=merc +a=6378137 +b=6378137 +lat_ts=0 +lon_0=0 +x_0=0 +y_0=0 +k=1 +units=m +nadgrids=@null +no_defs";
+
+/// A coordinate reference system to write positions in.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum CoordinateSystem {
+    /// Latitude and longitude in degrees, as recorded (EPSG:4326). This is the default.
+    #[default]
+    Wgs84,
+    /// Web Mercator (EPSG:3857), in meters.
+    WebMercator,
+    /// A system from the built-in table, by EPSG code.
+    Epsg(u16),
+    /// Any system, given as a PROJ string such as `+proj=utm +zone=32 +datum=WGS84 +units=m`.
+    Proj(String),
+}
+
+impl CoordinateSystem {
+    /// The PROJ definition of the system.
+    fn definition(&self) -> Result<String, ProjectionError> {
+        Ok(match self {
+            CoordinateSystem::Wgs84 => WGS84.to_string(),
+            CoordinateSystem::WebMercator => WEB_MERCATOR.to_string(),
+            CoordinateSystem::Proj(definition) => definition.clone(),
+            CoordinateSystem::Epsg(code) => match code {
+                4326 => WGS84.to_string(),
+                4258 => "+proj=longlat +ellps=GRS80 +no_defs".to_string(),
+                3857 => WEB_MERCATOR.to_string(),
+                3395 => "+proj=merc +lon_0=0 +k=1 +x_0=0 +y_0=0 +datum=WGS84 +units=m +no_defs".to_string(),
+                27700 => "+proj=tmerc +lat_0=49 +lon_0=-2 +k=0.9996012717 +x_0=400000 +y_0=-100000 +ellps=airy +towgs84=446.448,-125.157,542.06,0.15,0.247,0.842,-20.489 +units=m +no_defs".to_string(),
+                32601..=32660 => format!("+proj=utm +zone={} +datum=WGS84 +units=m +no_defs", code - 32600),
+                32701..=32760 => format!("+proj=utm +zone={} +south +datum=WGS84 +units=m +no_defs", code - 32700),
+                _ => return Err(ProjectionError::UnknownEpsg(*code)),
+            },
+        })
+    }
+}
+
+/// Why a coordinate reference system could not be used.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProjectionError {
+    /// The EPSG code is not in the built-in table. Give the system as a PROJ string instead.
+    UnknownEpsg(u16),
+    /// The PROJ string could not be parsed.
+    InvalidDefinition(String),
+}
+
+impl fmt::Display for ProjectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProjectionError::UnknownEpsg(code) => {
+                write!(f, "EPSG:{} is not a known coordinate system", code)
+            }
+            ProjectionError::InvalidDefinition(reason) => {
+                write!(f, "Invalid PROJ definition: {}", reason)
+            }
+        }
+    }
+}
+
+impl Error for ProjectionError {}
+
+/// Converts positions into a coordinate reference system.
+pub struct Projector {
+    source: Proj,
+    target: Proj,
+}
+
+impl Projector {
+    /// Prepares the conversion from WGS 84 into the given system.
+    ///
+    /// # Parameters
+    ///
+    /// `system: &CoordinateSystem` -- The system to convert into.
+    ///
+    /// # Returns
+    ///
+    /// `Result<Projector, ProjectionError>` -- The projector, or why the system cannot be used.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::projection::{CoordinateSystem, Projector};
+    /// use crate::tcx::Position;
+    ///
+    /// let projector = Projector::new(&CoordinateSystem::WebMercator).unwrap();
+    /// let (x, y) = projector.project(&Position { latitude: 0.0, longitude: 180.0 }).unwrap();
+    /// assert!((x - 20037508.34).abs() < 0.01);
+    /// assert!(y.abs() < 1e-6);
+    /// assert!(Projector::new(&CoordinateSystem::Epsg(1)).is_err());
+    /// ```
+    pub fn new(system: &CoordinateSystem) -> Result<Projector, ProjectionError> {
+        let parse = |definition: &str| {
+            Proj::from_proj_string(definition)
+                .map_err(|e| ProjectionError::InvalidDefinition(e.to_string()))
+        };
+        Ok(Projector {
+            source: parse(WGS84)?,
+            target: parse(&system.definition()?)?,
+        })
+    }
+
+    /// Converts a position.
+    ///
+    /// # Parameters
+    ///
+    /// `position: &Position` -- The WGS 84 position.
+    ///
+    /// # Returns
+    ///
+    /// `Option<(f64, f64)>` -- The easting and northing (x and y) in the system's units, or longitude and latitude
+    /// in degrees for geographic systems. `None` if the position cannot be projected, such as a pole in Mercator.
+    pub fn project(&self, position: &Position) -> Option<(f64, f64)> {
+        let mut point = (
+            position.longitude.to_radians(),
+            position.latitude.to_radians(),
+            0.0,
+        );
+        transform(&self.source, &self.target, &mut point).ok()?;
+        let (x, y) = if self.target.is_latlong() {
+            (point.0.to_degrees(), point.1.to_degrees())
+        } else {
+            (point.0, point.1)
+        };
+        (x.is_finite() && y.is_finite()).then_some((x, y))
+    }
+}
@@ -0,0 +1,935 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Conversion to and from a flat protobuf representation, so that services can ship parsed activities over gRPC
+//! without re-serializing XML or JSON. Requires the `proto` feature. The schema is `proto/tcx.proto` and the message
+//! types below are what prost generates for it. tcx::Activities (with their laps, Trackpoints, TPX and LX values),
+//! multisport sessions, courses (with their tracks and course points) and workouts are carried; folders, course laps
+//! and other extensions are not.
+
+use chrono::NaiveDate;
+
+use chrono::{DateTime, Utc};
+use prost::Message;
+use std::error::Error;
+use std::fmt;
+
+use std::convert::TryFrom;
+
+use crate::tcx::{self, TrainingCenterDatabase};
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Database {
+    #[prost(message, repeated, tag = "1")]
+    pub activities: Vec<Activity>,
+    #[prost(message, repeated, tag = "2")]
+    pub courses: Vec<Course>,
+    #[prost(message, repeated, tag = "3")]
+    pub multi_sport_sessions: Vec<MultiSportSession>,
+    #[prost(message, repeated, tag = "4")]
+    pub workouts: Vec<Workout>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Activity {
+    #[prost(string, tag = "1")]
+    pub sport: String,
+    #[prost(string, tag = "2")]
+    pub id: String,
+    #[prost(message, repeated, tag = "3")]
+    pub laps: Vec<Lap>,
+    #[prost(string, optional, tag = "4")]
+    pub notes: Option<String>,
+    #[prost(message, optional, tag = "5")]
+    pub creator: Option<Device>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Device {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(uint32, optional, tag = "2")]
+    pub unit_id: Option<u32>,
+    #[prost(uint32, optional, tag = "3")]
+    pub product_id: Option<u32>,
+    #[prost(message, optional, tag = "4")]
+    pub version: Option<Version>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Version {
+    #[prost(uint32, tag = "1")]
+    pub version_major: u32,
+    #[prost(uint32, tag = "2")]
+    pub version_minor: u32,
+    #[prost(uint32, optional, tag = "3")]
+    pub build_major: Option<u32>,
+    #[prost(uint32, optional, tag = "4")]
+    pub build_minor: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Intensity {
+    Unspecified = 0,
+    Active = 1,
+    Resting = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum TriggerMethod {
+    Unspecified = 0,
+    Manual = 1,
+    Distance = 2,
+    Location = 3,
+    Time = 4,
+    HeartRate = 5,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Lap {
+    #[prost(int64, optional, tag = "1")]
+    pub start_time_ms: Option<i64>,
+    #[prost(double, tag = "2")]
+    pub total_time_seconds: f64,
+    #[prost(double, tag = "3")]
+    pub distance_meters: f64,
+    #[prost(double, optional, tag = "4")]
+    pub maximum_speed: Option<f64>,
+    #[prost(uint32, tag = "5")]
+    pub calories: u32,
+    #[prost(double, optional, tag = "6")]
+    pub average_heart_rate: Option<f64>,
+    #[prost(double, optional, tag = "7")]
+    pub maximum_heart_rate: Option<f64>,
+    #[prost(enumeration = "Intensity", tag = "8")]
+    pub intensity: i32,
+    #[prost(uint32, optional, tag = "9")]
+    pub cadence: Option<u32>,
+    #[prost(enumeration = "TriggerMethod", tag = "10")]
+    pub trigger_method: i32,
+    #[prost(message, repeated, tag = "11")]
+    pub tracks: Vec<Track>,
+    #[prost(string, optional, tag = "12")]
+    pub notes: Option<String>,
+    #[prost(message, optional, tag = "13")]
+    pub lx: Option<LapExtension>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LapExtension {
+    #[prost(double, optional, tag = "1")]
+    pub avg_speed: Option<f64>,
+    #[prost(uint32, optional, tag = "2")]
+    pub max_bike_cadence: Option<u32>,
+    #[prost(uint32, optional, tag = "3")]
+    pub avg_run_cadence: Option<u32>,
+    #[prost(uint32, optional, tag = "4")]
+    pub max_run_cadence: Option<u32>,
+    #[prost(uint32, optional, tag = "5")]
+    pub steps: Option<u32>,
+    #[prost(uint32, optional, tag = "6")]
+    pub avg_watts: Option<u32>,
+    #[prost(uint32, optional, tag = "7")]
+    pub max_watts: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MultiSportSession {
+    #[prost(int64, tag = "1")]
+    pub id_ms: i64,
+    #[prost(message, repeated, tag = "2")]
+    pub legs: Vec<Leg>,
+    #[prost(string, optional, tag = "3")]
+    pub notes: Option<String>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Leg {
+    #[prost(message, optional, tag = "1")]
+    pub transition: Option<Lap>,
+    #[prost(message, optional, tag = "2")]
+    pub activity: Option<Activity>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Track {
+    #[prost(message, repeated, tag = "1")]
+    pub trackpoints: Vec<Trackpoint>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Trackpoint {
+    #[prost(int64, tag = "1")]
+    pub time_ms: i64,
+    #[prost(double, optional, tag = "2")]
+    pub latitude: Option<f64>,
+    #[prost(double, optional, tag = "3")]
+    pub longitude: Option<f64>,
+    #[prost(double, optional, tag = "4")]
+    pub altitude_meters: Option<f64>,
+    #[prost(double, optional, tag = "5")]
+    pub distance_meters: Option<f64>,
+    #[prost(double, optional, tag = "6")]
+    pub heart_rate: Option<f64>,
+    #[prost(uint32, optional, tag = "7")]
+    pub cadence: Option<u32>,
+    #[prost(double, optional, tag = "8")]
+    pub speed: Option<f64>,
+    #[prost(uint32, optional, tag = "9")]
+    pub run_cadence: Option<u32>,
+    #[prost(uint32, optional, tag = "10")]
+    pub watts: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum CoursePointType {
+    Unspecified = 0,
+    Generic = 1,
+    Summit = 2,
+    Valley = 3,
+    Water = 4,
+    Food = 5,
+    Danger = 6,
+    Left = 7,
+    Right = 8,
+    Straight = 9,
+    FirstAid = 10,
+    FourthCategory = 11,
+    ThirdCategory = 12,
+    SecondCategory = 13,
+    FirstCategory = 14,
+    HorsCategory = 15,
+    Sprint = 16,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Course {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub tracks: Vec<Track>,
+    #[prost(string, optional, tag = "3")]
+    pub notes: Option<String>,
+    #[prost(message, repeated, tag = "4")]
+    pub course_points: Vec<CoursePoint>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CoursePoint {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(int64, tag = "2")]
+    pub time_ms: i64,
+    #[prost(double, optional, tag = "3")]
+    pub latitude: Option<f64>,
+    #[prost(double, optional, tag = "4")]
+    pub longitude: Option<f64>,
+    #[prost(double, optional, tag = "5")]
+    pub altitude_meters: Option<f64>,
+    #[prost(enumeration = "CoursePointType", tag = "6")]
+    pub point_type: i32,
+    #[prost(string, optional, tag = "7")]
+    pub notes: Option<String>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Workout {
+    #[prost(string, tag = "1")]
+    pub sport: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(message, repeated, tag = "3")]
+    pub steps: Vec<WorkoutStep>,
+    #[prost(string, repeated, tag = "4")]
+    pub scheduled_on: Vec<String>,
+    #[prost(string, optional, tag = "5")]
+    pub notes: Option<String>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WorkoutStep {
+    #[prost(string, optional, tag = "1")]
+    pub step_type: Option<String>,
+    #[prost(uint32, tag = "2")]
+    pub step_id: u32,
+    #[prost(string, optional, tag = "3")]
+    pub name: Option<String>,
+    #[prost(message, optional, tag = "4")]
+    pub duration: Option<StepDuration>,
+    #[prost(enumeration = "Intensity", tag = "5")]
+    pub intensity: i32,
+    #[prost(message, optional, tag = "6")]
+    pub target: Option<StepTarget>,
+    #[prost(uint32, optional, tag = "7")]
+    pub repetitions: Option<u32>,
+    #[prost(message, repeated, tag = "8")]
+    pub children: Vec<WorkoutStep>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StepDuration {
+    #[prost(string, optional, tag = "1")]
+    pub duration_type: Option<String>,
+    #[prost(uint32, optional, tag = "2")]
+    pub seconds: Option<u32>,
+    #[prost(uint32, optional, tag = "3")]
+    pub meters: Option<u32>,
+    #[prost(message, optional, tag = "4")]
+    pub heart_rate: Option<HeartRateValue>,
+    #[prost(uint32, optional, tag = "5")]
+    pub calories: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StepTarget {
+    #[prost(string, optional, tag = "1")]
+    pub target_type: Option<String>,
+    #[prost(message, optional, tag = "2")]
+    pub speed_zone: Option<Zone>,
+    #[prost(message, optional, tag = "3")]
+    pub heart_rate_zone: Option<Zone>,
+    #[prost(double, optional, tag = "4")]
+    pub low: Option<f64>,
+    #[prost(double, optional, tag = "5")]
+    pub high: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum SpeedType {
+    Unspecified = 0,
+    Pace = 1,
+    Speed = 2,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Zone {
+    #[prost(string, optional, tag = "1")]
+    pub zone_type: Option<String>,
+    #[prost(uint32, optional, tag = "2")]
+    pub number: Option<u32>,
+    #[prost(enumeration = "SpeedType", tag = "3")]
+    pub view_as: i32,
+    #[prost(double, optional, tag = "4")]
+    pub low_in_meters_per_second: Option<f64>,
+    #[prost(double, optional, tag = "5")]
+    pub high_in_meters_per_second: Option<f64>,
+    #[prost(message, optional, tag = "6")]
+    pub low: Option<HeartRateValue>,
+    #[prost(message, optional, tag = "7")]
+    pub high: Option<HeartRateValue>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HeartRateValue {
+    #[prost(string, optional, tag = "1")]
+    pub value_type: Option<String>,
+    #[prost(uint32, tag = "2")]
+    pub value: u32,
+}
+
+/// Course point types in the order of the schema's `CoursePointType` enumeration, which numbers them from 1.
+const COURSE_POINT_TYPES: [tcx::CoursePointType; 16] = [
+    tcx::CoursePointType::Generic,
+    tcx::CoursePointType::Summit,
+    tcx::CoursePointType::Valley,
+    tcx::CoursePointType::Water,
+    tcx::CoursePointType::Food,
+    tcx::CoursePointType::Danger,
+    tcx::CoursePointType::Left,
+    tcx::CoursePointType::Right,
+    tcx::CoursePointType::Straight,
+    tcx::CoursePointType::FirstAid,
+    tcx::CoursePointType::FourthCategory,
+    tcx::CoursePointType::ThirdCategory,
+    tcx::CoursePointType::SecondCategory,
+    tcx::CoursePointType::FirstCategory,
+    tcx::CoursePointType::HorsCategory,
+    tcx::CoursePointType::Sprint,
+];
+
+/// Why a protobuf message could not be converted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConversionError {
+    /// A time, in milliseconds since the Unix epoch, is out of range.
+    InvalidTime(i64),
+
+    /// A workout's scheduled date is not a `YYYY-MM-DD` date.
+    InvalidDate(String),
+
+    /// A leg of a multisport session has no activity.
+    MissingActivity,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConversionError::InvalidTime(millis) => write!(f, "Time {} ms is out of range", millis),
+            ConversionError::InvalidDate(date) => {
+                write!(f, "Date {} is not a YYYY-MM-DD date", date)
+            }
+            ConversionError::MissingActivity => write!(f, "A multisport leg has no activity"),
+        }
+    }
+}
+
+impl Error for ConversionError {}
+
+impl TrainingCenterDatabase {
+    /// Converts the activities, multisport sessions, courses and workouts to their protobuf representation. See the
+    /// module description for what is carried.
+    ///
+    /// # Returns
+    ///
+    /// `Database` -- The protobuf message.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let bytes = tcx.to_proto_bytes();
+    /// let again = tcx::TrainingCenterDatabase::from_proto_bytes(&bytes).unwrap();
+    /// assert_eq!(again.to_proto(), tcx.to_proto());
+    /// ```
+    pub fn to_proto(&self) -> Database {
+        let database = self.refreshed();
+        Database {
+            activities: database
+                .activities
+                .iter()
+                .flat_map(|activities| activities.activities.iter())
+                .map(activity_to_proto)
+                .collect(),
+            multi_sport_sessions: database
+                .activities
+                .iter()
+                .flat_map(|activities| activities.multi_sport_sessions.iter())
+                .map(|session| MultiSportSession {
+                    id_ms: session.id.timestamp_millis(),
+                    legs: std::iter::once(Leg {
+                        transition: None,
+                        activity: Some(activity_to_proto(&session.first_sport.activity)),
+                    })
+                    .chain(session.next_sports.iter().map(|next| Leg {
+                        transition: next.transition.as_ref().map(lap_to_proto),
+                        activity: Some(activity_to_proto(&next.activity)),
+                    }))
+                    .collect(),
+                    notes: session.notes.clone(),
+                })
+                .collect(),
+            workouts: self
+                .workouts
+                .iter()
+                .flat_map(|workouts| workouts.workouts.iter())
+                .map(|workout| Workout {
+                    sport: workout.sport.clone(),
+                    name: workout.name.clone(),
+                    steps: workout.steps.iter().map(step_to_proto).collect(),
+                    scheduled_on: workout
+                        .scheduled_on
+                        .iter()
+                        .map(|date| date.format("%Y-%m-%d").to_string())
+                        .collect(),
+                    notes: workout.notes.clone(),
+                })
+                .collect(),
+            courses: self
+                .courses
+                .iter()
+                .flat_map(|courses| courses.courses.iter())
+                .map(|course| Course {
+                    name: course.name.clone(),
+                    tracks: course.tracks.iter().flatten().map(track_to_proto).collect(),
+                    notes: course.notes.clone(),
+                    course_points: course
+                        .course_points
+                        .iter()
+                        .map(|point| CoursePoint {
+                            name: point.name.clone(),
+                            time_ms: point.time.timestamp_millis(),
+                            latitude: point.position.as_ref().map(|position| position.latitude),
+                            longitude: point.position.as_ref().map(|position| position.longitude),
+                            altitude_meters: point.altitude_meters,
+                            point_type: point
+                                .point_type
+                                .and_then(|kind| {
+                                    COURSE_POINT_TYPES.iter().position(|known| *known == kind)
+                                })
+                                .map_or(0, |index| index as i32 + 1),
+                            notes: point.notes.clone(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Converts the protobuf representation back. Unknown enumeration values are read as absent.
+    ///
+    /// # Parameters
+    ///
+    /// `database: Database` -- The protobuf message.
+    ///
+    /// # Returns
+    ///
+    /// `Result<TrainingCenterDatabase, ConversionError>` -- The database if OK, Error if a time or date is out of
+    /// range or a multisport leg has no activity.
+    pub fn from_proto(database: Database) -> Result<TrainingCenterDatabase, ConversionError> {
+        let mut tcx = TrainingCenterDatabase::default();
+        if !database.activities.is_empty() || !database.multi_sport_sessions.is_empty() {
+            tcx.activities = Some(tcx::Activities {
+                activities: database
+                    .activities
+                    .into_iter()
+                    .map(activity_from_proto)
+                    .collect::<Result<_, _>>()?,
+                multi_sport_sessions: database
+                    .multi_sport_sessions
+                    .into_iter()
+                    .map(session_from_proto)
+                    .collect::<Result<_, _>>()?,
+            });
+        }
+        if !database.workouts.is_empty() {
+            let mut workouts = tcx::WorkoutList::default();
+            for workout in database.workouts {
+                workouts.workouts.push(tcx::Workout {
+                    sport: workout.sport,
+                    name: workout.name,
+                    steps: workout.steps.into_iter().map(step_from_proto).collect(),
+                    scheduled_on: workout
+                        .scheduled_on
+                        .into_iter()
+                        .map(|date| {
+                            NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                                .map_err(|_| ConversionError::InvalidDate(date))
+                        })
+                        .collect::<Result<_, _>>()?,
+                    notes: workout.notes,
+                    ..Default::default()
+                });
+            }
+            tcx.workouts = Some(workouts);
+        }
+        if !database.courses.is_empty() {
+            let mut courses = tcx::CourseList::default();
+            for course in database.courses {
+                courses.courses.push(tcx::Course {
+                    name: course.name,
+                    tracks: match course.tracks.is_empty() {
+                        true => None,
+                        false => Some(
+                            course
+                                .tracks
+                                .into_iter()
+                                .map(track_from_proto)
+                                .collect::<Result<_, _>>()?,
+                        ),
+                    },
+                    notes: course.notes,
+                    course_points: course
+                        .course_points
+                        .into_iter()
+                        .map(|point| {
+                            Ok(tcx::CoursePoint {
+                                name: point.name,
+                                time: time_from_proto(point.time_ms)?,
+                                position: position_from_proto(point.latitude, point.longitude),
+                                altitude_meters: point.altitude_meters,
+                                point_type: usize::try_from(point.point_type - 1)
+                                    .ok()
+                                    .and_then(|index| COURSE_POINT_TYPES.get(index).copied()),
+                                notes: point.notes,
+                                extensions: None,
+                            })
+                        })
+                        .collect::<Result<_, _>>()?,
+                    ..Default::default()
+                });
+            }
+            tcx.courses = Some(courses);
+        }
+        Ok(tcx)
+    }
+
+    /// Converts to the protobuf representation (see `to_proto`) and encodes it.
+    pub fn to_proto_bytes(&self) -> Vec<u8> {
+        self.to_proto().encode_to_vec()
+    }
+
+    /// Decodes the protobuf representation and converts it back (see `from_proto`).
+    ///
+    /// # Parameters
+    ///
+    /// `bytes: &[u8]` -- The encoded `Database` message.
+    ///
+    /// # Returns
+    ///
+    /// `Result<TrainingCenterDatabase, Box<dyn Error>>` -- The database if OK, Error if not.
+    pub fn from_proto_bytes(bytes: &[u8]) -> Result<TrainingCenterDatabase, Box<dyn Error>> {
+        let database = Database::decode(bytes)?;
+        Ok(TrainingCenterDatabase::from_proto(database)?)
+    }
+}
+
+fn activity_to_proto(activity: &tcx::Activity) -> Activity {
+    Activity {
+        sport: activity.sport.clone(),
+        id: activity.id.clone(),
+        laps: activity.laps.iter().map(lap_to_proto).collect(),
+        notes: activity.notes.clone(),
+        creator: activity.creator.as_ref().map(|device| Device {
+            name: device.name.clone(),
+            unit_id: device.unit_id,
+            product_id: device.product_id.map(u32::from),
+            version: device.version.as_ref().map(|version| Version {
+                version_major: version.version_major.into(),
+                version_minor: version.version_minor.into(),
+                build_major: version.build_major.map(u32::from),
+                build_minor: version.build_minor.map(u32::from),
+            }),
+        }),
+    }
+}
+
+fn lap_to_proto(lap: &tcx::ActivityLap) -> Lap {
+    let lx = lap.extensions.as_ref().and_then(|ext| ext.lx.as_ref());
+    Lap {
+        start_time_ms: lap.start_time.map(|time| time.timestamp_millis()),
+        total_time_seconds: lap.total_time_seconds,
+        distance_meters: lap.distance_meters,
+        maximum_speed: lap.maximum_speed,
+        calories: lap.calories as u32,
+        average_heart_rate: lap.average_heart_rate,
+        maximum_heart_rate: lap.maximum_heart_rate,
+        intensity: intensity_to_proto(lap.intensity.as_ref()),
+        cadence: lap.cadence.map(u32::from),
+        trigger_method: match lap.trigger_method {
+            None => TriggerMethod::Unspecified,
+            Some(tcx::TriggerMethod::Manual) => TriggerMethod::Manual,
+            Some(tcx::TriggerMethod::Distance) => TriggerMethod::Distance,
+            Some(tcx::TriggerMethod::Location) => TriggerMethod::Location,
+            Some(tcx::TriggerMethod::Time) => TriggerMethod::Time,
+            Some(tcx::TriggerMethod::HeartRate) => TriggerMethod::HeartRate,
+        } as i32,
+        tracks: lap.tracks.iter().map(track_to_proto).collect(),
+        notes: lap.notes.clone(),
+        lx: lx.map(|lx| LapExtension {
+            avg_speed: lx.avg_speed,
+            max_bike_cadence: lx.max_bike_cadence.map(u32::from),
+            avg_run_cadence: lx.avg_run_cadence.map(u32::from),
+            max_run_cadence: lx.max_run_cadence.map(u32::from),
+            steps: lx.steps.map(u32::from),
+            avg_watts: lx.avg_watts.map(u32::from),
+            max_watts: lx.max_watts.map(u32::from),
+        }),
+    }
+}
+
+fn intensity_to_proto(intensity: Option<&tcx::Intensity>) -> i32 {
+    let intensity = match intensity {
+        None => Intensity::Unspecified,
+        Some(tcx::Intensity::Active) => Intensity::Active,
+        Some(tcx::Intensity::Resting) => Intensity::Resting,
+    };
+    intensity as i32
+}
+
+fn intensity_from_proto(intensity: i32) -> Option<tcx::Intensity> {
+    match Intensity::try_from(intensity) {
+        Ok(Intensity::Active) => Some(tcx::Intensity::Active),
+        Ok(Intensity::Resting) => Some(tcx::Intensity::Resting),
+        _ => None,
+    }
+}
+
+fn activity_from_proto(activity: Activity) -> Result<tcx::Activity, ConversionError> {
+    Ok(tcx::Activity {
+        sport: activity.sport,
+        id: activity.id,
+        laps: activity
+            .laps
+            .into_iter()
+            .map(lap_from_proto)
+            .collect::<Result<_, _>>()?,
+        notes: activity.notes,
+        creator: activity.creator.map(|device| tcx::Device {
+            name: device.name,
+            unit_id: device.unit_id,
+            product_id: device.product_id.and_then(|id| u16::try_from(id).ok()),
+            version: device.version.map(|version| tcx::Version {
+                version_major: u16::try_from(version.version_major).unwrap_or(u16::MAX),
+                version_minor: u16::try_from(version.version_minor).unwrap_or(u16::MAX),
+                build_major: version
+                    .build_major
+                    .and_then(|build| u16::try_from(build).ok()),
+                build_minor: version
+                    .build_minor
+                    .and_then(|build| u16::try_from(build).ok()),
+            }),
+        }),
+        extensions: None,
+    })
+}
+
+fn lap_from_proto(lap: Lap) -> Result<tcx::ActivityLap, ConversionError> {
+    Ok(tcx::ActivityLap {
+        start_time: lap.start_time_ms.map(time_from_proto).transpose()?,
+        total_time_seconds: lap.total_time_seconds,
+        distance_meters: lap.distance_meters,
+        maximum_speed: lap.maximum_speed,
+        calories: u16::try_from(lap.calories).unwrap_or(u16::MAX),
+        average_heart_rate: lap.average_heart_rate,
+        maximum_heart_rate: lap.maximum_heart_rate,
+        intensity: intensity_from_proto(lap.intensity),
+        cadence: lap.cadence.and_then(|cadence| u8::try_from(cadence).ok()),
+        trigger_method: match TriggerMethod::try_from(lap.trigger_method) {
+            Ok(TriggerMethod::Manual) => Some(tcx::TriggerMethod::Manual),
+            Ok(TriggerMethod::Distance) => Some(tcx::TriggerMethod::Distance),
+            Ok(TriggerMethod::Location) => Some(tcx::TriggerMethod::Location),
+            Ok(TriggerMethod::Time) => Some(tcx::TriggerMethod::Time),
+            Ok(TriggerMethod::HeartRate) => Some(tcx::TriggerMethod::HeartRate),
+            _ => None,
+        },
+        tracks: lap
+            .tracks
+            .into_iter()
+            .map(track_from_proto)
+            .collect::<Result<_, _>>()?,
+        notes: lap.notes,
+        extensions: lap.lx.map(|lx| tcx::Extensions {
+            lx: Some(tcx::Ns3Lx {
+                avg_speed: lx.avg_speed,
+                max_bike_cadence: lx
+                    .max_bike_cadence
+                    .and_then(|cadence| u8::try_from(cadence).ok()),
+                avg_run_cadence: lx
+                    .avg_run_cadence
+                    .and_then(|cadence| u8::try_from(cadence).ok()),
+                max_run_cadence: lx
+                    .max_run_cadence
+                    .and_then(|cadence| u8::try_from(cadence).ok()),
+                steps: lx.steps.and_then(|steps| u16::try_from(steps).ok()),
+                avg_watts: lx.avg_watts.and_then(|watts| u16::try_from(watts).ok()),
+                max_watts: lx.max_watts.and_then(|watts| u16::try_from(watts).ok()),
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+fn session_from_proto(
+    session: MultiSportSession,
+) -> Result<tcx::MultiSportSession, ConversionError> {
+    let mut legs = session.legs.into_iter();
+    let first = legs
+        .next()
+        .and_then(|leg| leg.activity)
+        .ok_or(ConversionError::MissingActivity)?;
+    Ok(tcx::MultiSportSession {
+        id: time_from_proto(session.id_ms)?,
+        first_sport: tcx::FirstSport {
+            activity: activity_from_proto(first)?,
+        },
+        next_sports: legs
+            .map(|leg| {
+                Ok(tcx::NextSport {
+                    transition: leg.transition.map(lap_from_proto).transpose()?,
+                    activity: activity_from_proto(
+                        leg.activity.ok_or(ConversionError::MissingActivity)?,
+                    )?,
+                })
+            })
+            .collect::<Result<_, _>>()?,
+        notes: session.notes,
+    })
+}
+
+fn step_to_proto(step: &tcx::WorkoutStep) -> WorkoutStep {
+    WorkoutStep {
+        step_type: step.step_type.clone(),
+        step_id: step.step_id.into(),
+        name: step.name.clone(),
+        duration: step.duration.as_ref().map(|duration| StepDuration {
+            duration_type: duration.duration_type.clone(),
+            seconds: duration.seconds.map(u32::from),
+            meters: duration.meters.map(u32::from),
+            heart_rate: duration.heart_rate.as_ref().map(heart_rate_to_proto),
+            calories: duration.calories.map(u32::from),
+        }),
+        intensity: intensity_to_proto(step.intensity.as_ref()),
+        target: step.target.as_ref().map(|target| StepTarget {
+            target_type: target.target_type.clone(),
+            speed_zone: target.speed_zone.as_ref().map(zone_to_proto),
+            heart_rate_zone: target.heart_rate_zone.as_ref().map(zone_to_proto),
+            low: target.low,
+            high: target.high,
+        }),
+        repetitions: step.repetitions.map(u32::from),
+        children: step.children.iter().map(step_to_proto).collect(),
+    }
+}
+
+fn step_from_proto(step: WorkoutStep) -> tcx::WorkoutStep {
+    tcx::WorkoutStep {
+        step_type: step.step_type,
+        step_id: u8::try_from(step.step_id).unwrap_or(u8::MAX),
+        name: step.name,
+        duration: step.duration.map(|duration| tcx::StepDuration {
+            duration_type: duration.duration_type,
+            seconds: duration
+                .seconds
+                .and_then(|seconds| u16::try_from(seconds).ok()),
+            meters: duration
+                .meters
+                .and_then(|meters| u16::try_from(meters).ok()),
+            heart_rate: duration.heart_rate.map(heart_rate_from_proto),
+            calories: duration
+                .calories
+                .and_then(|calories| u16::try_from(calories).ok()),
+        }),
+        intensity: intensity_from_proto(step.intensity),
+        target: step.target.map(|target| tcx::StepTarget {
+            target_type: target.target_type,
+            speed_zone: target.speed_zone.map(zone_from_proto),
+            heart_rate_zone: target.heart_rate_zone.map(zone_from_proto),
+            low: target.low,
+            high: target.high,
+        }),
+        repetitions: step
+            .repetitions
+            .and_then(|repetitions| u8::try_from(repetitions).ok()),
+        children: step.children.into_iter().map(step_from_proto).collect(),
+    }
+}
+
+fn zone_to_proto(zone: &tcx::Zone) -> Zone {
+    Zone {
+        zone_type: zone.zone_type.clone(),
+        number: zone.number.map(u32::from),
+        view_as: match zone.view_as {
+            None => SpeedType::Unspecified,
+            Some(tcx::SpeedType::Pace) => SpeedType::Pace,
+            Some(tcx::SpeedType::Speed) => SpeedType::Speed,
+        } as i32,
+        low_in_meters_per_second: zone.low_in_meters_per_second,
+        high_in_meters_per_second: zone.high_in_meters_per_second,
+        low: zone.low.as_ref().map(heart_rate_to_proto),
+        high: zone.high.as_ref().map(heart_rate_to_proto),
+    }
+}
+
+fn zone_from_proto(zone: Zone) -> tcx::Zone {
+    tcx::Zone {
+        zone_type: zone.zone_type,
+        number: zone.number.and_then(|number| u8::try_from(number).ok()),
+        view_as: match SpeedType::try_from(zone.view_as) {
+            Ok(SpeedType::Pace) => Some(tcx::SpeedType::Pace),
+            Ok(SpeedType::Speed) => Some(tcx::SpeedType::Speed),
+            _ => None,
+        },
+        low_in_meters_per_second: zone.low_in_meters_per_second,
+        high_in_meters_per_second: zone.high_in_meters_per_second,
+        low: zone.low.map(heart_rate_from_proto),
+        high: zone.high.map(heart_rate_from_proto),
+    }
+}
+
+fn heart_rate_to_proto(heart_rate: &tcx::HeartRateValue) -> HeartRateValue {
+    HeartRateValue {
+        value_type: heart_rate.value_type.clone(),
+        value: heart_rate.value.into(),
+    }
+}
+
+fn heart_rate_from_proto(heart_rate: HeartRateValue) -> tcx::HeartRateValue {
+    tcx::HeartRateValue {
+        value_type: heart_rate.value_type,
+        value: u8::try_from(heart_rate.value).unwrap_or(u8::MAX),
+    }
+}
+
+fn track_to_proto(track: &tcx::Track) -> Track {
+    Track {
+        trackpoints: track
+            .trackpoints
+            .iter()
+            .map(|point| {
+                let tpx = point.extensions.as_ref().and_then(|ext| ext.tpx.as_ref());
+                Trackpoint {
+                    time_ms: point.time.timestamp_millis(),
+                    latitude: point.position.as_ref().map(|position| position.latitude),
+                    longitude: point.position.as_ref().map(|position| position.longitude),
+                    altitude_meters: point.altitude_meters,
+                    distance_meters: point.distance_meters,
+                    heart_rate: point.heart_rate.as_ref().map(|hr| hr.value),
+                    cadence: point.cadence.map(u32::from),
+                    speed: tpx.and_then(|tpx| tpx.speed),
+                    run_cadence: tpx.and_then(|tpx| tpx.run_cadence).map(u32::from),
+                    watts: tpx.and_then(|tpx| tpx.watts).map(u32::from),
+                }
+            })
+            .collect(),
+    }
+}
+
+fn track_from_proto(track: Track) -> Result<tcx::Track, ConversionError> {
+    let mut trackpoints = Vec::with_capacity(track.trackpoints.len());
+    for point in track.trackpoints {
+        let tpx = tcx::Ns3Tpx {
+            speed: point.speed,
+            run_cadence: point
+                .run_cadence
+                .and_then(|cadence| u8::try_from(cadence).ok()),
+            watts: point.watts.and_then(|watts| u16::try_from(watts).ok()),
+        };
+        let has_tpx = tpx.speed.is_some() || tpx.run_cadence.is_some() || tpx.watts.is_some();
+        trackpoints.push(tcx::Trackpoint {
+            time: time_from_proto(point.time_ms)?,
+            position: position_from_proto(point.latitude, point.longitude),
+            altitude_meters: point.altitude_meters,
+            distance_meters: point.distance_meters,
+            heart_rate: point.heart_rate.map(|value| tcx::HeartRate { value }),
+            cadence: point.cadence.and_then(|cadence| u8::try_from(cadence).ok()),
+            extensions: has_tpx.then(|| tcx::Extensions {
+                tpx: Some(tpx),
+                ..Default::default()
+            }),
+        });
+    }
+    Ok(tcx::Track { trackpoints })
+}
+
+fn time_from_proto(millis: i64) -> Result<DateTime<Utc>, ConversionError> {
+    DateTime::from_timestamp_millis(millis).ok_or(ConversionError::InvalidTime(millis))
+}
+
+fn position_from_proto(latitude: Option<f64>, longitude: Option<f64>) -> Option<tcx::Position> {
+    Some(tcx::Position {
+        latitude: latitude?,
+        longitude: longitude?,
+    })
+}
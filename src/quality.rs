@@ -0,0 +1,256 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Data-quality checks. Each problem found is reported as an `Issue` with a machine-readable code and a severity,
+//! so that ingestion services can decide per code whether to reject, repair or accept a file.
+
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::geo::seconds_between;
+use crate::tcx::{Activity, Trackpoint, TrainingCenterDatabase};
+
+/// Speed, in meters per second, above which consecutive Trackpoints are considered implausible for any sport.
+const MAX_PLAUSIBLE_SPEED: f64 = 60.0;
+
+/// How serious an issue is.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The data is suspicious or incomplete, but usable.
+    Warning,
+    /// The data is inconsistent and will produce wrong results if used as-is.
+    Error,
+}
+
+/// The kinds of data-quality problems that are detected. Codes starting with `E` are errors and codes starting
+/// with `W` are warnings.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IssueCode {
+    /// E001: A Trackpoint is timestamped before the one preceding it.
+    NonMonotonicTime,
+    /// E002: A Trackpoint's `DistanceMeters` is less than the one preceding it.
+    NonMonotonicDistance,
+    /// E003: A position lies outside the valid latitude/longitude range.
+    InvalidPosition,
+    /// W101: Trackpoints with a position have no altitude.
+    MissingAltitude,
+    /// W102: Some, but not all, Trackpoints of an activity have a position.
+    MissingPosition,
+    /// W103: Two consecutive Trackpoints share a timestamp.
+    DuplicateTimestamp,
+    /// W104: The distance between consecutive Trackpoints implies an impossible speed.
+    ImplausibleSpeed,
+    /// W105: A lap has no Trackpoints.
+    EmptyLap,
+}
+
+impl IssueCode {
+    /// The short machine-readable code, such as `E001`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            IssueCode::NonMonotonicTime => "E001",
+            IssueCode::NonMonotonicDistance => "E002",
+            IssueCode::InvalidPosition => "E003",
+            IssueCode::MissingAltitude => "W101",
+            IssueCode::MissingPosition => "W102",
+            IssueCode::DuplicateTimestamp => "W103",
+            IssueCode::ImplausibleSpeed => "W104",
+            IssueCode::EmptyLap => "W105",
+        }
+    }
+
+    /// The severity associated with the code.
+    pub fn severity(&self) -> Severity {
+        if self.code().starts_with('E') {
+            Severity::Error
+        } else {
+            Severity::Warning
+        }
+    }
+}
+
+impl fmt::Display for IssueCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:?}", self.code(), self)
+    }
+}
+
+/// A single data-quality problem and where it was found.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Issue {
+    /// What kind of problem this is.
+    pub code: IssueCode,
+
+    /// Index of the activity within the database.
+    pub activity: usize,
+
+    /// Index of the lap within the activity, if the problem is specific to a lap.
+    pub lap: Option<usize>,
+
+    /// Index of the Trackpoint within the lap (counting across tracks), if the problem is specific to a Trackpoint.
+    pub trackpoint: Option<usize>,
+
+    /// Human readable description.
+    pub message: String,
+}
+
+impl Issue {
+    /// The severity of the issue.
+    pub fn severity(&self) -> Severity {
+        self.code.severity()
+    }
+}
+
+impl TrainingCenterDatabase {
+    /// Checks every activity for data-quality problems.
+    ///
+    /// # Parameters
+    ///
+    /// None. `&self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<Issue>` -- The problems found, in document order. Empty if the data looks clean.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::quality::Severity;
+    ///
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let issues = tcx.check_quality();
+    /// assert!(issues.iter().all(|issue| issue.severity() != Severity::Error));
+    /// ```
+    pub fn check_quality(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        if let Some(activities) = &self.activities {
+            for (index, activity) in activities.activities.iter().enumerate() {
+                check_activity(activity, index, &mut issues);
+            }
+        }
+        issues
+    }
+}
+
+/// Appends the problems found in a single activity.
+fn check_activity(activity: &Activity, activity_index: usize, issues: &mut Vec<Issue>) {
+    let has_positions = activity.trackpoints().any(|point| point.position.is_some());
+    let mut previous: Option<&Trackpoint> = None;
+
+    for (lap_index, lap) in activity.laps.iter().enumerate() {
+        let mut issue = |code: IssueCode, trackpoint: Option<usize>, message: String| {
+            issues.push(Issue {
+                code,
+                activity: activity_index,
+                lap: Some(lap_index),
+                trackpoint,
+                message,
+            })
+        };
+
+        let mut count = 0;
+        let mut missing_altitude = 0;
+        let mut missing_position = 0;
+        for (point_index, point) in lap.trackpoints().enumerate() {
+            count += 1;
+            match &point.position {
+                Some(position) => {
+                    if position.latitude.abs() > 90.0 || position.longitude.abs() > 180.0 {
+                        issue(
+                            IssueCode::InvalidPosition,
+                            Some(point_index),
+                            format!(
+                                "position {}, {} is out of range",
+                                position.latitude, position.longitude
+                            ),
+                        );
+                    }
+                    if point.altitude_meters.is_none() {
+                        missing_altitude += 1;
+                    }
+                }
+                None => missing_position += 1,
+            }
+
+            if let Some(prev) = previous {
+                let elapsed = seconds_between(prev, point);
+                if elapsed < 0.0 {
+                    issue(
+                        IssueCode::NonMonotonicTime,
+                        Some(point_index),
+                        format!("{} is before {}", point.time, prev.time),
+                    );
+                } else if elapsed == 0.0 {
+                    issue(
+                        IssueCode::DuplicateTimestamp,
+                        Some(point_index),
+                        format!("{} appears twice", point.time),
+                    );
+                }
+
+                if let (Some(d1), Some(d2)) = (prev.distance_meters, point.distance_meters) {
+                    if d2 < d1 {
+                        issue(
+                            IssueCode::NonMonotonicDistance,
+                            Some(point_index),
+                            format!("distance drops from {} m to {} m", d1, d2),
+                        );
+                    } else if elapsed > 0.0 && (d2 - d1) / elapsed > MAX_PLAUSIBLE_SPEED {
+                        issue(
+                            IssueCode::ImplausibleSpeed,
+                            Some(point_index),
+                            format!("{:.0} m covered in {} s", d2 - d1, elapsed),
+                        );
+                    }
+                }
+            }
+            previous = Some(point);
+        }
+
+        if count == 0 {
+            issue(
+                IssueCode::EmptyLap,
+                None,
+                String::from("lap has no trackpoints"),
+            );
+        }
+        if missing_altitude > 0 {
+            issue(
+                IssueCode::MissingAltitude,
+                None,
+                format!(
+                    "{} of {} trackpoints have a position but no altitude",
+                    missing_altitude, count
+                ),
+            );
+        }
+        if has_positions && missing_position > 0 {
+            issue(
+                IssueCode::MissingPosition,
+                None,
+                format!(
+                    "{} of {} trackpoints have no position",
+                    missing_position, count
+                ),
+            );
+        }
+    }
+}
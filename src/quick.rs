@@ -0,0 +1,294 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! An alternative reader built on quick-xml, several times faster than the default serde-xml-rs reader on large
+//! files. Extension elements are matched by local name, so `TPX` is found whatever prefix the file binds to the
+//! ActivityExtension namespace, and extension elements that this crate does not model are kept as raw XML on
+//! `Extensions::unknown` (for activities, laps and Trackpoints) rather than dropped. Requires the `quick_xml`
+//! feature.
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::ResolveResult;
+use quick_xml::reader::NsReader;
+use std::collections::HashMap;
+use std::io::{BufReader, Read};
+
+use crate::error::Error;
+use crate::storage::TcxSource;
+use crate::tcx::{
+    with_trackpoint_policy, Extensions, OriginalXml, ReadOptions, TrackpointPolicy,
+    TrainingCenterDatabase,
+};
+
+/// Extension elements that deserialize into `Extensions` fields.
+const KNOWN_EXTENSIONS: &[&str] = &[
+    "TPX",
+    "LX",
+    "Pressure",
+    "Temperature",
+    "HDOP",
+    "RRIntervals",
+    "HeartRateSource",
+];
+
+/// Elements whose unknown extensions are kept, by the path leading to them.
+const EXTENSION_OWNERS: &[(Owner, &[&str])] = &[
+    (
+        Owner::Activity,
+        &["TrainingCenterDatabase", "Activities", "Activity"],
+    ),
+    (
+        Owner::Lap,
+        &["TrainingCenterDatabase", "Activities", "Activity", "Lap"],
+    ),
+    (
+        Owner::Trackpoint,
+        &[
+            "TrainingCenterDatabase",
+            "Activities",
+            "Activity",
+            "Lap",
+            "Track",
+            "Trackpoint",
+        ],
+    ),
+];
+
+/// The kinds of element whose unknown extensions are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Owner {
+    Activity,
+    Lap,
+    Trackpoint,
+}
+
+/// Reads TCX data from a buffer previously defined.
+///
+/// # Parameters
+///
+/// `reader: &mut BufReader<R>` -- A buffer of a file previously opened.
+///
+/// # Returns
+///
+/// `Result<TrainingCenterDatabase, crate::tcx::Error>`
+///
+/// # Example
+///
+/// ```rust
+/// let file = std::fs::File::open("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+/// let mut reader = std::io::BufReader::new(file);
+/// let tcx = crate::tcx::quick::read(&mut reader).unwrap();
+/// assert_eq!(tcx.activities.unwrap().activities.len(), 1);
+/// ```
+pub fn read<R: Read>(reader: &mut BufReader<R>) -> Result<TrainingCenterDatabase, Error> {
+    read_with_options(reader, &ReadOptions::default())
+}
+
+/// Reads TCX data from the filename specified.
+///
+/// # Parameters
+///
+/// `filename: &str` -- The name of the file to be read.
+///
+/// # Returns
+///
+/// `Result<TrainingCenterDatabase, crate::tcx::Error>`
+///
+/// # Example
+///
+/// ```rust
+/// let tcx = crate::tcx::quick::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+/// ```
+pub fn read_file(filename: &str) -> Result<TrainingCenterDatabase, Error> {
+    let mut path = std::path::Path::new(filename);
+    let file = path.open()?;
+    let mut reader = BufReader::new(file);
+    read(&mut reader)
+}
+
+/// Reads TCX data from a buffer previously defined, as controlled by the options given.
+///
+/// # Parameters
+///
+/// `reader: &mut BufReader<R>` -- A buffer of a file previously opened.
+///
+/// `options: &ReadOptions` -- How to read the data.
+///
+/// # Returns
+///
+/// `Result<TrainingCenterDatabase, crate::tcx::Error>`
+pub fn read_with_options<R: Read>(
+    reader: &mut BufReader<R>,
+    options: &ReadOptions,
+) -> Result<TrainingCenterDatabase, Error> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut tcx: TrainingCenterDatabase =
+        with_trackpoint_policy(options.trackpoint_policy, || {
+            quick_xml::de::from_reader(bytes.as_slice())
+                .map_err(|err| Error::from_quick_xml(err, &bytes))
+        })?;
+    attach_unknown_extensions(&mut tcx, &bytes, options.trackpoint_policy)?;
+    tcx.original = OriginalXml::new(bytes, options.original);
+    Ok(tcx)
+}
+
+/// Copies unmodelled extension elements of activities, laps and Trackpoints onto the matching `Extensions`. The
+/// Trackpoints the policy skipped are skipped here too, so that the rest line up.
+fn attach_unknown_extensions(
+    tcx: &mut TrainingCenterDatabase,
+    bytes: &[u8],
+    policy: TrackpointPolicy,
+) -> Result<(), Error> {
+    let mut unknown = collect_unknown_extensions(bytes, policy)?;
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    let mut take = |owner: Owner, index: usize, extensions: &mut Option<Extensions>| {
+        if let Some(fragments) = unknown.remove(&(owner, index)) {
+            extensions.get_or_insert_with(Extensions::default).unknown = fragments;
+        }
+    };
+
+    let activities = match tcx.activities.as_mut() {
+        Some(activities) => &mut activities.activities,
+        None => return Ok(()),
+    };
+    let (mut lap_index, mut point_index) = (0, 0);
+    for (activity_index, activity) in activities.iter_mut().enumerate() {
+        for lap in activity.laps.iter_mut() {
+            for point in lap.trackpoints_mut() {
+                take(Owner::Trackpoint, point_index, &mut point.extensions);
+                point_index += 1;
+            }
+            take(Owner::Lap, lap_index, &mut lap.extensions);
+            lap_index += 1;
+        }
+        take(Owner::Activity, activity_index, &mut activity.extensions);
+    }
+    Ok(())
+}
+
+/// Scans the document for extension elements that `Extensions` has no field for, keyed by the kind of element they
+/// belong to and its index in document order.
+fn collect_unknown_extensions(
+    bytes: &[u8],
+    policy: TrackpointPolicy,
+) -> Result<HashMap<(Owner, usize), Vec<String>>, Error> {
+    let mut reader = NsReader::from_reader(bytes);
+    // The index of the next Trackpoint within the current track.
+    let mut track_index = 0;
+    // Each open element's local name, and its key if it is an owner of extensions.
+    let mut path: Vec<(String, Option<(Owner, usize)>)> = Vec::new();
+    let mut counts: HashMap<Owner, usize> = HashMap::new();
+    let mut unknown: HashMap<(Owner, usize), Vec<String>> = HashMap::new();
+
+    loop {
+        let start = reader.buffer_position() as usize;
+        let event = reader
+            .read_event()
+            .map_err(|err| Error::from_quick_xml(err.into(), bytes))?;
+        let (element, is_empty) = match &event {
+            Event::Start(element) => (element.to_owned(), false),
+            Event::Empty(element) => (element.to_owned(), true),
+            Event::End(_) => {
+                path.pop();
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+        let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+
+        // An unknown child of an owner's Extensions: keep it whole.
+        if let [.., (_, Some(key)), (parent, _)] = path.as_slice() {
+            if parent == "Extensions" && !KNOWN_EXTENSIONS.contains(&name.as_str()) {
+                if !is_empty {
+                    reader
+                        .read_to_end(element.name())
+                        .map_err(|err| Error::from_quick_xml(err.into(), bytes))?;
+                }
+                let end = reader.buffer_position() as usize;
+                let fragment = String::from_utf8_lossy(&bytes[start..end]).into_owned();
+                unknown
+                    .entry(*key)
+                    .or_default()
+                    .push(declare_namespace(&reader, &element, fragment));
+                continue;
+            }
+        }
+
+        if let [.., (parent, _)] = path.as_slice() {
+            if parent == "Track" && name == "Trackpoint" {
+                track_index += 1;
+                if !policy.keeps(track_index - 1) {
+                    if !is_empty {
+                        reader
+                            .read_to_end(element.name())
+                            .map_err(|err| Error::from_quick_xml(err.into(), bytes))?;
+                    }
+                    continue;
+                }
+            }
+        }
+        if name == "Track" {
+            track_index = 0;
+        }
+
+        if !is_empty {
+            path.push((name, None));
+            let names: Vec<&str> = path.iter().map(|(name, _)| name.as_str()).collect();
+            if let Some((owner, _)) = EXTENSION_OWNERS
+                .iter()
+                .find(|(_, owner_path)| names.as_slice() == *owner_path)
+            {
+                let count = counts.entry(*owner).or_insert(0);
+                path.last_mut().unwrap().1 = Some((*owner, *count));
+                *count += 1;
+            }
+        }
+    }
+    Ok(unknown)
+}
+
+/// Adds a declaration of the element's namespace prefix to a fragment, so that it remains valid XML on its own.
+fn declare_namespace(reader: &NsReader<&[u8]>, element: &BytesStart, fragment: String) -> String {
+    let prefix = match element.name().prefix() {
+        Some(prefix) => String::from_utf8_lossy(prefix.as_ref()).into_owned(),
+        None => return fragment,
+    };
+    let namespace = match reader.resolve_element(element.name()).0 {
+        ResolveResult::Bound(namespace) => String::from_utf8_lossy(namespace.as_ref()).into_owned(),
+        _ => return fragment,
+    };
+    let declaration = format!("xmlns:{}=", prefix);
+    let tag_end = 1 + element.name().as_ref().len();
+    if fragment[..fragment.find('>').unwrap_or(fragment.len())].contains(&declaration) {
+        return fragment;
+    }
+    format!(
+        "{} {}\"{}\"{}",
+        &fragment[..tag_end],
+        declaration,
+        namespace,
+        &fragment[tag_end..]
+    )
+}
@@ -0,0 +1,206 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Repairs for inconsistencies commonly found in files exported by third-party software.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::tcx::{Activity, Track, Trackpoint};
+
+/// Slack, in seconds, allowed when comparing a lap's `TotalTimeSeconds` against its time window.
+const LAP_TIME_TOLERANCE_SECONDS: f64 = 1.0;
+
+/// The outcome of `Activity::align_laps_to_trackpoints`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct LapAlignmentReport {
+    /// Number of Trackpoints moved to a different lap.
+    pub moved_trackpoints: usize,
+
+    /// Indices of laps whose `TotalTimeSeconds` is longer than the time between their start and the start of the next lap.
+    pub inconsistent_laps: Vec<usize>,
+}
+
+impl Activity {
+    /// Verifies that each lap's Trackpoints fall between its `StartTime` and the next lap's `StartTime`, moving
+    /// any that were assigned to the wrong lap (typically the first or last point of a lap in third-party exports).
+    /// Laps without a `StartTime` take the time of their first Trackpoint. Laps whose `TotalTimeSeconds` cannot fit
+    /// in their time window are reported, but not changed, since the correct value cannot be known.
+    ///
+    /// # Parameters
+    ///
+    /// None. `&mut self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// `LapAlignmentReport` -- How many Trackpoints were moved and which laps are still inconsistent.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let report = tcx.activities.as_mut().unwrap().activities[0].align_laps_to_trackpoints();
+    /// assert_eq!(report.moved_trackpoints, 0);
+    /// ```
+    pub fn align_laps_to_trackpoints(&mut self) -> LapAlignmentReport {
+        let mut report = LapAlignmentReport::default();
+
+        for lap in self.laps.iter_mut() {
+            if lap.start_time.is_none() {
+                let first_time = lap.trackpoints().next().map(|point| point.time);
+                lap.start_time = first_time;
+            }
+        }
+        let starts: Vec<Option<DateTime<Utc>>> =
+            self.laps.iter().map(|lap| lap.start_time).collect();
+
+        // Pull every misplaced Trackpoint out of its lap, remembering where it came from.
+        let mut misplaced: Vec<(usize, usize, Trackpoint)> = Vec::new();
+        for (lap_index, lap) in self.laps.iter_mut().enumerate() {
+            for track in lap.tracks.iter_mut() {
+                let mut kept = Vec::with_capacity(track.trackpoints.len());
+                for point in track.trackpoints.drain(..) {
+                    match lap_for_time(&starts, point.time) {
+                        Some(target) if target != lap_index => {
+                            misplaced.push((lap_index, target, point))
+                        }
+                        _ => kept.push(point),
+                    }
+                }
+                track.trackpoints = kept;
+            }
+        }
+        report.moved_trackpoints = misplaced.len();
+
+        // Points from an earlier lap go to the front of the target lap, points from a later lap go to the back.
+        for (source, target, point) in misplaced {
+            let lap = &mut self.laps[target];
+            if lap.tracks.is_empty() {
+                lap.tracks.push(Track::default());
+            }
+            if source < target {
+                let track = lap.tracks.first_mut().unwrap();
+                let position = track
+                    .trackpoints
+                    .iter()
+                    .position(|existing| existing.time > point.time)
+                    .unwrap_or(track.trackpoints.len());
+                track.trackpoints.insert(position, point);
+            } else {
+                lap.tracks.last_mut().unwrap().trackpoints.push(point);
+            }
+        }
+        for lap in self.laps.iter_mut() {
+            lap.tracks.retain(|track| !track.trackpoints.is_empty());
+        }
+
+        // Check that each lap's timer time fits within its window.
+        for (lap_index, lap) in self.laps.iter().enumerate() {
+            let start = match lap.start_time {
+                Some(start) => start,
+                None => continue,
+            };
+            let end = match starts.get(lap_index + 1).copied().flatten() {
+                Some(next_start) => Some(next_start),
+                None => lap.trackpoints().last().map(|point| point.time),
+            };
+            if let Some(end) = end {
+                let window = (end - start).num_milliseconds() as f64 / 1000.0;
+                if lap.total_time_seconds > window + LAP_TIME_TOLERANCE_SECONDS {
+                    report.inconsistent_laps.push(lap_index);
+                }
+            }
+        }
+
+        report
+    }
+
+    /// Rebuilds the cumulative `DistanceMeters` series wherever it decreases. A value closer to zero than to the
+    /// previous value is treated as a sensor reset, so the distance covered since the reset is added on top of the
+    /// distance before it. Any other decrease is treated as a glitch and held at the previous value. Lap totals
+    /// are not changed.
+    ///
+    /// # Parameters
+    ///
+    /// None. `&mut self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// `usize` -- The number of Trackpoints whose distance was changed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let fixed = tcx.activities.as_mut().unwrap().activities[0].fix_distance_monotonicity();
+    /// assert_eq!(fixed, 0);
+    /// ```
+    pub fn fix_distance_monotonicity(&mut self) -> usize {
+        let mut fixed = 0;
+        let mut previous_raw: Option<f64> = None;
+        let mut offset = 0.0;
+        let mut previous_corrected = 0.0;
+
+        for point in self.trackpoints_mut() {
+            let raw = match point.distance_meters {
+                Some(raw) => raw,
+                None => continue,
+            };
+            let corrected = match previous_raw {
+                Some(previous) if raw < previous => {
+                    if raw <= previous - raw {
+                        // Reset: the sensor started counting from zero again.
+                        offset = previous_corrected;
+                        previous_raw = Some(raw);
+                        offset + raw
+                    } else {
+                        // Glitch: keep measuring from the previous value.
+                        previous_corrected
+                    }
+                }
+                _ => {
+                    previous_raw = Some(raw);
+                    offset + raw
+                }
+            };
+            if corrected != raw {
+                point.distance_meters = Some(corrected);
+                fixed += 1;
+            }
+            previous_corrected = corrected;
+        }
+
+        fixed
+    }
+}
+
+/// Index of the lap whose window contains the given time, or `None` if the lap start times are unknown.
+fn lap_for_time(starts: &[Option<DateTime<Utc>>], time: DateTime<Utc>) -> Option<usize> {
+    let mut result = None;
+    for (index, start) in starts.iter().enumerate() {
+        match start {
+            Some(start) if *start <= time => result = Some(index),
+            Some(_) => break,
+            None => return None,
+        }
+    }
+    Some(result.unwrap_or(0))
+}
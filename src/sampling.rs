@@ -0,0 +1,220 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! How often each channel was actually sampled. Devices record either every second or, in "smart recording" mode,
+//! only when something changes, and sensors drop out while the device keeps recording. Both affect analyses that
+//! assume evenly spaced samples, so this report tells callers whether to resample first.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::seconds_between;
+use crate::tcx::{Activity, Trackpoint};
+
+/// Shortest stretch, in seconds, without a channel while Trackpoints are still being recorded that counts as a
+/// dropout.
+const MIN_DROPOUT_SECONDS: f64 = 5.0;
+
+/// How far, in seconds, an interval may stray from one second and still count as every-second recording.
+const INTERVAL_TOLERANCE_SECONDS: f64 = 0.5;
+
+/// The share of intervals that must be about one second for the recording to count as every-second.
+const EVERY_SECOND_SHARE: f64 = 0.9;
+
+/// The data recorded in Trackpoints.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Position,
+    Altitude,
+    Distance,
+    HeartRate,
+    Cadence,
+    Speed,
+    Power,
+}
+
+impl Channel {
+    /// Every channel, in Trackpoint order.
+    pub const ALL: [Channel; 7] = [
+        Channel::Position,
+        Channel::Altitude,
+        Channel::Distance,
+        Channel::HeartRate,
+        Channel::Cadence,
+        Channel::Speed,
+        Channel::Power,
+    ];
+
+    /// True if the Trackpoint has a value for the channel. Cadence includes the TPX `RunCadence`.
+    fn present(self, point: &Trackpoint) -> bool {
+        let tpx = point.extensions.as_ref().and_then(|ext| ext.tpx.as_ref());
+        match self {
+            Channel::Position => point.position.is_some(),
+            Channel::Altitude => point.altitude_meters.is_some(),
+            Channel::Distance => point.distance_meters.is_some(),
+            Channel::HeartRate => point.heart_rate.is_some(),
+            Channel::Cadence => {
+                point.cadence.is_some() || tpx.is_some_and(|tpx| tpx.run_cadence.is_some())
+            }
+            Channel::Speed => tpx.is_some_and(|tpx| tpx.speed.is_some()),
+            Channel::Power => tpx.is_some_and(|tpx| tpx.watts.is_some()),
+        }
+    }
+}
+
+/// A stretch in which Trackpoints were recorded without a channel, such as when a sensor lost its connection.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Dropout {
+    /// Time of the last Trackpoint with the channel before the dropout.
+    pub start_time: DateTime<Utc>,
+
+    /// Time of the first Trackpoint with the channel after the dropout.
+    pub end_time: DateTime<Utc>,
+
+    /// How long the channel was missing, in seconds.
+    pub duration_seconds: f64,
+}
+
+/// How one channel was sampled.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChannelSampling {
+    /// The channel.
+    pub channel: Channel,
+
+    /// The number of Trackpoints with a value for the channel.
+    pub samples: usize,
+
+    /// The median time between consecutive samples, in seconds. `None` with fewer than two samples.
+    pub median_interval_seconds: Option<f64>,
+
+    /// The longest time between consecutive samples, in seconds. `None` with fewer than two samples.
+    pub max_interval_seconds: Option<f64>,
+
+    /// Stretches of at least five seconds without the channel between two samples of it.
+    pub dropouts: Vec<Dropout>,
+}
+
+/// How an activity was sampled, overall and per channel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SamplingReport {
+    /// The number of Trackpoints.
+    pub trackpoints: usize,
+
+    /// The median time between consecutive Trackpoints, in seconds. `None` with fewer than two Trackpoints.
+    pub median_interval_seconds: Option<f64>,
+
+    /// True if fewer than 90% of the intervals between Trackpoints are about a second, as with smart recording.
+    pub smart_recording: bool,
+
+    /// The channels with at least one sample, in Trackpoint order.
+    pub channels: Vec<ChannelSampling>,
+
+    /// True if the samples are unevenly spaced or a channel dropped out, so analyses that assume a sample every
+    /// second should resample first (see `ActivitySeries::resample`).
+    pub needs_resampling: bool,
+}
+
+impl Activity {
+    /// Describes how often the Trackpoints, and each channel within them, were sampled.
+    ///
+    /// # Returns
+    ///
+    /// `SamplingReport` -- The overall and per channel sampling.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let report = tcx.activities.unwrap().activities[0].sampling_report();
+    /// assert_eq!(report.median_interval_seconds, Some(1.0));
+    /// assert!(!report.smart_recording);
+    /// ```
+    pub fn sampling_report(&self) -> SamplingReport {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        let intervals: Vec<f64> = points
+            .windows(2)
+            .map(|pair| seconds_between(pair[0], pair[1]))
+            .collect();
+        let every_second = intervals
+            .iter()
+            .filter(|interval| (*interval - 1.0).abs() <= INTERVAL_TOLERANCE_SECONDS)
+            .count();
+        let smart_recording = !intervals.is_empty()
+            && (every_second as f64) < intervals.len() as f64 * EVERY_SECOND_SHARE;
+
+        let channels: Vec<ChannelSampling> = Channel::ALL
+            .iter()
+            .map(|channel| channel_sampling(&points, *channel))
+            .filter(|sampling| sampling.samples > 0)
+            .collect();
+        let needs_resampling = smart_recording
+            || channels
+                .iter()
+                .any(|sampling| !sampling.dropouts.is_empty());
+        SamplingReport {
+            trackpoints: points.len(),
+            median_interval_seconds: median(intervals),
+            smart_recording,
+            channels,
+            needs_resampling,
+        }
+    }
+}
+
+fn channel_sampling(points: &[&Trackpoint], channel: Channel) -> ChannelSampling {
+    let sampled: Vec<usize> = (0..points.len())
+        .filter(|i| channel.present(points[*i]))
+        .collect();
+    let mut intervals = Vec::with_capacity(sampled.len());
+    let mut dropouts = Vec::new();
+    for pair in sampled.windows(2) {
+        let (from, to) = (points[pair[0]], points[pair[1]]);
+        let interval = seconds_between(from, to);
+        intervals.push(interval);
+        if pair[1] > pair[0] + 1 && interval >= MIN_DROPOUT_SECONDS {
+            dropouts.push(Dropout {
+                start_time: from.time,
+                end_time: to.time,
+                duration_seconds: interval,
+            });
+        }
+    }
+    ChannelSampling {
+        channel,
+        samples: sampled.len(),
+        max_interval_seconds: intervals.iter().copied().reduce(f64::max),
+        median_interval_seconds: median(intervals),
+        dropouts,
+    }
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        Some((values[middle - 1] + values[middle]) / 2.0)
+    } else {
+        Some(values[middle])
+    }
+}
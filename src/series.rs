@@ -0,0 +1,285 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Time series: an activity flattened into aligned vectors, one entry per sample, with resampling to a fixed
+//! interval, interpolation across short gaps and altitude smoothing.
+
+use chrono::{DateTime, Duration, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::speeds;
+use crate::tcx::{Activity, Trackpoint};
+
+/// An activity's Trackpoints as parallel vectors. Every vector has one entry per sample; values that were not
+/// recorded are `None`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ActivitySeries {
+    /// Time of each sample.
+    pub time: Vec<DateTime<Utc>>,
+
+    /// Latitude in degrees.
+    pub latitude: Vec<Option<f64>>,
+
+    /// Longitude in degrees.
+    pub longitude: Vec<Option<f64>>,
+
+    /// Altitude in meters.
+    pub altitude: Vec<Option<f64>>,
+
+    /// Cumulative distance in meters.
+    pub distance: Vec<Option<f64>>,
+
+    /// Heart rate in beats per minute.
+    pub heart_rate: Vec<Option<f64>>,
+
+    /// Cadence, from the Trackpoint or the TPX running cadence.
+    pub cadence: Vec<Option<f64>>,
+
+    /// Power in watts.
+    pub power: Vec<Option<f64>>,
+
+    /// Speed in meters per second, from the TPX extension or derived from the distances.
+    pub speed: Vec<Option<f64>>,
+
+    /// Air temperature in degrees Celsius, from the extension.
+    pub temperature: Vec<Option<f64>>,
+}
+
+impl ActivitySeries {
+    /// Flattens Trackpoints, in order, into a series.
+    ///
+    /// # Parameters
+    ///
+    /// `points: &[&Trackpoint]` -- The Trackpoints, in time order.
+    ///
+    /// # Returns
+    ///
+    /// `ActivitySeries` -- One sample per Trackpoint.
+    pub fn from_trackpoints(points: &[&Trackpoint]) -> Self {
+        let mut series = ActivitySeries {
+            speed: speeds(points),
+            ..Default::default()
+        };
+        for point in points {
+            let tpx = point.extensions.as_ref().and_then(|ext| ext.tpx.as_ref());
+            series.time.push(point.time);
+            series
+                .latitude
+                .push(point.position.as_ref().map(|pos| pos.latitude));
+            series
+                .longitude
+                .push(point.position.as_ref().map(|pos| pos.longitude));
+            series.altitude.push(point.altitude_meters);
+            series.distance.push(point.distance_meters);
+            series
+                .heart_rate
+                .push(point.heart_rate.as_ref().map(|hr| hr.value));
+            series.cadence.push(
+                point
+                    .cadence
+                    .or_else(|| tpx.and_then(|tpx| tpx.run_cadence))
+                    .map(f64::from),
+            );
+            series
+                .power
+                .push(tpx.and_then(|tpx| tpx.watts).map(f64::from));
+            series
+                .temperature
+                .push(point.extensions.as_ref().and_then(|ext| ext.temperature));
+        }
+        series
+    }
+
+    /// Number of samples.
+    pub fn len(&self) -> usize {
+        self.time.len()
+    }
+
+    /// Returns true if there are no samples.
+    pub fn is_empty(&self) -> bool {
+        self.time.is_empty()
+    }
+
+    /// Resamples the series to a fixed interval, starting at the first sample. Each value is interpolated
+    /// linearly between the recorded values either side of it; values in gaps longer than `max_gap_seconds`
+    /// (such as while the recording was paused) are left as `None`.
+    ///
+    /// # Parameters
+    ///
+    /// `interval_seconds: f64` -- The time between samples, for example `1.0` for 1 Hz.
+    ///
+    /// `max_gap_seconds: f64` -- The longest gap between recorded values to interpolate across.
+    ///
+    /// # Returns
+    ///
+    /// `ActivitySeries` -- The resampled series.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let series = tcx.activities.unwrap().activities[0].series().resample(1.0, 10.0);
+    /// assert_eq!(series.len(), 1232);
+    /// ```
+    pub fn resample(&self, interval_seconds: f64, max_gap_seconds: f64) -> ActivitySeries {
+        let (first, last) = match (self.time.first(), self.time.last()) {
+            (Some(first), Some(last)) if interval_seconds > 0.0 => (*first, *last),
+            _ => return ActivitySeries::default(),
+        };
+        let span = offset_seconds(first, last);
+        let count = (span / interval_seconds).floor() as usize + 1;
+        let targets: Vec<f64> = (0..count).map(|i| i as f64 * interval_seconds).collect();
+        let sources = self.offsets();
+        let resample =
+            |values: &[Option<f64>]| interpolate_at(&sources, values, &targets, max_gap_seconds);
+
+        ActivitySeries {
+            time: targets
+                .iter()
+                .map(|offset| first + Duration::milliseconds((offset * 1000.0).round() as i64))
+                .collect(),
+            latitude: resample(&self.latitude),
+            longitude: resample(&self.longitude),
+            altitude: resample(&self.altitude),
+            distance: resample(&self.distance),
+            heart_rate: resample(&self.heart_rate),
+            cadence: resample(&self.cadence),
+            power: resample(&self.power),
+            speed: resample(&self.speed),
+            temperature: resample(&self.temperature),
+        }
+    }
+
+    /// Fills missing values in place by linear interpolation between the recorded values either side, for gaps of
+    /// up to `max_gap_seconds`. Values before the first or after the last recorded value stay `None`.
+    ///
+    /// # Parameters
+    ///
+    /// `max_gap_seconds: f64` -- The longest gap between recorded values to interpolate across.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn interpolate(&mut self, max_gap_seconds: f64) {
+        let offsets = self.offsets();
+        for values in [
+            &mut self.latitude,
+            &mut self.longitude,
+            &mut self.altitude,
+            &mut self.distance,
+            &mut self.heart_rate,
+            &mut self.cadence,
+            &mut self.power,
+            &mut self.speed,
+            &mut self.temperature,
+        ] {
+            *values = interpolate_at(&offsets, values, &offsets, max_gap_seconds);
+        }
+    }
+
+    /// Smooths the altitude with a centered moving average over the recorded values, to remove GPS and barometer
+    /// noise. Missing values stay missing.
+    ///
+    /// # Parameters
+    ///
+    /// `window: usize` -- Number of samples averaged. `1` leaves the altitude unchanged.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    pub fn smooth_altitude(&mut self, window: usize) {
+        let known: Vec<(usize, f64)> = self
+            .altitude
+            .iter()
+            .enumerate()
+            .filter_map(|(i, altitude)| altitude.map(|altitude| (i, altitude)))
+            .collect();
+        let half = window.max(1) / 2;
+        for (k, (index, _)) in known.iter().enumerate() {
+            let span = &known[k.saturating_sub(half)..(k + half + 1).min(known.len())];
+            let mean = span.iter().map(|(_, altitude)| altitude).sum::<f64>() / span.len() as f64;
+            self.altitude[*index] = Some(mean);
+        }
+    }
+
+    /// Seconds from the first sample to each sample.
+    fn offsets(&self) -> Vec<f64> {
+        match self.time.first() {
+            Some(first) => self
+                .time
+                .iter()
+                .map(|time| offset_seconds(*first, *time))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Activity {
+    /// Flattens the activity's Trackpoints into aligned vectors. See `ActivitySeries`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let series = tcx.activities.unwrap().activities[0].series();
+    /// assert_eq!(series.heart_rate.len(), series.time.len());
+    /// ```
+    pub fn series(&self) -> ActivitySeries {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        ActivitySeries::from_trackpoints(&points)
+    }
+}
+
+fn offset_seconds(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    (to - from).num_milliseconds() as f64 / 1000.0
+}
+
+/// Linearly interpolates `values`, sampled at the ascending offsets `sources`, at the ascending offsets `targets`.
+/// Only recorded values no more than `max_gap` seconds apart are interpolated between.
+fn interpolate_at(
+    sources: &[f64],
+    values: &[Option<f64>],
+    targets: &[f64],
+    max_gap: f64,
+) -> Vec<Option<f64>> {
+    let known: Vec<(f64, f64)> = sources
+        .iter()
+        .zip(values)
+        .filter_map(|(offset, value)| value.map(|value| (*offset, value)))
+        .collect();
+    let mut result = Vec::with_capacity(targets.len());
+    let mut k = 0;
+    for target in targets {
+        while k + 1 < known.len() && known[k + 1].0 <= *target {
+            k += 1;
+        }
+        let value = match (known.get(k), known.get(k + 1)) {
+            (Some((t0, v0)), _) if t0 == target => Some(*v0),
+            (Some((t0, v0)), Some((t1, v1))) if t0 < target && t1 - t0 <= max_gap => {
+                Some(v0 + (v1 - v0) * (target - t0) / (t1 - t0))
+            }
+            _ => None,
+        };
+        result.push(value);
+    }
+    result
+}
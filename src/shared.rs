@@ -0,0 +1,179 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Sharing parsed activities between threads. Every model type owns its data outright, so all of them are
+//! `Send + Sync` (checked below at compile time); `ActivityRef` adds cheap, immutable sharing through an `Arc`, and
+//! `ActivityEditor` copies the activity only once something actually changes it.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use crate::tcx::{Activity, TrainingCenterDatabase};
+
+/// Fails to compile if a model type stops being safe to share between threads.
+#[allow(dead_code)]
+fn assert_send_sync() {
+    fn check<T: Send + Sync>() {}
+    check::<TrainingCenterDatabase>();
+    check::<Activity>();
+    check::<crate::tcx::ActivityLap>();
+    check::<crate::tcx::Trackpoint>();
+    check::<crate::tcx::Course>();
+    check::<crate::tcx::Workout>();
+    check::<crate::tcx::MultiSportSession>();
+    check::<ActivityRef>();
+    check::<ActivityEditor>();
+}
+
+/// A shared, immutable activity. Cloning it only bumps a reference count, so it can be handed to many threads or
+/// kept in a cache cheaply. Dereferences to `Activity`.
+#[derive(Debug, Clone, Default)]
+pub struct ActivityRef(Arc<Activity>);
+
+impl ActivityRef {
+    /// Wraps an activity for sharing.
+    pub fn new(activity: Activity) -> Self {
+        ActivityRef(Arc::new(activity))
+    }
+
+    /// Starts editing the activity. Nothing is copied until the editor is first used to change it, and this
+    /// reference, along with every clone of it, keeps seeing the activity as it was.
+    ///
+    /// # Returns
+    ///
+    /// `ActivityEditor` -- The editing handle. Call `commit` on it to get the edited activity back as a new
+    /// `ActivityRef`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let shared = tcx.into_shared_activities().remove(0);
+    /// let mut editor = shared.edit();
+    /// editor.notes = Some("Easy run".to_string());
+    /// let edited = editor.commit();
+    /// assert!(shared.notes.is_none());
+    /// assert_eq!(edited.notes.as_deref(), Some("Easy run"));
+    /// ```
+    pub fn edit(&self) -> ActivityEditor {
+        ActivityEditor {
+            base: self.0.clone(),
+            edited: None,
+        }
+    }
+
+    /// Whether both references share the same activity, rather than equal copies of it.
+    pub fn ptr_eq(&self, other: &ActivityRef) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Returns the activity, copying it only if it is still shared.
+    pub fn into_activity(self) -> Activity {
+        Arc::try_unwrap(self.0).unwrap_or_else(|shared| (*shared).clone())
+    }
+}
+
+impl Deref for ActivityRef {
+    type Target = Activity;
+
+    fn deref(&self) -> &Activity {
+        &self.0
+    }
+}
+
+impl From<Activity> for ActivityRef {
+    fn from(activity: Activity) -> Self {
+        ActivityRef::new(activity)
+    }
+}
+
+/// A copy-on-write editing handle for a shared activity, made by `ActivityRef::edit`. Reading through it sees the
+/// shared activity until the first mutable access, which takes a private copy to change.
+#[derive(Debug, Clone)]
+pub struct ActivityEditor {
+    /// The activity being edited, as shared.
+    base: Arc<Activity>,
+    /// The private copy, once something has been changed.
+    edited: Option<Activity>,
+}
+
+impl ActivityEditor {
+    /// Whether the activity has been accessed mutably, and so copied.
+    pub fn is_modified(&self) -> bool {
+        self.edited.is_some()
+    }
+
+    /// Finishes editing.
+    ///
+    /// # Returns
+    ///
+    /// `ActivityRef` -- The edited activity, or the original shared one if nothing was changed.
+    pub fn commit(self) -> ActivityRef {
+        match self.edited {
+            Some(activity) => ActivityRef::new(activity),
+            None => ActivityRef(self.base),
+        }
+    }
+}
+
+impl Deref for ActivityEditor {
+    type Target = Activity;
+
+    fn deref(&self) -> &Activity {
+        self.edited.as_ref().unwrap_or(&self.base)
+    }
+}
+
+impl DerefMut for ActivityEditor {
+    fn deref_mut(&mut self) -> &mut Activity {
+        let base = &self.base;
+        self.edited.get_or_insert_with(|| (**base).clone())
+    }
+}
+
+impl TrainingCenterDatabase {
+    /// Moves the activities out of the database for sharing between threads. Multisport sessions are left out.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<ActivityRef>` -- The activities, in order.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activities = tcx.into_shared_activities();
+    /// let cached = activities[0].clone();
+    /// assert!(cached.ptr_eq(&activities[0]));
+    /// std::thread::spawn(move || assert!(cached.trackpoints().count() > 0)).join().unwrap();
+    /// ```
+    pub fn into_shared_activities(self) -> Vec<ActivityRef> {
+        self.activities
+            .map(|activities| {
+                activities
+                    .activities
+                    .into_iter()
+                    .map(ActivityRef::new)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
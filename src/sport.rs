@@ -0,0 +1,233 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Changing the sport of an activity. Cycling cadence (revolutions per minute) lives in the Trackpoint's
+//! `Cadence` and the lap's `Cadence` and `MaxBikeCadence`, while running cadence (strides per minute) lives in the
+//! TPX `RunCadence` and the lap's `AvgRunCadence` and `MaxRunCadence`, so a reclassified activity has its cadence
+//! moved to where the new sport keeps it. Also filtering a document's activities by sport.
+
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::tcx::{Activities, Activity, ActivityLap, Extensions, Ns3Lx, Trackpoint};
+
+/// The sports the TCX schema knows (`Sport_t`). `name` gives the schema's spelling of each.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Sport {
+    /// Running, with cadence in the TPX `RunCadence` and the lap's `AvgRunCadence` and `MaxRunCadence`.
+    Running,
+    /// Cycling, with cadence in the Trackpoint's and lap's `Cadence` and the lap's `MaxBikeCadence`.
+    Biking,
+    /// Anything else, such as swimming or yoga. Also what sports outside the schema are classified as.
+    Other,
+}
+
+impl Sport {
+    /// Every sport, in schema order.
+    pub const ALL: [Sport; 3] = [Sport::Running, Sport::Biking, Sport::Other];
+
+    /// Returns the name as spelled by the schema.
+    pub fn name(self) -> &'static str {
+        match self {
+            Sport::Running => "Running",
+            Sport::Biking => "Biking",
+            Sport::Other => "Other",
+        }
+    }
+
+    /// Classifies a sport name, ignoring case and surrounding whitespace. Names outside the schema are `Other`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::sport::Sport;
+    ///
+    /// assert_eq!(Sport::from_name(" biking"), Sport::Biking);
+    /// assert_eq!(Sport::from_name("Swimming"), Sport::Other);
+    /// ```
+    pub fn from_name(name: &str) -> Sport {
+        Sport::known(name).unwrap_or(Sport::Other)
+    }
+
+    /// Like `from_name`, but `None` for names outside the schema.
+    pub(crate) fn known(name: &str) -> Option<Sport> {
+        let name = name.trim();
+        Sport::ALL
+            .iter()
+            .copied()
+            .find(|sport| sport.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl fmt::Display for Sport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Activities {
+    /// Iterates over the activities of one sport: the single-sport activities first, then the legs of multisport
+    /// sessions, each in file order. Transitions are not activities and are never included.
+    ///
+    /// # Parameters
+    ///
+    /// `sport: Sport` -- The sport to keep. Activities whose sport is outside the schema count as `Other`.
+    ///
+    /// # Returns
+    ///
+    /// `impl Iterator<Item = &Activity>` -- The matching activities.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::sport::Sport;
+    ///
+    /// let tcx = crate::tcx::read_file("tests/20210704_triathlon_multisport.tcx").unwrap();
+    /// let activities = tcx.activities.unwrap();
+    /// assert_eq!(activities.by_sport(Sport::Biking).count(), 1);
+    /// ```
+    pub fn by_sport(&self, sport: Sport) -> impl Iterator<Item = &Activity> {
+        self.activities
+            .iter()
+            .chain(
+                self.multi_sport_sessions
+                    .iter()
+                    .flat_map(|session| session.activities()),
+            )
+            .filter(move |activity| Sport::from_name(&activity.sport) == sport)
+    }
+
+    /// Lists the sports of the activities, including multisport legs, each once and in schema order.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<Sport>` -- The sports present.
+    pub fn sports_present(&self) -> Vec<Sport> {
+        Sport::ALL
+            .iter()
+            .copied()
+            .filter(|sport| self.by_sport(*sport).next().is_some())
+            .collect()
+    }
+}
+
+impl Activity {
+    /// Changes the sport, moving the cadence of every Trackpoint to the field the new sport uses and recomputing
+    /// the laps' cadence summaries to match. A sport matching `Running`, `Biking` or `Other` regardless of case is
+    /// written in the schema's casing. Cadence is left where it is for `Other` and for sports outside the schema.
+    ///
+    /// # Parameters
+    ///
+    /// `sport: &str` -- The new sport.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let activity = &mut tcx.activities.as_mut().unwrap().activities[0];
+    /// activity.set_sport("running");
+    /// assert_eq!(activity.sport, "Running");
+    /// assert!(activity.trackpoints().all(|point| point.cadence.is_none()));
+    /// ```
+    pub fn set_sport(&mut self, sport: &str) {
+        self.sport = Sport::known(sport)
+            .map(|known| known.name().to_string())
+            .unwrap_or_else(|| sport.to_string());
+
+        let running = match self.sport.as_str() {
+            "Running" => true,
+            "Biking" => false,
+            _ => return,
+        };
+        for lap in self.laps.iter_mut() {
+            for point in lap.trackpoints_mut() {
+                if running {
+                    to_run_cadence(point);
+                } else {
+                    to_bike_cadence(point);
+                }
+            }
+            update_lap_cadence(lap, running);
+        }
+    }
+}
+
+fn to_run_cadence(point: &mut Trackpoint) {
+    if let Some(cadence) = point.cadence.take() {
+        let extensions = point.extensions.get_or_insert_with(Extensions::default);
+        let tpx = extensions.tpx.get_or_insert_with(Default::default);
+        tpx.run_cadence = tpx.run_cadence.or(Some(cadence));
+    }
+}
+
+fn to_bike_cadence(point: &mut Trackpoint) {
+    let run_cadence = point
+        .extensions
+        .as_mut()
+        .and_then(|ext| ext.tpx.as_mut())
+        .and_then(|tpx| tpx.run_cadence.take());
+    point.cadence = point.cadence.or(run_cadence);
+}
+
+/// Moves the lap's cadence summaries to the fields for the sport, recomputing them from the Trackpoints when they
+/// have cadence. Step counts only make sense for running, so they are dropped for cycling.
+fn update_lap_cadence(lap: &mut ActivityLap, running: bool) {
+    let summary = lap.summary();
+    let lx = lap.extensions.as_ref().and_then(|ext| ext.lx.clone());
+    let average = summary
+        .average_cadence
+        .map(|cadence| cadence.round() as u8)
+        .or(lap.cadence)
+        .or_else(|| lx.as_ref().and_then(|lx| lx.avg_run_cadence));
+    let max = summary.max_cadence.or_else(|| {
+        lx.as_ref()
+            .and_then(|lx| lx.max_bike_cadence.or(lx.max_run_cadence))
+    });
+
+    if running {
+        lap.cadence = None;
+    } else {
+        lap.cadence = average;
+    }
+    let has_lx_values = max.is_some() || (running && average.is_some());
+    if lx.is_none() && !has_lx_values {
+        return;
+    }
+    let lx = lap
+        .extensions
+        .get_or_insert_with(Extensions::default)
+        .lx
+        .get_or_insert_with(Ns3Lx::default);
+    if running {
+        lx.avg_run_cadence = average;
+        lx.max_run_cadence = max;
+        lx.max_bike_cadence = None;
+    } else {
+        lx.avg_run_cadence = None;
+        lx.max_run_cadence = None;
+        lx.max_bike_cadence = max;
+        lx.steps = None;
+    }
+}
@@ -0,0 +1,535 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Summary statistics computed from the Trackpoints, at the lap, activity and database level. This extends what
+//! `calc_heartrates` does for heart rate to the rest of the commonly reported metrics.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::{
+    haversine_distance, pedalling, seconds_between, smoothed_altitudes, speeds,
+    MOVING_SPEED_THRESHOLD,
+};
+use crate::tcx::{Activity, ActivityLap, Trackpoint, TrainingCenterDatabase};
+
+/// Width, in seconds, of the rolling average used for normalized power.
+const NORMALIZED_POWER_WINDOW_SECONDS: f64 = 30.0;
+
+/// Summary statistics for a set of Trackpoints.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Summary {
+    /// Distance covered, in meters.
+    pub distance_meters: f64,
+
+    /// Time from the first to the last Trackpoint, in seconds.
+    pub elapsed_seconds: f64,
+
+    /// Time spent moving, in seconds. Judged by speed or, in files without any, by cadence and power.
+    pub moving_seconds: f64,
+
+    /// Total elevation gained, in meters, after smoothing the altitude.
+    pub elevation_gain_meters: f64,
+
+    /// Total elevation lost, in meters, after smoothing the altitude.
+    pub elevation_loss_meters: f64,
+
+    /// Average moving speed (distance over moving time), in meters per second.
+    pub average_speed: Option<f64>,
+
+    /// Maximum speed, in meters per second.
+    pub max_speed: Option<f64>,
+
+    /// Average heart rate, in beats per minute.
+    pub average_heart_rate: Option<f64>,
+
+    /// Maximum heart rate, in beats per minute.
+    pub max_heart_rate: Option<f64>,
+
+    /// Average power, in watts.
+    pub average_power: Option<f64>,
+
+    /// Normalized power, in watts: the fourth root of the mean of the fourth power of the 30 second rolling
+    /// average power.
+    pub normalized_power: Option<f64>,
+
+    /// Maximum power, in watts.
+    pub max_power: Option<u16>,
+
+    /// Average cadence, ignoring zeros (coasting or standing), in revolutions or steps per minute.
+    pub average_cadence: Option<f64>,
+
+    /// Maximum cadence, in revolutions or steps per minute.
+    pub max_cadence: Option<u8>,
+
+    /// Minimum air temperature, in degrees Celsius.
+    pub min_temperature: Option<f64>,
+
+    /// Average air temperature, in degrees Celsius.
+    pub average_temperature: Option<f64>,
+
+    /// Maximum air temperature, in degrees Celsius.
+    pub max_temperature: Option<f64>,
+}
+
+/// Computes summary statistics for a sequence of Trackpoints.
+///
+/// # Parameters
+///
+/// `points: &[&Trackpoint]` -- The Trackpoints, in time order.
+///
+/// # Returns
+///
+/// `Summary` -- The statistics. Metrics with no data are `None`.
+pub fn summarize(points: &[&Trackpoint]) -> Summary {
+    let mut summary = Summary::default();
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return summary,
+    };
+    summary.elapsed_seconds = seconds_between(first, last).max(0.0);
+    summary.distance_meters = distance(points);
+
+    let speeds = speeds(points);
+    for (i, speed) in speeds.iter().enumerate() {
+        if let Some(speed) = *speed {
+            summary.max_speed = max_of(summary.max_speed, Some(speed));
+            if i > 0 && speed >= MOVING_SPEED_THRESHOLD {
+                summary.moving_seconds += seconds_between(points[i - 1], points[i]).max(0.0);
+            }
+        }
+    }
+    // Indoor files often have no speed at all, so fall back to cadence and power to tell moving from stopped.
+    if speeds.iter().all(Option::is_none) {
+        for i in 1..points.len() {
+            if pedalling(points[i]) == Some(true) {
+                summary.moving_seconds += seconds_between(points[i - 1], points[i]).max(0.0);
+            }
+        }
+    }
+    if summary.moving_seconds > 0.0 && summary.distance_meters > 0.0 {
+        summary.average_speed = Some(summary.distance_meters / summary.moving_seconds);
+    }
+
+    let with_altitude: Vec<&Trackpoint> = points
+        .iter()
+        .copied()
+        .filter(|point| point.altitude_meters.is_some())
+        .collect();
+    for pair in smoothed_altitudes(&with_altitude).windows(2) {
+        let change = pair[1] - pair[0];
+        if change > 0.0 {
+            summary.elevation_gain_meters += change;
+        } else {
+            summary.elevation_loss_meters -= change;
+        }
+    }
+
+    let heart_rates: Vec<f64> = points
+        .iter()
+        .filter_map(|point| point.heart_rate.as_ref().map(|hr| hr.value))
+        .collect();
+    summary.average_heart_rate = mean(heart_rates.iter().copied());
+    summary.max_heart_rate = heart_rates.iter().copied().reduce(f64::max);
+
+    let watts: Vec<(&Trackpoint, u16)> = points
+        .iter()
+        .filter_map(|point| power(point).map(|watts| (*point, watts)))
+        .collect();
+    summary.average_power = mean(watts.iter().map(|(_, watts)| *watts as f64));
+    summary.max_power = watts.iter().map(|(_, watts)| *watts).max();
+    summary.normalized_power = normalized_power(&rolling_power(points));
+
+    let cadences: Vec<u8> = points.iter().filter_map(|point| cadence(point)).collect();
+    summary.average_cadence = mean(
+        cadences
+            .iter()
+            .filter(|cadence| **cadence > 0)
+            .map(|cadence| *cadence as f64),
+    );
+    summary.max_cadence = cadences.iter().copied().max();
+
+    let temperatures: Vec<f64> = points
+        .iter()
+        .filter_map(|point| point.extensions.as_ref().and_then(|ext| ext.temperature))
+        .collect();
+    summary.min_temperature = temperatures.iter().copied().reduce(f64::min);
+    summary.average_temperature = mean(temperatures.iter().copied());
+    summary.max_temperature = temperatures.iter().copied().reduce(f64::max);
+    summary
+}
+
+/// Distance covered, from the recorded distances when present, otherwise from the positions.
+fn distance(points: &[&Trackpoint]) -> f64 {
+    let distances: Vec<f64> = points
+        .iter()
+        .filter_map(|point| point.distance_meters)
+        .collect();
+    if let (Some(first), Some(last)) = (distances.first(), distances.last()) {
+        return (last - first).max(0.0);
+    }
+    let positions: Vec<_> = points
+        .iter()
+        .filter_map(|point| point.position.as_ref())
+        .collect();
+    positions
+        .windows(2)
+        .map(|pair| haversine_distance(pair[0], pair[1]))
+        .sum()
+}
+
+/// Power from the TPX extension.
+fn power(point: &Trackpoint) -> Option<u16> {
+    point
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.tpx.as_ref())
+        .and_then(|tpx| tpx.watts)
+}
+
+/// Cadence, from the Trackpoint or, for running, from the TPX extension.
+fn cadence(point: &Trackpoint) -> Option<u8> {
+    point.cadence.or_else(|| {
+        point
+            .extensions
+            .as_ref()
+            .and_then(|ext| ext.tpx.as_ref())
+            .and_then(|tpx| tpx.run_cadence)
+    })
+}
+
+fn mean<I: Iterator<Item = f64>>(values: I) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+    if count > 0 {
+        Some(sum / count as f64)
+    } else {
+        None
+    }
+}
+
+/// The rolling average power, over the normalized power window, at each Trackpoint with power at least one window
+/// after the first.
+pub(crate) fn rolling_power(points: &[&Trackpoint]) -> Vec<(DateTime<Utc>, f64)> {
+    let samples: Vec<(&Trackpoint, f64)> = points
+        .iter()
+        .filter_map(|point| power(point).map(|watts| (*point, watts as f64)))
+        .collect();
+    rolling_average(&samples)
+}
+
+/// The rolling average of the samples over the normalized power window, at each sample at least one window after
+/// the first.
+pub(crate) fn rolling_average(samples: &[(&Trackpoint, f64)]) -> Vec<(DateTime<Utc>, f64)> {
+    let first = match samples.first() {
+        Some(first) => first.0,
+        None => return Vec::new(),
+    };
+    let mut start = 0;
+    let mut window_sum = 0.0;
+    let mut rolling = Vec::new();
+    for (i, (point, value)) in samples.iter().enumerate() {
+        window_sum += value;
+        while seconds_between(samples[start].0, point) >= NORMALIZED_POWER_WINDOW_SECONDS {
+            window_sum -= samples[start].1;
+            start += 1;
+        }
+        if seconds_between(first, point) >= NORMALIZED_POWER_WINDOW_SECONDS {
+            rolling.push((point.time, window_sum / (i + 1 - start) as f64));
+        }
+    }
+    rolling
+}
+
+/// The exponentially weighted average power, with the given time constant in seconds, at each Trackpoint with power
+/// at least one time constant after the first. The average starts at the first sample and each later sample is
+/// weighted by the time since the one before it, so gaps in the recording are bridged rather than averaged over.
+pub(crate) fn exponential_power(
+    points: &[&Trackpoint],
+    time_constant_seconds: f64,
+) -> Vec<(DateTime<Utc>, f64)> {
+    let mut samples = points
+        .iter()
+        .filter_map(|point| power(point).map(|watts| (*point, watts as f64)));
+    let (first, mut average) = match samples.next() {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+    let mut previous = first;
+    let mut smoothed = Vec::new();
+    for (point, watts) in samples {
+        let elapsed = seconds_between(previous, point);
+        if elapsed > 0.0 {
+            average += (watts - average) * (1.0 - (-elapsed / time_constant_seconds).exp());
+        }
+        previous = point;
+        if seconds_between(first, point) >= time_constant_seconds {
+            smoothed.push((point.time, average));
+        }
+    }
+    smoothed
+}
+
+/// Normalized power from the rolling averages made by `rolling_power` (or any other smoothed power, such as from
+/// `exponential_power`), or `None` if there are none because the power spans less than one window.
+pub(crate) fn normalized_power(rolling: &[(DateTime<Utc>, f64)]) -> Option<f64> {
+    if rolling.is_empty() {
+        return None;
+    }
+    let fourth_powers: f64 = rolling.iter().map(|(_, watts)| watts.powi(4)).sum();
+    Some((fourth_powers / rolling.len() as f64).powf(0.25))
+}
+
+/// Combines summaries of separate recordings. Totals are added; averages are weighted by elapsed time.
+fn combine(summaries: &[Summary]) -> Summary {
+    let weighted = |value: fn(&Summary) -> Option<f64>, exponent: i32| {
+        let mut total = 0.0;
+        let mut weight = 0.0;
+        for summary in summaries {
+            if let Some(value) = value(summary) {
+                total += value.powi(exponent) * summary.elapsed_seconds;
+                weight += summary.elapsed_seconds;
+            }
+        }
+        if weight > 0.0 {
+            Some((total / weight).powf(1.0 / exponent as f64))
+        } else {
+            None
+        }
+    };
+
+    let mut combined = Summary {
+        average_heart_rate: weighted(|summary| summary.average_heart_rate, 1),
+        average_power: weighted(|summary| summary.average_power, 1),
+        normalized_power: weighted(|summary| summary.normalized_power, 4),
+        average_cadence: weighted(|summary| summary.average_cadence, 1),
+        average_temperature: weighted(|summary| summary.average_temperature, 1),
+        ..Default::default()
+    };
+    for summary in summaries {
+        combined.distance_meters += summary.distance_meters;
+        combined.elapsed_seconds += summary.elapsed_seconds;
+        combined.moving_seconds += summary.moving_seconds;
+        combined.elevation_gain_meters += summary.elevation_gain_meters;
+        combined.elevation_loss_meters += summary.elevation_loss_meters;
+        combined.max_speed = max_of(combined.max_speed, summary.max_speed);
+        combined.max_heart_rate = max_of(combined.max_heart_rate, summary.max_heart_rate);
+        combined.max_power = combined.max_power.max(summary.max_power);
+        combined.max_cadence = combined.max_cadence.max(summary.max_cadence);
+        combined.min_temperature = min_of(combined.min_temperature, summary.min_temperature);
+        combined.max_temperature = max_of(combined.max_temperature, summary.max_temperature);
+    }
+    if combined.moving_seconds > 0.0 {
+        combined.average_speed = Some(combined.distance_meters / combined.moving_seconds);
+    }
+    combined
+}
+
+fn max_of(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        _ => a.or(b),
+    }
+}
+
+fn min_of(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        _ => a.or(b),
+    }
+}
+
+/// Distance, time and elevation change over one part of an activity, such as a lap or a kilometer. The change from
+/// one Trackpoint to the next counts towards the part the later Trackpoint is in, so the parts add up to the whole
+/// activity.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Split {
+    /// When the part starts: the time of the last Trackpoint before it, or of its first for the first part.
+    pub start_time: DateTime<Utc>,
+
+    /// Distance covered, in meters.
+    pub distance_meters: f64,
+
+    /// Time taken, in seconds.
+    pub elapsed_seconds: f64,
+
+    /// Elevation gained, in meters, after smoothing the altitude over the whole activity.
+    pub elevation_gain_meters: f64,
+
+    /// Elevation lost, in meters, after smoothing the altitude over the whole activity.
+    pub elevation_loss_meters: f64,
+}
+
+/// Distance from one Trackpoint to the next, from the recorded distances when both have one, otherwise from their
+/// positions.
+pub(crate) fn step_distance(previous: &Trackpoint, point: &Trackpoint) -> f64 {
+    match (previous.distance_meters, point.distance_meters) {
+        (Some(from), Some(to)) => (to - from).max(0.0),
+        _ => match (&previous.position, &point.position) {
+            (Some(from), Some(to)) => haversine_distance(from, to),
+            _ => 0.0,
+        },
+    }
+}
+
+/// Divides the Trackpoints into consecutive parts by the key of each, starting a new part whenever the key changes.
+fn splits(points: &[&Trackpoint], keys: &[usize]) -> Vec<Split> {
+    let mut result: Vec<Split> = Vec::new();
+    let mut current_key = None;
+    let mut altitudes = Vec::new();
+    for (i, point) in points.iter().enumerate() {
+        if current_key != Some(keys[i]) {
+            current_key = Some(keys[i]);
+            result.push(Split {
+                start_time: points[i.saturating_sub(1)].time,
+                ..Default::default()
+            });
+        }
+        if point.altitude_meters.is_some() {
+            altitudes.push((*point, result.len() - 1));
+        }
+        let split = result.last_mut().unwrap();
+        if i > 0 {
+            let previous = points[i - 1];
+            split.elapsed_seconds += seconds_between(previous, point).max(0.0);
+            split.distance_meters += step_distance(previous, point);
+        }
+    }
+
+    let with_altitude: Vec<&Trackpoint> = altitudes.iter().map(|(point, _)| *point).collect();
+    let smoothed = smoothed_altitudes(&with_altitude);
+    for (i, pair) in smoothed.windows(2).enumerate() {
+        let split = &mut result[altitudes[i + 1].1];
+        let change = pair[1] - pair[0];
+        if change > 0.0 {
+            split.elevation_gain_meters += change;
+        } else {
+            split.elevation_loss_meters -= change;
+        }
+    }
+    result
+}
+
+impl ActivityLap {
+    /// Computes summary statistics from the lap's Trackpoints. See `summarize`.
+    pub fn summary(&self) -> Summary {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        summarize(&points)
+    }
+}
+
+impl Activity {
+    /// Computes summary statistics from the activity's Trackpoints. See `summarize`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let summary = tcx.activities.unwrap().activities[0].summary();
+    /// assert_eq!(summary.max_power, Some(462));
+    /// assert!(summary.normalized_power.unwrap() >= summary.average_power.unwrap());
+    /// ```
+    pub fn summary(&self) -> Summary {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        summarize(&points)
+    }
+
+    /// Computes the distance, time and ascent and descent of each lap. Unlike `ActivityLap::summary`, the altitude is
+    /// smoothed over the whole activity and the step from one lap into the next is counted, so the laps add up to the
+    /// activity's totals, as hill-repeat analysis needs. Laps without Trackpoints are left out.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<Split>` -- One per lap with Trackpoints, in order.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// let gain: f64 = activity.lap_splits().iter().map(|split| split.elevation_gain_meters).sum();
+    /// assert!((gain - activity.summary().elevation_gain_meters).abs() < 1e-6);
+    /// ```
+    pub fn lap_splits(&self) -> Vec<Split> {
+        let (points, keys): (Vec<&Trackpoint>, Vec<usize>) = self
+            .laps
+            .iter()
+            .enumerate()
+            .flat_map(|(i, lap)| lap.trackpoints().map(move |point| (point, i)))
+            .unzip();
+        splits(&points, &keys)
+    }
+
+    /// Computes the distance, time and ascent and descent of each stretch of the given distance, such as every
+    /// kilometer or mile. The last split is usually shorter.
+    ///
+    /// # Parameters
+    ///
+    /// `split_meters: f64` -- The length of each split, in meters.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<Split>` -- The splits, in order. Empty if `split_meters` is not positive.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let splits = tcx.activities.unwrap().activities[0].distance_splits(1000.0);
+    /// assert!(splits.iter().rev().skip(1).all(|split| (split.distance_meters - 1000.0).abs() < 20.0));
+    /// ```
+    pub fn distance_splits(&self, split_meters: f64) -> Vec<Split> {
+        if split_meters <= 0.0 {
+            return Vec::new();
+        }
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        let mut keys = Vec::with_capacity(points.len());
+        let mut total = 0.0;
+        for (i, point) in points.iter().enumerate() {
+            if i > 0 {
+                total += step_distance(points[i - 1], point);
+            }
+            keys.push((total / split_meters) as usize);
+        }
+        splits(&points, &keys)
+    }
+}
+
+impl TrainingCenterDatabase {
+    /// Computes summary statistics over every activity in the database, including each leg of a multisport session.
+    /// Distances, times and elevation are added up; averages are weighted by each activity's elapsed time, so gaps
+    /// between activities, and the transitions between legs, are not counted.
+    pub fn summary(&self) -> Summary {
+        let summaries: Vec<Summary> = self
+            .activities
+            .iter()
+            .flat_map(|activities| {
+                activities.activities.iter().chain(
+                    activities
+                        .multi_sport_sessions
+                        .iter()
+                        .flat_map(|session| session.activities()),
+                )
+            })
+            .map(Activity::summary)
+            .collect();
+        combine(&summaries)
+    }
+}
@@ -0,0 +1,187 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Stops: where and for how long the athlete stood still, such as at coffee stops and traffic lights, and, for
+//! indoor files without positions, when they stopped pedalling.
+
+use chrono::{DateTime, Duration, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::{haversine_distance, pedalling, seconds_between, speeds, MOVING_SPEED_THRESHOLD};
+use crate::tcx::{Activity, Position, Trackpoint};
+
+/// How far, in meters, GPS drift may wander from where the athlete stopped before they are considered to have
+/// moved on.
+const STOP_RADIUS_METERS: f64 = 25.0;
+
+/// A place where the athlete stopped.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Stop {
+    /// Time of the first Trackpoint of the stop.
+    pub start_time: DateTime<Utc>,
+
+    /// How long the stop lasted, in seconds.
+    pub duration_seconds: f64,
+
+    /// The average of the positions recorded during the stop.
+    pub position: Position,
+}
+
+/// A period in which the athlete stopped, inferred from cadence and power.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pause {
+    /// Time of the last Trackpoint before the pause, when the athlete was still moving.
+    pub start_time: DateTime<Utc>,
+
+    /// Time of the last Trackpoint of the pause.
+    pub end_time: DateTime<Utc>,
+
+    /// How long the pause lasted, in seconds.
+    pub duration_seconds: f64,
+}
+
+impl Activity {
+    /// Finds the places where the athlete stopped for at least `min_duration`. A stop begins where the speed drops
+    /// below walking pace and lasts for as long as the following positions stay within a small radius,
+    /// so GPS drift while standing still, and pauses where the device recorded nothing, are both counted.
+    ///
+    /// # Parameters
+    ///
+    /// `min_duration: Duration` -- The shortest stop to report.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<Stop>` -- The stops, in the order they occurred.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let stops = tcx.activities.unwrap().activities[0].significant_stops(chrono::Duration::minutes(1));
+    /// assert!(stops.iter().all(|stop| stop.duration_seconds >= 60.0));
+    /// ```
+    pub fn significant_stops(&self, min_duration: Duration) -> Vec<Stop> {
+        let min_seconds = min_duration.num_milliseconds() as f64 / 1000.0;
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        let speeds = speeds(&points);
+        let positioned: Vec<(&Trackpoint, &Position, Option<f64>)> = points
+            .iter()
+            .zip(speeds)
+            .filter_map(|(point, speed)| point.position.as_ref().map(|pos| (*point, pos, speed)))
+            .collect();
+
+        let mut stops = Vec::new();
+        let mut start = 0;
+        while start < positioned.len() {
+            // The speed is measured over the interval leading up to a Trackpoint, so a slow next Trackpoint
+            // means the stop begins here.
+            let (first, anchor, _) = positioned[start];
+            let is_slow = |index: usize| matches!(positioned.get(index), Some((_, _, Some(speed))) if *speed < MOVING_SPEED_THRESHOLD);
+            if !is_slow(start) && !is_slow(start + 1) {
+                start += 1;
+                continue;
+            }
+
+            let mut end = start;
+            while end + 1 < positioned.len()
+                && haversine_distance(anchor, positioned[end + 1].1) <= STOP_RADIUS_METERS
+            {
+                end += 1;
+            }
+            // Moving off still leaves a few Trackpoints inside the radius; they are not part of the stop.
+            while end > start && !is_slow(end) {
+                end -= 1;
+            }
+
+            let duration = seconds_between(first, positioned[end].0);
+            if duration < min_seconds {
+                start += 1;
+                continue;
+            }
+            let cluster = &positioned[start..=end];
+            let count = cluster.len() as f64;
+            stops.push(Stop {
+                start_time: first.time,
+                duration_seconds: duration,
+                position: Position {
+                    latitude: cluster.iter().map(|(_, pos, _)| pos.latitude).sum::<f64>() / count,
+                    longitude: cluster.iter().map(|(_, pos, _)| pos.longitude).sum::<f64>() / count,
+                },
+            });
+            start = end + 1;
+        }
+        stops
+    }
+
+    /// Finds the periods in which the athlete stopped, for indoor files where there is no position or speed to go
+    /// by: runs of Trackpoints whose cadence and power are zero. Trackpoints with neither do not end a pause, so
+    /// sensor dropouts while stopped are bridged. The same rule gives `summary` its moving time for files without
+    /// speed.
+    ///
+    /// # Parameters
+    ///
+    /// `min_duration: Duration` -- The shortest pause to report.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<Pause>` -- The pauses, in the order they occurred.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// let pauses = activity.reconstruct_pauses_from_cadence(chrono::Duration::seconds(5));
+    /// assert!(pauses.iter().all(|pause| pause.duration_seconds >= 5.0));
+    /// ```
+    pub fn reconstruct_pauses_from_cadence(&self, min_duration: Duration) -> Vec<Pause> {
+        let min_seconds = min_duration.num_milliseconds() as f64 / 1000.0;
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+
+        let mut pauses = Vec::new();
+        let mut i = 1;
+        while i < points.len() {
+            if pedalling(points[i]) != Some(false) {
+                i += 1;
+                continue;
+            }
+            // Each Trackpoint stands for the interval leading up to it, so the pause starts at the one before.
+            let mut end = i;
+            let mut last_stopped = i;
+            while end + 1 < points.len() && pedalling(points[end + 1]) != Some(true) {
+                end += 1;
+                if pedalling(points[end]) == Some(false) {
+                    last_stopped = end;
+                }
+            }
+            let duration = seconds_between(points[i - 1], points[last_stopped]);
+            if duration >= min_seconds {
+                pauses.push(Pause {
+                    start_time: points[i - 1].time,
+                    end_time: points[last_stopped].time,
+                    duration_seconds: duration,
+                });
+            }
+            i = end + 1;
+        }
+        pauses
+    }
+}
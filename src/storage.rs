@@ -0,0 +1,212 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Where TCX data is read from and written to. `TcxSource` and `TcxSink` abstract over files, memory and anything
+//! else an embedder can supply (a browser download in wasm, a content URI on mobile, a network stream), so that one
+//! pair of entry points, `read_from` and `TrainingCenterDatabase::write_to`, serves them all.
+
+use std::error::Error;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::tcx::{
+    read_with_options, JsonOptions, ReadOptions, TrainingCenterDatabase, WriteOptions,
+};
+
+/// Somewhere TCX data can be read from.
+pub trait TcxSource {
+    /// Opens the data for reading, from the start.
+    fn open(&mut self) -> std::io::Result<Box<dyn Read + '_>>;
+}
+
+/// Somewhere TCX (or JSON) data can be written to.
+pub trait TcxSink {
+    /// Opens the destination for writing, replacing anything already there.
+    fn create(&mut self) -> std::io::Result<Box<dyn Write + '_>>;
+}
+
+impl TcxSource for PathBuf {
+    fn open(&mut self) -> std::io::Result<Box<dyn Read + '_>> {
+        open_file(self)
+    }
+}
+
+impl TcxSource for &Path {
+    fn open(&mut self) -> std::io::Result<Box<dyn Read + '_>> {
+        open_file(self)
+    }
+}
+
+impl TcxSource for &[u8] {
+    fn open(&mut self) -> std::io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(*self))
+    }
+}
+
+impl TcxSource for Vec<u8> {
+    fn open(&mut self) -> std::io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(self.as_slice()))
+    }
+}
+
+impl TcxSink for PathBuf {
+    fn create(&mut self) -> std::io::Result<Box<dyn Write + '_>> {
+        create_file(self)
+    }
+}
+
+impl TcxSink for &Path {
+    fn create(&mut self) -> std::io::Result<Box<dyn Write + '_>> {
+        create_file(self)
+    }
+}
+
+impl TcxSink for Vec<u8> {
+    fn create(&mut self) -> std::io::Result<Box<dyn Write + '_>> {
+        self.clear();
+        Ok(Box::new(self))
+    }
+}
+
+/// A source or sink made from any reader or writer, such as a network stream. As a source it can only be read once,
+/// since a stream cannot be rewound; as a sink it writes on from wherever the writer is.
+#[derive(Debug)]
+pub struct Stream<T>(pub T);
+
+impl<R: Read> TcxSource for Stream<R> {
+    fn open(&mut self) -> std::io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(&mut self.0))
+    }
+}
+
+impl<W: Write> TcxSink for Stream<W> {
+    fn create(&mut self) -> std::io::Result<Box<dyn Write + '_>> {
+        Ok(Box::new(&mut self.0))
+    }
+}
+
+/// Reads everything from a source.
+pub(crate) fn read_all<S: TcxSource + ?Sized>(source: &mut S) -> std::io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    source.open()?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Writes to a sink with the function given and flushes it, as the exporters do.
+pub(crate) fn write_with<S, E, F>(sink: &mut S, write: F) -> Result<(), E>
+where
+    S: TcxSink + ?Sized,
+    E: From<std::io::Error>,
+    F: FnOnce(&mut dyn Write) -> Result<(), E>,
+{
+    let mut writer = sink.create()?;
+    write(&mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Opens a file for reading, as the `Path` sources do.
+fn open_file(path: &Path) -> std::io::Result<Box<dyn Read + 'static>> {
+    Ok(Box::new(std::fs::File::open(path)?))
+}
+
+/// Creates a buffered file for writing, as the `Path` sinks do. The caller flushes it.
+fn create_file(path: &Path) -> std::io::Result<Box<dyn Write + 'static>> {
+    Ok(Box::new(std::io::BufWriter::new(std::fs::File::create(
+        path,
+    )?)))
+}
+
+/// Reads TCX data from any source, as controlled by the options given.
+///
+/// # Parameters
+///
+/// `source: &mut S` -- Where to read from, such as a `PathBuf`, a byte slice or a `Stream`.
+///
+/// `options: &ReadOptions` -- How to read the data.
+///
+/// # Returns
+///
+/// `Result<TrainingCenterDatabase, crate::tcx::Error>`
+///
+/// # Example
+///
+/// ```rust
+/// let mut path = std::path::PathBuf::from("tests/20210119_run_garmin_fenix6.tcx");
+/// let tcx = crate::tcx::storage::read_from(&mut path, &Default::default()).unwrap();
+///
+/// let mut bytes = Vec::new();
+/// tcx.write_to(&mut bytes, &Default::default()).unwrap();
+/// let again = crate::tcx::storage::read_from(&mut bytes, &Default::default()).unwrap();
+/// assert_eq!(again.activities.unwrap().activities.len(), 1);
+/// ```
+pub fn read_from<S: TcxSource + ?Sized>(
+    source: &mut S,
+    options: &ReadOptions,
+) -> Result<TrainingCenterDatabase, crate::error::Error> {
+    let mut reader = BufReader::new(source.open()?);
+    read_with_options(&mut reader, options)
+}
+
+impl TrainingCenterDatabase {
+    /// Writes the database as TCX XML to any sink. See `to_writer_with_options`.
+    ///
+    /// # Parameters
+    ///
+    /// `sink: &mut S` -- Where to write, such as a `PathBuf`, a `Vec<u8>` or a `Stream`.
+    ///
+    /// `options: &WriteOptions` -- Controls how the XML is written.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), std::io::Error>` -- Nothing if OK, Error if not.
+    pub fn write_to<S: TcxSink + ?Sized>(
+        &self,
+        sink: &mut S,
+        options: &WriteOptions,
+    ) -> std::io::Result<()> {
+        let mut writer = sink.create()?;
+        self.to_writer_with_options(&mut writer, options)?;
+        writer.flush()
+    }
+
+    /// Writes the database as JSON to any sink. See `to_json_writer`.
+    ///
+    /// # Parameters
+    ///
+    /// `sink: &mut S` -- Where to write.
+    ///
+    /// `options: &JsonOptions` -- Controls how values such as timestamps and numbers are written.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn write_json_to<S: TcxSink + ?Sized>(
+        &self,
+        sink: &mut S,
+        options: &JsonOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut writer = sink.create()?;
+        self.to_json_writer(&mut writer, options)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,352 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Pull-based reading of Trackpoints, for files too large to be loaded into memory as a whole.
+
+use serde_derive::{Deserialize, Serialize};
+use std::io::{BufReader, Read};
+use xml::common::Position;
+use xml::escape::{escape_str_attribute, escape_str_pcdata};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::error::Error;
+use crate::tcx::Trackpoint;
+
+/// Iterates over the Trackpoints of every activity in a TCX document, parsing them one at a time as they are read.
+/// Only the Trackpoint currently being parsed is held in memory. Each item is
+/// `(activity_index, lap_index, Trackpoint)`, with indices counted from zero in document order. Trackpoints belonging
+/// to courses are skipped. Iteration stops after the first error. A long read can be checkpointed with `position`
+/// and picked up again later with `resume`.
+pub struct TrackpointStream<R: Read> {
+    events: EventReader<BufReader<R>>,
+    in_activities: bool,
+    activity_index: Option<usize>,
+    lap_index: Option<usize>,
+    /// The number of Trackpoints seen so far in the current lap.
+    point_index: usize,
+    /// Trackpoints before this position are skipped without being parsed.
+    resume_at: StreamPosition,
+    done: bool,
+    /// Reused between Trackpoints to avoid an allocation per point.
+    fragment: String,
+    /// The elements open within `Activities`, outermost first, not counting Trackpoints.
+    path: Vec<String>,
+    /// The summary of each activity seen so far, in document order.
+    summaries: Vec<ActivitySummary>,
+}
+
+/// Where a `TrackpointStream` has got to: the indices, counted from zero in document order, of the Trackpoint after
+/// the last one it returned. That index may be one past the end of a lap, in which case the stream carries on with
+/// the next lap. Serializable, so that a checkpoint can be saved and the stream resumed in another process.
+#[derive(
+    Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct StreamPosition {
+    /// Index of the activity within `Activities`.
+    pub activity_index: usize,
+
+    /// Index of the lap within the activity.
+    pub lap_index: usize,
+
+    /// Index of the Trackpoint within the lap, counting across all of the lap's tracks.
+    pub point_index: usize,
+}
+
+/// The identity and lap totals of an activity, noted while streaming its Trackpoints. Totals that cannot be read
+/// are left out of the sums.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ActivitySummary {
+    pub(crate) id: String,
+    pub(crate) sport: String,
+    pub(crate) total_time_seconds: f64,
+    pub(crate) distance_meters: f64,
+    pub(crate) calories: u32,
+}
+
+impl<R: Read> TrackpointStream<R> {
+    /// Creates a stream over the TCX document provided by `reader`.
+    ///
+    /// # Parameters
+    ///
+    /// `reader: R` -- The source of the TCX data. It is buffered internally.
+    ///
+    /// # Returns
+    ///
+    /// `TrackpointStream<R>` -- The stream.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let file = std::fs::File::open("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let mut max_hr = 0.0;
+    /// for item in crate::tcx::TrackpointStream::new(file) {
+    ///     let (_activity, _lap, point) = item.unwrap();
+    ///     if let Some(hr) = point.heart_rate {
+    ///         max_hr = f64::max(max_hr, hr.value);
+    ///     }
+    /// }
+    /// assert!(max_hr > 0.0);
+    /// ```
+    pub fn new(reader: R) -> Self {
+        Self::resume(reader, StreamPosition::default())
+    }
+
+    /// Creates a stream over the TCX document provided by `reader` that starts at a position saved from an earlier
+    /// stream over the same document. The Trackpoints before it are skipped without being parsed.
+    ///
+    /// # Parameters
+    ///
+    /// `reader: R` -- The source of the TCX data, from the start of the document. It is buffered internally.
+    ///
+    /// `position: StreamPosition` -- The position to resume from, as returned by `position`.
+    ///
+    /// # Returns
+    ///
+    /// `TrackpointStream<R>` -- The stream, whose first item is the Trackpoint at `position`, or the next one after
+    /// it if there is no such Trackpoint.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let filename = "tests/20210119_run_garmin_fenix6.tcx";
+    /// let mut stream = crate::tcx::TrackpointStream::new(std::fs::File::open(filename).unwrap());
+    /// stream.by_ref().take(10).for_each(drop);
+    /// let checkpoint = serde_json::to_string(&stream.position()).unwrap();
+    /// let tenth = stream.next().unwrap().unwrap();
+    ///
+    /// let position = serde_json::from_str(&checkpoint).unwrap();
+    /// let mut resumed = crate::tcx::TrackpointStream::resume(std::fs::File::open(filename).unwrap(), position);
+    /// assert_eq!(resumed.next().unwrap().unwrap().2.time, tenth.2.time);
+    /// ```
+    pub fn resume(reader: R, position: StreamPosition) -> Self {
+        TrackpointStream {
+            events: EventReader::new(BufReader::new(reader)),
+            in_activities: false,
+            activity_index: None,
+            lap_index: None,
+            point_index: 0,
+            resume_at: position,
+            done: false,
+            fragment: String::new(),
+            path: Vec::new(),
+            summaries: Vec::new(),
+        }
+    }
+
+    /// The position after the last Trackpoint the stream returned, to be saved as a checkpoint and passed to
+    /// `resume`.
+    ///
+    /// # Returns
+    ///
+    /// `StreamPosition` -- The position.
+    pub fn position(&self) -> StreamPosition {
+        self.current().max(self.resume_at)
+    }
+
+    /// The position of the next Trackpoint in the document.
+    fn current(&self) -> StreamPosition {
+        StreamPosition {
+            activity_index: self.activity_index.unwrap_or_default(),
+            lap_index: self.lap_index.unwrap_or_default(),
+            point_index: self.point_index,
+        }
+    }
+
+    /// The summaries of the activities read so far, indexed like the Trackpoints' `activity_index`.
+    pub(crate) fn summaries(&self) -> &[ActivitySummary] {
+        &self.summaries
+    }
+
+    /// Notes text belonging to the activity's `Id` or to a lap total.
+    fn note_summary_text(&mut self, text: &str) {
+        let summary = match self.summaries.last_mut() {
+            Some(summary) => summary,
+            None => return,
+        };
+        let (parent, element) = match self.path.as_slice() {
+            [.., parent, element] => (parent.as_str(), element.as_str()),
+            _ => return,
+        };
+        match (parent, element) {
+            ("Activity", "Id") => summary.id.push_str(text.trim()),
+            ("Lap", "TotalTimeSeconds") => {
+                summary.total_time_seconds += text.trim().parse::<f64>().unwrap_or_default()
+            }
+            ("Lap", "DistanceMeters") => {
+                summary.distance_meters += text.trim().parse::<f64>().unwrap_or_default()
+            }
+            ("Lap", "Calories") => {
+                summary.calories += text.trim().parse::<u32>().unwrap_or_default()
+            }
+            _ => {}
+        }
+    }
+
+    /// Reads past the rest of the element that has just been opened.
+    fn skip_element(&mut self) -> Result<(), Error> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self
+                .events
+                .next()
+                .map_err(|err| Error::from_deserializer(err.into(), &[]))?
+            {
+                XmlEvent::StartElement { .. } => depth += 1,
+                XmlEvent::EndElement { .. } => depth -= 1,
+                XmlEvent::EndDocument => {
+                    let position = self.events.position();
+                    return Err(Error::Xml {
+                        message: String::from("Unexpected end of document inside a Trackpoint"),
+                        line: position.row + 1,
+                        column: position.column + 1,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the events of the Trackpoint element that has just been opened back into a standalone XML fragment,
+    /// so that it can be handed to the same deserializer used for whole documents. Namespace prefixes are dropped,
+    /// as they are when reading a whole document.
+    fn read_trackpoint(&mut self) -> Result<Trackpoint, Error> {
+        let fragment = &mut self.fragment;
+        fragment.clear();
+        fragment.push_str("<Trackpoint>");
+        let mut depth = 1;
+        while depth > 0 {
+            let event = self
+                .events
+                .next()
+                .map_err(|err| Error::from_deserializer(err.into(), &[]))?;
+            match event {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    fragment.push('<');
+                    fragment.push_str(&name.local_name);
+                    for attribute in attributes {
+                        fragment.push_str(&format!(
+                            " {}=\"{}\"",
+                            attribute.name.local_name,
+                            escape_str_attribute(&attribute.value)
+                        ));
+                    }
+                    fragment.push('>');
+                    depth += 1;
+                }
+                XmlEvent::EndElement { name } => {
+                    fragment.push_str(&format!("</{}>", name.local_name));
+                    depth -= 1;
+                }
+                XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                    fragment.push_str(&escape_str_pcdata(&text));
+                }
+                XmlEvent::EndDocument => {
+                    let position = self.events.position();
+                    return Err(Error::Xml {
+                        message: String::from("Unexpected end of document inside a Trackpoint"),
+                        line: position.row + 1,
+                        column: position.column + 1,
+                    });
+                }
+                _ => {}
+            }
+        }
+        serde_xml_rs::from_reader(fragment.as_bytes())
+            .map_err(|err| Error::from_deserializer(err, fragment.as_bytes()))
+    }
+}
+
+impl<R: Read> Iterator for TrackpointStream<R> {
+    type Item = Result<(usize, usize, Trackpoint), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let event = match self.events.next() {
+                Ok(event) => event,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(Error::from_deserializer(e.into(), &[])));
+                }
+            };
+            match event {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => match name.local_name.as_str() {
+                    "Activities" => self.in_activities = true,
+                    "Activity" if self.in_activities => {
+                        self.activity_index =
+                            Some(self.activity_index.map_or(0, |index| index + 1));
+                        self.lap_index = None;
+                        self.point_index = 0;
+                        self.summaries.push(ActivitySummary {
+                            sport: attributes
+                                .into_iter()
+                                .find(|attribute| attribute.name.local_name == "Sport")
+                                .map(|attribute| attribute.value)
+                                .unwrap_or_default(),
+                            ..ActivitySummary::default()
+                        });
+                        self.path.push(name.local_name);
+                    }
+                    "Lap" if self.in_activities => {
+                        self.lap_index = Some(self.lap_index.map_or(0, |index| index + 1));
+                        self.point_index = 0;
+                        self.path.push(name.local_name);
+                    }
+                    "Trackpoint" if self.in_activities && self.current() < self.resume_at => {
+                        self.point_index += 1;
+                        if let Err(err) = self.skip_element() {
+                            self.done = true;
+                            return Some(Err(err));
+                        }
+                    }
+                    "Trackpoint" if self.in_activities => {
+                        self.point_index += 1;
+                        let result = self.read_trackpoint().map(|point| {
+                            (
+                                self.activity_index.unwrap_or_default(),
+                                self.lap_index.unwrap_or_default(),
+                                point,
+                            )
+                        });
+                        self.done = result.is_err();
+                        return Some(result);
+                    }
+                    _ if self.in_activities => self.path.push(name.local_name),
+                    _ => {}
+                },
+                XmlEvent::EndElement { name } if name.local_name == "Activities" => {
+                    self.in_activities = false;
+                }
+                XmlEvent::EndElement { .. } if self.in_activities => {
+                    self.path.pop();
+                }
+                XmlEvent::Characters(text) if self.in_activities => self.note_summary_text(&text),
+                XmlEvent::EndDocument => self.done = true,
+                _ => {}
+            }
+        }
+        None
+    }
+}
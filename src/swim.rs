@@ -0,0 +1,381 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Segmenting pool swims into lengths. TCX has no swimming sport (swims are `Other`) and no length records, so the
+//! lengths are recovered from what the device left behind: laps that each cover one pool length, as some devices
+//! export them, or otherwise the dips in speed where the swimmer turns at each wall. Stroke counts come from the
+//! stroke rate recorded as cadence, and stroke types from a `SwimStroke` or `StrokeType` lap extension when the
+//! document was read with the quick-xml reader (see `crate::quick`), which keeps such extensions.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::{seconds_between, speeds};
+use crate::tcx::{Activity, ActivityLap, Intensity, Trackpoint};
+
+/// How far, as a fraction of the pool length, a lap's distance may be from it and still be taken as one length.
+const LENGTH_TOLERANCE: f64 = 0.1;
+
+/// Swim strokes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StrokeType {
+    Freestyle,
+    Backstroke,
+    Breaststroke,
+    Butterfly,
+    Drill,
+    Mixed,
+    Unknown,
+}
+
+impl StrokeType {
+    /// Classifies a stroke name such as `Freestyle`, `back` or `IM`, ignoring case. Unrecognized names are
+    /// `Unknown`.
+    pub fn from_name(name: &str) -> StrokeType {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "freestyle" | "free" | "crawl" => StrokeType::Freestyle,
+            "backstroke" | "back" => StrokeType::Backstroke,
+            "breaststroke" | "breast" => StrokeType::Breaststroke,
+            "butterfly" | "fly" => StrokeType::Butterfly,
+            "drill" | "kick" => StrokeType::Drill,
+            "mixed" | "im" | "individual_medley" => StrokeType::Mixed,
+            _ => StrokeType::Unknown,
+        }
+    }
+}
+
+/// Where a length was recovered from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthSource {
+    /// A lap covering one pool length.
+    Lap,
+    /// The dips in speed at the walls.
+    Speed,
+}
+
+/// Controls `Activity::swim_lengths`.
+#[derive(Debug, Clone)]
+pub struct SwimOptions {
+    /// The length of the pool, in meters. Defaults to 25.
+    pub pool_length_meters: f64,
+
+    /// The speed, in meters per second, below which the swimmer is taken to be resting. Defaults to 0.2.
+    pub rest_speed: f64,
+
+    /// The shortest rest, in seconds, reported as a rest interval. Shorter stops are part of a length. Defaults
+    /// to 5.
+    pub min_rest_seconds: f64,
+
+    /// The shortest time, in seconds, one length can take. Defaults to 10.
+    pub min_length_seconds: f64,
+
+    /// How far, as a fraction of the typical speed, the speed has to dip at a wall to mark a turn. Defaults to 0.6.
+    pub turn_speed_fraction: f64,
+}
+
+impl Default for SwimOptions {
+    fn default() -> Self {
+        SwimOptions {
+            pool_length_meters: 25.0,
+            rest_speed: 0.2,
+            min_rest_seconds: 5.0,
+            min_length_seconds: 10.0,
+            turn_speed_fraction: 0.6,
+        }
+    }
+}
+
+/// One pool length.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Length {
+    pub start_time: DateTime<Utc>,
+    pub duration_seconds: f64,
+
+    /// The distance, in meters: the pool length.
+    pub distance_meters: f64,
+
+    /// The pace, in seconds per 100 meters.
+    pub pace_seconds_per_100m: f64,
+
+    /// The number of strokes, from the recorded stroke rate, if there is one.
+    pub strokes: Option<u32>,
+
+    pub stroke_type: StrokeType,
+    pub source: LengthSource,
+}
+
+/// A rest at the wall between lengths.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RestInterval {
+    pub start_time: DateTime<Utc>,
+    pub duration_seconds: f64,
+}
+
+/// The lengths and rests of a pool swim. See `Activity::swim_lengths`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct SwimReport {
+    pub lengths: Vec<Length>,
+    pub rests: Vec<RestInterval>,
+
+    /// The distance swum, in meters: the number of lengths times the pool length.
+    pub distance_meters: f64,
+
+    /// The time spent swimming lengths, in seconds.
+    pub swimming_seconds: f64,
+
+    /// The average pace, in seconds per 100 meters, or `None` without any lengths.
+    pub average_pace_seconds_per_100m: Option<f64>,
+}
+
+impl Activity {
+    /// Segments a pool swim into lengths and rests. When every active lap covers one pool length, the laps are the
+    /// lengths and resting laps are the rests; otherwise the Trackpoint speeds are split into rests, where the
+    /// speed stays below `rest_speed`, and lengths, at the dips in speed where the swimmer turns.
+    ///
+    /// # Parameters
+    ///
+    /// `options: &SwimOptions` -- The pool length and the thresholds for rests and turns.
+    ///
+    /// # Returns
+    ///
+    /// `SwimReport` -- The lengths and rests, in order.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::builder::{ActivityBuilder, LapBuilder};
+    /// use crate::tcx::swim::{LengthSource, SwimOptions};
+    ///
+    /// let start = chrono::Utc::now();
+    /// let length = |seconds: i64| {
+    ///     LapBuilder::new()
+    ///         .start_time(start + chrono::Duration::seconds(seconds))
+    ///         .total_time_seconds(20.0)
+    ///         .distance_meters(25.0)
+    ///         .cadence(30)
+    ///         .build()
+    /// };
+    /// let swim = ActivityBuilder::new("Other").id("swim").lap(length(0)).lap(length(20)).build().unwrap();
+    /// let report = swim.swim_lengths(&SwimOptions::default());
+    /// assert_eq!(report.lengths.len(), 2);
+    /// assert_eq!(report.lengths[0].source, LengthSource::Lap);
+    /// assert_eq!(report.lengths[0].strokes, Some(10));
+    /// assert_eq!(report.average_pace_seconds_per_100m, Some(80.0));
+    /// ```
+    pub fn swim_lengths(&self, options: &SwimOptions) -> SwimReport {
+        let pool = options.pool_length_meters.max(1.0);
+        let (lengths, rests) = if self.laps_are_lengths(pool) {
+            self.lengths_from_laps(pool)
+        } else {
+            self.lengths_from_speed(options, pool)
+        };
+        let swimming_seconds: f64 = lengths.iter().map(|length| length.duration_seconds).sum();
+        let distance_meters = lengths.len() as f64 * pool;
+        SwimReport {
+            average_pace_seconds_per_100m: (!lengths.is_empty())
+                .then(|| swimming_seconds / distance_meters * 100.0),
+            lengths,
+            rests,
+            distance_meters,
+            swimming_seconds,
+        }
+    }
+
+    /// Whether every active lap covers one pool length, and there is at least one.
+    fn laps_are_lengths(&self, pool: f64) -> bool {
+        let mut active = self.laps.iter().filter(|lap| !is_rest(lap)).peekable();
+        active.peek().is_some()
+            && active.all(|lap| (lap.distance_meters - pool).abs() <= pool * LENGTH_TOLERANCE)
+    }
+
+    fn lengths_from_laps(&self, pool: f64) -> (Vec<Length>, Vec<RestInterval>) {
+        let mut lengths = Vec::new();
+        let mut rests = Vec::new();
+        for lap in &self.laps {
+            let start_time = match lap
+                .start_time
+                .or_else(|| lap.trackpoints().next().map(|p| p.time))
+            {
+                Some(time) => time,
+                None => continue,
+            };
+            let duration_seconds = lap.total_time_seconds;
+            if is_rest(lap) {
+                rests.push(RestInterval {
+                    start_time,
+                    duration_seconds,
+                });
+                continue;
+            }
+            let stroke_rate = lap.cadence.map(f64::from).or_else(|| {
+                let points: Vec<&Trackpoint> = lap.trackpoints().collect();
+                average_cadence(&points)
+            });
+            lengths.push(Length {
+                start_time,
+                duration_seconds,
+                distance_meters: pool,
+                pace_seconds_per_100m: duration_seconds / pool * 100.0,
+                strokes: strokes(stroke_rate, duration_seconds),
+                stroke_type: stroke_type(lap),
+                source: LengthSource::Lap,
+            });
+        }
+        (lengths, rests)
+    }
+
+    fn lengths_from_speed(
+        &self,
+        options: &SwimOptions,
+        pool: f64,
+    ) -> (Vec<Length>, Vec<RestInterval>) {
+        let mut lengths = Vec::new();
+        let mut rests = Vec::new();
+        for lap in &self.laps {
+            let points: Vec<&Trackpoint> = lap.trackpoints().collect();
+            let speeds: Vec<f64> = speeds(&points)
+                .into_iter()
+                .map(|s| s.unwrap_or(0.0))
+                .collect();
+            let kind = stroke_type(lap);
+
+            // Split into swimming stretches at the rests.
+            let mut stretches = Vec::new();
+            let mut start = 0;
+            let mut i = 0;
+            while i < points.len() {
+                if speeds[i] >= options.rest_speed {
+                    i += 1;
+                    continue;
+                }
+                let mut end = i;
+                while end + 1 < points.len() && speeds[end + 1] < options.rest_speed {
+                    end += 1;
+                }
+                if seconds_between(points[i], points[end]) >= options.min_rest_seconds {
+                    rests.push(RestInterval {
+                        start_time: points[i].time,
+                        duration_seconds: seconds_between(points[i], points[end]),
+                    });
+                    if i > start {
+                        stretches.push(start..i);
+                    }
+                    start = end + 1;
+                }
+                i = end + 1;
+            }
+            if start < points.len() {
+                stretches.push(start..points.len());
+            }
+
+            // Split each stretch into lengths at the turns.
+            for stretch in stretches {
+                let mut typical: Vec<f64> = speeds[stretch.clone()].to_vec();
+                typical.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                let turn_speed = typical[typical.len() / 2] * options.turn_speed_fraction;
+                let mut bounds = vec![stretch.start];
+                for j in stretch.start + 1..stretch.end.saturating_sub(1) {
+                    let dip = speeds[j] < turn_speed
+                        && speeds[j] <= speeds[j - 1]
+                        && speeds[j] < speeds[j + 1];
+                    let last = bounds[bounds.len() - 1];
+                    if dip && seconds_between(points[last], points[j]) >= options.min_length_seconds
+                    {
+                        bounds.push(j);
+                    }
+                }
+                let last = stretch.end - 1;
+                if seconds_between(points[bounds[bounds.len() - 1]], points[last])
+                    < options.min_length_seconds
+                    && bounds.len() > 1
+                {
+                    bounds.pop();
+                }
+                bounds.push(last);
+                for pair in bounds.windows(2) {
+                    let duration_seconds = seconds_between(points[pair[0]], points[pair[1]]);
+                    if duration_seconds < options.min_length_seconds {
+                        continue;
+                    }
+                    lengths.push(Length {
+                        start_time: points[pair[0]].time,
+                        duration_seconds,
+                        distance_meters: pool,
+                        pace_seconds_per_100m: duration_seconds / pool * 100.0,
+                        strokes: strokes(
+                            average_cadence(&points[pair[0]..pair[1]]),
+                            duration_seconds,
+                        ),
+                        stroke_type: kind,
+                        source: LengthSource::Speed,
+                    });
+                }
+            }
+        }
+        (lengths, rests)
+    }
+}
+
+/// Whether a lap is a rest: marked resting, or covering no distance.
+fn is_rest(lap: &ActivityLap) -> bool {
+    matches!(lap.intensity, Some(Intensity::Resting)) || lap.distance_meters <= 0.0
+}
+
+/// The average stroke rate recorded as Trackpoint cadence.
+fn average_cadence(points: &[&Trackpoint]) -> Option<f64> {
+    let rates: Vec<f64> = points
+        .iter()
+        .filter_map(|point| point.cadence)
+        .map(f64::from)
+        .collect();
+    (!rates.is_empty()).then(|| rates.iter().sum::<f64>() / rates.len() as f64)
+}
+
+/// The number of strokes at a stroke rate, in strokes per minute, over a duration.
+fn strokes(stroke_rate: Option<f64>, duration_seconds: f64) -> Option<u32> {
+    stroke_rate
+        .filter(|rate| *rate > 0.0)
+        .map(|rate| (rate * duration_seconds / 60.0).round() as u32)
+}
+
+/// The stroke type named by a lap's `SwimStroke` or `StrokeType` extension, with or without a namespace prefix.
+fn stroke_type(lap: &ActivityLap) -> StrokeType {
+    let fragments = lap
+        .extensions
+        .iter()
+        .flat_map(|extensions| extensions.unknown.iter());
+    for fragment in fragments {
+        let open_end = match fragment.find('>') {
+            Some(end) => end,
+            None => continue,
+        };
+        let tag = fragment[1..open_end]
+            .split_whitespace()
+            .next()
+            .unwrap_or("");
+        let name = tag.rsplit(':').next().unwrap_or(tag);
+        if name == "SwimStroke" || name == "StrokeType" {
+            let value = fragment[open_end + 1..].split('<').next().unwrap_or("");
+            return StrokeType::from_name(value);
+        }
+    }
+    StrokeType::Unknown
+}
@@ -0,0 +1,310 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Seeded synthetic routes for benchmarks and fuzzing. A route is a sequence of roads joined at intersections, with
+//! gentle bends along each road, right-angle and shallow turns between them, and climbs and descents that the speed
+//! and heart rate respond to. The same options, including the seed, always give the same route, and the generator
+//! carries its own random number generator so that routes do not change with a dependency's version.
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use crate::builder::{ActivityBuilder, LapBuilder, TrackpointBuilder};
+use crate::error::Error;
+use crate::geo::{haversine_distance, EARTH_RADIUS_METERS};
+use crate::sport::Sport;
+use crate::tcx::{Activity, Position, Trackpoint};
+
+/// Whether a route ends where it started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteShape {
+    /// The route ends wherever the roads lead.
+    PointToPoint,
+
+    /// The route is bent back so that it ends at its start, at the same altitude.
+    Loop,
+}
+
+/// Controls `route` and `activity`.
+#[derive(Debug, Clone)]
+pub struct RouteOptions {
+    /// Seeds the random number generator. Defaults to 0.
+    pub seed: u64,
+
+    /// The number of Trackpoints. Defaults to 3600.
+    pub trackpoints: usize,
+
+    /// The time between Trackpoints, in seconds. Defaults to 1.
+    pub interval_seconds: u32,
+
+    /// The time of the first Trackpoint. Defaults to 2021-01-01T08:00:00Z.
+    pub start_time: DateTime<Utc>,
+
+    /// Where the route starts, in degrees. Defaults to 40.0, -105.0.
+    pub start_latitude: f64,
+    pub start_longitude: f64,
+
+    /// The altitude the route starts at, in meters. Defaults to 1600.
+    pub start_altitude_meters: f64,
+
+    /// The speed on flat road, in meters per second. Defaults to 8.
+    pub speed_meters_per_second: f64,
+
+    /// Defaults to `RouteShape::Loop`.
+    pub shape: RouteShape,
+}
+
+impl Default for RouteOptions {
+    fn default() -> Self {
+        RouteOptions {
+            seed: 0,
+            trackpoints: 3600,
+            interval_seconds: 1,
+            start_time: Utc.with_ymd_and_hms(2021, 1, 1, 8, 0, 0).unwrap(),
+            start_latitude: 40.0,
+            start_longitude: -105.0,
+            start_altitude_meters: 1600.0,
+            speed_meters_per_second: 8.0,
+            shape: RouteShape::Loop,
+        }
+    }
+}
+
+/// The SplitMix64 generator: small, fast and the same everywhere.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A value in `[low, high)`.
+    fn range(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// True with the given probability.
+    fn chance(&mut self, probability: f64) -> bool {
+        self.range(0.0, 1.0) < probability
+    }
+}
+
+/// Checks that the options describe a route that can be generated.
+fn check(options: &RouteOptions) -> Result<(), Error> {
+    if !(-90.0..=90.0).contains(&options.start_latitude) {
+        return Err(Error::InvalidValue(format!(
+            "start latitude {} is not between -90 and 90",
+            options.start_latitude
+        )));
+    }
+    if !(-180.0..=180.0).contains(&options.start_longitude) {
+        return Err(Error::InvalidValue(format!(
+            "start longitude {} is not between -180 and 180",
+            options.start_longitude
+        )));
+    }
+    if !options.start_altitude_meters.is_finite() || !options.speed_meters_per_second.is_finite() {
+        return Err(Error::InvalidValue(String::from(
+            "start altitude and speed must be finite",
+        )));
+    }
+    Ok(())
+}
+
+/// Generates a route. Every Trackpoint has a time, position, altitude, cumulative distance, heart rate and TPX
+/// speed. Longitudes wrap across the antimeridian.
+///
+/// # Parameters
+///
+/// `options: &RouteOptions` -- The seed, length, start and shape of the route.
+///
+/// # Returns
+///
+/// `Result<Vec<Trackpoint>, crate::tcx::Error>` -- The Trackpoints, in order. An `InvalidValue` error if the start
+/// is not a valid position, or the route would run over a pole.
+///
+/// # Example
+///
+/// ```rust
+/// use crate::tcx::synthetic::{route, RouteOptions};
+///
+/// let options = RouteOptions { seed: 7, trackpoints: 600, ..Default::default() };
+/// let points = route(&options).unwrap();
+/// assert_eq!(points.len(), 600);
+/// assert_eq!(route(&options).unwrap()[599].position, points[599].position);
+/// assert!(route(&RouteOptions { start_latitude: 89.99, ..Default::default() }).is_err());
+/// ```
+pub fn route(options: &RouteOptions) -> Result<Vec<Trackpoint>, Error> {
+    check(options)?;
+    let mut rng = Rng(options.seed);
+    let interval = options.interval_seconds.max(1) as f64;
+    let flat_speed = options.speed_meters_per_second.max(0.5);
+
+    // Walk the roads in local east/north meters.
+    let mut east = 0.0;
+    let mut north = 0.0;
+    let mut altitude = options.start_altitude_meters;
+    let mut heading = rng.range(0.0, 360.0);
+    let mut bend = 0.0;
+    let mut grade = 0.0;
+    let mut target_grade = 0.0;
+    let mut road_left = 0.0;
+    let mut slow_down = 0.0;
+    let mut heart_rate = 110.0;
+    let mut walk = Vec::with_capacity(options.trackpoints);
+    for _ in 0..options.trackpoints {
+        if road_left <= 0.0 {
+            // An intersection: turn onto the next road, which may climb or descend.
+            let turn = if rng.chance(0.45) {
+                90.0
+            } else {
+                rng.range(10.0, 45.0)
+            };
+            heading += if rng.chance(0.5) { turn } else { -turn };
+            slow_down = (turn / 90.0).min(1.0);
+            road_left = rng.range(200.0, 1500.0);
+            target_grade = if rng.chance(0.6) {
+                rng.range(-0.01, 0.01)
+            } else {
+                rng.range(0.02, 0.08) * if rng.chance(0.5) { 1.0 } else { -1.0 }
+            };
+        }
+        bend = (bend + rng.range(-0.02, 0.02)).clamp(-0.3, 0.3);
+        grade += (target_grade - grade) * 0.05;
+        let speed = flat_speed * (1.0 - 6.0 * grade).clamp(0.4, 1.5) * (1.0 - 0.5 * slow_down);
+        slow_down *= 0.8;
+        let target_heart_rate = (135.0 + 600.0 * grade).clamp(95.0, 185.0);
+        heart_rate += (target_heart_rate - heart_rate) * 0.05;
+        walk.push((east, north, altitude, heart_rate + rng.range(-1.0, 1.0)));
+
+        let step = speed * interval;
+        heading += bend * step / 10.0;
+        east += step * heading.to_radians().sin();
+        north += step * heading.to_radians().cos();
+        altitude += step * grade;
+        road_left -= step;
+    }
+
+    // Bend a loop back to its start by spreading the gap over the route.
+    if options.shape == RouteShape::Loop && walk.len() > 1 {
+        let (end_east, end_north, end_altitude, _) = walk[walk.len() - 1];
+        let last = (walk.len() - 1) as f64;
+        for (index, point) in walk.iter_mut().enumerate() {
+            let progress = index as f64 / last;
+            point.0 -= end_east * progress;
+            point.1 -= end_north * progress;
+            point.2 -= (end_altitude - options.start_altitude_meters) * progress;
+        }
+    }
+
+    let meters_per_degree = EARTH_RADIUS_METERS.to_radians();
+    let longitude_scale = options.start_latitude.to_radians().cos().max(0.01);
+    let mut points: Vec<Trackpoint> = Vec::with_capacity(walk.len());
+    let mut distance = 0.0;
+    let mut previous: Option<Position> = None;
+    for (index, (east, north, altitude, heart_rate)) in walk.into_iter().enumerate() {
+        let latitude = options.start_latitude + north / meters_per_degree;
+        if !(-90.0..=90.0).contains(&latitude) {
+            return Err(Error::InvalidValue(format!(
+                "the route from latitude {} runs over a pole",
+                options.start_latitude
+            )));
+        }
+        let longitude = options.start_longitude + east / (meters_per_degree * longitude_scale);
+        let position = Position {
+            latitude,
+            longitude: (longitude + 180.0).rem_euclid(360.0) - 180.0,
+        };
+        let step = previous
+            .as_ref()
+            .map_or(0.0, |previous| haversine_distance(previous, &position));
+        distance += step;
+        let time = options.start_time + Duration::seconds(index as i64 * interval as i64);
+        points.push(
+            TrackpointBuilder::new(time)
+                .position(position.latitude, position.longitude)
+                .altitude(altitude)
+                .distance(distance)
+                .heart_rate(heart_rate.round())
+                .speed(step / interval)
+                .build(),
+        );
+        previous = Some(position);
+    }
+    Ok(points)
+}
+
+/// Generates a single-lap activity along a route (see `route`), with cadence where the sport keeps it: the
+/// Trackpoint cadence for biking and the TPX run cadence for running.
+///
+/// # Parameters
+///
+/// `sport: Sport` -- The sport of the activity.
+///
+/// `options: &RouteOptions` -- The seed, length, start and shape of the route.
+///
+/// # Returns
+///
+/// `Result<Activity, crate::tcx::Error>` -- The activity, with its lap totals filled in. An `InvalidValue` error if
+/// the options ask for no Trackpoints, or for a route that `route` cannot generate.
+///
+/// # Example
+///
+/// ```rust
+/// use crate::tcx::sport::Sport;
+/// use crate::tcx::synthetic::{activity, RouteOptions};
+///
+/// let ride = activity(Sport::Biking, &RouteOptions { seed: 1, ..Default::default() }).unwrap();
+/// assert!(activity(Sport::Biking, &RouteOptions { trackpoints: 0, ..Default::default() }).is_err());
+/// assert_eq!(ride.laps[0].total_time_seconds, 3599.0);
+/// assert!(ride.laps[0].distance_meters > 10_000.0);
+/// ```
+pub fn activity(sport: Sport, options: &RouteOptions) -> Result<Activity, Error> {
+    if options.trackpoints == 0 {
+        return Err(Error::InvalidValue(String::from(
+            "an activity needs at least one Trackpoint",
+        )));
+    }
+    let mut rng = Rng(options.seed ^ 0xC0FF_EE00_D15E_A5E5);
+    let mut points = route(options)?;
+    for point in points.iter_mut() {
+        let tpx = point
+            .extensions
+            .as_mut()
+            .and_then(|extensions| extensions.tpx.as_mut());
+        match (sport, tpx) {
+            (Sport::Biking, _) => point.cadence = Some(rng.range(80.0, 95.0) as u8),
+            (Sport::Running, Some(tpx)) => tpx.run_cadence = Some(rng.range(84.0, 92.0) as u8),
+            _ => {}
+        }
+    }
+    let mut activity = ActivityBuilder::new(sport.name())
+        .lap(LapBuilder::new().trackpoints(points).build())
+        .build()
+        .map_err(|err| Error::InvalidValue(err.to_string()))?;
+    for lap in activity.laps.iter_mut() {
+        lap.calc_heartrates();
+    }
+    Ok(activity)
+}
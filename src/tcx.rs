@@ -19,10 +19,11 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::error::Error;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Write};
 
 use chrono;
 use serde_json;
@@ -49,7 +50,7 @@ pub enum TriggerMethod {
 }
 
 /// Describes the type of Course Point.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum CoursePointType {
     Generic,
     Summit,
@@ -60,17 +61,23 @@ pub enum CoursePointType {
     Left,
     Right,
     Straight,
+    #[serde(rename = "First Aid", alias = "FirstAid")]
     FirstAid,
+    #[serde(rename = "4th Category", alias = "FourthCategory")]
     FourthCategory,
+    #[serde(rename = "3rd Category", alias = "ThirdCategory")]
     ThirdCategory,
+    #[serde(rename = "2nd Category", alias = "SecondCategory")]
     SecondCategory,
+    #[serde(rename = "1st Category", alias = "FirstCategory")]
     FirstCategory,
+    #[serde(rename = "Hors Category", alias = "HorsCategory")]
     HorsCategory,
     Sprint,
 }
 
 /// The build type for the software that created the TCX file.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum BuildType {
     Internal,
     Alpha,
@@ -85,9 +92,200 @@ pub enum SpeedType {
     Speed,
 }
 
+/// How timestamps are written when exporting to JSON.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum TimestampFormat {
+    /// RFC 3339 strings, such as `2021-01-19T23:30:50Z`. This is the default.
+    #[default]
+    Rfc3339,
+    /// Milliseconds since the Unix epoch.
+    EpochMillis,
+    /// Seconds since the Unix epoch.
+    EpochSeconds,
+}
+
+/// How much of the original XML to keep when reading.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum OriginalRetention {
+    /// Keep nothing. This is the default.
+    #[default]
+    Nothing,
+    /// Keep a SHA-256 hash of the original bytes.
+    Hash,
+    /// Keep the original bytes, along with their hash.
+    Bytes,
+}
+
+/// Which Trackpoints to keep when reading. Those skipped are read past without being deserialized, so they cost
+/// almost no parse time and no memory, which matters for callers that only need the lap summaries of large files.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum TrackpointPolicy {
+    /// Keep every Trackpoint. This is the default.
+    #[default]
+    All,
+    /// Keep no Trackpoints, leaving the tracks empty.
+    None,
+    /// Keep the first Trackpoint of each track and every nth one after it. `EveryNth(0)` and `EveryNth(1)` keep all.
+    EveryNth(usize),
+}
+
+/// An attribute of the root `TrainingCenterDatabase` element written by `to_writer`: a namespace declaration or
+/// the schema location.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum RootAttribute {
+    /// `xsi:schemaLocation`, pointing at the TCX schema.
+    SchemaLocation,
+    /// The declaration of the ActivityExtension v2 namespace, under `WriteOptions::extension_prefix`.
+    ActivityExtension,
+    /// The declaration of the TCX namespace as the default namespace.
+    TrainingCenterDatabase,
+    /// The declaration of the XML Schema instance namespace, under `xsi`.
+    SchemaInstance,
+}
+
+/// The kind of sensor that measured a heart rate.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum HeartRateSource {
+    /// An electrical chest strap, generally the more accurate.
+    ChestStrap,
+    /// An optical (photoplethysmography) sensor, such as on a watch or armband.
+    Optical,
+}
+
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 // Structs
 
+/// Options controlling JSON export.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct JsonOptions {
+    /// How `Time` and `StartTime` values are written.
+    pub timestamps: TimestampFormat,
+
+    /// Rounds floating-point values to at most this many decimal places. Either way they are written in plain
+    /// decimal notation, never as `1.2e3`. Defaults to `None`, keeping every digit.
+    pub decimal_places: Option<usize>,
+}
+
+/// Options controlling how TCX XML is written.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WriteOptions {
+    /// The prefix bound to the ActivityExtension v2 namespace, under which `TPX` and `LX` are written, such as
+    /// `ns3`, `ns2` or `x`. Defaults to `ns3`, and an empty prefix is also taken to mean `ns3`.
+    pub extension_prefix: String,
+
+    /// The order of the attributes on the root element. Any left out are written after these, in the default
+    /// order, since all of them are needed for a valid file. Defaults to `SchemaLocation`, `ActivityExtension`,
+    /// `TrainingCenterDatabase`, `SchemaInstance`.
+    pub root_attributes: Vec<RootAttribute>,
+
+    /// Rounds floating-point values, such as distances, altitudes and coordinates, to at most this many decimal
+    /// places. Either way they are written in plain decimal notation, never as `1.2e3`. Defaults to `None`, keeping
+    /// every digit.
+    pub decimal_places: Option<usize>,
+
+    /// Rounds `LatitudeDegrees` and `LongitudeDegrees` to at most this many decimal places, in place of
+    /// `decimal_places`. Six places are accurate to about ten centimeters. Defaults to `None`, following
+    /// `decimal_places`.
+    pub coordinate_decimal_places: Option<usize>,
+
+    /// Rounds `AltitudeMeters`, `BeginAltitudeMeters` and `EndAltitudeMeters` to at most this many decimal places,
+    /// in place of `decimal_places`. Defaults to `None`, following `decimal_places`.
+    pub altitude_decimal_places: Option<usize>,
+
+    /// Rounds `DistanceMeters`, of laps and Trackpoints, to at most this many decimal places, in place of
+    /// `decimal_places`. Defaults to `None`, following `decimal_places`.
+    pub distance_decimal_places: Option<usize>,
+
+    /// Whether to write optional elements that have nothing in them, such as empty `Notes`, or `Extensions` with no
+    /// known or retained children. Leaving them out keeps the output smaller. Defaults to true.
+    pub emit_empty_elements: bool,
+
+    /// Whether to check the document with `TrainingCenterDatabase::validate` first, and fail with the first
+    /// violation (as an `InvalidData` error wrapping a `crate::validate::Violation`) rather than write a file
+    /// devices reject. Defaults to true.
+    pub validate: bool,
+
+    /// The longest `Notes` allowed when validating. The schema sets no limit, but some devices do. Defaults to
+    /// `None`.
+    pub max_notes_chars: Option<usize>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            extension_prefix: "ns3".to_string(),
+            root_attributes: vec![
+                RootAttribute::SchemaLocation,
+                RootAttribute::ActivityExtension,
+                RootAttribute::TrainingCenterDatabase,
+                RootAttribute::SchemaInstance,
+            ],
+            decimal_places: None,
+            coordinate_decimal_places: None,
+            altitude_decimal_places: None,
+            distance_decimal_places: None,
+            emit_empty_elements: true,
+            validate: true,
+            max_notes_chars: None,
+        }
+    }
+}
+
+/// Options controlling how TCX data is read.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ReadOptions {
+    /// How much of the original XML to keep on the resulting `TrainingCenterDatabase`. See `OriginalXml`.
+    pub original: OriginalRetention,
+
+    /// Whether to repair malformed files rather than fail, as `crate::lenient::read_lossy` does but without
+    /// reporting what was repaired. Defaults to false.
+    pub lenient: bool,
+
+    /// Which Trackpoints to keep, in activities and courses alike. Defaults to `TrackpointPolicy::All`. The original
+    /// XML, if kept, is unaffected.
+    pub trackpoint_policy: TrackpointPolicy,
+}
+
+/// The original XML a `TrainingCenterDatabase` was read from, kept when requested through `ReadOptions`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OriginalXml {
+    /// SHA-256 hash of the original bytes.
+    pub sha256: [u8; 32],
+
+    /// The original bytes, if they were kept.
+    pub bytes: Option<Vec<u8>>,
+}
+
+impl OriginalXml {
+    pub(crate) fn new(bytes: Vec<u8>, retention: OriginalRetention) -> Option<Self> {
+        match retention {
+            OriginalRetention::Nothing => None,
+            OriginalRetention::Hash => Some(OriginalXml {
+                sha256: Sha256::digest(&bytes).into(),
+                bytes: None,
+            }),
+            OriginalRetention::Bytes => Some(OriginalXml {
+                sha256: Sha256::digest(&bytes).into(),
+                bytes: Some(bytes),
+            }),
+        }
+    }
+
+    /// Returns the SHA-256 hash as a lowercase hexadecimal string.
+    pub fn sha256_hex(&self) -> String {
+        self.sha256
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Returns true if `bytes` are identical to the original, by hash.
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        let sha256: [u8; 32] = Sha256::digest(bytes).into();
+        sha256 == self.sha256
+    }
+}
+
 /// Version information for the software that produced the TCX. Note: Does not follow [Semantic Versioning](https://semver.org).
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Version {
@@ -112,14 +310,78 @@ pub struct Version {
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct AbstractSource {}
 
-/// Empty placeholder for course name reference information in the `CourseFolder` struct.
+/// The device that recorded an activity, from `<Creator xsi:type="Device_t">`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Device {
+    /// The device's name, such as `fenix 6 Sapphire`. Serializes to `Name`.
+    #[serde(rename = "Name")]
+    pub name: String,
+
+    /// The device's serial number. Serializes to `UnitId`.
+    #[serde(rename = "UnitId")]
+    pub unit_id: Option<u32>,
+
+    /// The manufacturer's product identifier. Serializes to `ProductID`.
+    #[serde(rename = "ProductID")]
+    pub product_id: Option<u16>,
+
+    /// The device's firmware version. Serializes to `Version`.
+    #[serde(rename = "Version")]
+    pub version: Option<Version>,
+}
+
+/// Build information for the software that produced the TCX.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Build {
+    /// The version of the software. Serializes to `Version`.
+    #[serde(rename = "Version")]
+    pub version: Version,
+
+    /// The type of build. Serializes to `Type`.
+    #[serde(rename = "Type")]
+    pub build_type: Option<BuildType>,
+
+    /// When the software was built. Serializes to `Time`.
+    #[serde(rename = "Time")]
+    pub time: Option<String>,
+
+    /// Who built the software. Serializes to `Builder`.
+    #[serde(rename = "Builder")]
+    pub builder: Option<String>,
+}
+
+/// The application that produced the TCX file, from `<Author xsi:type="Application_t">`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Application {
+    /// The application's name, such as `Connect Api`. Serializes to `Name`.
+    #[serde(rename = "Name")]
+    pub name: String,
+
+    /// The application's build. Serializes to `Build`.
+    #[serde(rename = "Build")]
+    pub build: Build,
+
+    /// The application's language, such as `en`. Serializes to `LangID`.
+    #[serde(rename = "LangID")]
+    pub lang_id: Option<String>,
+
+    /// The manufacturer's part number for the application. Serializes to `PartNumber`.
+    #[serde(rename = "PartNumber")]
+    pub part_number: Option<String>,
+}
+
+/// Refers to a workout or course by name, from within a folder.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
-pub struct NameKeyReference {}
+pub struct NameKeyReference {
+    /// The name of the workout or course. Serializes to `Id`.
+    #[serde(rename = "Id")]
+    pub id: String,
+}
 
-/// Describes courses with extensions.
+/// The course folder tree, found within `Folders`. The courses themselves are in `CourseList`.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Courses {
-    /// A folder of courses.
+    /// The root folder of courses. Serializes to `CourseFolder`.
     #[serde(rename = "CourseFolder")]
     pub folder: Option<CourseFolder>,
 
@@ -131,29 +393,108 @@ pub struct Courses {
 /// Defines a folder for course information.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct CourseFolder {
-    /// Describes a self-contained folder. Serializes to `Folder.
-    #[serde(rename = "Folder")]
-    pub folder: Box<Option<CourseFolder>>,
+    /// The name of the folder. Serializes to `Name`.
+    #[serde(rename = "Name", alias = "@Name", default)]
+    pub name: String,
+
+    /// Sub-folders. Serializes to `Folder`.
+    #[serde(rename = "Folder", default)]
+    pub folders: Vec<CourseFolder>,
+
+    /// References to the courses in this folder. Serializes to `CourseNameRef`.
+    #[serde(rename = "CourseNameRef", default)]
+    pub course_name_refs: Vec<NameKeyReference>,
 
     /// Describes any optional notes attached to the folder. Serializes to `Notes`.
     #[serde(rename = "Notes")]
     pub notes: Option<String>,
 
-    /// Optional name key reference for the course. Serializes to `CourseNameRef`.
-    #[serde(rename = "CourseNameRef")]
-    pub course_name_ref: Option<NameKeyReference>,
-
     /// Any extensional information about the folder. Serializes to `Extensions`.
     #[serde(rename = "Extensions")]
     pub extensions: Option<Extensions>,
 }
 
+/// The courses found in the TCX file.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct CourseList {
+    /// The courses. Serializes to `Course`.
+    #[serde(rename = "Course", default)]
+    pub courses: Vec<Course>,
+
+    /// Additional extensional information about the courses. Serializes to `Extensions`.
+    #[serde(rename = "Extensions")]
+    pub extensions: Option<Extensions>,
+}
+
+impl CourseList {
+    /// Finds a course by name. The schema requires course names to be unique, so at most one should match.
+    ///
+    /// # Parameters
+    ///
+    /// `name: &str` -- The course name. Leading and trailing whitespace is ignored, on both sides.
+    ///
+    /// # Returns
+    ///
+    /// `Option<&Course>` -- The first course with that name, if any.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210601_course_lake_loop.tcx").unwrap();
+    /// let courses = tcx.courses.unwrap();
+    /// assert_eq!(courses.get("Lake Loop").unwrap().course_points.len(), 2);
+    /// assert!(courses.get("River Run").is_none());
+    /// ```
+    pub fn get(&self, name: &str) -> Option<&Course> {
+        let name = name.trim();
+        self.courses
+            .iter()
+            .find(|course| course.name.trim() == name)
+    }
+
+    /// Finds a course by name, for editing. See `get`.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Course> {
+        let name = name.trim();
+        self.courses
+            .iter_mut()
+            .find(|course| course.name.trim() == name)
+    }
+
+    /// Iterates over the course names, in file order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.courses.iter().map(|course| course.name.trim())
+    }
+
+    /// Adds a course, replacing any existing course with the same name so that names stay unique.
+    ///
+    /// # Parameters
+    ///
+    /// `course: Course` -- The course to add.
+    ///
+    /// # Returns
+    ///
+    /// `Option<Course>` -- The course it replaced, if any.
+    pub fn insert(&mut self, course: Course) -> Option<Course> {
+        match self.get_mut(&course.name) {
+            Some(existing) => Some(std::mem::replace(existing, course)),
+            None => {
+                self.courses.push(course);
+                None
+            }
+        }
+    }
+}
+
 /// Describes a course.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Course {
-    /// Contains a lap within a course. Serializes to `CourseLap`.
-    #[serde(rename = "CourseLap")]
-    pub lap: Option<CourseLap>,
+    /// The name of the course, up to 15 characters. Serializes to `Name`.
+    #[serde(rename = "Name", default)]
+    pub name: String,
+
+    /// The laps of the course. Serializes to `Lap`.
+    #[serde(rename = "Lap", alias = "CourseLap", default)]
+    pub laps: Vec<CourseLap>,
 
     /// Contains a list of tracks within the course. Serializes to `Track`.
     #[serde(rename = "Track")]
@@ -163,9 +504,9 @@ pub struct Course {
     #[serde(rename = "Notes")]
     pub notes: Option<String>,
 
-    /// Contains a (way-) point on a course. Serializes to `CoursePoint`.
-    #[serde(rename = "CoursePoint")]
-    pub course_point: Option<CoursePoint>,
+    /// Contains the (way-) points on a course. Serializes to `CoursePoint`.
+    #[serde(rename = "CoursePoint", default)]
+    pub course_points: Vec<CoursePoint>,
 
     /// Identifies the creator for the course. Serializes to `Creator`.
     #[serde(rename = "Creator")]
@@ -176,6 +517,40 @@ pub struct Course {
     pub extensions: Option<Extensions>,
 }
 
+impl Course {
+    /// Creates an empty course with the given name. See `set_name`.
+    pub fn new(name: &str) -> Self {
+        let mut course = Course::default();
+        course.set_name(name);
+        course
+    }
+
+    /// Sets the course name, trimming whitespace and cutting it to the 15 characters the schema allows.
+    ///
+    /// # Parameters
+    ///
+    /// `name: &str` -- The new name.
+    ///
+    /// # Returns
+    ///
+    /// `bool` -- True if the name had to be shortened.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut course = crate::tcx::Course::new("Lake Loop");
+    /// assert_eq!(course.name, "Lake Loop");
+    /// assert!(course.set_name(" Lake Loop the long way round"));
+    /// assert_eq!(course.name, "Lake Loop the l");
+    /// ```
+    pub fn set_name(&mut self, name: &str) -> bool {
+        let name = name.trim();
+        let shortened: String = name.chars().take(crate::validate::MAX_NAME_CHARS).collect();
+        self.name = shortened.trim_end().to_string();
+        self.name.len() < name.len()
+    }
+}
+
 /// Describes a lap within a course.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct CourseLap {
@@ -201,14 +576,26 @@ pub struct CourseLap {
 
     /// Altitude in meteres at the end of the lap. Serializes to `EndAltitudeMeters`
     #[serde(rename = "EndAltitudeMeters")]
-    pub end_altitude_meters: f64,
-
-    /// Average heart rate for the lap in Beats per Minute (BPM). Serializes to `AverageHeartRateBpm`.
-    #[serde(rename = "AverageHeartRateBpm")]
+    pub end_altitude_meters: Option<f64>,
+
+    /// Average heart rate for the lap in Beats per Minute (BPM). Serializes to `AverageHeartRateBpm`, and is also
+    /// read from `AverageHeartRate`.
+    #[serde(
+        rename = "AverageHeartRateBpm",
+        alias = "AverageHeartRate",
+        default,
+        deserialize_with = "deserialize_bpm"
+    )]
     pub average_heart_rate: Option<f64>,
 
-    /// Maximum heart rate for the lap in Beats per Minute (BPM). Serializes to `MaximumHeartRateBpm`
-    #[serde(rename = "MaximumHeartRate")]
+    /// Maximum heart rate for the lap in Beats per Minute (BPM). Serializes to `MaximumHeartRateBpm`, and is also
+    /// read from `MaximumHeartRate`, the name used by earlier versions.
+    #[serde(
+        rename = "MaximumHeartRateBpm",
+        alias = "MaximumHeartRate",
+        default,
+        deserialize_with = "deserialize_bpm"
+    )]
     pub maximum_heart_rate: Option<f64>,
 
     /// Intensity (`Active` or `Resting`) for this lap. Serializes to `Intensity`.
@@ -224,21 +611,71 @@ pub struct CourseLap {
     pub extensions: Option<Extensions>,
 }
 
-/// Identifies a point of interest within a course.
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
-pub struct CoursePointName {
-    pub token: u8,
+impl CourseLap {
+    /// Fills the lap's totals from the track it covers, for courses built programmatically: `TotalTimeSeconds` from
+    /// the first and last Trackpoint times, `DistanceMeters` from the recorded distances or else the positions, the
+    /// begin and end positions and altitudes from the first and last Trackpoints that have them, and the average
+    /// speed (LX `AvgSpeed`) at which devices pace the course. Nothing changes if the track is empty.
+    ///
+    /// # Parameters
+    ///
+    /// `track: &Track` -- The Trackpoints of the lap, in time order.
+    ///
+    /// # Returns
+    ///
+    /// Nothing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// let track = &activity.laps[0].tracks[0];
+    /// let mut lap = crate::tcx::CourseLap::default();
+    /// lap.recalc_from_track(track);
+    /// assert_eq!(lap.begin_position, track.trackpoints[0].position);
+    /// assert!(lap.total_time_seconds > 0.0);
+    /// assert!(lap.extensions.unwrap().lx.unwrap().avg_speed.is_some());
+    /// ```
+    pub fn recalc_from_track(&mut self, track: &Track) {
+        let points = &track.trackpoints;
+        let (first, last) = match (points.first(), points.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return,
+        };
+        self.total_time_seconds = crate::geo::seconds_between(first, last).max(0.0);
+        self.distance_meters = points
+            .windows(2)
+            .map(|pair| crate::stats::step_distance(&pair[0], &pair[1]))
+            .sum();
+
+        let begin = points.iter().find(|point| point.position.is_some());
+        let end = points.iter().rev().find(|point| point.position.is_some());
+        self.begin_position = begin.and_then(|point| point.position.clone());
+        self.end_position = end.and_then(|point| point.position.clone());
+        self.begin_altitude_meters = points.iter().find_map(|point| point.altitude_meters);
+        self.end_altitude_meters = points.iter().rev().find_map(|point| point.altitude_meters);
+
+        if self.total_time_seconds > 0.0 {
+            let speed = self.distance_meters / self.total_time_seconds;
+            self.extensions
+                .get_or_insert_with(Extensions::default)
+                .lx
+                .get_or_insert_with(Ns3Lx::default)
+                .avg_speed = Some(speed);
+        }
+    }
 }
 
 /// Describes a point of interest within a course.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CoursePoint {
-    /// The name of the course point. Serializes to `Name`.
+    /// The name of the course point, up to 10 characters. Serializes to `Name`.
     #[serde(rename = "Name")]
-    pub name: Option<CoursePointName>,
+    pub name: Option<String>,
 
     /// The time the course point was recorded. Serializes to `Time`.
-    #[serde(rename = "Time")]
+    #[serde(rename = "Time", serialize_with = "serialize_time")]
     pub time: DateTime<Utc>,
 
     /// The GPS position of the course point. Serializes to `Position`.
@@ -271,7 +708,7 @@ pub struct HeartRate {
 }
 
 /// GPS position in degrees latitude and longitude.
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct Position {
     /// Degrees latitude. Positive numbers are North of the Equator, negative numbers are South. Serializes to `LatitudeDegrees`.
     #[serde(rename = "LatitudeDegrees")]
@@ -286,7 +723,7 @@ pub struct Position {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Trackpoint {
     /// Indicates the time the Trackpoint was recorded. Serializes to `Time`.
-    #[serde(rename = "Time")]
+    #[serde(rename = "Time", serialize_with = "serialize_time")]
     pub time: DateTime<Utc>,
 
     /// The GPS position at which the Trackpoint was recorded. Serializes to `Position`.
@@ -315,16 +752,24 @@ pub struct Trackpoint {
 }
 
 /// Describes a Track as a list of Trackpoints.
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[derive(Serialize, Debug, Default, Clone)]
 pub struct Track {
     /// A list of Trackpoints that make up a Track. Serializes to `Trackpoint`.
-    #[serde(rename = "Trackpoint")]
+    #[serde(rename = "Trackpoint", default)]
     pub trackpoints: Vec<Trackpoint>,
 }
 
 /// Contains summary information for each individual lap within an activity.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct ActivityLap {
+    /// The time the lap started. Serializes to the `StartTime` attribute.
+    #[serde(
+        rename = "StartTime",
+        alias = "@StartTime",
+        serialize_with = "serialize_optional_time"
+    )]
+    pub start_time: Option<DateTime<Utc>>,
+
     /// Total lap duration in secons. Serializes to `TotalTimeSeconds`.
     #[serde(rename = "TotalTimeSeconds")]
     pub total_time_seconds: f64,
@@ -341,12 +786,24 @@ pub struct ActivityLap {
     #[serde(rename = "Calories")]
     pub calories: u16,
 
-    /// Average heart rate in Beats per Minute (BPM) for the lap. Serializes to `AverageHeartRate`.
-    #[serde(rename = "AverageHeartRate")]
+    /// Average heart rate in Beats per Minute (BPM) for the lap. Serializes to `AverageHeartRateBpm`, and is also
+    /// read from `AverageHeartRate`, the name used by earlier versions.
+    #[serde(
+        rename = "AverageHeartRateBpm",
+        alias = "AverageHeartRate",
+        default,
+        deserialize_with = "deserialize_bpm"
+    )]
     pub average_heart_rate: Option<f64>,
 
-    /// Maximum heart rate in Beats per Minute (BPM) for the lap. Serializes to `MaximumHeartRate`.
-    #[serde(rename = "MaximumHeartRate")]
+    /// Maximum heart rate in Beats per Minute (BPM) for the lap. Serializes to `MaximumHeartRateBpm`, and is also
+    /// read from `MaximumHeartRate`, the name used by earlier versions.
+    #[serde(
+        rename = "MaximumHeartRateBpm",
+        alias = "MaximumHeartRate",
+        default,
+        deserialize_with = "deserialize_bpm"
+    )]
     pub maximum_heart_rate: Option<f64>,
 
     /// Intensity level for the lap, either `Active` or `Resting`. Serializes to `Intensity`.
@@ -362,7 +819,7 @@ pub struct ActivityLap {
     pub trigger_method: Option<TriggerMethod>,
 
     /// A list of tracks within the lap. Serializes to `Track`.
-    #[serde(rename = "Track")]
+    #[serde(rename = "Track", default)]
     pub tracks: Vec<Track>,
 
     /// Any additional notes that may describe the lap. Serializes to `Notes`.
@@ -372,10 +829,17 @@ pub struct ActivityLap {
     /// Any extensional information about the lap. Serializes to `Extensions`.
     #[serde(rename = "Extensions")]
     pub extensions: Option<Extensions>,
+
+    /// True when the lap's Trackpoints have been changed through the Trackpoint editing methods (such as
+    /// `Activity::set_trackpoint_hr`) since its totals were last computed. The totals are recomputed by
+    /// `refresh_summary`, or when the lap is written or exported. Not serialized.
+    #[serde(skip)]
+    pub summary_dirty: bool,
 }
 
 impl ActivityLap {
     /// Calculates the maximum and average heart rates based on the information recorded in the TrackPoints in each Track and sets the `average_heart_rate` and `maximum_heart_rate` fields.
+    /// Laps without heart rate samples, such as rest laps without a Track, keep the values the device recorded.
     ///
     /// # Parameters
     ///
@@ -395,11 +859,11 @@ impl ActivityLap {
     pub fn calc_heartrates(&mut self) {
         let mut max_hr = 0.0;
         let mut avg_hr = 0.0;
-        let mut num_tp = 0;
+        let mut num_hr = 0;
         for track in &self.tracks {
-            num_tp += track.trackpoints.len();
             for point in &track.trackpoints {
                 if let Some(hr) = &point.heart_rate {
+                    num_hr += 1;
                     avg_hr += hr.value; // Add up for avg heart rate
                     if hr.value > max_hr {
                         max_hr = hr.value; // Find max HR
@@ -414,21 +878,58 @@ impl ActivityLap {
         }
 
         // Set average heart rate
-        if num_tp > 0 {
-            self.average_heart_rate = Some(avg_hr / num_tp as f64);
+        if num_hr > 0 {
+            self.average_heart_rate = Some(avg_hr / num_hr as f64);
         }
     } // pub fn
+
+    /// Returns true if the lap has no Trackpoints, as rest laps and pool swim laps often do. Such laps have no Track
+    /// at all, or only empty ones, and their totals are only what the device recorded.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210415_pool_swim_rest_laps.tcx").unwrap();
+    /// let laps = &tcx.activities.unwrap().activities[0].laps;
+    /// let empty: Vec<bool> = laps.iter().map(|lap| lap.is_empty()).collect();
+    /// assert_eq!(empty, vec![false, true, false, true, false]);
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.trackpoints().next().is_none()
+    }
+
+    /// Iterates over every Trackpoint in every Track of the lap, in file order.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let lap = &tcx.activities.unwrap().activities[0].laps[0];
+    /// assert_eq!(lap.trackpoints().count(), 1232);
+    /// ```
+    pub fn trackpoints(&self) -> impl Iterator<Item = &Trackpoint> {
+        self.tracks
+            .iter()
+            .flat_map(|track| track.trackpoints.iter())
+    }
+
+    /// Iterates mutably over every Trackpoint in every Track of the lap, in file order.
+    pub fn trackpoints_mut(&mut self) -> impl Iterator<Item = &mut Trackpoint> {
+        self.tracks
+            .iter_mut()
+            .flat_map(|track| track.trackpoints.iter_mut())
+    }
 }
 
 /// Holds high-level information about an activity. This includes a the name and (often) the start time for the activity, as well as a list of laps.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Activity {
     /// The name of the activity being performed. Serializes to `Sport`.
-    #[serde(rename = "Sport")]
+    #[serde(rename = "Sport", alias = "@Sport")]
     pub sport: String,
 
     /// An identifier for the activity. This is often the start time of the activity. Serializes to `Id`.
-    #[serde(rename = "Id")]
+    #[serde(rename = "Id", serialize_with = "serialize_id")]
     pub id: String,
 
     /// A list of laps. Serializes to `Lap`.
@@ -439,84 +940,662 @@ pub struct Activity {
     #[serde(rename = "Notes")]
     pub notes: Option<String>,
 
+    /// The device that recorded the activity. Serializes to `Creator`.
+    #[serde(rename = "Creator")]
+    pub creator: Option<Device>,
+
     /// Any extentional data about the activity. Serializes to `Extensions`.
     #[serde(rename = "Extensions")]
     pub extensions: Option<Extensions>,
 }
 
+impl Activity {
+    /// Returns the start time of the activity: the time of the first Trackpoint or, failing that, the `Id` when it holds a timestamp.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let start = tcx.activities.unwrap().activities[0].start_time().unwrap();
+    /// assert_eq!(start.to_rfc3339(), "2021-01-19T23:30:50+00:00");
+    /// ```
+    pub fn start_time(&self) -> Option<DateTime<Utc>> {
+        match self.trackpoints().next() {
+            Some(point) => Some(point.time),
+            None => DateTime::parse_from_rfc3339(self.id.trim())
+                .ok()
+                .map(|time| time.with_timezone(&Utc)),
+        }
+    }
+
+    /// Iterates over every Trackpoint in every lap of the activity, in file order.
+    pub fn trackpoints(&self) -> impl Iterator<Item = &Trackpoint> {
+        self.laps.iter().flat_map(|lap| lap.trackpoints())
+    }
+
+    /// Iterates mutably over every Trackpoint in every lap of the activity, in file order.
+    pub fn trackpoints_mut(&mut self) -> impl Iterator<Item = &mut Trackpoint> {
+        self.laps.iter_mut().flat_map(|lap| lap.trackpoints_mut())
+    }
+}
+
 /// A list of the activities found in the TCX file
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Activities {
-    #[serde(rename = "Activity")]
+    /// Single-sport activities. Serializes to `Activity`.
+    #[serde(rename = "Activity", default)]
     pub activities: Vec<Activity>,
+
+    /// Multisport sessions, such as triathlons. Serializes to `MultiSportSession`.
+    #[serde(rename = "MultiSportSession", default)]
+    pub multi_sport_sessions: Vec<MultiSportSession>,
+}
+
+/// A session made up of several activities of different sports, such as a triathlon, along with the transitions
+/// between them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultiSportSession {
+    /// Uniquely identifies the session; the start time. Serializes to `Id`.
+    #[serde(rename = "Id", serialize_with = "serialize_time")]
+    pub id: DateTime<Utc>,
+
+    /// The first leg of the session. Serializes to `FirstSport`.
+    #[serde(rename = "FirstSport")]
+    pub first_sport: FirstSport,
+
+    /// The remaining legs, each with the transition that preceded it. Serializes to `NextSport`.
+    #[serde(rename = "NextSport", default)]
+    pub next_sports: Vec<NextSport>,
+
+    /// Optional notes about the session. Serializes to `Notes`.
+    #[serde(rename = "Notes")]
+    pub notes: Option<String>,
+}
+
+impl MultiSportSession {
+    /// Iterates over the legs of the session in order, excluding transitions.
+    pub fn activities(&self) -> impl Iterator<Item = &Activity> {
+        std::iter::once(&self.first_sport.activity)
+            .chain(self.next_sports.iter().map(|next| &next.activity))
+    }
+
+    /// Iterates over the transitions between legs, in order.
+    pub fn transitions(&self) -> impl Iterator<Item = &ActivityLap> {
+        self.next_sports
+            .iter()
+            .filter_map(|next| next.transition.as_ref())
+    }
 }
 
-/// Placeholder struct for history information. Currently not used.
+/// The first leg of a multisport session.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
-pub struct History {}
+pub struct FirstSport {
+    /// The activity for this leg. Serializes to `Activity`.
+    #[serde(rename = "Activity")]
+    pub activity: Activity,
+}
 
-/// Placeholder struct for workouts information. Currently not used.
+/// A subsequent leg of a multisport session.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
-pub struct Workouts {}
+pub struct NextSport {
+    /// The transition from the previous leg, recorded as a lap. Serializes to `Transition`.
+    #[serde(rename = "Transition")]
+    pub transition: Option<ActivityLap>,
 
-/// NS3 TPX Extension data.
+    /// The activity for this leg. Serializes to `Activity`.
+    #[serde(rename = "Activity")]
+    pub activity: Activity,
+}
+
+/// The history folder tree, found within `Folders`, with a folder per sport.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
-pub struct Ns3Tpx {
-    /// Speed, typically in meters per second. Serializes to `Speed`.
-    #[serde(rename = "Speed")]
-    pub speed: Option<f64>,
+pub struct History {
+    /// Running activities. Serializes to `Running`.
+    #[serde(rename = "Running")]
+    pub running: Option<HistoryFolder>,
 
-    /// Excertion in Watts. Serializes to `Watts`.
-    #[serde(rename = "Watts")]
-    pub watts: Option<u16>,
+    /// Cycling activities. Serializes to `Biking`.
+    #[serde(rename = "Biking")]
+    pub biking: Option<HistoryFolder>,
+
+    /// Other activities. Serializes to `Other`.
+    #[serde(rename = "Other")]
+    pub other: Option<HistoryFolder>,
+
+    /// Multisport sessions. Serializes to `MultiSport`.
+    #[serde(rename = "MultiSport")]
+    pub multi_sport: Option<HistoryFolder>,
+
+    /// Any extensional information about the history. Serializes to `Extensions`.
+    #[serde(rename = "Extensions")]
+    pub extensions: Option<Extensions>,
 }
 
-/// Placeholder struct for extension data. Currently supports NS3 TPX extensions.
+/// A folder of activities.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
-pub struct Extensions {
-    /// NS3 type TPX extensions. Serializes to `TPX`.
-    #[serde(rename = "TPX")]
-    pub tpx: Option<Ns3Tpx>,
+pub struct HistoryFolder {
+    /// The name of the folder. Serializes to `Name`.
+    #[serde(rename = "Name", alias = "@Name", default)]
+    pub name: String,
+
+    /// Sub-folders. Serializes to `Folder`.
+    #[serde(rename = "Folder", default)]
+    pub folders: Vec<HistoryFolder>,
+
+    /// References to the activities in this folder. Serializes to `ActivityRef`.
+    #[serde(rename = "ActivityRef", default)]
+    pub activity_refs: Vec<ActivityReference>,
+
+    /// References to the multisport sessions in this folder (multisport folders only). Serializes to
+    /// `MultisportActivityRef`.
+    #[serde(rename = "MultisportActivityRef", default)]
+    pub multisport_activity_refs: Vec<ActivityReference>,
+
+    /// Weekly notes. Serializes to `Week`.
+    #[serde(rename = "Week", default)]
+    pub weeks: Vec<Week>,
+
+    /// Describes any optional notes attached to the folder. Serializes to `Notes`.
+    #[serde(rename = "Notes")]
+    pub notes: Option<String>,
+
+    /// Any extensional information about the folder. Serializes to `Extensions`.
+    #[serde(rename = "Extensions")]
+    pub extensions: Option<Extensions>,
 }
 
-/// Folders for various types of information: History, Workouts and Courses.
+/// Refers to an activity by its `Id`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ActivityReference {
+    /// The `Id` of the activity. Serializes to `Id`.
+    #[serde(rename = "Id", serialize_with = "serialize_time")]
+    pub id: DateTime<Utc>,
+}
+
+/// Notes about a week of training.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Week {
+    /// The first day of the week. Serializes to `StartDay`.
+    #[serde(rename = "StartDay", alias = "@StartDay")]
+    pub start_day: NaiveDate,
+
+    /// Notes for the week. Serializes to `Notes`.
+    #[serde(rename = "Notes")]
+    pub notes: Option<String>,
+}
+
+/// The workout folder tree, found within `Folders`, with a folder per sport. The workouts themselves are in
+/// `WorkoutList`.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
-pub struct Folders {
-    /// Holds information about History data, which is currently an empty struct. Serializes to `History`.
-    #[serde(rename = "History")]
-    pub history: Option<History>,
+pub struct Workouts {
+    /// Running workouts. Serializes to `Running`.
+    #[serde(rename = "Running")]
+    pub running: Option<WorkoutFolder>,
 
-    /// Holds information about workout data, which is currently an empty struct. Serializes to `Workouts`.
-    #[serde(rename = "Workouts")]
-    pub workouts: Option<Workouts>,
+    /// Cycling workouts. Serializes to `Biking`.
+    #[serde(rename = "Biking")]
+    pub biking: Option<WorkoutFolder>,
 
-    /// Holds information about Course folders. Serializes to `Courses`.
-    #[serde(rename = "Courses")]
-    pub courses: Option<Courses>,
+    /// Other workouts. Serializes to `Other`.
+    #[serde(rename = "Other")]
+    pub other: Option<WorkoutFolder>,
+
+    /// Any extensional information about the workouts. Serializes to `Extensions`.
+    #[serde(rename = "Extensions")]
+    pub extensions: Option<Extensions>,
 }
 
-/// The top-level struct that contains all the information found in the TCX file, along with associated functions.
+/// A folder of workouts.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
-pub struct TrainingCenterDatabase {
-    /// A list of activities, if there are any. Serializes to `Activities`.
-    #[serde(rename = "Activities")]
-    pub activities: Option<Activities>,
+pub struct WorkoutFolder {
+    /// The name of the folder. Serializes to `Name`.
+    #[serde(rename = "Name", alias = "@Name", default)]
+    pub name: String,
 
-    /// Any folders that may be present in the file. Serializes to `Folders`.
-    #[serde(rename = "Folders")]
-    pub folders: Option<Folders>,
+    /// Sub-folders. Serializes to `Folder`.
+    #[serde(rename = "Folder", default)]
+    pub folders: Vec<WorkoutFolder>,
 
-    /// Any courses that may be present in the file. Serializes to `Courses`.
-    #[serde(rename = "Courses")]
-    pub courses: Option<Courses>,
+    /// References to the workouts in this folder. Serializes to `WorkoutNameRef`.
+    #[serde(rename = "WorkoutNameRef", default)]
+    pub workout_name_refs: Vec<NameKeyReference>,
 
-    /// Any extensions that may be present in the file. Serializes to `Extensions`.
+    /// Any extensional information about the folder. Serializes to `Extensions`.
     #[serde(rename = "Extensions")]
     pub extensions: Option<Extensions>,
 }
 
-impl TrainingCenterDatabase {
-    /// Reads and parses TCX data from the filename specified.
+/// The workouts found in the TCX file.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct WorkoutList {
+    /// The workouts. Serializes to `Workout`.
+    #[serde(rename = "Workout", default)]
+    pub workouts: Vec<Workout>,
+}
+
+/// A planned workout, made up of steps.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Workout {
+    /// The sport (`Running`, `Biking` or `Other`). Serializes to `Sport`.
+    #[serde(rename = "Sport", alias = "@Sport")]
+    pub sport: String,
+
+    /// The name of the workout, up to 15 characters. Serializes to `Name`.
+    #[serde(rename = "Name")]
+    pub name: String,
+
+    /// The steps of the workout, in order. Serializes to `Step`.
+    #[serde(rename = "Step", default)]
+    pub steps: Vec<WorkoutStep>,
+
+    /// The days the workout is scheduled on. Serializes to `ScheduledOn`.
+    #[serde(rename = "ScheduledOn", default)]
+    pub scheduled_on: Vec<NaiveDate>,
+
+    /// Describes any optional notes attached to the workout. Serializes to `Notes`.
+    #[serde(rename = "Notes")]
+    pub notes: Option<String>,
+
+    /// Identifies the creator for the workout. Serializes to `Creator`.
+    #[serde(rename = "Creator")]
+    pub creator: Option<AbstractSource>,
+
+    /// Any extensional information about the workout. Serializes to `Extensions`.
+    #[serde(rename = "Extensions")]
+    pub extensions: Option<Extensions>,
+}
+
+/// A workout step: either a single step (`Step_t`) with a duration and target, or a repeat block (`Repeat_t`)
+/// that runs its children a number of times. The schema distinguishes the two with `xsi:type`; both are read into
+/// this one struct.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct WorkoutStep {
+    /// The schema type of the step, `Step_t` or `Repeat_t`. Serializes to `type`.
+    #[serde(rename = "type", alias = "@type")]
+    pub step_type: Option<String>,
+
+    /// Identifies the step within the workout. Serializes to `StepId`.
+    #[serde(rename = "StepId")]
+    pub step_id: u8,
+
+    /// The name of the step (single steps only). Serializes to `Name`.
+    #[serde(rename = "Name")]
+    pub name: Option<String>,
+
+    /// When the step ends (single steps only). Serializes to `Duration`.
+    #[serde(rename = "Duration")]
+    pub duration: Option<StepDuration>,
+
+    /// Intensity (`Active` or `Resting`) for the step (single steps only). Serializes to `Intensity`.
+    #[serde(rename = "Intensity")]
+    pub intensity: Option<Intensity>,
+
+    /// What to aim for during the step (single steps only). Serializes to `Target`.
+    #[serde(rename = "Target")]
+    pub target: Option<StepTarget>,
+
+    /// How many times to run the children (repeat blocks only). Serializes to `Repetitions`.
+    #[serde(rename = "Repetitions")]
+    pub repetitions: Option<u8>,
+
+    /// The steps to repeat (repeat blocks only). Serializes to `Child`.
+    #[serde(rename = "Child", default)]
+    pub children: Vec<WorkoutStep>,
+}
+
+impl WorkoutStep {
+    /// Returns true if this is a repeat block rather than a single step.
+    pub fn is_repeat(&self) -> bool {
+        match self.step_type.as_deref() {
+            Some(step_type) => step_type == "Repeat_t",
+            None => self.repetitions.is_some(),
+        }
+    }
+}
+
+/// When a workout step ends. Which field is set depends on the schema type: `Time_t` (`seconds`), `Distance_t`
+/// (`meters`), `HeartRateAbove_t` and `HeartRateBelow_t` (`heart_rate`), `CaloriesBurned_t` (`calories`) or
+/// `UserInitiated_t` (none).
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct StepDuration {
+    /// The schema type of the duration. Serializes to `type`.
+    #[serde(rename = "type", alias = "@type")]
+    pub duration_type: Option<String>,
+
+    /// Duration in seconds. Serializes to `Seconds`.
+    #[serde(rename = "Seconds")]
+    pub seconds: Option<u16>,
+
+    /// Distance in meters. Serializes to `Meters`.
+    #[serde(rename = "Meters")]
+    pub meters: Option<u16>,
+
+    /// The heart rate to reach. Serializes to `HeartRate`.
+    #[serde(rename = "HeartRate")]
+    pub heart_rate: Option<HeartRateValue>,
+
+    /// Calories to burn. Serializes to `Calories`.
+    #[serde(rename = "Calories")]
+    pub calories: Option<u16>,
+}
+
+/// What to aim for during a workout step. Which field is set depends on the schema type: `Speed_t`
+/// (`speed_zone`), `HeartRate_t` (`heart_rate_zone`), `Cadence_t` (`low` and `high`) or `None_t` (none).
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct StepTarget {
+    /// The schema type of the target. Serializes to `type`.
+    #[serde(rename = "type", alias = "@type")]
+    pub target_type: Option<String>,
+
+    /// The speed zone to stay in. Serializes to `SpeedZone`.
+    #[serde(rename = "SpeedZone")]
+    pub speed_zone: Option<Zone>,
+
+    /// The heart rate zone to stay in. Serializes to `HeartRateZone`.
+    #[serde(rename = "HeartRateZone")]
+    pub heart_rate_zone: Option<Zone>,
+
+    /// Lowest cadence. Serializes to `Low`.
+    #[serde(rename = "Low")]
+    pub low: Option<f64>,
+
+    /// Highest cadence. Serializes to `High`.
+    #[serde(rename = "High")]
+    pub high: Option<f64>,
+}
+
+/// A speed or heart rate zone, either one of the device's predefined zones (`PredefinedSpeedZone_t`,
+/// `PredefinedHeartRateZone_t`: `number`) or a custom range (`CustomSpeedZone_t`: `view_as` and the speeds,
+/// `CustomHeartRateZone_t`: `low` and `high`).
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Zone {
+    /// The schema type of the zone. Serializes to `type`.
+    #[serde(rename = "type", alias = "@type")]
+    pub zone_type: Option<String>,
+
+    /// The predefined zone number, 1 to 10. Serializes to `Number`.
+    #[serde(rename = "Number")]
+    pub number: Option<u8>,
+
+    /// How a custom speed zone is displayed. Serializes to `ViewAs`.
+    #[serde(rename = "ViewAs")]
+    pub view_as: Option<SpeedType>,
+
+    /// Lowest speed of a custom speed zone, in meters per second. Serializes to `LowInMetersPerSecond`.
+    #[serde(rename = "LowInMetersPerSecond")]
+    pub low_in_meters_per_second: Option<f64>,
+
+    /// Highest speed of a custom speed zone, in meters per second. Serializes to `HighInMetersPerSecond`.
+    #[serde(rename = "HighInMetersPerSecond")]
+    pub high_in_meters_per_second: Option<f64>,
+
+    /// Lowest heart rate of a custom heart rate zone. Serializes to `Low`.
+    #[serde(rename = "Low")]
+    pub low: Option<HeartRateValue>,
+
+    /// Highest heart rate of a custom heart rate zone. Serializes to `High`.
+    #[serde(rename = "High")]
+    pub high: Option<HeartRateValue>,
+}
+
+/// A heart rate in a workout, either in beats per minute (`HeartRateInBeatsPerMinute_t`) or as a percentage of
+/// the maximum (`HeartRateAsPercentOfMax_t`).
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct HeartRateValue {
+    /// The schema type of the value. Serializes to `type`.
+    #[serde(rename = "type", alias = "@type")]
+    pub value_type: Option<String>,
+
+    /// The heart rate. Serializes to `Value`.
+    #[serde(rename = "Value")]
+    pub value: u8,
+}
+
+/// NS3 TPX Extension data.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Ns3Tpx {
+    /// Speed, typically in meters per second. Serializes to `Speed`.
+    #[serde(rename = "Speed")]
+    pub speed: Option<f64>,
+
+    /// Running cadence in strides per minute (one foot). Serializes to `RunCadence`.
+    #[serde(rename = "RunCadence")]
+    pub run_cadence: Option<u8>,
+
+    /// Excertion in Watts. Serializes to `Watts`.
+    #[serde(rename = "Watts")]
+    pub watts: Option<u16>,
+}
+
+impl Ns3Tpx {
+    /// Fills in the fields this one lacks from another TPX element of the same Trackpoint.
+    fn merge(&mut self, other: Ns3Tpx) {
+        self.speed = self.speed.or(other.speed);
+        self.run_cadence = self.run_cadence.or(other.run_cadence);
+        self.watts = self.watts.or(other.watts);
+    }
+}
+
+/// NS3 LX Extension data, holding lap summaries that the core schema has no place for.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Ns3Lx {
+    /// Average speed in meters per second. Serializes to `AvgSpeed`.
+    #[serde(rename = "AvgSpeed")]
+    pub avg_speed: Option<f64>,
+
+    /// Maximum cycling cadence in revolutions per minute. Serializes to `MaxBikeCadence`.
+    #[serde(rename = "MaxBikeCadence")]
+    pub max_bike_cadence: Option<u8>,
+
+    /// Average running cadence in strides per minute. Serializes to `AvgRunCadence`.
+    #[serde(rename = "AvgRunCadence")]
+    pub avg_run_cadence: Option<u8>,
+
+    /// Maximum running cadence in strides per minute. Serializes to `MaxRunCadence`.
+    #[serde(rename = "MaxRunCadence")]
+    pub max_run_cadence: Option<u8>,
+
+    /// Number of steps taken. Serializes to `Steps`.
+    #[serde(rename = "Steps")]
+    pub steps: Option<u16>,
+
+    /// Average power in Watts. Serializes to `AvgWatts`.
+    #[serde(rename = "AvgWatts")]
+    pub avg_watts: Option<u16>,
+
+    /// Maximum power in Watts. Serializes to `MaxWatts`.
+    #[serde(rename = "MaxWatts")]
+    pub max_watts: Option<u16>,
+}
+
+impl Ns3Lx {
+    /// Fills in the fields this one lacks from another LX element of the same lap.
+    fn merge(&mut self, other: Ns3Lx) {
+        self.avg_speed = self.avg_speed.or(other.avg_speed);
+        self.max_bike_cadence = self.max_bike_cadence.or(other.max_bike_cadence);
+        self.avg_run_cadence = self.avg_run_cadence.or(other.avg_run_cadence);
+        self.max_run_cadence = self.max_run_cadence.or(other.max_run_cadence);
+        self.steps = self.steps.or(other.steps);
+        self.avg_watts = self.avg_watts.or(other.avg_watts);
+        self.max_watts = self.max_watts.or(other.max_watts);
+    }
+}
+
+/// Placeholder struct for extension data. Currently supports NS3 TPX and LX extensions, barometric pressure,
+/// temperature, GPS dilution of precision and R-R intervals. `TPX` and `LX` may be repeated within one `Extensions`
+/// element, as some exporters declare the ActivityExtension namespace under two prefixes and use both in the same
+/// Trackpoint or lap (such as `ns3:TPX` holding the speed and `ax:TPX` the power); the repeated elements are merged,
+/// the first value of each field being kept.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Extensions {
+    /// NS3 type TPX extensions. Serializes to `TPX`.
+    #[serde(rename = "TPX")]
+    pub tpx: Option<Ns3Tpx>,
+
+    /// NS3 type LX extensions, found on laps. Serializes to `LX`.
+    #[serde(rename = "LX")]
+    pub lx: Option<Ns3Lx>,
+
+    /// Raw barometric pressure in Pascals, as recorded by some devices in a vendor extension. Not part of the Garmin
+    /// schema, so it is not written back out by `to_writer`. Serializes to `Pressure`.
+    #[serde(rename = "Pressure")]
+    pub pressure: Option<f64>,
+
+    /// Air temperature in degrees Celsius, as carried by GPX files (Garmin TrackPointExtension `atemp`). Not part of
+    /// the TCX schema, so it is not written back out by `to_writer`. Serializes to `Temperature`.
+    #[serde(rename = "Temperature")]
+    pub temperature: Option<f64>,
+
+    /// Horizontal dilution of precision of the GPS fix, as carried by GPX files (`hdop`) and some vendor extensions.
+    /// Not part of the TCX schema, so it is not written back out by `to_writer`. Serializes to `HDOP`.
+    #[serde(rename = "HDOP")]
+    pub hdop: Option<f64>,
+
+    /// R-R intervals, the times between successive heart beats, recorded since the previous Trackpoint, in
+    /// milliseconds. Some devices embed these in a vendor extension as a list separated by spaces or commas, in
+    /// milliseconds or in seconds. Not part of the TCX schema, so it is not written back out by `to_writer`.
+    /// Serializes to `RRIntervals`, and only when there are any.
+    #[serde(rename = "RRIntervals", skip_serializing_if = "Vec::is_empty")]
+    pub rr_intervals: Vec<u16>,
+
+    /// The kind of sensor that measured the Trackpoint's heart rate, if known. Set with `tag_heart_rate_source`.
+    /// Not part of the TCX schema, so `to_writer` writes it as a `HeartRateSource` element under the
+    /// ActivityExtension prefix, beside `TPX`, where the schema's lax extension rules allow it and other readers
+    /// skip it. Serializes to `HeartRateSource`.
+    #[serde(rename = "HeartRateSource")]
+    pub heart_rate_source: Option<HeartRateSource>,
+
+    /// Extension elements this crate does not model, as raw XML. Only filled in by the quick-xml reader (see
+    /// `crate::quick`), and written back out unchanged by `to_writer`. Serializes to `extensions_raw`, and only when
+    /// there are any, so that JSON consumers see vendor fields too.
+    #[serde(rename = "extensions_raw", skip_serializing_if = "Vec::is_empty")]
+    pub unknown: Vec<String>,
+}
+
+/// The names `Extensions` fields are read from.
+const EXTENSIONS_FIELDS: &[&str] = &[
+    "TPX",
+    "LX",
+    "Pressure",
+    "Temperature",
+    "HDOP",
+    "RRIntervals",
+    "HeartRateSource",
+    "extensions_raw",
+];
+
+impl<'de> serde::Deserialize<'de> for Extensions {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("Extensions", EXTENSIONS_FIELDS, ExtensionsVisitor)
+    }
+}
+
+/// Reads the children of `Extensions` one at a time, so that repeated `TPX` and `LX` elements can be merged. The
+/// deserializers match elements by local name, so the prefix each one is written with does not matter.
+struct ExtensionsVisitor;
+
+impl<'de> serde::de::Visitor<'de> for ExtensionsVisitor {
+    type Value = Extensions;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("extension elements")
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Extensions, A::Error> {
+        let mut extensions = Extensions::default();
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "TPX" => {
+                    if let Some(tpx) = map.next_value::<Option<Ns3Tpx>>()? {
+                        match extensions.tpx.as_mut() {
+                            Some(first) => first.merge(tpx),
+                            None => extensions.tpx = Some(tpx),
+                        }
+                    }
+                }
+                "LX" => {
+                    if let Some(lx) = map.next_value::<Option<Ns3Lx>>()? {
+                        match extensions.lx.as_mut() {
+                            Some(first) => first.merge(lx),
+                            None => extensions.lx = Some(lx),
+                        }
+                    }
+                }
+                "Pressure" => extensions.pressure = extensions.pressure.or(map.next_value()?),
+                "Temperature" => {
+                    extensions.temperature = extensions.temperature.or(map.next_value()?)
+                }
+                "HDOP" => extensions.hdop = extensions.hdop.or(map.next_value()?),
+                "RRIntervals" => {
+                    let RrIntervals(rr_intervals) = map.next_value()?;
+                    if extensions.rr_intervals.is_empty() {
+                        extensions.rr_intervals = rr_intervals;
+                    }
+                }
+                "HeartRateSource" => {
+                    extensions.heart_rate_source =
+                        extensions.heart_rate_source.or(map.next_value()?)
+                }
+                "extensions_raw" => extensions.unknown = map.next_value()?,
+                _ => {
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(extensions)
+    }
+}
+
+/// Folders for various types of information: History, Workouts and Courses.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Folders {
+    /// Holds information about History folders. Serializes to `History`.
+    #[serde(rename = "History")]
+    pub history: Option<History>,
+
+    /// Holds information about workout folders. Serializes to `Workouts`.
+    #[serde(rename = "Workouts")]
+    pub workouts: Option<Workouts>,
+
+    /// Holds information about Course folders. Serializes to `Courses`.
+    #[serde(rename = "Courses")]
+    pub courses: Option<Courses>,
+}
+
+/// The top-level struct that contains all the information found in the TCX file, along with associated functions.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct TrainingCenterDatabase {
+    /// A list of activities, if there are any. Serializes to `Activities`.
+    #[serde(rename = "Activities")]
+    pub activities: Option<Activities>,
+
+    /// Any folders that may be present in the file. Serializes to `Folders`.
+    #[serde(rename = "Folders")]
+    pub folders: Option<Folders>,
+
+    /// Any workouts that may be present in the file. Serializes to `Workouts`.
+    #[serde(rename = "Workouts")]
+    pub workouts: Option<WorkoutList>,
+
+    /// Any courses that may be present in the file. Serializes to `Courses`.
+    #[serde(rename = "Courses")]
+    pub courses: Option<CourseList>,
+
+    /// The application that produced the file. Serializes to `Author`.
+    #[serde(rename = "Author")]
+    pub author: Option<Application>,
+
+    /// Any extensions that may be present in the file. Serializes to `Extensions`.
+    #[serde(rename = "Extensions")]
+    pub extensions: Option<Extensions>,
+
+    /// The original XML, if it was retained when reading (see `ReadOptions`). Never serialized.
+    #[serde(skip)]
+    pub original: Option<OriginalXml>,
+}
+
+impl TrainingCenterDatabase {
+    /// Reads and parses TCX data from the filename specified.
     ///
     /// # Parameters
     ///
@@ -524,24 +1603,53 @@ impl TrainingCenterDatabase {
     ///
     /// # Returns
     ///
-    /// `Result<Self, serde_xml_rs::Error>`
+    /// `Result<Self, crate::tcx::Error>`
     ///
     /// # Example
     ///
     /// ```rust
     /// let my_activities = crate::tcx::TrainingCenterDatabase::from_file("tests/20210119_run_garmin_fenix6.tcx");
     /// ```
+    pub fn from_file(filename: &str) -> Result<Self, crate::error::Error> {
+        crate::storage::read_from(&mut std::path::Path::new(filename), &ReadOptions::default())
+    }
+
+    /// Writes out the original XML exactly as it was read, which requires that it was read with
+    /// `OriginalRetention::Bytes`. Unlike `to_writer`, this is byte-for-byte faithful to the source file.
+    ///
+    /// # Parameters
+    ///
+    /// `writer: &mut W` -- Where to write the original XML.
+    ///
+    /// # Returns
     ///
-    /// # References
+    /// `Result<bool, std::io::Error>` -- True if the original was written, false if it was not retained.
     ///
-    /// [`serde_xml_rs::Error`](https://docs.rs/serde-xml-rs/0.5.1/serde_xml_rs/enum.Error.html)
-    pub fn from_file(filename: &str) -> Result<Self, serde_xml_rs::Error> {
-        let file = std::fs::File::open(filename).unwrap();
-        let mut reader = std::io::BufReader::new(file);
-        serde_xml_rs::from_reader(&mut reader)
+    /// # Example
+    ///
+    /// ```rust
+    /// let options = crate::tcx::ReadOptions { original: crate::tcx::OriginalRetention::Bytes, ..Default::default() };
+    /// let tcx = crate::tcx::read_file_with_options("tests/20210323_yoga.tcx", &options).unwrap();
+    /// let mut copy = Vec::new();
+    /// assert!(tcx.write_original(&mut copy).unwrap());
+    /// assert_eq!(copy, std::fs::read("tests/20210323_yoga.tcx").unwrap());
+    /// ```
+    pub fn write_original<W: Write>(&self, writer: &mut W) -> std::io::Result<bool> {
+        match self
+            .original
+            .as_ref()
+            .and_then(|original| original.bytes.as_ref())
+        {
+            Some(bytes) => {
+                writer.write_all(bytes)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
     }
 
-    /// Calculates heart rates for all ActivityLap items. For now.
+    /// Calculates heart rates for all ActivityLap items. For now. Documents without activities, such as those holding
+    /// only courses, are left unchanged.
     ///
     /// # Parameters:
     ///
@@ -560,10 +1668,11 @@ impl TrainingCenterDatabase {
     /// ```
     pub fn calc_heartrates(&mut self) {
         // Calculate heart rate for ActivityLaps.
-        // FIXME - There has to be a better way to do this. Using iterators gives all kinds of problems with borrowing.
-        for act in 0..self.activities.as_ref().unwrap().activities.len() {
-            for lap in 0..self.activities.as_ref().unwrap().activities[act].laps.len() {
-                self.activities.as_mut().unwrap().activities[act].laps[lap].calc_heartrates();
+        if let Some(activities) = self.activities.as_mut() {
+            for activity in activities.activities.iter_mut() {
+                for lap in activity.laps.iter_mut() {
+                    lap.calc_heartrates();
+                }
             }
         }
 
@@ -588,20 +1697,510 @@ impl TrainingCenterDatabase {
     /// tcx.export_json("tests/20210119_run_garmin_fenix6.json");
     /// ```
     pub fn export_json(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        self.export_json_with_options(filename, &JsonOptions::default())
+    }
+
+    /// Exports the parsed contents of the TCX file to JSON format, using the given options.
+    ///
+    /// # Parameters
+    ///
+    /// `filename: &str` -- The name of the JSON file to be produced.
+    ///
+    /// `options: &JsonOptions` -- Controls how values such as timestamps are written.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let options = crate::tcx::JsonOptions {
+    ///     timestamps: crate::tcx::TimestampFormat::EpochMillis,
+    ///     ..Default::default()
+    /// };
+    /// tcx.export_json_with_options("tests/20210119_run_garmin_fenix6.epoch.json", &options);
+    /// ```
+    pub fn export_json_with_options(
+        &self,
+        filename: &str,
+        options: &JsonOptions,
+    ) -> Result<(), Box<dyn Error>> {
         // Write the session data to JSON
-        serde_json::to_writer_pretty(
-            &std::fs::File::create(&std::path::PathBuf::from(filename))?,
-            &self,
-        )?;
+        self.write_json_to(&mut std::path::Path::new(filename), options)
+    }
 
-        // Return safely
-        Ok(())
+    /// Writes the parsed contents as pretty-printed JSON, using the given options. Fields are written in the order of
+    /// the schema, and floating-point values in plain decimal notation, never as `1.2e3`.
+    ///
+    /// # Parameters
+    ///
+    /// `writer: &mut W` -- Where to write the JSON.
+    ///
+    /// `options: &JsonOptions` -- Controls how values such as timestamps and numbers are written.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), serde_json::Error>` -- Nothing if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let options = crate::tcx::JsonOptions { decimal_places: Some(2), ..Default::default() };
+    /// let mut json = Vec::new();
+    /// tcx.to_json_writer(&mut json, &options).unwrap();
+    /// assert!(!String::from_utf8(json).unwrap().contains("e-"));
+    /// ```
+    pub fn to_json_writer<W: Write>(
+        &self,
+        writer: &mut W,
+        options: &JsonOptions,
+    ) -> Result<(), serde_json::Error> {
+        let formatter = PlainNumberFormatter {
+            inner: serde_json::ser::PrettyFormatter::new(),
+            decimal_places: options.decimal_places,
+        };
+        let mut serializer = serde_json::Serializer::with_formatter(writer, formatter);
+        with_json_timestamps(options.timestamps, || {
+            serde::Serialize::serialize(&*self.refreshed(), &mut serializer)
+        })
+    }
+
+    /// Converts the parsed contents of the TCX file to a JSON value, using the given options. The value's objects
+    /// keep their keys sorted; use `to_json_writer` for the fields in schema order.
+    ///
+    /// # Parameters
+    ///
+    /// `options: &JsonOptions` -- Controls how values such as timestamps are written.
+    ///
+    /// # Returns
+    ///
+    /// `Result<serde_json::Value, serde_json::Error>`
+    pub fn to_json_value(
+        &self,
+        options: &JsonOptions,
+    ) -> Result<serde_json::Value, serde_json::Error> {
+        with_json_timestamps(options.timestamps, || {
+            serde_json::to_value(&*self.refreshed())
+        })
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 // Functions
 
+/// Reads a lap's heart rate summary either as the schema writes it, a `Value` element within
+/// `AverageHeartRateBpm` or `MaximumHeartRateBpm`, or as a plain number, as JSON exports hold it.
+fn deserialize_bpm<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    deserializer.deserialize_any(BpmVisitor)
+}
+
+struct BpmVisitor;
+
+impl<'de> serde::de::Visitor<'de> for BpmVisitor {
+    type Value = Option<f64>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a heart rate, or an element with a heart rate Value")
+    }
+
+    fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<Self::Value, E> {
+        Ok(Some(value))
+    }
+
+    fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        Ok(Some(value as f64))
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        Ok(Some(value as f64))
+    }
+
+    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(value), &self))
+    }
+
+    fn visit_none<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserialize_bpm(deserializer)
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        // The XML readers present the element's text as `$value` or `$text`.
+        let mut value = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "Value" | "$value" | "$text" => value = map.next_value::<Bpm>()?.0,
+                _ => {
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// Reads R-R intervals either as a list of numbers separated by spaces or commas, as devices write them, or as a
+/// sequence, as JSON exports hold them. Values below ten are taken to be in seconds and the rest in milliseconds.
+fn deserialize_rr_intervals<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<u16>, D::Error> {
+    deserializer.deserialize_any(RrIntervalsVisitor)
+}
+
+/// Converts one R-R interval to milliseconds, guessing the unit from its size.
+fn rr_milliseconds(value: f64) -> u16 {
+    let milliseconds = if value < 10.0 { value * 1000.0 } else { value };
+    milliseconds.round().clamp(0.0, f64::from(u16::MAX)) as u16
+}
+
+struct RrIntervalsVisitor;
+
+impl<'de> serde::de::Visitor<'de> for RrIntervalsVisitor {
+    type Value = Vec<u16>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a list of R-R intervals")
+    }
+
+    fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<Self::Value, E> {
+        Ok(vec![rr_milliseconds(value)])
+    }
+
+    fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        self.visit_f64(value as f64)
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        self.visit_f64(value as f64)
+    }
+
+    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        value
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                item.parse::<f64>()
+                    .map(rr_milliseconds)
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(value), &self))
+            })
+            .collect()
+    }
+
+    fn visit_none<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+        Ok(Vec::new())
+    }
+
+    fn visit_unit<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+        Ok(Vec::new())
+    }
+
+    fn visit_some<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserialize_rr_intervals(deserializer)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut intervals = Vec::new();
+        while let Some(value) = seq.next_element::<f64>()? {
+            intervals.push(rr_milliseconds(value));
+        }
+        Ok(intervals)
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        // The XML readers present the element's text as `$value` or `$text`.
+        let mut intervals = Vec::new();
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "$value" | "$text" => intervals = map.next_value::<RrIntervals>()?.0,
+                _ => {
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(intervals)
+    }
+}
+
+/// R-R intervals read with `deserialize_rr_intervals`, for the text within an element.
+struct RrIntervals(Vec<u16>);
+
+impl<'de> serde::Deserialize<'de> for RrIntervals {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_rr_intervals(deserializer).map(RrIntervals)
+    }
+}
+
+/// A heart rate read with `deserialize_bpm`, for the values nested within an element.
+struct Bpm(Option<f64>);
+
+impl<'de> serde::Deserialize<'de> for Bpm {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_bpm(deserializer).map(Bpm)
+    }
+}
+
+impl TrackpointPolicy {
+    /// Whether the Trackpoint at the given index within its track is kept.
+    pub(crate) fn keeps(&self, index: usize) -> bool {
+        match self {
+            TrackpointPolicy::All | TrackpointPolicy::EveryNth(0) => true,
+            TrackpointPolicy::None => false,
+            TrackpointPolicy::EveryNth(n) => index.is_multiple_of(*n),
+        }
+    }
+}
+
+thread_local! {
+    /// The Trackpoint policy of the read in progress on this thread, which `Track` consults as it is deserialized.
+    static TRACKPOINT_POLICY: std::cell::Cell<TrackpointPolicy> =
+        const { std::cell::Cell::new(TrackpointPolicy::All) };
+}
+
+/// Runs `read` with the given Trackpoint policy applied to every `Track` it deserializes on this thread.
+pub(crate) fn with_trackpoint_policy<T>(policy: TrackpointPolicy, read: impl FnOnce() -> T) -> T {
+    /// Restores the previous policy, even if the read panics.
+    struct Restore(TrackpointPolicy);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            TRACKPOINT_POLICY.with(|current| current.set(self.0));
+        }
+    }
+
+    let _restore = Restore(TRACKPOINT_POLICY.with(|current| current.replace(policy)));
+    read()
+}
+
+impl<'de> serde::Deserialize<'de> for Track {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("Track", &["Trackpoint"], TrackVisitor)
+    }
+}
+
+/// Reads the Trackpoints of a `Track`, skipping those the thread's Trackpoint policy leaves out at the level of the
+/// parser's events, so that comments, CDATA and namespace prefixes are handled as they are everywhere else. The
+/// count restarts at every `Track`.
+struct TrackVisitor;
+
+impl<'de> serde::de::Visitor<'de> for TrackVisitor {
+    type Value = Track;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a track")
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Track, A::Error> {
+        let mut track = Track::default();
+        let mut seed = TrackpointSeed {
+            policy: TRACKPOINT_POLICY.with(|policy| policy.get()),
+            index: 0,
+            kept: &mut track.trackpoints,
+        };
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "Trackpoint" => map.next_value_seed(&mut seed)?,
+                _ => {
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(track)
+    }
+}
+
+/// Deserializes a run of Trackpoints, keeping those the policy keeps and skipping the rest unparsed.
+struct TrackpointSeed<'a> {
+    policy: TrackpointPolicy,
+    /// The index within the track of the next Trackpoint.
+    index: usize,
+    kept: &'a mut Vec<Trackpoint>,
+}
+
+impl<'de> serde::de::DeserializeSeed<'de> for &mut TrackpointSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for &mut TrackpointSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a sequence of Trackpoints")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        loop {
+            let more = if self.policy.keeps(self.index) {
+                seq.next_element::<Trackpoint>()?
+                    .map(|point| self.kept.push(point))
+                    .is_some()
+            } else {
+                seq.next_element::<serde::de::IgnoredAny>()?.is_some()
+            };
+            if !more {
+                return Ok(());
+            }
+            self.index += 1;
+        }
+    }
+}
+
+/// A JSON formatter that writes floating-point values with `crate::writer::format_number`, so never in scientific
+/// notation, and leaves everything else to the wrapped formatter.
+struct PlainNumberFormatter<F> {
+    inner: F,
+    decimal_places: Option<usize>,
+}
+
+impl<F: serde_json::ser::Formatter> serde_json::ser::Formatter for PlainNumberFormatter<F> {
+    fn write_f64<W: ?Sized + Write>(&mut self, writer: &mut W, value: f64) -> std::io::Result<()> {
+        let mut formatted = crate::writer::format_number(value, self.decimal_places);
+        // Keep whole numbers recognizable as floating-point, as serde_json itself does.
+        if self.decimal_places.is_none() && !formatted.contains('.') {
+            formatted.push_str(".0");
+        }
+        writer.write_all(formatted.as_bytes())
+    }
+
+    fn write_f32<W: ?Sized + Write>(&mut self, writer: &mut W, value: f32) -> std::io::Result<()> {
+        self.write_f64(writer, value as f64)
+    }
+
+    fn begin_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.inner.begin_array(writer)
+    }
+
+    fn end_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.inner.end_array(writer)
+    }
+
+    fn begin_array_value<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        self.inner.begin_array_value(writer, first)
+    }
+
+    fn end_array_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.inner.end_array_value(writer)
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.inner.begin_object(writer)
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.inner.end_object(writer)
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        self.inner.begin_object_key(writer, first)
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.inner.begin_object_value(writer)
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.inner.end_object_value(writer)
+    }
+}
+
+thread_local! {
+    /// The timestamp format of the JSON export in progress on this thread, which the model's times consult as they
+    /// are serialized.
+    static JSON_TIMESTAMPS: std::cell::Cell<TimestampFormat> =
+        const { std::cell::Cell::new(TimestampFormat::Rfc3339) };
+}
+
+/// Runs `export` with every time it serializes on this thread written in the given format.
+fn with_json_timestamps<T>(format: TimestampFormat, export: impl FnOnce() -> T) -> T {
+    /// Restores the previous format, even if the export panics.
+    struct Restore(TimestampFormat);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            JSON_TIMESTAMPS.with(|current| current.set(self.0));
+        }
+    }
+
+    let _restore = Restore(JSON_TIMESTAMPS.with(|current| current.replace(format)));
+    export()
+}
+
+/// Serializes a time in the format of the JSON export in progress, or as RFC 3339 outside of one.
+fn serialize_time<S: serde::Serializer>(
+    time: &DateTime<Utc>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match JSON_TIMESTAMPS.with(|format| format.get()) {
+        TimestampFormat::Rfc3339 => serde::Serialize::serialize(time, serializer),
+        TimestampFormat::EpochMillis => serializer.serialize_i64(time.timestamp_millis()),
+        TimestampFormat::EpochSeconds => serializer.serialize_i64(time.timestamp()),
+    }
+}
+
+/// Serializes an optional time with `serialize_time`.
+fn serialize_optional_time<S: serde::Serializer>(
+    time: &Option<DateTime<Utc>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    /// A time serialized with `serialize_time`.
+    struct Time<'a>(&'a DateTime<Utc>);
+
+    impl serde::Serialize for Time<'_> {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serialize_time(self.0, serializer)
+        }
+    }
+
+    match time {
+        Some(time) => serializer.serialize_some(&Time(time)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Serializes an activity's `Id` with `serialize_time` if it is a time, as it usually is, and as it is otherwise.
+fn serialize_id<S: serde::Serializer>(id: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    let format = JSON_TIMESTAMPS.with(|format| format.get());
+    match DateTime::parse_from_rfc3339(id) {
+        Ok(time) if format != TimestampFormat::Rfc3339 => {
+            serialize_time(&time.with_timezone(&Utc), serializer)
+        }
+        _ => serializer.serialize_str(id),
+    }
+}
+
 /// Reads TCX data from a buffer previously defined.
 ///
 /// # Parameters
@@ -610,7 +2209,7 @@ impl TrainingCenterDatabase {
 ///
 /// # Returns
 ///
-/// `Result<TrainingCenterDatabase, serde_xml_rs::Error>`
+/// `Result<TrainingCenterDatabase, crate::tcx::Error>`
 ///
 /// # Example
 ///
@@ -619,14 +2218,10 @@ impl TrainingCenterDatabase {
 /// let mut reader = std::io::BufReader::new(file);
 /// let result = crate::tcx::read(&mut reader);
 /// ```
-///
-/// # References
-///
-/// [`serde_xml_rs::Error`](https://docs.rs/serde-xml-rs/0.5.1/serde_xml_rs/enum.Error.html)
 pub fn read<R: Read>(
     reader: &mut BufReader<R>,
-) -> Result<TrainingCenterDatabase, serde_xml_rs::Error> {
-    serde_xml_rs::from_reader(reader)
+) -> Result<TrainingCenterDatabase, crate::error::Error> {
+    read_with_options(reader, &ReadOptions::default())
 }
 
 /// Reads TCX data from the filename specified.
@@ -637,17 +2232,78 @@ pub fn read<R: Read>(
 ///
 /// # Returns
 ///
-/// `Result<TrainingCenterDatabase, serde_xml_rs::Error>`
+/// `Result<TrainingCenterDatabase, crate::tcx::Error>`
 ///
 /// # Example
 ///
 /// ```rust
 /// let my_activities = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx");
 /// ```
+pub fn read_file(filename: &str) -> Result<TrainingCenterDatabase, crate::error::Error> {
+    TrainingCenterDatabase::from_file(filename)
+}
+
+/// Reads TCX data from a buffer previously defined, as controlled by the options given.
 ///
-/// # References
+/// # Parameters
 ///
-/// [`serde_xml_rs::Error`](https://docs.rs/serde-xml-rs/0.5.1/serde_xml_rs/enum.Error.html)
-pub fn read_file(filename: &str) -> Result<TrainingCenterDatabase, serde_xml_rs::Error> {
-    TrainingCenterDatabase::from_file(filename)
+/// `reader: &mut BufReader<R>` -- A buffer of a file previously opened.
+///
+/// `options: &ReadOptions` -- How to read the data.
+///
+/// # Returns
+///
+/// `Result<TrainingCenterDatabase, crate::tcx::Error>`
+///
+/// # Example
+///
+/// ```rust
+/// let file = std::fs::File::open("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+/// let mut reader = std::io::BufReader::new(file);
+/// let options = crate::tcx::ReadOptions { original: crate::tcx::OriginalRetention::Hash, ..Default::default() };
+/// let tcx = crate::tcx::read_with_options(&mut reader, &options).unwrap();
+/// assert_eq!(tcx.original.unwrap().sha256_hex().len(), 64);
+/// ```
+pub fn read_with_options<R: Read>(
+    reader: &mut BufReader<R>,
+    options: &ReadOptions,
+) -> Result<TrainingCenterDatabase, crate::error::Error> {
+    // The document is buffered so that, on failure, it can be searched for the element at fault.
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut tcx: TrainingCenterDatabase = if options.lenient {
+        crate::lenient::deserialize(&bytes, options.trackpoint_policy)?.0
+    } else {
+        with_trackpoint_policy(options.trackpoint_policy, || {
+            serde_xml_rs::from_reader(bytes.as_slice())
+                .map_err(|err| crate::error::Error::from_deserializer(err, &bytes))
+        })?
+    };
+    tcx.original = OriginalXml::new(bytes, options.original);
+    Ok(tcx)
+}
+
+/// Reads TCX data from the filename specified, as controlled by the options given.
+///
+/// # Parameters
+///
+/// `filename: &str` -- The name of the file to be read.
+///
+/// `options: &ReadOptions` -- How to read the data.
+///
+/// # Returns
+///
+/// `Result<TrainingCenterDatabase, crate::tcx::Error>`
+///
+/// # Example
+///
+/// ```rust
+/// let options = crate::tcx::ReadOptions::default();
+/// let my_activities = crate::tcx::read_file_with_options("tests/20210119_run_garmin_fenix6.tcx", &options);
+/// ```
+pub fn read_file_with_options(
+    filename: &str,
+    options: &ReadOptions,
+) -> Result<TrainingCenterDatabase, crate::error::Error> {
+    crate::storage::read_from(&mut std::path::Path::new(filename), options)
 }
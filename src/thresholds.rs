@@ -0,0 +1,243 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tracking estimated Functional Threshold Power (FTP) and Lactate Threshold Heart Rate (LTHR) over a season. Each
+//! activity's best 20 and 60 minute efforts give an estimate: Coggan's 95% of 20 minute power or all of 60 minute
+//! power for FTP, and the best 20 minute average heart rate for LTHR, as in Friel's field test. Since most activities
+//! are not maximal efforts, the season estimate is the highest of the estimates within a trailing window.
+
+use chrono::{DateTime, Duration, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::tcx::{Activity, Trackpoint};
+
+/// Seconds in the shorter of the two efforts.
+const SHORT_EFFORT_SECONDS: f64 = 20.0 * 60.0;
+
+/// Seconds in the longer of the two efforts.
+const LONG_EFFORT_SECONDS: f64 = 60.0 * 60.0;
+
+/// Options for `season_thresholds`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ThresholdOptions {
+    /// How far back, in days, an effort still counts towards the season estimate. Defaults to 42.
+    pub window_days: u32,
+
+    /// How much, in percent, the season estimate has to move from the last flagged value before it is flagged as a
+    /// change. Defaults to 3.
+    pub min_change_percent: f64,
+}
+
+impl Default for ThresholdOptions {
+    fn default() -> Self {
+        ThresholdOptions {
+            window_days: 42,
+            min_change_percent: 3.0,
+        }
+    }
+}
+
+/// The threshold estimates as of one activity.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ThresholdEstimate {
+    /// The `Id` of the activity.
+    pub activity_id: String,
+
+    /// When the activity started.
+    pub time: DateTime<Utc>,
+
+    /// The FTP estimated from this activity alone, in Watts, if it has 20 minutes of power.
+    pub effort_ftp: Option<f64>,
+
+    /// The LTHR estimated from this activity alone, in beats per minute, if it has 20 minutes of heart rate.
+    pub effort_lthr: Option<f64>,
+
+    /// The season's FTP estimate as of this activity, in Watts.
+    pub ftp: Option<f64>,
+
+    /// The season's LTHR estimate as of this activity, in beats per minute.
+    pub lthr: Option<f64>,
+
+    /// True if the FTP estimate has likely changed since it was last flagged. The first estimate is never flagged.
+    pub ftp_changed: bool,
+
+    /// True if the LTHR estimate has likely changed since it was last flagged. The first estimate is never flagged.
+    pub lthr_changed: bool,
+}
+
+impl Activity {
+    /// Estimates Functional Threshold Power from the best 20 and 60 minute average power. See the module description.
+    ///
+    /// # Returns
+    ///
+    /// `Option<f64>` -- The estimate in Watts, or `None` if the activity has less than 20 minutes of power.
+    pub fn estimated_ftp(&self) -> Option<f64> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        let short = best_average(&points, SHORT_EFFORT_SECONDS, watts)?;
+        let long = best_average(&points, LONG_EFFORT_SECONDS, watts).unwrap_or(0.0);
+        Some(f64::max(short * 0.95, long))
+    }
+
+    /// Estimates Lactate Threshold Heart Rate from the best 20 minute average heart rate.
+    ///
+    /// # Returns
+    ///
+    /// `Option<f64>` -- The estimate in beats per minute, or `None` if the activity has less than 20 minutes of heart
+    /// rate.
+    pub fn estimated_lthr(&self) -> Option<f64> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        best_average(&points, SHORT_EFFORT_SECONDS, |point| {
+            point.heart_rate.as_ref().map(|hr| hr.value)
+        })
+    }
+}
+
+/// Tracks the estimated FTP and LTHR across a season of activities, flagging where they likely changed.
+///
+/// # Parameters
+///
+/// `activities: &[Activity]` -- The activities, in any order. Those without a start time are left out.
+///
+/// `options: &ThresholdOptions` -- The trailing window and the size of a change worth flagging.
+///
+/// # Returns
+///
+/// `Vec<ThresholdEstimate>` -- One per activity, oldest first.
+///
+/// # Example
+///
+/// ```rust
+/// use crate::tcx::thresholds::{season_thresholds, ThresholdOptions};
+///
+/// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+/// let activities = tcx.activities.unwrap().activities;
+/// let season = season_thresholds(&activities, &ThresholdOptions::default());
+/// assert_eq!(season[0].ftp, activities[0].estimated_ftp());
+/// assert!(!season[0].ftp_changed);
+/// ```
+pub fn season_thresholds(
+    activities: &[Activity],
+    options: &ThresholdOptions,
+) -> Vec<ThresholdEstimate> {
+    let mut estimates: Vec<ThresholdEstimate> = activities
+        .iter()
+        .filter_map(|activity| {
+            Some(ThresholdEstimate {
+                activity_id: activity.id.clone(),
+                time: activity.start_time()?,
+                effort_ftp: activity.estimated_ftp(),
+                effort_lthr: activity.estimated_lthr(),
+                ftp: None,
+                lthr: None,
+                ftp_changed: false,
+                lthr_changed: false,
+            })
+        })
+        .collect();
+    estimates.sort_by_key(|estimate| estimate.time);
+
+    let window = Duration::days(options.window_days as i64);
+    let mut ftp_baseline = None;
+    let mut lthr_baseline = None;
+    for i in 0..estimates.len() {
+        let time = estimates[i].time;
+        let recent = estimates[..=i]
+            .iter()
+            .filter(|earlier| time - earlier.time <= window);
+        let ftp = recent
+            .clone()
+            .filter_map(|earlier| earlier.effort_ftp)
+            .reduce(f64::max);
+        let lthr = recent
+            .filter_map(|earlier| earlier.effort_lthr)
+            .reduce(f64::max);
+        let estimate = &mut estimates[i];
+        estimate.ftp = ftp;
+        estimate.lthr = lthr;
+        estimate.ftp_changed = changed(&mut ftp_baseline, ftp, options.min_change_percent);
+        estimate.lthr_changed = changed(&mut lthr_baseline, lthr, options.min_change_percent);
+    }
+    estimates
+}
+
+/// Compares an estimate with the last flagged value, moving the baseline when the difference is large enough. The
+/// first estimate sets the baseline without being flagged.
+fn changed(baseline: &mut Option<f64>, estimate: Option<f64>, min_change_percent: f64) -> bool {
+    match (*baseline, estimate) {
+        (None, Some(estimate)) => {
+            *baseline = Some(estimate);
+            false
+        }
+        (Some(previous), Some(estimate))
+            if (estimate - previous).abs() / previous * 100.0 >= min_change_percent =>
+        {
+            *baseline = Some(estimate);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The power of a Trackpoint, from the TPX extension.
+fn watts(point: &Trackpoint) -> Option<f64> {
+    point
+        .extensions
+        .as_ref()
+        .and_then(|ext| ext.tpx.as_ref())
+        .and_then(|tpx| tpx.watts)
+        .map(f64::from)
+}
+
+/// Finds the highest time-weighted average of a value over any stretch of at least `seconds`. Each value is held
+/// over the interval leading up to its Trackpoint, and intervals without a value are left out of the time.
+fn best_average(
+    points: &[&Trackpoint],
+    seconds: f64,
+    value: impl Fn(&Trackpoint) -> Option<f64>,
+) -> Option<f64> {
+    // Running totals of time and value times time, up to each Trackpoint.
+    let mut times = vec![0.0];
+    let mut sums = vec![0.0];
+    for pair in points.windows(2) {
+        let elapsed = crate::geo::seconds_between(pair[0], pair[1]).max(0.0);
+        let (time, sum) = match value(pair[1]) {
+            Some(value) => (elapsed, value * elapsed),
+            None => (0.0, 0.0),
+        };
+        times.push(times.last().unwrap() + time);
+        sums.push(sums.last().unwrap() + sum);
+    }
+
+    let mut best: Option<f64> = None;
+    let mut start = 0;
+    for end in 0..times.len() {
+        // Shortest stretch ending here that is long enough.
+        while start < end && times[end] - times[start + 1] >= seconds {
+            start += 1;
+        }
+        let span = times[end] - times[start];
+        if span >= seconds && span > 0.0 {
+            let average = (sums[end] - sums[start]) / span;
+            best = Some(best.map_or(average, |best| best.max(average)));
+        }
+    }
+    best
+}
@@ -0,0 +1,152 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Telling loops, out-and-backs and point-to-point routes apart, as a tag for route libraries. A route that ends
+//! near where it started is a loop unless its way back retraces its way out, in which case it is an out-and-back.
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::haversine_distance;
+use crate::tcx::{Activity, Course, Position};
+
+/// The farthest, in meters, the end may be from the start for the route to count as returning to it, for short
+/// routes. Longer routes may end up to `CLOSED_FRACTION` of their length away.
+const CLOSED_METERS: f64 = 200.0;
+
+/// The fraction of the route's length the end may be from the start for the route to count as returning to it.
+const CLOSED_FRACTION: f64 = 0.05;
+
+/// How close, in meters, the way back must pass to the way out to count as retracing it.
+const OVERLAP_METERS: f64 = 30.0;
+
+/// The fraction of the way back that must retrace the way out for an out-and-back.
+const OUT_AND_BACK_OVERLAP: f64 = 0.6;
+
+/// The most positions compared when measuring overlap. Longer routes are sampled evenly by distance.
+const MAX_SAMPLES: usize = 2000;
+
+/// The overall shape of a route.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteTopology {
+    /// Ends near the start, by a different way than it went out.
+    Loop,
+    /// Ends near the start, by the way it went out.
+    OutAndBack,
+    /// Ends away from the start.
+    PointToPoint,
+}
+
+/// Classifies a route by whether it ends near its start and, if so, how much of its second half retraces its
+/// first.
+///
+/// # Parameters
+///
+/// `positions: &[&Position]` -- The positions along the route, in order.
+///
+/// # Returns
+///
+/// `Option<RouteTopology>` -- The shape, or `None` if the route does not cover any distance.
+///
+/// # Example
+///
+/// ```rust
+/// use crate::tcx::topology::{classify_route, RouteTopology};
+/// use crate::tcx::Position;
+///
+/// let line: Vec<Position> = (0..=100).map(|i| Position { latitude: 45.0 + i as f64 * 1e-4, longitude: 7.0 }).collect();
+/// let back: Vec<&Position> = line.iter().chain(line.iter().rev()).collect();
+/// assert_eq!(classify_route(&back), Some(RouteTopology::OutAndBack));
+/// let there: Vec<&Position> = line.iter().collect();
+/// assert_eq!(classify_route(&there), Some(RouteTopology::PointToPoint));
+/// ```
+pub fn classify_route(positions: &[&Position]) -> Option<RouteTopology> {
+    let mut along = Vec::with_capacity(positions.len());
+    let mut length = 0.0;
+    for (i, position) in positions.iter().enumerate() {
+        if i > 0 {
+            length += haversine_distance(positions[i - 1], position);
+        }
+        along.push(length);
+    }
+    if length <= 0.0 {
+        return None;
+    }
+
+    let gap = haversine_distance(positions[0], positions[positions.len() - 1]);
+    if gap > CLOSED_METERS.max(CLOSED_FRACTION * length) {
+        return Some(RouteTopology::PointToPoint);
+    }
+
+    // Sample evenly by distance so that stops and dense recording do not weigh more than the rest of the route.
+    let spacing = length / MAX_SAMPLES as f64;
+    let mut samples: Vec<(&Position, f64)> = Vec::new();
+    for (position, distance) in positions.iter().zip(&along) {
+        if samples
+            .last()
+            .is_none_or(|(_, last)| distance - last >= spacing)
+        {
+            samples.push((position, *distance));
+        }
+    }
+    let (out, back): (Vec<_>, Vec<_>) = samples
+        .iter()
+        .partition(|(_, distance)| *distance <= length / 2.0);
+    if back.is_empty() {
+        return Some(RouteTopology::Loop);
+    }
+    let retraced = back
+        .iter()
+        .filter(|(position, _)| {
+            out.iter()
+                .any(|(other, _)| haversine_distance(position, other) <= OVERLAP_METERS)
+        })
+        .count();
+    if retraced as f64 >= OUT_AND_BACK_OVERLAP * back.len() as f64 {
+        Some(RouteTopology::OutAndBack)
+    } else {
+        Some(RouteTopology::Loop)
+    }
+}
+
+impl Activity {
+    /// Classifies the activity's route. See `classify_route`.
+    pub fn route_topology(&self) -> Option<RouteTopology> {
+        let positions: Vec<&Position> = self
+            .trackpoints()
+            .filter_map(|point| point.position.as_ref())
+            .collect();
+        classify_route(&positions)
+    }
+}
+
+impl Course {
+    /// Classifies the course's route. See `classify_route`.
+    pub fn route_topology(&self) -> Option<RouteTopology> {
+        let positions: Vec<&Position> = self
+            .tracks
+            .iter()
+            .flatten()
+            .flat_map(|track| track.trackpoints.iter())
+            .filter_map(|point| point.position.as_ref())
+            .collect();
+        classify_route(&positions)
+    }
+}
@@ -0,0 +1,159 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Rolling activities up into weekly or monthly totals, the data behind training-volume charts.
+
+use chrono::{Datelike, Duration, NaiveDate};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::tcx::Activity;
+
+/// The length of a rollup period.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    /// Weeks starting on Monday, as in ISO 8601.
+    Week,
+    /// Calendar months.
+    Month,
+}
+
+impl Period {
+    /// Returns the first day of the period containing `day`.
+    fn start(self, day: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Week => day - Duration::days(day.weekday().num_days_from_monday() as i64),
+            Period::Month => day.with_day(1).unwrap(),
+        }
+    }
+
+    /// Returns the first day of the period after the one starting on `start`.
+    fn next(self, start: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Week => start + Duration::days(7),
+            Period::Month if start.month() == 12 => {
+                NaiveDate::from_ymd_opt(start.year() + 1, 1, 1).unwrap()
+            }
+            Period::Month => NaiveDate::from_ymd_opt(start.year(), start.month() + 1, 1).unwrap(),
+        }
+    }
+}
+
+/// The totals for one period.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rollup {
+    /// The first day of the period (a Monday for weeks), in UTC.
+    pub period_start: NaiveDate,
+
+    /// The number of activities that started in the period.
+    pub sessions: usize,
+
+    /// Total distance, in meters.
+    pub distance_meters: f64,
+
+    /// Total time from first to last Trackpoint, in seconds.
+    pub elapsed_seconds: f64,
+
+    /// Total time spent moving, in seconds.
+    pub moving_seconds: f64,
+
+    /// Total elevation gained, in meters.
+    pub elevation_gain_meters: f64,
+
+    /// Total Training Stress Score of the activities with enough power data, or `None` if no FTP was given or none
+    /// had it.
+    pub training_stress_score: Option<f64>,
+}
+
+impl Rollup {
+    fn empty(period_start: NaiveDate) -> Self {
+        Rollup {
+            period_start,
+            sessions: 0,
+            distance_meters: 0.0,
+            elapsed_seconds: 0.0,
+            moving_seconds: 0.0,
+            elevation_gain_meters: 0.0,
+            training_stress_score: None,
+        }
+    }
+}
+
+/// Totals the activities by week or month. Each activity counts towards the period containing its start time (in
+/// UTC); activities without a start time are left out. Periods without activities between the first and last are
+/// included with zero totals, so the result can be charted directly.
+///
+/// # Parameters
+///
+/// `activities: &[Activity]` -- The activities, in any order.
+///
+/// `period: Period` -- Whether to total by week or by month.
+///
+/// `ftp: Option<f64>` -- The athlete's Functional Threshold Power in Watts, needed for the Training Stress Score.
+///
+/// # Returns
+///
+/// `Vec<Rollup>` -- One per period, oldest first. Empty if no activity has a start time.
+///
+/// # Example
+///
+/// ```rust
+/// use crate::tcx::training_log::{rollup, Period};
+///
+/// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+/// let activities = tcx.activities.unwrap().activities;
+/// let weeks = rollup(&activities, Period::Week, Some(250.0));
+/// assert_eq!(weeks.len(), 1);
+/// assert_eq!(weeks[0].period_start.to_string(), "2021-03-08");
+/// assert_eq!(weeks[0].sessions, 1);
+/// assert!(weeks[0].training_stress_score.unwrap() > 0.0);
+/// ```
+pub fn rollup(activities: &[Activity], period: Period, ftp: Option<f64>) -> Vec<Rollup> {
+    let mut dated: Vec<(NaiveDate, &Activity)> = activities
+        .iter()
+        .filter_map(|activity| {
+            let start = activity.start_time()?;
+            Some((period.start(start.date_naive()), activity))
+        })
+        .collect();
+    dated.sort_by_key(|(start, _)| *start);
+
+    let mut rollups: Vec<Rollup> = Vec::new();
+    for (start, activity) in dated {
+        while rollups.last().is_none_or(|last| last.period_start < start) {
+            let next = match rollups.last() {
+                Some(last) => period.next(last.period_start),
+                None => start,
+            };
+            rollups.push(Rollup::empty(next));
+        }
+        let totals = rollups.last_mut().unwrap();
+        let summary = activity.summary();
+        totals.sessions += 1;
+        totals.distance_meters += summary.distance_meters;
+        totals.elapsed_seconds += summary.elapsed_seconds;
+        totals.moving_seconds += summary.moving_seconds;
+        totals.elevation_gain_meters += summary.elevation_gain_meters;
+        if let Some(score) = ftp.and_then(|ftp| activity.training_stress_score(ftp)) {
+            *totals.training_stress_score.get_or_insert(0.0) += score;
+        }
+    }
+    rollups
+}
@@ -0,0 +1,377 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Checking a document against the string-length and numeric-range restrictions of the TCX schema before it is
+//! written, so that problems surface as descriptive errors rather than as files that devices reject.
+
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::tcx::{
+    Activity, ActivityLap, CourseLap, Ns3Lx, Position, Trackpoint, TrainingCenterDatabase,
+    WorkoutStep,
+};
+
+/// The longest course, workout and workout step name the schema allows (`RestrictedToken_t`).
+pub const MAX_NAME_CHARS: usize = 15;
+
+/// The longest course point name the schema allows (`CoursePointName_t`).
+pub const MAX_COURSE_POINT_NAME_CHARS: usize = 10;
+
+/// The highest cadence the schema allows (`CadenceValue_t`), in revolutions or steps per minute.
+pub const MAX_CADENCE: u8 = 254;
+
+/// The highest workout step ID the schema allows (`StepId_t`).
+pub const MAX_STEP_ID: u8 = 20;
+
+/// What is wrong with a value.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// A required name is empty.
+    Empty,
+    /// A required element is absent.
+    Missing,
+    /// A string is longer than allowed.
+    TooLong { length: usize, max: usize },
+    /// A number is outside the allowed range.
+    OutOfRange { value: f64, min: f64, max: f64 },
+    /// A name that must be unique is already used by an earlier element.
+    Duplicate { name: String },
+}
+
+/// A value that breaks a schema restriction, along with where it is.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// Where the value is, such as `Activities/Activity[0]/Lap[2]/Track[0]/Trackpoint[17]/Cadence`. Indices start at
+    /// zero.
+    pub path: String,
+
+    /// What is wrong.
+    pub problem: Problem,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.problem {
+            Problem::Empty => write!(f, "{} is empty", self.path),
+            Problem::Missing => write!(f, "{} is missing", self.path),
+            Problem::TooLong { length, max } => write!(
+                f,
+                "{} is {} characters long, but at most {} are allowed",
+                self.path, length, max
+            ),
+            Problem::OutOfRange { value, min, max } => write!(
+                f,
+                "{} is {}, outside the allowed range of {} to {}",
+                self.path, value, min, max
+            ),
+            Problem::Duplicate { name } => {
+                write!(f, "{} \"{}\" is already used", self.path, name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Violation {}
+
+impl TrainingCenterDatabase {
+    /// Checks the document against the restrictions of the TCX schema: course, workout and step names of 1 to 15
+    /// characters, unique course names, course point names of at most 10 and a position for every course point, cadences of at most 254, heart rates of 1
+    /// to 255 beats per minute, latitudes and longitudes within range, step ids of 1 to 20 and repetitions of 2 to
+    /// 99. The schema does not limit `Notes`, but some devices do, so a limit can be given for them too.
+    ///
+    /// # Parameters
+    ///
+    /// `max_notes_chars: Option<usize>` -- The longest `Notes` allowed, if any.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<Violation>` -- Every value that breaks a restriction, in document order. Empty if the document is valid.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let mut tcx = crate::tcx::read_file("tests/20210601_course_lake_loop.tcx").unwrap();
+    /// assert!(tcx.validate(None).is_empty());
+    /// tcx.courses.as_mut().unwrap().courses[0].name = "Lake loop the long way round".to_string();
+    /// assert_eq!(tcx.validate(None)[0].path, "Courses/Course[0]/Name");
+    /// ```
+    pub fn validate(&self, max_notes_chars: Option<usize>) -> Vec<Violation> {
+        let mut checker = Checker {
+            max_notes_chars,
+            violations: Vec::new(),
+        };
+        if let Some(activities) = &self.activities {
+            for (i, activity) in activities.activities.iter().enumerate() {
+                checker.activity(&format!("Activities/Activity[{}]", i), activity);
+            }
+            for (i, session) in activities.multi_sport_sessions.iter().enumerate() {
+                let path = format!("Activities/MultiSportSession[{}]", i);
+                checker.notes(&path, &session.notes);
+                checker.activity(
+                    &format!("{}/FirstSport/Activity", path),
+                    &session.first_sport.activity,
+                );
+                for (j, next) in session.next_sports.iter().enumerate() {
+                    let path = format!("{}/NextSport[{}]", path, j);
+                    if let Some(transition) = &next.transition {
+                        checker.lap(&format!("{}/Transition", path), transition);
+                    }
+                    checker.activity(&format!("{}/Activity", path), &next.activity);
+                }
+            }
+        }
+        if let Some(workouts) = &self.workouts {
+            for (i, workout) in workouts.workouts.iter().enumerate() {
+                let path = format!("Workouts/Workout[{}]", i);
+                checker.name(&format!("{}/Name", path), &workout.name, MAX_NAME_CHARS);
+                checker.steps(&path, &workout.steps);
+                checker.notes(&path, &workout.notes);
+            }
+        }
+        if let Some(courses) = &self.courses {
+            for (i, course) in courses.courses.iter().enumerate() {
+                let path = format!("Courses/Course[{}]", i);
+                checker.name(&format!("{}/Name", path), &course.name, MAX_NAME_CHARS);
+                let name = course.name.trim();
+                if !name.is_empty() && courses.courses[..i].iter().any(|c| c.name.trim() == name) {
+                    checker.report(
+                        format!("{}/Name", path),
+                        Problem::Duplicate {
+                            name: name.to_string(),
+                        },
+                    );
+                }
+                for (j, lap) in course.laps.iter().enumerate() {
+                    checker.course_lap(&format!("{}/Lap[{}]", path, j), lap);
+                }
+                for (j, track) in course.tracks.iter().flatten().enumerate() {
+                    for (k, point) in track.trackpoints.iter().enumerate() {
+                        checker
+                            .trackpoint(&format!("{}/Track[{}]/Trackpoint[{}]", path, j, k), point);
+                    }
+                }
+                checker.notes(&path, &course.notes);
+                for (j, point) in course.course_points.iter().enumerate() {
+                    let point_path = format!("{}/CoursePoint[{}]", path, j);
+                    if let Some(name) = &point.name {
+                        checker.length(
+                            &format!("{}/Name", point_path),
+                            name,
+                            MAX_COURSE_POINT_NAME_CHARS,
+                        );
+                    }
+                    let position_path = format!("{}/Position", point_path);
+                    if point.position.is_none() {
+                        checker.report(position_path, Problem::Missing);
+                    } else {
+                        checker.position(&position_path, &point.position);
+                    }
+                    checker.notes(&point_path, &point.notes);
+                }
+            }
+        }
+        checker.violations
+    }
+}
+
+impl Activity {
+    /// Checks the activity against the restrictions of the TCX schema. See `TrainingCenterDatabase::validate`.
+    ///
+    /// # Parameters
+    ///
+    /// `max_notes_chars: Option<usize>` -- The longest `Notes` allowed, if any.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<Violation>` -- Every value that breaks a restriction, with paths starting at the activity's laps.
+    pub fn validate(&self, max_notes_chars: Option<usize>) -> Vec<Violation> {
+        let mut checker = Checker {
+            max_notes_chars,
+            violations: Vec::new(),
+        };
+        checker.activity("", self);
+        checker.violations
+    }
+}
+
+/// Collects violations while walking the document.
+struct Checker {
+    max_notes_chars: Option<usize>,
+    violations: Vec<Violation>,
+}
+
+impl Checker {
+    fn report(&mut self, path: String, problem: Problem) {
+        self.violations.push(Violation { path, problem });
+    }
+
+    fn length(&mut self, path: &str, value: &str, max: usize) {
+        let length = value.chars().count();
+        if length > max {
+            self.report(path.to_string(), Problem::TooLong { length, max });
+        }
+    }
+
+    fn name(&mut self, path: &str, name: &str, max: usize) {
+        if name.trim().is_empty() {
+            self.report(path.to_string(), Problem::Empty);
+        } else {
+            self.length(path, name, max);
+        }
+    }
+
+    fn notes(&mut self, path: &str, notes: &Option<String>) {
+        if let (Some(notes), Some(max)) = (notes, self.max_notes_chars) {
+            self.length(&format!("{}/Notes", path), notes, max);
+        }
+    }
+
+    fn range(&mut self, path: &str, value: Option<f64>, min: f64, max: f64) {
+        if let Some(value) = value {
+            if !(min..=max).contains(&value) {
+                self.report(path.to_string(), Problem::OutOfRange { value, min, max });
+            }
+        }
+    }
+
+    fn cadence(&mut self, path: &str, cadence: Option<u8>) {
+        self.range(path, cadence.map(f64::from), 0.0, MAX_CADENCE as f64);
+    }
+
+    fn heart_rate(&mut self, path: &str, heart_rate: Option<f64>) {
+        self.range(path, heart_rate.map(f64::round), 1.0, 255.0);
+    }
+
+    /// Checks a position, with `path` leading to the position element itself, such as `.../Position` or
+    /// `.../BeginPosition`.
+    fn position(&mut self, path: &str, position: &Option<Position>) {
+        if let Some(position) = position {
+            self.range(
+                &format!("{}/LatitudeDegrees", path),
+                Some(position.latitude),
+                -90.0,
+                90.0,
+            );
+            self.range(
+                &format!("{}/LongitudeDegrees", path),
+                Some(position.longitude),
+                -180.0,
+                180.0,
+            );
+        }
+    }
+
+    /// Checks an activity. Paths are relative to the activity when `path` is empty.
+    fn activity(&mut self, path: &str, activity: &Activity) {
+        let prefix = if path.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", path)
+        };
+        for (i, lap) in activity.laps.iter().enumerate() {
+            self.lap(&format!("{}Lap[{}]", prefix, i), lap);
+        }
+        if let (Some(notes), Some(max)) = (&activity.notes, self.max_notes_chars) {
+            self.length(&format!("{}Notes", prefix), notes, max);
+        }
+    }
+
+    fn lap(&mut self, path: &str, lap: &ActivityLap) {
+        self.heart_rate(
+            &format!("{}/AverageHeartRateBpm", path),
+            lap.average_heart_rate,
+        );
+        self.heart_rate(
+            &format!("{}/MaximumHeartRateBpm", path),
+            lap.maximum_heart_rate,
+        );
+        self.cadence(&format!("{}/Cadence", path), lap.cadence);
+        for (i, track) in lap.tracks.iter().enumerate() {
+            for (j, point) in track.trackpoints.iter().enumerate() {
+                self.trackpoint(&format!("{}/Track[{}]/Trackpoint[{}]", path, i, j), point);
+            }
+        }
+        self.notes(path, &lap.notes);
+        if let Some(lx) = lap.extensions.as_ref().and_then(|ext| ext.lx.as_ref()) {
+            self.lx(&format!("{}/Extensions/LX", path), lx);
+        }
+    }
+
+    fn course_lap(&mut self, path: &str, lap: &CourseLap) {
+        self.position(&format!("{}/BeginPosition", path), &lap.begin_position);
+        self.position(&format!("{}/EndPosition", path), &lap.end_position);
+        self.heart_rate(
+            &format!("{}/AverageHeartRateBpm", path),
+            lap.average_heart_rate,
+        );
+        self.heart_rate(
+            &format!("{}/MaximumHeartRateBpm", path),
+            lap.maximum_heart_rate,
+        );
+        self.cadence(&format!("{}/Cadence", path), lap.cadence);
+    }
+
+    fn trackpoint(&mut self, path: &str, point: &Trackpoint) {
+        self.position(&format!("{}/Position", path), &point.position);
+        self.heart_rate(
+            &format!("{}/HeartRateBpm", path),
+            point.heart_rate.as_ref().map(|hr| hr.value),
+        );
+        self.cadence(&format!("{}/Cadence", path), point.cadence);
+        if let Some(tpx) = point.extensions.as_ref().and_then(|ext| ext.tpx.as_ref()) {
+            self.cadence(
+                &format!("{}/Extensions/TPX/RunCadence", path),
+                tpx.run_cadence,
+            );
+        }
+    }
+
+    fn lx(&mut self, path: &str, lx: &Ns3Lx) {
+        self.cadence(&format!("{}/MaxBikeCadence", path), lx.max_bike_cadence);
+        self.cadence(&format!("{}/AvgRunCadence", path), lx.avg_run_cadence);
+        self.cadence(&format!("{}/MaxRunCadence", path), lx.max_run_cadence);
+    }
+
+    fn steps(&mut self, path: &str, steps: &[WorkoutStep]) {
+        for (i, step) in steps.iter().enumerate() {
+            let path = format!("{}/Step[{}]", path, i);
+            self.range(
+                &format!("{}/StepId", path),
+                Some(step.step_id as f64),
+                1.0,
+                MAX_STEP_ID as f64,
+            );
+            if let Some(name) = &step.name {
+                self.length(&format!("{}/Name", path), name, MAX_NAME_CHARS);
+            }
+            if step.is_repeat() {
+                self.range(
+                    &format!("{}/Repetitions", path),
+                    step.repetitions.map(f64::from),
+                    2.0,
+                    99.0,
+                );
+                self.steps(&format!("{}/Child", path), &step.children);
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Directory watching, for sync daemons that ingest TCX files as they are dropped into a folder.
+//! Requires the `watch` feature.
+
+use notify::event::{AccessKind, AccessMode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::error::Error;
+use crate::tcx::{read_file, TrainingCenterDatabase};
+
+/// Keeps a directory watch alive. Watching stops when this is dropped.
+pub struct DirWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+/// Watches a directory and parses every TCX file that is created, modified or closed after writing in it, passing
+/// each successfully parsed file to the callback. Files that are still being written usually fail to parse; they are
+/// delivered once a later change event finds them complete. A file is delivered again only if its modification time
+/// changes, and a file that failed to parse is not read again until its modification time or size changes.
+///
+/// # Parameters
+///
+/// `path: &Path` -- The directory to watch. Subdirectories are not watched.
+///
+/// `callback: F` -- Called, on a background thread, with the path and contents of each new or changed TCX file.
+///
+/// # Returns
+///
+/// `notify::Result<DirWatcher>` -- A handle that keeps the watch running, or an error if the directory cannot be watched.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// let _watcher = crate::tcx::watch::watch_dir(std::path::Path::new("inbox"), |path, database| {
+///     println!("{}: {} activities", path.display(), database.activities.map_or(0, |a| a.activities.len()));
+/// })
+/// .unwrap();
+/// std::thread::park();
+/// ```
+pub fn watch_dir<F>(path: &Path, callback: F) -> notify::Result<DirWatcher>
+where
+    F: FnMut(&Path, TrainingCenterDatabase) + Send + 'static,
+{
+    watch_dir_with(path, read_file, callback)
+}
+
+/// `watch_dir`, reading each file with `read`.
+pub(crate) fn watch_dir_with<R, F>(
+    path: &Path,
+    mut read: R,
+    mut callback: F,
+) -> notify::Result<DirWatcher>
+where
+    R: FnMut(&str) -> Result<TrainingCenterDatabase, Error> + Send + 'static,
+    F: FnMut(&Path, TrainingCenterDatabase) + Send + 'static,
+{
+    let mut delivered: HashMap<PathBuf, SystemTime> = HashMap::new();
+    let mut failed: HashMap<PathBuf, (SystemTime, u64)> = HashMap::new();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let event = match event {
+            Ok(event) => event,
+            Err(_) => return,
+        };
+        // Reading a file raises access events of its own, so only a close after writing counts.
+        if !matches!(
+            event.kind,
+            EventKind::Create(_)
+                | EventKind::Modify(_)
+                | EventKind::Access(AccessKind::Close(AccessMode::Write))
+        ) {
+            return;
+        }
+
+        for file in event.paths {
+            let is_tcx = file
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("tcx"));
+            let (modified, size) = match std::fs::metadata(&file)
+                .and_then(|meta| meta.modified().map(|modified| (modified, meta.len())))
+            {
+                Ok(stamp) => stamp,
+                Err(_) => continue,
+            };
+            if !is_tcx
+                || delivered.get(&file) == Some(&modified)
+                || failed.get(&file) == Some(&(modified, size))
+            {
+                continue;
+            }
+
+            let name = match file.to_str() {
+                Some(name) => name,
+                None => continue,
+            };
+            match read(name) {
+                Ok(database) => {
+                    failed.remove(&file);
+                    delivered.insert(file.clone(), modified);
+                    callback(&file, database);
+                }
+                Err(_) => {
+                    failed.insert(file.clone(), (modified, size));
+                }
+            }
+        }
+    })?;
+    watcher.watch(path, RecursiveMode::NonRecursive)?;
+    Ok(DirWatcher { _watcher: watcher })
+}
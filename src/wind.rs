@@ -0,0 +1,128 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Wind: combines a caller-supplied wind field with the direction of travel to split the wind into headwind and
+//! crosswind components, and to estimate the speed through the air at each Trackpoint. The wind data itself comes
+//! from the caller through the `WindField` trait, so a weather API, a station log or a constant can be used.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::{bearings, speeds};
+use crate::tcx::{Activity, Position, Trackpoint};
+
+/// The wind at a place and time.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Wind {
+    /// Wind speed, in meters per second.
+    pub speed_meters_per_second: f64,
+
+    /// The direction the wind is blowing from, in degrees clockwise from true north, as in weather reports.
+    pub from_degrees: f64,
+}
+
+/// Supplies the wind at a place and time. Implement this over whatever weather data is available.
+pub trait WindField {
+    /// Returns the wind at `position` at `time`, or `None` if it is not known.
+    fn wind_at(&self, time: DateTime<Utc>, position: &Position) -> Option<Wind>;
+}
+
+/// A constant wind, the same everywhere and at all times.
+impl WindField for Wind {
+    fn wind_at(&self, _time: DateTime<Utc>, _position: &Position) -> Option<Wind> {
+        Some(*self)
+    }
+}
+
+/// The wind's effect at one Trackpoint.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WindAdjustedPoint {
+    /// Time of the Trackpoint.
+    pub time: DateTime<Utc>,
+
+    /// Direction of travel, in degrees clockwise from true north.
+    pub bearing_degrees: f64,
+
+    /// Component of the wind against the direction of travel, in meters per second. Negative for a tailwind.
+    pub headwind_meters_per_second: f64,
+
+    /// Component of the wind across the direction of travel, in meters per second. Positive when the wind comes
+    /// from the right.
+    pub crosswind_meters_per_second: f64,
+
+    /// Speed over the ground, in meters per second.
+    pub ground_speed: f64,
+
+    /// Speed through the air (ground speed plus headwind), in meters per second.
+    pub air_speed: f64,
+}
+
+impl Activity {
+    /// Combines the wind field with the direction of travel at each Trackpoint. The bearing at a Trackpoint is
+    /// taken from the last position at least a couple of meters behind it.
+    ///
+    /// # Parameters
+    ///
+    /// `wind: &W` -- The wind provider.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<WindAdjustedPoint>` -- One entry per Trackpoint with a position, a bearing, a speed and a known wind,
+    /// in time order.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// let wind = crate::tcx::wind::Wind { speed_meters_per_second: 5.0, from_degrees: 270.0 };
+    /// for point in activity.wind_adjusted_speeds(&wind) {
+    ///     assert!((point.air_speed - point.ground_speed - point.headwind_meters_per_second).abs() < 1e-9);
+    /// }
+    /// ```
+    pub fn wind_adjusted_speeds<W: WindField + ?Sized>(&self, wind: &W) -> Vec<WindAdjustedPoint> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        let bearings = bearings(&points);
+        let speeds = speeds(&points);
+        let mut result = Vec::new();
+        for ((point, bearing), speed) in points.iter().zip(bearings).zip(speeds) {
+            let (position, bearing, ground_speed) = match (&point.position, bearing, speed) {
+                (Some(position), Some(bearing), Some(speed)) => (position, bearing, speed),
+                _ => continue,
+            };
+            let wind = match wind.wind_at(point.time, position) {
+                Some(wind) => wind,
+                None => continue,
+            };
+            let relative = (wind.from_degrees - bearing).to_radians();
+            let headwind = wind.speed_meters_per_second * relative.cos();
+            result.push(WindAdjustedPoint {
+                time: point.time,
+                bearing_degrees: bearing,
+                headwind_meters_per_second: headwind,
+                crosswind_meters_per_second: wind.speed_meters_per_second * relative.sin(),
+                ground_speed,
+                air_speed: ground_speed + headwind,
+            });
+        }
+        result
+    }
+}
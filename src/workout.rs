@@ -0,0 +1,493 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! A simple, platform-neutral description of a structured workout, along with exporters for the text formats
+//! understood by indoor trainer software.
+
+use serde_derive::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::Write;
+
+use crate::geo::seconds_between;
+use crate::storage::TcxSink;
+use crate::tcx::{Activity, Intensity, StepDuration, Workout, WorkoutStep};
+use crate::validate::MAX_STEP_ID;
+
+/// Length, in seconds, of the steps used when turning a recorded power profile into a workout.
+const POWER_PROFILE_STEP_SECONDS: f64 = 30.0;
+
+/// Intensity, as a percentage of FTP, below which a step is written to TCX as `Resting` (the top of the active
+/// recovery zone).
+const RESTING_PERCENT_FTP: f64 = 55.0;
+
+/// Intensities given to TCX steps, which carry no power target, when reading them into a structured workout.
+const TCX_ACTIVE_PERCENT_FTP: f64 = 75.0;
+const TCX_RESTING_PERCENT_FTP: f64 = 50.0;
+
+/// One step of a structured workout. Intensities are expressed as a percentage of Functional Threshold Power (FTP)
+/// so that the workout can be scaled to any athlete.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum StructuredStep {
+    /// A period of riding at a steady intensity, or ramping from one intensity to another.
+    Interval {
+        /// Optional name of the step, such as "Warm up".
+        name: Option<String>,
+
+        /// Length of the step in seconds.
+        duration_seconds: f64,
+
+        /// Intensity at the start of the step, as a percentage of FTP.
+        start_percent_ftp: f64,
+
+        /// Intensity at the end of the step, as a percentage of FTP. Equal to the start for steady steps.
+        end_percent_ftp: f64,
+    },
+
+    /// A block of steps performed several times in a row.
+    Repeat {
+        /// Number of times the block is performed.
+        count: u32,
+
+        /// The steps in the block.
+        steps: Vec<StructuredStep>,
+    },
+}
+
+impl StructuredStep {
+    /// Creates a steady interval at a single intensity.
+    pub fn steady(duration_seconds: f64, percent_ftp: f64) -> Self {
+        StructuredStep::Interval {
+            name: None,
+            duration_seconds,
+            start_percent_ftp: percent_ftp,
+            end_percent_ftp: percent_ftp,
+        }
+    }
+
+    /// Creates an interval that ramps linearly from one intensity to another.
+    pub fn ramp(duration_seconds: f64, start_percent_ftp: f64, end_percent_ftp: f64) -> Self {
+        StructuredStep::Interval {
+            name: None,
+            duration_seconds,
+            start_percent_ftp,
+            end_percent_ftp,
+        }
+    }
+}
+
+/// A flattened interval: duration in seconds, start intensity and end intensity (percentage of FTP).
+type FlatInterval = (f64, f64, f64);
+
+/// A structured workout, used as an intermediate representation when moving workouts between platforms.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct StructuredWorkout {
+    /// Name of the workout.
+    pub name: String,
+
+    /// The sport the workout is for: `Running`, `Biking` or `Other`.
+    #[serde(default)]
+    pub sport: String,
+
+    /// Optional longer description of the workout.
+    pub description: Option<String>,
+
+    /// The steps of the workout, in order.
+    pub steps: Vec<StructuredStep>,
+}
+
+impl StructuredWorkout {
+    /// Builds a workout that replays the power recorded during an activity, averaged into fixed-length steps.
+    ///
+    /// # Parameters
+    ///
+    /// `activity: &Activity` -- An activity with power (TPX `Watts`) data.
+    ///
+    /// `ftp: u16` -- The athlete's Functional Threshold Power in Watts, used to express the steps as a percentage of FTP.
+    ///
+    /// `step_seconds: f64` -- Length of each step in seconds.
+    ///
+    /// # Returns
+    ///
+    /// `StructuredWorkout` -- One steady step per period. Periods without power readings become 0% steps. There are
+    /// no steps if `ftp` is zero or `step_seconds` is not positive.
+    pub fn from_activity_power(activity: &Activity, ftp: u16, step_seconds: f64) -> Self {
+        let mut totals: Vec<(f64, usize)> = Vec::new();
+        let first = activity.trackpoints().next();
+        if let Some(first) = first.filter(|_| ftp > 0 && step_seconds > 0.0) {
+            for point in activity.trackpoints() {
+                let watts = point
+                    .extensions
+                    .as_ref()
+                    .and_then(|ext| ext.tpx.as_ref())
+                    .and_then(|tpx| tpx.watts);
+                let index = (seconds_between(first, point) / step_seconds) as usize;
+                if totals.len() <= index {
+                    totals.resize(index + 1, (0.0, 0));
+                }
+                if let Some(watts) = watts {
+                    totals[index].0 += f64::from(watts);
+                    totals[index].1 += 1;
+                }
+            }
+        }
+
+        let steps = totals
+            .iter()
+            .map(|(sum, count)| {
+                let average = if *count > 0 { sum / *count as f64 } else { 0.0 };
+                StructuredStep::steady(step_seconds, average / f64::from(ftp) * 100.0)
+            })
+            .collect();
+        StructuredWorkout {
+            name: activity.id.clone(),
+            sport: activity.sport.clone(),
+            description: Some(format!("{} power profile", activity.sport)),
+            steps,
+        }
+    }
+
+    /// Converts the workout to a TCX `Workout`, so that it can be written into a TCX document. TCX has no power
+    /// targets, so each interval becomes a timed step with no target, marked `Resting` if its average intensity
+    /// is below 55% of FTP and `Active` otherwise.
+    ///
+    /// # Parameters
+    ///
+    /// None. `&self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// `Result<Workout, Error>` -- A workout for the same sport (`Other` if none is set), named after this one
+    /// (shortened to the schema's 15 characters), with the description as its notes. Fails if there are more steps,
+    /// counting repeats and the steps inside them, than the schema's 20 step IDs allow.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::workout::{StructuredStep, StructuredWorkout};
+    ///
+    /// let workout = StructuredWorkout {
+    ///     name: String::from("Threshold"),
+    ///     sport: String::from("Biking"),
+    ///     description: None,
+    ///     steps: vec![StructuredStep::Repeat {
+    ///         count: 4,
+    ///         steps: vec![StructuredStep::steady(480.0, 100.0), StructuredStep::steady(120.0, 50.0)],
+    ///     }],
+    /// };
+    /// let tcx_workout = workout.to_tcx_workout().unwrap();
+    /// assert_eq!(tcx_workout.sport, "Biking");
+    /// assert_eq!(tcx_workout.steps[0].repetitions, Some(4));
+    /// assert_eq!(tcx_workout.steps[0].children[0].duration.as_ref().unwrap().seconds, Some(480));
+    /// ```
+    pub fn to_tcx_workout(&self) -> Result<Workout, crate::error::Error> {
+        let mut step_id = 0;
+        let sport = if self.sport.is_empty() {
+            "Other"
+        } else {
+            &self.sport
+        };
+        Ok(Workout {
+            sport: sport.to_string(),
+            name: self
+                .name
+                .trim()
+                .chars()
+                .take(crate::validate::MAX_NAME_CHARS)
+                .collect::<String>()
+                .trim_end()
+                .to_string(),
+            steps: to_tcx_steps(&self.steps, &mut step_id)?,
+            notes: self.description.clone(),
+            ..Workout::default()
+        })
+    }
+
+    /// Total duration of the workout in seconds, with repeats expanded.
+    pub fn duration_seconds(&self) -> f64 {
+        self.flatten().iter().map(|interval| interval.0).sum()
+    }
+
+    /// Renders the workout as an MRC file, where intensities are a percentage of FTP.
+    ///
+    /// # Parameters
+    ///
+    /// None. `&self` is implicit.
+    ///
+    /// # Returns
+    ///
+    /// `String` -- The contents of the MRC file.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use crate::tcx::workout::{StructuredStep, StructuredWorkout};
+    ///
+    /// let workout = StructuredWorkout {
+    ///     name: String::from("Sweet spot"),
+    ///     sport: String::from("Biking"),
+    ///     description: None,
+    ///     steps: vec![
+    ///         StructuredStep::ramp(600.0, 40.0, 75.0),
+    ///         StructuredStep::Repeat {
+    ///             count: 3,
+    ///             steps: vec![StructuredStep::steady(600.0, 90.0), StructuredStep::steady(300.0, 50.0)],
+    ///         },
+    ///     ],
+    /// };
+    /// assert!(workout.to_mrc().contains("MINUTES PERCENT"));
+    /// ```
+    pub fn to_mrc(&self) -> String {
+        self.render("MINUTES PERCENT", None, |percent| format!("{:.0}", percent))
+    }
+
+    /// Renders the workout as an ERG file, where intensities are absolute Watts for the given FTP.
+    ///
+    /// # Parameters
+    ///
+    /// `ftp: u16` -- The athlete's Functional Threshold Power in Watts.
+    ///
+    /// # Returns
+    ///
+    /// `String` -- The contents of the ERG file.
+    pub fn to_erg(&self, ftp: u16) -> String {
+        self.render("MINUTES WATTS", Some(ftp), |percent| {
+            format!("{:.0}", percent * f64::from(ftp) / 100.0)
+        })
+    }
+
+    /// Writes the workout to an ERG file.
+    ///
+    /// # Parameters
+    ///
+    /// `filename: &str` -- The name of the ERG file to be produced.
+    ///
+    /// `ftp: u16` -- The athlete's Functional Threshold Power in Watts.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn export_erg(&self, filename: &str, ftp: u16) -> Result<(), Box<dyn Error>> {
+        self.write_erg_to(&mut std::path::Path::new(filename), ftp)
+    }
+
+    /// Writes the workout as ERG to any sink. See `to_erg`.
+    ///
+    /// # Parameters
+    ///
+    /// `sink: &mut S` -- Where to write, such as a `PathBuf`, a `Vec<u8>` or a `Stream`.
+    ///
+    /// `ftp: u16` -- The athlete's Functional Threshold Power in Watts.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn write_erg_to<S: TcxSink + ?Sized>(
+        &self,
+        sink: &mut S,
+        ftp: u16,
+    ) -> Result<(), Box<dyn Error>> {
+        crate::storage::write_with(sink, |writer| {
+            Ok(writer.write_all(self.to_erg(ftp).as_bytes())?)
+        })
+    }
+
+    /// Expands repeats into a flat list of intervals.
+    fn flatten(&self) -> Vec<FlatInterval> {
+        let mut intervals = Vec::new();
+        flatten_steps(&self.steps, &mut intervals);
+        intervals
+    }
+
+    /// Writes the shared ERG/MRC layout, converting each intensity with `value`.
+    fn render<F: Fn(f64) -> String>(&self, units: &str, ftp: Option<u16>, value: F) -> String {
+        let mut out = String::new();
+        out.push_str("[COURSE HEADER]\r\n");
+        out.push_str("VERSION = 2\r\n");
+        out.push_str("UNITS = ENGLISH\r\n");
+        let _ = write!(
+            out,
+            "DESCRIPTION = {}\r\n",
+            self.description.as_deref().unwrap_or("")
+        );
+        let _ = write!(out, "FILE NAME = {}\r\n", self.name);
+        if let Some(ftp) = ftp {
+            let _ = write!(out, "FTP = {}\r\n", ftp);
+        }
+        let _ = write!(out, "{}\r\n", units);
+        out.push_str("[END COURSE HEADER]\r\n");
+        out.push_str("[COURSE DATA]\r\n");
+
+        // Each interval becomes a pair of points; steps are vertical jumps between them.
+        let mut minutes = 0.0;
+        for (duration, start, end) in self.flatten() {
+            let _ = write!(out, "{:.2}\t{}\r\n", minutes, value(start));
+            minutes += duration / 60.0;
+            let _ = write!(out, "{:.2}\t{}\r\n", minutes, value(end));
+        }
+        out.push_str("[END COURSE DATA]\r\n");
+        out
+    }
+}
+
+/// Converts structured steps to TCX steps, numbering them in order with `step_id`. Fails once the numbers run past
+/// `MAX_STEP_ID`.
+fn to_tcx_steps(
+    steps: &[StructuredStep],
+    step_id: &mut u8,
+) -> Result<Vec<WorkoutStep>, crate::error::Error> {
+    steps
+        .iter()
+        .map(|step| {
+            if *step_id >= MAX_STEP_ID {
+                return Err(crate::error::Error::InvalidValue(format!(
+                    "a TCX workout has at most {} steps",
+                    MAX_STEP_ID
+                )));
+            }
+            *step_id += 1;
+            Ok(match step {
+                StructuredStep::Interval {
+                    name,
+                    duration_seconds,
+                    start_percent_ftp,
+                    end_percent_ftp,
+                } => {
+                    let resting = (start_percent_ftp + end_percent_ftp) / 2.0 < RESTING_PERCENT_FTP;
+                    WorkoutStep {
+                        step_type: Some(String::from("Step_t")),
+                        step_id: *step_id,
+                        name: name.clone(),
+                        duration: Some(StepDuration {
+                            duration_type: Some(String::from("Time_t")),
+                            seconds: Some(
+                                duration_seconds.round().clamp(0.0, f64::from(u16::MAX)) as u16
+                            ),
+                            ..StepDuration::default()
+                        }),
+                        intensity: Some(if resting {
+                            Intensity::Resting
+                        } else {
+                            Intensity::Active
+                        }),
+                        ..WorkoutStep::default()
+                    }
+                }
+                StructuredStep::Repeat { count, steps } => {
+                    let id = *step_id;
+                    WorkoutStep {
+                        step_type: Some(String::from("Repeat_t")),
+                        step_id: id,
+                        repetitions: Some((*count).min(u32::from(u8::MAX)) as u8),
+                        children: to_tcx_steps(steps, step_id)?,
+                        ..WorkoutStep::default()
+                    }
+                }
+            })
+        })
+        .collect()
+}
+
+/// Converts TCX steps to structured steps, skipping steps that do not end after a set time.
+fn from_tcx_steps(steps: &[WorkoutStep]) -> Vec<StructuredStep> {
+    steps
+        .iter()
+        .filter_map(|step| {
+            if step.is_repeat() {
+                let children = from_tcx_steps(&step.children);
+                return (!children.is_empty()).then(|| StructuredStep::Repeat {
+                    count: u32::from(step.repetitions.unwrap_or(2)),
+                    steps: children,
+                });
+            }
+            let seconds = step.duration.as_ref()?.seconds?;
+            let percent_ftp = match step.intensity {
+                Some(Intensity::Resting) => TCX_RESTING_PERCENT_FTP,
+                _ => TCX_ACTIVE_PERCENT_FTP,
+            };
+            Some(StructuredStep::Interval {
+                name: step.name.clone(),
+                duration_seconds: f64::from(seconds),
+                start_percent_ftp: percent_ftp,
+                end_percent_ftp: percent_ftp,
+            })
+        })
+        .collect()
+}
+
+impl From<&Workout> for StructuredWorkout {
+    /// Reads a TCX workout. TCX has no power targets, so timed steps become steady intervals at 75% of FTP, or 50%
+    /// if they are marked `Resting`. Steps that end on distance, heart rate, calories or a button press have no
+    /// fixed length and are left out, as are repeats left with nothing to repeat.
+    fn from(workout: &Workout) -> Self {
+        StructuredWorkout {
+            name: workout.name.clone(),
+            sport: workout.sport.clone(),
+            description: workout.notes.clone(),
+            steps: from_tcx_steps(&workout.steps),
+        }
+    }
+}
+
+/// Appends the intervals described by `steps` to `intervals`, expanding repeats.
+fn flatten_steps(steps: &[StructuredStep], intervals: &mut Vec<FlatInterval>) {
+    for step in steps {
+        match step {
+            StructuredStep::Interval {
+                duration_seconds,
+                start_percent_ftp,
+                end_percent_ftp,
+                ..
+            } => intervals.push((*duration_seconds, *start_percent_ftp, *end_percent_ftp)),
+            StructuredStep::Repeat { count, steps } => {
+                for _ in 0..*count {
+                    flatten_steps(steps, intervals);
+                }
+            }
+        }
+    }
+}
+
+impl Activity {
+    /// Writes the power recorded during the activity to an ERG file, in 30 second steps, so that the effort can be
+    /// replayed on trainer software.
+    ///
+    /// # Parameters
+    ///
+    /// `filename: &str` -- The name of the ERG file to be produced.
+    ///
+    /// `ftp: u16` -- The athlete's Functional Threshold Power in Watts.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// activity.export_erg("tests/20210308_virtual_ride_with_power.erg", 250).unwrap();
+    /// ```
+    pub fn export_erg(&self, filename: &str, ftp: u16) -> Result<(), Box<dyn Error>> {
+        StructuredWorkout::from_activity_power(self, ftp, POWER_PROFILE_STEP_SECONDS)
+            .export_erg(filename, ftp)
+    }
+}
@@ -0,0 +1,1192 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Writes a `TrainingCenterDatabase` back out as TCX XML. The derived `Serialize` implementations describe the
+//! JSON layout, not the schema's, so the XML is produced by hand here, following the element order required by the
+//! TrainingCenterDatabase v2 schema and placing extension data in the ActivityExtension v2 namespace (under the
+//! `ns3` prefix unless `WriteOptions` says otherwise).
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::error::Error;
+use std::io::Write;
+
+use crate::tcx::{
+    Activities, Activity, ActivityLap, ActivityReference, Application, BuildType, Course,
+    CourseFolder, CourseLap, CoursePoint, CoursePointType, Device, Extensions, Folders,
+    HeartRateSource, HeartRateValue, HistoryFolder, Intensity, MultiSportSession, Position,
+    RootAttribute, SpeedType, StepDuration, StepTarget, Track, Trackpoint, TrainingCenterDatabase,
+    TriggerMethod, Version, Workout, WorkoutFolder, WorkoutStep, WriteOptions, Zone,
+};
+
+/// Namespace of the TCX schema.
+const TCX_NAMESPACE: &str = "http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2";
+
+/// Location of the TCX schema.
+const TCX_SCHEMA_LOCATION: &str = "http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2 http://www.garmin.com/xmlschemas/TrainingCenterDatabasev2.xsd";
+
+/// Namespace of the activity extensions (TPX and LX).
+const ACTIVITY_EXTENSION_NAMESPACE: &str = "http://www.garmin.com/xmlschemas/ActivityExtension/v2";
+
+/// Namespace of XML Schema instance attributes.
+const XSI_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+/// The kinds of floating-point value whose precision can be set separately (see `WriteOptions`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Quantity {
+    Coordinate,
+    Altitude,
+    Distance,
+}
+
+/// Minimal indenting XML writer.
+pub(crate) struct XmlWriter<'a, W: Write> {
+    out: &'a mut W,
+    depth: usize,
+    /// The prefix of the ActivityExtension namespace.
+    extension_prefix: String,
+    /// How many decimal places to round floating-point values to, if any.
+    decimal_places: Option<usize>,
+    /// How many decimal places to round coordinates to, if not `decimal_places`.
+    coordinate_decimal_places: Option<usize>,
+    /// How many decimal places to round altitudes to, if not `decimal_places`.
+    altitude_decimal_places: Option<usize>,
+    /// How many decimal places to round distances to, if not `decimal_places`.
+    distance_decimal_places: Option<usize>,
+    /// Whether `optional` writes elements with empty text.
+    emit_empty_elements: bool,
+}
+
+impl<'a, W: Write> XmlWriter<'a, W> {
+    pub(crate) fn new(out: &'a mut W) -> Self {
+        XmlWriter {
+            out,
+            depth: 0,
+            extension_prefix: "ns3".to_string(),
+            decimal_places: None,
+            coordinate_decimal_places: None,
+            altitude_decimal_places: None,
+            distance_decimal_places: None,
+            emit_empty_elements: true,
+        }
+    }
+
+    /// Qualifies the name of an ActivityExtension element with the namespace prefix.
+    fn extension(&self, name: &str) -> String {
+        format!("{}:{}", self.extension_prefix, name)
+    }
+
+    fn indent(&mut self) -> std::io::Result<()> {
+        write!(self.out, "{:width$}", "", width = self.depth * 2)
+    }
+
+    /// Writes an opening tag with the given attributes.
+    pub(crate) fn open(&mut self, tag: &str, attributes: &[(&str, &str)]) -> std::io::Result<()> {
+        self.indent()?;
+        write!(self.out, "<{}", tag)?;
+        for (name, value) in attributes {
+            write!(self.out, " {}=\"{}\"", name, escape(value))?;
+        }
+        writeln!(self.out, ">")?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Writes the closing tag matching the last `open`.
+    pub(crate) fn close(&mut self, tag: &str) -> std::io::Result<()> {
+        self.depth -= 1;
+        self.indent()?;
+        writeln!(self.out, "</{}>", tag)
+    }
+
+    /// Writes an element containing only text.
+    pub(crate) fn text<T: std::fmt::Display>(
+        &mut self,
+        tag: &str,
+        value: T,
+    ) -> std::io::Result<()> {
+        self.indent()?;
+        writeln!(
+            self.out,
+            "<{}>{}</{}>",
+            tag,
+            escape(&value.to_string()),
+            tag
+        )
+    }
+
+    /// Writes an element containing a floating-point value, formatted with `format_number`. Fails if the value is NaN
+    /// or infinite, since the schema has no way to express either.
+    pub(crate) fn number(&mut self, tag: &str, value: f64) -> std::io::Result<()> {
+        let value = format_number(finite(tag, value)?, self.decimal_places);
+        self.text(tag, value)
+    }
+
+    /// Writes an element containing a floating-point value if the value is present.
+    pub(crate) fn optional_number(&mut self, tag: &str, value: Option<f64>) -> std::io::Result<()> {
+        match value {
+            Some(value) => self.number(tag, value),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes an element containing a coordinate, altitude or distance, rounded as set for that kind of value. Fails if
+    /// the value is NaN or infinite.
+    pub(crate) fn quantity(
+        &mut self,
+        tag: &str,
+        value: f64,
+        quantity: Quantity,
+    ) -> std::io::Result<()> {
+        let places = match quantity {
+            Quantity::Coordinate => self.coordinate_decimal_places,
+            Quantity::Altitude => self.altitude_decimal_places,
+            Quantity::Distance => self.distance_decimal_places,
+        };
+        let value = format_number(finite(tag, value)?, places.or(self.decimal_places));
+        self.text(tag, value)
+    }
+
+    /// Writes an element containing a coordinate, altitude or distance if the value is present.
+    pub(crate) fn optional_quantity(
+        &mut self,
+        tag: &str,
+        value: Option<f64>,
+        quantity: Quantity,
+    ) -> std::io::Result<()> {
+        match value {
+            Some(value) => self.quantity(tag, value, quantity),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes an element with attributes and no content.
+    pub(crate) fn empty(&mut self, tag: &str, attributes: &[(&str, &str)]) -> std::io::Result<()> {
+        self.indent()?;
+        write!(self.out, "<{}", tag)?;
+        for (name, value) in attributes {
+            write!(self.out, " {}=\"{}\"", name, escape(value))?;
+        }
+        writeln!(self.out, "/>")
+    }
+
+    /// Writes a fragment of XML as it is, on its own line.
+    pub(crate) fn raw(&mut self, fragment: &str) -> std::io::Result<()> {
+        self.indent()?;
+        writeln!(self.out, "{}", fragment)
+    }
+
+    /// Writes a text element if the value is present, and, unless empty elements are left out, not empty.
+    pub(crate) fn optional<T: std::fmt::Display>(
+        &mut self,
+        tag: &str,
+        value: Option<T>,
+    ) -> std::io::Result<()> {
+        match value {
+            Some(value) => {
+                let text = value.to_string();
+                if text.is_empty() && !self.emit_empty_elements {
+                    return Ok(());
+                }
+                self.text(tag, text)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl TrainingCenterDatabase {
+    /// Writes the database as TCX XML, including the namespace declarations needed for the file to be accepted by
+    /// Garmin Connect, Strava and similar services. Required lap fields that are missing (`Intensity` and
+    /// `TriggerMethod`) are written as `Active` and `Manual`; see also `fill_required_defaults`. Fails, writing
+    /// nothing, if a value breaks the schema's restrictions (see `validate` and `WriteOptions::validate`).
+    ///
+    /// # Parameters
+    ///
+    /// `writer: &mut W` -- Where to write the XML.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), std::io::Error>` -- Nothing if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let mut xml = Vec::new();
+    /// tcx.to_writer(&mut xml).unwrap();
+    /// let reparsed = crate::tcx::read(&mut std::io::BufReader::new(xml.as_slice())).unwrap();
+    /// assert_eq!(reparsed.activities.unwrap().activities[0].laps[0].tracks[0].trackpoints.len(), 1434);
+    /// ```
+    pub fn to_writer<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.to_writer_with_options(writer, &WriteOptions::default())
+    }
+
+    /// Writes the database as TCX XML, using the given options. See `to_writer`.
+    ///
+    /// # Parameters
+    ///
+    /// `writer: &mut W` -- Where to write the XML.
+    ///
+    /// `options: &WriteOptions` -- Controls the namespace prefixes and the order of the root element's attributes.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), std::io::Error>` -- Nothing if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let options = crate::tcx::WriteOptions { extension_prefix: "x".to_string(), ..Default::default() };
+    /// let mut xml = Vec::new();
+    /// tcx.to_writer_with_options(&mut xml, &options).unwrap();
+    /// assert!(String::from_utf8(xml).unwrap().contains("<x:TPX>"));
+    /// ```
+    pub fn to_writer_with_options<W: Write>(
+        &self,
+        writer: &mut W,
+        options: &WriteOptions,
+    ) -> std::io::Result<()> {
+        if options.validate {
+            if let Some(violation) = self.validate(options.max_notes_chars).into_iter().next() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    violation,
+                ));
+            }
+        }
+
+        writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        let mut xml = XmlWriter::new(writer);
+        if !options.extension_prefix.is_empty() {
+            xml.extension_prefix = options.extension_prefix.clone();
+        }
+        xml.decimal_places = options.decimal_places;
+        xml.coordinate_decimal_places = options.coordinate_decimal_places;
+        xml.altitude_decimal_places = options.altitude_decimal_places;
+        xml.distance_decimal_places = options.distance_decimal_places;
+        xml.emit_empty_elements = options.emit_empty_elements;
+        let extension_declaration = format!("xmlns:{}", xml.extension_prefix);
+        let mut order = options.root_attributes.clone();
+        for attribute in WriteOptions::default().root_attributes {
+            if !order.contains(&attribute) {
+                order.push(attribute);
+            }
+        }
+        let attributes: Vec<(&str, &str)> = order
+            .iter()
+            .map(|attribute| match attribute {
+                RootAttribute::SchemaLocation => ("xsi:schemaLocation", TCX_SCHEMA_LOCATION),
+                RootAttribute::ActivityExtension => {
+                    (extension_declaration.as_str(), ACTIVITY_EXTENSION_NAMESPACE)
+                }
+                RootAttribute::TrainingCenterDatabase => ("xmlns", TCX_NAMESPACE),
+                RootAttribute::SchemaInstance => ("xmlns:xsi", XSI_NAMESPACE),
+            })
+            .collect();
+        xml.open("TrainingCenterDatabase", &attributes)?;
+
+        if let Some(folders) = &self.folders {
+            write_folders(&mut xml, folders)?;
+        }
+        if let Some(activities) = &self.activities {
+            xml.open("Activities", &[])?;
+            for activity in &activities.activities {
+                write_activity(&mut xml, activity)?;
+            }
+            for session in &activities.multi_sport_sessions {
+                write_multi_sport_session(&mut xml, session)?;
+            }
+            xml.close("Activities")?;
+        }
+        if let Some(workouts) = &self.workouts {
+            xml.open("Workouts", &[])?;
+            for workout in &workouts.workouts {
+                write_workout(&mut xml, workout)?;
+            }
+            xml.close("Workouts")?;
+        }
+        if let Some(courses) = &self.courses {
+            xml.open("Courses", &[])?;
+            for course in &courses.courses {
+                write_course(&mut xml, course)?;
+            }
+            if let Some(extensions) = &courses.extensions {
+                write_extensions(&mut xml, extensions)?;
+            }
+            xml.close("Courses")?;
+        }
+        if let Some(author) = &self.author {
+            write_application(&mut xml, author)?;
+        }
+        if let Some(extensions) = &self.extensions {
+            write_extensions(&mut xml, extensions)?;
+        }
+
+        xml.close("TrainingCenterDatabase")
+    }
+
+    /// Writes the database to a TCX file. See `to_writer`.
+    ///
+    /// # Parameters
+    ///
+    /// `filename: &str` -- The name of the TCX file to be produced.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// tcx.export_tcx("target/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// ```
+    pub fn export_tcx(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        self.export_tcx_with_options(filename, &WriteOptions::default())
+    }
+
+    /// Writes the database to a TCX file, using the given options. See `to_writer_with_options`.
+    ///
+    /// # Parameters
+    ///
+    /// `filename: &str` -- The name of the TCX file to be produced.
+    ///
+    /// `options: &WriteOptions` -- Controls the namespace prefixes and the order of the root element's attributes.
+    ///
+    /// # Returns
+    ///
+    /// `Result<(), Box<dyn Error>>` -- Nothing if OK, Error if not.
+    pub fn export_tcx_with_options(
+        &self,
+        filename: &str,
+        options: &WriteOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        self.write_to(&mut std::path::Path::new(filename), options)?;
+        Ok(())
+    }
+
+    /// Splits the database into parts of at most `max_trackpoints` Trackpoints each, for services that reject large
+    /// files. Activities are divided as by `Activity::split_by_trackpoints`, and the pieces are packed in order into
+    /// as few parts as possible. Every part keeps the `Author`; folders, workouts, courses, multisport sessions
+    /// and extensions go in the first part only.
+    ///
+    /// # Parameters
+    ///
+    /// `max_trackpoints: usize` -- The most Trackpoints in any part. Treated as 1 if zero.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<TrainingCenterDatabase>` -- The parts. There is always at least one.
+    pub fn split_by_trackpoints(&self, max_trackpoints: usize) -> Vec<TrainingCenterDatabase> {
+        let max_trackpoints = max_trackpoints.max(1);
+        let mut first = TrainingCenterDatabase {
+            folders: self.folders.clone(),
+            workouts: self.workouts.clone(),
+            courses: self.courses.clone(),
+            author: self.author.clone(),
+            extensions: self.extensions.clone(),
+            ..Default::default()
+        };
+        let activities = match &self.activities {
+            Some(activities) => activities,
+            None => return vec![first],
+        };
+        first.activities = Some(Activities {
+            activities: Vec::new(),
+            multi_sport_sessions: activities.multi_sport_sessions.clone(),
+        });
+
+        let mut parts = vec![first];
+        let mut count = 0;
+        for piece in activities
+            .activities
+            .iter()
+            .flat_map(|activity| activity.split_by_trackpoints(max_trackpoints))
+        {
+            let piece_count = piece.trackpoints().count();
+            if count > 0 && count + piece_count > max_trackpoints {
+                parts.push(TrainingCenterDatabase {
+                    activities: Some(Activities::default()),
+                    author: self.author.clone(),
+                    ..Default::default()
+                });
+                count = 0;
+            }
+            count += piece_count;
+            if let Some(activities) = parts.last_mut().and_then(|part| part.activities.as_mut()) {
+                activities.activities.push(piece);
+            }
+        }
+        parts
+    }
+
+    /// Writes the database to as many TCX files as are needed to keep each within `max_trackpoints` Trackpoints.
+    /// See `split_by_trackpoints`. If one file is enough it is written to `filename`; otherwise the files are
+    /// numbered from 1, so `ride.tcx` becomes `ride_1.tcx`, `ride_2.tcx` and so on.
+    ///
+    /// # Parameters
+    ///
+    /// `filename: &str` -- The name of the TCX file to be produced.
+    ///
+    /// `max_trackpoints: usize` -- The most Trackpoints in any file.
+    ///
+    /// # Returns
+    ///
+    /// `Result<Vec<String>, Box<dyn Error>>` -- The names of the files written if OK, Error if not.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let files = tcx.export_tcx_chunked("target/20210308_virtual_ride_with_power.tcx", 1000).unwrap();
+    /// assert_eq!(files, vec!["target/20210308_virtual_ride_with_power_1.tcx", "target/20210308_virtual_ride_with_power_2.tcx"]);
+    /// ```
+    pub fn export_tcx_chunked(
+        &self,
+        filename: &str,
+        max_trackpoints: usize,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let parts = self.split_by_trackpoints(max_trackpoints);
+        if parts.len() == 1 {
+            parts[0].export_tcx(filename)?;
+            return Ok(vec![filename.to_string()]);
+        }
+
+        let path = std::path::Path::new(filename);
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut filenames = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            let mut name = format!("{}_{}", stem, i + 1);
+            if let Some(extension) = path.extension() {
+                name = format!("{}.{}", name, extension.to_string_lossy());
+            }
+            let part_filename = path.with_file_name(name).to_string_lossy().into_owned();
+            part.export_tcx(&part_filename)?;
+            filenames.push(part_filename);
+        }
+        Ok(filenames)
+    }
+}
+
+fn write_folders<W: Write>(xml: &mut XmlWriter<W>, folders: &Folders) -> std::io::Result<()> {
+    xml.open("Folders", &[])?;
+    if let Some(history) = &folders.history {
+        xml.open("History", &[])?;
+        let sports = [
+            ("Running", &history.running),
+            ("Biking", &history.biking),
+            ("Other", &history.other),
+            ("MultiSport", &history.multi_sport),
+        ];
+        for (tag, folder) in sports {
+            if let Some(folder) = folder {
+                write_history_folder(xml, tag, folder)?;
+            }
+        }
+        if let Some(extensions) = &history.extensions {
+            write_extensions(xml, extensions)?;
+        }
+        xml.close("History")?;
+    }
+    if let Some(workouts) = &folders.workouts {
+        xml.open("Workouts", &[])?;
+        let sports = [
+            ("Running", &workouts.running),
+            ("Biking", &workouts.biking),
+            ("Other", &workouts.other),
+        ];
+        for (tag, folder) in sports {
+            if let Some(folder) = folder {
+                write_workout_folder(xml, tag, folder)?;
+            }
+        }
+        if let Some(extensions) = &workouts.extensions {
+            write_extensions(xml, extensions)?;
+        }
+        xml.close("Workouts")?;
+    }
+    if let Some(courses) = &folders.courses {
+        xml.open("Courses", &[])?;
+        if let Some(folder) = &courses.folder {
+            write_course_folder(xml, "CourseFolder", folder)?;
+        }
+        if let Some(extensions) = &courses.extensions {
+            write_extensions(xml, extensions)?;
+        }
+        xml.close("Courses")?;
+    }
+    xml.close("Folders")
+}
+
+fn write_history_folder<W: Write>(
+    xml: &mut XmlWriter<W>,
+    tag: &str,
+    folder: &HistoryFolder,
+) -> std::io::Result<()> {
+    xml.open(tag, &[("Name", &folder.name)])?;
+    for child in &folder.folders {
+        write_history_folder(xml, "Folder", child)?;
+    }
+    write_activity_refs(xml, "ActivityRef", &folder.activity_refs)?;
+    write_activity_refs(
+        xml,
+        "MultisportActivityRef",
+        &folder.multisport_activity_refs,
+    )?;
+    for week in &folder.weeks {
+        let start_day = week.start_day.to_string();
+        xml.open("Week", &[("StartDay", &start_day)])?;
+        xml.optional("Notes", week.notes.as_ref())?;
+        xml.close("Week")?;
+    }
+    xml.optional("Notes", folder.notes.as_ref())?;
+    if let Some(extensions) = &folder.extensions {
+        write_extensions(xml, extensions)?;
+    }
+    xml.close(tag)
+}
+
+fn write_activity_refs<W: Write>(
+    xml: &mut XmlWriter<W>,
+    tag: &str,
+    refs: &[ActivityReference],
+) -> std::io::Result<()> {
+    for reference in refs {
+        xml.open(tag, &[])?;
+        xml.text("Id", format_time(reference.id))?;
+        xml.close(tag)?;
+    }
+    Ok(())
+}
+
+fn write_workout_folder<W: Write>(
+    xml: &mut XmlWriter<W>,
+    tag: &str,
+    folder: &WorkoutFolder,
+) -> std::io::Result<()> {
+    xml.open(tag, &[("Name", &folder.name)])?;
+    for child in &folder.folders {
+        write_workout_folder(xml, "Folder", child)?;
+    }
+    for reference in &folder.workout_name_refs {
+        xml.open("WorkoutNameRef", &[])?;
+        xml.text("Id", &reference.id)?;
+        xml.close("WorkoutNameRef")?;
+    }
+    if let Some(extensions) = &folder.extensions {
+        write_extensions(xml, extensions)?;
+    }
+    xml.close(tag)
+}
+
+fn write_course_folder<W: Write>(
+    xml: &mut XmlWriter<W>,
+    tag: &str,
+    folder: &CourseFolder,
+) -> std::io::Result<()> {
+    xml.open(tag, &[("Name", &folder.name)])?;
+    for child in &folder.folders {
+        write_course_folder(xml, "Folder", child)?;
+    }
+    for reference in &folder.course_name_refs {
+        xml.open("CourseNameRef", &[])?;
+        xml.text("Id", &reference.id)?;
+        xml.close("CourseNameRef")?;
+    }
+    xml.optional("Notes", folder.notes.as_ref())?;
+    if let Some(extensions) = &folder.extensions {
+        write_extensions(xml, extensions)?;
+    }
+    xml.close(tag)
+}
+
+fn write_workout<W: Write>(xml: &mut XmlWriter<W>, workout: &Workout) -> std::io::Result<()> {
+    xml.open("Workout", &[("Sport", &workout.sport)])?;
+    xml.text("Name", &workout.name)?;
+    for step in &workout.steps {
+        write_step(xml, "Step", step)?;
+    }
+    for day in &workout.scheduled_on {
+        xml.text("ScheduledOn", day)?;
+    }
+    xml.optional("Notes", workout.notes.as_ref())?;
+    if let Some(extensions) = &workout.extensions {
+        write_extensions(xml, extensions)?;
+    }
+    xml.close("Workout")
+}
+
+fn write_step<W: Write>(
+    xml: &mut XmlWriter<W>,
+    tag: &str,
+    step: &WorkoutStep,
+) -> std::io::Result<()> {
+    if step.is_repeat() {
+        xml.open(tag, &[("xsi:type", "Repeat_t")])?;
+        xml.text("StepId", step.step_id)?;
+        xml.text("Repetitions", step.repetitions.unwrap_or(2))?;
+        for child in &step.children {
+            write_step(xml, "Child", child)?;
+        }
+        return xml.close(tag);
+    }
+
+    xml.open(tag, &[("xsi:type", "Step_t")])?;
+    xml.text("StepId", step.step_id)?;
+    xml.optional("Name", step.name.as_ref())?;
+    match &step.duration {
+        Some(duration) => write_step_duration(xml, duration)?,
+        None => xml.empty("Duration", &[("xsi:type", "UserInitiated_t")])?,
+    }
+    xml.text("Intensity", intensity_name(step.intensity.as_ref()))?;
+    match &step.target {
+        Some(target) => write_step_target(xml, target)?,
+        None => xml.empty("Target", &[("xsi:type", "None_t")])?,
+    }
+    xml.close(tag)
+}
+
+fn write_step_duration<W: Write>(
+    xml: &mut XmlWriter<W>,
+    duration: &StepDuration,
+) -> std::io::Result<()> {
+    let duration_type =
+        duration
+            .duration_type
+            .as_deref()
+            .unwrap_or(if duration.seconds.is_some() {
+                "Time_t"
+            } else if duration.meters.is_some() {
+                "Distance_t"
+            } else if duration.calories.is_some() {
+                "CaloriesBurned_t"
+            } else {
+                "UserInitiated_t"
+            });
+    xml.open("Duration", &[("xsi:type", duration_type)])?;
+    xml.optional("Seconds", duration.seconds)?;
+    xml.optional("Meters", duration.meters)?;
+    if let Some(heart_rate) = &duration.heart_rate {
+        write_heart_rate_value(xml, "HeartRate", heart_rate)?;
+    }
+    xml.optional("Calories", duration.calories)?;
+    xml.close("Duration")
+}
+
+fn write_step_target<W: Write>(xml: &mut XmlWriter<W>, target: &StepTarget) -> std::io::Result<()> {
+    let target_type = target
+        .target_type
+        .as_deref()
+        .unwrap_or(if target.speed_zone.is_some() {
+            "Speed_t"
+        } else if target.heart_rate_zone.is_some() {
+            "HeartRate_t"
+        } else if target.low.is_some() || target.high.is_some() {
+            "Cadence_t"
+        } else {
+            "None_t"
+        });
+    xml.open("Target", &[("xsi:type", target_type)])?;
+    if let Some(zone) = &target.speed_zone {
+        write_zone(xml, "SpeedZone", zone)?;
+    }
+    if let Some(zone) = &target.heart_rate_zone {
+        write_zone(xml, "HeartRateZone", zone)?;
+    }
+    xml.optional_number("Low", target.low)?;
+    xml.optional_number("High", target.high)?;
+    xml.close("Target")
+}
+
+fn write_zone<W: Write>(xml: &mut XmlWriter<W>, tag: &str, zone: &Zone) -> std::io::Result<()> {
+    let zone_type = match (&zone.zone_type, zone.number.is_some(), tag) {
+        (Some(zone_type), _, _) => zone_type.as_str(),
+        (None, true, "SpeedZone") => "PredefinedSpeedZone_t",
+        (None, true, _) => "PredefinedHeartRateZone_t",
+        (None, false, "SpeedZone") => "CustomSpeedZone_t",
+        (None, false, _) => "CustomHeartRateZone_t",
+    };
+    xml.open(tag, &[("xsi:type", zone_type)])?;
+    xml.optional("Number", zone.number)?;
+    xml.optional("ViewAs", zone.view_as.as_ref().map(speed_type_name))?;
+    xml.optional_number("LowInMetersPerSecond", zone.low_in_meters_per_second)?;
+    xml.optional_number("HighInMetersPerSecond", zone.high_in_meters_per_second)?;
+    if let Some(low) = &zone.low {
+        write_heart_rate_value(xml, "Low", low)?;
+    }
+    if let Some(high) = &zone.high {
+        write_heart_rate_value(xml, "High", high)?;
+    }
+    xml.close(tag)
+}
+
+fn write_heart_rate_value<W: Write>(
+    xml: &mut XmlWriter<W>,
+    tag: &str,
+    value: &HeartRateValue,
+) -> std::io::Result<()> {
+    let value_type = value
+        .value_type
+        .as_deref()
+        .unwrap_or("HeartRateInBeatsPerMinute_t");
+    xml.open(tag, &[("xsi:type", value_type)])?;
+    xml.text("Value", value.value)?;
+    xml.close(tag)
+}
+
+fn write_course<W: Write>(xml: &mut XmlWriter<W>, course: &Course) -> std::io::Result<()> {
+    xml.open("Course", &[])?;
+    xml.text("Name", &course.name)?;
+    for lap in &course.laps {
+        write_course_lap(xml, lap)?;
+    }
+    for track in course.tracks.iter().flatten() {
+        write_track(xml, track)?;
+    }
+    xml.optional("Notes", course.notes.as_ref())?;
+    for point in &course.course_points {
+        write_course_point(xml, point)?;
+    }
+    if let Some(extensions) = &course.extensions {
+        write_extensions(xml, extensions)?;
+    }
+    xml.close("Course")
+}
+
+fn write_course_lap<W: Write>(xml: &mut XmlWriter<W>, lap: &CourseLap) -> std::io::Result<()> {
+    xml.open("Lap", &[])?;
+    xml.number("TotalTimeSeconds", lap.total_time_seconds)?;
+    xml.quantity("DistanceMeters", lap.distance_meters, Quantity::Distance)?;
+    if let Some(position) = &lap.begin_position {
+        write_position(xml, "BeginPosition", position)?;
+    }
+    xml.optional_quantity(
+        "BeginAltitudeMeters",
+        lap.begin_altitude_meters,
+        Quantity::Altitude,
+    )?;
+    if let Some(position) = &lap.end_position {
+        write_position(xml, "EndPosition", position)?;
+    }
+    xml.optional_quantity(
+        "EndAltitudeMeters",
+        lap.end_altitude_meters,
+        Quantity::Altitude,
+    )?;
+    write_heart_rate(xml, "AverageHeartRateBpm", lap.average_heart_rate)?;
+    write_heart_rate(xml, "MaximumHeartRateBpm", lap.maximum_heart_rate)?;
+    xml.text("Intensity", intensity_name(lap.intensity.as_ref()))?;
+    xml.optional("Cadence", lap.cadence)?;
+    if let Some(extensions) = &lap.extensions {
+        write_extensions(xml, extensions)?;
+    }
+    xml.close("Lap")
+}
+
+fn write_course_point<W: Write>(
+    xml: &mut XmlWriter<W>,
+    point: &CoursePoint,
+) -> std::io::Result<()> {
+    let point_type = point.point_type.unwrap_or(CoursePointType::Generic);
+    xml.open("CoursePoint", &[])?;
+    xml.text(
+        "Name",
+        point
+            .name
+            .as_deref()
+            .unwrap_or(course_point_type_name(point_type)),
+    )?;
+    xml.text("Time", format_time(point.time))?;
+    if let Some(position) = &point.position {
+        write_position(xml, "Position", position)?;
+    }
+    xml.optional_quantity("AltitudeMeters", point.altitude_meters, Quantity::Altitude)?;
+    xml.text("PointType", course_point_type_name(point_type))?;
+    xml.optional("Notes", point.notes.as_ref())?;
+    if let Some(extensions) = &point.extensions {
+        write_extensions(xml, extensions)?;
+    }
+    xml.close("CoursePoint")
+}
+
+fn write_multi_sport_session<W: Write>(
+    xml: &mut XmlWriter<W>,
+    session: &MultiSportSession,
+) -> std::io::Result<()> {
+    xml.open("MultiSportSession", &[])?;
+    xml.text("Id", format_time(session.id))?;
+    xml.open("FirstSport", &[])?;
+    write_activity(xml, &session.first_sport.activity)?;
+    xml.close("FirstSport")?;
+    for next in &session.next_sports {
+        xml.open("NextSport", &[])?;
+        if let Some(transition) = &next.transition {
+            write_lap(xml, "Transition", transition)?;
+        }
+        write_activity(xml, &next.activity)?;
+        xml.close("NextSport")?;
+    }
+    xml.optional("Notes", session.notes.as_ref())?;
+    xml.close("MultiSportSession")
+}
+
+fn write_activity<W: Write>(xml: &mut XmlWriter<W>, activity: &Activity) -> std::io::Result<()> {
+    xml.open("Activity", &[("Sport", &activity.sport)])?;
+    xml.text("Id", &activity.id)?;
+    for lap in &activity.laps {
+        write_lap(xml, "Lap", lap)?;
+    }
+    xml.optional("Notes", activity.notes.as_ref())?;
+    if let Some(creator) = &activity.creator {
+        write_device(xml, creator)?;
+    }
+    if let Some(extensions) = &activity.extensions {
+        write_extensions(xml, extensions)?;
+    }
+    xml.close("Activity")
+}
+
+fn write_device<W: Write>(xml: &mut XmlWriter<W>, device: &Device) -> std::io::Result<()> {
+    xml.open("Creator", &[("xsi:type", "Device_t")])?;
+    xml.text("Name", &device.name)?;
+    xml.optional("UnitId", device.unit_id)?;
+    xml.optional("ProductID", device.product_id)?;
+    if let Some(version) = &device.version {
+        write_version(xml, version)?;
+    }
+    xml.close("Creator")
+}
+
+fn write_application<W: Write>(
+    xml: &mut XmlWriter<W>,
+    application: &Application,
+) -> std::io::Result<()> {
+    xml.open("Author", &[("xsi:type", "Application_t")])?;
+    xml.text("Name", &application.name)?;
+    xml.open("Build", &[])?;
+    write_version(xml, &application.build.version)?;
+    if let Some(build_type) = &application.build.build_type {
+        xml.text("Type", build_type_name(build_type))?;
+    }
+    xml.optional("Time", application.build.time.as_ref())?;
+    xml.optional("Builder", application.build.builder.as_ref())?;
+    xml.close("Build")?;
+    xml.optional("LangID", application.lang_id.as_ref())?;
+    xml.optional("PartNumber", application.part_number.as_ref())?;
+    xml.close("Author")
+}
+
+fn write_version<W: Write>(xml: &mut XmlWriter<W>, version: &Version) -> std::io::Result<()> {
+    xml.open("Version", &[])?;
+    xml.text("VersionMajor", version.version_major)?;
+    xml.text("VersionMinor", version.version_minor)?;
+    xml.optional("BuildMajor", version.build_major)?;
+    xml.optional("BuildMinor", version.build_minor)?;
+    xml.close("Version")
+}
+
+fn write_lap<W: Write>(
+    xml: &mut XmlWriter<W>,
+    tag: &str,
+    lap: &ActivityLap,
+) -> std::io::Result<()> {
+    if lap.summary_dirty {
+        let mut lap = lap.clone();
+        lap.refresh_summary();
+        return write_lap(xml, tag, &lap);
+    }
+    let start_time = lap
+        .start_time
+        .or_else(|| lap.trackpoints().next().map(|point| point.time))
+        .map(format_time)
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{} has no StartTime and no trackpoints to take one from",
+                    tag
+                ),
+            )
+        })?;
+    xml.open(tag, &[("StartTime", &start_time)])?;
+    xml.number("TotalTimeSeconds", lap.total_time_seconds)?;
+    xml.quantity("DistanceMeters", lap.distance_meters, Quantity::Distance)?;
+    xml.optional_number("MaximumSpeed", lap.maximum_speed)?;
+    xml.text("Calories", lap.calories)?;
+    write_heart_rate(xml, "AverageHeartRateBpm", lap.average_heart_rate)?;
+    write_heart_rate(xml, "MaximumHeartRateBpm", lap.maximum_heart_rate)?;
+    xml.text("Intensity", intensity_name(lap.intensity.as_ref()))?;
+    xml.optional("Cadence", lap.cadence)?;
+    xml.text(
+        "TriggerMethod",
+        trigger_method_name(lap.trigger_method.as_ref()),
+    )?;
+    for track in &lap.tracks {
+        write_track(xml, track)?;
+    }
+    xml.optional("Notes", lap.notes.as_ref())?;
+    if let Some(extensions) = &lap.extensions {
+        write_extensions(xml, extensions)?;
+    }
+    xml.close(tag)
+}
+
+fn write_track<W: Write>(xml: &mut XmlWriter<W>, track: &Track) -> std::io::Result<()> {
+    // The schema requires at least one Trackpoint per Track.
+    if track.trackpoints.is_empty() {
+        return Ok(());
+    }
+    xml.open("Track", &[])?;
+    for point in &track.trackpoints {
+        write_trackpoint(xml, point)?;
+    }
+    xml.close("Track")
+}
+
+fn write_trackpoint<W: Write>(xml: &mut XmlWriter<W>, point: &Trackpoint) -> std::io::Result<()> {
+    xml.open("Trackpoint", &[])?;
+    xml.text("Time", format_time(point.time))?;
+    if let Some(position) = &point.position {
+        write_position(xml, "Position", position)?;
+    }
+    xml.optional_quantity("AltitudeMeters", point.altitude_meters, Quantity::Altitude)?;
+    xml.optional_quantity("DistanceMeters", point.distance_meters, Quantity::Distance)?;
+    write_heart_rate(
+        xml,
+        "HeartRateBpm",
+        point.heart_rate.as_ref().map(|hr| hr.value),
+    )?;
+    xml.optional("Cadence", point.cadence)?;
+    if let Some(extensions) = &point.extensions {
+        write_extensions(xml, extensions)?;
+    }
+    xml.close("Trackpoint")
+}
+
+fn write_position<W: Write>(
+    xml: &mut XmlWriter<W>,
+    tag: &str,
+    position: &Position,
+) -> std::io::Result<()> {
+    xml.open(tag, &[])?;
+    xml.quantity("LatitudeDegrees", position.latitude, Quantity::Coordinate)?;
+    xml.quantity("LongitudeDegrees", position.longitude, Quantity::Coordinate)?;
+    xml.close(tag)
+}
+
+/// Heart rates are stored as `f64` but the schema only allows whole beats per minute.
+fn write_heart_rate<W: Write>(
+    xml: &mut XmlWriter<W>,
+    tag: &str,
+    value: Option<f64>,
+) -> std::io::Result<()> {
+    if let Some(value) = value {
+        xml.open(tag, &[])?;
+        let value = finite(tag, value)?;
+        xml.text("Value", value.round().clamp(1.0, 255.0) as u8)?;
+        xml.close(tag)?;
+    }
+    Ok(())
+}
+
+fn write_extensions<W: Write>(
+    xml: &mut XmlWriter<W>,
+    extensions: &Extensions,
+) -> std::io::Result<()> {
+    let tpx = extensions.tpx.as_ref().filter(|tpx| {
+        xml.emit_empty_elements
+            || tpx.speed.is_some()
+            || tpx.run_cadence.is_some()
+            || tpx.watts.is_some()
+    });
+    let lx = extensions.lx.as_ref().filter(|lx| {
+        xml.emit_empty_elements
+            || lx.avg_speed.is_some()
+            || lx.max_bike_cadence.is_some()
+            || lx.avg_run_cadence.is_some()
+            || lx.max_run_cadence.is_some()
+            || lx.steps.is_some()
+            || lx.avg_watts.is_some()
+            || lx.max_watts.is_some()
+    });
+    if !xml.emit_empty_elements
+        && tpx.is_none()
+        && lx.is_none()
+        && extensions.heart_rate_source.is_none()
+        && extensions.unknown.is_empty()
+    {
+        return Ok(());
+    }
+    xml.open("Extensions", &[])?;
+    if let Some(tpx) = tpx {
+        xml.open(&xml.extension("TPX"), &[])?;
+        xml.optional_number(&xml.extension("Speed"), tpx.speed)?;
+        xml.optional(&xml.extension("RunCadence"), tpx.run_cadence)?;
+        xml.optional(&xml.extension("Watts"), tpx.watts)?;
+        xml.close(&xml.extension("TPX"))?;
+    }
+    if let Some(lx) = lx {
+        xml.open(&xml.extension("LX"), &[])?;
+        xml.optional_number(&xml.extension("AvgSpeed"), lx.avg_speed)?;
+        xml.optional(&xml.extension("MaxBikeCadence"), lx.max_bike_cadence)?;
+        xml.optional(&xml.extension("AvgRunCadence"), lx.avg_run_cadence)?;
+        xml.optional(&xml.extension("MaxRunCadence"), lx.max_run_cadence)?;
+        xml.optional(&xml.extension("Steps"), lx.steps)?;
+        xml.optional(&xml.extension("AvgWatts"), lx.avg_watts)?;
+        xml.optional(&xml.extension("MaxWatts"), lx.max_watts)?;
+        xml.close(&xml.extension("LX"))?;
+    }
+    xml.optional(
+        &xml.extension("HeartRateSource"),
+        extensions.heart_rate_source.map(heart_rate_source_name),
+    )?;
+    for fragment in &extensions.unknown {
+        xml.raw(fragment)?;
+    }
+    xml.close("Extensions")
+}
+
+pub(crate) fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn heart_rate_source_name(source: HeartRateSource) -> &'static str {
+    match source {
+        HeartRateSource::ChestStrap => "ChestStrap",
+        HeartRateSource::Optical => "Optical",
+    }
+}
+
+fn intensity_name(intensity: Option<&Intensity>) -> &'static str {
+    match intensity {
+        Some(Intensity::Resting) => "Resting",
+        Some(Intensity::Active) | None => "Active",
+    }
+}
+
+fn build_type_name(build_type: &BuildType) -> &'static str {
+    match build_type {
+        BuildType::Internal => "Internal",
+        BuildType::Alpha => "Alpha",
+        BuildType::Beta => "Beta",
+        BuildType::Release => "Release",
+    }
+}
+
+fn speed_type_name(speed_type: &SpeedType) -> &'static str {
+    match speed_type {
+        SpeedType::Pace => "Pace",
+        SpeedType::Speed => "Speed",
+    }
+}
+
+fn course_point_type_name(point_type: CoursePointType) -> &'static str {
+    match point_type {
+        CoursePointType::Generic => "Generic",
+        CoursePointType::Summit => "Summit",
+        CoursePointType::Valley => "Valley",
+        CoursePointType::Water => "Water",
+        CoursePointType::Food => "Food",
+        CoursePointType::Danger => "Danger",
+        CoursePointType::Left => "Left",
+        CoursePointType::Right => "Right",
+        CoursePointType::Straight => "Straight",
+        CoursePointType::FirstAid => "First Aid",
+        CoursePointType::FourthCategory => "4th Category",
+        CoursePointType::ThirdCategory => "3rd Category",
+        CoursePointType::SecondCategory => "2nd Category",
+        CoursePointType::FirstCategory => "1st Category",
+        CoursePointType::HorsCategory => "Hors Category",
+        CoursePointType::Sprint => "Sprint",
+    }
+}
+
+fn trigger_method_name(trigger_method: Option<&TriggerMethod>) -> &'static str {
+    match trigger_method {
+        Some(TriggerMethod::Distance) => "Distance",
+        Some(TriggerMethod::Location) => "Location",
+        Some(TriggerMethod::Time) => "Time",
+        Some(TriggerMethod::HeartRate) => "HeartRate",
+        Some(TriggerMethod::Manual) | None => "Manual",
+    }
+}
+
+/// Escapes the characters that are not allowed to appear literally in XML text or attribute values. Control
+/// characters that XML 1.0 cannot represent at all are dropped; everything else, including emoji and other non-ASCII
+/// text, is written as UTF-8.
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\r' => escaped.push_str("&#13;"),
+            '\t' | '\n' => escaped.push(c),
+            _ if c < ' ' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Formats a floating-point value in plain decimal notation, never as `1.2e3`, which some importers reject. With
+/// `decimal_places` the value is rounded to at most that many places, dropping trailing zeros; otherwise it is
+/// written with as many digits as needed to read back exactly.
+/// Returns the value, or an `InvalidData` error naming the element if it is NaN or infinite.
+fn finite(tag: &str, value: f64) -> std::io::Result<f64> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} is {}, which cannot be written", tag, value),
+        ))
+    }
+}
+
+pub(crate) fn format_number(value: f64, decimal_places: Option<usize>) -> String {
+    let formatted = match decimal_places {
+        Some(places) => {
+            let fixed = format!("{:.*}", places, value);
+            if fixed.contains('.') {
+                fixed
+                    .trim_end_matches('0')
+                    .trim_end_matches('.')
+                    .to_string()
+            } else {
+                fixed
+            }
+        }
+        None => value.to_string(),
+    };
+    if formatted == "-0" {
+        "0".to_string()
+    } else {
+        formatted
+    }
+}
@@ -0,0 +1,570 @@
+// by Michael J. Simms
+// Copyright (c) 2021 Michael J. Simms
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Training zone analysis. Zones are described by a list of ascending upper boundaries and
+//! time-in-zone is computed from the intervals between consecutive Trackpoints. Also home to the load metrics
+//! derived from heart rate and power: TRIMP, Intensity Factor and Training Stress Score.
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geo::{seconds_between, speeds};
+use crate::tcx::{Activity, ActivityLap, Trackpoint};
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Pace zones
+
+/// Pace zones for running, stored as speeds (meters per second) since faster paces are smaller numbers.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct PaceZones {
+    /// Ascending upper speed boundaries, in meters per second, for every zone except the last.
+    /// A speed at or above the final boundary falls into the last zone.
+    pub upper_bounds: Vec<f64>,
+}
+
+impl PaceZones {
+    /// Creates pace zones from explicit speed boundaries in meters per second.
+    ///
+    /// # Parameters
+    ///
+    /// `upper_bounds: Vec<f64>` -- Ascending upper boundary of each zone except the last.
+    ///
+    /// # Returns
+    ///
+    /// `PaceZones` with `upper_bounds.len() + 1` zones.
+    pub fn new(upper_bounds: Vec<f64>) -> Self {
+        PaceZones { upper_bounds }
+    }
+
+    /// Creates the classic five pace zones from a threshold pace (the pace that could be held for about an hour).
+    /// Zone 1 is slower than 129% of threshold pace, Zone 2 is 114-129%, Zone 3 is 106-113%, Zone 4 is 99-105% and Zone 5 is faster than 99%.
+    ///
+    /// # Parameters
+    ///
+    /// `threshold_secs_per_km: f64` -- Threshold pace in seconds per kilometer.
+    ///
+    /// # Returns
+    ///
+    /// `PaceZones` with five zones.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// // 4:30 per kilometer threshold pace.
+    /// let zones = crate::tcx::zones::PaceZones::from_threshold_pace(270.0);
+    /// assert_eq!(zones.num_zones(), 5);
+    /// ```
+    pub fn from_threshold_pace(threshold_secs_per_km: f64) -> Self {
+        let threshold_speed = 1000.0 / threshold_secs_per_km;
+        let pace_factors = [1.29, 1.14, 1.06, 0.99];
+        PaceZones {
+            upper_bounds: pace_factors
+                .iter()
+                .map(|factor| threshold_speed / factor)
+                .collect(),
+        }
+    }
+
+    /// Returns the number of zones described.
+    pub fn num_zones(&self) -> usize {
+        self.upper_bounds.len() + 1
+    }
+
+    /// Returns the zero-based zone index for the given speed in meters per second.
+    pub fn zone_for_speed(&self, speed: f64) -> usize {
+        zone_index(&self.upper_bounds, speed)
+    }
+}
+
+impl ActivityLap {
+    /// Computes the time, in seconds, spent in each of the given pace zones during the lap.
+    ///
+    /// # Parameters
+    ///
+    /// `zones: &PaceZones` -- The pace zones to bucket the lap's speed into.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<f64>` -- Seconds spent in each zone, indexed by zone (index 0 is Zone 1).
+    pub fn pace_zone_distribution(&self, zones: &PaceZones) -> Vec<f64> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        time_in_zones(&points, &speeds(&points), &zones.upper_bounds)
+    }
+}
+
+impl Activity {
+    /// Computes the time, in seconds, spent in each of the given pace zones during the activity.
+    ///
+    /// # Parameters
+    ///
+    /// `zones: &PaceZones` -- The pace zones to bucket the activity's speed into.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<f64>` -- Seconds spent in each zone, indexed by zone (index 0 is Zone 1).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let zones = crate::tcx::zones::PaceZones::from_threshold_pace(270.0);
+    /// let distribution = tcx.activities.unwrap().activities[0].pace_zone_distribution(&zones);
+    /// assert_eq!(distribution.len(), 5);
+    /// ```
+    pub fn pace_zone_distribution(&self, zones: &PaceZones) -> Vec<f64> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        time_in_zones(&points, &speeds(&points), &zones.upper_bounds)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Heart rate zones
+
+/// Heart rate zones, in Beats per Minute.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct HeartRateZones {
+    /// Ascending upper heart rate boundaries for every zone except the last. A heart rate at or above the final
+    /// boundary falls into the last zone.
+    pub upper_bounds: Vec<f64>,
+}
+
+impl HeartRateZones {
+    /// Creates heart rate zones from explicit boundaries in Beats per Minute.
+    ///
+    /// # Parameters
+    ///
+    /// `upper_bounds: Vec<f64>` -- Ascending upper boundary of each zone except the last.
+    ///
+    /// # Returns
+    ///
+    /// `HeartRateZones` with `upper_bounds.len() + 1` zones.
+    pub fn new(upper_bounds: Vec<f64>) -> Self {
+        HeartRateZones { upper_bounds }
+    }
+
+    /// Creates the common five zones from maximum heart rate. Zone 1 is below 60% of maximum, Zone 2 is 60-70%,
+    /// Zone 3 is 70-80%, Zone 4 is 80-90% and Zone 5 is 90% and above.
+    ///
+    /// # Parameters
+    ///
+    /// `max_hr: f64` -- The athlete's maximum heart rate in Beats per Minute.
+    ///
+    /// # Returns
+    ///
+    /// `HeartRateZones` with five zones.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let zones = crate::tcx::zones::HeartRateZones::from_max_hr(190.0);
+    /// assert_eq!(zones.zone_for_heart_rate(150.0), 2);
+    /// ```
+    pub fn from_max_hr(max_hr: f64) -> Self {
+        HeartRateZones {
+            upper_bounds: [0.6, 0.7, 0.8, 0.9]
+                .iter()
+                .map(|fraction| max_hr * fraction)
+                .collect(),
+        }
+    }
+
+    /// Creates five zones from lactate threshold heart rate (LTHR), after Friel. Zone 1 is below 85% of LTHR,
+    /// Zone 2 is 85-90%, Zone 3 is 90-95%, Zone 4 is 95-100% and Zone 5 is at or above threshold.
+    ///
+    /// # Parameters
+    ///
+    /// `lthr: f64` -- The athlete's lactate threshold heart rate in Beats per Minute.
+    ///
+    /// # Returns
+    ///
+    /// `HeartRateZones` with five zones.
+    pub fn from_lthr(lthr: f64) -> Self {
+        HeartRateZones {
+            upper_bounds: [0.85, 0.9, 0.95, 1.0]
+                .iter()
+                .map(|fraction| lthr * fraction)
+                .collect(),
+        }
+    }
+
+    /// Returns the number of zones described.
+    pub fn num_zones(&self) -> usize {
+        self.upper_bounds.len() + 1
+    }
+
+    /// Returns the zero-based zone index for the given heart rate in Beats per Minute.
+    pub fn zone_for_heart_rate(&self, heart_rate: f64) -> usize {
+        zone_index(&self.upper_bounds, heart_rate)
+    }
+}
+
+impl ActivityLap {
+    /// Computes the time, in seconds, spent in each of the given heart rate zones during the lap.
+    ///
+    /// # Parameters
+    ///
+    /// `zones: &HeartRateZones` -- The zones to bucket the lap's heart rate into.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<f64>` -- Seconds spent in each zone, indexed by zone (index 0 is Zone 1).
+    pub fn hr_zone_distribution(&self, zones: &HeartRateZones) -> Vec<f64> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        time_in_zones(&points, &heart_rates(&points), &zones.upper_bounds)
+    }
+}
+
+impl Activity {
+    /// Computes the time, in seconds, spent in each of the given heart rate zones during the activity.
+    ///
+    /// # Parameters
+    ///
+    /// `zones: &HeartRateZones` -- The zones to bucket the activity's heart rate into.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<f64>` -- Seconds spent in each zone, indexed by zone (index 0 is Zone 1).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let zones = crate::tcx::zones::HeartRateZones::from_max_hr(190.0);
+    /// let distribution = tcx.activities.unwrap().activities[0].hr_zone_distribution(&zones);
+    /// assert_eq!(distribution.len(), 5);
+    /// ```
+    pub fn hr_zone_distribution(&self, zones: &HeartRateZones) -> Vec<f64> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        time_in_zones(&points, &heart_rates(&points), &zones.upper_bounds)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Power zones
+
+/// Power zones, in Watts.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct PowerZones {
+    /// Ascending upper power boundaries for every zone except the last. Power at or above the final boundary
+    /// falls into the last zone.
+    pub upper_bounds: Vec<f64>,
+}
+
+impl PowerZones {
+    /// Creates power zones from explicit boundaries in Watts.
+    ///
+    /// # Parameters
+    ///
+    /// `upper_bounds: Vec<f64>` -- Ascending upper boundary of each zone except the last.
+    ///
+    /// # Returns
+    ///
+    /// `PowerZones` with `upper_bounds.len() + 1` zones.
+    pub fn new(upper_bounds: Vec<f64>) -> Self {
+        PowerZones { upper_bounds }
+    }
+
+    /// Creates Coggan's seven power zones from Functional Threshold Power. Zone 1 (active recovery) is below 55%
+    /// of FTP, Zone 2 is 55-75%, Zone 3 is 75-90%, Zone 4 is 90-105%, Zone 5 is 105-120%, Zone 6 is 120-150% and
+    /// Zone 7 (neuromuscular) is 150% and above.
+    ///
+    /// # Parameters
+    ///
+    /// `ftp: f64` -- The athlete's Functional Threshold Power in Watts.
+    ///
+    /// # Returns
+    ///
+    /// `PowerZones` with seven zones.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let zones = crate::tcx::zones::PowerZones::from_ftp(250.0);
+    /// assert_eq!(zones.num_zones(), 7);
+    /// assert_eq!(zones.zone_for_power(250.0), 3);
+    /// ```
+    pub fn from_ftp(ftp: f64) -> Self {
+        PowerZones {
+            upper_bounds: [0.55, 0.75, 0.9, 1.05, 1.2, 1.5]
+                .iter()
+                .map(|fraction| ftp * fraction)
+                .collect(),
+        }
+    }
+
+    /// Returns the number of zones described.
+    pub fn num_zones(&self) -> usize {
+        self.upper_bounds.len() + 1
+    }
+
+    /// Returns the zero-based zone index for the given power in Watts.
+    pub fn zone_for_power(&self, watts: f64) -> usize {
+        zone_index(&self.upper_bounds, watts)
+    }
+}
+
+impl ActivityLap {
+    /// Computes the time, in seconds, spent in each of the given power zones during the lap.
+    ///
+    /// # Parameters
+    ///
+    /// `zones: &PowerZones` -- The zones to bucket the lap's power into.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<f64>` -- Seconds spent in each zone, indexed by zone (index 0 is Zone 1).
+    pub fn power_zone_distribution(&self, zones: &PowerZones) -> Vec<f64> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        time_in_zones(&points, &watts(&points), &zones.upper_bounds)
+    }
+}
+
+impl Activity {
+    /// Computes the time, in seconds, spent in each of the given power zones during the activity.
+    ///
+    /// # Parameters
+    ///
+    /// `zones: &PowerZones` -- The zones to bucket the activity's power into.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<f64>` -- Seconds spent in each zone, indexed by zone (index 0 is Zone 1).
+    pub fn power_zone_distribution(&self, zones: &PowerZones) -> Vec<f64> {
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        time_in_zones(&points, &watts(&points), &zones.upper_bounds)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Training load
+
+/// Selects the weighting used by Banister's TRIMP, which differs between men and women.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Sex {
+    Male,
+    Female,
+}
+
+impl Activity {
+    /// Computes Banister's training impulse (TRIMP): the minutes between Trackpoints, each weighted by the
+    /// fraction of heart rate reserve reached and an exponential factor that favours hard efforts
+    /// (`0.64 e^(1.92 x)` for men, `0.86 e^(1.67 x)` for women).
+    ///
+    /// # Parameters
+    ///
+    /// `rest: f64` -- The athlete's resting heart rate in Beats per Minute.
+    ///
+    /// `max: f64` -- The athlete's maximum heart rate in Beats per Minute.
+    ///
+    /// `sex: Sex` -- Selects the weighting.
+    ///
+    /// # Returns
+    ///
+    /// `Option<f64>` -- The TRIMP, or `None` if there is no heart rate or `max` is not above `rest`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// let trimp = activity.trimp(50.0, 190.0, crate::tcx::zones::Sex::Male).unwrap();
+    /// assert!(trimp > 0.0);
+    /// ```
+    pub fn trimp(&self, rest: f64, max: f64, sex: Sex) -> Option<f64> {
+        if max <= rest {
+            return None;
+        }
+        let (scale, exponent) = match sex {
+            Sex::Male => (0.64, 1.92),
+            Sex::Female => (0.86, 1.67),
+        };
+        let points: Vec<&Trackpoint> = self.trackpoints().collect();
+        let heart_rates = heart_rates(&points);
+        let mut total = None;
+        for i in 1..points.len() {
+            let elapsed = seconds_between(points[i - 1], points[i]);
+            if let (Some(heart_rate), true) = (heart_rates[i], elapsed > 0.0) {
+                let reserve = ((heart_rate - rest) / (max - rest)).clamp(0.0, 1.0);
+                *total.get_or_insert(0.0) +=
+                    elapsed / 60.0 * reserve * scale * (exponent * reserve).exp();
+            }
+        }
+        total
+    }
+
+    /// Computes the Intensity Factor: normalized power as a fraction of Functional Threshold Power.
+    ///
+    /// # Parameters
+    ///
+    /// `ftp: f64` -- The athlete's Functional Threshold Power in Watts.
+    ///
+    /// # Returns
+    ///
+    /// `Option<f64>` -- The Intensity Factor, or `None` without enough power data to normalize.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210308_virtual_ride_with_power.tcx").unwrap();
+    /// let intensity = tcx.activities.unwrap().activities[0].intensity_factor(250.0).unwrap();
+    /// assert!(intensity > 0.8 && intensity < 1.0);
+    /// ```
+    pub fn intensity_factor(&self, ftp: f64) -> Option<f64> {
+        if ftp <= 0.0 {
+            return None;
+        }
+        self.summary().normalized_power.map(|np| np / ftp)
+    }
+
+    /// Computes the Training Stress Score: an hour at FTP scores 100, and the score grows with the square of the
+    /// Intensity Factor.
+    ///
+    /// # Parameters
+    ///
+    /// `ftp: f64` -- The athlete's Functional Threshold Power in Watts.
+    ///
+    /// # Returns
+    ///
+    /// `Option<f64>` -- The Training Stress Score, or `None` without enough power data to normalize.
+    pub fn training_stress_score(&self, ftp: f64) -> Option<f64> {
+        let intensity = self.intensity_factor(ftp)?;
+        let seconds = self.summary().elapsed_seconds;
+        Some(seconds * intensity * intensity / 3600.0 * 100.0)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Heart rate intensity
+
+impl Activity {
+    /// Transforms the heart rate recorded in the Trackpoints into a percentage of the athlete's maximum heart rate.
+    ///
+    /// # Parameters
+    ///
+    /// `max_hr: f64` -- The athlete's maximum heart rate in Beats per Minute.
+    ///
+    /// # Returns
+    ///
+    /// `Vec<(DateTime<Utc>, f64)>` -- The time and %HRmax of every Trackpoint that has a heart rate.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let series = tcx.activities.unwrap().activities[0].hr_percent_series(190.0);
+    /// assert_eq!(series[0].1, 55.0 / 190.0 * 100.0);
+    /// ```
+    pub fn hr_percent_series(&self, max_hr: f64) -> Vec<(DateTime<Utc>, f64)> {
+        self.hr_series()
+            .map(|(time, hr)| (time, hr / max_hr * 100.0))
+            .collect()
+    }
+
+    /// Transforms the heart rate recorded in the Trackpoints into a percentage of the athlete's heart rate reserve
+    /// (the Karvonen method), where 0% is the resting heart rate and 100% is the maximum heart rate.
+    ///
+    /// # Parameters
+    ///
+    /// `rest: f64` -- The athlete's resting heart rate in Beats per Minute.
+    ///
+    /// `max: f64` -- The athlete's maximum heart rate in Beats per Minute.
+    ///
+    /// # Returns
+    ///
+    /// `Option<Vec<(DateTime<Utc>, f64)>>` -- The time and %HRR of every Trackpoint that has a heart rate, or `None`
+    /// if `max` is not above `rest`, since there is then no reserve to measure against.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let tcx = crate::tcx::read_file("tests/20210119_run_garmin_fenix6.tcx").unwrap();
+    /// let activity = &tcx.activities.unwrap().activities[0];
+    /// let series = activity.hr_reserve_series(55.0, 190.0).unwrap();
+    /// assert_eq!(series[0].1, 0.0);
+    /// assert!(activity.hr_reserve_series(190.0, 190.0).is_none());
+    /// ```
+    pub fn hr_reserve_series(&self, rest: f64, max: f64) -> Option<Vec<(DateTime<Utc>, f64)>> {
+        if max <= rest {
+            return None;
+        }
+        let reserve = max - rest;
+        Some(
+            self.hr_series()
+                .map(|(time, hr)| (time, (hr - rest) / reserve * 100.0))
+                .collect(),
+        )
+    }
+
+    /// The time and heart rate of every Trackpoint that has a heart rate.
+    fn hr_series(&self) -> impl Iterator<Item = (DateTime<Utc>, f64)> + '_ {
+        self.trackpoints()
+            .filter_map(|point| point.heart_rate.as_ref().map(|hr| (point.time, hr.value)))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Helpers
+
+/// The heart rate of each Trackpoint.
+fn heart_rates(points: &[&Trackpoint]) -> Vec<Option<f64>> {
+    points
+        .iter()
+        .map(|point| point.heart_rate.as_ref().map(|hr| hr.value))
+        .collect()
+}
+
+/// The power of each Trackpoint, from the TPX extension.
+fn watts(points: &[&Trackpoint]) -> Vec<Option<f64>> {
+    points
+        .iter()
+        .map(|point| {
+            point
+                .extensions
+                .as_ref()
+                .and_then(|ext| ext.tpx.as_ref())
+                .and_then(|tpx| tpx.watts)
+                .map(f64::from)
+        })
+        .collect()
+}
+
+/// Returns the index of the first boundary that the value falls below, or the last zone if none.
+fn zone_index(upper_bounds: &[f64], value: f64) -> usize {
+    upper_bounds
+        .iter()
+        .position(|bound| value < *bound)
+        .unwrap_or(upper_bounds.len())
+}
+
+/// Accumulates the time between consecutive Trackpoints into the zone of the value recorded at the end of each interval.
+fn time_in_zones(points: &[&Trackpoint], values: &[Option<f64>], upper_bounds: &[f64]) -> Vec<f64> {
+    let mut totals = vec![0.0; upper_bounds.len() + 1];
+    for i in 1..points.len() {
+        let elapsed = seconds_between(points[i - 1], points[i]);
+        if elapsed <= 0.0 {
+            continue;
+        }
+        if let Some(value) = values[i] {
+            totals[zone_index(upper_bounds, value)] += elapsed;
+        }
+    }
+    totals
+}